@@ -1,6 +1,6 @@
 // build.rs
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 fn main() {
     // set by cargo, build scripts should use this directory for output files
@@ -10,10 +10,64 @@ fn main() {
     // https://doc.rust-lang.org/nightly/cargo/reference/unstable.html#artifact-dependencies
     let kernel = PathBuf::from(std::env::var_os("CARGO_BIN_FILE_KERNEL_kernel").unwrap());
 
+    // boot options for the kernel (kernel/src/config.rs): pong.cfg if there is one, then
+    // PONG_OPTIONS, e.g. PONG_OPTIONS="tick_hz=60 ai=hard theme=neon", loaded as the ramdisk
+    println!("cargo:rerun-if-changed=pong.cfg");
+    println!("cargo:rerun-if-env-changed=PONG_OPTIONS");
+    let mut options = std::fs::read_to_string("pong.cfg").unwrap_or_default();
+    if let Ok(extra) = std::env::var("PONG_OPTIONS") {
+        options.push('\n');
+        options.push_str(&extra);
+    }
+    let config_path = out_dir.join("pong.cfg");
+    std::fs::write(&config_path, &options).unwrap();
+
+    // artwork for the kernel (kernel/src/assets.rs): the files under assets/, e.g.
+    // assets/themes/ocean, packed with the boot options into one archive for the ramdisk
+    println!("cargo:rerun-if-changed=assets");
+    let mut assets = Vec::new();
+    collect_assets(Path::new("assets"), "", &mut assets);
+    let ramdisk_path = if assets.is_empty() {
+        config_path
+    } else {
+        let archive_path = out_dir.join("pong.pak");
+        let mut archive = b"PONGPAK1".to_vec();
+        assets.insert(0, ("pong.cfg".to_string(), options.clone().into_bytes()));
+        for (name, data) in &assets {
+            let name_len = u8::try_from(name.len()).unwrap_or_else(|_| panic!("asset path {} is too long", name));
+            archive.push(name_len);
+            archive.extend_from_slice(name.as_bytes());
+            archive.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            archive.extend_from_slice(data);
+        }
+        std::fs::write(&archive_path, &archive).unwrap();
+        archive_path
+    };
+
     // create an UEFI disk image (optional)
     let uefi_path = out_dir.join("uefi.img");
-    bootloader::UefiBoot::new(&kernel).create_disk_image(&uefi_path).unwrap();
+    let mut boot = bootloader::UefiBoot::new(&kernel);
+    if !options.trim().is_empty() || !assets.is_empty() {
+        boot.set_ramdisk(&ramdisk_path);
+    }
+    boot.create_disk_image(&uefi_path).unwrap();
 
     // pass the disk image paths as env variables to the `main.rs`
     println!("cargo:rustc-env=UEFI_PATH={}", uefi_path.display());
-}
\ No newline at end of file
+}
+
+/// Every file under `dir`, with its path from the assets directory, `/`-separated, in order.
+fn collect_assets(dir: &Path, prefix: &str, assets: &mut Vec<(String, Vec<u8>)>) {
+    let Ok(entries) = std::fs::read_dir(dir) else { return };
+    let mut entries: Vec<_> = entries.map(|entry| entry.unwrap().path()).collect();
+    entries.sort();
+    for path in entries {
+        let name = format!("{}{}", prefix, path.file_name().unwrap().to_string_lossy());
+        if path.is_dir() {
+            collect_assets(&path, &format!("{}/", name), assets);
+        } else {
+            println!("cargo:rerun-if-changed={}", path.display());
+            assets.push((name, std::fs::read(&path).unwrap()));
+        }
+    }
+}
@@ -0,0 +1,303 @@
+#![cfg_attr(not(test), no_std)]
+
+use core::fmt;
+use core::mem::MaybeUninit;
+use core::ops::Deref;
+
+// Collections with their capacity fixed when they are declared, which never touch the allocator:
+// for the kernel's interrupt handlers, which must not allocate (the heap's lock may be held by the
+// code they interrupted), for statics that must be there before the heap is, and for ring 3
+// programs, which have no heap at all. Each says what happens when it is full rather than
+// growing: [RingBuffer::push] and [FixedVec::push] refuse, [RingBuffer::push_overwrite] drops the
+// oldest, and a [FixedString] keeps what fits. They hold `Copy` values only, so nothing is ever
+// dropped. It uses nothing but core, so the kernel and the user crate both build it, and its
+// tests run on the host with `cargo test -p fixed`. For one interrupt handler feeding the game
+// loop without a lock, see the kernel's spsc.rs.
+
+/// A queue of at most `N` values, oldest first.
+pub struct RingBuffer<T, const N: usize> {
+    items: [MaybeUninit<T>; N],
+    /// Where the oldest is.
+    head: usize,
+    len: usize,
+}
+
+impl<T: Copy, const N: usize> RingBuffer<T, N> {
+    pub const fn new() -> Self {
+        Self { items: [const { MaybeUninit::uninit() }; N], head: 0, len: 0 }
+    }
+
+    /// Appends `value`. Returns false, dropping it, if the ring is full.
+    pub fn push(&mut self, value: T) -> bool {
+        if self.len == N {
+            return false;
+        }
+        self.items[(self.head + self.len) % N].write(value);
+        self.len += 1;
+        true
+    }
+
+    /// Appends `value`, dropping the oldest to make room if the ring is full.
+    pub fn push_overwrite(&mut self, value: T) {
+        if N == 0 {
+            return;
+        }
+        if self.len == N {
+            self.head = (self.head + 1) % N;
+            self.len -= 1;
+        }
+        self.push(value);
+    }
+
+    /// Takes the oldest.
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        let value = unsafe { self.items[self.head].assume_init() };
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        Some(value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    pub fn clear(&mut self) {
+        (self.head, self.len) = (0, 0);
+    }
+
+    /// The values, oldest first, in at most two pieces: the ring's end, then its start.
+    pub fn as_slices(&self) -> (&[T], &[T]) {
+        let first = self.len.min(N - self.head);
+        let pieces = (&self.items[self.head..self.head + first], &self.items[..self.len - first]);
+        // Everything from the head for len values has been written
+        unsafe { (slice_assume_init(pieces.0), slice_assume_init(pieces.1)) }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        let (first, second) = self.as_slices();
+        first.iter().chain(second)
+    }
+}
+
+impl<T: Copy, const N: usize> Default for RingBuffer<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A list of at most `N` values.
+pub struct FixedVec<T, const N: usize> {
+    items: [MaybeUninit<T>; N],
+    len: usize,
+}
+
+impl<T: Copy, const N: usize> FixedVec<T, N> {
+    pub const fn new() -> Self {
+        Self { items: [const { MaybeUninit::uninit() }; N], len: 0 }
+    }
+
+    /// Appends `value`, or hands it back if the list is full.
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        if self.len == N {
+            return Err(value);
+        }
+        self.items[self.len].write(value);
+        self.len += 1;
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        Some(unsafe { self.items[self.len].assume_init() })
+    }
+
+    /// Takes the value at `index` out, moving those after it down. Panics if there is none.
+    pub fn remove(&mut self, index: usize) -> T {
+        assert!(index < self.len, "index {} out of {}", index, self.len);
+        let value = unsafe { self.items[index].assume_init() };
+        self.items.copy_within(index + 1..self.len, index);
+        self.len -= 1;
+        value
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        // The first len have been written
+        unsafe { &mut *(&mut self.items[..self.len] as *mut [MaybeUninit<T>] as *mut [T]) }
+    }
+}
+
+impl<T: Copy, const N: usize> Default for FixedVec<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Copy, const N: usize> Deref for FixedVec<T, N> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        unsafe { slice_assume_init(&self.items[..self.len]) }
+    }
+}
+
+/// Text of at most `N` bytes.
+pub struct FixedString<const N: usize> {
+    bytes: FixedVec<u8, N>,
+}
+
+impl<const N: usize> FixedString<N> {
+    pub const fn new() -> Self {
+        Self { bytes: FixedVec::new() }
+    }
+
+    /// `args` formatted, cut off at the last character that fits rather than, as writing is, at
+    /// the last whole piece.
+    pub fn format(args: fmt::Arguments) -> Self {
+        struct Cut<'a, const N: usize>(&'a mut FixedString<N>);
+
+        impl<const N: usize> fmt::Write for Cut<'_, N> {
+            fn write_str(&mut self, text: &str) -> fmt::Result {
+                for c in text.chars() {
+                    if !self.0.push(c) {
+                        return Err(fmt::Error);
+                    }
+                }
+                Ok(())
+            }
+        }
+
+        let mut string = Self::new();
+        let _ = fmt::write(&mut Cut(&mut string), args);
+        string
+    }
+
+    /// Appends `c`. Returns false, leaving the text as it was, if it does not fit.
+    pub fn push(&mut self, c: char) -> bool {
+        self.push_str(c.encode_utf8(&mut [0; 4]))
+    }
+
+    /// Appends `text`. Returns false, leaving the text as it was, if it does not all fit.
+    pub fn push_str(&mut self, text: &str) -> bool {
+        if self.bytes.len() + text.len() > N {
+            return false;
+        }
+        for &byte in text.as_bytes() {
+            let _ = self.bytes.push(byte);
+        }
+        true
+    }
+
+    /// Takes the last character off.
+    pub fn pop(&mut self) -> Option<char> {
+        let c = self.chars().next_back()?;
+        for _ in 0..c.len_utf8() {
+            self.bytes.pop();
+        }
+        Some(c)
+    }
+
+    pub fn clear(&mut self) {
+        self.bytes.clear();
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Only ever whole strs are put in
+        unsafe { core::str::from_utf8_unchecked(&self.bytes) }
+    }
+}
+
+impl<const N: usize> Default for FixedString<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Deref for FixedString<N> {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+/// Writing past the end fails, keeping what was written before the piece that did not fit.
+impl<const N: usize> fmt::Write for FixedString<N> {
+    fn write_str(&mut self, text: &str) -> fmt::Result {
+        if self.push_str(text) { Ok(()) } else { Err(fmt::Error) }
+    }
+}
+
+impl<const N: usize> fmt::Display for FixedString<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// `items`, all written, as what was written.
+unsafe fn slice_assume_init<T>(items: &[MaybeUninit<T>]) -> &[T] {
+    unsafe { &*(items as *const [MaybeUninit<T>] as *const [T]) }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::fmt::Write;
+    use super::*;
+
+    #[test]
+    fn ring_buffer_refuses_or_overwrites_when_full() {
+        let mut ring = RingBuffer::<u8, 3>::new();
+        assert!(ring.push(1) && ring.push(2) && ring.push(3));
+        assert!(ring.is_full() && !ring.push(4));
+        assert_eq!(ring.pop(), Some(1));
+        assert!(ring.push(4));
+        // Wrapped round: the oldest at the end of the ring, the newest at its start
+        assert_eq!(ring.as_slices(), (&[2, 3][..], &[4][..]));
+        ring.push_overwrite(5);
+        assert_eq!(ring.iter().copied().collect::<Vec<_>>(), [3, 4, 5]);
+        assert_eq!((ring.pop(), ring.pop(), ring.pop(), ring.pop()), (Some(3), Some(4), Some(5), None));
+    }
+
+    #[test]
+    fn fixed_vec_and_string_refuse_what_does_not_fit() {
+        let mut vec = FixedVec::<u16, 2>::new();
+        assert_eq!((vec.push(1), vec.push(2), vec.push(3)), (Ok(()), Ok(()), Err(3)));
+        assert_eq!(vec.remove(0), 1);
+        assert_eq!(&vec[..], &[2]);
+        assert_eq!((vec.pop(), vec.pop()), (Some(2), None));
+
+        let mut string = FixedString::<6>::new();
+        assert!(string.push_str("pong"));
+        assert!(!string.push_str("!!!"));
+        assert!(string.push('\u{e9}'));
+        assert!(!string.push('!'));
+        assert_eq!(string.as_str(), "pong\u{e9}");
+        assert_eq!(string.pop(), Some('\u{e9}'));
+        // A write that does not fit fails, keeping what was there
+        assert!(write!(string, "{}", 1).is_ok());
+        assert!(write!(string, "{}", 234).is_err());
+        assert_eq!(string.as_str(), "pong1");
+        assert_eq!(FixedString::<6>::format(format_args!("{}-{}", "pong", 234)).as_str(), "pong-2");
+    }
+}
@@ -0,0 +1,202 @@
+// The computer player's personalities: how it goes after the ball, on top of how well the
+// [Difficulty](crate::Difficulty) lets it see where the ball is going. Each is a [Strategy]
+// choosing where to take the paddle, from which [ai_direction](crate::ai_direction) makes the
+// push; a [Controller] holds the one picked, with whatever it remembers from tick to tick.
+
+use crate::Game;
+
+/// Pixels [Jittery] goes past where it is heading before it turns back.
+pub const OVERSHOOT: isize = 24;
+
+/// Where the computer player takes its paddle, tick by tick.
+pub trait Strategy {
+    /// The target and the paddle's center, as [Game::ai_input] gives them: equal when it should
+    /// stay where it is.
+    fn aim(&mut self, game: &Game) -> (usize, usize);
+}
+
+/// Follows the ball as the difficulty sees it: [Game::ai_input].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Steady;
+
+/// Hugs the spot where the ball will reach its side, whatever the difficulty.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Aggressive;
+
+/// Stays where it is until the ball crosses the middle towards it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Lazy;
+
+/// Overshoots where it is heading by [OVERSHOOT] and corrects, one way and then the other.
+#[derive(Debug, Clone, Copy)]
+pub struct Jittery {
+    /// 1 while it overshoots below, -1 above.
+    side: isize,
+}
+
+impl Strategy for Steady {
+    fn aim(&mut self, game: &Game) -> (usize, usize) {
+        game.ai_input()
+    }
+}
+
+impl Strategy for Aggressive {
+    fn aim(&mut self, game: &Game) -> (usize, usize) {
+        let half = game.paddle_height() / 2;
+        let center = game.paddle(2).y as usize + half;
+        ((game.predicted_y() as usize).saturating_sub(half), center)
+    }
+}
+
+impl Strategy for Lazy {
+    fn aim(&mut self, game: &Game) -> (usize, usize) {
+        let ball = game.ball();
+        if ball.dx > 0.0 && ball.x > game.width as f32 / 2.0 {
+            return game.ai_input();
+        }
+        let center = game.paddle(2).y as usize + game.paddle_height() / 2;
+        (center, center)
+    }
+}
+
+impl Strategy for Jittery {
+    fn aim(&mut self, game: &Game) -> (usize, usize) {
+        let (target, center) = game.ai_input();
+        if target == center {
+            return (target, center);
+        }
+        let (target, center) = (target as isize, center as isize);
+        // Past the far side of where it was heading: turn back and overshoot the other way
+        if (center - (target + self.side * OVERSHOOT)) * self.side >= 0 {
+            self.side = -self.side;
+        }
+        ((target + self.side * OVERSHOOT).max(0) as usize, center as usize)
+    }
+}
+
+/// The personalities there are to pick from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Personality {
+    Steady,
+    Aggressive,
+    Lazy,
+    Jittery,
+}
+
+impl Personality {
+    /// Every personality, in declaration order: `personality as usize` is its index.
+    pub const ALL: [Personality; 4] = [Personality::Steady, Personality::Aggressive, Personality::Lazy, Personality::Jittery];
+
+    /// `steady`, `aggressive`, `lazy` or `jittery`.
+    pub fn from_name(name: &str) -> Option<Personality> {
+        Personality::ALL.into_iter().find(|personality| personality.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Personality::Steady => "steady",
+            Personality::Aggressive => "aggressive",
+            Personality::Lazy => "lazy",
+            Personality::Jittery => "jittery",
+        }
+    }
+}
+
+/// The computer player, as the personality picked plays.
+#[derive(Debug, Clone, Copy)]
+pub enum Controller {
+    Steady(Steady),
+    Aggressive(Aggressive),
+    Lazy(Lazy),
+    Jittery(Jittery),
+}
+
+impl Controller {
+    pub const fn new(personality: Personality) -> Self {
+        match personality {
+            Personality::Steady => Controller::Steady(Steady),
+            Personality::Aggressive => Controller::Aggressive(Aggressive),
+            Personality::Lazy => Controller::Lazy(Lazy),
+            Personality::Jittery => Controller::Jittery(Jittery { side: 1 }),
+        }
+    }
+
+    pub fn personality(&self) -> Personality {
+        match self {
+            Controller::Steady(_) => Personality::Steady,
+            Controller::Aggressive(_) => Personality::Aggressive,
+            Controller::Lazy(_) => Personality::Lazy,
+            Controller::Jittery(_) => Personality::Jittery,
+        }
+    }
+
+    pub fn strategy(&mut self) -> &mut dyn Strategy {
+        match self {
+            Controller::Steady(strategy) => strategy,
+            Controller::Aggressive(strategy) => strategy,
+            Controller::Lazy(strategy) => strategy,
+            Controller::Jittery(strategy) => strategy,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Difficulty, GameMode};
+
+    fn game() -> Game {
+        let mut game = Game::new(640, 480);
+        game.start(GameMode::OnePlayer, || 500);
+        game.paddle_mut(2).y = 0.0;
+        game
+    }
+
+    #[test]
+    fn lazy_waits_for_the_ball_to_cross_the_middle() {
+        let mut game = game();
+        game.ball_mut().x = 200.0;
+        game.ball_mut().y = 400.0;
+        game.ball_mut().dx = 10.0;
+        let (target, center) = Lazy.aim(&game);
+        assert_eq!(target, center);
+
+        game.ball_mut().x = 400.0;
+        let (target, center) = Lazy.aim(&game);
+        assert!(target > center);
+    }
+
+    #[test]
+    fn aggressive_heads_where_the_ball_will_be_at_any_difficulty() {
+        let mut game = game();
+        game.difficulty = Difficulty::Easy;
+        game.ball_mut().x = 330.0;
+        game.ball_mut().y = 100.0;
+        game.ball_mut().dx = 10.0;
+        game.ball_mut().dy = 10.0;
+        let (target, _) = Aggressive.aim(&game);
+        assert_eq!(target, game.predicted_y() as usize - game.paddle_height() / 2);
+    }
+
+    #[test]
+    fn jittery_overshoots_and_turns_back() {
+        let mut game = game();
+        game.ball_mut().y = 225.0;
+        let mut jittery = Jittery { side: 1 };
+        let (target, _) = Steady.aim(&game);
+        assert_eq!(jittery.aim(&game).0, target + OVERSHOOT as usize);
+
+        // Past the overshoot, it heads back above
+        game.paddle_mut(2).y = (target + OVERSHOOT as usize) as f32;
+        assert_eq!(jittery.aim(&game).0, target - OVERSHOOT as usize);
+    }
+
+    #[test]
+    fn personalities_round_trip_their_names() {
+        for personality in Personality::ALL {
+            assert_eq!(Personality::from_name(personality.name()), Some(personality));
+            assert_eq!(Controller::new(personality).personality(), personality);
+        }
+        assert_eq!(Personality::from_name("sleepy"), None);
+    }
+}
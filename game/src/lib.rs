@@ -0,0 +1,1285 @@
+#![cfg_attr(not(test), no_std)]
+
+// Pong's rules, without the screen, sound or input devices: ball and paddle physics, scoring,
+// the serve, the computer player and the game modes. It has no dependencies and does not
+// allocate, so the kernel builds it for bare metal and its tests run on the host with
+// `cargo test -p game`.
+//
+// Randomness comes from the caller, as a function returning random u32s: the kernel passes
+// kernel::rng::u32, whose seed a network game shares between the two machines.
+//
+// The computer player's personalities are in [ai], and the scoring, points, games and who has
+// won, in [scoreboard]. The other games the kernel's launcher hosts keep
+// their rules here too, in [snake] and [tetris].
+
+pub mod ai;
+pub mod scoreboard;
+pub mod snake;
+pub mod tetris;
+
+use scoreboard::{Outcome, Rules, Scoreboard};
+
+/// Default horizontal ball speed, in pixels per tick.
+pub const BALL_SPEED: f32 = 36.0;
+/// Steepest bounce off a paddle edge, as vertical speed over horizontal speed.
+pub const MAX_BOUNCE_SLOPE: f32 = 0.75;
+/// Points that end a game.
+pub const WINNING_SCORE: u32 = 1;
+/// Distance of each paddle from its edge of the screen.
+pub const PADDLE_INSET: usize = 10;
+/// Fastest a paddle moves, in pixels per tick.
+pub const PADDLE_MAX_SPEED: i32 = 15;
+/// Speed a paddle gains or loses each tick on the way to the speed its keys ask for.
+pub const PADDLE_ACCELERATION: i32 = 3;
+/// Ticks a key press keeps pushing its paddle. A held key repeats sooner, so the paddle keeps
+/// going; a tap moves it about as far as a step of old.
+pub const PADDLE_PUSH_TICKS: u8 = 3;
+/// Bytes in a [Game::save].
+pub const SAVED_LEN: usize = 65;
+/// Height of the paddles, in pixels.
+pub const PADDLE_HEIGHT: usize = 50;
+/// Size of the ball as drawn, in pixels across; it bounces off things from its center.
+pub const BALL_SIZE: f32 = 13.0;
+/// Most things on the field at once, the ball and the paddles included.
+pub const MAX_ENTITIES: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameMode {
+    Menu,
+    Settings,
+    Controls,
+    OnePlayer,
+    TwoPlayer,
+    GameOver,
+    MemoryMap,
+    /// A ring 3 program owns the screen and the keyboard until it exits.
+    Program,
+    /// Looking for another player on the network or the serial link.
+    NetworkLobby,
+    /// Playing one on the network or the serial link.
+    Network,
+    /// The rendering benchmark, which has the screen until it ends.
+    Benchmark,
+    /// Another of the launcher's games, which has the screen and the keyboard until it is left.
+    Launched,
+    /// The volumes, opened from the settings.
+    Sound,
+    /// The players' names and their records.
+    Profiles,
+    /// Contrast, sizes and flashing, opened from the settings.
+    Accessibility,
+    /// The input latency test, which has the screen and the keyboard until it is left.
+    LatencyTest,
+}
+
+impl GameMode {
+    /// Every mode, in declaration order: `mode as usize` is its index.
+    pub const ALL: [GameMode; 16] = [
+        GameMode::Menu,
+        GameMode::Settings,
+        GameMode::Controls,
+        GameMode::OnePlayer,
+        GameMode::TwoPlayer,
+        GameMode::GameOver,
+        GameMode::MemoryMap,
+        GameMode::Program,
+        GameMode::NetworkLobby,
+        GameMode::Network,
+        GameMode::Benchmark,
+        GameMode::Launched,
+        GameMode::Sound,
+        GameMode::Profiles,
+        GameMode::Accessibility,
+        GameMode::LatencyTest,
+    ];
+}
+
+/// What happened during one [Game::update], for the sound effects and the high score table.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Events {
+    /// The ball bounced off the top or the bottom.
+    pub wall_bounce: bool,
+    pub paddle_hit: bool,
+    /// The player who scored, 1 or 2.
+    pub scored: Option<u8>,
+    /// The game just ended; the mode it was played in.
+    pub game_over: Option<GameMode>,
+}
+
+/// One thing that happened during a [Game::update], as [Events::iter] hands them out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    WallBounce,
+    PaddleHit,
+    /// By Player 1 or Player 2.
+    PointScored(u8),
+    /// Of a game played in the mode.
+    GameOver(GameMode),
+}
+
+impl Events {
+    /// Each event, in the order they happen within a tick.
+    pub fn iter(&self) -> impl Iterator<Item = Event> {
+        let bounce = self.wall_bounce.then_some(Event::WallBounce);
+        let hit = self.paddle_hit.then_some(Event::PaddleHit);
+        bounce.into_iter().chain(hit).chain(self.scored.map(Event::PointScored)).chain(self.game_over.map(Event::GameOver))
+    }
+}
+
+/// Which way a paddle should move.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Direction {
+    Up,
+    Down,
+    Stay,
+}
+
+impl Direction {
+    /// The inverse of `direction as u8`; anything else is [Direction::Stay].
+    pub fn from_u8(value: u8) -> Direction {
+        match value {
+            v if v == Direction::Up as u8 => Direction::Up,
+            v if v == Direction::Down as u8 => Direction::Down,
+            _ => Direction::Stay,
+        }
+    }
+}
+
+/// How well the computer player plays.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Difficulty {
+    /// Follows the ball only while it comes towards its side.
+    Easy,
+    /// Follows the ball.
+    Normal,
+    /// Heads for where the ball will reach its side, bounces included.
+    Hard,
+}
+
+impl Difficulty {
+    /// Every difficulty, in declaration order: `difficulty as usize` is its index.
+    pub const ALL: [Difficulty; 3] = [Difficulty::Easy, Difficulty::Normal, Difficulty::Hard];
+
+    /// `easy`, `normal` or `hard`.
+    pub fn from_name(name: &str) -> Option<Difficulty> {
+        match name {
+            "easy" => Some(Difficulty::Easy),
+            "normal" => Some(Difficulty::Normal),
+            "hard" => Some(Difficulty::Hard),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Difficulty::Easy => "easy",
+            Difficulty::Normal => "normal",
+            Difficulty::Hard => "hard",
+        }
+    }
+}
+
+/// Which way the AI paddle should move to bring its center to `target`.
+pub fn ai_direction(target: usize, center: usize) -> Direction {
+    if center < target {
+        Direction::Down
+    } else if center > target {
+        Direction::Up
+    } else {
+        Direction::Stay
+    }
+}
+
+/// The way a paddle's keys last pushed it, and for how many more ticks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Push {
+    /// -1 up, 1 down.
+    direction: i8,
+    ticks: u8,
+}
+
+/// What an [Entity] is, which decides how it moves and what it bounces off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// Bounces off the top and the bottom and off the paddles, and scores past either side.
+    Ball,
+    /// Player 1's or Player 2's, by number: moves up and down as its keys push it, and stops at
+    /// the top and the bottom.
+    Paddle(u8),
+}
+
+/// Something on the field: what it is, where, how fast it moves and how big it is. [Game::update]
+/// moves them all by their velocities in one pass, then lets each kind collide with the walls
+/// and the others in another, so a new kind of thing needs a [Kind] and its collisions rather
+/// than fields of its own on [Game].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Entity {
+    pub kind: Kind,
+    /// Position, in pixels: the ball's center; a paddle's line and its top.
+    pub x: f32,
+    pub y: f32,
+    /// Velocity, in pixels per tick. A paddle only moves up and down, in whole pixels.
+    pub dx: f32,
+    pub dy: f32,
+    /// Size, in pixels, as drawn.
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Entity {
+    const fn ball(x: f32, y: f32) -> Self {
+        Self { kind: Kind::Ball, x, y, dx: BALL_SPEED, dy: BALL_SPEED, width: BALL_SIZE, height: BALL_SIZE }
+    }
+
+    const fn paddle(player: u8, x: usize, y: usize, height: usize) -> Self {
+        Self { kind: Kind::Paddle(player), x: x as f32, y: y as f32, dx: 0.0, dy: 0.0, width: 1.0, height: height as f32 }
+    }
+}
+
+/// Most points on a [Trajectory]: the ball, the bounces on the way and the paddle's line. The
+/// slope is bounded, so a few bounces cross the field.
+pub const MAX_TRAJECTORY: usize = 10;
+
+/// The ball's path to the line of the paddle it is heading for, as [Game::trajectory] works it
+/// out: straight lines between the points, the first where the ball is and the last on the line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Trajectory {
+    points: [(f32, f32); MAX_TRAJECTORY],
+    len: usize,
+}
+
+impl Trajectory {
+    pub fn points(&self) -> &[(f32, f32)] {
+        &self.points[..self.len]
+    }
+
+    /// Where the path meets the paddle's line.
+    pub fn end(&self) -> (f32, f32) {
+        self.points[self.len - 1]
+    }
+
+    fn push(&mut self, point: (f32, f32)) {
+        self.points[self.len] = point;
+        self.len += 1;
+    }
+}
+
+/// Index in the entities of the ball; Player 1's and Player 2's paddles follow it.
+const BALL: usize = 0;
+/// The ball and the paddles, always there.
+const FIXED_ENTITIES: usize = 3;
+
+pub struct Game {
+    pub game_mode: GameMode,
+    /// The ball and the paddles first, at [BALL] and after, then anything [Game::spawn]ed.
+    entities: [Entity; MAX_ENTITIES],
+    entity_count: usize,
+    /// Horizontal ball speed, in pixels per tick; the serial shell can change it.
+    pub ball_speed: f32,
+    /// What each paddle's keys ask of it, Player 1's first.
+    push: [Push; 2],
+    /// The points and the games won, and the rules that say when the match is over.
+    pub scoreboard: Scoreboard,
+    pub width: usize,
+    pub height: usize,
+    /// Times Player 1 returned the ball this game, the one-player score.
+    pub returns: u16,
+    /// Paddle hits, both players', since the last serve.
+    pub rally: u16,
+    /// Ticks played since the game started.
+    pub match_ticks: u32,
+    pub difficulty: Difficulty,
+    /// The mode of the last game started, for [Game::play_again].
+    last_game: GameMode,
+}
+
+impl Game {
+    pub const fn new(width: usize, height: usize) -> Self {
+        let mut entities = [Entity::ball(0.0, 0.0); MAX_ENTITIES];
+        entities[BALL] = Entity::ball(width as f32 / 2.0, height as f32 / 2.0);
+        entities[BALL + 1] = Entity::paddle(1, PADDLE_INSET, height / 2, PADDLE_HEIGHT);
+        entities[BALL + 2] = Entity::paddle(2, width.saturating_sub(PADDLE_INSET), height / 2, PADDLE_HEIGHT);
+        Self {
+            game_mode: GameMode::Menu,
+            entities,
+            entity_count: FIXED_ENTITIES,
+            ball_speed: BALL_SPEED,
+            push: [Push { direction: 0, ticks: 0 }; 2],
+            scoreboard: Scoreboard::new(Rules::SINGLE_GAME),
+            width,
+            height,
+            returns: 0,
+            rally: 0,
+            match_ticks: 0,
+            difficulty: Difficulty::Normal,
+            last_game: GameMode::OnePlayer,
+        }
+    }
+
+    /// Everything on the field, the ball and the paddles first.
+    pub fn entities(&self) -> &[Entity] {
+        &self.entities[..self.entity_count]
+    }
+
+    pub fn ball(&self) -> &Entity {
+        &self.entities[BALL]
+    }
+
+    pub fn ball_mut(&mut self) -> &mut Entity {
+        &mut self.entities[BALL]
+    }
+
+    /// Player 1's or Player 2's paddle.
+    pub fn paddle(&self, player: u8) -> &Entity {
+        &self.entities[BALL + player as usize]
+    }
+
+    pub fn paddle_mut(&mut self, player: u8) -> &mut Entity {
+        &mut self.entities[BALL + player as usize]
+    }
+
+    pub fn paddle_height(&self) -> usize {
+        self.paddle(1).height as usize
+    }
+
+    /// Adds `entity` to the field until the next serve. Returns false if there is no room.
+    pub fn spawn(&mut self, entity: Entity) -> bool {
+        if self.entity_count == MAX_ENTITIES {
+            return false;
+        }
+        self.entities[self.entity_count] = entity;
+        self.entity_count += 1;
+        true
+    }
+
+    /// Takes a new screen size, and serves again from the middle if it changed.
+    pub fn resize(&mut self, width: usize, height: usize, random: impl FnMut() -> u32) {
+        if (width, height) != (self.width, self.height) {
+            self.width = width;
+            self.height = height;
+            self.reset(random);
+        }
+    }
+
+    /// Serves from the middle, at a random angle up to [MAX_BOUNCE_SLOPE] either way, towards a
+    /// random side, with the paddles centered and nothing else on the field.
+    pub fn reset(&mut self, mut random: impl FnMut() -> u32) {
+        let slope = (random() % 1001) as f32 / 1000.0 * 2.0 - 1.0;
+        let dx = if random() & 1 != 0 { -self.ball_speed } else { self.ball_speed };
+        let (x, y, dy) = (self.width as f32 / 2.0, self.height as f32 / 2.0, slope * MAX_BOUNCE_SLOPE * self.ball_speed);
+        let ball = self.ball_mut();
+        (ball.x, ball.y, ball.dx, ball.dy) = (x, y, dx, dy);
+        let height = self.paddle_height();
+        self.entities[BALL + 1] = Entity::paddle(1, PADDLE_INSET, self.height / 2, height);
+        self.entities[BALL + 2] = Entity::paddle(2, self.width - PADDLE_INSET, self.height / 2, height);
+        self.entity_count = FIXED_ENTITIES;
+        self.push = [Push::default(); 2];
+        self.rally = 0;
+    }
+
+    /// Starts a game in `mode` with both scores at zero.
+    pub fn start(&mut self, mode: GameMode, random: impl FnMut() -> u32) {
+        self.scoreboard.clear();
+        self.returns = 0;
+        self.match_ticks = 0;
+        self.reset(random);
+        self.game_mode = mode;
+        self.last_game = mode;
+    }
+
+    /// Starts another game like the last one. A network game needs the other side, so it is
+    /// followed by a two player game here.
+    pub fn play_again(&mut self, random: impl FnMut() -> u32) {
+        let mode = match self.last_game {
+            GameMode::OnePlayer => GameMode::OnePlayer,
+            _ => GameMode::TwoPlayer,
+        };
+        self.start(mode, random);
+    }
+
+    /// Leaves the game for the menu, clearing the scores.
+    pub fn quit(&mut self) {
+        self.scoreboard.clear();
+        self.game_mode = GameMode::Menu;
+    }
+
+    /// Whether a game is on, local or over the network.
+    pub fn is_playing(&self) -> bool {
+        matches!(self.game_mode, GameMode::OnePlayer | GameMode::TwoPlayer | GameMode::Network)
+    }
+
+    /// Advances the game by one tick: steers the paddles, moves everything, then has it collide
+    /// with the walls and the paddles, scores and ends the game. Does nothing unless
+    /// [Game::is_playing].
+    pub fn update(&mut self, random: impl FnMut() -> u32) -> Events {
+        let mut events = Events::default();
+        if !self.is_playing() {
+            return events;
+        }
+        self.match_ticks = self.match_ticks.saturating_add(1);
+        self.steer_paddles();
+
+        let before = self.entities;
+        self.integrate();
+        self.collide_walls(&mut events);
+        self.collide_paddles(&before, &mut events);
+
+        // Scoring, past either side; the scoreboard says whether that ends the match
+        let ball_x = self.ball().x;
+        let scorer = if ball_x <= 0.0 {
+            Some(2)
+        } else if ball_x >= self.width as f32 {
+            Some(1)
+        } else {
+            None
+        };
+        if let Some(player) = scorer {
+            events.scored = Some(player);
+            self.reset(random);
+            if let Outcome::MatchWon(_) = self.scoreboard.point(player) {
+                events.game_over = Some(self.game_mode);
+                self.game_mode = GameMode::GameOver;
+            }
+        }
+        events
+    }
+
+    /// Moves everything one tick at its velocity.
+    fn integrate(&mut self) {
+        for entity in &mut self.entities[..self.entity_count] {
+            entity.x += entity.dx;
+            entity.y += entity.dy;
+        }
+    }
+
+    /// Bounces balls off the top and the bottom, and stops paddles there.
+    fn collide_walls(&mut self, events: &mut Events) {
+        let (bottom, height) = ((self.height - 2) as f32, self.height as f32);
+        for i in 0..self.entity_count {
+            let entity = self.entities[i];
+            match entity.kind {
+                Kind::Ball if entity.y <= 1.0 || entity.y >= bottom => {
+                    let y = self.wall_bounce(entity.y);
+                    let ball = &mut self.entities[i];
+                    (ball.y, ball.dy) = (y, -ball.dy);
+                    events.wall_bounce = true;
+                }
+                Kind::Paddle(_) if !(0.0..=height - entity.height).contains(&entity.y) => {
+                    let paddle = &mut self.entities[i];
+                    (paddle.y, paddle.dy) = (paddle.y.clamp(0.0, height - paddle.height), 0.0);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Returns balls off the paddles they hit, along each ball's whole path this tick from where
+    /// it was `before`: at full speed it moves further than a paddle is thick. The further from
+    /// the center the ball hits a paddle, the steeper it bounces off, from where it hit.
+    fn collide_paddles(&mut self, before: &[Entity; MAX_ENTITIES], events: &mut Events) {
+        for (i, from) in before.iter().enumerate().take(self.entity_count) {
+            if from.kind != Kind::Ball {
+                continue;
+            }
+            let hit = self.entities().iter().find_map(|paddle| match paddle.kind {
+                Kind::Paddle(player) => self.paddle_hit(paddle, from, self.entities[i].x).map(|hit| (player, hit)),
+                Kind::Ball => None,
+            });
+            let Some((player, (x, y, offset))) = hit else { continue };
+            // Away from the paddle's side
+            let away = if player == 1 { 1.0 } else { -1.0 };
+            let ball = &mut self.entities[i];
+            (ball.x, ball.y) = (x, y);
+            ball.dx = away * self.ball_speed;
+            ball.dy = offset * MAX_BOUNCE_SLOPE * self.ball_speed;
+            if player == 1 {
+                self.returns = self.returns.saturating_add(1);
+            }
+            self.rally = self.rally.saturating_add(1);
+            events.paddle_hit = true;
+        }
+    }
+
+    /// Folds a height past the top or the bottom wall back onto the field, as the ball bounces.
+    fn wall_bounce(&self, y: f32) -> f32 {
+        let bottom = (self.height - 2) as f32;
+        if y <= 1.0 {
+            2.0 - y
+        } else if y >= bottom {
+            2.0 * bottom - y
+        } else {
+            y
+        }
+    }
+
+    /// Whether a ball, moving this tick from `from` to `to_x` (and its velocity then), hit
+    /// `paddle` on the way: somewhere on its path it reached the paddle's line, coming towards it,
+    /// within 3 pixels. If so, returns where, and where along the paddle, from -1 (top) to 1
+    /// (bottom).
+    fn paddle_hit(&self, paddle: &Entity, from: &Entity, to_x: f32) -> Option<(f32, f32, f32)> {
+        let (from_x, from_y, dy) = (from.x, from.y, from.dy);
+        let (paddle_x, dx) = (paddle.x, to_x - from_x);
+        // Distances in front of the paddle, on the side facing the field
+        let front = if paddle_x < self.width as f32 / 2.0 { 1.0 } else { -1.0 };
+        let (start, end) = ((from_x - paddle_x) * front, (to_x - paddle_x) * front);
+        if dx * front >= 0.0 || start < -3.0 || end > 3.0 {
+            return None;
+        }
+        // Where its path crosses the line, or the end of it if it stops just short
+        let t = ((paddle_x - from_x) / dx).clamp(0.0, 1.0);
+        let (x, y) = (from_x + dx * t, self.wall_bounce(from_y + dy * t));
+        let offset = (y - paddle.y) / paddle.height;
+        (0.0..=1.0).contains(&offset).then_some((x, y, offset * 2.0 - 1.0))
+    }
+
+    /// Pushes a paddle up or down, as its key does: for the next [PADDLE_PUSH_TICKS] ticks it
+    /// speeds up that way, by [PADDLE_ACCELERATION] a tick up to [PADDLE_MAX_SPEED], and after
+    /// that slows down to a stop as fast.
+    pub fn move_paddle(&mut self, is_player1: bool, up: bool) {
+        let direction = if up { -1 } else { 1 };
+        self.push[if is_player1 { 0 } else { 1 }] = Push { direction, ticks: PADDLE_PUSH_TICKS };
+    }
+
+    /// Brings each paddle's speed a step closer to what its keys ask; [Game::integrate] then
+    /// moves it.
+    fn steer_paddles(&mut self) {
+        for (player, push) in [1, 2].into_iter().zip(&mut self.push) {
+            let target = if push.ticks > 0 { push.direction as i32 * PADDLE_MAX_SPEED } else { 0 };
+            push.ticks = push.ticks.saturating_sub(1);
+            let paddle = &mut self.entities[BALL + player];
+            let speed = paddle.dy as i32;
+            paddle.dy = (speed + (target - speed).clamp(-PADDLE_ACCELERATION, PADDLE_ACCELERATION)) as f32;
+        }
+    }
+
+    /// Moves Player 1's paddle by a vertical mouse movement, scaled by `sensitivity` (4 is one
+    /// pixel per count).
+    pub fn mouse_paddle(&mut self, dy: i16, sensitivity: usize) {
+        let step = -(dy as isize) * sensitivity as isize / 4;
+        let max_y = (self.height - self.paddle_height()) as isize;
+        let paddle = self.paddle_mut(1);
+        paddle.y = (paddle.y as isize + step).clamp(0, max_y) as f32;
+    }
+
+    /// The computer player's target and its paddle's center, for [ai_direction]; the two are
+    /// equal when it should stay where it is.
+    pub fn ai_input(&self) -> (usize, usize) {
+        let center = self.paddle(2).y as usize + self.paddle_height() / 2;
+        let ball_y = match self.difficulty {
+            Difficulty::Easy if self.ball().dx <= 0.0 => return (center, center),
+            Difficulty::Easy | Difficulty::Normal => self.ball().y,
+            Difficulty::Hard => self.predicted_y(),
+        };
+        let target = (ball_y as usize).saturating_sub(self.paddle_height() / 2);
+        (target, center)
+    }
+
+    /// Where the ball will be when it reaches Player 2's paddle, after any bounces off the top and
+    /// the bottom on the way; where it is now if it is moving away.
+    pub fn predicted_y(&self) -> f32 {
+        if self.ball().dx <= 0.0 {
+            return self.ball().y;
+        }
+        self.trajectory().end().1
+    }
+
+    /// The ball's path from where it is to the line of the paddle it is moving towards, bouncing
+    /// off the top and the bottom on the way. Just where it is if it is already past the line, or
+    /// not moving across.
+    pub fn trajectory(&self) -> Trajectory {
+        let ball = self.ball();
+        let mut trajectory = Trajectory { points: [(0.0, 0.0); MAX_TRAJECTORY], len: 0 };
+        trajectory.push((ball.x, ball.y));
+        let paddle_x = self.paddle(if ball.dx > 0.0 { 2 } else { 1 }).x;
+        if ball.dx == 0.0 || (paddle_x - ball.x) * ball.dx <= 0.0 {
+            return trajectory;
+        }
+        let (top, bottom) = (1.0, (self.height - 2) as f32);
+        let (mut x, mut y, mut dy) = (ball.x, ball.y, ball.dy);
+        loop {
+            let arrival = y + dy * (paddle_x - x) / ball.dx;
+            // The last point is on the line whatever is left, clamped onto the field
+            if (top..=bottom).contains(&arrival) || dy == 0.0 || trajectory.len == MAX_TRAJECTORY - 1 {
+                trajectory.push((paddle_x, arrival.clamp(top, bottom)));
+                return trajectory;
+            }
+            let wall = if arrival < top { top } else { bottom };
+            x += ball.dx * (wall - y) / dy;
+            y = wall;
+            dy = -dy;
+            trajectory.push((x, y));
+        }
+    }
+
+    /// Pushes the computer player's paddle (Player 2) in `direction`, or lets go of it.
+    pub fn move_ai(&mut self, direction: Direction) {
+        match direction {
+            Direction::Down => self.move_paddle(false, false),
+            Direction::Up => self.move_paddle(false, true),
+            Direction::Stay => self.push[1] = Push::default(),
+        }
+    }
+
+    /// The whole game as bytes, for [Game::restore]: the mode, the last game's mode and the
+    /// difficulty as indices, then the ball's position, velocity and speed as f32s, the paddles,
+    /// the size and the paddle height as u32s, the points, the returns and the rally as u16s and
+    /// the match's ticks as a u32, all little-endian; then for each paddle its speed, and its
+    /// push's direction and ticks, a byte each. Anything spawned is not saved.
+    pub fn save(&self) -> [u8; SAVED_LEN] {
+        let mut bytes = [0; SAVED_LEN];
+        let mut at = 0;
+        let mut put = |field: &[u8]| {
+            bytes[at..at + field.len()].copy_from_slice(field);
+            at += field.len();
+        };
+        put(&[self.game_mode as u8, self.last_game as u8, self.difficulty as u8]);
+        let ball = self.ball();
+        for value in [ball.x, ball.y, ball.dx, ball.dy, self.ball_speed] {
+            put(&value.to_le_bytes());
+        }
+        let (paddle1, paddle2) = (self.paddle(1), self.paddle(2));
+        for value in [paddle1.y as usize, paddle2.y as usize, self.width, self.height, self.paddle_height()] {
+            put(&(value as u32).to_le_bytes());
+        }
+        put(&self.scoreboard.points(1).to_le_bytes());
+        put(&self.scoreboard.points(2).to_le_bytes());
+        put(&self.returns.to_le_bytes());
+        put(&self.rally.to_le_bytes());
+        put(&self.match_ticks.to_le_bytes());
+        for (paddle, push) in [paddle1, paddle2].into_iter().zip(self.push) {
+            put(&[paddle.dy as i8 as u8, push.direction as u8, push.ticks]);
+        }
+        bytes
+    }
+
+    /// The game [Game::save] gave `bytes` for, or None if they are not one that can be played
+    /// on: the wrong length, a mode that does not exist, a ball that is not at a number, a
+    /// paddle off the field or faster than a paddle goes, or a score the game cannot reach.
+    pub fn restore(bytes: &[u8]) -> Option<Game> {
+        if bytes.len() != SAVED_LEN {
+            return None;
+        }
+        let mut reader = Reader(bytes);
+        let [mode, last_game, difficulty] = reader.take();
+        let mut float = || f32::from_le_bytes(reader.take());
+        let [ball_x, ball_y, ball_dx, ball_dy, ball_speed] = [float(), float(), float(), float(), float()];
+        let mut size = || u32::from_le_bytes(reader.take()) as usize;
+        let [player1_y, player2_y, width, height, paddle_height] = [size(), size(), size(), size(), size()];
+        let [player1_score, player2_score] = [u32::from_le_bytes(reader.take()), u32::from_le_bytes(reader.take())];
+        let [returns, rally] = [u16::from_le_bytes(reader.take()), u16::from_le_bytes(reader.take())];
+        let match_ticks = u32::from_le_bytes(reader.take());
+        let mut paddle = || {
+            let [speed, direction, ticks] = reader.take();
+            (speed as i8 as i32, Push { direction: direction as i8, ticks })
+        };
+        let [(player1_speed, push1), (player2_speed, push2)] = [paddle(), paddle()];
+
+        let finite = [ball_x, ball_y, ball_dx, ball_dy].iter().all(|value| value.is_finite());
+        let field = width > 2 * PADDLE_INSET && paddle_height > 0 && paddle_height < height;
+        let paddles = field && player1_y.max(player2_y) <= height - paddle_height;
+        let speeds = [player1_speed, player2_speed].iter().all(|speed| speed.abs() <= PADDLE_MAX_SPEED)
+            && [push1, push2].iter().all(|push| (-1..=1).contains(&push.direction) && push.ticks <= PADDLE_PUSH_TICKS);
+        if !(finite && ball_speed > 0.0 && ball_speed.is_finite() && paddles && speeds) {
+            return None;
+        }
+
+        let mut game = Game::new(width, height);
+        game.game_mode = *GameMode::ALL.get(mode as usize)?;
+        game.last_game = *GameMode::ALL.get(last_game as usize)?;
+        game.difficulty = *Difficulty::ALL.get(difficulty as usize)?;
+        *game.ball_mut() = Entity { dx: ball_dx, dy: ball_dy, ..Entity::ball(ball_x, ball_y) };
+        game.ball_speed = ball_speed;
+        *game.paddle_mut(1) = Entity { dy: player1_speed as f32, ..Entity::paddle(1, PADDLE_INSET, player1_y, paddle_height) };
+        *game.paddle_mut(2) = Entity { dy: player2_speed as f32, ..Entity::paddle(2, width - PADDLE_INSET, player2_y, paddle_height) };
+        game.push = [push1, push2];
+        if !game.scoreboard.can_stand(player1_score, player2_score) {
+            return None;
+        }
+        game.scoreboard.set_points(player1_score, player2_score);
+        (game.returns, game.rally, game.match_ticks) = (returns, rally, match_ticks);
+        Some(game)
+    }
+}
+
+/// Takes [Game::save]'s fields off the front of the bytes, in order.
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    /// The next `N` bytes; the caller has checked there are enough.
+    fn take<const N: usize>(&mut self) -> [u8; N] {
+        let (field, rest) = self.0.split_at(N);
+        self.0 = rest;
+        field.try_into().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Random numbers for the serve: the same ones over and over.
+    fn fixed(value: u32) -> impl FnMut() -> u32 {
+        move || value
+    }
+
+    /// A 640x480 game in `mode` with the ball still in the middle.
+    fn game(mode: GameMode) -> Game {
+        let mut game = Game::new(640, 480);
+        game.start(mode, fixed(500));
+        game.ball_mut().dx = 0.0;
+        game.ball_mut().dy = 0.0;
+        game
+    }
+
+    #[test]
+    fn ball_bounces_off_the_top_wall() {
+        let mut game = game(GameMode::TwoPlayer);
+        game.ball_mut().y = 3.0;
+        game.ball_mut().dy = -5.0;
+        let events = game.update(fixed(0));
+        assert!(events.wall_bounce);
+        assert_eq!(game.ball().y, 4.0);
+        assert_eq!(game.ball().dy, 5.0);
+    }
+
+    #[test]
+    fn ball_bounces_off_the_bottom_wall() {
+        let mut game = game(GameMode::TwoPlayer);
+        game.ball_mut().y = 475.0;
+        game.ball_mut().dy = 5.0;
+        let events = game.update(fixed(0));
+        assert!(events.wall_bounce);
+        assert_eq!(game.ball().y, 476.0);
+        assert_eq!(game.ball().dy, -5.0);
+    }
+
+    #[test]
+    fn paddle_center_returns_the_ball_straight() {
+        let mut game = game(GameMode::TwoPlayer);
+        game.paddle_mut(1).y = 200.0;
+        game.ball_mut().x = 20.0;
+        game.ball_mut().y = 225.0;
+        game.ball_mut().dx = -10.0;
+        let events = game.update(fixed(0));
+        assert!(events.paddle_hit);
+        assert_eq!(game.ball().dx, game.ball_speed);
+        assert_eq!(game.ball().dy, 0.0);
+        assert_eq!(game.returns, 1);
+    }
+
+    #[test]
+    fn rally_counts_both_players_hits_until_a_point() {
+        let mut game = game(GameMode::TwoPlayer);
+        game.paddle_mut(1).y = 200.0;
+        game.paddle_mut(2).y = 200.0;
+        for (x, dx) in [(20.0, -10.0), (620.0, 10.0), (20.0, -10.0)] {
+            game.ball_mut().x = x;
+            game.ball_mut().y = 225.0;
+            game.ball_mut().dx = dx;
+            game.update(fixed(0));
+        }
+        assert_eq!((game.rally, game.returns), (3, 2));
+
+        game.ball_mut().x = 635.0;
+        game.ball_mut().y = 10.0;
+        game.ball_mut().dx = 10.0;
+        game.update(fixed(0));
+        assert_eq!(game.rally, 0);
+        assert_eq!(game.match_ticks, 4);
+    }
+
+    #[test]
+    fn paddle_edges_return_the_ball_steeply() {
+        let mut game = game(GameMode::TwoPlayer);
+        game.paddle_mut(2).y = 100.0;
+        game.ball_mut().x = 620.0;
+        game.ball_mut().y = 150.0;
+        game.ball_mut().dx = 10.0;
+        game.update(fixed(0));
+        assert_eq!(game.ball().dx, -game.ball_speed);
+        assert_eq!(game.ball().dy, MAX_BOUNCE_SLOPE * game.ball_speed);
+        // Player 2's returns do not count
+        assert_eq!(game.returns, 0);
+
+        game.ball_mut().x = 20.0;
+        game.ball_mut().y = 200.0;
+        game.ball_mut().dx = -10.0;
+        game.ball_mut().dy = 0.0;
+        game.paddle_mut(1).y = 200.0;
+        game.update(fixed(0));
+        assert_eq!(game.ball().dx, game.ball_speed);
+        assert_eq!(game.ball().dy, -MAX_BOUNCE_SLOPE * game.ball_speed);
+    }
+
+    #[test]
+    fn ball_past_the_paddle_is_missed() {
+        let mut game = game(GameMode::TwoPlayer);
+        game.paddle_mut(1).y = 0.0;
+        game.ball_mut().x = 20.0;
+        game.ball_mut().y = 400.0;
+        game.ball_mut().dx = -10.0;
+        let events = game.update(fixed(0));
+        assert!(!events.paddle_hit);
+        assert_eq!(game.ball().dx, -10.0);
+    }
+
+    #[test]
+    fn fast_ball_cannot_pass_through_a_paddle() {
+        // From 24 to -12 in one tick: it would be behind the paddle at the end of it
+        let mut game = game(GameMode::TwoPlayer);
+        game.paddle_mut(1).y = 200.0;
+        game.ball_mut().x = 24.0;
+        game.ball_mut().y = 200.0;
+        game.ball_mut().dx = -36.0;
+        game.ball_mut().dy = 36.0;
+        let events = game.update(fixed(0));
+        assert!(events.paddle_hit);
+        assert_eq!(events.scored, None);
+        // Hit where its path crossed the paddle's line, 14/36 of the way
+        assert_eq!((game.ball().x, game.ball().y), (10.0, 214.0));
+        assert_eq!(game.ball().dx, game.ball_speed);
+    }
+
+    #[test]
+    fn spawned_balls_move_and_bounce_until_the_serve() {
+        let mut game = game(GameMode::TwoPlayer);
+        game.paddle_mut(2).y = 100.0;
+        let ball = Entity { dx: 10.0, dy: 0.0, ..Entity::ball(620.0, 125.0) };
+        assert!(game.spawn(ball));
+        game.update(fixed(0));
+        assert_eq!(game.entities().len(), 4);
+        let spawned = game.entities()[3];
+        assert_eq!((spawned.x, spawned.dx), (630.0, -game.ball_speed));
+        assert_eq!(game.rally, 1);
+
+        game.reset(fixed(0));
+        assert_eq!(game.entities().len(), 3);
+        while game.spawn(ball) {}
+        assert_eq!(game.entities().len(), MAX_ENTITIES);
+    }
+
+    #[test]
+    fn path_past_the_paddle_end_is_missed() {
+        let mut game = game(GameMode::TwoPlayer);
+        game.paddle_mut(2).y = 100.0;
+        game.ball_mut().x = 610.0;
+        game.ball_mut().y = 190.0;
+        game.ball_mut().dx = 36.0;
+        game.ball_mut().dy = 36.0;
+        // Level with the paddle's line at 210, below its bottom at 150
+        let events = game.update(fixed(0));
+        assert!(!events.paddle_hit);
+        assert_eq!(events.scored, Some(1));
+    }
+
+    #[test]
+    fn missed_ball_scores_for_the_other_side() {
+        let mut game = game(GameMode::TwoPlayer);
+        game.paddle_mut(1).y = 0.0;
+        game.ball_mut().x = 5.0;
+        game.ball_mut().y = 400.0;
+        game.ball_mut().dx = -10.0;
+        let events = game.update(fixed(0));
+        assert_eq!(events.scored, Some(2));
+        assert_eq!((game.scoreboard.points(1), game.scoreboard.points(2)), (0, 1));
+
+        let mut game = self::game(GameMode::TwoPlayer);
+        game.paddle_mut(2).y = 0.0;
+        game.ball_mut().x = 635.0;
+        game.ball_mut().y = 400.0;
+        game.ball_mut().dx = 10.0;
+        let events = game.update(fixed(0));
+        assert_eq!(events.scored, Some(1));
+        assert_eq!((game.scoreboard.points(1), game.scoreboard.points(2)), (1, 0));
+    }
+
+    #[test]
+    fn winning_score_ends_the_game() {
+        let mut game = game(GameMode::OnePlayer);
+        game.paddle_mut(2).y = 0.0;
+        game.ball_mut().x = 635.0;
+        game.ball_mut().y = 400.0;
+        game.ball_mut().dx = 10.0;
+        let events = game.update(fixed(0));
+        assert_eq!(events.game_over, Some(GameMode::OnePlayer));
+        assert_eq!(game.game_mode, GameMode::GameOver);
+
+        // Nothing moves once it is over
+        let (x, y) = (game.ball().x, game.ball().y);
+        assert_eq!(game.update(fixed(0)), Events::default());
+        assert_eq!((game.ball().x, game.ball().y), (x, y));
+    }
+
+    #[test]
+    fn series_plays_on_after_a_game_won() {
+        let mut game = game(GameMode::TwoPlayer);
+        game.scoreboard.rules = Rules { points_to_win: 1, win_by: 1, games_to_win: 2 };
+        game.paddle_mut(2).y = 0.0;
+        game.ball_mut().x = 635.0;
+        game.ball_mut().y = 400.0;
+        game.ball_mut().dx = 10.0;
+        let events = game.update(fixed(0));
+        assert_eq!((events.scored, events.game_over), (Some(1), None));
+        assert_eq!(game.game_mode, GameMode::TwoPlayer);
+        assert_eq!((game.scoreboard.games(1), game.scoreboard.points(1)), (1, 0));
+    }
+
+    #[test]
+    fn events_come_out_in_order() {
+        let events = Events { wall_bounce: true, paddle_hit: false, scored: Some(2), game_over: Some(GameMode::TwoPlayer) };
+        let mut iter = events.iter();
+        assert_eq!(iter.next(), Some(Event::WallBounce));
+        assert_eq!(iter.next(), Some(Event::PointScored(2)));
+        assert_eq!(iter.next(), Some(Event::GameOver(GameMode::TwoPlayer)));
+        assert_eq!(iter.next(), None);
+        assert_eq!(Events::default().iter().next(), None);
+    }
+
+    #[test]
+    fn serve_starts_in_the_middle() {
+        let mut game = game(GameMode::TwoPlayer);
+        game.ball_mut().x = 7.0;
+        game.paddle_mut(1).y = 0.0;
+        game.reset(fixed(500));
+        assert_eq!((game.ball().x, game.ball().y), (320.0, 240.0));
+        assert_eq!((game.paddle(1).y, game.paddle(2).y), (240.0, 240.0));
+    }
+
+    #[test]
+    fn serve_direction_follows_the_random_bit() {
+        let mut game = game(GameMode::TwoPlayer);
+        game.reset(fixed(1000));
+        assert_eq!(game.ball().dx, game.ball_speed);
+        game.reset(fixed(1001));
+        assert_eq!(game.ball().dx, -game.ball_speed);
+    }
+
+    #[test]
+    fn serve_angle_stays_within_the_bounce_slope() {
+        let mut game = game(GameMode::TwoPlayer);
+        let limit = MAX_BOUNCE_SLOPE * game.ball_speed;
+        // 0 and 1000 are the steepest, 500 is straight across
+        let mut values = [0, 1, 0].into_iter();
+        game.reset(move || values.next().unwrap());
+        assert_eq!(game.ball().dy, -limit);
+        let mut values = [1000, 0].into_iter();
+        game.reset(move || values.next().unwrap());
+        assert_eq!(game.ball().dy, limit);
+        game.reset(fixed(500));
+        assert_eq!(game.ball().dy, 0.0);
+        for seed in 0..5000 {
+            game.reset(fixed(seed * 7919));
+            assert!(game.ball().dy.abs() <= limit);
+            assert_eq!(game.ball().dx.abs(), game.ball_speed);
+        }
+    }
+
+    #[test]
+    fn ai_moves_towards_the_ball() {
+        let mut game = game(GameMode::OnePlayer);
+        game.paddle_mut(2).y = 0.0;
+        game.ball_mut().y = 400.0;
+        let (target, center) = game.ai_input();
+        assert_eq!(ai_direction(target, center), Direction::Down);
+        game.move_ai(Direction::Down);
+        game.update(fixed(0));
+        assert_eq!(game.paddle(2).y, PADDLE_ACCELERATION as f32);
+
+        game.paddle_mut(2).y = 400.0;
+        game.ball_mut().y = 30.0;
+        let (target, center) = game.ai_input();
+        assert_eq!(ai_direction(target, center), Direction::Up);
+        game.paddle_mut(2).dy = 0.0;
+        game.move_ai(Direction::Up);
+        game.update(fixed(0));
+        assert_eq!(game.paddle(2).y, 400.0 - PADDLE_ACCELERATION as f32);
+    }
+
+    #[test]
+    fn ai_holds_still_on_target() {
+        assert_eq!(ai_direction(100, 100), Direction::Stay);
+        let mut game = game(GameMode::OnePlayer);
+        let y = game.paddle(2).y;
+        game.move_ai(Direction::Stay);
+        game.update(fixed(0));
+        assert_eq!(game.paddle(2).y, y);
+    }
+
+    #[test]
+    fn easy_ai_waits_for_the_ball_to_come_back() {
+        let mut game = game(GameMode::OnePlayer);
+        game.difficulty = Difficulty::Easy;
+        game.paddle_mut(2).y = 0.0;
+        game.ball_mut().y = 400.0;
+        game.ball_mut().dx = -10.0;
+        let (target, center) = game.ai_input();
+        assert_eq!(ai_direction(target, center), Direction::Stay);
+        game.ball_mut().dx = 10.0;
+        let (target, center) = game.ai_input();
+        assert_eq!(ai_direction(target, center), Direction::Down);
+    }
+
+    #[test]
+    fn hard_ai_aims_where_the_ball_will_be() {
+        let mut game = game(GameMode::OnePlayer);
+        game.difficulty = Difficulty::Hard;
+        // Straight across, it arrives where it is
+        game.ball_mut().x = 330.0;
+        game.ball_mut().y = 100.0;
+        game.ball_mut().dx = 10.0;
+        assert_eq!(game.predicted_y(), 100.0);
+        // 300 pixels to go at a slope of -1 from y 100: up to the top and 199 back down
+        game.ball_mut().dy = -10.0;
+        assert_eq!(game.predicted_y(), 2.0 - (100.0 - 300.0));
+        // And off the bottom
+        game.ball_mut().y = 400.0;
+        game.ball_mut().dy = 10.0;
+        assert_eq!(game.predicted_y(), 2.0 * 478.0 - 700.0);
+
+        let (target, _) = game.ai_input();
+        assert_eq!(target, (2.0 * 478.0 - 700.0) as usize - game.paddle_height() / 2);
+    }
+
+    #[test]
+    fn trajectory_bounces_to_the_paddle_line() {
+        let mut game = game(GameMode::OnePlayer);
+        let (left, right) = (game.paddle(1).x, game.paddle(2).x);
+        // Heading right at a slope of -1 from y 100: off the top 99 pixels on, and down to the line
+        game.ball_mut().x = 330.0;
+        game.ball_mut().y = 100.0;
+        game.ball_mut().dx = 10.0;
+        game.ball_mut().dy = -10.0;
+        let trajectory = game.trajectory();
+        assert_eq!(trajectory.points(), &[(330.0, 100.0), (429.0, 1.0), (right, 2.0 - (100.0 - 300.0))]);
+        assert_eq!(trajectory.end().1, game.predicted_y());
+        // Heading left it ends at Player 1's paddle
+        game.ball_mut().dx = -10.0;
+        game.ball_mut().dy = 0.0;
+        assert_eq!(game.trajectory().points(), &[(330.0, 100.0), (left, 100.0)]);
+        // Past the line, it is only where the ball is
+        game.ball_mut().x = left - 5.0;
+        assert_eq!(game.trajectory().points(), &[(left - 5.0, 100.0)]);
+    }
+
+    #[test]
+    fn difficulty_names() {
+        assert_eq!(Difficulty::from_name("easy"), Some(Difficulty::Easy));
+        assert_eq!(Difficulty::from_name("hard"), Some(Difficulty::Hard));
+        assert_eq!(Difficulty::from_name("nightmare"), None);
+    }
+
+    #[test]
+    fn direction_survives_a_byte() {
+        for direction in [Direction::Up, Direction::Down, Direction::Stay] {
+            assert_eq!(Direction::from_u8(direction as u8), direction);
+        }
+        assert_eq!(Direction::from_u8(0xFF), Direction::Stay);
+    }
+
+    #[test]
+    fn one_player_game_plays_out() {
+        let mut game = game(GameMode::OnePlayer);
+        game.reset(fixed(123));
+        let mut ticks = 0;
+        while game.game_mode == GameMode::OnePlayer && ticks < 10_000 {
+            let (target, center) = game.ai_input();
+            game.move_ai(ai_direction(target, center));
+            game.update(fixed(123));
+            assert!(game.ball().y >= 0.0 && game.ball().y <= game.height as f32);
+            assert!(game.paddle(2).y as usize + game.paddle_height() <= game.height);
+            ticks += 1;
+        }
+        assert_eq!(game.game_mode, GameMode::GameOver);
+        assert_eq!(game.scoreboard.points(1) + game.scoreboard.points(2), WINNING_SCORE);
+    }
+
+    #[test]
+    fn paddles_stay_on_screen() {
+        let mut game = game(GameMode::TwoPlayer);
+        for _ in 0..100 {
+            game.move_paddle(true, true);
+            game.move_paddle(false, false);
+            game.update(fixed(0));
+        }
+        assert_eq!(game.paddle(1).y, 0.0);
+        assert_eq!(game.paddle(2).y as usize, game.height - game.paddle_height());
+        // Stopped by the edges
+        assert_eq!((game.paddle(1).dy, game.paddle(2).dy), (0.0, 0.0));
+
+        game.mouse_paddle(-10_000, 4);
+        assert_eq!(game.paddle(1).y as usize, game.height - game.paddle_height());
+        game.mouse_paddle(100, 8);
+        assert_eq!(game.paddle(1).y as usize, game.height - game.paddle_height() - 200);
+    }
+
+    #[test]
+    fn paddles_speed_up_and_slow_down() {
+        let mut game = game(GameMode::TwoPlayer);
+        game.paddle_mut(1).y = 200.0;
+        let mut speeds = [0.0; 8];
+        for (tick, speed) in speeds.iter_mut().enumerate() {
+            if tick < 2 {
+                game.move_paddle(true, false);
+            }
+            game.update(fixed(0));
+            *speed = game.paddle(1).dy;
+        }
+        // Pushed for two ticks and the push's three after the last
+        assert_eq!(speeds, [3.0, 6.0, 9.0, 12.0, 9.0, 6.0, 3.0, 0.0]);
+        assert_eq!(game.paddle(1).y, 200.0 + 48.0);
+
+        // Held, it goes no faster than the top speed; a tap is a short move
+        game.paddle_mut(1).y = 400.0;
+        for _ in 0..20 {
+            game.move_paddle(true, true);
+            game.update(fixed(0));
+        }
+        assert_eq!(game.paddle(1).dy, -PADDLE_MAX_SPEED as f32);
+        game.paddle_mut(1).dy = 0.0;
+        let y = game.paddle(1).y;
+        game.move_paddle(true, true);
+        for _ in 0..10 {
+            game.update(fixed(0));
+        }
+        assert_eq!(y - game.paddle(1).y, (3 + 6 + 9 + 6 + 3) as f32);
+    }
+
+    #[test]
+    fn start_clears_the_last_game() {
+        let mut game = game(GameMode::OnePlayer);
+        game.scoreboard.set_points(3, 2);
+        game.returns = 9;
+        game.rally = 4;
+        game.match_ticks = 900;
+        game.start(GameMode::TwoPlayer, fixed(0));
+        assert_eq!(game.game_mode, GameMode::TwoPlayer);
+        assert_eq!((game.scoreboard.points(1), game.scoreboard.points(2), game.returns), (0, 0, 0));
+        assert_eq!((game.rally, game.match_ticks), (0, 0));
+    }
+
+    #[test]
+    fn play_again_repeats_the_last_mode() {
+        // Whoever won the one player game, it is one player again
+        let mut game = game(GameMode::OnePlayer);
+        game.scoreboard.set_points(0, 1);
+        game.game_mode = GameMode::GameOver;
+        game.play_again(fixed(0));
+        assert_eq!(game.game_mode, GameMode::OnePlayer);
+
+        let mut game = self::game(GameMode::TwoPlayer);
+        game.scoreboard.set_points(1, 0);
+        game.game_mode = GameMode::GameOver;
+        game.play_again(fixed(0));
+        assert_eq!(game.game_mode, GameMode::TwoPlayer);
+        assert_eq!((game.scoreboard.points(1), game.scoreboard.points(2)), (0, 0));
+
+        let mut game = self::game(GameMode::Network);
+        game.game_mode = GameMode::GameOver;
+        game.play_again(fixed(0));
+        assert_eq!(game.game_mode, GameMode::TwoPlayer);
+    }
+
+    #[test]
+    fn quit_returns_to_the_menu() {
+        let mut game = game(GameMode::TwoPlayer);
+        game.scoreboard.set_points(1, 0);
+        game.game_mode = GameMode::GameOver;
+        game.quit();
+        assert_eq!(game.game_mode, GameMode::Menu);
+        assert_eq!((game.scoreboard.points(1), game.scoreboard.points(2)), (0, 0));
+        assert!(!game.is_playing());
+    }
+
+    #[test]
+    fn only_games_are_playing() {
+        let mut game = Game::new(640, 480);
+        for (mode, playing) in [
+            (GameMode::Menu, false),
+            (GameMode::Settings, false),
+            (GameMode::Controls, false),
+            (GameMode::OnePlayer, true),
+            (GameMode::TwoPlayer, true),
+            (GameMode::GameOver, false),
+            (GameMode::MemoryMap, false),
+            (GameMode::Program, false),
+            (GameMode::NetworkLobby, false),
+            (GameMode::Network, true),
+            (GameMode::Benchmark, false),
+            (GameMode::Launched, false),
+            (GameMode::Sound, false),
+            (GameMode::Profiles, false),
+            (GameMode::Accessibility, false),
+            (GameMode::LatencyTest, false),
+        ] {
+            game.game_mode = mode;
+            assert_eq!(game.is_playing(), playing, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn resize_serves_again_only_on_change() {
+        let mut game = game(GameMode::TwoPlayer);
+        game.ball_mut().x = 7.0;
+        game.resize(640, 480, fixed(0));
+        assert_eq!(game.ball().x, 7.0);
+        game.resize(800, 600, fixed(0));
+        assert_eq!((game.ball().x, game.ball().y), (400.0, 300.0));
+    }
+
+    #[test]
+    fn saved_game_restores_the_same() {
+        let mut game = game(GameMode::OnePlayer);
+        game.ball_mut().x = 123.5;
+        game.ball_mut().dy = -7.25;
+        game.paddle_mut(1).y = 17.0;
+        game.move_paddle(false, true);
+        game.paddle_mut(2).dy = -9.0;
+        game.scoreboard.set_points(0, 1);
+        game.returns = 9;
+        game.rally = 5;
+        game.match_ticks = 1234;
+        game.difficulty = Difficulty::Hard;
+        let restored = Game::restore(&game.save()).unwrap();
+        assert_eq!(restored.save(), game.save());
+        assert_eq!(restored.game_mode, GameMode::OnePlayer);
+        assert_eq!((restored.ball().x, restored.ball().dy), (123.5, -7.25));
+        assert_eq!((restored.paddle(1).y, restored.scoreboard.points(2), restored.returns), (17.0, 1, 9));
+        assert_eq!((restored.rally, restored.match_ticks), (5, 1234));
+        assert_eq!(restored.difficulty, Difficulty::Hard);
+
+        // Including which game comes next
+        let mut restored = restored;
+        restored.game_mode = GameMode::GameOver;
+        restored.play_again(fixed(0));
+        assert_eq!(restored.game_mode, GameMode::OnePlayer);
+    }
+
+    #[test]
+    fn restore_rejects_what_is_not_a_game() {
+        let saved = game(GameMode::TwoPlayer).save();
+        assert!(Game::restore(&saved[..SAVED_LEN - 1]).is_none());
+        assert!(Game::restore(&[saved.as_slice(), &[0]].concat()).is_none());
+
+        let mut bad_mode = saved;
+        bad_mode[0] = GameMode::ALL.len() as u8;
+        assert!(Game::restore(&bad_mode).is_none());
+
+        let mut nan = saved;
+        nan[3..7].copy_from_slice(&f32::NAN.to_le_bytes());
+        assert!(Game::restore(&nan).is_none());
+
+        let mut off_field = game(GameMode::TwoPlayer);
+        off_field.paddle_mut(2).y = 480.0;
+        assert!(Game::restore(&off_field.save()).is_none());
+
+        let mut too_fast = game(GameMode::TwoPlayer);
+        too_fast.paddle_mut(1).dy = (PADDLE_MAX_SPEED + 1) as f32;
+        assert!(Game::restore(&too_fast.save()).is_none());
+
+        // Points no game gets to, which would overflow on the next
+        let mut past_winning = game(GameMode::TwoPlayer);
+        past_winning.scoreboard.set_points(u32::MAX, 0);
+        assert!(Game::restore(&past_winning.save()).is_none());
+    }
+}
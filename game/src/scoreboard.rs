@@ -0,0 +1,157 @@
+// Scoring, apart from the physics and the game modes: the points of the game being played, the
+// games each player has won in a series, deuce, and when a game and the series are won.
+// [crate::Game::update] only tells it who scored, and reacts to the [Outcome] it gets back by
+// serving again or ending the match; a new way of winning is a change to the [Rules] or here.
+
+/// How a game and a series are won.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rules {
+    /// Points that win a game.
+    pub points_to_win: u32,
+    /// The lead a game is won by: 2 plays deuce, as in table tennis, with the players level one
+    /// point from winning playing on until one is two ahead; 1 does not.
+    pub win_by: u32,
+    /// Games that win the series; 1 plays single games.
+    pub games_to_win: u32,
+}
+
+impl Rules {
+    /// Pong's own: one game, to [crate::WINNING_SCORE].
+    pub const SINGLE_GAME: Rules = Rules { points_to_win: crate::WINNING_SCORE, win_by: 1, games_to_win: 1 };
+}
+
+/// What a point came to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// Play goes on.
+    Point,
+    /// Play goes on, the players level at deuce.
+    Deuce,
+    /// The point won the game for the player, by number, and the series goes on from 0 - 0.
+    GameWon(u8),
+    /// The point won the series for the player, by number: the match is over.
+    MatchWon(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Scoreboard {
+    pub rules: Rules,
+    /// Player 1's, then Player 2's.
+    points: [u32; 2],
+    games: [u32; 2],
+}
+
+impl Scoreboard {
+    pub const fn new(rules: Rules) -> Self {
+        Self { rules, points: [0; 2], games: [0; 2] }
+    }
+
+    /// Player 1's or Player 2's points in the game being played, or at the end of the match.
+    pub fn points(&self, player: u8) -> u32 {
+        self.points[player as usize - 1]
+    }
+
+    /// Games won by Player 1 or Player 2 in the series.
+    pub fn games(&self, player: u8) -> u32 {
+        self.games[player as usize - 1]
+    }
+
+    /// Puts the points at `player1` - `player2`, as for a saved game.
+    pub fn set_points(&mut self, player1: u32, player2: u32) {
+        self.points = [player1, player2];
+    }
+
+    /// Whether `player1` - `player2` can stand in a game under these rules: neither is past the
+    /// points that win a game from deuce, and only in deuce can both have the winning score.
+    pub fn can_stand(&self, player1: u32, player2: u32) -> bool {
+        let Rules { points_to_win, win_by, .. } = self.rules;
+        player1.max(player2) < points_to_win + win_by && (win_by > 1 || player1.min(player2) < points_to_win)
+    }
+
+    /// Starts a series over, at no games and no points.
+    pub fn clear(&mut self) {
+        (self.points, self.games) = ([0; 2], [0; 2]);
+    }
+
+    /// The player ahead, in games and then in points; Player 2 when they are level.
+    pub fn leader(&self) -> u8 {
+        if (self.games[0], self.points[0]) > (self.games[1], self.points[1]) { 1 } else { 2 }
+    }
+
+    /// Whether the players are level with a point or less to go, and have to play on until one is
+    /// [Rules::win_by] ahead.
+    pub fn is_deuce(&self) -> bool {
+        let [player1, player2] = self.points;
+        self.rules.win_by > 1 && player1 == player2 && player1 + 1 >= self.rules.points_to_win
+    }
+
+    /// Scores a point for `player`, 1 or 2, and says what it came to. A game won leaves the points
+    /// at 0 - 0 for the next; the match won leaves them as they were, to show.
+    pub fn point(&mut self, player: u8) -> Outcome {
+        let (scorer, other) = (player as usize - 1, 2 - player as usize);
+        self.points[scorer] += 1;
+        let (points, lead) = (self.points[scorer], self.points[scorer].saturating_sub(self.points[other]));
+        if points < self.rules.points_to_win || lead < self.rules.win_by {
+            return if self.is_deuce() { Outcome::Deuce } else { Outcome::Point };
+        }
+        self.games[scorer] += 1;
+        if self.games[scorer] >= self.rules.games_to_win {
+            return Outcome::MatchWon(player);
+        }
+        self.points = [0; 2];
+        Outcome::GameWon(player)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_game_ends_at_the_winning_score() {
+        let mut scoreboard = Scoreboard::new(Rules { points_to_win: 3, win_by: 1, games_to_win: 1 });
+        assert_eq!(scoreboard.point(2), Outcome::Point);
+        assert_eq!(scoreboard.point(1), Outcome::Point);
+        assert_eq!(scoreboard.point(2), Outcome::Point);
+        assert_eq!(scoreboard.point(2), Outcome::MatchWon(2));
+        assert_eq!((scoreboard.points(1), scoreboard.points(2), scoreboard.leader()), (1, 3, 2));
+    }
+
+    #[test]
+    fn deuce_plays_on_until_two_ahead() {
+        let mut scoreboard = Scoreboard::new(Rules { points_to_win: 3, win_by: 2, games_to_win: 1 });
+        for player in [1, 2, 1] {
+            scoreboard.point(player);
+        }
+        assert_eq!(scoreboard.point(2), Outcome::Deuce);
+        // Advantage, then deuce again
+        assert_eq!(scoreboard.point(1), Outcome::Point);
+        assert_eq!(scoreboard.point(2), Outcome::Deuce);
+        assert_eq!(scoreboard.point(1), Outcome::Point);
+        assert_eq!(scoreboard.point(1), Outcome::MatchWon(1));
+        assert_eq!((scoreboard.points(1), scoreboard.points(2)), (5, 3));
+    }
+
+    #[test]
+    fn series_goes_on_until_enough_games_are_won() {
+        let mut scoreboard = Scoreboard::new(Rules { points_to_win: 1, win_by: 1, games_to_win: 2 });
+        assert_eq!(scoreboard.point(1), Outcome::GameWon(1));
+        assert_eq!((scoreboard.points(1), scoreboard.games(1)), (0, 1));
+        assert_eq!(scoreboard.point(2), Outcome::GameWon(2));
+        assert_eq!(scoreboard.point(2), Outcome::MatchWon(2));
+        assert_eq!((scoreboard.games(1), scoreboard.games(2), scoreboard.leader()), (1, 2, 2));
+
+        scoreboard.clear();
+        assert_eq!((scoreboard.games(2), scoreboard.points(2)), (0, 0));
+    }
+
+    #[test]
+    fn only_reachable_scores_can_stand() {
+        let single = Scoreboard::new(Rules { points_to_win: 3, win_by: 1, games_to_win: 1 });
+        assert!(single.can_stand(3, 2) && single.can_stand(0, 0));
+        assert!(!single.can_stand(3, 3) && !single.can_stand(4, 0) && !single.can_stand(u32::MAX, 0));
+        let deuce = Scoreboard::new(Rules { points_to_win: 3, win_by: 2, games_to_win: 1 });
+        assert!(deuce.can_stand(3, 3) && deuce.can_stand(4, 2));
+        assert!(!deuce.can_stand(5, 3));
+    }
+}
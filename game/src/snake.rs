@@ -0,0 +1,249 @@
+// Snake's rules: a snake crawling a grid of cells one cell per step, growing by one each time it
+// eats the food, until it runs into a wall or itself. Like Pong's, they take their random numbers
+// from the caller and do not allocate: the body is a ring of cells as big as the largest grid.
+
+/// Largest grid, in cells.
+pub const MAX_COLUMNS: usize = 80;
+pub const MAX_ROWS: usize = 50;
+/// Cells in a new snake.
+pub const START_LEN: usize = 3;
+
+const MAX_LEN: usize = MAX_COLUMNS * MAX_ROWS;
+
+/// Which way the snake crawls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Heading {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Heading {
+    fn opposite(self) -> Heading {
+        match self {
+            Heading::Up => Heading::Down,
+            Heading::Down => Heading::Up,
+            Heading::Left => Heading::Right,
+            Heading::Right => Heading::Left,
+        }
+    }
+}
+
+/// What happened during one [Snake::step].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Events {
+    pub ate: bool,
+    /// The snake ran into a wall or itself; the game is over.
+    pub died: bool,
+}
+
+pub struct Snake {
+    pub columns: usize,
+    pub rows: usize,
+    /// Cells of the body as (column, row), a ring from [Self::tail] with the head last.
+    body: [(u8, u8); MAX_LEN],
+    tail: usize,
+    len: usize,
+    /// The way it moved on its last step, and the way it will move on the next.
+    moved: Heading,
+    heading: Heading,
+    pub food: (usize, usize),
+    pub over: bool,
+}
+
+impl Snake {
+    /// A snake of [START_LEN] cells in the middle of a `columns` x `rows` grid, heading right,
+    /// and food somewhere else. The grid is cut down to [MAX_COLUMNS] x [MAX_ROWS], and must be at
+    /// least [START_LEN] + 2 columns wide and 1 row high.
+    pub fn new(columns: usize, rows: usize, random: impl FnMut() -> u32) -> Self {
+        let (columns, rows) = (columns.min(MAX_COLUMNS), rows.min(MAX_ROWS));
+        let mut snake = Snake {
+            columns,
+            rows,
+            body: [(0, 0); MAX_LEN],
+            tail: 0,
+            len: START_LEN,
+            moved: Heading::Right,
+            heading: Heading::Right,
+            food: (0, 0),
+            over: false,
+        };
+        let (x, y) = (columns / 2, rows / 2);
+        for (i, cell) in snake.body[..START_LEN].iter_mut().enumerate() {
+            *cell = ((x + i + 1 - START_LEN) as u8, y as u8);
+        }
+        snake.place_food(random);
+        snake
+    }
+
+    /// The body's cells, from the tail to the head.
+    pub fn body(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        (0..self.len).map(move |i| {
+            let (x, y) = self.body[(self.tail + i) % MAX_LEN];
+            (x as usize, y as usize)
+        })
+    }
+
+    pub fn head(&self) -> (usize, usize) {
+        let (x, y) = self.body[(self.tail + self.len - 1) % MAX_LEN];
+        (x as usize, y as usize)
+    }
+
+    /// Food eaten so far.
+    pub fn score(&self) -> usize {
+        self.len - START_LEN
+    }
+
+    /// Heads `heading` from the next step. Turning right round, back into the body, is ignored.
+    pub fn turn(&mut self, heading: Heading) {
+        if heading != self.moved.opposite() {
+            self.heading = heading;
+        }
+    }
+
+    /// Moves one cell. Eating the food grows the snake by one and puts new food down. Does
+    /// nothing once the game is over.
+    pub fn step(&mut self, random: impl FnMut() -> u32) -> Events {
+        let mut events = Events::default();
+        if self.over {
+            return events;
+        }
+        let (x, y) = self.head();
+        let next = match self.heading {
+            Heading::Up => y.checked_sub(1).map(|y| (x, y)),
+            Heading::Down => Some((x, y + 1)).filter(|&(_, y)| y < self.rows),
+            Heading::Left => x.checked_sub(1).map(|x| (x, y)),
+            Heading::Right => Some((x + 1, y)).filter(|&(x, _)| x < self.columns),
+        };
+        self.moved = self.heading;
+        let ate = next == Some(self.food);
+        // The tail moves on as the head does, unless the snake grows, so the head may take its cell
+        let body_len = if ate { self.len } else { self.len - 1 };
+        let Some(next) = next.filter(|&next| !self.body().skip(self.len - body_len).any(|cell| cell == next)) else {
+            self.over = true;
+            events.died = true;
+            return events;
+        };
+        if ate {
+            self.len += 1;
+        } else {
+            self.tail = (self.tail + 1) % MAX_LEN;
+        }
+        self.body[(self.tail + self.len - 1) % MAX_LEN] = (next.0 as u8, next.1 as u8);
+        if ate {
+            events.ate = true;
+            self.place_food(random);
+        }
+        events
+    }
+
+    /// Puts the food on a random free cell; a full grid leaves it where it is.
+    fn place_food(&mut self, mut random: impl FnMut() -> u32) {
+        let cells = self.columns * self.rows;
+        let free = cells - self.len.min(cells);
+        if free == 0 {
+            return;
+        }
+        // The n-th free cell, counting across the rows
+        let mut n = random() as usize % free;
+        for i in 0..cells {
+            let cell = (i % self.columns, i / self.columns);
+            if self.body().any(|body| body == cell) {
+                continue;
+            }
+            if n == 0 {
+                self.food = cell;
+                return;
+            }
+            n -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixed(value: u32) -> impl FnMut() -> u32 {
+        move || value
+    }
+
+    #[test]
+    fn new_snake_heads_right_from_the_middle() {
+        let snake = Snake::new(20, 10, fixed(0));
+        assert_eq!(snake.head(), (10, 5));
+        assert_eq!(snake.body().collect::<Vec<_>>(), [(8, 5), (9, 5), (10, 5)]);
+        // The first free cell
+        assert_eq!(snake.food, (0, 0));
+    }
+
+    #[test]
+    fn eating_grows_the_snake() {
+        let mut snake = Snake::new(20, 10, fixed(0));
+        snake.food = (11, 5);
+        let events = snake.step(fixed(0));
+        assert!(events.ate);
+        assert_eq!((snake.body().count(), snake.score()), (START_LEN + 1, 1));
+        assert_eq!(snake.body().next(), Some((8, 5)));
+        assert_ne!(snake.food, (11, 5));
+
+        snake.step(fixed(0));
+        assert_eq!(snake.body().count(), START_LEN + 1);
+        assert_eq!(snake.body().next(), Some((9, 5)));
+    }
+
+    #[test]
+    fn turning_back_is_ignored() {
+        let mut snake = Snake::new(20, 10, fixed(0));
+        snake.turn(Heading::Left);
+        snake.step(fixed(0));
+        assert_eq!(snake.head(), (11, 5));
+
+        // Checked against the last step, not the last turn: up and then left still goes up
+        snake.turn(Heading::Up);
+        snake.turn(Heading::Left);
+        snake.step(fixed(0));
+        assert_eq!(snake.head(), (11, 4));
+    }
+
+    #[test]
+    fn running_into_a_wall_ends_the_game() {
+        let mut snake = Snake::new(6, 3, fixed(0));
+        snake.turn(Heading::Up);
+        assert!(!snake.step(fixed(0)).died);
+        let events = snake.step(fixed(0));
+        assert!(events.died && snake.over);
+        assert_eq!(snake.step(fixed(0)), Events::default());
+    }
+
+    #[test]
+    fn running_into_itself_ends_the_game_but_not_into_its_tail() {
+        let mut snake = Snake::new(20, 10, fixed(0));
+        for _ in 0..2 {
+            snake.food = (snake.head().0 + 1, 5);
+            snake.step(fixed(7));
+        }
+        // Five long: down, left and up runs into the body
+        assert_eq!(snake.body().count(), 5);
+        snake.food = (0, 0);
+        snake.turn(Heading::Down);
+        snake.step(fixed(0));
+        snake.turn(Heading::Left);
+        snake.step(fixed(0));
+        snake.turn(Heading::Up);
+        assert!(snake.step(fixed(0)).died);
+
+        // Four long, the same square only reaches the cell the tail is leaving
+        let mut snake = Snake::new(20, 10, fixed(0));
+        snake.food = (11, 5);
+        snake.step(fixed(0));
+        snake.food = (0, 0);
+        snake.turn(Heading::Down);
+        snake.step(fixed(0));
+        snake.turn(Heading::Left);
+        snake.step(fixed(0));
+        snake.turn(Heading::Up);
+        assert!(!snake.step(fixed(0)).died);
+    }
+}
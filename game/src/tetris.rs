@@ -0,0 +1,284 @@
+// Tetris's rules: pieces of four cells fall into a well [COLUMNS] wide and [ROWS] deep, moved
+// sideways and rotated on the way; a piece that cannot fall further locks in place, full rows are
+// cleared and scored, and the game ends when a new piece has no room at the top. Random numbers
+// come from the caller, as for Pong.
+
+pub const COLUMNS: usize = 10;
+pub const ROWS: usize = 20;
+/// Points for clearing 1, 2, 3 or 4 rows at once, times the level.
+const LINE_SCORES: [u32; 5] = [0, 100, 300, 500, 800];
+/// Rows cleared per level.
+const LINES_PER_LEVEL: u32 = 10;
+
+/// The seven pieces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shape {
+    I,
+    O,
+    T,
+    S,
+    Z,
+    J,
+    L,
+}
+
+impl Shape {
+    pub const ALL: [Shape; 7] = [Shape::I, Shape::O, Shape::T, Shape::S, Shape::Z, Shape::J, Shape::L];
+
+    fn random(mut random: impl FnMut() -> u32) -> Shape {
+        Shape::ALL[random() as usize % Shape::ALL.len()]
+    }
+
+    /// The piece's cells in its bounding box as (column, row) before any rotation, and the size
+    /// of the box, which it rotates in.
+    fn cells(self) -> ([(i8, i8); 4], i8) {
+        match self {
+            Shape::I => ([(0, 1), (1, 1), (2, 1), (3, 1)], 4),
+            Shape::O => ([(0, 0), (1, 0), (0, 1), (1, 1)], 2),
+            Shape::T => ([(1, 0), (0, 1), (1, 1), (2, 1)], 3),
+            Shape::S => ([(1, 0), (2, 0), (0, 1), (1, 1)], 3),
+            Shape::Z => ([(0, 0), (1, 0), (1, 1), (2, 1)], 3),
+            Shape::J => ([(0, 0), (0, 1), (1, 1), (2, 1)], 3),
+            Shape::L => ([(2, 0), (0, 1), (1, 1), (2, 1)], 3),
+        }
+    }
+}
+
+/// A piece on its way down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Piece {
+    pub shape: Shape,
+    /// Top left of its bounding box in the well.
+    x: i8,
+    y: i8,
+    /// Quarter turns clockwise.
+    rotation: u8,
+}
+
+impl Piece {
+    /// A piece at the top of the well, in the middle.
+    fn new(shape: Shape) -> Piece {
+        let (_, size) = shape.cells();
+        Piece { shape, x: (COLUMNS as i8 - size) / 2, y: 0, rotation: 0 }
+    }
+
+    /// A piece unturned at the top left of the well, for showing which comes next.
+    pub fn preview(shape: Shape) -> Piece {
+        Piece { shape, x: 0, y: 0, rotation: 0 }
+    }
+
+    /// The cells of the well it covers, as (column, row).
+    pub fn cells(&self) -> [(i8, i8); 4] {
+        let (mut cells, size) = self.shape.cells();
+        for _ in 0..self.rotation {
+            for cell in &mut cells {
+                *cell = (size - 1 - cell.1, cell.0);
+            }
+        }
+        cells.map(|(x, y)| (self.x + x, self.y + y))
+    }
+}
+
+/// What happened during one [Tetris::fall] or [Tetris::hard_drop].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Events {
+    /// The piece came to rest and the next one started.
+    pub locked: bool,
+    /// Rows cleared by it.
+    pub lines: u8,
+    /// The next piece had no room; the game is over.
+    pub game_over: bool,
+}
+
+pub struct Tetris {
+    /// The shape each cell was part of, by row from the top, or None if it is empty.
+    well: [[Option<Shape>; COLUMNS]; ROWS],
+    pub piece: Piece,
+    /// The shape after this one.
+    pub next: Shape,
+    pub score: u32,
+    /// Rows cleared.
+    pub lines: u32,
+    pub over: bool,
+}
+
+impl Tetris {
+    pub fn new(mut random: impl FnMut() -> u32) -> Self {
+        let piece = Piece::new(Shape::random(&mut random));
+        Tetris { well: [[None; COLUMNS]; ROWS], piece, next: Shape::random(random), score: 0, lines: 0, over: false }
+    }
+
+    /// What is in the well at (column, row), not counting the falling piece.
+    pub fn cell(&self, column: usize, row: usize) -> Option<Shape> {
+        self.well[row][column]
+    }
+
+    /// Starts at 1 and goes up every [LINES_PER_LEVEL] rows cleared; the pieces fall faster.
+    pub fn level(&self) -> u32 {
+        self.lines / LINES_PER_LEVEL + 1
+    }
+
+    fn fits(&self, piece: &Piece) -> bool {
+        piece.cells().iter().all(|&(x, y)| {
+            (0..COLUMNS as i8).contains(&x) && (0..ROWS as i8).contains(&y) && self.well[y as usize][x as usize].is_none()
+        })
+    }
+
+    /// Puts the piece at `piece` if it fits there.
+    fn try_move(&mut self, piece: Piece) -> bool {
+        let fits = !self.over && self.fits(&piece);
+        if fits {
+            self.piece = piece;
+        }
+        fits
+    }
+
+    /// Moves the piece a column left (-1) or right (1), if there is room.
+    pub fn shift(&mut self, dx: i8) -> bool {
+        self.try_move(Piece { x: self.piece.x + dx, ..self.piece })
+    }
+
+    /// Turns the piece a quarter clockwise. Against a wall or another piece it may move a column
+    /// or two sideways to make room; without any, it stays as it was.
+    pub fn rotate(&mut self) -> bool {
+        let turned = Piece { rotation: (self.piece.rotation + 1) % 4, ..self.piece };
+        [0, -1, 1, -2, 2].into_iter().any(|dx| self.try_move(Piece { x: turned.x + dx, ..turned }))
+    }
+
+    /// Moves the piece down a row, or locks it where it is if it cannot go further.
+    pub fn fall(&mut self, random: impl FnMut() -> u32) -> Events {
+        if self.over || self.try_move(Piece { y: self.piece.y + 1, ..self.piece }) {
+            return Events::default();
+        }
+        self.lock(random)
+    }
+
+    /// Drops the piece as far as it goes and locks it there.
+    pub fn hard_drop(&mut self, random: impl FnMut() -> u32) -> Events {
+        while self.try_move(Piece { y: self.piece.y + 1, ..self.piece }) {}
+        self.fall(random)
+    }
+
+    /// Leaves the piece in the well, clears the rows it filled, and starts the next one.
+    fn lock(&mut self, random: impl FnMut() -> u32) -> Events {
+        let mut events = Events { locked: true, ..Events::default() };
+        for (x, y) in self.piece.cells() {
+            self.well[y as usize][x as usize] = Some(self.piece.shape);
+        }
+        let mut row = ROWS;
+        while row > 0 {
+            row -= 1;
+            if self.well[row].iter().all(Option::is_some) {
+                // Everything above comes down a row, and this row is looked at again
+                self.well.copy_within(0..row, 1);
+                self.well[0] = [None; COLUMNS];
+                events.lines += 1;
+                row += 1;
+            }
+        }
+        self.score += LINE_SCORES[events.lines as usize] * self.level();
+        self.lines += events.lines as u32;
+
+        self.piece = Piece::new(self.next);
+        self.next = Shape::random(random);
+        if !self.fits(&self.piece) {
+            self.over = true;
+            events.game_over = true;
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixed(value: u32) -> impl FnMut() -> u32 {
+        move || value
+    }
+
+    /// A game whose pieces are all `shape`.
+    fn tetris(shape: Shape) -> Tetris {
+        let index = Shape::ALL.iter().position(|&s| s == shape).unwrap() as u32;
+        Tetris::new(fixed(index))
+    }
+
+    #[test]
+    fn pieces_start_at_the_top_in_the_middle_and_fall() {
+        let mut tetris = tetris(Shape::T);
+        assert_eq!(tetris.piece.cells(), [(4, 0), (3, 1), (4, 1), (5, 1)]);
+        assert_eq!(tetris.fall(fixed(2)), Events::default());
+        assert_eq!(tetris.piece.cells(), [(4, 1), (3, 2), (4, 2), (5, 2)]);
+    }
+
+    #[test]
+    fn walls_stop_the_piece() {
+        let mut tetris = tetris(Shape::O);
+        let moves = (0..10).filter(|_| tetris.shift(-1)).count();
+        assert_eq!(moves, 4);
+        assert_eq!(tetris.piece.cells()[0], (0, 0));
+    }
+
+    #[test]
+    fn rotating_turns_the_piece_clockwise() {
+        let mut tetris = tetris(Shape::T);
+        tetris.fall(fixed(2));
+        assert!(tetris.rotate());
+        let mut cells = tetris.piece.cells();
+        cells.sort();
+        assert_eq!(cells, [(4, 1), (4, 2), (4, 3), (5, 2)]);
+    }
+
+    #[test]
+    fn rotating_against_a_wall_moves_the_piece_off_it() {
+        let mut tetris = tetris(Shape::I);
+        tetris.fall(fixed(0));
+        tetris.rotate();
+        while tetris.shift(1) {}
+        // Upright against the right wall; lying down again needs three columns to its left
+        assert!(tetris.rotate());
+        assert!(tetris.piece.cells().iter().all(|&(x, _)| x < COLUMNS as i8));
+        assert_eq!(tetris.piece.cells().iter().map(|&(x, _)| x).max(), Some(COLUMNS as i8 - 1));
+    }
+
+    #[test]
+    fn dropped_piece_locks_at_the_bottom() {
+        let mut tetris = tetris(Shape::O);
+        let events = tetris.hard_drop(fixed(1));
+        assert_eq!(events, Events { locked: true, lines: 0, game_over: false });
+        assert_eq!(tetris.cell(4, ROWS - 1), Some(Shape::O));
+        assert_eq!(tetris.cell(5, ROWS - 2), Some(Shape::O));
+        assert_eq!(tetris.piece.cells()[0], (4, 0));
+    }
+
+    #[test]
+    fn full_rows_clear_and_score() {
+        let mut tetris = tetris(Shape::I);
+        for row in [ROWS - 2, ROWS - 1] {
+            for cell in tetris.well[row].iter_mut().take(COLUMNS - 4) {
+                *cell = Some(Shape::J);
+            }
+        }
+        tetris.well[ROWS - 3][0] = Some(Shape::L);
+        while tetris.shift(1) {}
+        let events = tetris.hard_drop(fixed(0));
+        assert_eq!(events.lines, 1);
+        assert_eq!((tetris.score, tetris.lines), (100, 1));
+        // The rows above came down one
+        assert_eq!(tetris.cell(0, ROWS - 1), Some(Shape::J));
+        assert_eq!(tetris.cell(COLUMNS - 1, ROWS - 1), None);
+        assert_eq!(tetris.cell(0, ROWS - 2), Some(Shape::L));
+    }
+
+    #[test]
+    fn no_room_for_the_next_piece_ends_the_game() {
+        let mut tetris = tetris(Shape::O);
+        for row in tetris.well.iter_mut().skip(2) {
+            row[4] = Some(Shape::J);
+        }
+        let events = tetris.hard_drop(fixed(1));
+        assert!(events.game_over && tetris.over);
+        assert!(!tetris.shift(1));
+        assert_eq!(tetris.fall(fixed(1)), Events::default());
+    }
+}
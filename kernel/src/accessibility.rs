@@ -0,0 +1,103 @@
+use pc_keyboard::DecodedKey;
+use crate::screen::screenwriter;
+use crate::strings::{Text, fill, text};
+use crate::theme::{self, Theme};
+
+const ITEM_COUNT: usize = 3;
+/// Sizes the ball and the paddles can be drawn at, in percent.
+pub const SIZES: [usize; 3] = [100, 150, 200];
+
+/// Options for players who find the game hard to see or flashing hard to take, edited from the
+/// accessibility screen, which the settings screen opens. They only change what is drawn: the ball
+/// and the paddles are drawn bigger, but the game plays at their usual size.
+pub struct AccessibilitySettings {
+    /// The [theme::HIGH_CONTRAST] colors instead of the theme's.
+    pub high_contrast: bool,
+    /// One of [SIZES].
+    pub size: usize,
+    /// Leaves out what flashes: the score's pop, the rainbow ball, the pulsing prompts and the
+    /// keyboard's lights for a point.
+    pub reduced_flash: bool,
+    selected: usize,
+}
+
+impl AccessibilitySettings {
+    pub const fn new() -> Self {
+        Self { high_contrast: false, size: 100, reduced_flash: false, selected: 0 }
+    }
+
+    /// The colors to draw with, given the picked `theme`.
+    pub fn theme(&self, theme: &'static Theme) -> &'static Theme {
+        if self.high_contrast { &theme::HIGH_CONTRAST } else { theme }
+    }
+
+    /// How far from its center a ball `radius` pixels across is drawn.
+    pub fn ball_radius(&self, radius: usize) -> usize {
+        radius * self.size / 100
+    }
+
+    /// How many pixels wide a paddle is drawn: 1 at the usual size.
+    pub fn paddle_width(&self) -> usize {
+        1 + (self.size - 100) / 25
+    }
+
+    pub fn draw(&self) {
+        screenwriter().draw_string_centered(100, text(Text::AccessibilityTitle), 0xFF, 0xFF, 0xFF);
+
+        for i in 0..ITEM_COUNT {
+            let (r, g, b) = if i == self.selected { (0xFF, 0xFF, 0x55) } else { (0xAA, 0xAA, 0xAA) };
+            let label = match i {
+                0 => fill(Text::HighContrast, &[&on_off(self.high_contrast)]),
+                1 => fill(Text::DrawSize, &[&self.size]),
+                _ => fill(Text::ReducedFlash, &[&on_off(self.reduced_flash)]),
+            };
+            screenwriter().draw_string_centered(130 + i * 20, &label, r, g, b);
+        }
+
+        let y = 130 + ITEM_COUNT * 20 + 20;
+        screenwriter().draw_string_centered(y, text(Text::SettingsHelp), 0xFF, 0xFF, 0xFF);
+        screenwriter().draw_string_centered(y + 20, text(Text::ReturnToSettings), 0xFF, 0xFF, 0xFF);
+    }
+
+    /// Handles a key press on the accessibility screen. Returns false when the player leaves the
+    /// screen.
+    pub fn handle_key(&mut self, key: DecodedKey) -> bool {
+        let increase = key == DecodedKey::Unicode('d');
+        match key {
+            DecodedKey::Unicode('w') => self.selected = (self.selected + ITEM_COUNT - 1) % ITEM_COUNT,
+            DecodedKey::Unicode('s') => self.selected = (self.selected + 1) % ITEM_COUNT,
+            DecodedKey::Unicode('a' | 'd') => match self.selected {
+                0 => self.high_contrast = !self.high_contrast,
+                1 => {
+                    let current = SIZES.iter().position(|&size| size == self.size).unwrap_or(0);
+                    let next = if increase { (current + 1).min(SIZES.len() - 1) } else { current.saturating_sub(1) };
+                    self.size = SIZES[next];
+                }
+                _ => self.reduced_flash = !self.reduced_flash,
+            },
+            DecodedKey::Unicode('r') => return false,
+            _ => {}
+        }
+        true
+    }
+}
+
+fn on_off(on: bool) -> &'static str {
+    text(if on { Text::On } else { Text::Off })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn accessibility_changes_only_the_drawing() {
+        let mut accessibility = AccessibilitySettings::new();
+        assert!(core::ptr::eq(accessibility.theme(&theme::THEMES[1]), &theme::THEMES[1]));
+        assert_eq!((accessibility.ball_radius(6), accessibility.paddle_width()), (6, 1));
+        accessibility.high_contrast = true;
+        accessibility.size = 200;
+        assert!(core::ptr::eq(accessibility.theme(&theme::THEMES[1]), &theme::HIGH_CONTRAST));
+        assert_eq!((accessibility.ball_radius(6), accessibility.paddle_width()), (12, 5));
+    }
+}
@@ -0,0 +1,326 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::ops::RangeInclusive;
+use core::ptr::NonNull;
+use acpi::address::{AddressSpace, GenericAddress};
+use acpi::fadt::Fadt as FadtTable;
+use acpi::hpet::HpetInfo;
+use acpi::mcfg::Mcfg;
+use acpi::platform::interrupt::{Polarity, TriggerMode};
+use acpi::platform::ProcessorState;
+use acpi::{AcpiHandler, AcpiTable, AcpiTables, InterruptModel, PhysicalMapping};
+use spin::Once;
+use x86_64::{PhysAddr, VirtAddr};
+use crate::ioapic::RouteFlags;
+use crate::{memory, serial};
+
+// The ACPI tables the kernel uses, read once at boot from the RSDP the bootloader found.
+//
+// The acpi crate walks the XSDT (or RSDT) and checks each table; this turns the ones the kernel
+// needs into plain structures, so the rest of the kernel never touches the raw tables: the MADT
+// (processors, IOAPICs, interrupt overrides), the FADT (power management registers), the HPET and
+// the MCFG (PCI Express configuration space).
+
+pub struct AcpiHandlerImpl {
+    physical_memory_offset: VirtAddr,
+}
+
+impl AcpiHandlerImpl {
+    pub fn new(physical_memory_offset: VirtAddr) -> Self {
+        Self {
+            physical_memory_offset,
+        }
+    }
+}
+
+unsafe impl Send for AcpiHandlerImpl {}
+unsafe impl Sync for AcpiHandlerImpl {}
+
+impl Clone for AcpiHandlerImpl {
+    fn clone(&self) -> Self {
+        Self {
+            physical_memory_offset: self.physical_memory_offset,
+        }
+    }
+}
+
+impl AcpiHandler for AcpiHandlerImpl {
+    unsafe fn map_physical_region<T>(
+        &self,
+        physical_address: usize,
+        size: usize,
+    ) -> PhysicalMapping<Self, T> {
+        let phys_addr = PhysAddr::new(physical_address as u64);
+        let virt_addr = self.physical_memory_offset + phys_addr.as_u64();
+
+        unsafe {
+            PhysicalMapping::new(
+                physical_address,
+                NonNull::new(virt_addr.as_mut_ptr()).expect("Failed to get virtual address"),
+                size,
+                size,
+                self.clone(),
+            )
+        }
+    }
+
+    fn unmap_physical_region<T>(_region: &PhysicalMapping<Self, T>) {
+        // No unmapping necessary as we didn't create any new mappings
+    }
+}
+
+/// A processor listed in the MADT.
+#[derive(Debug, Clone, Copy)]
+pub struct Processor {
+    pub uid: u32,
+    pub local_apic_id: u32,
+    /// The processor the firmware booted, which runs the kernel.
+    pub is_boot: bool,
+    /// False if the firmware marked it disabled; it must not be started.
+    pub usable: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct IoApic {
+    pub id: u8,
+    pub address: u32,
+    /// The first global system interrupt it handles.
+    pub gsi_base: u32,
+}
+
+/// An ISA interrupt wired to another input than its number, or with other electrical properties.
+#[derive(Debug, Clone, Copy)]
+pub struct InterruptOverride {
+    pub isa_irq: u8,
+    pub gsi: u32,
+    pub flags: RouteFlags,
+}
+
+/// Multiple APIC Description Table: the interrupt controllers and processors.
+#[derive(Debug)]
+pub struct Madt {
+    pub local_apic_address: u64,
+    pub processors: Vec<Processor>,
+    pub io_apics: Vec<IoApic>,
+    pub overrides: Vec<InterruptOverride>,
+    /// Whether there are 8259 PICs as well, which must be masked.
+    pub has_legacy_pics: bool,
+}
+
+impl Madt {
+    pub fn boot_processor(&self) -> Option<&Processor> {
+        self.processors.iter().find(|processor| processor.is_boot)
+    }
+
+    /// The application processors that can be started.
+    pub fn application_processors(&self) -> impl Iterator<Item = &Processor> {
+        self.processors.iter().filter(|processor| !processor.is_boot && processor.usable)
+    }
+}
+
+/// The reset register, if it is an I/O port: writing `value` to `port` resets the machine.
+#[derive(Debug, Clone, Copy)]
+pub struct ResetRegister {
+    pub port: u16,
+    pub value: u8,
+}
+
+/// Fixed ACPI Description Table: the power management registers. Only registers in I/O space are
+/// kept; the others are None.
+#[derive(Debug, Clone, Copy)]
+pub struct Fadt {
+    /// ISA interrupt of the ACPI system control interrupt.
+    pub sci_interrupt: u16,
+    /// Where to write [Fadt::acpi_enable] to take the power registers over from the firmware; 0
+    /// if they already belong to the OS.
+    pub smi_command: u16,
+    pub acpi_enable: u8,
+    pub pm1a_event: Option<u16>,
+    pub pm1a_control: Option<u16>,
+    pub pm1b_control: Option<u16>,
+    /// The 3.579545 MHz power management timer.
+    pub pm_timer: Option<u16>,
+    pub pm_timer_is_32_bit: bool,
+    pub reset: Option<ResetRegister>,
+    /// CMOS register holding the century, or 0.
+    pub century_register: u8,
+    /// Whether there is an 8042 PS/2 controller (always assumed before ACPI 2.0, which had no flag).
+    pub has_8042: bool,
+}
+
+/// High Precision Event Timer.
+#[derive(Debug, Clone, Copy)]
+pub struct Hpet {
+    pub base_address: u64,
+    pub comparators: u8,
+    pub counter_is_64_bit: bool,
+    pub legacy_replacement: bool,
+    /// Smallest period, in counter ticks, a periodic timer can be set to without losing interrupts.
+    pub minimum_tick: u16,
+}
+
+/// A window of memory-mapped PCI Express configuration space, from the MCFG.
+#[derive(Debug, Clone)]
+pub struct EcamRegion {
+    pub segment_group: u16,
+    pub buses: RangeInclusive<u8>,
+    pub base_address: u64,
+}
+
+struct Tables {
+    signatures: Vec<String>,
+    madt: Option<Madt>,
+    fadt: Option<Fadt>,
+    hpet: Option<Hpet>,
+    mcfg: Vec<EcamRegion>,
+    /// Physical address and length of the DSDT's AML.
+    dsdt: Option<(PhysAddr, usize)>,
+}
+
+static TABLES: Once<Tables> = Once::new();
+
+fn io_port(address: GenericAddress) -> Option<u16> {
+    (address.address_space == AddressSpace::SystemIo && address.address != 0).then_some(address.address as u16)
+}
+
+fn route_flags(polarity: Polarity, trigger_mode: TriggerMode) -> RouteFlags {
+    // ISA defaults apply where the override defers to the bus
+    RouteFlags {
+        active_low: polarity == Polarity::ActiveLow,
+        level_triggered: trigger_mode == TriggerMode::Level,
+        masked: false,
+    }
+}
+
+/// Reads the tables from the RSDP at physical address `rsdp` and logs what was found. Panics if
+/// there is no valid RSDP, since the kernel cannot set up interrupts without the MADT.
+pub fn init(rsdp: usize, physical_offset: VirtAddr) {
+    let handler = AcpiHandlerImpl::new(physical_offset);
+    let tables = unsafe { AcpiTables::from_rsdp(handler, rsdp).expect("Failed to parse ACPI tables") };
+    let tables = TABLES.call_once(|| Tables {
+        signatures: tables.headers().map(|header| String::from(header.signature.as_str())).collect(),
+        madt: read_madt(&tables),
+        fadt: read_fadt(&tables),
+        hpet: HpetInfo::new(&tables).ok().map(|hpet| Hpet {
+            base_address: hpet.base_address as u64,
+            comparators: hpet.num_comparators(),
+            counter_is_64_bit: hpet.main_counter_is_64bits(),
+            legacy_replacement: hpet.legacy_irq_capable(),
+            minimum_tick: hpet.clock_tick_unit,
+        }),
+        mcfg: read_mcfg(&tables),
+        dsdt: tables.dsdt().ok().map(|dsdt| (PhysAddr::new(dsdt.address as u64), dsdt.length as usize)),
+    });
+
+    writeln!(serial(), "ACPI: tables {}", tables.signatures.join(" ")).unwrap();
+    if let Some(madt) = &tables.madt {
+        writeln!(serial(), "ACPI: {} processors ({} usable), {} IOAPICs, {} interrupt overrides", madt.processors.len(), madt.processors.iter().filter(|p| p.usable).count(), madt.io_apics.len(), madt.overrides.len()).unwrap();
+    }
+    if let Some(fadt) = &tables.fadt {
+        writeln!(serial(), "ACPI: SCI on IRQ {}, PM1a control {:x?}, PM timer {:x?}", fadt.sci_interrupt, fadt.pm1a_control, fadt.pm_timer).unwrap();
+    }
+    if let Some(hpet) = &tables.hpet {
+        writeln!(serial(), "ACPI: HPET at {:#x}, {} comparators", hpet.base_address, hpet.comparators).unwrap();
+    }
+    for region in &tables.mcfg {
+        writeln!(serial(), "ACPI: ECAM segment {} buses {:?} at {:#x}", region.segment_group, region.buses, region.base_address).unwrap();
+    }
+}
+
+fn read_madt<H: AcpiHandler>(tables: &AcpiTables<H>) -> Option<Madt> {
+    let platform_info = tables.platform_info().ok()?;
+    let InterruptModel::Apic(apic) = platform_info.interrupt_model else { return None };
+
+    let mut processors = Vec::new();
+    if let Some(info) = &platform_info.processor_info {
+        for (processor, is_boot) in core::iter::once((&info.boot_processor, true)).chain(info.application_processors.iter().map(|ap| (ap, false))) {
+            processors.push(Processor {
+                uid: processor.processor_uid,
+                local_apic_id: processor.local_apic_id,
+                is_boot,
+                usable: processor.state != ProcessorState::Disabled,
+            });
+        }
+    }
+    Some(Madt {
+        local_apic_address: apic.local_apic_address,
+        processors,
+        io_apics: apic.io_apics.iter().map(|io_apic| IoApic {
+            id: io_apic.id,
+            address: io_apic.address,
+            gsi_base: io_apic.global_system_interrupt_base,
+        }).collect(),
+        overrides: apic.interrupt_source_overrides.iter().map(|source| InterruptOverride {
+            isa_irq: source.isa_source,
+            gsi: source.global_system_interrupt,
+            flags: route_flags(source.polarity, source.trigger_mode),
+        }).collect(),
+        has_legacy_pics: apic.also_has_legacy_pics,
+    })
+}
+
+fn read_fadt<H: AcpiHandler>(tables: &AcpiTables<H>) -> Option<Fadt> {
+    let fadt = tables.find_table::<FadtTable>().ok()?;
+    let flags = fadt.flags;
+    let reset = if flags.supports_system_reset_via_fadt() {
+        fadt.reset_register().ok().and_then(io_port).map(|port| ResetRegister { port, value: fadt.reset_value })
+    } else {
+        None
+    };
+    // Revision 1 tables predate the flag
+    let has_8042 = fadt.header().revision < 2 || { fadt.iapc_boot_arch }.motherboard_implements_8042();
+    Some(Fadt {
+        sci_interrupt: fadt.sci_interrupt,
+        smi_command: fadt.smi_cmd_port as u16,
+        acpi_enable: fadt.acpi_enable,
+        pm1a_event: fadt.pm1a_event_block().ok().and_then(io_port),
+        pm1a_control: fadt.pm1a_control_block().ok().and_then(io_port),
+        pm1b_control: fadt.pm1b_control_block().ok().flatten().and_then(io_port),
+        pm_timer: fadt.pm_timer_block().ok().flatten().and_then(io_port),
+        pm_timer_is_32_bit: flags.pm_timer_is_32_bit(),
+        reset,
+        century_register: fadt.century,
+        has_8042,
+    })
+}
+
+fn read_mcfg<H: AcpiHandler>(tables: &AcpiTables<H>) -> Vec<EcamRegion> {
+    let Ok(mcfg) = tables.find_table::<Mcfg>() else { return Vec::new() };
+    // The entries are packed; copy them out before reading fields
+    mcfg.entries().iter().map(|&entry| EcamRegion {
+        segment_group: entry.pci_segment_group,
+        buses: entry.bus_number_start..=entry.bus_number_end,
+        base_address: entry.base_address,
+    }).collect()
+}
+
+/// The MADT, or None before [init] or on a machine without APICs.
+pub fn madt() -> Option<&'static Madt> {
+    TABLES.get()?.madt.as_ref()
+}
+
+pub fn fadt() -> Option<&'static Fadt> {
+    TABLES.get()?.fadt.as_ref()
+}
+
+pub fn hpet() -> Option<&'static Hpet> {
+    TABLES.get()?.hpet.as_ref()
+}
+
+/// The ECAM windows from the MCFG; empty without one.
+pub fn mcfg() -> &'static [EcamRegion] {
+    TABLES.get().map_or(&[], |tables| tables.mcfg.as_slice())
+}
+
+/// The DSDT's AML bytecode.
+pub fn dsdt() -> Option<&'static [u8]> {
+    let (address, len) = TABLES.get()?.dsdt?;
+    let aml = memory::phys_to_virt(address);
+    Some(unsafe { core::slice::from_raw_parts(aml.as_ptr(), len) })
+}
+
+/// Signatures of all tables in the XSDT, in order.
+pub fn signatures() -> impl Iterator<Item = &'static str> {
+    TABLES.get().into_iter().flat_map(|tables| tables.signatures.iter().map(String::as_str))
+}
@@ -0,0 +1,375 @@
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::time::Duration;
+use x86_64::PhysAddr;
+use crate::block::{self, BlockError, SECTOR_SIZE};
+use crate::dma::{self, DmaBuffer};
+use crate::pci::{self, Bar};
+use crate::sync::IrqSafeMutex;
+use crate::{acpi_tables, memory, msi, serial, time};
+
+// AHCI SATA controller (QEMU: the q35 machine's ICH9, or `-device ahci`), a backend of block.rs.
+// Uses the first port with a disk attached.
+//
+// Each request takes one of the port's command slots, whose command table holds the FIS and a
+// single PRD entry for the slot's own data buffer. Commands are issued by setting their bit in
+// PxCI and are done once the HBA clears it; the port interrupts (through MSI when there is one)
+// when a command has finished or failed. A failed command fails every command in flight and
+// restarts the port. See the "Serial ATA AHCI 1.3.1 Specification".
+
+const CLASS: (u8, u8, u8) = (0x01, 0x06, 0x01);
+const ABAR: usize = 5;
+
+// HBA registers
+const CAP: u64 = 0x00;
+const GHC: u64 = 0x04;
+const IS: u64 = 0x08;
+const PI: u64 = 0x0C;
+const PORTS: u64 = 0x100;
+const PORT_SIZE: u64 = 0x80;
+
+const GHC_RESET: u32 = 1 << 0;
+const GHC_INTERRUPTS: u32 = 1 << 1;
+const GHC_AHCI: u32 = 1 << 31;
+
+// Port registers
+const PX_CLB: u64 = 0x00;
+const PX_FB: u64 = 0x08;
+const PX_IS: u64 = 0x10;
+const PX_IE: u64 = 0x14;
+const PX_CMD: u64 = 0x18;
+const PX_TFD: u64 = 0x20;
+const PX_SIG: u64 = 0x24;
+const PX_SSTS: u64 = 0x28;
+const PX_SERR: u64 = 0x30;
+const PX_CI: u64 = 0x38;
+
+const CMD_START: u32 = 1 << 0;
+const CMD_FIS_RECEIVE: u32 = 1 << 4;
+const CMD_FIS_RUNNING: u32 = 1 << 14;
+const CMD_LIST_RUNNING: u32 = 1 << 15;
+const TFD_ERROR: u32 = 1 << 0;
+const TFD_DRQ: u32 = 1 << 3;
+const TFD_BUSY: u32 = 1 << 7;
+/// Device to host register FIS received: a command finished.
+const IS_D2H: u32 = 1 << 0;
+/// Task file error: a command failed.
+const IS_TASK_FILE_ERROR: u32 = 1 << 30;
+const SSTS_PRESENT: u32 = 3;
+const SIG_ATA: u32 = 0x0000_0101;
+
+const FIS_H2D: u8 = 0x27;
+const ATA_READ_DMA_EXT: u8 = 0x25;
+const ATA_WRITE_DMA_EXT: u8 = 0x35;
+const ATA_FLUSH_CACHE_EXT: u8 = 0xEA;
+const ATA_IDENTIFY: u8 = 0xEC;
+
+/// Command slots used, if the HBA has that many.
+const SLOTS: usize = 8;
+/// Sectors in one request; longer transfers are split. The ATA count field would allow 65536.
+const MAX_SECTORS: usize = 128;
+/// A command table: the FIS and ATAPI areas, then one PRD entry.
+const TABLE_SIZE: usize = 0x80 + 16;
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy)]
+enum SlotState {
+    Free,
+    InFlight,
+    /// Finished, and whether it succeeded.
+    Done(bool),
+    /// Given up on by [block::wait]; free once the HBA is done with it.
+    Abandoned,
+}
+
+struct Slot {
+    data: DmaBuffer,
+    state: SlotState,
+}
+
+struct Ahci {
+    hba: u64,
+    index: u32,
+    /// The port's registers.
+    port: u64,
+    command_list: DmaBuffer,
+    /// Kept for the HBA, which writes received FISes there.
+    _fis: DmaBuffer,
+    tables: DmaBuffer,
+    slots: Vec<Slot>,
+    /// In sectors.
+    capacity: u64,
+    interrupts: bool,
+}
+
+static AHCI: IrqSafeMutex<Option<Ahci>> = IrqSafeMutex::new(None);
+
+fn read32(address: u64) -> u32 {
+    unsafe { (address as *const u32).read_volatile() }
+}
+
+fn write32(address: u64, value: u32) {
+    unsafe { (address as *mut u32).write_volatile(value) }
+}
+
+fn write64(address: u64, value: u64) {
+    write32(address, value as u32);
+    write32(address + 4, (value >> 32) as u32);
+}
+
+/// Finds an AHCI controller with a disk and brings its port up. Returns false if there is none,
+/// or it cannot be set up.
+pub fn init() -> bool {
+    let (class, subclass, prog_if) = CLASS;
+    let Some(found) = pci::devices().iter().find(|device| (device.class, device.subclass, device.prog_if) == (class, subclass, prog_if)) else {
+        return false;
+    };
+    let Some(Bar::Memory { address, size, .. }) = found.bars[ABAR] else {
+        writeln!(serial(), "ahci: {} has no ABAR", found).unwrap();
+        return false;
+    };
+    found.address.enable_bus_master();
+    let hba = memory::map_region(PhysAddr::new(address), size, memory::MMIO).as_u64();
+
+    write32(hba + GHC, GHC_RESET);
+    if !time::poll_until(TIMEOUT, || read32(hba + GHC) & GHC_RESET == 0) {
+        writeln!(serial(), "ahci: HBA did not reset").unwrap();
+        return false;
+    }
+    write32(hba + GHC, GHC_AHCI);
+    let command_slots = ((read32(hba + CAP) >> 8) & 0x1F) as usize + 1;
+
+    let implemented = read32(hba + PI);
+    let disk = (0..32).filter(|index| implemented & (1 << index) != 0).find(|&index| {
+        let port = hba + PORTS + index as u64 * PORT_SIZE;
+        read32(port + PX_SSTS) & 0xF == SSTS_PRESENT && read32(port + PX_SIG) == SIG_ATA
+    });
+    let Some(index) = disk else {
+        writeln!(serial(), "ahci: no disk on {:?}", found.address).unwrap();
+        return false;
+    };
+
+    let Some(mut ahci) = bring_up(hba, index, SLOTS.min(command_slots)) else {
+        writeln!(serial(), "ahci: port {} could not be set up", index).unwrap();
+        return false;
+    };
+    let Some(identify) = ahci.identify() else {
+        writeln!(serial(), "ahci: port {}: IDENTIFY DEVICE failed", index).unwrap();
+        return false;
+    };
+    let word = |i: usize| u16::from_le_bytes([identify[2 * i], identify[2 * i + 1]]);
+    // Word 83 bit 10: 48-bit addresses, and the count in words 100-103
+    ahci.capacity = if word(83) & (1 << 10) != 0 {
+        (100..104).rev().fold(0, |count, i| count << 16 | word(i) as u64)
+    } else {
+        (word(61) as u64) << 16 | word(60) as u64
+    };
+    // ATA strings have the bytes of each word swapped
+    let model: alloc::string::String = (27..47).flat_map(|i| word(i).to_be_bytes()).map(|byte| byte as char).collect();
+
+    let cpu = acpi_tables::madt().and_then(|madt| madt.boot_processor()).map_or(0, |processor| processor.local_apic_id as u8);
+    ahci.interrupts = msi::allocate_msi(found.address, cpu, interrupt).is_some();
+    if ahci.interrupts {
+        write32(ahci.port + PX_IS, u32::MAX);
+        write32(ahci.port + PX_IE, IS_D2H | IS_TASK_FILE_ERROR);
+        write32(hba + GHC, GHC_AHCI | GHC_INTERRUPTS);
+    }
+    writeln!(serial(), "ahci: port {}: {}, {} sectors ({} MiB), {}", index, model.trim(), ahci.capacity, (ahci.capacity * SECTOR_SIZE as u64) >> 20,
+        if ahci.interrupts { "MSI" } else { "polled" }).unwrap();
+    *AHCI.lock() = Some(ahci);
+    true
+}
+
+/// Stops the port's command list and FIS receive engines.
+fn stop(port: u64) -> bool {
+    write32(port + PX_CMD, read32(port + PX_CMD) & !CMD_START);
+    let stopped = time::poll_until(TIMEOUT, || read32(port + PX_CMD) & CMD_LIST_RUNNING == 0);
+    write32(port + PX_CMD, read32(port + PX_CMD) & !CMD_FIS_RECEIVE);
+    stopped && time::poll_until(TIMEOUT, || read32(port + PX_CMD) & CMD_FIS_RUNNING == 0)
+}
+
+/// Clears the port's errors and starts it once the device is idle.
+fn start(port: u64) -> bool {
+    write32(port + PX_SERR, u32::MAX);
+    write32(port + PX_IS, u32::MAX);
+    write32(port + PX_CMD, read32(port + PX_CMD) | CMD_FIS_RECEIVE);
+    let idle = time::poll_until(TIMEOUT, || read32(port + PX_TFD) & (TFD_BUSY | TFD_DRQ) == 0);
+    write32(port + PX_CMD, read32(port + PX_CMD) | CMD_START);
+    idle
+}
+
+fn bring_up(hba: u64, index: u32, slots: usize) -> Option<Ahci> {
+    let port = hba + PORTS + index as u64 * PORT_SIZE;
+    if !stop(port) {
+        return None;
+    }
+    // 32 command headers of 32 bytes, and the received FIS area; pages, so aligned enough
+    let command_list = dma::alloc_contiguous(32 * 32)?;
+    let fis = dma::alloc_contiguous(256)?;
+    let tables = dma::alloc_contiguous(slots * TABLE_SIZE)?;
+    let slots = (0..slots).map(|_| dma::alloc_contiguous(MAX_SECTORS * SECTOR_SIZE).map(|data| Slot { data, state: SlotState::Free })).collect::<Option<Vec<_>>>()?;
+    write64(port + PX_CLB, command_list.phys_addr().as_u64());
+    write64(port + PX_FB, fis.phys_addr().as_u64());
+    if !start(port) {
+        return None;
+    }
+    Some(Ahci { hba, index, port, command_list, _fis: fis, tables, slots, capacity: 0, interrupts: false })
+}
+
+impl Ahci {
+    /// Fills in slot `index`'s command header and table for `command` on `len` bytes of its
+    /// buffer at `sector`, and issues it.
+    fn issue(&mut self, index: usize, command: u8, sector: u64, len: usize, write: bool) {
+        let table = self.tables.phys_addr() + (index * TABLE_SIZE) as u64;
+        let table_bytes = &mut self.tables.as_mut_slice()[index * TABLE_SIZE..(index + 1) * TABLE_SIZE];
+        table_bytes.fill(0);
+        let count = (len / SECTOR_SIZE) as u16;
+        let lba = sector.to_le_bytes();
+        let fis = &mut table_bytes[..20];
+        fis[0] = FIS_H2D;
+        // A command, not a device control update
+        fis[1] = 1 << 7;
+        fis[2] = command;
+        fis[4..7].copy_from_slice(&lba[0..3]);
+        // LBA mode
+        fis[7] = 1 << 6;
+        fis[8..11].copy_from_slice(&lba[3..6]);
+        fis[12..14].copy_from_slice(&count.to_le_bytes());
+
+        let prd_count = if len > 0 {
+            let prd = &mut table_bytes[0x80..0x90];
+            prd[0..8].copy_from_slice(&self.slots[index].data.phys_addr().as_u64().to_le_bytes());
+            prd[12..16].copy_from_slice(&(len as u32 - 1).to_le_bytes());
+            1
+        } else {
+            0
+        };
+
+        let header = &mut self.command_list.as_mut_slice()[index * 32..(index + 1) * 32];
+        header.fill(0);
+        // FIS length in dwords, direction, PRD entries
+        let flags: u32 = 5 | if write { 1 << 6 } else { 0 } | prd_count << 16;
+        header[0..4].copy_from_slice(&flags.to_le_bytes());
+        header[8..16].copy_from_slice(&table.as_u64().to_le_bytes());
+
+        self.slots[index].state = SlotState::InFlight;
+        write32(self.port + PX_CI, 1 << index);
+    }
+
+    /// IDENTIFY DEVICE, polled, before interrupts are set up. Uses slot 0.
+    fn identify(&mut self) -> Option<Vec<u8>> {
+        self.issue(0, ATA_IDENTIFY, 0, SECTOR_SIZE, false);
+        let port = self.port;
+        let done = time::poll_until(TIMEOUT, || read32(port + PX_CI) & 1 == 0);
+        self.slots[0].state = SlotState::Free;
+        (done && read32(port + PX_TFD) & TFD_ERROR == 0).then(|| self.slots[0].data.as_slice()[..SECTOR_SIZE].to_vec())
+    }
+
+    /// Marks the slots the HBA has finished with done; after an error, every slot in flight
+    /// fails and the port restarts.
+    fn collect(&mut self) {
+        let status = read32(self.port + PX_IS);
+        write32(self.port + PX_IS, status);
+        write32(self.hba + IS, 1 << self.index);
+
+        let failed = status & IS_TASK_FILE_ERROR != 0;
+        let issued = read32(self.port + PX_CI);
+        for (index, slot) in self.slots.iter_mut().enumerate() {
+            let finished = issued & (1 << index) == 0;
+            slot.state = match slot.state {
+                SlotState::InFlight if failed => SlotState::Done(false),
+                SlotState::InFlight if finished => SlotState::Done(true),
+                SlotState::Abandoned if failed || finished => SlotState::Free,
+                state => state,
+            };
+        }
+        if failed {
+            writeln!(serial(), "ahci: port {}: command failed, task file {:#x}", self.index, read32(self.port + PX_TFD)).unwrap();
+            stop(self.port);
+            start(self.port);
+        }
+    }
+
+    fn submit(&mut self, command: u8, sector: u64, len: usize, data: Option<&[u8]>) -> Result<usize, BlockError> {
+        let index = self.slots.iter().position(|slot| matches!(slot.state, SlotState::Free)).ok_or(BlockError::Busy)?;
+        if let Some(data) = data {
+            self.slots[index].data.as_mut_slice()[..data.len()].copy_from_slice(data);
+        }
+        self.issue(index, command, sector, len, data.is_some());
+        Ok(index)
+    }
+
+    fn complete(&mut self, index: usize, out: Option<&mut [u8]>) -> Option<Result<(), BlockError>> {
+        let slot = &mut self.slots[index];
+        let SlotState::Done(succeeded) = slot.state else { return None };
+        slot.state = SlotState::Free;
+        if !succeeded {
+            return Some(Err(BlockError::Io));
+        }
+        if let Some(out) = out {
+            out.copy_from_slice(&slot.data.as_slice()[..out.len()]);
+        }
+        Some(Ok(()))
+    }
+}
+
+/// Port interrupt.
+fn interrupt() {
+    if let Some(ahci) = AHCI.lock().as_mut() {
+        ahci.collect();
+    }
+}
+
+fn wait(slot: usize, mut out: Option<&mut [u8]>) -> Result<(), BlockError> {
+    let interrupt_driven = AHCI.lock().as_ref().is_some_and(|ahci| ahci.interrupts);
+    block::wait(interrupt_driven, |timed_out| {
+        let mut guard = AHCI.lock();
+        let Some(ahci) = guard.as_mut() else { return Some(Err(BlockError::NoDevice)) };
+        ahci.collect();
+        ahci.complete(slot, out.as_deref_mut()).or_else(|| timed_out.then(|| {
+            ahci.slots[slot].state = SlotState::Abandoned;
+            Err(BlockError::Timeout)
+        }))
+    })
+}
+
+fn check(sector: u64, len: usize, write: bool) -> Result<(), BlockError> {
+    let guard = AHCI.lock();
+    let ahci = guard.as_ref().ok_or(BlockError::NoDevice)?;
+    block::check_request(sector, len, ahci.capacity, false, write)
+}
+
+fn submit(command: u8, sector: u64, len: usize, data: Option<&[u8]>) -> Result<usize, BlockError> {
+    AHCI.lock().as_mut().ok_or(BlockError::NoDevice)?.submit(command, sector, len, data)
+}
+
+/// Size of the disk in sectors, 0 without one.
+pub fn capacity() -> u64 {
+    AHCI.lock().as_ref().map_or(0, |ahci| ahci.capacity)
+}
+
+/// See [block::read].
+pub fn read(sector: u64, buffer: &mut [u8]) -> Result<(), BlockError> {
+    check(sector, buffer.len(), false)?;
+    for (i, chunk) in buffer.chunks_mut(MAX_SECTORS * SECTOR_SIZE).enumerate() {
+        let slot = submit(ATA_READ_DMA_EXT, sector + (i * MAX_SECTORS) as u64, chunk.len(), None)?;
+        wait(slot, Some(chunk))?;
+    }
+    Ok(())
+}
+
+/// See [block::write].
+pub fn write(sector: u64, data: &[u8]) -> Result<(), BlockError> {
+    check(sector, data.len(), true)?;
+    for (i, chunk) in data.chunks(MAX_SECTORS * SECTOR_SIZE).enumerate() {
+        let slot = submit(ATA_WRITE_DMA_EXT, sector + (i * MAX_SECTORS) as u64, chunk.len(), Some(chunk))?;
+        wait(slot, None)?;
+    }
+    Ok(())
+}
+
+/// Waits until the disk's write cache is written out.
+pub fn flush() -> Result<(), BlockError> {
+    let slot = submit(ATA_FLUSH_CACHE_EXT, 0, 0, None)?;
+    wait(slot, None)
+}
@@ -1,36 +1,371 @@
 #[global_allocator]
-static ALLOCATOR: DummyAllocator = DummyAllocator;
+static ALLOCATOR: HeapAllocator = HeapAllocator::new();
 
 use alloc::alloc::{GlobalAlloc, Layout};
-use core::ptr::null_mut;
 use core::fmt::Write;
+use core::mem::{align_of, size_of};
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use crate::buddy::{BUDDY, PAGE_SIZE};
+use crate::{eventlog, heap_debug, serial};
+use crate::slab::{self, SlabCache, SIZE_CLASSES, SLAB_SIZE};
 
-use crate::serial;
-pub struct DummyAllocator;
-
-pub static mut HEAP_START: usize = 0x0;
-pub static mut OFFSET: usize = 0x0;
+/// Initial heap size, collected from the usable memory regions in order.
 pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB
 
-unsafe impl GlobalAlloc for DummyAllocator {
-    unsafe fn alloc(&self, _layout: Layout) -> *mut u8 {
+/// Default for how large [enable_growth] lets the heap become.
+pub const HEAP_LIMIT: usize = 16 * 1024 * 1024; // 16 MiB
+
+// Smallest step the heap grows by
+const GROW_MIN: usize = 64 * 1024;
+
+static PHYSICAL_OFFSET: AtomicU64 = AtomicU64::new(0);
+static LIMIT: AtomicUsize = AtomicUsize::new(0);
+
+/// Lets the heap take more memory from the buddy allocator when it runs out, until it holds
+/// `limit` bytes in total. Pages are reached through the physical memory mapping at
+/// `physical_offset`, so no new mappings are needed. Grown memory stays with the heap.
+pub fn enable_growth(physical_offset: u64, limit: usize) {
+    LIMIT.store(limit, Ordering::Relaxed);
+    PHYSICAL_OFFSET.store(physical_offset, Ordering::Relaxed);
+}
+
+/// Snapshot of heap usage, from [stats].
+#[derive(Debug, Clone, Copy)]
+pub struct HeapStats {
+    /// Bytes handed out and not yet freed, including rounding up to the block or size class.
+    pub used: usize,
+    pub free: usize,
+    /// Highest `used` seen since boot.
+    pub peak: usize,
+    pub allocations: usize,
+    pub deallocations: usize,
+}
+
+static CAPACITY: AtomicUsize = AtomicUsize::new(0);
+static USED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static DEALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+pub fn stats() -> HeapStats {
+    let used = USED.load(Ordering::Relaxed);
+    HeapStats {
+        used,
+        free: CAPACITY.load(Ordering::Relaxed).saturating_sub(used),
+        peak: PEAK.load(Ordering::Relaxed),
+        allocations: ALLOCATIONS.load(Ordering::Relaxed),
+        deallocations: DEALLOCATIONS.load(Ordering::Relaxed),
+    }
+}
+
+static LOW_MEMORY_HANDLER: Mutex<Option<fn()>> = Mutex::new(None);
+
+/// Registers a function that frees what it can when an allocation fails, after which the
+/// allocation is retried once. It runs inside the allocator, so it must not wait for locks the
+/// failing code may hold (use `try_lock`).
+pub fn set_low_memory_handler(handler: fn()) {
+    without_interrupts(|| *LOW_MEMORY_HANDLER.lock() = Some(handler));
+}
+
+/// Writes the failed request and the heap state to `out`.
+fn write_oom_report(out: &mut impl Write, layout: Layout, heap: HeapStats) -> core::fmt::Result {
+    writeln!(out, "out of memory: {} bytes aligned to {}", layout.size(), layout.align())?;
+    writeln!(out, "heap used: {} B, free: {} B, peak: {} B", heap.used, heap.free, heap.peak)?;
+    writeln!(out, "allocs: {}, frees: {}", heap.allocations, heap.deallocations)?;
+    if let Some(buddy) = BUDDY.try_lock() {
+        writeln!(out, "free pages: {} KiB", buddy.free_bytes() / 1024)?;
+    }
+    Ok(())
+}
+
+static OOM_DISPLAY: Mutex<Option<fn(&str)>> = Mutex::new(None);
+
+/// Registers the function that shows out-of-memory reports on screen, on top of serial. It is
+/// called with a piece of text at a time and must not allocate.
+pub fn set_oom_display(display: fn(&str)) {
+    without_interrupts(|| *OOM_DISPLAY.lock() = Some(display));
+}
+
+/// Passes what is written to the function set with [set_oom_display].
+struct OomDisplay(fn(&str));
+
+impl Write for OomDisplay {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        (self.0)(s);
+        Ok(())
+    }
+}
+
+/// Reports a failed allocation on serial and the screen. The default alloc error handler panics
+/// afterwards unless the caller handles the failure (`try_reserve` and the like).
+fn report_oom(layout: Layout) {
+    let heap = stats();
+    eventlog::record(eventlog::Kind::Alloc, format_args!("{} bytes, align {}, {} free", layout.size(), layout.align(), heap.free));
+    let _ = write_oom_report(&mut serial(), layout, heap);
+    let display = without_interrupts(|| *OOM_DISPLAY.lock());
+    if let Some(display) = display {
+        let _ = write_oom_report(&mut OomDisplay(display), layout, heap);
+    }
+}
+
+/// Bytes actually reserved for `layout`.
+fn reserved_size(layout: Layout) -> usize {
+    match slab::size_class(layout) {
+        Some(class) => SIZE_CLASSES[class],
+        None => block_layout(layout).0,
+    }
+}
+
+/// Header written at the start of every free block. Free blocks form a singly linked list
+/// sorted by address, so neighbours can be merged when a block is returned.
+struct FreeBlock {
+    size: usize,
+    next: *mut FreeBlock,
+}
+
+// Anything smaller could not hold a header once freed
+const MIN_BLOCK: usize = size_of::<FreeBlock>();
+
+struct FreeList {
+    head: *mut FreeBlock,
+}
+
+unsafe impl Send for FreeList {}
+
+impl FreeList {
+    /// Returns the block at `[address, address + size)` to the list, merging it with adjacent
+    /// free blocks.
+    unsafe fn add_free(&mut self, address: usize, size: usize) {
+        unsafe {
+            let mut prev: *mut FreeBlock = null_mut();
+            let mut current = self.head;
+            while !current.is_null() && (current as usize) < address {
+                prev = current;
+                current = (*current).next;
+            }
+
+            let mut size = size;
+            let mut next = current;
+            if !current.is_null() && address + size == current as usize {
+                size += (*current).size;
+                next = (*current).next;
+            }
+
+            if !prev.is_null() && prev as usize + (*prev).size == address {
+                (*prev).size += size;
+                (*prev).next = next;
+            } else {
+                let block = address as *mut FreeBlock;
+                block.write(FreeBlock { size, next });
+                if prev.is_null() {
+                    self.head = block;
+                } else {
+                    (*prev).next = block;
+                }
+            }
+        }
+    }
+
+    /// First fit: carves `size` bytes aligned to `align` out of the first block that can hold
+    /// them, returning the unused ends of the block to the list.
+    unsafe fn allocate(&mut self, size: usize, align: usize) -> *mut u8 {
         unsafe {
-            // TODO: Implement me!
-            OFFSET += _layout.size();
-            (HEAP_START + OFFSET) as *mut u8
-           
+            let mut prev: *mut FreeBlock = null_mut();
+            let mut current = self.head;
+            while !current.is_null() {
+                let block_start = current as usize;
+                let block_end = block_start + (*current).size;
+
+                let mut start = align_up(block_start, align);
+                if start != block_start && start - block_start < MIN_BLOCK {
+                    // The gap in front would be too small to stay on the list
+                    start = align_up(block_start + MIN_BLOCK, align);
+                }
+                let end = start.saturating_add(size);
+                let back = block_end.saturating_sub(end);
+
+                if end <= block_end && (back == 0 || back >= MIN_BLOCK) {
+                    let next = (*current).next;
+                    if prev.is_null() {
+                        self.head = next;
+                    } else {
+                        (*prev).next = next;
+                    }
+                    if start > block_start {
+                        self.add_free(block_start, start - block_start);
+                    }
+                    if back > 0 {
+                        self.add_free(end, back);
+                    }
+                    return start as *mut u8;
+                }
+
+                prev = current;
+                current = (*current).next;
+            }
+            null_mut()
+        }
+    }
+}
+
+fn align_up(address: usize, align: usize) -> usize {
+    (address + align - 1) & !(align - 1)
+}
+
+/// Size and alignment actually reserved for `layout`: every block must be able to hold a
+/// [FreeBlock] header once it is freed.
+fn block_layout(layout: Layout) -> (usize, usize) {
+    let align = layout.align().max(align_of::<FreeBlock>());
+    let size = align_up(layout.size().max(MIN_BLOCK), align_of::<FreeBlock>());
+    (size, align)
+}
+
+/// Heap allocator: small requests come from per-size [slab] caches, everything else from a
+/// first-fit free list that merges neighbouring blocks. Slabs are themselves carved out of the
+/// free list as needed.
+pub struct HeapAllocator {
+    // Lock order: slabs before free
+    slabs: Mutex<[SlabCache; SIZE_CLASSES.len()]>,
+    free: Mutex<FreeList>,
+}
+
+impl HeapAllocator {
+    pub const fn new() -> Self {
+        Self { slabs: Mutex::new(slab::caches()), free: Mutex::new(FreeList { head: null_mut() }) }
+    }
+
+    unsafe fn try_alloc(&self, layout: Layout) -> *mut u8 {
+        if let Some(class) = slab::size_class(layout) {
+            return unsafe { self.alloc_small(class) };
         }
+        let (size, align) = block_layout(layout);
+        unsafe { self.free.lock().allocate(size, align) }
     }
 
-    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {
-        writeln!(serial(), "dealloc was called at {_ptr:?}").unwrap();
+    /// Extends the heap with pages from the buddy allocator so that `layout` fits, within the
+    /// limit set by [enable_growth]. Returns whether the heap grew.
+    fn grow(&self, layout: Layout) -> bool {
+        let physical_offset = PHYSICAL_OFFSET.load(Ordering::Relaxed);
+        if physical_offset == 0 {
+            return false;
+        }
+        let (size, align) = block_layout(layout);
+        let needed = (size + align).max(SLAB_SIZE).max(GROW_MIN);
+        let pages = needed.div_ceil(PAGE_SIZE as usize).next_power_of_two();
+        let bytes = pages * PAGE_SIZE as usize;
+        if CAPACITY.load(Ordering::Relaxed) + bytes > LIMIT.load(Ordering::Relaxed) {
+            return false;
+        }
+
+        // try_lock: the lock may be held by the code this allocation interrupted
+        let Some(address) = BUDDY.try_lock().and_then(|mut buddy| buddy.alloc_pages(pages)) else {
+            return false;
+        };
+        unsafe { self.free.lock().add_free((physical_offset + address.as_u64()) as usize, bytes) };
+        CAPACITY.fetch_add(bytes, Ordering::Relaxed);
+        true
+    }
+
+    unsafe fn alloc_small(&self, class: usize) -> *mut u8 {
+        let mut slabs = self.slabs.lock();
+        let cache = &mut slabs[class];
+        if let Some(object) = cache.alloc() {
+            return object;
+        }
+        let slab = unsafe { self.free.lock().allocate(SLAB_SIZE, SLAB_SIZE) };
+        if slab.is_null() {
+            return null_mut();
+        }
+        unsafe { cache.add_slab(slab) };
+        cache.alloc().unwrap_or(null_mut())
+    }
+
+    unsafe fn alloc_block(&self, layout: Layout) -> *mut u8 {
+        // Interrupt handlers may allocate too, so never let one spin on a lock held below it
+        let alloc_or_grow = || without_interrupts(|| {
+            let ptr = unsafe { self.try_alloc(layout) };
+            if ptr.is_null() && self.grow(layout) {
+                unsafe { self.try_alloc(layout) }
+            } else {
+                ptr
+            }
+        });
+
+        let mut ptr = alloc_or_grow();
+        if ptr.is_null() {
+            let handler = without_interrupts(|| *LOW_MEMORY_HANDLER.lock());
+            if let Some(handler) = handler {
+                handler();
+                ptr = alloc_or_grow();
+            }
+            if ptr.is_null() {
+                report_oom(layout);
+            }
+        }
+
+        if !ptr.is_null() {
+            let size = reserved_size(layout);
+            let used = USED.fetch_add(size, Ordering::Relaxed) + size;
+            PEAK.fetch_max(used, Ordering::Relaxed);
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc_block(&self, ptr: *mut u8, layout: Layout) {
+        USED.fetch_sub(reserved_size(layout), Ordering::Relaxed);
+        DEALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        without_interrupts(|| {
+            if let Some(class) = slab::size_class(layout) {
+                unsafe { self.slabs.lock()[class].free(ptr) };
+                return;
+            }
+            let (size, _) = block_layout(layout);
+            unsafe { self.free.lock().add_free(ptr as usize, size) };
+        });
     }
 }
 
-pub fn init_heap(offset: usize) {
-    unsafe {
-        // TODO: Implement me!
-        HEAP_START = offset;
-        OFFSET = 0;
+impl Default for HeapAllocator {
+    fn default() -> Self {
+        Self::new()
     }
-}
\ No newline at end of file
+}
+
+unsafe impl GlobalAlloc for HeapAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if cfg!(feature = "heap-debug") {
+            let Some(block_layout) = heap_debug::block_layout(layout) else { return null_mut() };
+            let block = unsafe { self.alloc_block(block_layout) };
+            return if block.is_null() { block } else { unsafe { heap_debug::on_alloc(block, layout) } };
+        }
+        unsafe { self.alloc_block(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if cfg!(feature = "heap-debug") {
+            // Anything it will not have freed is leaked
+            if let (Some(block), Some(block_layout)) = (unsafe { heap_debug::on_free(ptr, layout) }, heap_debug::block_layout(layout)) {
+                unsafe { self.dealloc_block(block, block_layout) };
+            }
+            return;
+        }
+        unsafe { self.dealloc_block(ptr, layout) }
+    }
+}
+
+/// Adds `size` bytes at virtual address `offset` to the heap. Called once for each piece of
+/// memory the heap is built from; adjacent pieces merge.
+pub fn init_heap(offset: usize, size: usize) {
+    let start = align_up(offset, align_of::<FreeBlock>());
+    let end = offset + size;
+    if end < start + MIN_BLOCK {
+        return;
+    }
+    let size = end - start;
+    without_interrupts(|| unsafe { ALLOCATOR.free.lock().add_free(start, size) });
+    CAPACITY.fetch_add(size, Ordering::Relaxed);
+}
@@ -0,0 +1,188 @@
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use kernel::serial;
+use crate::screen::Renderer;
+use crate::vga_text::{PALETTE, nearest};
+
+// The screen for headless runs, with the boot option `headless=on` or a kernel built with the
+// `headless` feature: the game is drawn in an ANSI terminal on the serial console instead of the
+// display, so it plays under `qemu -display none` or `-nographic` and in CI. Like the VGA text
+// screen it pretends to be 640x400 pixels in 80x25 cells of 8x16: a pixel fills its cell with a
+// block, a character takes the cell its top-left corner falls in, and the colors are the 16 text
+// mode ones.
+//
+// Drawing only changes the cells in memory. Each frame, [AnsiScreen::changes] gives what to send
+// to bring the terminal up to date, the cells that changed and nothing else, so a frame of play
+// is a few dozen bytes. The picture takes the top lines of the terminal; the rest is a scrolling
+// region where the serial log goes on, which needs a terminal more than [LOG_LINE] lines high.
+
+const COLUMNS: usize = 80;
+const ROWS: usize = 25;
+const CELL_WIDTH: usize = 8;
+const CELL_HEIGHT: usize = 16;
+/// First terminal line below the picture, where the log scrolls.
+const LOG_LINE: usize = ROWS + 2;
+
+const BLOCK: char = '█';
+/// Light grey.
+const DEFAULT_FOREGROUND: u8 = 0x07;
+/// Light green, for text written at the cursor.
+const TEXT_FOREGROUND: u8 = 0x0A;
+
+/// The ANSI color number of each of the first 8 text mode colors, which has red and blue the
+/// other way round. The other 8 are their bright versions.
+const ANSI: [u8; 8] = [0, 4, 2, 6, 1, 5, 3, 7];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Cell {
+    character: char,
+    /// Palette indices.
+    foreground: u8,
+    background: u8,
+}
+
+impl Cell {
+    const BLANK: Cell = Cell { character: ' ', foreground: DEFAULT_FOREGROUND, background: 0 };
+    /// What the terminal is taken to show before the first frame, so that it is all sent.
+    const UNKNOWN: Cell = Cell { character: '\0', foreground: 0, background: 0 };
+}
+
+pub struct AnsiScreen {
+    cells: Vec<Cell>,
+    /// The cells as the terminal shows them.
+    shown: Vec<Cell>,
+    /// Background of a cleared cell.
+    background: u8,
+    column: usize,
+    row: usize,
+}
+
+impl AnsiScreen {
+    /// Clears the terminal and leaves its lines below the picture to the log.
+    pub fn new() -> Self {
+        let _ = write!(serial(), "\x1b[0m\x1b[2J\x1b[{}r\x1b[{};1H", LOG_LINE, LOG_LINE);
+        AnsiScreen { cells: vec![Cell::BLANK; COLUMNS * ROWS], shown: vec![Cell::UNKNOWN; COLUMNS * ROWS], background: 0, column: 0, row: 0 }
+    }
+
+    /// Sets the cell at pixel (x, y) to `character` in (r, g, b), on the cell's background.
+    fn put(&mut self, x: usize, y: usize, character: char, r: u8, g: u8, b: u8) {
+        let (column, row) = (x / CELL_WIDTH, y / CELL_HEIGHT);
+        if column < COLUMNS && row < ROWS {
+            let cell = &mut self.cells[row * COLUMNS + column];
+            *cell = Cell { character, foreground: nearest(r, g, b, PALETTE.len()), background: cell.background };
+        }
+    }
+
+    /// The escape sequences that bring the terminal up to date with what has been drawn, empty
+    /// when it is. They leave the cursor and the colors where the log had them.
+    pub fn changes(&mut self) -> String {
+        let mut out = String::new();
+        // Where the terminal's cursor is, and its colors, once anything is sent
+        let mut cursor = None;
+        let mut colors = None;
+        for (i, (cell, shown)) in self.cells.iter().zip(self.shown.iter_mut()).enumerate() {
+            if cell == shown {
+                continue;
+            }
+            if out.is_empty() {
+                out.push_str("\x1b7");
+            }
+            if cursor != Some(i) {
+                let _ = write!(out, "\x1b[{};{}H", i / COLUMNS + 1, i % COLUMNS + 1);
+            }
+            if colors != Some((cell.foreground, cell.background)) {
+                let _ = write!(out, "\x1b[{};{}m", color(cell.foreground, 30), color(cell.background, 40));
+                colors = Some((cell.foreground, cell.background));
+            }
+            out.push(cell.character);
+            *shown = *cell;
+            // At the end of a line the cursor stays on its last column
+            cursor = Some(i + 1).filter(|next| next % COLUMNS != 0);
+        }
+        if !out.is_empty() {
+            out.push_str("\x1b8");
+        }
+        out
+    }
+}
+
+/// The SGR parameter for palette color `index`, `base` 30 for the foreground or 40 for the
+/// background.
+fn color(index: u8, base: u8) -> u8 {
+    let bright = if index & 0x08 != 0 { 60 } else { 0 };
+    base + bright + ANSI[index as usize & 0x07]
+}
+
+/// What a character is drawn as: itself, or `?` for a control character.
+fn printable(c: char) -> char {
+    if c.is_control() { '?' } else { c }
+}
+
+impl Renderer for AnsiScreen {
+    fn width(&self) -> usize {
+        COLUMNS * CELL_WIDTH
+    }
+
+    fn height(&self) -> usize {
+        ROWS * CELL_HEIGHT
+    }
+
+    fn clear(&mut self) {
+        self.column = 0;
+        self.row = 0;
+        self.background = 0;
+        self.cells.fill(Cell::BLANK);
+    }
+
+    fn clear_screen(&mut self, r: u8, g: u8, b: u8) {
+        self.background = nearest(r, g, b, 8);
+        self.cells.fill(Cell { character: ' ', foreground: 0x0F, background: self.background });
+    }
+
+    fn draw_pixel(&mut self, x: usize, y: usize, r: u8, g: u8, b: u8) {
+        self.put(x, y, BLOCK, r, g, b);
+    }
+
+    fn read_pixel(&self, x: usize, y: usize) -> (u8, u8, u8) {
+        let (column, row) = (x / CELL_WIDTH, y / CELL_HEIGHT);
+        if column >= COLUMNS || row >= ROWS {
+            return (0, 0, 0);
+        }
+        let cell = self.cells[row * COLUMNS + column];
+        let color = if cell.character == ' ' { cell.background } else { cell.foreground };
+        PALETTE[color as usize]
+    }
+
+    fn draw_char(&mut self, x: usize, y: usize, c: char, r: u8, g: u8, b: u8) {
+        self.put(x, y, printable(c), r, g, b);
+    }
+}
+
+impl fmt::Write for AnsiScreen {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            match c {
+                '\n' => {
+                    self.column = 0;
+                    self.row += 1;
+                }
+                '\r' => self.column = 0,
+                c => {
+                    if self.column >= COLUMNS {
+                        self.column = 0;
+                        self.row += 1;
+                    }
+                    if self.row >= ROWS {
+                        self.clear();
+                    }
+                    let cell = Cell { character: printable(c), foreground: TEXT_FOREGROUND, background: self.background };
+                    self.cells[self.row * COLUMNS + self.column] = cell;
+                    self.column += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,87 @@
+use alloc::vec::Vec;
+use core::fmt::Write;
+use spin::Once;
+use crate::serial;
+
+// The files the bootloader's ramdisk carries: the boot options and artwork (themes, sounds,
+// anything else) that can change without rebuilding the kernel, only the ramdisk (see build.rs,
+// which packs `assets/` at the top of the repository). [get] finds one by its path there.
+//
+// The archive is [MAGIC], then for each file its path's length as a byte, the path, its length
+// as a little-endian u32 and its bytes, up to the end. A ramdisk without the magic is a plain
+// boot options file, as before there were assets, and reads as [CONFIG] alone. The files stay
+// where the bootloader loaded them; nothing is copied.
+
+const MAGIC: &[u8; 8] = b"PONGPAK1";
+/// The boot options' file, for crate::config.
+pub const CONFIG: &str = "pong.cfg";
+
+static ASSETS: Once<Vec<(&'static str, &'static [u8])>> = Once::new();
+
+/// Why an archive did not parse.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// A file runs past the end of the archive.
+    Truncated,
+    /// A path is not UTF-8.
+    BadName,
+}
+
+/// Reads the files from the `ramdisk` and logs their paths and sizes to serial. Runs once at boot,
+/// after the heap is set up. An archive that does not parse is logged and treated as empty.
+pub fn init(ramdisk: &'static [u8]) {
+    let assets = ASSETS.call_once(|| parse(ramdisk).unwrap_or_else(|error| {
+        let _ = writeln!(serial(), "assets: ignoring the ramdisk, {:?}", error);
+        Vec::new()
+    }));
+    for (name, data) in assets {
+        let _ = writeln!(serial(), "assets: {} ({} bytes)", name, data.len());
+    }
+}
+
+/// The files in `archive`, in the order they are in it. Without the magic, it is all [CONFIG].
+pub fn parse(archive: &[u8]) -> Result<Vec<(&str, &[u8])>, Error> {
+    let Some(mut rest) = archive.strip_prefix(MAGIC) else {
+        return Ok(alloc::vec![(CONFIG, archive)]);
+    };
+    let mut files = Vec::new();
+    while let Some((&name_len, after)) = rest.split_first() {
+        let (name, after) = after.split_at_checked(name_len as usize).ok_or(Error::Truncated)?;
+        let (len, after) = after.split_first_chunk::<4>().ok_or(Error::Truncated)?;
+        let (data, after) = after.split_at_checked(u32::from_le_bytes(*len) as usize).ok_or(Error::Truncated)?;
+        files.push((core::str::from_utf8(name).map_err(|_| Error::BadName)?, data));
+        rest = after;
+    }
+    Ok(files)
+}
+
+/// The file at `path`, e.g. `themes/ocean`; None if the ramdisk has none, or before [init].
+pub fn get(path: &str) -> Option<&'static [u8]> {
+    ASSETS.get()?.iter().find(|(name, _)| *name == path).map(|&(_, data)| data)
+}
+
+/// The files whose paths start with `prefix`, e.g. `themes/`, with the prefix taken off.
+pub fn under(prefix: &str) -> impl Iterator<Item = (&'static str, &'static [u8])> + '_ {
+    ASSETS.get().into_iter().flatten().filter_map(move |&(name, data)| Some((name.strip_prefix(prefix)?, data)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn archive_lists_its_files() {
+        let mut archive = Vec::from(*b"PONGPAK1");
+        for (name, data) in [("pong.cfg", &b"ai=hard"[..]), ("sounds/music", b"440:200 0:100")] {
+            archive.push(name.len() as u8);
+            archive.extend_from_slice(name.as_bytes());
+            archive.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            archive.extend_from_slice(data);
+        }
+        assert_eq!(parse(&archive).unwrap(), [("pong.cfg", &b"ai=hard"[..]), ("sounds/music", b"440:200 0:100")]);
+
+        // A plain options file, as before; and one cut short
+        assert_eq!(parse(b"tick_hz=60").unwrap(), [(CONFIG, &b"tick_hz=60"[..])]);
+        assert_eq!(parse(&archive[..archive.len() - 1]), Err(Error::Truncated));
+    }
+}
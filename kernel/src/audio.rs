@@ -0,0 +1,406 @@
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use core::time::Duration;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::port::Port;
+use crate::dma::{self, DmaBuffer};
+use crate::{pci, serial, time};
+
+// AC'97 audio output (QEMU: `-device AC97`) with a small software mixer. The controller plays a
+// ring of buffers by DMA; the timer interrupt keeps the buffers ahead of it filled with the mix of
+// the playing voices. See the "Intel I/O Controller Hub: AC '97" programmer's reference and
+// https://wiki.osdev.org/AC97
+//
+// Intel HDA controllers are not supported; on machines with only one of those, the game is silent.
+//
+// Each voice plays on a [Channel]. The mix scales each channel by its volume and the whole by the
+// master volume, unless muted, so turning one down takes effect at once, on what is playing too.
+
+/// Output rate, in frames per second. The codec's default, so it needs no variable rate support.
+pub const SAMPLE_RATE: u32 = 48_000;
+
+const BUFFER_COUNT: usize = 32;
+/// Stereo frames per buffer, about 10 ms.
+const BUFFER_FRAMES: usize = 512;
+const BUFFER_BYTES: usize = BUFFER_FRAMES * 2 * 2;
+/// Buffers kept filled ahead of the one playing. Enough to ride out a late timer tick; more
+/// would delay sound effects.
+const LEAD: usize = 8;
+const MAX_VOICES: usize = 8;
+
+// Mixer registers (BAR 0)
+const NAM_RESET: u16 = 0x00;
+const NAM_MASTER_VOLUME: u16 = 0x02;
+const NAM_PCM_OUT_VOLUME: u16 = 0x18;
+
+// Bus master registers (BAR 1); the PCM out box starts at 0x10
+const PO_BDBAR: u16 = 0x10;
+const PO_CIV: u16 = 0x14;
+const PO_LVI: u16 = 0x15;
+const PO_SR: u16 = 0x16;
+const PO_CR: u16 = 0x1B;
+const GLOBAL_CONTROL: u16 = 0x2C;
+const GLOBAL_STATUS: u16 = 0x30;
+
+const CR_RUN: u8 = 1 << 0;
+const CR_RESET: u8 = 1 << 1;
+const SR_HALTED: u16 = 1 << 0;
+/// Status bits cleared by writing 1.
+const SR_CLEAR: u16 = 0x1C;
+const GLOBAL_COLD_RESET: u32 = 1 << 1;
+const STATUS_CODEC_READY: u32 = 1 << 8;
+
+/// A note of a melody: a square wave of `frequency` Hz (0 for a rest) for `ms` milliseconds.
+#[derive(Debug, Clone, Copy)]
+pub struct Note {
+    pub frequency: u16,
+    pub ms: u16,
+}
+
+/// A melody written as text, as the ramdisk's sounds are (crate::assets): `frequency:ms` words,
+/// e.g. `440:200 0:100 523:400`, a frequency of 0 for a rest. None if a word is not one, or if
+/// the melody takes no time at all.
+pub fn parse_melody(text: &[u8]) -> Option<Vec<Note>> {
+    let text = core::str::from_utf8(text).ok()?;
+    let notes = text
+        .split_whitespace()
+        .map(|word| {
+            let (frequency, ms) = word.split_once(':')?;
+            Some(Note { frequency: frequency.parse().ok()?, ms: ms.parse().ok()? })
+        })
+        .collect::<Option<Vec<_>>>()?;
+    has_length(&notes).then_some(notes)
+}
+
+/// Whether `notes` take any time to play; a melody that doesn't would never get to a sample.
+fn has_length(notes: &[Note]) -> bool {
+    notes.iter().any(|note| note.ms > 0)
+}
+
+/// Steps of the master and channel volumes; at the top, voices play as loud as they were asked to.
+pub const MAX_LEVEL: u8 = 10;
+
+/// What a voice is for. Tones and samples are effects, melodies music.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Effects,
+    Music,
+}
+
+static MASTER: AtomicU8 = AtomicU8::new(MAX_LEVEL);
+/// Volume of each [Channel], indexed by `channel as usize`.
+static LEVELS: [AtomicU8; 2] = [AtomicU8::new(MAX_LEVEL), AtomicU8::new(MAX_LEVEL)];
+static MUTED: AtomicBool = AtomicBool::new(false);
+
+/// A playing sound, returned so it can be stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VoiceId {
+    slot: usize,
+    generation: u32,
+}
+
+enum Source {
+    /// Square wave; `remaining` frames to go.
+    Tone { frequency: u32, remaining: u32 },
+    /// Mono PCM at [SAMPLE_RATE].
+    Pcm { samples: &'static [i16], position: usize },
+    Melody { notes: &'static [Note], index: usize, remaining: u32 },
+}
+
+struct Voice {
+    source: Source,
+    channel: Channel,
+    /// Peak amplitude of square waves, or the scale of PCM samples (256 is unchanged).
+    volume: i32,
+    looping: bool,
+    /// Position in the square wave's period, in units of 1/SAMPLE_RATE of a period.
+    phase: u32,
+    generation: u32,
+}
+
+impl Voice {
+    fn square(&mut self, frequency: u32) -> i32 {
+        if frequency == 0 {
+            return 0;
+        }
+        self.phase = self.phase.wrapping_add(frequency % SAMPLE_RATE) % SAMPLE_RATE;
+        if self.phase < SAMPLE_RATE / 2 { self.volume } else { -self.volume }
+    }
+
+    /// The next sample, or None once the voice has finished.
+    fn next_sample(&mut self) -> Option<i32> {
+        match self.source {
+            Source::Tone { frequency, ref mut remaining } => {
+                *remaining = remaining.checked_sub(1)?;
+                Some(self.square(frequency))
+            }
+            Source::Pcm { samples, ref mut position } => {
+                if *position == samples.len() {
+                    if !self.looping || samples.is_empty() {
+                        return None;
+                    }
+                    *position = 0;
+                }
+                let sample = samples[*position] as i32;
+                *position += 1;
+                Some(sample * self.volume / 256)
+            }
+            Source::Melody { notes, ref mut index, ref mut remaining } => {
+                while *remaining == 0 {
+                    *index += 1;
+                    if *index >= notes.len() {
+                        if !self.looping || notes.is_empty() {
+                            return None;
+                        }
+                        *index = 0;
+                    }
+                    *remaining = frames(notes[*index].ms as u32);
+                }
+                *remaining -= 1;
+                let frequency = notes[*index].frequency as u32;
+                Some(self.square(frequency))
+            }
+        }
+    }
+}
+
+fn frames(ms: u32) -> u32 {
+    SAMPLE_RATE / 1000 * ms
+}
+
+struct Ac97 {
+    mixer: u16,
+    bus_master: u16,
+    /// Buffer descriptor list: one (address, samples, flags) entry per buffer. Only the
+    /// controller reads it.
+    _descriptors: DmaBuffer,
+    buffers: DmaBuffer,
+    /// Next buffer to fill.
+    next: usize,
+    voices: [Option<Voice>; MAX_VOICES],
+    next_generation: u32,
+}
+
+static AUDIO: Mutex<Option<Ac97>> = Mutex::new(None);
+
+fn read8(port: u16) -> u8 {
+    unsafe { Port::<u8>::new(port).read() }
+}
+
+fn write8(port: u16, value: u8) {
+    unsafe { Port::<u8>::new(port).write(value) }
+}
+
+fn read16(port: u16) -> u16 {
+    unsafe { Port::<u16>::new(port).read() }
+}
+
+fn write16(port: u16, value: u16) {
+    unsafe { Port::<u16>::new(port).write(value) }
+}
+
+fn read32(port: u16) -> u32 {
+    unsafe { Port::<u32>::new(port).read() }
+}
+
+fn write32(port: u16, value: u32) {
+    unsafe { Port::<u32>::new(port).write(value) }
+}
+
+/// Finds an AC'97 controller and starts playing silence on it. Returns false if there is none.
+pub fn init() -> bool {
+    // Multimedia, audio
+    let Some(device) = pci::find_by_class(0x04, 0x01, 0x00) else {
+        if pci::find_by_class(0x04, 0x03, 0x00).is_some() {
+            writeln!(serial(), "audio: found an HDA controller, which is not supported").unwrap();
+        }
+        return false;
+    };
+    device.enable_io_space();
+    device.enable_bus_master();
+    let mixer = device.io_bar(0);
+    let bus_master = device.io_bar(1);
+
+    write32(bus_master + GLOBAL_CONTROL, GLOBAL_COLD_RESET);
+    if !time::poll_until(Duration::from_millis(500), || read32(bus_master + GLOBAL_STATUS) & STATUS_CODEC_READY != 0) {
+        writeln!(serial(), "audio: codec not ready").unwrap();
+        return false;
+    }
+    write16(mixer + NAM_RESET, 0);
+    write16(mixer + NAM_MASTER_VOLUME, 0);
+    write16(mixer + NAM_PCM_OUT_VOLUME, 0x0808);
+
+    write8(bus_master + PO_CR, CR_RESET);
+    time::poll_until(Duration::from_millis(100), || read8(bus_master + PO_CR) & CR_RESET == 0);
+
+    let (Some(mut descriptors), Some(buffers)) = (dma::alloc_contiguous(BUFFER_COUNT * 8), dma::alloc_contiguous(BUFFER_COUNT * BUFFER_BYTES)) else {
+        writeln!(serial(), "audio: no memory for buffers").unwrap();
+        return false;
+    };
+    let entries = descriptors.as_mut_ptr() as *mut u64;
+    for i in 0..BUFFER_COUNT {
+        let address = buffers.phys_addr().as_u64() + (i * BUFFER_BYTES) as u64;
+        let samples = (BUFFER_FRAMES * 2) as u64;
+        unsafe { entries.add(i).write_volatile(address | samples << 32) };
+    }
+    write32(bus_master + PO_BDBAR, descriptors.phys_addr().as_u64() as u32);
+
+    let mut ac97 = Ac97 { mixer, bus_master, _descriptors: descriptors, buffers, next: 0, voices: [const { None }; MAX_VOICES], next_generation: 0 };
+    ac97.refill();
+    write8(bus_master + PO_CR, CR_RUN);
+    writeln!(serial(), "audio: AC'97 at {:?}, mixer {:#x}, bus master {:#x}", device, ac97.mixer, ac97.bus_master).unwrap();
+    without_interrupts(|| *AUDIO.lock() = Some(ac97));
+    true
+}
+
+impl Ac97 {
+    /// Mixes the voices into the buffers between the playing one and [LEAD] ahead of it, and
+    /// moves the last valid index along.
+    fn refill(&mut self) {
+        let playing = read8(self.bus_master + PO_CIV) as usize;
+        let mut filled = false;
+        while (self.next + BUFFER_COUNT - playing) % BUFFER_COUNT < LEAD {
+            self.mix(self.next);
+            self.next = (self.next + 1) % BUFFER_COUNT;
+            filled = true;
+        }
+        if !filled {
+            return;
+        }
+        write8(self.bus_master + PO_LVI, ((self.next + BUFFER_COUNT - 1) % BUFFER_COUNT) as u8);
+        // Ran dry before this refill; start it again
+        let status = read16(self.bus_master + PO_SR);
+        write16(self.bus_master + PO_SR, status & SR_CLEAR);
+        if status & SR_HALTED != 0 {
+            write8(self.bus_master + PO_CR, CR_RUN);
+        }
+    }
+
+    fn mix(&mut self, buffer: usize) {
+        let frames = unsafe { (self.buffers.as_mut_ptr() as *mut [i16; 2]).add(buffer * BUFFER_FRAMES) };
+        let master = if MUTED.load(Ordering::Relaxed) { 0 } else { MASTER.load(Ordering::Relaxed) as i32 };
+        let levels = LEVELS.each_ref().map(|level| level.load(Ordering::Relaxed) as i32);
+        let full = MAX_LEVEL as i32 * MAX_LEVEL as i32;
+        for i in 0..BUFFER_FRAMES {
+            let mut sums = [0; 2];
+            for slot in &mut self.voices {
+                if let Some(voice) = slot {
+                    match voice.next_sample() {
+                        Some(sample) => sums[voice.channel as usize] += sample,
+                        None => *slot = None,
+                    }
+                }
+            }
+            let sum = (sums[0] * levels[0] + sums[1] * levels[1]) * master / full;
+            let sample = sum.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+            unsafe { frames.add(i).write_volatile([sample, sample]) };
+        }
+    }
+
+    fn add(&mut self, source: Source, channel: Channel, volume: i32, looping: bool) -> Option<VoiceId> {
+        let slot = self.voices.iter().position(Option::is_none)?;
+        let generation = self.next_generation;
+        self.next_generation = self.next_generation.wrapping_add(1);
+        self.voices[slot] = Some(Voice { source, channel, volume, looping, phase: 0, generation });
+        Some(VoiceId { slot, generation })
+    }
+}
+
+/// Keeps the output buffers filled. Called from the timer interrupt.
+pub(crate) fn poll() {
+    if let Some(audio) = AUDIO.try_lock().as_mut().and_then(|audio| audio.as_mut()) {
+        audio.refill();
+    }
+}
+
+fn play(source: Source, channel: Channel, volume: i32, looping: bool) -> Option<VoiceId> {
+    without_interrupts(|| AUDIO.lock().as_mut()?.add(source, channel, volume, looping))
+}
+
+/// Whether [init] found a sound card.
+pub fn is_available() -> bool {
+    without_interrupts(|| AUDIO.lock().is_some())
+}
+
+/// Plays a square wave on the effects channel. `volume` goes from 0 to 255; keep it low, voices add up. Returns None
+/// without a sound card or when all voices are busy.
+pub fn play_tone(frequency: u32, duration: Duration, volume: u8) -> Option<VoiceId> {
+    let remaining = frames(duration.as_millis() as u32);
+    play(Source::Tone { frequency, remaining }, Channel::Effects, volume as i32 * 32, false)
+}
+
+/// Plays mono 16-bit samples at [SAMPLE_RATE] on the effects channel, scaled by `volume` / 256.
+pub fn play_pcm(samples: &'static [i16], volume: u8, looping: bool) -> Option<VoiceId> {
+    play(Source::Pcm { samples, position: 0 }, Channel::Effects, volume as i32, looping)
+}
+
+/// Plays a tune of square wave notes on the music channel, from the start again once it ends if `looping`.
+/// Returns None, as well, for a tune with no notes or only notes of 0 ms.
+pub fn play_melody(notes: &'static [Note], volume: u8, looping: bool) -> Option<VoiceId> {
+    if !has_length(notes) {
+        return None;
+    }
+    let remaining = notes.first().map_or(0, |note| frames(note.ms as u32));
+    play(Source::Melody { notes, index: 0, remaining }, Channel::Music, volume as i32 * 32, looping)
+}
+
+/// Sets the volume of everything, from 0 to [MAX_LEVEL].
+pub fn set_master_volume(level: u8) {
+    MASTER.store(level.min(MAX_LEVEL), Ordering::Relaxed);
+}
+
+/// Sets the volume of one channel, from 0 to [MAX_LEVEL].
+pub fn set_volume(channel: Channel, level: u8) {
+    LEVELS[channel as usize].store(level.min(MAX_LEVEL), Ordering::Relaxed);
+}
+
+/// Silences everything, or lets it be heard again, without touching the volumes.
+pub fn set_muted(muted: bool) {
+    MUTED.store(muted, Ordering::Relaxed);
+}
+
+/// Mutes if not muted and the other way round. Returns whether it is muted now.
+pub fn toggle_mute() -> bool {
+    !MUTED.fetch_xor(true, Ordering::Relaxed)
+}
+
+pub fn is_muted() -> bool {
+    MUTED.load(Ordering::Relaxed)
+}
+
+/// Stops a voice, if it is still playing.
+pub fn stop(voice: VoiceId) {
+    without_interrupts(|| {
+        if let Some(audio) = AUDIO.lock().as_mut() {
+            let slot = &mut audio.voices[voice.slot];
+            if slot.as_ref().is_some_and(|playing| playing.generation == voice.generation) {
+                *slot = None;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn melodies_are_read_as_frequency_ms_words() {
+        let notes = parse_melody(b"440:200 0:100").unwrap();
+        assert_eq!((notes[0].frequency, notes[0].ms, notes[1].frequency), (440, 200, 0));
+        assert!(parse_melody(b"440").is_none());
+        // Nothing to play, which would never end if looped
+        assert!(parse_melody(b"").is_none());
+        assert!(parse_melody(b"440:0 0:0").is_none());
+    }
+
+    #[test_case]
+    fn mute_toggles_back() {
+        assert!(!is_muted());
+        assert!(toggle_mute());
+        assert!(is_muted());
+        assert!(!toggle_mute());
+    }
+}
@@ -0,0 +1,73 @@
+use core::arch::asm;
+use core::fmt::Write;
+use x86_64::structures::paging::mapper::TranslateResult;
+use x86_64::VirtAddr;
+use crate::{memory, serial, symbols};
+
+// Walks the chain of saved frame pointers: with frame pointers forced on (see .cargo/config.toml)
+// every function starts with `push rbp; mov rbp, rsp`, so [rbp] is the caller's rbp and
+// [rbp + 8] the return address into the caller.
+
+const MAX_FRAMES: usize = 32;
+
+/// The current frame pointer, to start a walk from here.
+#[inline(always)]
+pub fn frame_pointer() -> u64 {
+    let rbp: u64;
+    unsafe { asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags)) };
+    rbp
+}
+
+/// Whether the 16 bytes of a frame record at `rbp` can be read. Checked without waiting for the
+/// page tables; if they are busy, the walk stops.
+fn is_readable(rbp: u64) -> bool {
+    rbp != 0
+        && rbp.is_multiple_of(8)
+        && [rbp, rbp + 8].iter().all(|&address| {
+            VirtAddr::try_new(address).is_ok_and(|address| matches!(memory::try_translate(address), Some(TranslateResult::Mapped { .. })))
+        })
+}
+
+/// The return addresses on the stack, innermost first, starting from the frame at `rbp`.
+pub fn return_addresses(rbp: u64) -> impl Iterator<Item = u64> {
+    let mut rbp = rbp;
+    core::iter::from_fn(move || {
+        if !is_readable(rbp) {
+            return None;
+        }
+        let (caller_rbp, return_address) = unsafe { (*(rbp as *const u64), *((rbp + 8) as *const u64)) };
+        // Frames only get older further up the stack; anything else is garbage
+        rbp = if caller_rbp > rbp { caller_rbp } else { 0 };
+        (return_address != 0).then_some(return_address)
+    })
+    .take(MAX_FRAMES)
+}
+
+/// Writes a symbolized backtrace to serial: `instruction_pointer` (where the fault or panic
+/// happened, if known) and then the frames from `rbp` up.
+pub fn print(instruction_pointer: Option<u64>, rbp: u64) {
+    let _ = writeln!(serial(), "backtrace:");
+    // A return address points past the call, which may be the start of the next function
+    let frames = instruction_pointer.map(|address| (address, address)).into_iter().chain(return_addresses(rbp).map(|address| (address, address - 1)));
+    print_frames(frames);
+}
+
+/// Writes return addresses collected earlier with [return_addresses] to serial, symbolized.
+pub fn print_addresses(addresses: impl Iterator<Item = u64>) {
+    print_frames(addresses.map(|address| (address, address - 1)));
+}
+
+/// Frames as (address, call site) pairs.
+fn print_frames(frames: impl Iterator<Item = (u64, u64)>) {
+    let mut serial = serial();
+    for (i, (address, call_site)) in frames.enumerate() {
+        match symbols::lookup(call_site) {
+            Some((name, offset)) => {
+                let _ = writeln!(serial, "  #{:<2} {:#018x} {}+{:#x}", i, address, name, offset + address - call_site);
+            }
+            None => {
+                let _ = writeln!(serial, "  #{:<2} {:#018x} ?", i, address);
+            }
+        }
+    }
+}
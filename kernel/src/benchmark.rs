@@ -0,0 +1,133 @@
+use alloc::vec::Vec;
+use core::fmt::Write;
+use game::GameMode;
+use kernel::profiler::{self, Phase};
+use kernel::{rng, serial};
+use crate::Pong;
+use crate::screen::screenwriter;
+
+// Rendering benchmark, hidden from the menu: F6 there starts it, and so does the boot option
+// `benchmark=on`. It bounces more and more balls around the screen, [STAGE_FRAMES] frames at each
+// count in [STAGES], and after each count reports on serial what clearing and drawing a frame
+// took, what presenting it took, and the whole frame, from the profiler's Draw, Present and Frame
+// phases, against the time a frame has at the tick rate. Any key stops it early; at the end it
+// goes back to the menu.
+
+/// Balls on screen in each stage.
+const STAGES: [usize; 4] = [100, 200, 400, 800];
+const STAGE_FRAMES: u32 = 150;
+const BALL_SIZE: usize = 8;
+const MAX_SPEED: u32 = 6;
+
+struct Ball {
+    x: usize,
+    y: usize,
+    dx: isize,
+    dy: isize,
+    color: (u8, u8, u8),
+}
+
+pub struct Benchmark {
+    stage: usize,
+    /// Frames drawn in this stage.
+    frames: u32,
+    balls: Vec<Ball>,
+}
+
+/// Starts the benchmark in place of whatever `pong` was showing.
+pub fn start(pong: &mut Pong) {
+    let frame_us = 1_000_000 / crate::tick_hz();
+    writeln!(serial(), "bench: {}x{}, {} us per frame at {} Hz", pong.game.width, pong.game.height, frame_us, crate::tick_hz()).unwrap();
+    let mut benchmark = Benchmark { stage: 0, frames: 0, balls: Vec::new() };
+    benchmark.begin_stage(pong.game.width, pong.game.height);
+    pong.benchmark = Some(benchmark);
+    pong.game.game_mode = GameMode::Benchmark;
+}
+
+/// Ends the benchmark, finished or not, and goes back to the menu.
+pub fn stop(pong: &mut Pong) {
+    if pong.benchmark.take().is_some_and(|benchmark| benchmark.stage < STAGES.len()) {
+        writeln!(serial(), "bench: stopped").unwrap();
+    }
+    pong.game.game_mode = GameMode::Menu;
+}
+
+/// Moves the balls one frame. After a stage's last frame reports it, and goes on to the next
+/// stage or ends.
+pub fn update(pong: &mut Pong) {
+    let Some(benchmark) = &mut pong.benchmark else { return };
+    let (width, height) = (pong.game.width, pong.game.height);
+    benchmark.step(width, height);
+    benchmark.frames += 1;
+    if benchmark.frames < STAGE_FRAMES {
+        return;
+    }
+    benchmark.report();
+    benchmark.stage += 1;
+    if benchmark.stage < STAGES.len() {
+        benchmark.begin_stage(width, height);
+    } else {
+        writeln!(serial(), "bench: done").unwrap();
+        stop(pong);
+    }
+}
+
+impl Benchmark {
+    /// Adds balls up to this stage's count and starts the profiler's figures over.
+    fn begin_stage(&mut self, width: usize, height: usize) {
+        let speed = || {
+            let speed = rng::range(1..MAX_SPEED + 1) as isize;
+            if rng::bool() { speed } else { -speed }
+        };
+        while self.balls.len() < STAGES[self.stage] {
+            self.balls.push(Ball {
+                x: rng::range(0..width.saturating_sub(BALL_SIZE).max(1) as u32) as usize,
+                y: rng::range(0..height.saturating_sub(BALL_SIZE).max(1) as u32) as usize,
+                dx: speed(),
+                dy: speed(),
+                color: (rng::u32() as u8 | 0x40, (rng::u32() >> 8) as u8 | 0x40, (rng::u32() >> 16) as u8 | 0x40),
+            });
+        }
+        self.frames = 0;
+        profiler::reset();
+    }
+
+    fn step(&mut self, width: usize, height: usize) {
+        let (max_x, max_y) = (width.saturating_sub(BALL_SIZE) as isize, height.saturating_sub(BALL_SIZE) as isize);
+        for ball in &mut self.balls {
+            let (x, y) = (ball.x as isize + ball.dx, ball.y as isize + ball.dy);
+            if x < 0 || x > max_x {
+                ball.dx = -ball.dx;
+            }
+            if y < 0 || y > max_y {
+                ball.dy = -ball.dy;
+            }
+            ball.x = x.clamp(0, max_x) as usize;
+            ball.y = y.clamp(0, max_y) as usize;
+        }
+    }
+
+    fn report(&self) {
+        let frame_us = 1_000_000 / crate::tick_hz() as u128;
+        let frame = profiler::stats(Phase::Frame);
+        writeln!(serial(), "bench: {} balls, {} frames, {}% of the frame time on average, {}% at worst",
+            self.balls.len(), frame.count, frame.avg.as_micros() * 100 / frame_us, frame.max.as_micros() * 100 / frame_us).unwrap();
+        for phase in [Phase::Draw, Phase::Present, Phase::Frame] {
+            writeln!(serial(), "bench:   {}", profiler::stats(phase)).unwrap();
+        }
+    }
+
+    pub fn draw(&self) {
+        let mut writer = screenwriter();
+        for ball in &self.balls {
+            let (r, g, b) = ball.color;
+            for y in ball.y..ball.y + BALL_SIZE {
+                for x in ball.x..ball.x + BALL_SIZE {
+                    writer.draw_pixel(x, y, r, g, b);
+                }
+            }
+        }
+        let label = alloc::format!("{} balls", self.balls.len());
+        writer.draw_string(10, 10, &label, 0xFF, 0xFF, 0xFF);
+    }
+}
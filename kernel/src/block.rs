@@ -0,0 +1,158 @@
+use core::fmt::Write;
+use core::time::Duration;
+use spin::Once;
+use x86_64::instructions::interrupts;
+use crate::time::Instant;
+use crate::{ahci, idle, nvme, serial, virtio_blk};
+
+// The disk, whichever driver found it. [init] tries each in turn and the first disk found is the
+// one [read] and [write] go to; everything above (fat32.rs) works in 512-byte sectors and does not
+// care which it is.
+
+pub const SECTOR_SIZE: usize = 512;
+/// How long a request may take before [wait] gives up on it.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockError {
+    /// No disk, or [init] has not run.
+    NoDevice,
+    /// Not a whole number of sectors.
+    BadLength,
+    /// Past the end of the disk.
+    OutOfRange,
+    ReadOnly,
+    /// The device reported an error.
+    Io,
+    /// The device does not do this kind of request.
+    Unsupported,
+    /// All request slots are in use.
+    Busy,
+    /// The device did not answer within 5 s.
+    Timeout,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    VirtioBlk,
+    Nvme,
+    Ahci,
+}
+
+static BACKEND: Once<Backend> = Once::new();
+
+/// Looks for a disk: virtio-blk, then NVMe, then SATA through AHCI. Returns false if there is
+/// none.
+pub fn init() -> bool {
+    let backend = if virtio_blk::init() {
+        Backend::VirtioBlk
+    } else if nvme::init() {
+        Backend::Nvme
+    } else if ahci::init() {
+        Backend::Ahci
+    } else {
+        writeln!(serial(), "block: no disk").unwrap();
+        return false;
+    };
+    BACKEND.call_once(|| backend);
+    true
+}
+
+/// The driver of the disk, if [init] found one.
+pub fn backend() -> Option<Backend> {
+    BACKEND.get().copied()
+}
+
+pub fn is_available() -> bool {
+    backend().is_some()
+}
+
+/// Size of the disk in sectors, 0 without one.
+pub fn capacity() -> u64 {
+    match backend() {
+        Some(Backend::VirtioBlk) => virtio_blk::capacity(),
+        Some(Backend::Nvme) => nvme::capacity(),
+        Some(Backend::Ahci) => ahci::capacity(),
+        None => 0,
+    }
+}
+
+pub fn is_read_only() -> bool {
+    match backend() {
+        Some(Backend::VirtioBlk) => virtio_blk::is_read_only(),
+        Some(Backend::Nvme | Backend::Ahci) | None => false,
+    }
+}
+
+/// Reads the sectors from `sector` on into `buffer`, whose length must be a whole number of
+/// sectors. Waits until they are in.
+pub fn read(sector: u64, buffer: &mut [u8]) -> Result<(), BlockError> {
+    match backend() {
+        Some(Backend::VirtioBlk) => virtio_blk::read(sector, buffer),
+        Some(Backend::Nvme) => nvme::read(sector, buffer),
+        Some(Backend::Ahci) => ahci::read(sector, buffer),
+        None => Err(BlockError::NoDevice),
+    }
+}
+
+/// Writes `data`, a whole number of sectors, from `sector` on. Waits until the device has taken
+/// it, which may be before it reaches the disk; see [flush].
+pub fn write(sector: u64, data: &[u8]) -> Result<(), BlockError> {
+    match backend() {
+        Some(Backend::VirtioBlk) => virtio_blk::write(sector, data),
+        Some(Backend::Nvme) => nvme::write(sector, data),
+        Some(Backend::Ahci) => ahci::write(sector, data),
+        None => Err(BlockError::NoDevice),
+    }
+}
+
+/// Waits until everything written so far is on the disk.
+pub fn flush() -> Result<(), BlockError> {
+    match backend() {
+        Some(Backend::VirtioBlk) => virtio_blk::flush(),
+        Some(Backend::Nvme) => nvme::flush(),
+        Some(Backend::Ahci) => ahci::flush(),
+        None => Err(BlockError::NoDevice),
+    }
+}
+
+/// The checks every driver makes before a transfer of `len` bytes at `sector`.
+pub(crate) fn check_request(sector: u64, len: usize, capacity: u64, read_only: bool, write: bool) -> Result<(), BlockError> {
+    if !len.is_multiple_of(SECTOR_SIZE) {
+        return Err(BlockError::BadLength);
+    }
+    if sector.checked_add((len / SECTOR_SIZE) as u64).is_none_or(|end| end > capacity) {
+        return Err(BlockError::OutOfRange);
+    }
+    if write && read_only {
+        return Err(BlockError::ReadOnly);
+    }
+    Ok(())
+}
+
+/// Waits for a driver's request to finish: calls `check` until it returns the result, with
+/// interrupts disabled and whether the request has timed out. Halts between calls when
+/// `interrupt_driven` (the completion interrupt ends the halt), and spins otherwise.
+pub(crate) fn wait<T>(interrupt_driven: bool, mut check: impl FnMut(bool) -> Option<T>) -> T {
+    let deadline = Instant::now() + TIMEOUT;
+    let enabled = interrupts::are_enabled();
+    let halt = enabled && interrupt_driven;
+    loop {
+        // Checked with interrupts off, so a completion after the check still ends the halt
+        interrupts::disable();
+        if let Some(result) = check(Instant::now() >= deadline) {
+            if enabled {
+                interrupts::enable();
+            }
+            return result;
+        }
+        if halt {
+            idle::halt();
+        } else {
+            if enabled {
+                interrupts::enable();
+            }
+            core::hint::spin_loop();
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use pc_keyboard::{DecodedKey, KeyCode};
+
+// The controller bridge: a host-side tool (tools/controller_bridge.py) streams the input of
+// controllers plugged into the host over the serial console, so real gamepads work without a USB
+// stack. The packets are mixed in with whatever else is typed on the console; the receive
+// interrupt takes them out before the shell and the keys see the bytes (see
+// interrupts::serial_received), and the input handler set with HandlerTable::bridge gets them.
+//
+// Every packet is [PACKET_LEN] bytes: [SYNC], the kind, the device, an index, a value and a check
+// byte, the wrapping sum of the four before it. Typed text is ASCII or UTF-8, neither of which has
+// the byte [SYNC]. The kinds:
+//
+//   0 hello   a device is connected: the index is its type, 0 for a keyboard, 1 for a gamepad
+//   1 axis    the index is the axis, 0 across and 1 down, the value its position as an i8
+//   2 button  the index is the button, from 0; the value is 1 when pressed and 0 when released
+//   3 key     the index is the key: printable ASCII, 13 for Enter, or 0x80 to 0x83 for the arrows
+//             up, down, left and right; the value is 1 when pressed and 0 when released
+//
+// Devices are numbered from 0 to [MAX_DEVICES] - 1, by the host, in the order it found them.
+
+pub const SYNC: u8 = 0xFE;
+pub const PACKET_LEN: usize = 6;
+pub const MAX_DEVICES: usize = 4;
+
+const HELLO: u8 = 0;
+const AXIS: u8 = 1;
+const BUTTON: u8 = 2;
+const KEY: u8 = 3;
+
+/// What a device says it is, in its hello.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceKind {
+    Keyboard,
+    Gamepad,
+}
+
+/// A packet from the host, read by the [Decoder].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Packet {
+    Hello { device: u8, kind: DeviceKind },
+    Axis { device: u8, axis: u8, value: i8 },
+    Button { device: u8, button: u8, pressed: bool },
+    Key { device: u8, key: DecodedKey, pressed: bool },
+}
+
+/// Reads packets out of the bytes received on the serial console.
+pub struct Decoder {
+    frame: [u8; PACKET_LEN],
+    len: usize,
+}
+
+impl Decoder {
+    pub const fn new() -> Self {
+        Self { frame: [0; PACKET_LEN], len: 0 }
+    }
+
+    /// Whether `byte` belongs to a packet: it starts one, or one has started. Other bytes are
+    /// the console's.
+    pub fn takes(&self, byte: u8) -> bool {
+        self.len > 0 || byte == SYNC
+    }
+
+    /// Feeds a byte [Decoder::takes]. Returns the packet once the last of its bytes is in and it
+    /// checks out; one that does not is dropped.
+    pub fn add_byte(&mut self, byte: u8) -> Option<Packet> {
+        self.frame[self.len] = byte;
+        self.len += 1;
+        if self.len < PACKET_LEN {
+            return None;
+        }
+        let frame = self.frame;
+        let sum = frame[1..PACKET_LEN - 1].iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+        if sum != frame[PACKET_LEN - 1] {
+            // A byte went missing, so this frame may run into the next packet: start again at its
+            // sync byte, if there is one
+            let next = frame[1..].iter().position(|&byte| byte == SYNC).map_or(PACKET_LEN, |at| at + 1);
+            self.frame.copy_within(next.., 0);
+            self.len = PACKET_LEN - next;
+            return None;
+        }
+        self.len = 0;
+        parse(frame[1], frame[2], frame[3], frame[4])
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn parse(kind: u8, device: u8, index: u8, value: u8) -> Option<Packet> {
+    if device as usize >= MAX_DEVICES {
+        return None;
+    }
+    match kind {
+        HELLO => {
+            let kind = match index {
+                0 => DeviceKind::Keyboard,
+                1 => DeviceKind::Gamepad,
+                _ => return None,
+            };
+            Some(Packet::Hello { device, kind })
+        }
+        AXIS => Some(Packet::Axis { device, axis: index, value: value as i8 }),
+        BUTTON => Some(Packet::Button { device, button: index, pressed: value != 0 }),
+        KEY => {
+            let key = match index {
+                b'\r' => DecodedKey::Unicode('\n'),
+                0x20..=0x7E => DecodedKey::Unicode(index as char),
+                0x80 => DecodedKey::RawKey(KeyCode::ArrowUp),
+                0x81 => DecodedKey::RawKey(KeyCode::ArrowDown),
+                0x82 => DecodedKey::RawKey(KeyCode::ArrowLeft),
+                0x83 => DecodedKey::RawKey(KeyCode::ArrowRight),
+                _ => return None,
+            };
+            Some(Packet::Key { device, key, pressed: value != 0 })
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use super::*;
+
+    #[test_case]
+    fn bridge_packets_are_found_among_typed_text() {
+        let mut decoder = Decoder::new();
+        let bytes = [
+            b'a',
+            // A button packet missing its check byte, then an axis packet, then a key
+            SYNC, 2, 1, 0, 1,
+            SYNC, 1, 0, 1, 0x80, 0x82,
+            SYNC, 3, 0, b'w', 1, 0x7B,
+            b'b',
+        ];
+        let mut packets = Vec::new();
+        let mut typed = Vec::new();
+        for byte in bytes {
+            if !decoder.takes(byte) {
+                typed.push(byte);
+            } else if let Some(packet) = decoder.add_byte(byte) {
+                packets.push(packet);
+            }
+        }
+        assert_eq!(typed, b"ab");
+        assert_eq!(packets, [
+            Packet::Axis { device: 0, axis: 1, value: -128 },
+            Packet::Key { device: 0, key: DecodedKey::Unicode('w'), pressed: true },
+        ]);
+    }
+}
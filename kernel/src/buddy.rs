@@ -0,0 +1,130 @@
+use core::ops::Range;
+use spin::Mutex;
+use x86_64::PhysAddr;
+
+pub const PAGE_SIZE: u64 = 4096;
+/// Largest block is 2^MAX_ORDER pages (4 MiB).
+pub const MAX_ORDER: usize = 10;
+
+const NONE: u64 = u64::MAX;
+
+/// Binary buddy allocator for runs of physical pages. A block of order n is 2^n pages and is
+/// aligned to its own size, so its buddy is found by flipping a single address bit. Free lists
+/// are intrusive: the first 8 bytes of each free block hold the address of the next one.
+pub struct BuddyAllocator {
+    physical_offset: u64,
+    free_lists: [u64; MAX_ORDER + 1],
+    free_pages: u64,
+}
+
+pub static BUDDY: Mutex<BuddyAllocator> = Mutex::new(BuddyAllocator::new());
+
+impl BuddyAllocator {
+    pub const fn new() -> Self {
+        Self { physical_offset: 0, free_lists: [NONE; MAX_ORDER + 1], free_pages: 0 }
+    }
+
+    /// Hands the physical range to the allocator. The range must be unused RAM reachable
+    /// through the physical memory mapping at `physical_offset`.
+    pub fn add_region(&mut self, physical_offset: u64, range: Range<u64>) {
+        self.physical_offset = physical_offset;
+        let mut start = range.start.next_multiple_of(PAGE_SIZE);
+        let end = range.end & !(PAGE_SIZE - 1);
+
+        // Split into the largest naturally aligned blocks that fit
+        while start < end {
+            let mut order = MAX_ORDER;
+            while order > 0 && (!start.is_multiple_of(block_size(order)) || start + block_size(order) > end) {
+                order -= 1;
+            }
+            self.free(PhysAddr::new(start), order);
+            start += block_size(order);
+        }
+    }
+
+    /// Allocates `pages` physically contiguous pages, rounded up to a power of two.
+    pub fn alloc_pages(&mut self, pages: usize) -> Option<PhysAddr> {
+        self.alloc(order_for(pages)?)
+    }
+
+    /// Returns pages obtained from [BuddyAllocator::alloc_pages] with the same `pages` count.
+    pub fn free_pages(&mut self, address: PhysAddr, pages: usize) {
+        if let Some(order) = order_for(pages) {
+            self.free(address, order);
+        }
+    }
+
+    /// Bytes currently free.
+    pub fn free_bytes(&self) -> u64 {
+        self.free_pages * PAGE_SIZE
+    }
+
+    fn alloc(&mut self, order: usize) -> Option<PhysAddr> {
+        // Take the smallest free block that is large enough, splitting it down to size
+        let found = (order..=MAX_ORDER).find(|&o| self.free_lists[o] != NONE)?;
+        let block = self.pop(found);
+        for split in (order..found).rev() {
+            self.push(split, block + block_size(split));
+        }
+        self.free_pages -= 1 << order;
+        Some(PhysAddr::new(block))
+    }
+
+    fn free(&mut self, address: PhysAddr, order: usize) {
+        let mut block = address.as_u64();
+        let mut order = order;
+        self.free_pages += 1 << order;
+
+        // Merge with the buddy for as long as it is free too
+        while order < MAX_ORDER && self.remove(order, block ^ block_size(order)) {
+            block &= !block_size(order);
+            order += 1;
+        }
+        self.push(order, block);
+    }
+
+    fn next_of(&self, block: u64) -> *mut u64 {
+        (self.physical_offset + block) as *mut u64
+    }
+
+    fn push(&mut self, order: usize, block: u64) {
+        unsafe { self.next_of(block).write(self.free_lists[order]) };
+        self.free_lists[order] = block;
+    }
+
+    fn pop(&mut self, order: usize) -> u64 {
+        let block = self.free_lists[order];
+        self.free_lists[order] = unsafe { self.next_of(block).read() };
+        block
+    }
+
+    /// Unlinks `block` from the free list of `order`, returning whether it was there.
+    fn remove(&mut self, order: usize, block: u64) -> bool {
+        let mut link: *mut u64 = &mut self.free_lists[order];
+        unsafe {
+            while *link != NONE {
+                if *link == block {
+                    *link = self.next_of(block).read();
+                    return true;
+                }
+                link = self.next_of(*link);
+            }
+        }
+        false
+    }
+}
+
+impl Default for BuddyAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+const fn block_size(order: usize) -> u64 {
+    PAGE_SIZE << order
+}
+
+fn order_for(pages: usize) -> Option<usize> {
+    let order = pages.max(1).next_power_of_two().trailing_zeros() as usize;
+    (order <= MAX_ORDER).then_some(order)
+}
@@ -0,0 +1,174 @@
+use core::fmt::Write;
+use core::time::Duration;
+use game::{Event, Events, GameMode};
+use kernel::{audio, eventlog};
+use crate::tween::{Easing, Tween};
+use crate::{EFFECT_VOLUME, LED_FLASH_MS, Pong, SCORE_POP_MS, profiles, saved, tick_hz};
+
+// What the game does about what happens in play. Pong::update publishes each of a tick's events
+// (game::Event) here, after the physics, and every subscriber gets each one in the order they
+// subscribed: the sound effects, the score's pop and the keyboard's lights, the instant replay, the
+// high score table, the players' records, the statistics and the event log, by default. Something
+// new to do on a paddle hit or a point is one more subscriber, not another branch in the update.
+
+/// Most subscribers at once.
+const MAX_SUBSCRIBERS: usize = 8;
+
+/// A reaction to events, given the game as it is after the tick.
+pub type Subscriber = fn(&mut Pong, Event);
+
+/// The subscribers, in order.
+pub struct Bus {
+    subscribers: [Option<Subscriber>; MAX_SUBSCRIBERS],
+}
+
+impl Bus {
+    /// One with the game's own subscribers.
+    pub const fn new() -> Self {
+        let mut subscribers = [None; MAX_SUBSCRIBERS];
+        subscribers[0] = Some(sound as Subscriber);
+        subscribers[1] = Some(effects as Subscriber);
+        subscribers[2] = Some(instant_replay as Subscriber);
+        subscribers[3] = Some(high_scores as Subscriber);
+        subscribers[4] = Some(records as Subscriber);
+        subscribers[5] = Some(statistics as Subscriber);
+        subscribers[6] = Some(log as Subscriber);
+        Self { subscribers }
+    }
+
+    /// Adds `subscriber` after the others. Returns false if there is no room for it.
+    pub fn subscribe(&mut self, subscriber: Subscriber) -> bool {
+        match self.subscribers.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => {
+                *slot = Some(subscriber);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Hands each of `events` to every subscriber.
+pub fn publish(pong: &mut Pong, events: Events) {
+    // Copied out, so a subscriber can have the whole game
+    let subscribers = pong.bus.subscribers;
+    for event in events.iter() {
+        for subscriber in subscribers.iter().flatten() {
+            subscriber(pong, event);
+        }
+    }
+}
+
+/// Counts of what has happened in play since boot, for the shell's `stats`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Statistics {
+    pub wall_bounces: u32,
+    pub paddle_hits: u32,
+    pub points: u32,
+    pub games: u32,
+    /// The most paddle hits before a point.
+    pub longest_rally: u16,
+}
+
+impl Statistics {
+    pub const fn new() -> Self {
+        Self { wall_bounces: 0, paddle_hits: 0, points: 0, games: 0, longest_rally: 0 }
+    }
+}
+
+fn sound(_: &mut Pong, event: Event) {
+    let (frequency, ms) = match event {
+        Event::WallBounce => (440, 30),
+        Event::PaddleHit => (880, 40),
+        Event::PointScored(_) => (220, 250),
+        Event::GameOver(_) => return,
+    };
+    audio::play_tone(frequency, Duration::from_millis(ms), EFFECT_VOLUME);
+}
+
+fn effects(pong: &mut Pong, event: Event) {
+    if let Event::PointScored(_) = event {
+        pong.score_pop = Tween::new(1.0, 0.0, SCORE_POP_MS, Easing::EaseIn);
+        if !pong.settings.accessibility.reduced_flash {
+            pong.led_flash_until = pong.ticks + LED_FLASH_MS * tick_hz() as u64 / 1000;
+        }
+    }
+}
+
+/// A point shows the last moments again, unless it ended the game or the game is on the network.
+fn instant_replay(pong: &mut Pong, event: Event) {
+    let playing = matches!(pong.game.game_mode, GameMode::OnePlayer | GameMode::TwoPlayer);
+    if matches!(event, Event::PointScored(_)) && playing {
+        pong.instant_replay.start();
+    }
+}
+
+fn high_scores(pong: &mut Pong, event: Event) {
+    if event == Event::GameOver(GameMode::OnePlayer) {
+        pong.new_high_score = pong.high_scores.record(pong.game.returns);
+        if pong.new_high_score.is_some() {
+            saved::save(pong);
+        }
+    }
+}
+
+fn records(pong: &mut Pong, event: Event) {
+    if let Event::GameOver(mode) = event {
+        let winner = pong.game.scoreboard.leader();
+        if pong.profiles.count_game(mode, winner) {
+            kernel::deferred::defer(profiles::save);
+        }
+    }
+}
+
+fn statistics(pong: &mut Pong, event: Event) {
+    let stats = &mut pong.statistics;
+    match event {
+        Event::WallBounce => stats.wall_bounces = stats.wall_bounces.saturating_add(1),
+        Event::PaddleHit => {
+            stats.paddle_hits = stats.paddle_hits.saturating_add(1);
+            stats.longest_rally = stats.longest_rally.max(pong.game.rally);
+        }
+        Event::PointScored(_) => stats.points = stats.points.saturating_add(1),
+        Event::GameOver(_) => stats.games = stats.games.saturating_add(1),
+    }
+}
+
+/// Points go in the event log; the end of a game is there already, as a change of mode.
+fn log(pong: &mut Pong, event: Event) {
+    if let Event::PointScored(player) = event {
+        let (player1, player2) = (pong.game.scoreboard.points(1), pong.game.scoreboard.points(2));
+        eventlog::record(eventlog::Kind::Score, format_args!("player {} scores, {} - {}", player, player1, player2));
+    }
+}
+
+/// The statistics, for the shell.
+pub fn write_statistics(out: &mut impl Write, stats: &Statistics) -> core::fmt::Result {
+    writeln!(out, "{} games, {} points, {} paddle hits, {} wall bounces, longest rally {}",
+        stats.games, stats.points, stats.paddle_hits, stats.wall_bounces, stats.longest_rally)
+}
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::{AtomicU32, Ordering};
+    use super::*;
+    use crate::tests::game;
+
+    #[test_case]
+    fn subscribers_hear_each_event() {
+        static HEARD: AtomicU32 = AtomicU32::new(0);
+        fn count(_: &mut Pong, event: Event) {
+            if event == Event::WallBounce {
+                HEARD.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        let mut pong = game(GameMode::TwoPlayer);
+        assert!(pong.bus.subscribe(count));
+        pong.game.ball_mut().y = 3.0;
+        pong.game.ball_mut().dx = 0.0;
+        pong.game.ball_mut().dy = -5.0;
+        pong.update();
+        assert_eq!(HEARD.load(Ordering::Relaxed), 1);
+        assert_eq!(pong.statistics.wall_bounces, 1);
+    }
+}
@@ -0,0 +1,72 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::str::FromStr;
+use spin::Once;
+use crate::serial;
+
+// Boot options, given as text the bootloader loads as the ramdisk: `key=value` words separated by
+// spaces or lines, e.g. `tick_hz=120 ai=hard theme=neon serial_shell=off`. A `#` starts a comment
+// that runs to the end of the line, and a later value for a key replaces an earlier one. The
+// runner puts `pong.cfg` and the PONG_OPTIONS variable there (see build.rs). Each part of the
+// kernel looks up its own options; what an option means is up to it.
+
+static OPTIONS: Once<Vec<(String, String)>> = Once::new();
+
+/// Reads the options from `text` and logs them to serial. Runs once at boot, after the heap is
+/// set up; until then, and without a ramdisk, every option is unset.
+pub fn init(text: &[u8]) {
+    let options = OPTIONS.call_once(|| parse(&String::from_utf8_lossy(text)));
+    if !options.is_empty() {
+        let _ = write!(serial(), "config:");
+        for (key, value) in options {
+            let _ = write!(serial(), " {}={}", key, value);
+        }
+        let _ = writeln!(serial());
+    }
+}
+
+fn parse(text: &str) -> Vec<(String, String)> {
+    let mut options: Vec<(String, String)> = Vec::new();
+    for line in text.lines() {
+        let line = line.split('#').next().unwrap_or("");
+        for word in line.split_whitespace() {
+            let Some((key, value)) = word.split_once('=') else {
+                let _ = writeln!(serial(), "config: ignoring {}, expected key=value", word);
+                continue;
+            };
+            options.retain(|(existing, _)| existing != key);
+            options.push((key.to_string(), value.to_string()));
+        }
+    }
+    options
+}
+
+/// The value given for `key`, as written.
+pub fn get(key: &str) -> Option<&'static str> {
+    let options = OPTIONS.get()?;
+    options.iter().find(|(existing, _)| existing == key).map(|(_, value)| value.as_str())
+}
+
+/// The value given for `key`, parsed as a `T`. A value that does not parse is reported on serial
+/// and treated as unset.
+pub fn value<T: FromStr>(key: &str) -> Option<T> {
+    let value = get(key)?;
+    let parsed = value.parse().ok();
+    if parsed.is_none() {
+        let _ = writeln!(serial(), "config: ignoring {}={}, not a valid value", key, value);
+    }
+    parsed
+}
+
+/// An on/off option: `on`, `yes`, `true` or `1`, and `off`, `no`, `false` or `0`.
+pub fn flag(key: &str) -> Option<bool> {
+    match get(key)? {
+        "on" | "yes" | "true" | "1" => Some(true),
+        "off" | "no" | "false" | "0" => Some(false),
+        value => {
+            let _ = writeln!(serial(), "config: ignoring {}={}, expected on or off", key, value);
+            None
+        }
+    }
+}
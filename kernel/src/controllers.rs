@@ -0,0 +1,75 @@
+use game::GameMode;
+use kernel::bridge::{MAX_DEVICES, Packet};
+use kernel::gamepad::GamepadState;
+use spin::Mutex;
+use crate::{InputEvent, Pong, netplay};
+
+// Controllers on the host, streamed in over the controller bridge (kernel::bridge). The bridge
+// handler folds each device's axis and button packets into a GamepadState, passed on when it
+// changes, and hands on keys as keys, which the key bindings give to a player as the keyboard's
+// do. Device 0 plays as Player 1 and device 1 as Player 2; any others only work the menus. A stick
+// pushed past [DEAD_ZONE] moves its paddle for as long as it is held, and the buttons do what a USB
+// gamepad's do.
+
+const DEAD_ZONE: i8 = 64;
+/// The vertical axis, the one that moves the paddles.
+const AXIS_Y: u8 = 1;
+
+/// Each device's state as its packets so far leave it. Only the bridge handler touches it.
+static STATES: Mutex<[GamepadState; MAX_DEVICES]> = Mutex::new([GamepadState::new(); MAX_DEVICES]);
+
+/// The input `packet` makes, if any. Called from the bridge handler.
+pub fn input(packet: Packet) -> Option<InputEvent> {
+    let device = match packet {
+        Packet::Key { key, pressed, .. } => return pressed.then_some(InputEvent::Key(key)),
+        Packet::Hello { device, .. } | Packet::Axis { device, .. } | Packet::Button { device, .. } => device,
+    };
+    let mut states = STATES.lock();
+    let slot = &mut states[device as usize];
+    let mut state = *slot;
+    match packet {
+        // Connected again: nothing is held any more
+        Packet::Hello { .. } => state = GamepadState::new(),
+        Packet::Axis { axis: AXIS_Y, value, .. } => {
+            state.up = value < -DEAD_ZONE;
+            state.down = value > DEAD_ZONE;
+        }
+        Packet::Button { button, pressed, .. } if button < 16 => {
+            if pressed {
+                state.buttons |= 1 << button;
+            } else {
+                state.buttons &= !(1 << button);
+            }
+        }
+        _ => {}
+    }
+    if state == *slot {
+        return None;
+    }
+    *slot = state;
+    Some(InputEvent::Controller(device, state))
+}
+
+/// Applies a device's new state: a button pressed does what it does on a USB gamepad.
+pub fn handle(pong: &mut Pong, device: u8, state: GamepadState) {
+    let held = &mut pong.controllers[device as usize];
+    let pressed = state.buttons & !held.buttons;
+    *held = state;
+    crate::press_gamepad_buttons(pong, pressed);
+}
+
+/// Moves the paddles of the devices that play, for as long as their sticks are held.
+pub fn move_paddles(pong: &mut Pong) {
+    for (device, state) in pong.controllers.into_iter().enumerate() {
+        if !state.up && !state.down {
+            continue;
+        }
+        match (device, pong.game.game_mode) {
+            // This side's paddle, in a network game
+            (0, GameMode::Network) => netplay::press(pong, state.up),
+            (0, _) => pong.game.move_paddle(true, state.up),
+            (1, GameMode::TwoPlayer) => pong.game.move_paddle(false, state.up),
+            _ => {}
+        }
+    }
+}
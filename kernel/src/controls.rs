@@ -0,0 +1,122 @@
+use alloc::format;
+use alloc::string::String;
+use pc_keyboard::{DecodedKey, KeyCode};
+use crate::screen::screenwriter;
+use crate::strings::{Text, fill, text};
+
+/// Keys without a character that recordings and saved states can hold, by name or by index here.
+/// Others are left out of them.
+pub const RAW_KEYS: [KeyCode; 22] = [
+    KeyCode::ArrowUp, KeyCode::ArrowDown, KeyCode::ArrowLeft, KeyCode::ArrowRight,
+    KeyCode::F1, KeyCode::F2, KeyCode::F3, KeyCode::F4, KeyCode::F5, KeyCode::F6,
+    KeyCode::F7, KeyCode::F8, KeyCode::F9, KeyCode::F10, KeyCode::F11, KeyCode::F12,
+    KeyCode::Home, KeyCode::End, KeyCode::PageUp, KeyCode::PageDown, KeyCode::Insert, KeyCode::Delete,
+];
+
+/// Game actions that can be bound to a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Player1Up,
+    Player1Down,
+    Player2Up,
+    Player2Down,
+}
+
+impl Action {
+    pub const ALL: [Action; 4] = [Action::Player1Up, Action::Player1Down, Action::Player2Up, Action::Player2Down];
+
+    /// The action's name, in the language chosen.
+    pub fn name(self) -> String {
+        match self {
+            Action::Player1Up => fill(Text::ActionUp, &[&1]),
+            Action::Player1Down => fill(Text::ActionDown, &[&1]),
+            Action::Player2Up => fill(Text::ActionUp, &[&2]),
+            Action::Player2Down => fill(Text::ActionDown, &[&2]),
+        }
+    }
+}
+
+/// Action → key table, edited from the controls screen.
+pub struct KeyBindings {
+    keys: [DecodedKey; Action::ALL.len()],
+    selected: usize,
+    rebinding: bool,
+}
+
+impl KeyBindings {
+    pub const fn new() -> Self {
+        Self {
+            keys: [
+                DecodedKey::Unicode('w'),
+                DecodedKey::Unicode('s'),
+                DecodedKey::Unicode('i'),
+                DecodedKey::Unicode('k'),
+            ],
+            selected: 0,
+            rebinding: false,
+        }
+    }
+
+    pub fn key(&self, action: Action) -> DecodedKey {
+        self.keys[action as usize]
+    }
+
+    /// Returns the action bound to the given key, if any.
+    pub fn action(&self, key: DecodedKey) -> Option<Action> {
+        Action::ALL.iter().copied().find(|&action| self.key(action) == key)
+    }
+
+    /// Binds the key to the action. If another action already uses the key, the two swap keys.
+    pub fn bind(&mut self, action: Action, key: DecodedKey) {
+        if let Some(other) = self.action(key) {
+            self.keys[other as usize] = self.key(action);
+        }
+        self.keys[action as usize] = key;
+    }
+
+    pub fn draw(&self) {
+        screenwriter().draw_string_centered(100, text(Text::ControlsTitle), 0xFF, 0xFF, 0xFF);
+
+        for (i, &action) in Action::ALL.iter().enumerate() {
+            let (r, g, b) = if i == self.selected { (0xFF, 0xFF, 0x55) } else { (0xAA, 0xAA, 0xAA) };
+            let line = format!("{}: {}", action.name(), key_name(self.key(action)));
+            screenwriter().draw_string_centered(130 + i * 20, &line, r, g, b);
+        }
+
+        let y = 130 + Action::ALL.len() * 20 + 20;
+        if self.rebinding {
+            let prompt = fill(Text::PressKeyFor, &[&Action::ALL[self.selected].name()]);
+            screenwriter().draw_string_centered(y, &prompt, 0xFF, 0xFF, 0x55);
+        } else {
+            screenwriter().draw_string_centered(y, text(Text::ControlsHelp), 0xFF, 0xFF, 0xFF);
+            screenwriter().draw_string_centered(y + 20, text(Text::ReturnToMenu), 0xFF, 0xFF, 0xFF);
+        }
+    }
+
+    /// Handles a key press on the controls screen. Returns false when the player leaves the screen.
+    pub fn handle_key(&mut self, key: DecodedKey) -> bool {
+        if self.rebinding {
+            self.bind(Action::ALL[self.selected], key);
+            self.rebinding = false;
+            return true;
+        }
+
+        match key {
+            DecodedKey::Unicode('w') => self.selected = (self.selected + Action::ALL.len() - 1) % Action::ALL.len(),
+            DecodedKey::Unicode('s') => self.selected = (self.selected + 1) % Action::ALL.len(),
+            DecodedKey::Unicode('\n') => self.rebinding = true,
+            DecodedKey::Unicode('r') => return false,
+            _ => {}
+        }
+        true
+    }
+}
+
+/// Human-readable name of a key for on-screen display.
+pub fn key_name(key: DecodedKey) -> String {
+    match key {
+        DecodedKey::Unicode(' ') => String::from("Space"),
+        DecodedKey::Unicode(c) => c.to_uppercase().collect(),
+        DecodedKey::RawKey(code) => format!("{:?}", code),
+    }
+}
@@ -0,0 +1,129 @@
+use core::arch::x86_64::{__cpuid, __cpuid_count};
+use core::fmt;
+use core::fmt::Write;
+use spin::Mutex;
+use crate::serial;
+
+/// What the CPU supports, from CPUID. Read once by [init]; use [features] to choose between
+/// code paths instead of assuming a capability.
+#[derive(Debug, Clone, Copy)]
+pub struct Features {
+    /// "GenuineIntel", "AuthenticAMD", ...
+    pub vendor: [u8; 12],
+    /// The TSC runs at a constant rate in all power states, so it can be used as a clock.
+    pub invariant_tsc: bool,
+    pub sse: bool,
+    pub sse2: bool,
+    pub sse3: bool,
+    pub sse4_1: bool,
+    pub sse4_2: bool,
+    pub avx: bool,
+    pub avx2: bool,
+    pub rdrand: bool,
+    pub rdseed: bool,
+    pub x2apic: bool,
+    /// 1 GiB pages.
+    pub huge_pages_1g: bool,
+    /// Machine check exceptions, and the architecture's error banks to say what went wrong.
+    pub mce: bool,
+    pub mca: bool,
+}
+
+impl Features {
+    const fn none() -> Self {
+        Features {
+            vendor: [0; 12],
+            invariant_tsc: false,
+            sse: false,
+            sse2: false,
+            sse3: false,
+            sse4_1: false,
+            sse4_2: false,
+            avx: false,
+            avx2: false,
+            rdrand: false,
+            rdseed: false,
+            x2apic: false,
+            huge_pages_1g: false,
+            mce: false,
+            mca: false,
+        }
+    }
+
+    pub fn vendor(&self) -> &str {
+        core::str::from_utf8(&self.vendor).unwrap_or("unknown")
+    }
+}
+
+impl fmt::Display for Features {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", self.vendor())?;
+        let flags = [
+            ("invariant-tsc", self.invariant_tsc),
+            ("sse", self.sse),
+            ("sse2", self.sse2),
+            ("sse3", self.sse3),
+            ("sse4.1", self.sse4_1),
+            ("sse4.2", self.sse4_2),
+            ("avx", self.avx),
+            ("avx2", self.avx2),
+            ("rdrand", self.rdrand),
+            ("rdseed", self.rdseed),
+            ("x2apic", self.x2apic),
+            ("1g-pages", self.huge_pages_1g),
+            ("mce", self.mce),
+            ("mca", self.mca),
+        ];
+        for (name, present) in flags {
+            write!(f, " {}{}", if present { '+' } else { '-' }, name)?;
+        }
+        Ok(())
+    }
+}
+
+static FEATURES: Mutex<Features> = Mutex::new(Features::none());
+
+fn bit(register: u32, bit: u32) -> bool {
+    register & (1 << bit) != 0
+}
+
+/// Reads the features and logs a summary to serial. Runs first thing at boot; until then
+/// [features] reports nothing as supported.
+pub fn init() {
+    let basic = __cpuid(0);
+    let mut vendor = [0; 12];
+    vendor[0..4].copy_from_slice(&basic.ebx.to_le_bytes());
+    vendor[4..8].copy_from_slice(&basic.edx.to_le_bytes());
+    vendor[8..12].copy_from_slice(&basic.ecx.to_le_bytes());
+
+    let leaf1 = __cpuid(1);
+    let leaf7 = if basic.eax >= 7 { __cpuid_count(7, 0).ebx } else { 0 };
+    let max_extended = __cpuid(0x8000_0000).eax;
+    let extended1 = if max_extended >= 0x8000_0001 { __cpuid(0x8000_0001).edx } else { 0 };
+    let extended7 = if max_extended >= 0x8000_0007 { __cpuid(0x8000_0007).edx } else { 0 };
+
+    let features = Features {
+        vendor,
+        invariant_tsc: bit(extended7, 8),
+        sse: bit(leaf1.edx, 25),
+        sse2: bit(leaf1.edx, 26),
+        sse3: bit(leaf1.ecx, 0),
+        sse4_1: bit(leaf1.ecx, 19),
+        sse4_2: bit(leaf1.ecx, 20),
+        avx: bit(leaf1.ecx, 28),
+        avx2: bit(leaf7, 5),
+        rdrand: bit(leaf1.ecx, 30),
+        rdseed: bit(leaf7, 18),
+        x2apic: bit(leaf1.ecx, 21),
+        huge_pages_1g: bit(extended1, 26),
+        mce: bit(leaf1.edx, 7),
+        mca: bit(leaf1.edx, 14),
+    };
+    *FEATURES.lock() = features;
+    writeln!(serial(), "CPU {}", features).unwrap();
+}
+
+/// The features read by [init].
+pub fn features() -> Features {
+    *FEATURES.lock()
+}
@@ -0,0 +1,218 @@
+use core::arch::global_asm;
+use core::fmt;
+use core::fmt::Write;
+use spin::Mutex;
+use x86_64::registers::control::{Cr2, Cr3};
+use x86_64::structures::idt::InterruptDescriptorTable;
+use x86_64::VirtAddr;
+use crate::{backtrace, crashdump, hlt_loop, interrupts, process, serial};
+
+/// CPU state at the time of an exception, as saved by the entry stubs below.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Registers {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+    pub vector: u64,
+    /// 0 for exceptions that do not push one.
+    pub error_code: u64,
+    // Pushed by the CPU
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+/// A fatal exception, with everything the crash screen shows.
+#[derive(Debug, Clone, Copy)]
+pub struct Crash {
+    pub registers: Registers,
+    pub cr2: u64,
+    pub cr3: u64,
+}
+
+impl Crash {
+    pub fn name(&self) -> &'static str {
+        exception_name(self.registers.vector as u8)
+    }
+
+    /// The register dump, two registers per line.
+    pub fn lines(&self) -> impl Iterator<Item = [(&'static str, u64); 2]> {
+        let r = &self.registers;
+        [
+            [("RAX", r.rax), ("RBX", r.rbx)],
+            [("RCX", r.rcx), ("RDX", r.rdx)],
+            [("RSI", r.rsi), ("RDI", r.rdi)],
+            [("RBP", r.rbp), ("RSP", r.rsp)],
+            [("R8 ", r.r8), ("R9 ", r.r9)],
+            [("R10", r.r10), ("R11", r.r11)],
+            [("R12", r.r12), ("R13", r.r13)],
+            [("R14", r.r14), ("R15", r.r15)],
+            [("RIP", r.rip), ("RFL", r.rflags)],
+            [("CS ", r.cs), ("SS ", r.ss)],
+            [("CR2", self.cr2), ("CR3", self.cr3)],
+        ]
+        .into_iter()
+    }
+}
+
+impl fmt::Display for Crash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "EXCEPTION: {} (vector {}, error code {:#x})", self.name(), self.registers.vector, self.registers.error_code)?;
+        for [(a, a_value), (b, b_value)] in self.lines() {
+            writeln!(f, "  {} {:#018x}  {} {:#018x}", a, a_value, b, b_value)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn exception_name(vector: u8) -> &'static str {
+    match vector {
+        0 => "divide error",
+        4 => "overflow",
+        5 => "bound range exceeded",
+        6 => "invalid opcode",
+        7 => "device not available",
+        10 => "invalid TSS",
+        11 => "segment not present",
+        12 => "stack-segment fault",
+        13 => "general protection fault",
+        16 => "x87 floating-point exception",
+        17 => "alignment check",
+        19 => "SIMD floating-point exception",
+        _ => "exception",
+    }
+}
+
+static DISPLAY: Mutex<Option<fn(&Crash)>> = Mutex::new(None);
+
+/// Registers the function that draws the crash screen, on top of the dump on serial.
+pub fn set_display(display: fn(&Crash)) {
+    *DISPLAY.lock() = Some(display);
+}
+
+// Each stub pushes a 0 in place of the error code if the CPU did not push one, then the vector,
+// and jumps to crash_common, which pushes every general purpose register and hands the resulting
+// Registers to crash_handler.
+macro_rules! exception_stubs {
+    ($($name:ident: $vector:literal, $error_code:ident;)*) => {
+        $(exception_stubs!(@stub $name, $vector, $error_code);)*
+        unsafe extern "C" {
+            $(fn $name();)*
+        }
+        const STUBS: &[(u8, unsafe extern "C" fn())] = &[$(($vector, $name)),*];
+    };
+    (@stub $name:ident, $vector:literal, error_code) => {
+        global_asm!(
+            concat!(".global ", stringify!($name)),
+            concat!(stringify!($name), ":"),
+            concat!("push ", stringify!($vector)),
+            "jmp crash_common",
+        );
+    };
+    (@stub $name:ident, $vector:literal, no_error_code) => {
+        global_asm!(
+            concat!(".global ", stringify!($name)),
+            concat!(stringify!($name), ":"),
+            "push 0",
+            concat!("push ", stringify!($vector)),
+            "jmp crash_common",
+        );
+    };
+}
+
+exception_stubs! {
+    crash_divide_error: 0, no_error_code;
+    crash_overflow: 4, no_error_code;
+    crash_bound_range: 5, no_error_code;
+    crash_invalid_opcode: 6, no_error_code;
+    crash_device_not_available: 7, no_error_code;
+    crash_invalid_tss: 10, error_code;
+    crash_segment_not_present: 11, error_code;
+    crash_stack_segment: 12, error_code;
+    crash_general_protection: 13, error_code;
+    crash_x87: 16, no_error_code;
+    crash_alignment_check: 17, error_code;
+    crash_simd: 19, no_error_code;
+}
+
+global_asm!(
+    "crash_common:",
+    "push rax",
+    "push rbx",
+    "push rcx",
+    "push rdx",
+    "push rsi",
+    "push rdi",
+    "push rbp",
+    "push r8",
+    "push r9",
+    "push r10",
+    "push r11",
+    "push r12",
+    "push r13",
+    "push r14",
+    "push r15",
+    "mov rdi, rsp",
+    // The handler never returns, so the stack only needs aligning
+    "and rsp, -16",
+    "call {handler}",
+    "ud2",
+    handler = sym crash_handler,
+);
+
+/// Points the exceptions that have no handler of their own at the crash screen.
+pub(crate) fn install(idt: &mut InterruptDescriptorTable) {
+    for &(vector, stub) in STUBS {
+        let address = VirtAddr::new(stub as usize as u64);
+        unsafe {
+            match vector {
+                0 => idt.divide_error.set_handler_addr(address),
+                4 => idt.overflow.set_handler_addr(address),
+                5 => idt.bound_range_exceeded.set_handler_addr(address),
+                6 => idt.invalid_opcode.set_handler_addr(address),
+                7 => idt.device_not_available.set_handler_addr(address),
+                10 => idt.invalid_tss.set_handler_addr(address),
+                11 => idt.segment_not_present.set_handler_addr(address),
+                12 => idt.stack_segment_fault.set_handler_addr(address),
+                13 => idt.general_protection_fault.set_handler_addr(address),
+                16 => idt.x87_floating_point.set_handler_addr(address),
+                17 => idt.alignment_check.set_handler_addr(address),
+                _ => idt.simd_floating_point.set_handler_addr(address),
+            };
+        }
+    }
+}
+
+extern "C" fn crash_handler(registers: &Registers) -> ! {
+    interrupts::count(registers.vector as u8);
+    let crash = Crash { registers: *registers, cr2: Cr2::read_raw(), cr3: Cr3::read().0.start_address().as_u64() };
+    let _ = write!(serial(), "{}", crash);
+    backtrace::print(Some(registers.rip), registers.rbp);
+
+    if registers.cs & 3 == 3 {
+        // A user program's fault only ends the program
+        process::exit(-1);
+    }
+    crashdump::write(crashdump::Reason::Exception(crash.name(), registers), registers.rbp);
+
+    let display = DISPLAY.try_lock().and_then(|display| *display);
+    if let Some(display) = display {
+        display(&crash);
+    }
+    hlt_loop();
+}
@@ -0,0 +1,199 @@
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, Ordering};
+use x86_64::registers::control::{Cr2, Cr3};
+use x86_64::structures::paging::mapper::TranslateResult;
+use x86_64::VirtAddr;
+use crate::crash::Registers;
+use crate::{allocator, backtrace, eventlog, memory, percpu, serial, symbols, time, uart};
+
+// Machine-readable crash dump, written to serial after the human-readable report of a panic or a
+// fatal exception, for tools/crashdump.py to pick out of a serial log, pretty-print and archive.
+//
+// The dump sits between BEGIN and END marker lines. Every line in between is a record: a keyword,
+// a space, and fields separated by spaces, numbers in hex with 0x or in decimal:
+//
+//   version 1
+//   reason panic|exception
+//   message <text to the end of the line>
+//   uptime_ns <n>
+//   cpu <index>
+//   reg <name> <value>                 every register known, CR2 and CR3 included
+//   frame <n> <address> [<symbol+offset>]
+//   heap <used> <free> <peak> <allocations> <frees>
+//   stack <address> <hex bytes>        32 bytes a line, around RSP, while mapped
+//   event <uptime_ns> <kind> <repeats> <text>   the recent events in eventlog.rs, oldest first
+//   log <text>                         the last serial output, one line each
+//
+// Unknown keywords are for later versions and should be skipped.
+
+const BEGIN: &str = "-----BEGIN CRASH DUMP-----";
+const END: &str = "-----END CRASH DUMP-----";
+const VERSION: u32 = 1;
+
+/// Set by the first dump; a crash while dumping must not dump again, and loop.
+static DUMPED: AtomicBool = AtomicBool::new(false);
+
+/// Stack bytes dumped below RSP (the red zone the crash may have been using) and above it (the
+/// frames of the callers).
+const STACK_BELOW: u64 = 128;
+const STACK_ABOVE: u64 = 1024;
+const STACK_LINE: usize = 32;
+
+pub enum Reason<'a> {
+    Panic(&'a dyn fmt::Display),
+    Exception(&'a str, &'a Registers),
+}
+
+/// Writes the crash dump to serial. `rbp` starts the backtrace; an exception's registers give
+/// their own, and RSP for the stack. Only reads memory it has checked is mapped. Only the first
+/// crash is dumped.
+pub fn write(reason: Reason, rbp: u64) {
+    if DUMPED.swap(true, Ordering::Relaxed) {
+        return;
+    }
+    let mut out = serial();
+    let _ = write_dump(&mut out, &reason, rbp);
+}
+
+fn write_dump(out: &mut impl Write, reason: &Reason, rbp: u64) -> fmt::Result {
+    writeln!(out, "{}", BEGIN)?;
+    writeln!(out, "version {}", VERSION)?;
+    let (rsp, rbp, rip) = match reason {
+        Reason::Panic(message) => {
+            writeln!(out, "reason panic")?;
+            write!(out, "message ")?;
+            write!(OneLine(out), "{}", message)?;
+            writeln!(out)?;
+            let rsp: u64;
+            unsafe { core::arch::asm!("mov {}, rsp", out(reg) rsp, options(nomem, nostack, preserves_flags)) };
+            writeln!(out, "reg rsp {:#x}", rsp)?;
+            writeln!(out, "reg rbp {:#x}", rbp)?;
+            (rsp, rbp, None)
+        }
+        Reason::Exception(name, registers) => {
+            writeln!(out, "reason exception")?;
+            writeln!(out, "message {} (vector {}, error code {:#x})", name, registers.vector, registers.error_code)?;
+            write_registers(out, registers)?;
+            (registers.rsp, registers.rbp, Some(registers.rip))
+        }
+    };
+    writeln!(out, "reg cr2 {:#x}", Cr2::read_raw())?;
+    writeln!(out, "reg cr3 {:#x}", Cr3::read().0.start_address().as_u64())?;
+    writeln!(out, "uptime_ns {}", time::uptime_ns())?;
+    if let Some(cpu) = percpu::try_current() {
+        writeln!(out, "cpu {}", cpu.index())?;
+    }
+
+    let frames = rip.into_iter().chain(backtrace::return_addresses(rbp));
+    for (i, address) in frames.enumerate() {
+        // A return address points past the call
+        let call_site = if i == 0 && rip.is_some() { address } else { address - 1 };
+        match symbols::lookup(call_site) {
+            Some((name, offset)) => writeln!(out, "frame {} {:#x} {}+{:#x}", i, address, name, offset + address - call_site)?,
+            None => writeln!(out, "frame {} {:#x}", i, address)?,
+        }
+    }
+
+    let heap = allocator::stats();
+    writeln!(out, "heap {} {} {} {} {}", heap.used, heap.free, heap.peak, heap.allocations, heap.deallocations)?;
+
+    write_stack(out, rsp)?;
+    write_events(out)?;
+    write_log(out)?;
+    writeln!(out, "{}", END)
+}
+
+fn write_registers(out: &mut impl Write, r: &Registers) -> fmt::Result {
+    let registers = [
+        ("rax", r.rax), ("rbx", r.rbx), ("rcx", r.rcx), ("rdx", r.rdx),
+        ("rsi", r.rsi), ("rdi", r.rdi), ("rbp", r.rbp), ("rsp", r.rsp),
+        ("r8", r.r8), ("r9", r.r9), ("r10", r.r10), ("r11", r.r11),
+        ("r12", r.r12), ("r13", r.r13), ("r14", r.r14), ("r15", r.r15),
+        ("rip", r.rip), ("rflags", r.rflags), ("cs", r.cs), ("ss", r.ss),
+    ];
+    registers.iter().try_for_each(|(name, value)| writeln!(out, "reg {} {:#x}", name, value))
+}
+
+fn is_mapped(address: u64) -> bool {
+    VirtAddr::try_new(address).is_ok_and(|address| matches!(memory::try_translate(address), Some(TranslateResult::Mapped { .. })))
+}
+
+/// The stack around `rsp`, stopping at the first page that is not mapped.
+fn write_stack(out: &mut impl Write, rsp: u64) -> fmt::Result {
+    let start = (rsp & !(STACK_LINE as u64 - 1)).saturating_sub(STACK_BELOW);
+    let end = rsp.saturating_add(STACK_ABOVE);
+    let mut address = start;
+    // Below RSP may be unmapped while RSP itself is fine; start from the first mapped page
+    while address < rsp && !is_mapped(address) {
+        address = (address | 0xFFF) + 1;
+    }
+    while address < end {
+        if (address.is_multiple_of(4096) || address == start) && !is_mapped(address) {
+            break;
+        }
+        let line = unsafe { core::slice::from_raw_parts(address as *const u8, STACK_LINE) };
+        write!(out, "stack {:#x} ", address)?;
+        line.iter().try_for_each(|byte| write!(out, "{:02x}", byte))?;
+        writeln!(out)?;
+        address += STACK_LINE as u64;
+    }
+    Ok(())
+}
+
+/// The recent events, unless the crash left the ring locked.
+fn write_events(out: &mut impl Write) -> fmt::Result {
+    let mut result = Ok(());
+    eventlog::recent(None, eventlog::CAPACITY, |event| {
+        result = result.and_then(|_| {
+            write!(out, "event {} {} {} ", event.time_ns, event.kind.name(), event.repeats)?;
+            write!(OneLine(out), "{}", event.text())?;
+            writeln!(out)
+        });
+    });
+    result
+}
+
+/// The recent serial output, a line per record, with anything unprintable as '.'.
+fn write_log(out: &mut impl Write) -> fmt::Result {
+    let mut result = Ok(());
+    let mut at_line_start = true;
+    uart::recent_output(|piece| {
+        for &byte in piece {
+            if result.is_err() {
+                return;
+            }
+            result = match byte {
+                b'\n' if !at_line_start => {
+                    at_line_start = true;
+                    writeln!(out)
+                }
+                b'\n' | b'\r' => Ok(()),
+                byte => {
+                    let prefix = if at_line_start { "log " } else { "" };
+                    at_line_start = false;
+                    let c = if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' };
+                    write!(out, "{}{}", prefix, c)
+                }
+            };
+        }
+    });
+    if !at_line_start {
+        writeln!(out)?;
+    }
+    result
+}
+
+/// Passes text through with line breaks turned into " | ", to keep a record on one line.
+struct OneLine<'a, W: Write>(&'a mut W);
+
+impl<W: Write> Write for OneLine<'_, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for (i, line) in s.split('\n').enumerate() {
+            if i > 0 {
+                self.0.write_str(" | ")?;
+            }
+            self.0.write_str(line.trim_end_matches('\r'))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,41 @@
+use crate::percpu;
+
+// Work queued by interrupt handlers, run by the CPU loop with interrupts enabled. Each CPU has a
+// queue of its own in its per-CPU block, so work runs on the CPU whose handler deferred it.
+
+/// Queues `work` to run outside interrupt context, the next time the CPU loop wakes up.
+/// Safe to call from interrupt handlers. Returns false if the queue is full.
+pub fn defer(work: fn()) -> bool {
+    percpu::current().deferred().push(work)
+}
+
+/// Runs all queued work. A custom [crate::HandlerTable::cpu_loop] must call this regularly,
+/// or the timer handler never runs.
+pub fn run_pending() {
+    while let Some(work) = percpu::current().deferred().pop() {
+        work();
+    }
+}
+
+/// Whether work is waiting for [run_pending].
+pub(crate) fn has_pending() -> bool {
+    !percpu::current().deferred().is_empty()
+}
+
+/// The default CPU loop: sleeps until an interrupt arrives, then runs whatever it deferred.
+/// The time asleep is measured by [crate::idle].
+pub fn run_loop() -> ! {
+    use x86_64::instructions::interrupts;
+
+    loop {
+        // Check for work with interrupts disabled, so one arriving in between cannot be missed
+        // by the hlt; idle::halt re-enables them atomically with going to sleep.
+        interrupts::disable();
+        if !has_pending() {
+            crate::idle::halt();
+        } else {
+            interrupts::enable();
+            run_pending();
+        }
+    }
+}
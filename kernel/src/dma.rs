@@ -0,0 +1,65 @@
+use core::slice;
+use x86_64::PhysAddr;
+use crate::buddy::{BUDDY, PAGE_SIZE};
+use crate::memory;
+
+/// A physically contiguous, page-aligned buffer for devices to read and write. The CPU reaches it
+/// through the physical memory map; devices are given [DmaBuffer::phys_addr]. The pages go back
+/// to the buddy allocator when it is dropped, so keep it alive for as long as a device may use it.
+pub struct DmaBuffer {
+    phys: PhysAddr,
+    virt: *mut u8,
+    len: usize,
+    pages: usize,
+}
+
+unsafe impl Send for DmaBuffer {}
+
+/// Allocates a zeroed buffer of at least `len` bytes backed by contiguous physical pages. Returns
+/// None if no run of pages that long is free.
+pub fn alloc_contiguous(len: usize) -> Option<DmaBuffer> {
+    let pages = len.max(1).div_ceil(PAGE_SIZE as usize);
+    let phys = BUDDY.lock().alloc_pages(pages)?;
+    let virt = memory::phys_to_virt(phys).as_mut_ptr::<u8>();
+    unsafe { virt.write_bytes(0, pages * PAGE_SIZE as usize) };
+    Some(DmaBuffer { phys, virt, len, pages })
+}
+
+impl DmaBuffer {
+    /// Address to program into the device.
+    pub fn phys_addr(&self) -> PhysAddr {
+        self.phys
+    }
+
+    pub fn as_ptr(&self) -> *const u8 {
+        self.virt
+    }
+
+    pub fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.virt
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The buffer's contents. A device may be writing to it, so read anything it shares with one
+    /// through volatile accesses instead.
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.virt, self.len) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.virt, self.len) }
+    }
+}
+
+impl Drop for DmaBuffer {
+    fn drop(&mut self) {
+        BUDDY.lock().free_pages(self.phys, self.pages);
+    }
+}
@@ -0,0 +1,183 @@
+// Just enough of the ELF64 format to list the loadable segments of an image, and to relocate a
+// statically linked position-independent one.
+// See https://refspecs.linuxfoundation.org/elf/gabi4+/ch5.pheader.html
+
+use alloc::vec::Vec;
+
+const MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+const CLASS_64: u8 = 2;
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+const DT_NULL: u64 = 0;
+const DT_RELA: u64 = 7;
+const DT_RELASZ: u64 = 8;
+const R_X86_64_RELATIVE: u32 = 8;
+
+pub const PF_X: u32 = 1 << 0;
+pub const PF_W: u32 = 1 << 1;
+pub const PF_R: u32 = 1 << 2;
+
+/// A PT_LOAD program header.
+#[derive(Debug, Clone, Copy)]
+pub struct Segment {
+    pub offset: u64,
+    pub virtual_address: u64,
+    pub file_size: u64,
+    pub memory_size: u64,
+    /// [PF_R], [PF_W] and [PF_X] bits.
+    pub flags: u32,
+}
+
+impl Segment {
+    pub fn is_writable(&self) -> bool {
+        self.flags & PF_W != 0
+    }
+
+    pub fn is_executable(&self) -> bool {
+        self.flags & PF_X != 0
+    }
+}
+
+fn read_u16(image: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(image.get(offset..offset + 2)?.try_into().ok()?))
+}
+
+fn read_u32(image: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(image.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+fn read_u64(image: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(image.get(offset..offset + 8)?.try_into().ok()?))
+}
+
+fn is_elf64(image: &[u8]) -> bool {
+    image.get(..4) == Some(&MAGIC[..]) && image.get(4) == Some(&CLASS_64)
+}
+
+/// The address execution starts at, relative to where the image is loaded for a
+/// position-independent one.
+pub fn entry_point(image: &[u8]) -> Option<u64> {
+    if !is_elf64(image) {
+        return None;
+    }
+    read_u64(image, 0x18)
+}
+
+/// Program headers of type `kind`, as offsets into `image`.
+fn program_headers(image: &[u8], kind: u32) -> impl Iterator<Item = usize> + '_ {
+    let program_headers = read_u64(image, 0x20).unwrap_or(0) as usize;
+    let entry_size = read_u16(image, 0x36).unwrap_or(0) as usize;
+    let count = if is_elf64(image) { read_u16(image, 0x38).unwrap_or(0) as usize } else { 0 };
+    (0..count)
+        .map(move |i| program_headers + i * entry_size)
+        .filter(move |&header| read_u32(image, header) == Some(kind))
+}
+
+/// The loadable segments of a 64-bit ELF image. Yields nothing if `image` is not one; headers
+/// that run past the end of `image` are skipped.
+pub fn load_segments(image: &[u8]) -> impl Iterator<Item = Segment> + '_ {
+    program_headers(image, PT_LOAD).filter_map(move |header| {
+        Some(Segment {
+            flags: read_u32(image, header + 4)?,
+            offset: read_u64(image, header + 8)?,
+            virtual_address: read_u64(image, header + 16)?,
+            file_size: read_u64(image, header + 32)?,
+            memory_size: read_u64(image, header + 40)?,
+        })
+    })
+}
+
+/// A relocation a position-independent image needs applied once it is loaded at `base`: the
+/// 64-bit word at `base + offset` is set to `base + addend`.
+#[derive(Debug, Clone, Copy)]
+pub struct Relocation {
+    pub offset: u64,
+    pub addend: u64,
+}
+
+/// The relocations in the image's dynamic section. A statically linked position-independent
+/// executable only has R_X86_64_RELATIVE ones; Err carries the type of any other kind found.
+pub fn relocations(image: &[u8]) -> Result<Vec<Relocation>, u32> {
+    let mut relocations = Vec::new();
+    let Some(dynamic) = program_headers(image, PT_DYNAMIC).next() else { return Ok(relocations) };
+    let (Some(dynamic_offset), Some(dynamic_size)) = (read_u64(image, dynamic + 8), read_u64(image, dynamic + 32)) else {
+        return Ok(relocations);
+    };
+
+    let (mut table, mut table_size) = (None, 0);
+    for entry in (dynamic_offset..dynamic_offset + dynamic_size).step_by(16) {
+        let (Some(tag), Some(value)) = (read_u64(image, entry as usize), read_u64(image, entry as usize + 8)) else { break };
+        match tag {
+            DT_NULL => break,
+            DT_RELA => table = Some(value),
+            DT_RELASZ => table_size = value,
+            _ => {}
+        }
+    }
+    // DT_RELA is an address; find it in the file through the segment that loads it
+    let Some(table) = table.and_then(|address| {
+        load_segments(image)
+            .find(|segment| (segment.virtual_address..segment.virtual_address + segment.file_size).contains(&address))
+            .map(|segment| segment.offset + address - segment.virtual_address)
+    }) else {
+        return Ok(relocations);
+    };
+
+    for entry in (table..table + table_size).step_by(24) {
+        let entry = entry as usize;
+        let (Some(offset), Some(info), Some(addend)) = (read_u64(image, entry), read_u64(image, entry + 8), read_u64(image, entry + 16)) else { break };
+        if info as u32 != R_X86_64_RELATIVE {
+            return Err(info as u32);
+        }
+        relocations.push(Relocation { offset, addend });
+    }
+    Ok(relocations)
+}
+
+const SHT_SYMTAB: u32 = 2;
+const STT_FUNC: u8 = 2;
+
+/// A function in the image's symbol table.
+#[derive(Debug, Clone, Copy)]
+pub struct Symbol<'a> {
+    /// As linked, so mangled.
+    pub name: &'a str,
+    /// Before relocation.
+    pub address: u64,
+    pub size: u64,
+}
+
+fn read_str(image: &[u8], offset: usize) -> Option<&str> {
+    let bytes = image.get(offset..)?;
+    let len = bytes.iter().position(|&byte| byte == 0)?;
+    core::str::from_utf8(&bytes[..len]).ok()
+}
+
+/// The functions in the image's symbol table. Yields nothing if it has none (a stripped image).
+pub fn function_symbols(image: &[u8]) -> impl Iterator<Item = Symbol<'_>> + '_ {
+    let section_headers = read_u64(image, 0x28).unwrap_or(0) as usize;
+    let entry_size = read_u16(image, 0x3A).unwrap_or(0) as usize;
+    let count = if is_elf64(image) { read_u16(image, 0x3C).unwrap_or(0) as usize } else { 0 };
+    let section = move |i: usize| section_headers + i * entry_size;
+
+    let symtab = (0..count).find(|&i| read_u32(image, section(i) + 4) == Some(SHT_SYMTAB));
+    let table = symtab.and_then(|i| {
+        let offset = read_u64(image, section(i) + 24)? as usize;
+        let size = read_u64(image, section(i) + 32)? as usize;
+        // The string table holding the names is the section sh_link points at
+        let strings = read_u64(image, section(read_u32(image, section(i) + 40)? as usize) + 24)? as usize;
+        Some((offset, size, strings))
+    });
+    let (offset, size, strings) = table.unwrap_or((0, 0, 0));
+
+    (offset..offset + size).step_by(24).filter_map(move |entry| {
+        if image.get(entry + 4)? & 0xF != STT_FUNC {
+            return None;
+        }
+        Some(Symbol {
+            name: read_str(image, strings + read_u32(image, entry)? as usize)?,
+            address: read_u64(image, entry + 8)?,
+            size: read_u64(image, entry + 16)?,
+        })
+    })
+}
@@ -0,0 +1,183 @@
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU64, Ordering};
+use crate::sync::IrqSafeMutex;
+use crate::time;
+
+// Ring of recent events, kept in memory for looking at after the fact instead of logging every
+// one to serial: key presses, scores and game mode changes, failed allocations and assertions,
+// and device interrupts. Each event is stamped with the uptime and holds a short line of text, formatted into
+// a fixed buffer, so recording never allocates and works in interrupt handlers and the allocator.
+// When the ring is full the oldest event goes. An event just like the one before it (an
+// interrupt firing again, say) adds to that one's repeat count instead of taking a slot.
+//
+// The serial shell's `events` command dumps or filters the ring, and crash dumps include it.
+
+pub const CAPACITY: usize = 256;
+/// Longest text kept; longer is cut off.
+const TEXT_LEN: usize = 48;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Key,
+    Score,
+    /// The game changing mode: starting, ending, going to the menu.
+    Game,
+    /// A failed allocation.
+    Alloc,
+    Interrupt,
+    /// A failed kassert!.
+    Assert,
+}
+
+impl Kind {
+    pub const ALL: [Kind; 6] = [Kind::Key, Kind::Score, Kind::Game, Kind::Alloc, Kind::Interrupt, Kind::Assert];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Key => "key",
+            Kind::Score => "score",
+            Kind::Game => "game",
+            Kind::Alloc => "alloc",
+            Kind::Interrupt => "irq",
+            Kind::Assert => "assert",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Kind> {
+        Kind::ALL.iter().copied().find(|kind| kind.name() == name)
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Event {
+    /// Uptime when it first happened.
+    pub time_ns: u64,
+    pub kind: Kind,
+    /// How many times it happened in a row, 1 or more.
+    pub repeats: u32,
+    len: u8,
+    text: [u8; TEXT_LEN],
+}
+
+impl Event {
+    const EMPTY: Event = Event { time_ns: 0, kind: Kind::Game, repeats: 0, len: 0, text: [0; TEXT_LEN] };
+
+    pub fn text(&self) -> &str {
+        // Cut off on a byte boundary, which may be inside a character
+        match core::str::from_utf8(&self.text[..self.len as usize]) {
+            Ok(text) => text,
+            Err(error) => core::str::from_utf8(&self.text[..error.valid_up_to()]).unwrap_or(""),
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ms = self.time_ns / 1_000_000;
+        write!(f, "{:>6}.{:03}s {:<5} {}", ms / 1000, ms % 1000, self.kind.name(), self.text())?;
+        if self.repeats > 1 {
+            write!(f, " (x{})", self.repeats)?;
+        }
+        Ok(())
+    }
+}
+
+struct Ring {
+    events: [Event; CAPACITY],
+    /// Where the next event goes.
+    next: usize,
+    len: usize,
+}
+
+impl Ring {
+    /// The stored events, oldest first.
+    fn iter(&self) -> impl Iterator<Item = &Event> {
+        let start = (self.next + CAPACITY - self.len) % CAPACITY;
+        (0..self.len).map(move |i| &self.events[(start + i) % CAPACITY])
+    }
+}
+
+static RING: IrqSafeMutex<Ring> = IrqSafeMutex::new(Ring { events: [Event::EMPTY; CAPACITY], next: 0, len: 0 });
+/// Events that found the ring in use and were dropped.
+static MISSED: AtomicU64 = AtomicU64::new(0);
+
+/// Fills an event's text, dropping what does not fit.
+struct Text {
+    len: usize,
+    text: [u8; TEXT_LEN],
+}
+
+impl Write for Text {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let n = s.len().min(TEXT_LEN - self.len);
+        self.text[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+/// Records an event: `record(Kind::Score, format_args!("player {} scores", 1))`. Never waits:
+/// an event that finds the ring in use (by an NMI's interrupted code, or another CPU) is dropped
+/// and counted in [missed].
+pub fn record(kind: Kind, args: fmt::Arguments) {
+    let mut text = Text { len: 0, text: [0; TEXT_LEN] };
+    let _ = text.write_fmt(args);
+    let Some(mut ring) = RING.try_lock() else {
+        MISSED.fetch_add(1, Ordering::Relaxed);
+        return;
+    };
+    let last = (ring.next + CAPACITY - 1) % CAPACITY;
+    if ring.len > 0 {
+        let last = &mut ring.events[last];
+        if last.kind == kind && last.text[..last.len as usize] == text.text[..text.len] {
+            last.repeats = last.repeats.saturating_add(1);
+            return;
+        }
+    }
+    let next = ring.next;
+    ring.events[next] = Event { time_ns: time::uptime_ns(), kind, repeats: 1, len: text.len as u8, text: text.text };
+    ring.next = (next + 1) % CAPACITY;
+    ring.len = (ring.len + 1).min(CAPACITY);
+}
+
+/// Calls `f` with the last `count` events of `kind`, or of any kind, oldest first. Returns false,
+/// without calling it, if the ring is in use: the crash dump must not wait for it.
+pub fn recent(kind: Option<Kind>, count: usize, f: impl FnMut(&Event)) -> bool {
+    let Some(ring) = RING.try_lock() else { return false };
+    let matching = |event: &&Event| kind.is_none_or(|kind| event.kind == kind);
+    let skip = ring.iter().filter(matching).count().saturating_sub(count);
+    ring.iter().filter(matching).skip(skip).for_each(f);
+    true
+}
+
+/// Writes the last `count` events of `kind`, or of any kind, a line each. They are copied out
+/// first: writing to serial is slow, and the ring holds interrupts off.
+pub fn write_recent(out: &mut impl Write, kind: Option<Kind>, count: usize) -> fmt::Result {
+    let mut events = Vec::with_capacity(count.min(CAPACITY));
+    while !recent(kind, count, |event| events.push(*event)) {
+        core::hint::spin_loop();
+    }
+    for event in &events {
+        writeln!(out, "{}", event)?;
+    }
+    if events.is_empty() {
+        writeln!(out, "no events")?;
+    }
+    match missed() {
+        0 => Ok(()),
+        missed => writeln!(out, "{} events missed", missed),
+    }
+}
+
+/// Events dropped since boot because the ring was in use.
+pub fn missed() -> u64 {
+    MISSED.load(Ordering::Relaxed)
+}
+
+/// Forgets every event.
+pub fn clear() {
+    let mut ring = RING.lock();
+    ring.next = 0;
+    ring.len = 0;
+}
@@ -0,0 +1,149 @@
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec::Vec;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+use core::time::Duration;
+use pc_keyboard::DecodedKey;
+use spin::Mutex;
+use x86_64::instructions::interrupts::{self, without_interrupts};
+use crate::{deferred, idle};
+use crate::spsc::SpscQueue;
+use crate::time::Instant;
+
+type Task = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Tasks that are not being polled right now, by id.
+static TASKS: Mutex<BTreeMap<u64, Task>> = Mutex::new(BTreeMap::new());
+/// Ids of tasks whose wakers fired. Interrupt handlers push here, so it is only locked with
+/// interrupts disabled.
+static READY: Mutex<VecDeque<u64>> = Mutex::new(VecDeque::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Starts running `future` as a task on the executor. Tasks only make progress while
+/// [run] is the CPU loop.
+pub fn spawn(future: impl Future<Output = ()> + Send + 'static) {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    TASKS.lock().insert(id, Box::pin(future));
+    wake_task(id);
+}
+
+fn wake_task(id: u64) {
+    without_interrupts(|| READY.lock().push_back(id));
+}
+
+// A waker is just the task id
+const VTABLE: RawWakerVTable = RawWakerVTable::new(
+    |data| RawWaker::new(data, &VTABLE),
+    |data| wake_task(data as u64),
+    |data| wake_task(data as u64),
+    |_| {},
+);
+
+fn waker(id: u64) -> Waker {
+    unsafe { Waker::from_raw(RawWaker::new(id as *const (), &VTABLE)) }
+}
+
+/// Polls every woken task once. Returns whether any was.
+fn run_ready() -> bool {
+    let mut ran = false;
+    while let Some(id) = without_interrupts(|| READY.lock().pop_front()) {
+        // Taken out of the map while polled, so the task can spawn others
+        let Some(mut task) = TASKS.lock().remove(&id) else { continue };
+        let waker = waker(id);
+        if task.as_mut().poll(&mut Context::from_waker(&waker)).is_pending() {
+            TASKS.lock().insert(id, task);
+        }
+        ran = true;
+    }
+    ran
+}
+
+/// A CPU loop for [crate::HandlerTable::cpu_loop] that runs async tasks alongside the deferred
+/// work of the interrupt handlers, and halts when neither has anything to do.
+pub fn run() -> ! {
+    loop {
+        run_ready();
+        deferred::run_pending();
+
+        // As in deferred::run_loop, check with interrupts disabled so a wakeup cannot slip in
+        // between the check and the hlt
+        interrupts::disable();
+        if READY.lock().is_empty() && !deferred::has_pending() {
+            idle::halt();
+        } else {
+            interrupts::enable();
+        }
+    }
+}
+
+/// Wakers of tasks waiting for the next timer interrupt.
+static TICK_WAKERS: Mutex<Vec<Waker>> = Mutex::new(Vec::new());
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Called from the timer interrupt.
+pub(crate) fn tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+    for waker in TICK_WAKERS.lock().drain(..) {
+        waker.wake();
+    }
+}
+
+fn wake_on_tick(waker: &Waker) {
+    without_interrupts(|| TICK_WAKERS.lock().push(waker.clone()));
+}
+
+/// Completes at the next timer interrupt, i.e. the next frame.
+pub fn next_frame() -> impl Future<Output = ()> {
+    let start = TICKS.load(Ordering::Relaxed);
+    core::future::poll_fn(move |cx| {
+        if TICKS.load(Ordering::Relaxed) != start {
+            return Poll::Ready(());
+        }
+        wake_on_tick(cx.waker());
+        Poll::Pending
+    })
+}
+
+/// Completes once `duration` has passed, checked on every timer interrupt.
+pub fn sleep(duration: Duration) -> impl Future<Output = ()> {
+    let deadline = Instant::now() + duration;
+    core::future::poll_fn(move |cx| {
+        if Instant::now() >= deadline {
+            return Poll::Ready(());
+        }
+        wake_on_tick(cx.waker());
+        Poll::Pending
+    })
+}
+
+/// Keys typed since the last [next_key] took one. Kept short: a task that stops reading
+/// should not get a backlog of stale keys.
+static KEYS: SpscQueue<DecodedKey, 16> = SpscQueue::new();
+static KEY_WAKER: Mutex<Option<Waker>> = Mutex::new(None);
+
+/// Called from the keyboard interrupt with every decoded key.
+pub(crate) fn key_pressed(key: DecodedKey) {
+    KEYS.push(key);
+    if let Some(waker) = KEY_WAKER.lock().take() {
+        waker.wake();
+    }
+}
+
+/// Completes with the next key typed. Meant for a single task; with several waiting, only the
+/// last one to poll is woken.
+pub fn next_key() -> impl Future<Output = DecodedKey> {
+    core::future::poll_fn(|cx| {
+        if let Some(key) = KEYS.pop() {
+            return Poll::Ready(key);
+        }
+        without_interrupts(|| *KEY_WAKER.lock() = Some(cx.waker().clone()));
+        // A key may have arrived before the waker was stored
+        match KEYS.pop() {
+            Some(key) => Poll::Ready(key),
+            None => Poll::Pending,
+        }
+    })
+}
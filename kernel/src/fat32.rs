@@ -0,0 +1,678 @@
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use spin::Mutex;
+use crate::block::{self, BlockError, SECTOR_SIZE};
+use crate::{rtc, serial};
+
+// FAT32 on the disk (see block.rs), either the whole disk or its first FAT32 partition. A blank disk
+// (first sector all zeros) is formatted on mount. Paths are split on '/' from the root directory,
+// with 8.3 names compared case-insensitively; long file names are skipped when reading a
+// directory and never written. Files are read and written whole, which is all scores, settings
+// and assets need.
+//
+// One FAT sector is cached for following cluster chains; changes to the FAT are written to every
+// copy right away. The free cluster count in the FSInfo sector is marked unknown on the first
+// change rather than kept up to date.
+
+const ATTR_READ_ONLY: u8 = 0x01;
+const ATTR_HIDDEN: u8 = 0x02;
+const ATTR_SYSTEM: u8 = 0x04;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+const ATTR_LONG_NAME: u8 = ATTR_READ_ONLY | ATTR_HIDDEN | ATTR_SYSTEM | ATTR_VOLUME_ID;
+
+const ENTRY_SIZE: usize = 32;
+/// First name byte of a deleted entry; 0 marks the end of the directory.
+const DELETED: u8 = 0xE5;
+const CLUSTER_MASK: u32 = 0x0FFF_FFFF;
+/// FAT entries from here on end a chain.
+const END_OF_CHAIN: u32 = 0x0FFF_FFF8;
+/// FAT32 needs at least this many clusters; fewer and it is FAT12 or FAT16 by definition.
+const MIN_CLUSTERS: u64 = 65525;
+
+const FSINFO_LEAD: u32 = 0x4161_5252;
+const FSINFO_STRUCT: u32 = 0x6141_7272;
+const FSINFO_TRAIL: u32 = 0xAA55_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsError {
+    /// No disk, or no FAT32 volume on it.
+    NotMounted,
+    NotFound,
+    NotADirectory,
+    IsADirectory,
+    /// A path component that is not a valid 8.3 name.
+    InvalidName,
+    AlreadyExists,
+    /// Removing a directory that still has entries.
+    NotEmpty,
+    DiskFull,
+    /// A cluster chain or directory that makes no sense.
+    Corrupt,
+    Disk(BlockError),
+}
+
+impl From<BlockError> for FsError {
+    fn from(error: BlockError) -> Self {
+        FsError::Disk(error)
+    }
+}
+
+/// An entry of a directory, from [list_dir].
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    pub name: String,
+    pub is_dir: bool,
+    /// In bytes, 0 for a directory.
+    pub size: u32,
+}
+
+impl fmt::Display for DirEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_dir {
+            write!(f, "{:<12}  <DIR>", self.name)
+        } else {
+            write!(f, "{:<12}  {}", self.name, self.size)
+        }
+    }
+}
+
+/// Where a directory entry sits: its directory's cluster and its index there.
+#[derive(Clone, Copy)]
+struct Location {
+    cluster: u32,
+    index: usize,
+}
+
+/// A file or directory found by a path. The root directory has no entry.
+struct Node {
+    cluster: u32,
+    is_dir: bool,
+    size: u32,
+    location: Option<Location>,
+}
+
+struct FileSystem {
+    sectors_per_cluster: u32,
+    /// First sector of the first FAT, on the disk.
+    fat_start: u64,
+    /// Sectors in each FAT.
+    fat_size: u32,
+    fats: u32,
+    /// Sector of cluster 2, on the disk.
+    data_start: u64,
+    cluster_count: u32,
+    root_cluster: u32,
+    /// FSInfo sector on the disk, and whether its free count is still to be marked unknown.
+    fsinfo: Option<(u64, bool)>,
+    /// Where to start looking for a free cluster.
+    next_free: u32,
+    fat_cache: Option<(u64, Vec<u8>)>,
+}
+
+static FS: Mutex<Option<FileSystem>> = Mutex::new(None);
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_sector(sector: u64) -> Result<Vec<u8>, FsError> {
+    let mut buffer = vec![0; SECTOR_SIZE];
+    block::read(sector, &mut buffer)?;
+    Ok(buffer)
+}
+
+/// Mounts the FAT32 volume on the disk, formatting the disk first if it is blank. Returns false
+/// without a disk or a volume.
+pub fn init() -> bool {
+    if !block::is_available() {
+        return false;
+    }
+    let result = read_sector(0).and_then(|first| {
+        if first.iter().all(|&byte| byte == 0) {
+            writeln!(serial(), "fat32: blank disk, formatting").unwrap();
+            format(block::capacity())?;
+        }
+        mount()
+    });
+    match result {
+        Ok(fs) => {
+            writeln!(serial(), "fat32: {} clusters of {} B", fs.cluster_count, fs.sectors_per_cluster as usize * SECTOR_SIZE).unwrap();
+            *FS.lock() = Some(fs);
+            true
+        }
+        Err(error) => {
+            writeln!(serial(), "fat32: not mounted: {:?}", error).unwrap();
+            false
+        }
+    }
+}
+
+/// Whether [init] found a volume.
+pub fn is_mounted() -> bool {
+    FS.lock().is_some()
+}
+
+/// Reads the boot sector of the volume at sector 0, or of the first FAT32 partition in the MBR
+/// there.
+fn mount() -> Result<FileSystem, FsError> {
+    let mut start = 0;
+    let mut boot = read_sector(0)?;
+    if !is_fat32(&boot) {
+        let partition = (0..4).map(|i| &boot[446 + 16 * i..462 + 16 * i]).find(|entry| entry[4] == 0x0B || entry[4] == 0x0C).ok_or(FsError::NotMounted)?;
+        start = read_u32(partition, 8) as u64;
+        boot = read_sector(start)?;
+        if !is_fat32(&boot) {
+            return Err(FsError::NotMounted);
+        }
+    }
+
+    let sectors_per_cluster = boot[13] as u32;
+    let reserved = read_u16(&boot, 14) as u64;
+    let fats = boot[16] as u32;
+    let total = read_u32(&boot, 32) as u64;
+    let fat_size = read_u32(&boot, 36);
+    let data_start = start + reserved + fats as u64 * fat_size as u64;
+    let cluster_count = (total.saturating_sub(data_start - start) / sectors_per_cluster as u64)
+        // Each FAT entry must exist
+        .min(fat_size as u64 * SECTOR_SIZE as u64 / 4 - 2) as u32;
+    let fsinfo = match read_u16(&boot, 48) {
+        0 | 0xFFFF => None,
+        sector => Some((start + sector as u64, true)),
+    };
+    let mut fs = FileSystem {
+        sectors_per_cluster,
+        fat_start: start + reserved,
+        fat_size,
+        fats,
+        data_start,
+        cluster_count,
+        root_cluster: read_u32(&boot, 44),
+        fsinfo,
+        next_free: 2,
+        fat_cache: None,
+    };
+    if let Some((sector, _)) = fs.fsinfo {
+        let info = read_sector(sector)?;
+        let hint = read_u32(&info, 492);
+        if read_u32(&info, 0) == FSINFO_LEAD && fs.is_cluster(hint) {
+            fs.next_free = hint;
+        }
+    }
+    if !fs.is_cluster(fs.root_cluster) {
+        return Err(FsError::Corrupt);
+    }
+    Ok(fs)
+}
+
+fn is_fat32(boot: &[u8]) -> bool {
+    read_u16(boot, 510) == 0xAA55
+        && read_u16(boot, 11) as usize == SECTOR_SIZE
+        && boot[13].is_power_of_two()
+        && read_u16(boot, 14) > 0
+        && boot[16] > 0
+        // No fixed root directory and no 16-bit FAT size: FAT32
+        && read_u16(boot, 17) == 0
+        && read_u16(boot, 22) == 0
+        && read_u32(boot, 36) > 0
+}
+
+/// Formats the whole disk, `sectors` long, as one FAT32 volume.
+fn format(sectors: u64) -> Result<(), FsError> {
+    const RESERVED: u64 = 32;
+    const FATS: u64 = 2;
+    // 512 B clusters up to 260 MB, then 4 KiB, as Microsoft's table has it
+    let sectors_per_cluster: u64 = if sectors <= 532_480 { 1 } else { 8 };
+    let total = sectors.min(u32::MAX as u64);
+    // A disk smaller than the reserved sectors and the FATs has no room for any cluster
+    let data = total.saturating_sub(RESERVED);
+    let fat_size = data.div_ceil((256 * sectors_per_cluster + FATS) / 2);
+    let clusters = data.saturating_sub(FATS * fat_size) / sectors_per_cluster;
+    if clusters < MIN_CLUSTERS {
+        writeln!(serial(), "fat32: disk too small, {} sectors make {} clusters", sectors, clusters).unwrap();
+        return Err(FsError::DiskFull);
+    }
+
+    let mut boot = vec![0; SECTOR_SIZE];
+    boot[0..3].copy_from_slice(&[0xEB, 0x58, 0x90]);
+    boot[3..11].copy_from_slice(b"PONG    ");
+    boot[11..13].copy_from_slice(&(SECTOR_SIZE as u16).to_le_bytes());
+    boot[13] = sectors_per_cluster as u8;
+    boot[14..16].copy_from_slice(&(RESERVED as u16).to_le_bytes());
+    boot[16] = FATS as u8;
+    // Media: fixed disk
+    boot[21] = 0xF8;
+    boot[24..26].copy_from_slice(&32u16.to_le_bytes());
+    boot[26..28].copy_from_slice(&64u16.to_le_bytes());
+    boot[32..36].copy_from_slice(&(total as u32).to_le_bytes());
+    boot[36..40].copy_from_slice(&(fat_size as u32).to_le_bytes());
+    // Root directory in cluster 2, FSInfo in sector 1, backup boot sector in 6
+    boot[44..48].copy_from_slice(&2u32.to_le_bytes());
+    boot[48..50].copy_from_slice(&1u16.to_le_bytes());
+    boot[50..52].copy_from_slice(&6u16.to_le_bytes());
+    boot[64] = 0x80;
+    boot[66] = 0x29;
+    boot[67..71].copy_from_slice(&(rtc::now().timestamp() as u32).to_le_bytes());
+    boot[71..82].copy_from_slice(b"PONG       ");
+    boot[82..90].copy_from_slice(b"FAT32   ");
+    boot[510..512].copy_from_slice(&0xAA55u16.to_le_bytes());
+
+    let mut info = vec![0; SECTOR_SIZE];
+    info[0..4].copy_from_slice(&FSINFO_LEAD.to_le_bytes());
+    info[484..488].copy_from_slice(&FSINFO_STRUCT.to_le_bytes());
+    info[488..492].copy_from_slice(&((clusters - 1) as u32).to_le_bytes());
+    info[492..496].copy_from_slice(&3u32.to_le_bytes());
+    info[508..512].copy_from_slice(&FSINFO_TRAIL.to_le_bytes());
+
+    // Reserved sectors and FATs zeroed, in chunks to keep the buffer small
+    let zeros = vec![0; 64 * SECTOR_SIZE];
+    let mut sector = 0;
+    let end = RESERVED + FATS * fat_size;
+    while sector < end {
+        let count = (end - sector).min(64);
+        block::write(sector, &zeros[..count as usize * SECTOR_SIZE])?;
+        sector += count;
+    }
+    for offset in [0, 6] {
+        block::write(offset, &boot)?;
+        block::write(offset + 1, &info)?;
+    }
+
+    // Entries 0 and 1 are reserved, 2 is the root directory's only cluster
+    let mut fat = vec![0; SECTOR_SIZE];
+    fat[0..4].copy_from_slice(&0x0FFF_FFF8u32.to_le_bytes());
+    fat[4..8].copy_from_slice(&CLUSTER_MASK.to_le_bytes());
+    fat[8..12].copy_from_slice(&CLUSTER_MASK.to_le_bytes());
+    for copy in 0..FATS {
+        block::write(RESERVED + copy * fat_size, &fat)?;
+    }
+    block::write(end, &zeros[..sectors_per_cluster as usize * SECTOR_SIZE])?;
+    block::flush()?;
+    Ok(())
+}
+
+/// The 11-byte directory entry name for `component`, upper-cased.
+fn short_name(component: &str) -> Result<[u8; 11], FsError> {
+    let (base, extension) = component.rsplit_once('.').unwrap_or((component, ""));
+    if base.is_empty() || base.len() > 8 || extension.len() > 3 {
+        return Err(FsError::InvalidName);
+    }
+    let mut name = [b' '; 11];
+    let (name_base, name_extension) = name.split_at_mut(8);
+    for (slot, byte) in name_base.iter_mut().zip(base.bytes()).chain(name_extension.iter_mut().zip(extension.bytes())) {
+        if !(byte.is_ascii_alphanumeric() || b"$%'-_@~`!(){}^#&".contains(&byte)) {
+            return Err(FsError::InvalidName);
+        }
+        *slot = byte.to_ascii_uppercase();
+    }
+    Ok(name)
+}
+
+fn display_name(entry: &[u8]) -> String {
+    let mut name: String = entry[..8].iter().map(|&byte| byte as char).collect::<String>().trim_end().into();
+    // 0xE5 is a valid first character, escaped because it marks deleted entries
+    if entry[0] == 0x05 {
+        name.replace_range(..1, "\u{E5}");
+    }
+    let extension = core::str::from_utf8(&entry[8..11]).unwrap_or("").trim_end();
+    if !extension.is_empty() {
+        name.push('.');
+        name.push_str(extension);
+    }
+    name
+}
+
+fn entry_cluster(entry: &[u8]) -> u32 {
+    ((read_u16(entry, 20) as u32) << 16 | read_u16(entry, 26) as u32) & CLUSTER_MASK
+}
+
+fn set_entry_cluster(entry: &mut [u8], cluster: u32) {
+    entry[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+    entry[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+}
+
+/// Sets the creation, access and modification times to now.
+fn stamp(entry: &mut [u8], created: bool) {
+    let now = rtc::now();
+    let date = (now.year.saturating_sub(1980) << 9) | ((now.month as u16) << 5) | now.day as u16;
+    let time = ((now.hour as u16) << 11) | ((now.minute as u16) << 5) | (now.second as u16 / 2);
+    if created {
+        entry[14..16].copy_from_slice(&time.to_le_bytes());
+        entry[16..18].copy_from_slice(&date.to_le_bytes());
+    }
+    entry[18..20].copy_from_slice(&date.to_le_bytes());
+    entry[22..24].copy_from_slice(&time.to_le_bytes());
+    entry[24..26].copy_from_slice(&date.to_le_bytes());
+}
+
+fn new_entry(name: [u8; 11], attributes: u8, cluster: u32, size: u32) -> [u8; ENTRY_SIZE] {
+    let mut entry = [0; ENTRY_SIZE];
+    entry[..11].copy_from_slice(&name);
+    entry[11] = attributes;
+    set_entry_cluster(&mut entry, cluster);
+    entry[28..32].copy_from_slice(&size.to_le_bytes());
+    stamp(&mut entry, true);
+    entry
+}
+
+impl FileSystem {
+    fn cluster_size(&self) -> usize {
+        self.sectors_per_cluster as usize * SECTOR_SIZE
+    }
+
+    fn is_cluster(&self, cluster: u32) -> bool {
+        (2..self.cluster_count + 2).contains(&cluster)
+    }
+
+    fn cluster_sector(&self, cluster: u32) -> u64 {
+        self.data_start + (cluster - 2) as u64 * self.sectors_per_cluster as u64
+    }
+
+    fn read_cluster(&self, cluster: u32) -> Result<Vec<u8>, FsError> {
+        let mut buffer = vec![0; self.cluster_size()];
+        block::read(self.cluster_sector(cluster), &mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// Writes `data`, at most a cluster, to the start of `cluster`; the rest is zeroed.
+    fn write_cluster(&self, cluster: u32, data: &[u8]) -> Result<(), FsError> {
+        let mut buffer = vec![0; self.cluster_size()];
+        buffer[..data.len()].copy_from_slice(data);
+        block::write(self.cluster_sector(cluster), &buffer)?;
+        Ok(())
+    }
+
+    /// The FAT sector holding `cluster`'s entry, relative to the FAT's start, and the entry's
+    /// offset in it.
+    fn fat_position(cluster: u32) -> (u64, usize) {
+        let offset = cluster as usize * 4;
+        ((offset / SECTOR_SIZE) as u64, offset % SECTOR_SIZE)
+    }
+
+    fn fat_sector(&mut self, sector: u64) -> Result<&mut Vec<u8>, FsError> {
+        if self.fat_cache.as_ref().is_none_or(|(cached, _)| *cached != sector) {
+            self.fat_cache = Some((sector, read_sector(self.fat_start + sector)?));
+        }
+        Ok(&mut self.fat_cache.as_mut().unwrap().1)
+    }
+
+    fn fat_entry(&mut self, cluster: u32) -> Result<u32, FsError> {
+        let (sector, offset) = Self::fat_position(cluster);
+        Ok(read_u32(self.fat_sector(sector)?, offset) & CLUSTER_MASK)
+    }
+
+    fn set_fat_entry(&mut self, cluster: u32, value: u32) -> Result<(), FsError> {
+        self.mark_fsinfo_stale()?;
+        let (sector, offset) = Self::fat_position(cluster);
+        let bytes = self.fat_sector(sector)?;
+        // The top four bits are reserved and kept
+        let old = read_u32(bytes, offset);
+        bytes[offset..offset + 4].copy_from_slice(&((old & !CLUSTER_MASK) | (value & CLUSTER_MASK)).to_le_bytes());
+        let bytes = bytes.clone();
+        for copy in 0..self.fats {
+            block::write(self.fat_start + copy as u64 * self.fat_size as u64 + sector, &bytes)?;
+        }
+        Ok(())
+    }
+
+    fn mark_fsinfo_stale(&mut self) -> Result<(), FsError> {
+        let Some((sector, true)) = self.fsinfo else { return Ok(()) };
+        let mut info = read_sector(sector)?;
+        if read_u32(&info, 0) == FSINFO_LEAD {
+            info[488..492].copy_from_slice(&u32::MAX.to_le_bytes());
+            block::write(sector, &info)?;
+        }
+        self.fsinfo = Some((sector, false));
+        Ok(())
+    }
+
+    /// The clusters of the chain starting at `first`, none for 0 (an empty file).
+    fn chain(&mut self, first: u32) -> Result<Vec<u32>, FsError> {
+        let mut clusters = Vec::new();
+        let mut cluster = first;
+        while cluster != 0 && cluster < END_OF_CHAIN {
+            if !self.is_cluster(cluster) || clusters.len() >= self.cluster_count as usize {
+                return Err(FsError::Corrupt);
+            }
+            clusters.push(cluster);
+            cluster = self.fat_entry(cluster)?;
+        }
+        Ok(clusters)
+    }
+
+    /// Allocates a chain of `count` clusters and returns its first, 0 for none. Nothing is
+    /// allocated if there are not enough free ones.
+    fn allocate(&mut self, count: usize) -> Result<u32, FsError> {
+        let mut free = Vec::with_capacity(count);
+        for i in 0..self.cluster_count {
+            if free.len() == count {
+                break;
+            }
+            let cluster = 2 + (self.next_free - 2 + i) % self.cluster_count;
+            if self.fat_entry(cluster)? == 0 {
+                free.push(cluster);
+            }
+        }
+        if free.len() < count {
+            return Err(FsError::DiskFull);
+        }
+        for (i, &cluster) in free.iter().enumerate() {
+            self.set_fat_entry(cluster, free.get(i + 1).copied().unwrap_or(CLUSTER_MASK))?;
+        }
+        if let Some(&last) = free.last() {
+            self.next_free = 2 + (last - 1) % self.cluster_count;
+        }
+        Ok(free.first().copied().unwrap_or(0))
+    }
+
+    fn free_chain(&mut self, first: u32) -> Result<(), FsError> {
+        for cluster in self.chain(first)? {
+            self.set_fat_entry(cluster, 0)?;
+        }
+        Ok(())
+    }
+
+    /// Allocates clusters for `data` and writes it; returns the first cluster.
+    fn store(&mut self, data: &[u8]) -> Result<u32, FsError> {
+        let first = self.allocate(data.len().div_ceil(self.cluster_size()))?;
+        for (cluster, chunk) in self.chain(first)?.into_iter().zip(data.chunks(self.cluster_size())) {
+            self.write_cluster(cluster, chunk)?;
+        }
+        Ok(first)
+    }
+
+    /// The entries of the directory at `cluster` in use, without long name parts and the volume
+    /// label.
+    fn entries(&mut self, cluster: u32) -> Result<Vec<(Location, [u8; ENTRY_SIZE])>, FsError> {
+        let mut entries = Vec::new();
+        for cluster in self.chain(cluster)? {
+            let data = self.read_cluster(cluster)?;
+            for (index, entry) in data.as_chunks::<ENTRY_SIZE>().0.iter().enumerate() {
+                match entry[0] {
+                    0 => return Ok(entries),
+                    DELETED => {}
+                    _ if entry[11] & ATTR_LONG_NAME == ATTR_LONG_NAME || entry[11] & ATTR_VOLUME_ID != 0 => {}
+                    _ => entries.push((Location { cluster, index }, *entry)),
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    fn find(&mut self, directory: u32, name: &[u8; 11]) -> Result<Option<(Location, [u8; ENTRY_SIZE])>, FsError> {
+        Ok(self.entries(directory)?.into_iter().find(|(_, entry)| &entry[..11] == name))
+    }
+
+    fn resolve(&mut self, path: &str) -> Result<Node, FsError> {
+        let mut node = Node { cluster: self.root_cluster, is_dir: true, size: 0, location: None };
+        for component in path.split('/').filter(|component| !component.is_empty()) {
+            if !node.is_dir {
+                return Err(FsError::NotADirectory);
+            }
+            let (location, entry) = self.find(node.cluster, &short_name(component)?)?.ok_or(FsError::NotFound)?;
+            let is_dir = entry[11] & ATTR_DIRECTORY != 0;
+            let cluster = entry_cluster(&entry);
+            node = Node {
+                // A ".." entry pointing at the root says 0
+                cluster: if is_dir && cluster == 0 { self.root_cluster } else { cluster },
+                is_dir,
+                size: read_u32(&entry, 28),
+                location: Some(location),
+            };
+        }
+        Ok(node)
+    }
+
+    /// The directory `path` is in, and its name there.
+    fn resolve_parent(&mut self, path: &str) -> Result<(u32, [u8; 11]), FsError> {
+        let path = path.trim_end_matches('/');
+        let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+        let name = short_name(name)?;
+        let parent = self.resolve(parent)?;
+        if !parent.is_dir {
+            return Err(FsError::NotADirectory);
+        }
+        Ok((parent.cluster, name))
+    }
+
+    fn write_entry(&self, location: Location, entry: &[u8; ENTRY_SIZE]) -> Result<(), FsError> {
+        let offset = location.index * ENTRY_SIZE;
+        let sector = self.cluster_sector(location.cluster) + (offset / SECTOR_SIZE) as u64;
+        let mut data = read_sector(sector)?;
+        data[offset % SECTOR_SIZE..offset % SECTOR_SIZE + ENTRY_SIZE].copy_from_slice(entry);
+        block::write(sector, &data)?;
+        Ok(())
+    }
+
+    /// Puts `entry` in the first free slot of the directory at `directory`, growing it by a
+    /// cluster if it is full.
+    fn add_entry(&mut self, directory: u32, entry: &[u8; ENTRY_SIZE]) -> Result<(), FsError> {
+        let chain = self.chain(directory)?;
+        for &cluster in &chain {
+            let data = self.read_cluster(cluster)?;
+            if let Some(index) = data.as_chunks::<ENTRY_SIZE>().0.iter().position(|slot| slot[0] == 0 || slot[0] == DELETED) {
+                return self.write_entry(Location { cluster, index }, entry);
+            }
+        }
+        let cluster = self.allocate(1)?;
+        self.write_cluster(cluster, entry)?;
+        self.set_fat_entry(*chain.last().ok_or(FsError::Corrupt)?, cluster)
+    }
+}
+
+fn with_fs<T>(f: impl FnOnce(&mut FileSystem) -> Result<T, FsError>) -> Result<T, FsError> {
+    let mut guard = FS.lock();
+    f(guard.as_mut().ok_or(FsError::NotMounted)?)
+}
+
+/// The whole contents of the file at `path`.
+pub fn read_file(path: &str) -> Result<Vec<u8>, FsError> {
+    with_fs(|fs| {
+        let node = fs.resolve(path)?;
+        if node.is_dir {
+            return Err(FsError::IsADirectory);
+        }
+        let mut data = Vec::with_capacity(node.size as usize);
+        for cluster in fs.chain(node.cluster)? {
+            if data.len() >= node.size as usize {
+                break;
+            }
+            data.extend_from_slice(&fs.read_cluster(cluster)?);
+        }
+        if data.len() < node.size as usize {
+            return Err(FsError::Corrupt);
+        }
+        data.truncate(node.size as usize);
+        Ok(data)
+    })
+}
+
+/// Replaces the contents of the file at `path` with `data`, creating the file if there is none.
+/// Its directory must exist.
+pub fn write_file(path: &str, data: &[u8]) -> Result<(), FsError> {
+    let size = u32::try_from(data.len()).map_err(|_| FsError::DiskFull)?;
+    with_fs(|fs| {
+        let (directory, name) = fs.resolve_parent(path)?;
+        match fs.find(directory, &name)? {
+            Some((_, entry)) if entry[11] & ATTR_DIRECTORY != 0 => return Err(FsError::IsADirectory),
+            Some((location, mut entry)) => {
+                // Freed first, so a file can be rewritten on a nearly full disk
+                fs.free_chain(entry_cluster(&entry))?;
+                let first = fs.store(data)?;
+                set_entry_cluster(&mut entry, first);
+                entry[28..32].copy_from_slice(&size.to_le_bytes());
+                entry[11] |= ATTR_ARCHIVE;
+                stamp(&mut entry, false);
+                fs.write_entry(location, &entry)?;
+            }
+            None => {
+                let first = fs.store(data)?;
+                fs.add_entry(directory, &new_entry(name, ATTR_ARCHIVE, first, size))?;
+            }
+        }
+        block::flush()?;
+        Ok(())
+    })
+}
+
+/// The entries of the directory at `path`, without "." and "..".
+pub fn list_dir(path: &str) -> Result<Vec<DirEntry>, FsError> {
+    with_fs(|fs| {
+        let node = fs.resolve(path)?;
+        if !node.is_dir {
+            return Err(FsError::NotADirectory);
+        }
+        Ok(fs.entries(node.cluster)?.into_iter()
+            .filter(|(_, entry)| entry[0] != b'.')
+            .map(|(_, entry)| DirEntry {
+                name: display_name(&entry),
+                is_dir: entry[11] & ATTR_DIRECTORY != 0,
+                size: read_u32(&entry, 28),
+            })
+            .collect())
+    })
+}
+
+/// Creates an empty directory at `path`; the directory it goes in must exist.
+pub fn create_dir(path: &str) -> Result<(), FsError> {
+    with_fs(|fs| {
+        let (parent, name) = fs.resolve_parent(path)?;
+        if fs.find(parent, &name)?.is_some() {
+            return Err(FsError::AlreadyExists);
+        }
+        let cluster = fs.allocate(1)?;
+        let mut dot = new_entry(*b".          ", ATTR_DIRECTORY, cluster, 0);
+        let dot_dot = new_entry(*b"..         ", ATTR_DIRECTORY, if parent == fs.root_cluster { 0 } else { parent }, 0);
+        let mut data = dot.to_vec();
+        data.extend_from_slice(&dot_dot);
+        fs.write_cluster(cluster, &data)?;
+        dot[..11].copy_from_slice(&name);
+        fs.add_entry(parent, &dot)?;
+        block::flush()?;
+        Ok(())
+    })
+}
+
+/// Removes the file or empty directory at `path`.
+pub fn remove(path: &str) -> Result<(), FsError> {
+    with_fs(|fs| {
+        let node = fs.resolve(path)?;
+        let location = node.location.ok_or(FsError::InvalidName)?;
+        if node.is_dir && fs.entries(node.cluster)?.iter().any(|(_, entry)| entry[0] != b'.') {
+            return Err(FsError::NotEmpty);
+        }
+        fs.free_chain(node.cluster)?;
+        let mut entry = [0; ENTRY_SIZE];
+        entry[0] = DELETED;
+        fs.write_entry(location, &entry)?;
+        block::flush()?;
+        Ok(())
+    })
+}
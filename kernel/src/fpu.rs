@@ -0,0 +1,59 @@
+use core::arch::asm;
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr4, Cr4Flags};
+
+// The x87 FPU and SSE. Neither is usable until the OS says it saves their state (CR4.OSFXSR);
+// before that every SSE instruction is an invalid opcode.
+//
+// Note that x86_64-unknown-none compiles f32/f64 arithmetic to software routines (rustc no longer
+// allows SSE code generation on it), so floats in Rust code work either way. What this enables is
+// SSE instructions used directly, by inline assembly or by ring 3 programs, and the scheduler keeps
+// their state per thread.
+
+/// Default MXCSR: all exceptions masked, round to nearest.
+const MXCSR_DEFAULT: u32 = 0x1F80;
+
+/// Enables the FPU and SSE on the calling CPU and gives it a clean state. Every CPU must run it
+/// before anything touches an FPU or SSE register.
+pub fn init() {
+    unsafe {
+        Cr0::update(|flags| {
+            flags.remove(Cr0Flags::EMULATE_COPROCESSOR | Cr0Flags::TASK_SWITCHED);
+            flags.insert(Cr0Flags::MONITOR_COPROCESSOR | Cr0Flags::NUMERIC_ERROR);
+        });
+        Cr4::update(|flags| flags.insert(Cr4Flags::OSFXSR | Cr4Flags::OSXMMEXCPT_ENABLE));
+    }
+    reset();
+}
+
+/// Puts the FPU and SSE control state back to its defaults, for a fresh thread.
+pub fn reset() {
+    let mxcsr = MXCSR_DEFAULT;
+    unsafe {
+        asm!("fninit", options(nomem, nostack));
+        asm!("ldmxcsr [{}]", in(reg) &mxcsr, options(readonly, nostack));
+    }
+}
+
+/// The x87, MMX and SSE registers, in the layout of FXSAVE.
+#[repr(C, align(16))]
+pub struct FpuState([u8; 512]);
+
+impl FpuState {
+    pub const fn new() -> Self {
+        FpuState([0; 512])
+    }
+
+    pub fn save(&mut self) {
+        unsafe { asm!("fxsave64 [{}]", in(reg) self.0.as_mut_ptr(), options(nostack)) };
+    }
+
+    pub fn restore(&self) {
+        unsafe { asm!("fxrstor64 [{}]", in(reg) self.0.as_ptr(), options(readonly, nostack)) };
+    }
+}
+
+impl Default for FpuState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,40 +1,51 @@
 use bootloader_api::info::MemoryRegionKind::Usable;
 use bootloader_api::info::MemoryRegions;
+use core::ops::Range;
 use x86_64::registers::control::Cr3;
-use x86_64::structures::paging::{FrameAllocator, OffsetPageTable, PageTable, PhysFrame, Size4KiB};
-use x86_64::{PhysAddr, VirtAddr};
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, OffsetPageTable, PageTable, PhysFrame, Size4KiB};
+use x86_64::VirtAddr;
+use crate::buddy::BUDDY;
 
-pub struct BootInfoFrameAllocator {
-    memory_map: &'static MemoryRegions,
-    next: usize,
-}
+/// Hands out single frames from the buddy allocator, which owns all usable memory the heap
+/// does not.
+pub struct BootInfoFrameAllocator;
 
 impl BootInfoFrameAllocator {
-    pub fn new(memory_map: &'static MemoryRegions) -> Self {
-        BootInfoFrameAllocator {
-            memory_map,
-            next: 0,
-        }
+    pub fn new() -> Self {
+        BootInfoFrameAllocator
     }
-    pub fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
-        let regions = self.memory_map.iter();
-
-        let usable_regions = regions.filter(|region| region.kind == Usable);
-        let address_ranges = usable_regions.map(|region| region.start..region.end);
-        let frame_addresses = address_ranges.flat_map(|region| region.step_by(4096));
+}
 
-        frame_addresses.map(|address| PhysFrame::containing_address(PhysAddr::new(address)))
+impl Default for BootInfoFrameAllocator {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
-        let frame = self.usable_frames().nth(self.next);
-        self.next += 1;
-        frame
+        let address = BUDDY.lock().alloc_pages(1)?;
+        Some(PhysFrame::containing_address(address))
+    }
+}
+
+/// Frames returned here (for example after [Mapper::unmap](x86_64::structures::paging::Mapper::unmap))
+/// go back on the buddy allocator's free lists, merging with free neighbours, and are handed out
+/// again by later allocations.
+impl FrameDeallocator<Size4KiB> for BootInfoFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size4KiB>) {
+        BUDDY.lock().free_pages(frame.start_address(), 1);
     }
 }
 
+/// Physical address ranges of the usable regions in the bootloader's memory map.
+pub fn usable_regions(memory_map: &MemoryRegions) -> impl Iterator<Item = Range<u64>> + '_ {
+    memory_map
+        .iter()
+        .filter(|region| region.kind == Usable)
+        .map(|region| region.start..region.end)
+}
+
 pub fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
     let level4_table = active_level4_table(physical_memory_offset);
     unsafe { OffsetPageTable::new(level4_table, physical_memory_offset) }
@@ -0,0 +1,70 @@
+// Keeps the game's pace apart from the timer's and the display's. The timer interrupt only wakes
+// the game up; the clock decides how many ticks are due, at the tick rate (tick_hz), and whether
+// a frame is, at the frame rate in the settings. A display too slow to draw every tick makes the
+// game skip frames rather than slow down, and a timer firing faster than the tick rate does not
+// speed it up.
+
+/// Most ticks caught up at once. Further behind than this, say after a long pause in the
+/// debugger, the game loses the time rather than racing through it.
+const MAX_STEPS: u32 = 4;
+
+pub struct FrameLimiter {
+    /// The clock at the last [FrameLimiter::advance], in nanoseconds.
+    last_ns: Option<u64>,
+    /// Time due to ticks and to frames, not yet spent on them.
+    tick_lag: u64,
+    frame_lag: u64,
+    /// Ticks run without a frame drawn after them, since boot.
+    pub skipped: u64,
+}
+
+impl FrameLimiter {
+    pub const fn new() -> Self {
+        Self { last_ns: None, tick_lag: 0, frame_lag: 0, skipped: 0 }
+    }
+
+    /// How many ticks are due by `now_ns`, at `tick_hz`, and whether a frame should be drawn
+    /// after them, at no more than `frame_hz`. The first call runs one tick and draws it.
+    pub fn advance(&mut self, now_ns: u64, tick_hz: u32, frame_hz: u32) -> (u32, bool) {
+        let (tick_ns, frame_ns) = (1_000_000_000 / tick_hz as u64, 1_000_000_000 / frame_hz as u64);
+        let elapsed = self.last_ns.map_or(tick_ns.max(frame_ns), |last| now_ns.saturating_sub(last));
+        self.last_ns = Some(now_ns);
+
+        self.tick_lag += elapsed;
+        let steps = (self.tick_lag / tick_ns).min(MAX_STEPS as u64) as u32;
+        self.tick_lag = if steps == MAX_STEPS { self.tick_lag % tick_ns } else { self.tick_lag - steps as u64 * tick_ns };
+
+        // Nothing has changed without a tick; nor is more than one frame ever owed
+        self.frame_lag = (self.frame_lag + elapsed).min(2 * frame_ns);
+        let draw = steps > 0 && self.frame_lag >= frame_ns;
+        if draw {
+            self.frame_lag -= frame_ns;
+        }
+        self.skipped += (steps - draw as u32) as u64;
+        (steps, draw)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn frame_limiter_keeps_the_tick_rate() {
+        const MS: u64 = 1_000_000;
+        let mut limiter = FrameLimiter::new();
+        assert_eq!(limiter.advance(1000 * MS, 30, 60), (1, true));
+        // A fast timer: a tick every other call at most
+        assert_eq!(limiter.advance(1010 * MS, 30, 60), (0, false));
+        assert_eq!(limiter.advance(1035 * MS, 30, 60), (1, true));
+        // A slow frame: the ticks it held up run together, drawn once
+        assert_eq!(limiter.advance(1135 * MS, 30, 60), (3, true));
+        assert_eq!(limiter.skipped, 2);
+        // Drawing at 15 fps, every other tick
+        assert_eq!(limiter.advance(1168 * MS, 30, 15), (1, false));
+        assert_eq!(limiter.advance(1201 * MS, 30, 15), (1, true));
+        // Long gone: the time is lost rather than raced through
+        assert_eq!(limiter.advance(11_201 * MS, 30, 60).0, 4);
+        assert_eq!(limiter.advance(11_211 * MS, 30, 60).0, 0);
+    }
+}
@@ -0,0 +1,100 @@
+use game::{Game, GameMode};
+use pc_keyboard::DecodedKey;
+use crate::screen::screenwriter;
+
+// A debug mode for collision and scoring bugs: F7 during a game holds it, and each press of the
+// space bar then lets exactly one tick of the physics through, with the ball's and the paddles'
+// state shown over the field. F7 again lets the game run on. Only the game's update waits: the
+// paddle keys still push, and the push shows on the next step. Not in a network game, which the
+// other side runs on.
+
+const COLOR: (u8, u8, u8) = (0x55, 0xFF, 0x55);
+/// Lines in the overlay.
+const LINES: usize = 5;
+
+pub struct FrameStep {
+    on: bool,
+    /// Ticks let through that have not run yet.
+    steps: u32,
+}
+
+impl FrameStep {
+    pub const fn new() -> Self {
+        Self { on: false, steps: 0 }
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.on
+    }
+
+    pub fn toggle(&mut self) {
+        (self.on, self.steps) = (!self.on, 0);
+    }
+
+    /// Lets a tick through if `key` is the space bar and the game is held. Returns whether it was.
+    pub fn key(&mut self, key: DecodedKey) -> bool {
+        if self.on && key == DecodedKey::Unicode(' ') {
+            self.steps += 1;
+            return true;
+        }
+        false
+    }
+
+    /// Whether `game` may update this tick: always unless it is held, and once a step when it is.
+    /// The hold ends with the game.
+    pub fn take_tick(&mut self, game: &Game) -> bool {
+        if !matches!(game.game_mode, GameMode::OnePlayer | GameMode::TwoPlayer) {
+            self.on = false;
+        }
+        if !self.on {
+            return true;
+        }
+        if self.steps == 0 {
+            return false;
+        }
+        self.steps -= 1;
+        true
+    }
+
+    /// The state of `game`'s ball and paddles, at the bottom left.
+    pub fn draw(&self, game: &Game) {
+        let (ball, paddle1, paddle2) = (game.ball(), game.paddle(1), game.paddle(2));
+        let lines = [
+            alloc::format!("FRAME STEP, tick {} (Space: step, F7: run)", game.match_ticks),
+            alloc::format!("ball x {:.2} y {:.2} dx {:.3} dy {:.3}", ball.x, ball.y, ball.dx, ball.dy),
+            alloc::format!("paddle 1 x {:.2} y {:.2} dy {:.3}", paddle1.x, paddle1.y, paddle1.dy),
+            alloc::format!("paddle 2 x {:.2} y {:.2} dy {:.3}", paddle2.x, paddle2.y, paddle2.dy),
+            alloc::format!("score {}-{}, rally {}", game.scoreboard.points(1), game.scoreboard.points(2), game.rally),
+        ];
+        let mut writer = screenwriter();
+        let mut y = writer.height().saturating_sub(10 + LINES * 16);
+        let (r, g, b) = COLOR;
+        for line in &lines {
+            writer.draw_string(10, y, line, r, g, b);
+            y += 16;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::game;
+
+    #[test_case]
+    fn frame_step_lets_one_tick_through_a_press() {
+        let mut pong = game(GameMode::TwoPlayer);
+        assert!(pong.frame_step.take_tick(&pong.game));
+        pong.frame_step.toggle();
+        assert!(!pong.frame_step.take_tick(&pong.game));
+        assert!(pong.frame_step.key(DecodedKey::Unicode(' ')));
+        assert!(pong.frame_step.take_tick(&pong.game));
+        assert!(!pong.frame_step.take_tick(&pong.game));
+
+        // Held no longer once the game is over
+        pong.game.game_mode = GameMode::GameOver;
+        assert!(pong.frame_step.take_tick(&pong.game));
+        assert!(!pong.frame_step.is_on());
+        assert!(!pong.frame_step.key(DecodedKey::Unicode(' ')));
+    }
+}
@@ -0,0 +1,226 @@
+// HID report descriptor parsing for gamepads, see the "Device Class Definition for HID 1.11",
+// section 6.2.2. Only the fields Pong cares about are located: the Y axis, the hat switch
+// (D-pad) and the buttons.
+
+const USAGE_PAGE_GENERIC_DESKTOP: u32 = 0x01;
+const USAGE_PAGE_BUTTON: u32 = 0x09;
+const USAGE_Y: u32 = 0x31;
+const USAGE_HAT_SWITCH: u32 = 0x39;
+
+const MAX_LOCAL_USAGES: usize = 16;
+
+/// Direction and button state decoded from one gamepad report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GamepadState {
+    pub up: bool,
+    pub down: bool,
+    /// Bit n is set while button n + 1 is held.
+    pub buttons: u16,
+}
+
+impl GamepadState {
+    pub const fn new() -> Self {
+        Self { up: false, down: false, buttons: 0 }
+    }
+}
+
+impl Default for GamepadState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Field {
+    offset: usize,
+    size: usize,
+    min: i32,
+    max: i32,
+}
+
+impl Field {
+    fn value(&self, data: &[u8]) -> Option<i32> {
+        let mut raw: u32 = 0;
+        for bit in 0..self.size.min(32) {
+            let index = self.offset + bit;
+            let byte = *data.get(index / 8)?;
+            raw |= ((byte >> (index % 8)) as u32 & 1) << bit;
+        }
+        if self.min < 0 && (1..32).contains(&self.size) && raw & (1 << (self.size - 1)) != 0 {
+            // Signed field, sign-extend
+            Some((raw | !0 << self.size) as i32)
+        } else {
+            Some(raw as i32)
+        }
+    }
+}
+
+/// Where the interesting fields live inside an input report.
+#[derive(Debug, Clone, Copy)]
+pub struct ReportLayout {
+    report_id: Option<u8>,
+    y: Option<Field>,
+    hat: Option<Field>,
+    buttons: Option<(usize, usize)>,
+}
+
+impl ReportLayout {
+    /// Parses a report descriptor. Returns None if it has neither a Y axis nor a hat switch.
+    pub fn parse(descriptor: &[u8]) -> Option<Self> {
+        let mut layout = ReportLayout { report_id: None, y: None, hat: None, buttons: None };
+
+        let mut usage_page = 0;
+        let mut logical_min = 0;
+        let mut logical_max = 0;
+        let mut report_size = 0;
+        let mut report_count = 0;
+        let mut report_id = None;
+        let mut offset = 0;
+        let mut usages = [0u32; MAX_LOCAL_USAGES];
+        let mut usage_count = 0;
+        let mut usage_min = 0;
+        let mut usage_max = 0;
+
+        let mut i = 0;
+        while i < descriptor.len() {
+            let prefix = descriptor[i];
+            if prefix == 0xFE {
+                // Long item, skip it entirely
+                let size = *descriptor.get(i + 1)? as usize;
+                i += 3 + size;
+                continue;
+            }
+            let size = match prefix & 0x3 { 3 => 4, n => n as usize };
+            let data = descriptor.get(i + 1..i + 1 + size)?;
+            i += 1 + size;
+
+            let unsigned = data.iter().rev().fold(0u32, |acc, &b| acc << 8 | b as u32);
+            let signed = match size {
+                1 => data[0] as i8 as i32,
+                2 => i16::from_le_bytes([data[0], data[1]]) as i32,
+                4 => unsigned as i32,
+                _ => 0,
+            };
+
+            let kind = (prefix >> 2) & 0x3;
+            let tag = prefix >> 4;
+            match (kind, tag) {
+                // Main: Input
+                (0, 0x8) => {
+                    let constant = unsigned & 0x1 != 0;
+                    let interesting = layout.report_id.is_none() || layout.report_id == report_id;
+                    for n in 0..report_count as usize {
+                        let usage = if usage_count > 0 {
+                            usages[n.min(usage_count - 1)]
+                        } else {
+                            usage_min + n as u32
+                        };
+                        let field = Field { offset, size: report_size as usize, min: logical_min, max: logical_max };
+                        let page = if usage > 0xFFFF { usage >> 16 } else { usage_page };
+                        let usage = usage & 0xFFFF;
+
+                        if !constant && interesting {
+                            layout.record(page, usage, field, report_id, usage_min, usage_max);
+                        }
+                        offset += report_size as usize;
+                    }
+                    usage_count = 0;
+                    usage_min = 0;
+                    usage_max = 0;
+                }
+                // Other main items only reset the local state
+                (0, _) => {
+                    usage_count = 0;
+                    usage_min = 0;
+                    usage_max = 0;
+                }
+                (1, 0x0) => usage_page = unsigned,
+                (1, 0x1) => logical_min = signed,
+                (1, 0x2) => logical_max = if logical_min >= 0 { unsigned as i32 } else { signed },
+                (1, 0x7) => report_size = unsigned,
+                (1, 0x8) => {
+                    report_id = Some(unsigned as u8);
+                    offset = 0;
+                }
+                (1, 0x9) => report_count = unsigned,
+                (2, 0x0) => {
+                    if usage_count < MAX_LOCAL_USAGES {
+                        usages[usage_count] = unsigned;
+                        usage_count += 1;
+                    }
+                }
+                (2, 0x1) => usage_min = unsigned,
+                (2, 0x2) => usage_max = unsigned,
+                _ => {}
+            }
+        }
+
+        if layout.y.is_some() || layout.hat.is_some() {
+            Some(layout)
+        } else {
+            None
+        }
+    }
+
+    fn record(&mut self, page: u32, usage: u32, field: Field, report_id: Option<u8>, usage_min: u32, usage_max: u32) {
+        let claimed = match (page, usage) {
+            (USAGE_PAGE_GENERIC_DESKTOP, USAGE_Y) if self.y.is_none() => {
+                self.y = Some(field);
+                true
+            }
+            (USAGE_PAGE_GENERIC_DESKTOP, USAGE_HAT_SWITCH) if self.hat.is_none() => {
+                self.hat = Some(field);
+                true
+            }
+            (USAGE_PAGE_BUTTON, _) if self.buttons.is_none() && field.size == 1 => {
+                let count = (usage_max.saturating_sub(usage_min) as usize + 1).min(16);
+                self.buttons = Some((field.offset, count));
+                true
+            }
+            _ => false,
+        };
+        if claimed && self.report_id.is_none() {
+            self.report_id = report_id;
+        }
+    }
+
+    /// Decodes an input report. Returns None for reports with a different report ID.
+    pub fn decode(&self, report: &[u8]) -> Option<GamepadState> {
+        let data = match self.report_id {
+            Some(id) if report.first() != Some(&id) => return None,
+            Some(_) => &report[1..],
+            None => report,
+        };
+
+        let mut state = GamepadState::new();
+
+        if let Some(y) = self.y {
+            let value = y.value(data)?;
+            let quarter = (y.max - y.min) / 4;
+            state.up |= value < y.min + quarter;
+            state.down |= value > y.max - quarter;
+        }
+
+        if let Some(hat) = self.hat {
+            let value = hat.value(data)?;
+            // Values outside the logical range mean "centered"
+            if (hat.min..=hat.max).contains(&value) {
+                let direction = value - hat.min;
+                if hat.max - hat.min == 3 {
+                    state.up |= direction == 0;
+                    state.down |= direction == 2;
+                } else {
+                    state.up |= matches!(direction, 7 | 0 | 1);
+                    state.down |= matches!(direction, 3..=5);
+                }
+            }
+        }
+
+        if let Some((offset, count)) = self.buttons {
+            let field = Field { offset, size: count, min: 0, max: 1 };
+            state.buttons = field.value(data)? as u16;
+        }
+
+        Some(state)
+    }
+}
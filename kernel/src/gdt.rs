@@ -5,19 +5,20 @@ use x86_64::instructions::tables::load_tss;
 use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
 use x86_64::structures::tss::TaskStateSegment;
 use x86_64::VirtAddr;
-
-pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+use crate::interrupts::{IST_INDICES, IST_STACK_SIZE};
 
 lazy_static! {
     static ref TSS: TaskStateSegment = {
         let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-            let stack_start = VirtAddr::from_ptr( addr_of!(STACK) );
-            stack_start + STACK_SIZE as u64 // stack_end
-        };
+        // Each critical exception gets a stack of its own, so it can still be reported when the
+        // kernel stack is what broke
+        static mut STACKS: [[u8; IST_STACK_SIZE]; IST_INDICES.len()] = [[0; IST_STACK_SIZE]; IST_INDICES.len()];
+        for (i, &index) in IST_INDICES.iter().enumerate() {
+            let stack_start = VirtAddr::from_ptr(unsafe { addr_of!(STACKS[i]) });
+            tss.interrupt_stack_table[index as usize] = (stack_start + IST_STACK_SIZE as u64).align_down(16u64);
+        }
+        // Interrupts from ring 3 switch to this stack; system calls use it too
+        tss.privilege_stack_table[0] = privilege_stack_top();
         tss
     };
 
@@ -26,6 +27,9 @@ lazy_static! {
 
         let code_selector = gdt.append(Descriptor::kernel_code_segment());
         let data_selector = gdt.append(Descriptor::kernel_data_segment());
+        // sysret expects user data right before user code
+        let user_data_selector = gdt.append(Descriptor::user_data_segment());
+        let user_code_selector = gdt.append(Descriptor::user_code_segment());
         let tss_selector = gdt.append(Descriptor::tss_segment(&TSS));
 
         (
@@ -33,6 +37,8 @@ lazy_static! {
             Selectors {
                 code_selector,
                 data_selector,
+                user_code_selector,
+                user_data_selector,
                 tss_selector,
             },
         )
@@ -42,6 +48,8 @@ lazy_static! {
 struct Selectors {
     code_selector: SegmentSelector,
     data_selector: SegmentSelector,
+    user_code_selector: SegmentSelector,
+    user_data_selector: SegmentSelector,
     tss_selector: SegmentSelector,
 }
 
@@ -57,4 +65,14 @@ pub fn init() {
 
         load_tss(GDT.1.tss_selector)
     }
+    // After GS is loaded, which resets its base
+    crate::percpu::init(0);
+    crate::syscall::init(GDT.1.code_selector, GDT.1.data_selector, GDT.1.user_code_selector, GDT.1.user_data_selector, privilege_stack_top());
+}
+
+fn privilege_stack_top() -> VirtAddr {
+    const STACK_SIZE: usize = 4096 * 16;
+    // mut, so it lands in writable memory
+    static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+    (VirtAddr::from_ptr(addr_of!(STACK)) + STACK_SIZE as u64).align_down(16u64)
 }
\ No newline at end of file
@@ -0,0 +1,138 @@
+use alloc::alloc::Layout;
+use core::fmt::Write;
+use core::mem::size_of;
+use core::sync::atomic::{AtomicU64, Ordering};
+use crate::{backtrace, eventlog, serial};
+
+// Heap corruption checks, built in with the `heap-debug` feature (`cargo run --features
+// heap-debug`). The allocator asks for more than each caller did, and lays the block out as
+//
+//   [slack] [Header] [front canary] [the caller's bytes] [back canary]
+//
+// The slack at the start is where the free list and the slabs keep their links once the block
+// is freed, so the header survives a free. The header holds the caller's size, a state word,
+// and the return addresses of the allocation. New memory is filled with [POISON_NEW], freed
+// memory with [POISON_FREED], so reads of uninitialized or freed memory stand out in a dump.
+//
+// A free checks the header and both canaries, and reports on serial, with the allocation's
+// backtrace and the free's: a double free (the state says freed; the block is then not freed
+// again), a pointer that was never allocated or whose header was overwritten (leaked rather than
+// freed), a size that does not match the allocation's, and bytes written over either end.
+// Double frees are caught until the memory is handed out again.
+
+const CANARY: u8 = 0xFD;
+const CANARY_LEN: usize = 16;
+const POISON_NEW: u8 = 0xCD;
+const POISON_FREED: u8 = 0xDD;
+/// Room left at the start of a block for the allocator's own links.
+const SLACK: usize = 16;
+/// Return addresses kept per allocation.
+const CALLERS: usize = 8;
+
+const LIVE: u64 = 0x4C49_5645_4845_4150;
+const FREED: u64 = 0x4652_4545_4845_4150;
+
+#[repr(C)]
+struct Header {
+    state: u64,
+    /// The size the caller asked for.
+    size: usize,
+    /// Allocation number, counting from 1 at boot.
+    id: u64,
+    callers: [u64; CALLERS],
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+/// Problems found since boot.
+static REPORTS: AtomicU64 = AtomicU64::new(0);
+
+/// Where the caller's bytes start in a block for `layout`.
+fn data_offset(layout: Layout) -> usize {
+    (SLACK + size_of::<Header>() + CANARY_LEN).next_multiple_of(layout.align())
+}
+
+/// The layout to ask the allocator for in place of `layout`.
+pub(crate) fn block_layout(layout: Layout) -> Option<Layout> {
+    let size = data_offset(layout).checked_add(layout.size())?.checked_add(CANARY_LEN)?;
+    Layout::from_size_align(size, layout.align().max(align_of::<Header>())).ok()
+}
+
+fn header_of(data: *mut u8) -> *mut Header {
+    data.wrapping_sub(CANARY_LEN + size_of::<Header>()) as *mut Header
+}
+
+/// Sets up a new `block` for `layout` and returns the caller's pointer into it.
+///
+/// ## Safety
+/// `block` must be a fresh allocation of [block_layout]`(layout)`.
+pub(crate) unsafe fn on_alloc(block: *mut u8, layout: Layout) -> *mut u8 {
+    let mut callers = [0; CALLERS];
+    for (slot, address) in callers.iter_mut().zip(backtrace::return_addresses(backtrace::frame_pointer())) {
+        *slot = address;
+    }
+    unsafe {
+        let data = block.add(data_offset(layout));
+        header_of(data).write(Header { state: LIVE, size: layout.size(), id: NEXT_ID.fetch_add(1, Ordering::Relaxed), callers });
+        data.sub(CANARY_LEN).write_bytes(CANARY, CANARY_LEN);
+        data.write_bytes(POISON_NEW, layout.size());
+        data.add(layout.size()).write_bytes(CANARY, CANARY_LEN);
+        data
+    }
+}
+
+/// Checks the allocation at `data` as it is freed, and poisons it. Returns the block to give
+/// back to the allocator, or None when it must not be: freed already, or not an allocation.
+///
+/// ## Safety
+/// `data` must be what the caller was given for an allocation with `layout`, as `dealloc`
+/// requires; this checks as much of that as it can.
+pub(crate) unsafe fn on_free(data: *mut u8, layout: Layout) -> Option<*mut u8> {
+    let header = unsafe { header_of(data).read() };
+    match header.state {
+        LIVE => {}
+        FREED => {
+            report(data, &header, format_args!("double free of {:p}, {} bytes", data, header.size));
+            return None;
+        }
+        state => {
+            report(data, &header, format_args!("free of {:p}: not a live allocation, or its header was overwritten (state {:#x})", data, state));
+            return None;
+        }
+    }
+    if header.size != layout.size() {
+        report(data, &header, format_args!("{:p} freed as {} bytes, allocated as {}", data, layout.size(), header.size));
+    }
+    let size = header.size.min(layout.size());
+    let front = unsafe { core::slice::from_raw_parts(data.sub(CANARY_LEN), CANARY_LEN) };
+    if let Some(offset) = front.iter().rposition(|&byte| byte != CANARY) {
+        report(data, &header, format_args!("{:p}: written {} bytes before its start", data, CANARY_LEN - offset));
+    }
+    let back = unsafe { core::slice::from_raw_parts(data.add(size), CANARY_LEN) };
+    if let Some(offset) = back.iter().position(|&byte| byte != CANARY) {
+        report(data, &header, format_args!("{:p}: written past its end of {} bytes, at +{}", data, size, size + offset));
+    }
+    unsafe {
+        data.write_bytes(POISON_FREED, size);
+        (*header_of(data)).state = FREED;
+        Some(data.sub(data_offset(layout)))
+    }
+}
+
+fn report(data: *mut u8, header: &Header, problem: core::fmt::Arguments) {
+    REPORTS.fetch_add(1, Ordering::Relaxed);
+    eventlog::record(eventlog::Kind::Alloc, problem);
+    let mut serial = serial();
+    let _ = writeln!(serial, "HEAP CORRUPTION: {}", problem);
+    // The header means nothing if it is not one
+    if matches!(header.state, LIVE | FREED) {
+        let _ = writeln!(serial, "allocation #{} of {} bytes at {:p}, allocated at:", header.id, header.size, data);
+        backtrace::print_addresses(header.callers.iter().copied().take_while(|&address| address != 0));
+    }
+    let _ = writeln!(serial, "freed at:");
+    backtrace::print(None, backtrace::frame_pointer());
+}
+
+/// Problems found since boot.
+pub fn reports() -> u64 {
+    REPORTS.load(Ordering::Relaxed)
+}
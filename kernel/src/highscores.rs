@@ -0,0 +1,48 @@
+use alloc::format;
+use crate::screen::screenwriter;
+use crate::strings::{Text, fill, text};
+
+/// Scores kept in the table.
+pub const COUNT: usize = 5;
+
+/// The best one-player games, scored by how many times Player 1 returned the ball. Saved with the
+/// settings, see [crate::saved].
+pub struct HighScores {
+    /// Best first; 0 for an empty place.
+    scores: [u16; COUNT],
+}
+
+impl HighScores {
+    pub const fn new() -> Self {
+        Self { scores: [0; COUNT] }
+    }
+
+    pub fn scores(&self) -> [u16; COUNT] {
+        self.scores
+    }
+
+    /// Replaces the table, as loaded from a saved record.
+    pub fn set_scores(&mut self, mut scores: [u16; COUNT]) {
+        scores.sort_unstable_by(|a, b| b.cmp(a));
+        self.scores = scores;
+    }
+
+    /// Enters a game's score. Returns its place in the table, 0 the best, or None if it did not
+    /// make it.
+    pub fn record(&mut self, score: u16) -> Option<usize> {
+        let place = self.scores.iter().position(|&best| score > best)?;
+        self.scores[place..].rotate_right(1);
+        self.scores[place] = score;
+        Some(place)
+    }
+
+    /// Draws the table from `y` down, with the place `new` stood out.
+    pub fn draw(&self, y: usize, new: Option<usize>) {
+        screenwriter().draw_string_centered(y, text(Text::HighScores), 0xFF, 0xFF, 0xFF);
+        for (place, &score) in self.scores.iter().enumerate().filter(|&(_, &score)| score > 0) {
+            let (r, g, b) = if Some(place) == new { (0xFF, 0xFF, 0x55) } else { (0xAA, 0xAA, 0xAA) };
+            let line = format!("{}. {}", place + 1, fill(Text::Returns, &[&score]));
+            screenwriter().draw_string_centered(y + 20 + place * 20, &line, r, g, b);
+        }
+    }
+}
@@ -0,0 +1,49 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use crate::time;
+
+// Idle time of the boot CPU, which runs the CPU loop. The idle loops halt through [halt], which
+// adds up how long the CPU slept; the rest of the time it was busy. The interrupt that ends a
+// halt is handled before the hlt returns, so its handler counts as idle, a small overestimate.
+
+/// How often [busy_percent] takes a new measurement.
+const SAMPLE_PERIOD: Duration = Duration::from_secs(1);
+
+static HALTED_NS: AtomicU64 = AtomicU64::new(0);
+
+/// (uptime, halted time) at the last sample, in nanoseconds, and the busy percentage since the
+/// one before.
+static LAST_SAMPLE: Mutex<(u64, u64, u32)> = Mutex::new((0, 0, 0));
+
+/// Enables interrupts and halts until the next one, counting the time as idle. Call with
+/// interrupts disabled, after checking there is no work: enabling them and halting happen
+/// atomically, so an interrupt arriving after the check still wakes the CPU.
+pub fn halt() {
+    let start = time::uptime_ns();
+    interrupts::enable_and_hlt();
+    HALTED_NS.fetch_add(time::uptime_ns() - start, Ordering::Relaxed);
+}
+
+/// Total time the boot CPU spent halted in its idle loop.
+pub fn halted() -> Duration {
+    Duration::from_nanos(HALTED_NS.load(Ordering::Relaxed))
+}
+
+/// Share of the last [SAMPLE_PERIOD] the boot CPU was busy, in percent; 0 until the first period
+/// is over. The rest is headroom the game loop could still use.
+pub fn busy_percent() -> u32 {
+    let mut last = LAST_SAMPLE.lock();
+    let (last_uptime, last_halted, percent) = *last;
+    let uptime = time::uptime_ns();
+    let elapsed = uptime - last_uptime;
+    if elapsed < SAMPLE_PERIOD.as_nanos() as u64 {
+        return percent;
+    }
+    let halted = HALTED_NS.load(Ordering::Relaxed);
+    let busy = elapsed.saturating_sub(halted - last_halted);
+    let percent = (busy * 100 / elapsed) as u32;
+    *last = (uptime, halted, percent);
+    percent
+}
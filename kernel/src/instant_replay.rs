@@ -0,0 +1,122 @@
+use game::{Entity, Game, Kind, MAX_ENTITIES};
+
+// The instant replay after a point: the field as it was for the last [HISTORY] ticks is kept in a
+// ring, and when a point is scored the game holds still while they are shown again in slow
+// motion, before the serve goes. Only the positions are kept, for drawing; the game itself is not
+// wound back, so the replay changes nothing in it, and a recording (replay.rs) plays out the same.
+// A network game is never held, as the other side would not be.
+
+/// Ticks kept, a second at the usual tick rate.
+const HISTORY: usize = 30;
+/// Ticks each kept one is shown for: the replay runs at half speed.
+const SLOWDOWN: usize = 2;
+
+/// The field at one tick.
+#[derive(Clone, Copy)]
+struct Frame {
+    entities: [Entity; MAX_ENTITIES],
+    count: usize,
+}
+
+impl Frame {
+    const EMPTY: Self = Self {
+        entities: [Entity { kind: Kind::Ball, x: 0.0, y: 0.0, dx: 0.0, dy: 0.0, width: 0.0, height: 0.0 }; MAX_ENTITIES],
+        count: 0,
+    };
+}
+
+pub struct InstantReplay {
+    frames: [Frame; HISTORY],
+    /// Where the next tick goes in [Self::frames], and how many are kept.
+    next: usize,
+    len: usize,
+    /// Ticks into the replay being shown, if one is.
+    showing: Option<usize>,
+}
+
+impl InstantReplay {
+    pub const fn new() -> Self {
+        Self { frames: [Frame::EMPTY; HISTORY], next: 0, len: 0, showing: None }
+    }
+
+    /// Keeps the field as `game` has it now, over the oldest kept.
+    pub fn record(&mut self, game: &Game) {
+        let entities = game.entities();
+        let frame = &mut self.frames[self.next];
+        frame.entities[..entities.len()].copy_from_slice(entities);
+        frame.count = entities.len();
+        self.next = (self.next + 1) % HISTORY;
+        self.len = (self.len + 1).min(HISTORY);
+    }
+
+    /// Forgets what was kept, as after a serve or at the end of a game.
+    pub fn clear(&mut self) {
+        (self.next, self.len, self.showing) = (0, 0, None);
+    }
+
+    /// Starts showing what was kept, if anything was.
+    pub fn start(&mut self) {
+        if self.len > 0 {
+            self.showing = Some(0);
+        }
+    }
+
+    pub fn is_showing(&self) -> bool {
+        self.showing.is_some()
+    }
+
+    /// Moves the replay on by a tick. At its end what was kept is forgotten, so the next point's
+    /// replay starts from the serve at the earliest.
+    pub fn advance(&mut self) {
+        let Some(ticks) = self.showing else { return };
+        if ticks + 1 >= self.len * SLOWDOWN {
+            self.clear();
+        } else {
+            self.showing = Some(ticks + 1);
+        }
+    }
+
+    /// The field to draw while the replay is showing.
+    pub fn frame(&self) -> Option<&[Entity]> {
+        let ticks = self.showing?;
+        let oldest = (self.next + HISTORY - self.len) % HISTORY;
+        let frame = &self.frames[(oldest + ticks / SLOWDOWN) % HISTORY];
+        Some(&frame.entities[..frame.count])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use game::GameMode;
+    use super::*;
+
+    #[test_case]
+    fn instant_replay_shows_the_last_ticks_slowly() {
+        let mut game = Game::new(640, 480);
+        game.start(GameMode::TwoPlayer, || 0);
+        let mut replay = InstantReplay::new();
+        replay.start();
+        assert!(!replay.is_showing());
+        for x in 0..40 {
+            game.ball_mut().x = x as f32;
+            replay.record(&game);
+        }
+        replay.start();
+        // The oldest kept first, each for two ticks
+        let ball_x = |replay: &InstantReplay| replay.frame().unwrap()[0].x;
+        assert_eq!(ball_x(&replay), 10.0);
+        replay.advance();
+        assert_eq!(ball_x(&replay), 10.0);
+        replay.advance();
+        assert_eq!(ball_x(&replay), 11.0);
+        for _ in 2..59 {
+            replay.advance();
+        }
+        assert_eq!(ball_x(&replay), 39.0);
+        replay.advance();
+        assert!(replay.frame().is_none());
+        // Forgotten once shown
+        replay.start();
+        assert!(!replay.is_showing());
+    }
+}
@@ -1,15 +1,19 @@
 use core::fmt::Write;
-use core::ptr::NonNull;
+use core::ops::RangeInclusive;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
 use crate::serial;
 use lazy_static::lazy_static;
 use spin::Mutex;
 use x86_64::{PhysAddr, VirtAddr};
 use crate::HandlerTable;
-use acpi::{AcpiHandler, AcpiTables, PhysicalMapping};
-use pc_keyboard::{layouts, HandleControl, Keyboard, ScancodeSet1};
+use crate::{acpi_tables, audio, config, cpu, crash, deferred, eventlog, executor, ioapic, keyboard, link, machine_check, memory, page_fault, pci, percpu, pit, power, process, scheduler, smp, timers, uart, watchdog, xhci};
+use crate::page_fault::PageFault;
+use crate::mouse::Mouse;
+use crate::bridge;
+use crate::serial_input::SerialDecoder;
 use x86_64::registers::control::Cr2;
+use x86_64::registers::model_specific::Msr;
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
-use x86_64::structures::paging::{FrameAllocator, Mapper, PhysFrame, Size4KiB};
 use x86_64::instructions::port::Port;
 // This code is largely Copyright (c) 2019 Philipp Oppermann.
 // Gabriel Ferrer added:
@@ -111,51 +115,48 @@ pub enum APICOffset {
     R0x3F0 = 0x3F0,   // RESERVED = 0x3F0
 }
 
-pub struct AcpiHandlerImpl {
-    physical_memory_offset: VirtAddr,
-}
+// In x2APIC mode the same registers are MSRs, one per 16 bytes of the MMIO page from 0x800, and
+// the MMIO page no longer responds. The ICR is then one 64-bit register with the destination in
+// its upper half. Every CPU has to be switched over itself, before it touches its local APIC.
 
-impl AcpiHandlerImpl {
-    pub fn new(physical_memory_offset: VirtAddr) -> Self {
-        Self {
-            physical_memory_offset,
-        }
-    }
-}
+const IA32_APIC_BASE: u32 = 0x1B;
+const APIC_BASE_X2APIC: u64 = 1 << 10;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+const X2APIC_MSR_BASE: u32 = 0x800;
 
-unsafe impl Send for AcpiHandlerImpl {}
-unsafe impl Sync for AcpiHandlerImpl {}
+/// Set once the boot processor has switched to x2APIC mode; the others follow it.
+static X2APIC: AtomicBool = AtomicBool::new(false);
 
-impl Clone for AcpiHandlerImpl {
-    fn clone(&self) -> Self {
-        Self {
-            physical_memory_offset: self.physical_memory_offset,
-        }
-    }
+/// Whether the local APICs are reached through MSRs rather than the MMIO page.
+pub fn x2apic_enabled() -> bool {
+    X2APIC.load(Ordering::Relaxed)
 }
 
-impl AcpiHandler for AcpiHandlerImpl {
-    unsafe fn map_physical_region<T>(
-        &self,
-        physical_address: usize,
-        size: usize,
-    ) -> PhysicalMapping<Self, T> {
-        let phys_addr = PhysAddr::new(physical_address as u64);
-        let virt_addr = self.physical_memory_offset + phys_addr.as_u64();
+/// Puts the calling CPU's local APIC in x2APIC mode.
+fn enable_x2apic() {
+    let mut apic_base = Msr::new(IA32_APIC_BASE);
+    unsafe { apic_base.write(apic_base.read() | APIC_BASE_ENABLE | APIC_BASE_X2APIC) };
+}
 
-        unsafe {
-            PhysicalMapping::new(
-                physical_address,
-                NonNull::new(virt_addr.as_mut_ptr()).expect("Failed to get virtual address"),
-                size,
-                size,
-                self.clone(),
-            )
+/// Reads a local APIC register, through its MSR in x2APIC mode or else at `lapic_pointer`.
+unsafe fn lapic_read(lapic_pointer: *mut u32, register: APICOffset) -> u32 {
+    unsafe {
+        if x2apic_enabled() {
+            Msr::new(X2APIC_MSR_BASE + register as u32 / 16).read() as u32
+        } else {
+            lapic_pointer.offset(register as isize / 4).read_volatile()
         }
     }
+}
 
-    fn unmap_physical_region<T>(_region: &PhysicalMapping<Self, T>) {
-        // No unmapping necessary as we didn't create any new mappings
+/// Writes a local APIC register, through its MSR in x2APIC mode or else at `lapic_pointer`.
+unsafe fn lapic_write(lapic_pointer: *mut u32, register: APICOffset, value: u32) {
+    unsafe {
+        if x2apic_enabled() {
+            Msr::new(X2APIC_MSR_BASE + register as u32 / 16).write(value as u64);
+        } else {
+            lapic_pointer.offset(register as isize / 4).write_volatile(value);
+        }
     }
 }
 
@@ -164,114 +165,139 @@ lazy_static! {
         let mut idt = InterruptDescriptorTable::new();
 
         idt.breakpoint.set_handler_fn(breakpoint_handler);
-        idt.page_fault.set_handler_fn(page_fault_handler);
-        idt.double_fault.set_handler_fn(double_fault_handler);
+        crash::install(&mut idt);
+        unsafe {
+            idt.double_fault.set_handler_fn(double_fault_handler).set_stack_index(DOUBLE_FAULT_IST_INDEX);
+            idt.non_maskable_interrupt.set_handler_fn(nmi_handler).set_stack_index(NMI_IST_INDEX);
+            idt.machine_check.set_handler_fn(machine_check_handler).set_stack_index(MACHINE_CHECK_IST_INDEX);
+            idt.page_fault.set_handler_fn(page_fault_handler).set_stack_index(PAGE_FAULT_IST_INDEX);
+        }
 
         idt[InterruptIndex::Timer as u8].set_handler_fn(timer_interrupt_handler);
-        idt[InterruptIndex::Keyboard as u8].set_handler_fn(keyboard_interrupt_handler);
+        idt[InterruptIndex::Wakeup as u8].set_handler_fn(wakeup_interrupt_handler);
+        idt[InterruptIndex::Thermal as u8].set_handler_fn(thermal_interrupt_handler);
+        idt[InterruptIndex::LapicError as u8].set_handler_fn(lapic_error_interrupt_handler);
+        idt[InterruptIndex::Spurious as u8].set_handler_fn(spurious_interrupt_handler);
+        for &(vector, stub) in IRQ_STUBS {
+            idt[vector].set_handler_fn(stub);
+        }
 
         idt
     };
 
 }
 
-unsafe fn init_io_apic(
-    ioapic_address: usize,
-    mapper: &mut impl Mapper<Size4KiB>,
-    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
-) {
-    let virt_addr = map_apic(ioapic_address as u64, mapper, frame_allocator);
-
-    let ioapic_pointer = virt_addr.as_mut_ptr::<u32>();
-
-    unsafe {
-        ioapic_pointer.offset(0).write_volatile(0x12);
-        ioapic_pointer
-            .offset(4)
-            .write_volatile(InterruptIndex::Keyboard as u8 as u32);
-    }
-}
-
-unsafe fn init_local_apic(
-    local_apic_addr: usize,
-    mapper: &mut impl Mapper<Size4KiB>,
-    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
-) {
-    let virtual_address = map_apic(local_apic_addr as u64, mapper, frame_allocator);
+unsafe fn init_local_apic(local_apic_addr: usize) {
+    let virtual_address = memory::map_region(PhysAddr::new(local_apic_addr as u64), 4096, memory::MMIO);
 
     let lapic_pointer = virtual_address.as_mut_ptr::<u32>();
     LAPIC_ADDR.lock().address = lapic_pointer;
+    // The MMIO page stays mapped either way; `x2apic=off` keeps to it
+    if cpu::features().x2apic && config::flag("x2apic").unwrap_or(true) {
+        enable_x2apic();
+        X2APIC.store(true, Ordering::Relaxed);
+    }
+    writeln!(serial(), "LAPIC: {} mode", if x2apic_enabled() { "x2APIC" } else { "xAPIC (MMIO)" }).unwrap();
     unsafe {
         init_timer(lapic_pointer);
         init_keyboard(lapic_pointer);
+        init_error_vectors(lapic_pointer);
     }
     writeln!(serial(), "init LAPIC_ADDR {:?}", LAPIC_ADDR.lock()).unwrap();
 }
 
+/// Timer interrupts per second until [set_tick_hz] is called.
+pub const DEFAULT_TICK_HZ: u32 = 30;
+
+// Calibration window measured against the PIT
+const CALIBRATION_US: u64 = 10_000;
+
+/// LAPIC timer counts per second in divide-by-16 mode, measured at boot.
+static APIC_TIMER_FREQUENCY: AtomicU32 = AtomicU32::new(0);
+static TICK_HZ: AtomicU32 = AtomicU32::new(DEFAULT_TICK_HZ);
+
 unsafe fn init_timer(lapic_pointer: *mut u32) {
     unsafe {
-        let svr = lapic_pointer.offset(APICOffset::Svr as isize / 4);
-        svr.write_volatile(svr.read_volatile() | 0x100); // Set bit 8
+        // Set bit 8 (APIC enable) and the spurious vector
+        let svr = lapic_read(lapic_pointer, APICOffset::Svr);
+        lapic_write(lapic_pointer, APICOffset::Svr, (svr & !0xFF) | 0x100 | InterruptIndex::Spurious as u32);
 
-        let lvt_lint1 = lapic_pointer.offset(APICOffset::LvtT as isize / 4);
-        lvt_lint1.write_volatile(0x20 | (1 << 17)); // Vector 0x20, periodic mode
+        lapic_write(lapic_pointer, APICOffset::Tdcr, 0x3); // Divide by 16 mode
 
-        let tdcr = lapic_pointer.offset(APICOffset::Tdcr as isize / 4);
-        tdcr.write_volatile(0x3); // Divide by 16 mode
+        let frequency = calibrate_timer(lapic_pointer);
+        APIC_TIMER_FREQUENCY.store(frequency, Ordering::Relaxed);
+        writeln!(serial(), "LAPIC timer calibrated: {} Hz (divide by 16)", frequency).unwrap();
 
-        let ticr = lapic_pointer.offset(APICOffset::Ticr as isize / 4);
-        ticr.write_volatile(0x0400_0000); // An arbitrary value for the initial value of the timer
+        lapic_write(lapic_pointer, APICOffset::LvtT, 0x20 | (1 << 17)); // Vector 0x20, periodic mode
     }
+    set_tick_hz(DEFAULT_TICK_HZ);
 }
 
-unsafe fn init_keyboard(lapic_pointer: *mut u32) {
+/// Counts how far the LAPIC timer runs down during a known PIT interval.
+unsafe fn calibrate_timer(lapic_pointer: *mut u32) -> u32 {
     unsafe {
-        let keyboard_register = lapic_pointer.offset(APICOffset::LvtLint1 as isize / 4);
-        keyboard_register.write_volatile(InterruptIndex::Keyboard as u8 as u32);
+        lapic_write(lapic_pointer, APICOffset::LvtT, 1 << 16); // Masked, one-shot
+
+        lapic_write(lapic_pointer, APICOffset::Ticr, u32::MAX);
+        pit::wait_us(CALIBRATION_US);
+        let elapsed = u32::MAX - lapic_read(lapic_pointer, APICOffset::Tccr);
+        lapic_write(lapic_pointer, APICOffset::Ticr, 0);
+
+        (elapsed as u64 * 1_000_000 / CALIBRATION_US) as u32
     }
 }
 
-fn map_apic(
-    physical_address: u64,
-    mapper: &mut impl Mapper<Size4KiB>,
-    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
-) -> VirtAddr {
-    use x86_64::structures::paging::Page;
-    use x86_64::structures::paging::PageTableFlags as Flags;
+/// Programs the periodic LAPIC timer to fire the given number of times per second.
+pub fn set_tick_hz(hz: u32) {
+    let frequency = APIC_TIMER_FREQUENCY.load(Ordering::Relaxed);
+    let lapic_pointer = x86_64::instructions::interrupts::without_interrupts(|| LAPIC_ADDR.lock().address);
+    if lapic_pointer.is_null() || frequency == 0 {
+        return;
+    }
 
-    let physical_address = PhysAddr::new(physical_address);
-    let page = Page::containing_address(VirtAddr::new(physical_address.as_u64()));
-    let frame = PhysFrame::containing_address(physical_address);
+    let hz = hz.max(1);
+    unsafe { lapic_write(lapic_pointer, APICOffset::Ticr, (frequency / hz).max(1)) };
+    TICK_HZ.store(hz, Ordering::Relaxed);
+}
 
-    let flags = Flags::PRESENT | Flags::WRITABLE | Flags::NO_CACHE;
+/// Timer interrupts per second, as last set by [set_tick_hz].
+pub fn tick_hz() -> u32 {
+    TICK_HZ.load(Ordering::Relaxed)
+}
 
+/// Unmasks the LAPIC error and thermal interrupts so they are reported instead of ignored.
+unsafe fn init_error_vectors(lapic_pointer: *mut u32) {
     unsafe {
-        mapper
-            .map_to(page, frame, flags, frame_allocator)
-            .expect("APIC mapping failed")
-            .flush();
-    }
+        lapic_write(lapic_pointer, APICOffset::LvtE, InterruptIndex::LapicError as u32);
 
-    page.start_address()
-}
+        // The thermal LVT entry only exists when the version register reports 5 or more entries
+        let version = lapic_read(lapic_pointer, APICOffset::Vr);
+        if (version >> 16) & 0xFF >= 5 {
+            lapic_write(lapic_pointer, APICOffset::LvtTsr, InterruptIndex::Thermal as u32);
+        }
 
-pub fn init_apic(rsdp: usize, offset: u64, mapper: &mut impl Mapper<Size4KiB>, frame_allocator: &mut impl FrameAllocator<Size4KiB>) -> *mut u32 {
-    let handler = AcpiHandlerImpl::new(VirtAddr::new(offset));
-    let acpi_tables = unsafe { AcpiTables::from_rsdp(handler, rsdp).expect("Failed to parse ACPI tables") };
-    let platform_info = acpi_tables.platform_info().expect("Failed to get platform info");
+        // Clear errors latched before the vector was set up
+        lapic_write(lapic_pointer, APICOffset::Esr, 0);
+    }
+}
 
-    match platform_info.interrupt_model {
-        acpi::InterruptModel::Apic(apic) => {
-            let io_apic_address = apic.io_apics[0].address;
-            unsafe { init_io_apic(io_apic_address as usize, mapper, frame_allocator); }
+unsafe fn init_keyboard(lapic_pointer: *mut u32) {
+    unsafe { lapic_write(lapic_pointer, APICOffset::LvtLint1, InterruptIndex::Keyboard as u8 as u32) };
+}
 
-            let local_apic_address = apic.local_apic_address;
-            unsafe { init_local_apic(local_apic_address as usize, mapper, frame_allocator); }
-        },
-        _ => {
-            // handler other interrupt models, if necessary
-        }
+/// Sets up the IOAPICs and the local APIC from the MADT, which [acpi_tables::init] must have read.
+pub fn init_apic() -> *mut u32 {
+    let madt = acpi_tables::madt().expect("No MADT, the machine has no APIC");
+    power::init();
+    pci::init();
+
+    ioapic::init(&madt.io_apics, &madt.overrides);
+    let cpu = madt.boot_processor().map_or(0, |processor| processor.local_apic_id as u8);
+    smp::set_processors(madt.application_processors().map(|ap| ap.local_apic_id));
+    for vector in [InterruptIndex::Keyboard, InterruptIndex::Link, InterruptIndex::Serial, InterruptIndex::Mouse] {
+        ioapic::route_isa_irq(vector as u8 - ISA_VECTOR_BASE, vector as u8, cpu);
     }
+    unsafe { init_local_apic(madt.local_apic_address as usize); }
 
     disable_pic();
 
@@ -280,6 +306,48 @@ pub fn init_apic(rsdp: usize, offset: u64, mapper: &mut impl Mapper<Size4KiB>, f
     LAPIC_ADDR.lock().address
 }
 
+const ICR_DELIVERY_PENDING: u32 = 1 << 12;
+const ICR_ALL_EXCLUDING_SELF: u32 = 0b11 << 18;
+
+/// Writes an interrupt command to the local APIC of `apic_id` and waits until it is sent.
+pub(crate) fn send_ipi(apic_id: u32, command: u32) {
+    if x2apic_enabled() {
+        // One write sends it; there is no delivery status to wait on
+        let icr = X2APIC_MSR_BASE + APICOffset::Icr1 as u32 / 16;
+        unsafe { Msr::new(icr).write((apic_id as u64) << 32 | command as u64) };
+        return;
+    }
+    let lapic_pointer = x86_64::instructions::interrupts::without_interrupts(|| LAPIC_ADDR.lock().address);
+    unsafe {
+        lapic_pointer.offset(APICOffset::Icr2 as isize / 4).write_volatile(apic_id << 24);
+        let icr = lapic_pointer.offset(APICOffset::Icr1 as isize / 4);
+        icr.write_volatile(command);
+        while icr.read_volatile() & ICR_DELIVERY_PENDING != 0 {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// Wakes every other CPU from hlt.
+pub(crate) fn wake_others() {
+    send_ipi(0, ICR_ALL_EXCLUDING_SELF | InterruptIndex::Wakeup as u32);
+}
+
+/// Interrupt setup on an application processor: loads the shared IDT and enables its local APIC,
+/// which sits at the same address as the boot processor's.
+pub(crate) fn init_ap() {
+    IDT.load();
+    if x2apic_enabled() {
+        enable_x2apic();
+    }
+    let lapic_pointer = LAPIC_ADDR.lock().address;
+    unsafe {
+        let svr = lapic_read(lapic_pointer, APICOffset::Svr);
+        lapic_write(lapic_pointer, APICOffset::Svr, (svr & !0xFF) | 0x100 | InterruptIndex::Spurious as u32);
+        lapic_write(lapic_pointer, APICOffset::Tpr, 0);
+    }
+}
+
 fn disable_pic() {
     // Disable any unneeded PIC features, such as timer or keyboard to prevent it from firing interrupts
 
@@ -291,12 +359,18 @@ fn disable_pic() {
 
 fn end_interrupt() {
     let binding = LAPIC_ADDR.lock();
-    unsafe { binding.address.offset(APICOffset::Eoi as isize / 4).write_volatile(0); }
+    unsafe { lapic_write(binding.address, APICOffset::Eoi, 0); }
 }
 
 /// Initializes the interrupt table with the given interrupt handlers.
 pub fn init_idt(handlers: HandlerTable, lapic_pointer: *mut u32) {
     LAPIC_ADDR.lock().address = lapic_pointer;
+    set_tick_hz(handlers.tick_hz);
+    register_irq(InterruptIndex::Keyboard as u8, keyboard_irq);
+    register_irq(InterruptIndex::Mouse as u8, mouse_irq);
+    register_irq(InterruptIndex::Serial as u8, serial_irq);
+    register_irq(InterruptIndex::Link as u8, link::handle_interrupt);
+    uart::enable_interrupts();
     writeln!(serial(), "initialize IDT with LAPIC_ADDR {:?}", LAPIC_ADDR.lock()).unwrap();
     *(HANDLERS.lock()) = Some(handlers);
 
@@ -304,62 +378,492 @@ pub fn init_idt(handlers: HandlerTable, lapic_pointer: *mut u32) {
     x86_64::instructions::interrupts::enable();
 }
 
+/// Times each vector has fired since boot.
+static COUNTS: [AtomicU64; 256] = [const { AtomicU64::new(0) }; 256];
+/// Timer interrupts that found the previous tick still waiting to run.
+static MISSED_TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Counts `vector`. Every handler calls this first, so it also undoes whatever a program did to
+/// GS (see [percpu::restore]) before the handler reaches for this CPU's block.
+pub(crate) fn count(vector: u8) {
+    if process::is_running() {
+        percpu::restore();
+    }
+    COUNTS[vector as usize].fetch_add(1, Ordering::Relaxed);
+    if let Some(cpu) = percpu::try_current() {
+        cpu.count_interrupt();
+    }
+}
+
+/// Number of times the given vector has fired since boot.
+pub fn interrupt_count(vector: u8) -> u64 {
+    COUNTS[vector as usize].load(Ordering::Relaxed)
+}
+
+/// Number of timer ticks dropped because the CPU loop had not yet run the previous one.
+pub fn missed_ticks() -> u64 {
+    MISSED_TICKS.load(Ordering::Relaxed)
+}
+
+/// Vector, name and count of every vector that has fired at least once.
+pub fn interrupt_stats() -> impl Iterator<Item = (u8, &'static str, u64)> {
+    (0..=255u8)
+        .map(|vector| (vector, vector_name(vector), interrupt_count(vector)))
+        .filter(|&(_, _, count)| count > 0)
+}
+
+fn vector_name(vector: u8) -> &'static str {
+    const NMI: u8 = 2;
+    const BREAKPOINT: u8 = 3;
+    const DOUBLE_FAULT: u8 = 8;
+    const PAGE_FAULT: u8 = 14;
+    const MACHINE_CHECK: u8 = 18;
+    const TIMER: u8 = InterruptIndex::Timer as u8;
+    const KEYBOARD: u8 = InterruptIndex::Keyboard as u8;
+    const SERIAL: u8 = InterruptIndex::Serial as u8;
+    const LINK: u8 = InterruptIndex::Link as u8;
+    const MOUSE: u8 = InterruptIndex::Mouse as u8;
+    const WAKEUP: u8 = InterruptIndex::Wakeup as u8;
+    const THERMAL: u8 = InterruptIndex::Thermal as u8;
+    const LAPIC_ERROR: u8 = InterruptIndex::LapicError as u8;
+    const SPURIOUS: u8 = InterruptIndex::Spurious as u8;
+
+    match vector {
+        NMI => "NMI",
+        BREAKPOINT => "breakpoint",
+        DOUBLE_FAULT => "double fault",
+        PAGE_FAULT => "page fault",
+        TIMER => "timer",
+        KEYBOARD => "keyboard",
+        SERIAL => "serial",
+        LINK => "serial link",
+        MOUSE => "mouse",
+        WAKEUP => "wakeup IPI",
+        THERMAL => "thermal",
+        LAPIC_ERROR => "LAPIC error",
+        SPURIOUS => "spurious",
+        MACHINE_CHECK => "machine check",
+        0..32 => crash::exception_name(vector),
+        _ => "other",
+    }
+}
+
 extern "x86-interrupt" fn breakpoint_handler(
     stack_frame: InterruptStackFrame)
 {
+    count(3);
     writeln!(serial(), "EXCEPTION: BREAKPOINT\n{:#?}", stack_frame).unwrap();
 }
 
+/// Interrupt stack table entry the double fault handler runs on, so it still has a stack after
+/// the kernel stack overflows. The TSS must provide a stack at this index, and at the others in
+/// [IST_INDICES].
+pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+/// An NMI can arrive at any instruction, including in the middle of a stack switch.
+pub const NMI_IST_INDEX: u16 = 1;
+pub const MACHINE_CHECK_IST_INDEX: u16 = 2;
+/// Faults on the guard page below the kernel stack need a stack that works.
+pub const PAGE_FAULT_IST_INDEX: u16 = 3;
+pub const IST_INDICES: [u16; 4] = [DOUBLE_FAULT_IST_INDEX, NMI_IST_INDEX, MACHINE_CHECK_IST_INDEX, PAGE_FAULT_IST_INDEX];
+/// Size of each of the interrupt stacks.
+pub const IST_STACK_SIZE: usize = 4096 * 5;
+
+/// Start of the unmapped page below the kernel stack; 0 if none is registered.
+static STACK_GUARD: AtomicU64 = AtomicU64::new(0);
+
+/// Registers the guard page below the kernel stack, so faults on it are reported as a stack
+/// overflow rather than a generic fault.
+pub fn set_stack_guard(page: VirtAddr) {
+    STACK_GUARD.store(page.as_u64(), Ordering::Relaxed);
+}
+
+fn stack_overflow_at() -> Option<VirtAddr> {
+    let guard = STACK_GUARD.load(Ordering::Relaxed);
+    let address = Cr2::read().ok()?;
+    (guard != 0 && (guard..guard + 4096).contains(&address.as_u64())).then_some(address)
+}
+
 extern "x86-interrupt" fn page_fault_handler(stack_frame: InterruptStackFrame, error_code: PageFaultErrorCode) {
-    panic!("EXCEPTION: PAGE FAULT access address: {:?}\n ErrorCode: {:?}\n{:#?}", Cr2::read(), error_code, stack_frame);
+    count(14);
+    if let Some(address) = stack_overflow_at() {
+        panic!("kernel stack overflow: access to guard page at {:?}\n{:#?}", address, stack_frame);
+    }
+
+    let fault = PageFault {
+        address: VirtAddr::new_truncate(Cr2::read_raw()),
+        instruction_pointer: stack_frame.instruction_pointer,
+        error_code,
+    };
+    if page_fault::resolve(&fault) {
+        return;
+    }
+    page_fault::report(&fault);
+    if fault.is_user_mode() {
+        // Only the program dies; this does not return to it
+        process::exit(-1);
+    }
+    panic!("EXCEPTION: {}\n{:#?}", fault, stack_frame);
+}
+
+extern "x86-interrupt" fn nmi_handler(stack_frame: InterruptStackFrame) {
+    if let Some(reason) = machine_check::nmi_reason() {
+        count(2);
+        panic!("NMI: hardware error: {}\n{:#?}", reason, stack_frame);
+    }
+    if count_and_sample(2) {
+        writeln!(serial(), "NMI at {:?}", stack_frame.instruction_pointer).unwrap();
+    }
+}
+
+extern "x86-interrupt" fn machine_check_handler(stack_frame: InterruptStackFrame) -> ! {
+    count(18);
+    // The banks in full go to serial; the panic message has the one that matters
+    match machine_check::report() {
+        Some(bank) => panic!("EXCEPTION: MACHINE CHECK: {}\n{:#?}", bank, stack_frame),
+        None => panic!("EXCEPTION: MACHINE CHECK\n{:#?}", stack_frame),
+    }
 }
 
 extern "x86-interrupt" fn double_fault_handler(
     stack_frame: InterruptStackFrame, _error_code: u64) -> !
 {
+    count(8);
+    // The page fault handler has a stack of its own, so overflows are normally caught there;
+    // this is for a fault on the guard page while it is already running
+    if let Some(address) = stack_overflow_at() {
+        panic!("kernel stack overflow: access to guard page at {:?}\n{:#?}", address, stack_frame);
+    }
     panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
 }
 
 const PIC_1_OFFSET: u8 = 0x20;
+/// Vector of legacy ISA IRQ 0. The LAPIC prioritizes by vector, so these sit above the timer.
+const ISA_VECTOR_BASE: u8 = 0x40;
+
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
 enum InterruptIndex {
     Timer = PIC_1_OFFSET,
-    Keyboard,
+    Keyboard = ISA_VECTOR_BASE + 1,
+    Link = ISA_VECTOR_BASE + 3,
+    Serial = ISA_VECTOR_BASE + 4,
+    Mouse = ISA_VECTOR_BASE + 12,
+    Wakeup = 0xF0,
+    Thermal = 0xFD,
+    LapicError = 0xFE,
+    Spurious = 0xFF,
 }
 
-extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    
-    let h = &*HANDLERS.lock();
-    if let Some(handler) = h {
-        handler.handle_timer();
+/// Counts the vector and reports whether to log this occurrence. Only the 1st, 2nd, 4th, 8th, ...
+/// are logged, so an interrupt storm cannot flood the serial port.
+fn count_and_sample(vector: u8) -> bool {
+    count(vector);
+    eventlog::record(eventlog::Kind::Interrupt, format_args!("{:#04x} {}", vector, vector_name(vector)));
+    interrupt_count(vector).is_power_of_two()
+}
+
+extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    if count_and_sample(InterruptIndex::Spurious as u8) {
+        writeln!(serial(), "spurious interrupt (#{})", interrupt_count(InterruptIndex::Spurious as u8)).unwrap();
     }
+    // No end of interrupt: a spurious interrupt is never marked in service
+}
 
+extern "x86-interrupt" fn lapic_error_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    let lapic_pointer = LAPIC_ADDR.lock().address;
+    // The ESR latches errors on write, so write before reading
+    let esr = unsafe {
+        lapic_write(lapic_pointer, APICOffset::Esr, 0);
+        lapic_read(lapic_pointer, APICOffset::Esr)
+    };
+    if count_and_sample(InterruptIndex::LapicError as u8) {
+        writeln!(serial(), "LAPIC error {:#04x} (#{})", esr, interrupt_count(InterruptIndex::LapicError as u8)).unwrap();
+    }
     end_interrupt();
 }
 
-extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
+/// Sent to the application processors when work is queued for them; all it has to do is end their hlt.
+extern "x86-interrupt" fn wakeup_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    count(InterruptIndex::Wakeup as u8);
+    end_interrupt();
+}
+
+extern "x86-interrupt" fn thermal_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    if count_and_sample(InterruptIndex::Thermal as u8) {
+        writeln!(serial(), "thermal interrupt (#{})", interrupt_count(InterruptIndex::Thermal as u8)).unwrap();
+    }
+    end_interrupt();
+}
 
-    lazy_static! {
-        static ref KEYBOARD: Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>> =
-            Mutex::new(Keyboard::new(ScancodeSet1::new(), layouts::Us104Key,
-                HandleControl::Ignore)
-            );
+/// Set by the timer interrupt while a deferred tick is waiting to run.
+static TICK_DUE: AtomicBool = AtomicBool::new(false);
+
+/// Runs the timer handler from the CPU loop, outside interrupt context.
+fn run_timer_handler() {
+    TICK_DUE.store(false, Ordering::Release);
+    // Copy the handler out so HANDLERS is not held (and interrupts not blocked) while it runs
+    let timer = x86_64::instructions::interrupts::without_interrupts(|| HANDLERS.lock().as_ref().and_then(|h| h.timer));
+    if let Some(timer) = timer {
+        timer();
+    }
+}
+
+extern "x86-interrupt" fn timer_interrupt_handler(stack_frame: InterruptStackFrame) {
+    count(InterruptIndex::Timer as u8);
+    watchdog::check(&stack_frame);
+    if let Some(handler) = &*HANDLERS.lock() {
+        xhci::poll(|state| handler.handle_gamepad(state));
+    }
+    audio::poll();
+    // Coalesce: if the previous tick has not run yet, the frame is simply late, not doubled
+    if !TICK_DUE.swap(true, Ordering::AcqRel) {
+        deferred::defer(run_timer_handler);
+    } else {
+        MISSED_TICKS.fetch_add(1, Ordering::Relaxed);
     }
+    executor::tick();
+    // Software timers run with HANDLERS released, and with interrupts still off rather than under
+    // with_priority: they may defer work, and the input handlers push to the same queue
+    timers::run_due();
 
-    let mut keyboard = KEYBOARD.lock();
+    end_interrupt();
+    // Time slice over
+    scheduler::preempt();
+}
+
+fn keyboard_irq() {
     let mut port = Port::new(0x60);
 
     let scancode: u8 = unsafe { port.read() };
-    if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-        if let Some(key) = keyboard.process_keyevent(key_event) {
-            let h = &*HANDLERS.lock();
-            if let Some(handler) = h {
-                handler.handle_keyboard(key);
+    if keyboard::command_reply(scancode) {
+        return;
+    }
+    if let Some(key) = keyboard::decode(scancode) {
+        executor::key_pressed(key);
+        let h = &*HANDLERS.lock();
+        if let Some(handler) = h {
+            handler.handle_keyboard(key);
+        }
+    }
+}
+
+fn mouse_irq() {
+    static MOUSE: Mutex<Mouse> = Mutex::new(Mouse::new());
+
+    let mut port = Port::new(0x60);
+
+    let data: u8 = unsafe { port.read() };
+    if let Some(event) = MOUSE.lock().add_byte(data) {
+        let h = &*HANDLERS.lock();
+        if let Some(handler) = h {
+            handler.handle_mouse(event);
+        }
+    }
+}
+
+fn serial_irq() {
+    uart::handle_interrupt(serial_received);
+}
+
+/// Decodes a byte received on the serial console, from COM1 or a virtio-console, into keys for
+/// the serial input handler, or into a controller bridge packet for the bridge handler. Called
+/// with interrupts disabled.
+pub(crate) fn serial_received(byte: u8) {
+    static DECODER: Mutex<SerialDecoder> = Mutex::new(SerialDecoder::new());
+    static BRIDGE: Mutex<bridge::Decoder> = Mutex::new(bridge::Decoder::new());
+
+    // Packets are taken out before the shell, which reads the queued bytes, or the keys see them
+    if !uart::is_raw() {
+        let mut bridge = BRIDGE.lock();
+        if bridge.takes(byte) {
+            if let Some(packet) = bridge.add_byte(byte) {
+                let h = &*HANDLERS.lock();
+                if let Some(handler) = h {
+                    handler.handle_bridge(packet);
+                }
             }
+            return;
         }
     }
+    uart::received(byte);
+    if uart::is_raw() {
+        return;
+    }
+    if let Some(key) = DECODER.lock().add_byte(byte) {
+        let h = &*HANDLERS.lock();
+        if let Some(handler) = h {
+            handler.handle_serial(key);
+        }
+    }
+}
 
+/// Vectors that drivers can claim with [register_irq]. Legacy IRQ n sits at vector 0x40 + n,
+/// in the [PriorityClass::Input] class; the timer's vector 0x20 is handled separately.
+pub const IRQ_VECTORS: RangeInclusive<u8> = 0x21..=0x4F;
+
+/// Vectors handed out by [allocate_irq], the [PriorityClass::Device] class.
+const DEVICE_VECTORS: RangeInclusive<u8> = 0x30..=0x3F;
+
+/// The handler registered for each vector, if any.
+type IrqHandlers = [Option<fn()>; 256];
+
+static IRQ_HANDLERS: Mutex<IrqHandlers> = Mutex::new([None; 256]);
+
+/// Routes interrupts on `vector` to `handler`. The handler runs in interrupt context; the end of
+/// interrupt is signaled after it returns. Returns false if the vector is outside [IRQ_VECTORS]
+/// or already claimed.
+pub fn register_irq(vector: u8, handler: fn()) -> bool {
+    if !IRQ_VECTORS.contains(&vector) {
+        return false;
+    }
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut handlers = IRQ_HANDLERS.lock();
+        let slot = &mut handlers[vector as usize];
+        if slot.is_some() {
+            return false;
+        }
+        *slot = Some(handler);
+        true
+    })
+}
+
+/// Claims a free vector above the legacy IRQs for `handler`, for devices using message-signaled
+/// interrupts. Returns None when all are taken.
+pub fn allocate_irq(handler: fn()) -> Option<u8> {
+    let mut vectors = DEVICE_VECTORS;
+    vectors.find(|&vector| register_irq(vector, handler))
+}
+
+/// Releases a vector claimed with [register_irq].
+pub fn unregister_irq(vector: u8) {
+    x86_64::instructions::interrupts::without_interrupts(|| IRQ_HANDLERS.lock()[vector as usize] = None);
+}
+
+fn dispatch_irq(vector: u8) {
+    count(vector);
+    eventlog::record(eventlog::Kind::Interrupt, format_args!("{:#04x} {}", vector, vector_name(vector)));
+    // Copied out so the handler may register or unregister vectors itself
+    let handler = IRQ_HANDLERS.lock()[vector as usize];
+    if let Some(handler) = handler {
+        handler();
+    }
     end_interrupt();
+}
+
+// One entry stub per vector in IRQ_VECTORS, since the CPU does not say which vector fired
+macro_rules! irq_stubs {
+    ($($vector:literal => $name:ident),* $(,)?) => {
+        $(
+            extern "x86-interrupt" fn $name(_stack_frame: InterruptStackFrame) {
+                dispatch_irq($vector);
+            }
+        )*
+
+        const IRQ_STUBS: &[(u8, extern "x86-interrupt" fn(InterruptStackFrame))] = &[$(($vector, $name)),*];
+    };
+}
+
+irq_stubs! {
+    0x21 => irq_0x21, 0x22 => irq_0x22, 0x23 => irq_0x23, 0x24 => irq_0x24,
+    0x25 => irq_0x25, 0x26 => irq_0x26, 0x27 => irq_0x27, 0x28 => irq_0x28,
+    0x29 => irq_0x29, 0x2A => irq_0x2a, 0x2B => irq_0x2b, 0x2C => irq_0x2c,
+    0x2D => irq_0x2d, 0x2E => irq_0x2e, 0x2F => irq_0x2f, 0x30 => irq_0x30,
+    0x31 => irq_0x31, 0x32 => irq_0x32, 0x33 => irq_0x33, 0x34 => irq_0x34,
+    0x35 => irq_0x35, 0x36 => irq_0x36, 0x37 => irq_0x37, 0x38 => irq_0x38,
+    0x39 => irq_0x39, 0x3A => irq_0x3a, 0x3B => irq_0x3b, 0x3C => irq_0x3c,
+    0x3D => irq_0x3d, 0x3E => irq_0x3e, 0x3F => irq_0x3f, 0x40 => irq_0x40,
+    0x41 => irq_0x41, 0x42 => irq_0x42, 0x43 => irq_0x43, 0x44 => irq_0x44,
+    0x45 => irq_0x45, 0x46 => irq_0x46, 0x47 => irq_0x47, 0x48 => irq_0x48,
+    0x49 => irq_0x49, 0x4A => irq_0x4a, 0x4B => irq_0x4b, 0x4C => irq_0x4c,
+    0x4D => irq_0x4d, 0x4E => irq_0x4e, 0x4F => irq_0x4f,
+}
 
-}
\ No newline at end of file
+/// Priority classes of the vectors in use. The LAPIC only delivers an interrupt whose class
+/// (vector >> 4) is above both the Task Priority Register and the class being serviced, so a
+/// handler running at a low class can let higher classes in. See [with_priority].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum PriorityClass {
+    /// The timer tick and software timers
+    Timer = 2,
+    /// Devices using vectors from [allocate_irq]
+    Device = 3,
+    /// Legacy IRQs: keyboard, mouse and serial input
+    Input = 4,
+    /// LAPIC error, thermal and spurious vectors
+    System = 15,
+}
+
+/// Runs `f` with interrupts enabled, but with the Task Priority Register holding off every
+/// class up to and including `class`. Long-running handlers use this so input is still serviced
+/// while they work. The handlers let in may run in the middle of `f`, so `f` must not push to a
+/// [crate::spsc::SpscQueue] that any of them pushes to, such as this CPU's deferred work
+/// ([deferred::defer]) or the input queues: each takes one producer at a time.
+pub fn with_priority<R>(class: PriorityClass, f: impl FnOnce() -> R) -> R {
+    use x86_64::instructions::interrupts;
+
+    let was_enabled = interrupts::are_enabled();
+    interrupts::disable();
+    let lapic_pointer = LAPIC_ADDR.lock().address;
+    if lapic_pointer.is_null() {
+        if was_enabled {
+            interrupts::enable();
+        }
+        return f();
+    }
+
+    let previous = unsafe { lapic_read(lapic_pointer, APICOffset::Tpr) };
+    unsafe { lapic_write(lapic_pointer, APICOffset::Tpr, ((class as u32) << 4).max(previous)) };
+    interrupts::enable();
+
+    let result = f();
+
+    interrupts::disable();
+    unsafe { lapic_write(lapic_pointer, APICOffset::Tpr, previous) };
+    if was_enabled {
+        interrupts::enable();
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use core::time::Duration;
+    use super::*;
+    use crate::time;
+
+    /// A device vector no driver claims in the library's test kernel.
+    const TEST_VECTOR: u8 = 0x3D;
+    const ICR_SELF: u32 = 0b01 << 18;
+
+    static TEST_IRQS: AtomicU32 = AtomicU32::new(0);
+
+    fn test_irq() {
+        TEST_IRQS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn tpr() -> u32 {
+        x86_64::instructions::interrupts::without_interrupts(|| unsafe { lapic_read(LAPIC_ADDR.lock().address, APICOffset::Tpr) })
+    }
+
+    // Goes through the local APIC however it is reached, MSRs in x2APIC mode or else MMIO: the
+    // TPR, a self IPI and the end of interrupt
+    #[test_case]
+    fn with_priority_holds_off_its_class_until_it_returns() {
+        assert!(register_irq(TEST_VECTOR, test_irq));
+        let before = tpr();
+        with_priority(PriorityClass::Device, || {
+            assert_eq!(tpr(), (PriorityClass::Device as u32) << 4);
+            send_ipi(0, ICR_SELF | TEST_VECTOR as u32);
+            time::poll_until(Duration::from_millis(10), || false);
+            assert_eq!(TEST_IRQS.load(Ordering::Relaxed), 0);
+        });
+        assert_eq!(tpr(), before);
+        assert!(time::poll_until(Duration::from_millis(10), || TEST_IRQS.load(Ordering::Relaxed) == 1));
+
+        // Had the end of interrupt been lost, the timer, a class below, would be held off for good
+        let ticks = interrupt_count(InterruptIndex::Timer as u8);
+        assert!(time::poll_until(Duration::from_millis(100), || interrupt_count(InterruptIndex::Timer as u8) > ticks));
+        unregister_irq(TEST_VECTOR);
+    }
+}
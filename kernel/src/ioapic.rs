@@ -0,0 +1,153 @@
+use core::fmt::Write;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::PhysAddr;
+use crate::acpi_tables::{InterruptOverride, IoApic};
+use crate::{memory, serial};
+
+// https://wiki.osdev.org/IOAPIC
+/// Offset 0x00, in u32s like [IOWIN].
+const IOREGSEL: usize = 0;
+const IOWIN: usize = 0x10 / 4;
+
+const IOAPICVER: u32 = 0x01;
+const IOREDTBL: u32 = 0x10;
+
+const ACTIVE_LOW: u32 = 1 << 13;
+const LEVEL_TRIGGERED: u32 = 1 << 15;
+const MASKED: u32 = 1 << 16;
+
+const MAX_IO_APICS: usize = 8;
+const ISA_IRQS: usize = 16;
+
+/// Electrical properties of an interrupt line, for [route_irq].
+/// The default (edge triggered, active high) is right for ISA interrupts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RouteFlags {
+    pub active_low: bool,
+    pub level_triggered: bool,
+    pub masked: bool,
+}
+
+#[derive(Clone, Copy)]
+struct Controller {
+    registers: usize,
+    gsi_base: u32,
+    entries: u32,
+}
+
+impl Controller {
+    fn read(&self, register: u32) -> u32 {
+        let base = self.registers as *mut u32;
+        unsafe {
+            base.add(IOREGSEL).write_volatile(register);
+            base.add(IOWIN).read_volatile()
+        }
+    }
+
+    fn write(&self, register: u32, value: u32) {
+        let base = self.registers as *mut u32;
+        unsafe {
+            base.add(IOREGSEL).write_volatile(register);
+            base.add(IOWIN).write_volatile(value);
+        }
+    }
+
+    fn handles(&self, gsi: u32) -> bool {
+        (self.gsi_base..self.gsi_base + self.entries).contains(&gsi)
+    }
+}
+
+/// Where an ISA IRQ is wired, from the MADT's interrupt source overrides.
+#[derive(Clone, Copy)]
+struct IsaRoute {
+    gsi: u32,
+    flags: RouteFlags,
+}
+
+static CONTROLLERS: Mutex<[Option<Controller>; MAX_IO_APICS]> = Mutex::new([None; MAX_IO_APICS]);
+
+static ISA_ROUTES: Mutex<[IsaRoute; ISA_IRQS]> = Mutex::new({
+    let mut routes = [IsaRoute { gsi: 0, flags: RouteFlags { active_low: false, level_triggered: false, masked: false } }; ISA_IRQS];
+    let mut irq = 0;
+    while irq < ISA_IRQS {
+        routes[irq].gsi = irq as u32;
+        irq += 1;
+    }
+    routes
+});
+
+/// Maps every IOAPIC listed in the MADT, masks all of their inputs and records the interrupt
+/// source overrides used by [route_isa_irq].
+pub(crate) fn init(
+    io_apics: &[IoApic],
+    overrides: &[InterruptOverride],
+) {
+    let mut controllers = CONTROLLERS.lock();
+    for (slot, io_apic) in controllers.iter_mut().zip(io_apics) {
+        let registers = memory::map_region(PhysAddr::new(io_apic.address as u64), 4096, memory::MMIO).as_u64() as usize;
+        let mut controller = Controller { registers, gsi_base: io_apic.gsi_base, entries: 0 };
+        controller.entries = ((controller.read(IOAPICVER) >> 16) & 0xFF) + 1;
+
+        for entry in 0..controller.entries {
+            controller.write(IOREDTBL + entry * 2, MASKED);
+        }
+        writeln!(serial(), "IOAPIC {}: GSIs {}..{}", io_apic.id, controller.gsi_base, controller.gsi_base + controller.entries).unwrap();
+        *slot = Some(controller);
+    }
+
+    let mut routes = ISA_ROUTES.lock();
+    for source in overrides {
+        let Some(route) = routes.get_mut(source.isa_irq as usize) else { continue };
+        route.gsi = source.gsi;
+        route.flags = source.flags;
+        writeln!(serial(), "ISA IRQ {} -> GSI {} ({:?})", source.isa_irq, source.gsi, source.flags).unwrap();
+    }
+}
+
+/// Routes global system interrupt `gsi` to `vector` on the CPU with local APIC ID `cpu`.
+/// Returns false if no IOAPIC handles the GSI.
+pub fn route_irq(gsi: u32, vector: u8, cpu: u8, flags: RouteFlags) -> bool {
+    let mut low = vector as u32;
+    if flags.active_low {
+        low |= ACTIVE_LOW;
+    }
+    if flags.level_triggered {
+        low |= LEVEL_TRIGGERED;
+    }
+    if flags.masked {
+        low |= MASKED;
+    }
+
+    without_interrupts(|| {
+        let controllers = CONTROLLERS.lock();
+        let Some(controller) = controllers.iter().flatten().find(|c| c.handles(gsi)) else {
+            return false;
+        };
+        let register = IOREDTBL + (gsi - controller.gsi_base) * 2;
+        // Mask while the destination changes, then write the low half to unmask
+        controller.write(register, MASKED);
+        controller.write(register + 1, (cpu as u32) << 24);
+        controller.write(register, low);
+        true
+    })
+}
+
+/// Routes a legacy ISA IRQ (keyboard 1, COM1 4, RTC 8, mouse 12, ...), following any interrupt
+/// source override in the MADT.
+pub fn route_isa_irq(irq: u8, vector: u8, cpu: u8) -> bool {
+    let Some(route) = ISA_ROUTES.lock().get(irq as usize).copied() else { return false };
+    route_irq(route.gsi, vector, cpu, route.flags)
+}
+
+/// Masks or unmasks a global system interrupt without changing its route.
+pub fn set_masked(gsi: u32, masked: bool) {
+    without_interrupts(|| {
+        let controllers = CONTROLLERS.lock();
+        if let Some(controller) = controllers.iter().flatten().find(|c| c.handles(gsi)) {
+            let register = IOREDTBL + (gsi - controller.gsi_base) * 2;
+            let low = controller.read(register);
+            controller.write(register, if masked { low | MASKED } else { low & !MASKED });
+        }
+    });
+}
@@ -0,0 +1,74 @@
+use alloc::format;
+use alloc::string::String;
+use core::fmt::{self, Write};
+use crate::sync::IrqSafeMutex;
+use crate::{eventlog, serial};
+
+// Assertions for logic errors the game can recover from. A failed [kassert!] does not panic: it
+// reports the condition and where it is on serial and in the event log, and the code after it
+// runs on. In debug builds the failure is also kept as pending, for the game to pause on, show,
+// and let the player choose to continue or to reset the game; see [pending] and [resolve]. Use
+// `assert!` where going on would do harm.
+
+/// A failed assertion.
+#[derive(Debug, Clone)]
+pub struct Failure {
+    pub condition: &'static str,
+    pub file: &'static str,
+    pub line: u32,
+    pub column: u32,
+    /// The message given after the condition, if any.
+    pub message: Option<String>,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "assertion failed: {} at {}:{}:{}", self.condition, self.file, self.line, self.column)?;
+        if let Some(message) = &self.message {
+            write!(f, ": {}", message)?;
+        }
+        Ok(())
+    }
+}
+
+/// The failure the game has not dealt with yet. Later ones are only reported until it has.
+static PENDING: IrqSafeMutex<Option<Failure>> = IrqSafeMutex::new(None);
+
+/// Checks a condition that should hold, without panicking when it does not:
+/// `kassert!(ball_y < height)`, or with a message, `kassert!(ball_y < height, "ball at {}", ball_y)`.
+#[macro_export]
+macro_rules! kassert {
+    ($condition:expr $(,)?) => {
+        if !$condition {
+            $crate::kassert::fail(core::stringify!($condition), core::file!(), core::line!(), core::column!(), None);
+        }
+    };
+    ($condition:expr, $($message:tt)+) => {
+        if !$condition {
+            $crate::kassert::fail(core::stringify!($condition), core::file!(), core::line!(), core::column!(), Some(core::format_args!($($message)+)));
+        }
+    };
+}
+
+/// Reports a failed [kassert!], and in debug builds leaves it pending.
+pub fn fail(condition: &'static str, file: &'static str, line: u32, column: u32, message: Option<fmt::Arguments>) {
+    let failure = Failure { condition, file, line, column, message: message.map(|message| format!("{}", message)) };
+    let _ = writeln!(serial(), "{}", failure);
+    eventlog::record(eventlog::Kind::Assert, format_args!("{} at {}:{}", condition, file, line));
+    if cfg!(debug_assertions) {
+        let mut pending = PENDING.lock();
+        if pending.is_none() {
+            *pending = Some(failure);
+        }
+    }
+}
+
+/// The failure waiting for the game to deal with, if any.
+pub fn pending() -> Option<Failure> {
+    PENDING.lock().clone()
+}
+
+/// Marks the pending failure dealt with; the next one to fail is kept again.
+pub fn resolve() {
+    *PENDING.lock() = None;
+}
@@ -0,0 +1,208 @@
+use lazy_static::lazy_static;
+use pc_keyboard::layouts::{AnyLayout, Azerty, De105Key, Dvorak104Key, Us104Key};
+use pc_keyboard::{DecodedKey, HandleControl, Keyboard, ScancodeSet1};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use crate::fixed::FixedVec;
+use crate::ps2;
+
+/// Keyboard layouts selectable for the scancode decoder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    Qwerty,
+    Azerty,
+    Qwertz,
+    Dvorak,
+}
+
+impl Layout {
+    pub const ALL: [Layout; 4] = [Layout::Qwerty, Layout::Azerty, Layout::Qwertz, Layout::Dvorak];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Layout::Qwerty => "QWERTY (US)",
+            Layout::Azerty => "AZERTY (FR)",
+            Layout::Qwertz => "QWERTZ (DE)",
+            Layout::Dvorak => "Dvorak",
+        }
+    }
+
+    fn decoder_layout(self) -> AnyLayout {
+        match self {
+            Layout::Qwerty => AnyLayout::Us104Key(Us104Key),
+            Layout::Azerty => AnyLayout::Azerty(Azerty),
+            Layout::Qwertz => AnyLayout::De105Key(De105Key),
+            Layout::Dvorak => AnyLayout::Dvorak104Key(Dvorak104Key),
+        }
+    }
+}
+
+lazy_static! {
+    static ref KEYBOARD: Mutex<Keyboard<AnyLayout, ScancodeSet1>> =
+        Mutex::new(new_keyboard(Layout::Qwerty));
+}
+
+fn new_keyboard(layout: Layout) -> Keyboard<AnyLayout, ScancodeSet1> {
+    Keyboard::new(ScancodeSet1::new(), layout.decoder_layout(), HandleControl::Ignore)
+}
+
+/// Switches the layout used to decode subsequent key presses.
+pub fn set_layout(layout: Layout) {
+    let keyboard = new_keyboard(layout);
+    // The keyboard interrupt takes the same lock
+    without_interrupts(|| *KEYBOARD.lock() = keyboard);
+}
+
+/// Feeds one scancode to the decoder. The decoder lock is released before returning, so key
+/// handlers are free to call [set_layout].
+pub(crate) fn decode(scancode: u8) -> Option<DecodedKey> {
+    let mut keyboard = KEYBOARD.lock();
+    match keyboard.add_byte(scancode) {
+        Ok(Some(key_event)) => keyboard.process_keyevent(key_event),
+        _ => None,
+    }
+}
+
+// Commands to the keyboard itself: its lights and how it repeats a held key. Each is a command
+// byte and an argument, and the keyboard acknowledges every byte before it takes the next. The
+// acknowledgements come in through the keyboard interrupt, like scancodes, so the bytes are
+// queued and the interrupt sends each one after the last is acknowledged; nothing waits on the
+// keyboard, and the game can change the lights from its tick.
+
+const SET_LEDS: u8 = 0xED;
+const SET_TYPEMATIC: u8 = 0xF3;
+const RESEND: u8 = 0xFE;
+/// Commands queued at most.
+const QUEUE_LEN: usize = 4;
+/// Times a byte is sent again at the keyboard's request before the command is given up.
+const MAX_RETRIES: u8 = 3;
+
+/// The keyboard's lights.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Leds {
+    pub scroll_lock: bool,
+    pub num_lock: bool,
+    pub caps_lock: bool,
+}
+
+impl Leds {
+    fn bits(self) -> u8 {
+        self.scroll_lock as u8 | (self.num_lock as u8) << 1 | (self.caps_lock as u8) << 2
+    }
+}
+
+struct Commands {
+    /// Command bytes and their arguments, oldest first.
+    queue: FixedVec<(u8, u8), QUEUE_LEN>,
+    /// Bytes of the oldest acknowledged so far, 0 or 1.
+    acknowledged: usize,
+    /// Whether the next of its bytes has been sent and not acknowledged yet.
+    in_flight: bool,
+    retries: u8,
+}
+
+impl Commands {
+    /// Queues `command` with `argument`, in place of one of the same that has not started.
+    fn push(&mut self, command: u8, argument: u8) -> bool {
+        let started = (self.in_flight || self.acknowledged > 0) as usize;
+        let waiting = self.queue.as_mut_slice().iter_mut().skip(started).find(|(queued, _)| *queued == command);
+        if let Some(waiting) = waiting {
+            waiting.1 = argument;
+            return true;
+        }
+        self.queue.push((command, argument)).is_ok()
+    }
+
+    /// Moves on to the next byte, or with `drop` to the next command.
+    fn advance(&mut self, drop: bool) {
+        self.acknowledged += 1;
+        if drop || self.acknowledged == 2 {
+            self.queue.remove(0);
+            self.acknowledged = 0;
+        }
+        (self.in_flight, self.retries) = (false, 0);
+    }
+
+    /// Sends the next byte, if there is one and the last has been acknowledged.
+    fn send(&mut self) {
+        if !self.in_flight && !self.queue.is_empty() && ps2::keyboard_present() {
+            let (command, argument) = self.queue[0];
+            ps2::write_data(if self.acknowledged == 0 { command } else { argument });
+            self.in_flight = true;
+        }
+    }
+}
+
+static COMMANDS: Mutex<Commands> = Mutex::new(Commands { queue: FixedVec::new(), acknowledged: 0, in_flight: false, retries: 0 });
+
+fn queue(command: u8, argument: u8) -> bool {
+    // The keyboard interrupt takes the same lock
+    without_interrupts(|| {
+        let mut commands = COMMANDS.lock();
+        let queued = commands.push(command, argument);
+        commands.send();
+        queued
+    })
+}
+
+/// Turns the keyboard's lights on and off. Returns false if too many commands are waiting already.
+pub fn set_leds(leds: Leds) -> bool {
+    queue(SET_LEDS, leds.bits())
+}
+
+/// Sets how long a key is held before it repeats, and how many times a second it repeats then,
+/// to the keyboard's nearest settings. Returns false if too many commands are waiting already.
+pub fn set_typematic(delay_ms: u32, rate: u32) -> bool {
+    queue(SET_TYPEMATIC, typematic_byte(delay_ms, rate))
+}
+
+/// The argument to the typematic command: the delay in bits 5 and 6, 250 to 1000 ms in steps of
+/// 250, and the repeat period in bits 0 to 4, (8 + bits 0-2) * 2^(bits 3-4) * 4.17 ms.
+pub fn typematic_byte(delay_ms: u32, rate: u32) -> u8 {
+    let delay = (delay_ms.saturating_add(125) / 250).clamp(1, 4) - 1;
+    let period_us = 1_000_000 / rate.max(1) as i64;
+    let period = (0..32u8).min_by_key(|&code| {
+        let code_us = (8 + (code & 7) as i64) * (1 << (code >> 3)) * 4170;
+        (code_us - period_us).abs()
+    }).unwrap_or(0);
+    (delay as u8) << 5 | period
+}
+
+/// Takes a byte from the keyboard if it answers a command: an acknowledgement, which sends the
+/// next byte, or a request to send the last one again. Called from the keyboard interrupt, which
+/// decodes the byte as a scancode when this returns false.
+pub(crate) fn command_reply(byte: u8) -> bool {
+    let mut commands = COMMANDS.lock();
+    if !commands.in_flight || (byte != ps2::ACK && byte != RESEND) {
+        return false;
+    }
+    if byte == ps2::ACK {
+        commands.advance(false);
+    } else if commands.retries < MAX_RETRIES {
+        commands.retries += 1;
+        commands.in_flight = false;
+    } else {
+        commands.advance(true);
+    }
+    commands.send();
+    true
+}
+
+/// Sends the commands queued before [ps2::init] found the keyboard.
+pub(crate) fn send_queued() {
+    COMMANDS.lock().send();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn typematic_settings_are_the_nearest_the_keyboard_has() {
+        assert_eq!(typematic_byte(250, 30), 0x00);
+        assert_eq!(typematic_byte(1000, 2), 0x7F);
+        // 500 ms, and 10 a second is 12 * 2 * 4.17 ms apart
+        assert_eq!(typematic_byte(480, 10), 0x2C);
+        assert_eq!(typematic_byte(0, 0), 0x1F);
+    }
+}
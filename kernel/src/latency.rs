@@ -0,0 +1,137 @@
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU64, Ordering};
+use game::GameMode;
+use pc_keyboard::DecodedKey;
+use kernel::{serial, time};
+use crate::Pong;
+use crate::screen::screenwriter;
+
+// Input latency test, hidden from the menu: F5 there starts it. Each key pressed flashes a marker
+// in the middle of the screen, and the time from the keyboard interrupt to the present of the
+// first frame showing the marker is measured on the TSC (kernel::time). After every [BATCH]
+// presses the spread of the times, all of them since the start, goes to serial, so a change to
+// the input queue, the frame limiter or the presenting can be measured before and after. R leaves
+// it, back to the menu.
+
+/// Presses between reports.
+const BATCH: usize = 20;
+/// Pixels across the marker.
+const MARKER_SIZE: usize = 64;
+
+/// Nanoseconds since boot when the keyboard interrupt saw the press not yet shown, 0 for none.
+static PRESSED: AtomicU64 = AtomicU64::new(0);
+
+pub struct LatencyTest {
+    /// When the press the marker is up for came in, until the frame showing it is presented.
+    showing: Option<u64>,
+    /// Nanoseconds from interrupt to present, every press so far.
+    samples: Vec<u64>,
+}
+
+/// Starts the test in place of the menu.
+pub fn start(pong: &mut Pong) {
+    PRESSED.store(0, Ordering::Relaxed);
+    writeln!(serial(), "latency: press keys, R to stop; a report every {} presses", BATCH).unwrap();
+    pong.latency = Some(LatencyTest { showing: None, samples: Vec::new() });
+    pong.game.game_mode = GameMode::LatencyTest;
+}
+
+/// Notes when a key came in. Called from the keyboard interrupt; only the first press not yet
+/// shown counts, so a burst is timed from its start.
+pub fn key_interrupt() {
+    let now = time::uptime_ns().max(1);
+    let _ = PRESSED.compare_exchange(0, now, Ordering::Relaxed, Ordering::Relaxed);
+}
+
+/// Handles a key press while the test runs: R ends it, any other puts the marker up.
+pub fn handle_key(pong: &mut Pong, key: DecodedKey) {
+    if key == DecodedKey::Unicode('r') {
+        if let Some(test) = pong.latency.take() {
+            test.report();
+        }
+        pong.game.game_mode = GameMode::Menu;
+        return;
+    }
+    let Some(test) = &mut pong.latency else { return };
+    if test.showing.is_none() {
+        let pressed = PRESSED.load(Ordering::Relaxed);
+        test.showing = (pressed != 0).then_some(pressed);
+    }
+}
+
+/// Times the press whose marker was just presented. Call after each present.
+pub fn presented(pong: &mut Pong) {
+    let Some(test) = &mut pong.latency else { return };
+    let Some(pressed) = test.showing.take() else { return };
+    PRESSED.store(0, Ordering::Relaxed);
+    test.samples.push(time::uptime_ns().saturating_sub(pressed));
+    if test.samples.len() % BATCH == 0 {
+        test.report();
+    }
+}
+
+impl LatencyTest {
+    /// Writes the spread of the times so far to serial.
+    fn report(&self) {
+        let Some(spread) = Spread::of(&self.samples) else { return };
+        writeln!(serial(), "latency: {} presses, min {} us, median {} us, 95th percentile {} us, max {} us, mean {} us",
+            self.samples.len(), spread.min / 1000, spread.median / 1000, spread.p95 / 1000, spread.max / 1000, spread.mean / 1000).unwrap();
+    }
+
+    pub fn draw(&self) {
+        let mut writer = screenwriter();
+        writer.draw_string_centered(40, "LATENCY TEST: press any key, R to stop", 0xFF, 0xFF, 0xFF);
+        let count = alloc::format!("{} presses timed", self.samples.len());
+        writer.draw_string_centered(60, &count, 0xAA, 0xAA, 0xAA);
+        if self.showing.is_some() {
+            let left = writer.width().saturating_sub(MARKER_SIZE) / 2;
+            let top = writer.height().saturating_sub(MARKER_SIZE) / 2;
+            for y in top..top + MARKER_SIZE {
+                for x in left..left + MARKER_SIZE {
+                    writer.draw_pixel(x, y, 0xFF, 0xFF, 0xFF);
+                }
+            }
+        }
+    }
+}
+
+/// How a set of times is spread, in the times' unit.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Spread {
+    pub min: u64,
+    pub median: u64,
+    pub p95: u64,
+    pub max: u64,
+    pub mean: u64,
+}
+
+impl Spread {
+    /// The spread of `samples`, None if there are none.
+    pub fn of(samples: &[u64]) -> Option<Self> {
+        let mut sorted = samples.to_vec();
+        sorted.sort_unstable();
+        let last = sorted.len().checked_sub(1)?;
+        let percentile = |percent: usize| sorted[last * percent / 100];
+        Some(Spread {
+            min: sorted[0],
+            median: percentile(50),
+            p95: percentile(95),
+            max: sorted[last],
+            mean: sorted.iter().sum::<u64>() / sorted.len() as u64,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn latency_spread_is_taken_from_the_sorted_times() {
+        assert_eq!(Spread::of(&[]), None);
+        let times: Vec<u64> = (1..=20).rev().map(|ms| ms * 1000).collect();
+        let spread = Spread::of(&times).unwrap();
+        assert_eq!(spread, Spread { min: 1000, median: 10_000, p95: 19_000, max: 20_000, mean: 10_500 });
+    }
+}
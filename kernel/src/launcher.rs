@@ -0,0 +1,79 @@
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use game::GameMode;
+use pc_keyboard::DecodedKey;
+use crate::screen::screenwriter;
+use crate::strings::{self, Text};
+use crate::{Pong, snake, tetris};
+
+// The games the menu starts besides Pong, which is the host: the menu is Pong's, and so is
+// everything around it. Each of the others implements [Game] and has an entry in [GAMES]. The
+// menu lists the entries, and while one runs (GameMode::Launched) the kernel's input, update and
+// draw go to it through the trait, so adding a game means a module and a line in [GAMES], not a
+// change to the kernel's handlers.
+
+/// A game the launcher runs. It has the screen and the keyboard until it is left.
+pub trait Game: Send {
+    /// Advances the game one tick.
+    fn update(&mut self);
+    /// Draws the game on the screen, which has been cleared.
+    fn draw(&self);
+    /// Handles a key press. Returns false when the player leaves the game.
+    fn handle_key(&mut self, key: DecodedKey) -> bool;
+}
+
+pub struct Entry {
+    /// The key that starts it on the menu.
+    pub key: char,
+    pub name: &'static str,
+    /// Starts a game on a screen of this width and height.
+    start: fn(usize, usize) -> Box<dyn Game>,
+}
+
+pub const GAMES: [Entry; 2] = [
+    Entry { key: '8', name: "Snake", start: snake::start },
+    Entry { key: '9', name: "Tetris", start: tetris::start },
+];
+
+/// The menu line that lists the games: `Press 8: Snake  9: Tetris`.
+pub fn menu_line() -> String {
+    let games: Vec<String> = GAMES.iter().map(|entry| format!("{}: {}", entry.key, entry.name)).collect();
+    strings::fill(Text::MenuPress, &[&games.join("  ")])
+}
+
+/// Starts the game whose key `key` is, from the menu. Returns false if it is none's.
+pub fn launch(pong: &mut Pong, key: DecodedKey) -> bool {
+    let Some(entry) = GAMES.iter().find(|entry| key == DecodedKey::Unicode(entry.key)) else { return false };
+    pong.launched = Some((entry.start)(pong.game.width, pong.game.height));
+    pong.game.game_mode = GameMode::Launched;
+    true
+}
+
+/// Passes a key to the running game, and goes back to the menu when the player leaves it.
+pub fn handle_key(pong: &mut Pong, key: DecodedKey) {
+    if !pong.launched.as_mut().is_some_and(|game| game.handle_key(key)) {
+        pong.launched = None;
+        pong.game.game_mode = GameMode::Menu;
+    }
+}
+
+/// Advances the running game. One left behind by a reset to the menu is dropped.
+pub fn update(pong: &mut Pong) {
+    if pong.game.game_mode != GameMode::Launched {
+        pong.launched = None;
+    } else if let Some(game) = &mut pong.launched {
+        game.update();
+    }
+}
+
+/// Fills a `width` x `height` rectangle at (x, y), for drawing games made of blocks.
+pub fn fill(x: usize, y: usize, width: usize, height: usize, (r, g, b): (u8, u8, u8)) {
+    let mut writer = screenwriter();
+    for y in y..y + height {
+        for x in x..x + width {
+            writer.draw_pixel(x, y, r, g, b);
+        }
+    }
+}
@@ -1,21 +1,96 @@
 // Original code from rust-osdev/bootloader crate https://github.com/rust-osdev/bootloader
 #![no_std]
+#![cfg_attr(test, no_main)]
 #![feature(abi_x86_interrupt)]
+#![feature(custom_test_frameworks)]
+#![test_runner(crate::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
 
 use core::cell::UnsafeCell;
 use core::panic::PanicInfo;
 use core::fmt::Write;
-use uart_16550::SerialPort;
 use pc_keyboard::DecodedKey;
+use crate::bridge::Packet;
+use crate::gamepad::GamepadState;
+use crate::mouse::MouseEvent;
 
-mod interrupts;
+pub use fixed;
+
+pub mod acpi_tables;
+pub mod ahci;
+pub mod allocator;
+pub mod assets;
+pub mod audio;
+pub mod backtrace;
+pub mod block;
+pub mod bridge;
+pub mod buddy;
+pub mod config;
+pub mod cpu;
+pub mod crash;
+pub mod crashdump;
+pub mod deferred;
+pub mod dma;
+pub mod elf;
+pub mod eventlog;
+pub mod executor;
+pub mod fat32;
+pub mod fpu;
+pub mod frame_allocator;
+pub mod gamepad;
+pub mod gdt;
+pub mod heap_debug;
+pub mod idle;
+pub mod interrupts;
+pub mod ioapic;
+pub mod kassert;
+pub mod keyboard;
+pub mod link;
+pub mod machine_check;
+pub mod memory;
+pub mod mouse;
+pub mod page_fault;
+pub mod msi;
+pub mod net;
+pub mod nvme;
+pub mod nvram;
+pub mod pci;
+pub mod percpu;
+pub mod pit;
+pub mod power;
+pub mod process;
+pub mod profiler;
+pub mod ps2;
+pub mod qemu;
+pub mod rng;
+pub mod rtc;
+pub mod scheduler;
+pub mod serial_input;
+pub mod slab;
+pub mod smp;
+pub mod speaker;
+pub mod spsc;
+pub mod symbols;
+pub mod sync;
+pub mod syscall;
+pub mod testing;
+pub mod time;
+pub mod timers;
+pub mod uart;
+pub mod virtio;
+pub mod virtio_blk;
+pub mod virtio_console;
+pub mod virtio_gpu;
+pub mod virtio_net;
+pub mod watchdog;
+pub mod xhci;
+pub mod xmodem;
 
 extern crate alloc;
 
-pub fn serial() -> SerialPort {
-    let mut port = unsafe { SerialPort::new(0x3F8) };
-    port.init();
-    port
+/// Writer for the serial console, see [uart].
+pub fn serial() -> uart::Serial {
+    uart::Serial
 }
 
 /// Table of interrupt handlers. This struct uses the
@@ -24,44 +99,66 @@ pub fn serial() -> SerialPort {
 /// up the handlers. When ready, call the **.start()** method to start up your pluggable
 /// interrupt operating system.
 ///
-/// For now, it only includes timer and keyboard handlers.
+/// For now, it only includes timer, keyboard, mouse, gamepad, serial and controller bridge input
+/// handlers.
 pub struct HandlerTable {
     timer: Option<fn()>,
     keyboard: Option<fn(DecodedKey)>,
+    mouse: Option<fn(MouseEvent)>,
+    serial: Option<fn(DecodedKey)>,
+    gamepad: Option<fn(GamepadState)>,
+    bridge: Option<fn(Packet)>,
     startup: Option<fn()>,
     cpu_loop: fn() -> !,
+    tick_hz: u32,
 }
 
 impl HandlerTable {
     /// Creates a new HandlerTable with no handlers.
     pub fn new() -> Self {
-        HandlerTable {timer: None, keyboard: None, startup: None, cpu_loop: hlt_loop}
+        HandlerTable {timer: None, keyboard: None, mouse: None, serial: None, gamepad: None, bridge: None, startup: None, cpu_loop: deferred::run_loop, tick_hz: interrupts::DEFAULT_TICK_HZ}
     }
 
     /// Starts up a simple operating system using the specified handlers.
     pub fn start(self, lapic_ptr: *mut u32) -> ! {
         self.startup.map(|f| f());
         let fore = self.cpu_loop;
+
+        ps2::init(self.mouse.is_some());
+        if self.mouse.is_some() {
+            mouse::init();
+        }
         
         interrupts::init_idt(self, lapic_ptr);
         
         (fore)();
     }
 
-    /// Sets the timer handler.
+    /// Sets the timer handler. It runs from the CPU loop rather than inside the timer interrupt
+    /// (see [deferred]), so it may take its time without blocking other interrupts.
     /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
     pub fn timer(mut self, timer_handler: fn()) -> Self {
         self.timer = Some(timer_handler);
         self
     }
 
-    /// Called by the low-level interrupt routines to handle a timer event.
+    /// Runs the timer handler, if one is set.
     pub fn handle_timer(&self) {
         if let Some(timer) = self.timer {
             (timer)()
         }
     }
 
+    /// Sets how many times per second the timer handler runs. The LAPIC timer is calibrated
+    /// at boot, so the rate is accurate regardless of the CPU's bus frequency.
+    /// Defaults to [interrupts::DEFAULT_TICK_HZ].
+    ///
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub fn tick_hz(mut self, hz: u32) -> Self {
+        self.tick_hz = hz;
+        self
+    }
+
     /// Sets the keyboard handler. The [DecodedKey](https://docs.rs/pc-keyboard/0.5.1/pc_keyboard/enum.DecodedKey.html)
     /// enum comes from the [pc_keyboard](https://crates.io/crates/pc-keyboard) crate.
     ///
@@ -78,6 +175,70 @@ impl HandlerTable {
         }
     }
 
+    /// Sets the mouse handler. The second PS/2 port is only enabled when a mouse handler
+    /// has been set.
+    ///
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub fn mouse(mut self, mouse_handler: fn(MouseEvent)) -> Self {
+        self.mouse = Some(mouse_handler);
+        self
+    }
+
+    /// Called by the low-level interrupt routines to handle a complete mouse packet.
+    pub fn handle_mouse(&self, event: MouseEvent) {
+        if let Some(mouse) = self.mouse {
+            (mouse)(event)
+        }
+    }
+
+    /// Sets the serial input handler, called with keys typed on the serial console.
+    /// Arrow keys arrive as `DecodedKey::RawKey`.
+    ///
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub fn serial(mut self, serial_handler: fn(DecodedKey)) -> Self {
+        self.serial = Some(serial_handler);
+        self
+    }
+
+    /// Called by the low-level interrupt routines to handle a key received over serial.
+    pub fn handle_serial(&self, key: DecodedKey) {
+        if let Some(serial) = self.serial {
+            (serial)(key)
+        }
+    }
+
+    /// Sets the gamepad handler, called with each report from a USB gamepad found by
+    /// [xhci::init]. Reports are polled on every timer interrupt.
+    ///
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub fn gamepad(mut self, gamepad_handler: fn(GamepadState)) -> Self {
+        self.gamepad = Some(gamepad_handler);
+        self
+    }
+
+    /// Called by the low-level interrupt routines to handle a gamepad report.
+    pub fn handle_gamepad(&self, state: GamepadState) {
+        if let Some(gamepad) = self.gamepad {
+            (gamepad)(state)
+        }
+    }
+
+    /// Sets the controller bridge handler, called with each packet a host-side tool sends over the
+    /// serial console (see [bridge]).
+    ///
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub fn bridge(mut self, bridge_handler: fn(Packet)) -> Self {
+        self.bridge = Some(bridge_handler);
+        self
+    }
+
+    /// Called by the low-level interrupt routines to handle a controller bridge packet.
+    pub fn handle_bridge(&self, packet: Packet) {
+        if let Some(bridge) = self.bridge {
+            (bridge)(packet)
+        }
+    }
+
     /// Sets the startup handler.
     /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
     pub fn startup(mut self, startup_handler: fn()) -> Self {
@@ -86,7 +247,8 @@ impl HandlerTable {
     }
 
     /// Sets the cpu loop handler.
-    /// This function should contain an infinite loop.
+    /// This function should contain an infinite loop that calls [deferred::run_pending].
+    /// Defaults to [deferred::run_loop]; use [executor::run] to run async tasks as well.
     /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
     pub fn cpu_loop(mut self, cpu_loop: fn() -> !) -> Self {
         self.cpu_loop = cpu_loop;
@@ -100,9 +262,48 @@ pub fn hlt_loop() -> ! {
     }
 }
 
+// The library's tests, in a `mod tests` next to the code they test, run in a test kernel of
+// their own booted like those in kernel/tests
+#[cfg(test)]
+bootloader_api::entry_point!(test_kernel_main, config = &testing::BOOTLOADER_CONFIG);
+
+#[cfg(test)]
+fn test_kernel_main(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
+    let lapic = testing::init(boot_info);
+    HandlerTable::new().cpu_loop(run_tests).start(lapic)
+}
+
+#[cfg(test)]
+fn run_tests() -> ! {
+    test_main();
+    hlt_loop()
+}
+
+static PANIC_DISPLAY: spin::Mutex<Option<fn(&PanicInfo)>> = spin::Mutex::new(None);
+
+/// Registers the function that shows panics on screen, on top of the message on serial.
+pub fn set_panic_display(display: fn(&PanicInfo)) {
+    *PANIC_DISPLAY.lock() = Some(display);
+}
+
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
+    // Nothing may draw over the panic, or run on in a broken state
+    x86_64::instructions::interrupts::disable();
     let _ = writeln!(serial(), "PANIC: {info}");
+    backtrace::print(None, backtrace::frame_pointer());
+    crashdump::write(crashdump::Reason::Panic(info), backtrace::frame_pointer());
+    // try_lock: the panic may have hit while the display was being registered
+    let display = PANIC_DISPLAY.try_lock().and_then(|display| *display);
+    if let Some(display) = display {
+        display(info);
+    }
+    if qemu::exit_on_panic() {
+        // With interrupts off, everything above was sent as it was written, and what was queued
+        // before it went out first (see uart::write_bytes); no flush, which could wait on a lock
+        // the panic left held
+        qemu::exit(qemu::ExitCode::Failure);
+    }
     hlt_loop();
 }
 
@@ -0,0 +1,216 @@
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use spin::Mutex;
+use uart_16550::SerialPort;
+use x86_64::instructions::port::Port;
+use crate::serial;
+use crate::spsc::SpscQueue;
+
+// Point-to-point link over the second serial port, COM2, for talking to another machine without
+// a network: in QEMU, `-serial tcp::4555,server=on,wait=off` on one side and
+// `-serial tcp:localhost:4555` on the other. COM1 stays the console.
+//
+// Data goes in frames: FLAG, the payload and its CRC-16 with any FLAG or ESCAPE byte escaped, FLAG.
+// A receiver joining mid-frame, or a frame with a byte lost or flipped, costs that frame only;
+// the next FLAG starts over. Received bytes are queued by the interrupt handler, frames are
+// taken apart when read. Sending is polled, a FIFO at a time.
+
+const COM2: u16 = 0x2F8;
+const DATA: u16 = COM2;
+const DIVISOR_HIGH: u16 = COM2 + 1;
+const LINE_CONTROL: u16 = COM2 + 3;
+const LINE_STATUS: u16 = COM2 + 5;
+const SCRATCH: u16 = COM2 + 7;
+
+const LCR_DIVISOR_LATCH: u8 = 1 << 7;
+const LSR_DATA_READY: u8 = 1 << 0;
+const LSR_TRANSMIT_EMPTY: u8 = 1 << 5;
+const FIFO_SIZE: usize = 16;
+
+const FLAG: u8 = 0x7E;
+const ESCAPE: u8 = 0x7D;
+const ESCAPE_XOR: u8 = 0x20;
+/// Longest payload [send_frame] takes; longer frames received are dropped.
+pub const MAX_FRAME: usize = 256;
+
+static PRESENT: AtomicBool = AtomicBool::new(false);
+static RX: SpscQueue<u8, 4096> = SpscQueue::new();
+static DECODER: Mutex<Decoder> = Mutex::new(Decoder::new());
+static FRAMES_SENT: AtomicU64 = AtomicU64::new(0);
+static FRAMES_RECEIVED: AtomicU64 = AtomicU64::new(0);
+static FRAMES_BAD: AtomicU64 = AtomicU64::new(0);
+static BYTES_DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Counts since boot, from [stats].
+#[derive(Debug, Clone, Copy)]
+pub struct LinkStats {
+    pub sent: u64,
+    pub received: u64,
+    /// Frames received with a bad CRC or too long.
+    pub bad: u64,
+    /// Bytes received while the queue was full.
+    pub dropped: u64,
+}
+
+fn read(port: u16) -> u8 {
+    unsafe { Port::<u8>::new(port).read() }
+}
+
+fn write(port: u16, value: u8) {
+    unsafe { Port::<u8>::new(port).write(value) }
+}
+
+/// Looks for COM2 and programs it: 115200 baud, 8N1, FIFOs, receive interrupt. Returns false if
+/// there is no UART there.
+pub fn init() -> bool {
+    // An absent port reads back all ones
+    write(SCRATCH, 0x5A);
+    if read(SCRATCH) != 0x5A {
+        return false;
+    }
+    unsafe { SerialPort::new(COM2).init() };
+    // init leaves the divisor at 3 (38400 baud)
+    let line_control = read(LINE_CONTROL);
+    write(LINE_CONTROL, line_control | LCR_DIVISOR_LATCH);
+    write(DATA, 1);
+    write(DIVISOR_HIGH, 0);
+    write(LINE_CONTROL, line_control);
+    PRESENT.store(true, Ordering::Release);
+    writeln!(serial(), "link: COM2 at 115200 baud").unwrap();
+    true
+}
+
+/// Whether [init] found the port.
+pub fn is_available() -> bool {
+    PRESENT.load(Ordering::Acquire)
+}
+
+/// COM2 interrupt: queues what was received.
+pub(crate) fn handle_interrupt() {
+    // An absent port reads as always having data
+    if !is_available() {
+        return;
+    }
+    while read(LINE_STATUS) & LSR_DATA_READY != 0 {
+        if !RX.push(read(DATA)) {
+            BYTES_DROPPED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// CRC-16/CCITT-FALSE
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0xFFFF, |crc, &byte| {
+        let mut crc = crc ^ ((byte as u16) << 8);
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+        crc
+    })
+}
+
+/// Sends `payload` as one frame. Returns false without the port, or if the payload is longer
+/// than [MAX_FRAME]. Waits for the line, about 87 µs a byte.
+pub fn send_frame(payload: &[u8]) -> bool {
+    if !is_available() || payload.len() > MAX_FRAME {
+        return false;
+    }
+    let mut frame = Vec::with_capacity(payload.len() * 2 + 6);
+    frame.push(FLAG);
+    for &byte in payload.iter().chain(&crc16(payload).to_be_bytes()) {
+        if byte == FLAG || byte == ESCAPE {
+            frame.extend_from_slice(&[ESCAPE, byte ^ ESCAPE_XOR]);
+        } else {
+            frame.push(byte);
+        }
+    }
+    frame.push(FLAG);
+
+    for chunk in frame.chunks(FIFO_SIZE) {
+        while read(LINE_STATUS) & LSR_TRANSMIT_EMPTY == 0 {
+            core::hint::spin_loop();
+        }
+        for &byte in chunk {
+            write(DATA, byte);
+        }
+    }
+    FRAMES_SENT.fetch_add(1, Ordering::Relaxed);
+    true
+}
+
+struct Decoder {
+    frame: Vec<u8>,
+    escaped: bool,
+    /// Past [MAX_FRAME], or no FLAG seen yet: skipping to the next FLAG.
+    discarding: bool,
+}
+
+impl Decoder {
+    const fn new() -> Self {
+        Decoder { frame: Vec::new(), escaped: false, discarding: true }
+    }
+
+    /// Takes the next byte; returns the payload when it ends a good frame.
+    fn add_byte(&mut self, byte: u8) -> Option<Vec<u8>> {
+        if byte == FLAG {
+            let frame = core::mem::take(&mut self.frame);
+            self.discarding = false;
+            self.escaped = false;
+            // Back-to-back FLAGs between frames, or the end of a discarded one
+            if frame.is_empty() {
+                return None;
+            }
+            if frame.len() < 2 {
+                FRAMES_BAD.fetch_add(1, Ordering::Relaxed);
+                return None;
+            }
+            let (payload, crc) = frame.split_at(frame.len() - 2);
+            if crc16(payload).to_be_bytes() != crc {
+                FRAMES_BAD.fetch_add(1, Ordering::Relaxed);
+                return None;
+            }
+            FRAMES_RECEIVED.fetch_add(1, Ordering::Relaxed);
+            return Some(payload.to_vec());
+        }
+        if self.discarding {
+            return None;
+        }
+        let byte = if core::mem::take(&mut self.escaped) {
+            byte ^ ESCAPE_XOR
+        } else if byte == ESCAPE {
+            self.escaped = true;
+            return None;
+        } else {
+            byte
+        };
+        if self.frame.len() == MAX_FRAME + 2 {
+            self.frame.clear();
+            self.discarding = true;
+            FRAMES_BAD.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        self.frame.push(byte);
+        None
+    }
+}
+
+/// The payload of the next good frame received, if one is complete. Never blocks.
+pub fn receive_frame() -> Option<Vec<u8>> {
+    let mut decoder = DECODER.lock();
+    while let Some(byte) = RX.pop() {
+        if let Some(payload) = decoder.add_byte(byte) {
+            return Some(payload);
+        }
+    }
+    None
+}
+
+pub fn stats() -> LinkStats {
+    LinkStats {
+        sent: FRAMES_SENT.load(Ordering::Relaxed),
+        received: FRAMES_RECEIVED.load(Ordering::Relaxed),
+        bad: FRAMES_BAD.load(Ordering::Relaxed),
+        dropped: BYTES_DROPPED.load(Ordering::Relaxed),
+    }
+}
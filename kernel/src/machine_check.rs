@@ -0,0 +1,163 @@
+use core::fmt::{self, Write};
+use x86_64::instructions::port::Port;
+use x86_64::registers::control::{Cr4, Cr4Flags};
+use x86_64::registers::model_specific::Msr;
+use crate::{cpu, serial};
+
+// Hardware errors the CPU or the chipset reports: machine checks (#MC) and NMIs raised for memory
+// parity or bus errors. Both mean the machine itself has gone wrong, so there is no recovering; but
+// what the hardware says about it goes to serial and on screen before the kernel halts, rather
+// than a bare exception. Machine checks are only delivered once CR4.MCE is set, which [init] does;
+// the error banks (MCi_STATUS and friends) are read when the CPU has MCA.
+
+const IA32_MCG_CAP: u32 = 0x179;
+const IA32_MCG_STATUS: u32 = 0x17A;
+/// MCi_STATUS of bank 0; each bank's registers are 4 apart, CTL, STATUS, ADDR, MISC.
+const IA32_MC0_STATUS: u32 = 0x401;
+
+/// Most banks read. CPUs have a handful to a few dozen.
+const MAX_BANKS: usize = 32;
+
+/// The NMI status and control port: why the chipset raised an NMI.
+const NMI_STATUS_PORT: u16 = 0x61;
+const NMI_PARITY_ERROR: u8 = 1 << 7;
+const NMI_CHANNEL_CHECK: u8 = 1 << 6;
+
+/// Enables machine checks on the calling CPU, and logs errors left in the banks from before boot
+/// (a previous crash, often). Every CPU runs it.
+pub fn init() {
+    let features = cpu::features();
+    if !features.mce {
+        return;
+    }
+    unsafe { Cr4::update(|flags| flags.insert(Cr4Flags::MACHINE_CHECK_EXCEPTION)) };
+    if features.mca {
+        for bank in banks() {
+            writeln!(serial(), "MCE: left over from before boot: {}", bank).unwrap();
+        }
+    }
+}
+
+/// One error bank's status: MCi_STATUS, and MCi_ADDR and MCi_MISC when it says they hold anything.
+#[derive(Debug, Clone, Copy)]
+pub struct BankStatus {
+    pub bank: u8,
+    pub status: u64,
+    pub address: Option<u64>,
+    pub misc: Option<u64>,
+}
+
+impl BankStatus {
+    const VALID: u64 = 1 << 63;
+    const OVERFLOW: u64 = 1 << 62;
+    const UNCORRECTED: u64 = 1 << 61;
+    const MISC_VALID: u64 = 1 << 59;
+    const ADDRESS_VALID: u64 = 1 << 58;
+    const CONTEXT_CORRUPT: u64 = 1 << 57;
+
+    /// The architectural error code, bits 0 to 15.
+    pub fn mca_code(&self) -> u16 {
+        self.status as u16
+    }
+
+    /// The model-specific error code, bits 16 to 31.
+    pub fn model_code(&self) -> u16 {
+        (self.status >> 16) as u16
+    }
+
+    pub fn uncorrected(&self) -> bool {
+        self.status & Self::UNCORRECTED != 0
+    }
+}
+
+impl fmt::Display for BankStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "bank {} status {:#018x} (mca {:#06x}, model {:#06x}", self.bank, self.status, self.mca_code(), self.model_code())?;
+        for (flag, name) in [(Self::UNCORRECTED, "uncorrected"), (Self::CONTEXT_CORRUPT, "context corrupt"), (Self::OVERFLOW, "overflow")] {
+            if self.status & flag != 0 {
+                write!(f, ", {}", name)?;
+            }
+        }
+        write!(f, ")")?;
+        if let Some(address) = self.address {
+            write!(f, " addr {:#x}", address)?;
+        }
+        if let Some(misc) = self.misc {
+            write!(f, " misc {:#x}", misc)?;
+        }
+        Ok(())
+    }
+}
+
+/// The banks holding an error, in order. Nothing without MCA.
+pub fn banks() -> impl Iterator<Item = BankStatus> {
+    // The bank count is MCG_CAP's low byte
+    let count = if cpu::features().mca { (unsafe { Msr::new(IA32_MCG_CAP).read() } & 0xFF) as usize } else { 0 };
+    (0..count.min(MAX_BANKS)).filter_map(|bank| {
+        let base = IA32_MC0_STATUS + 4 * bank as u32;
+        let status = unsafe { Msr::new(base).read() };
+        if status & BankStatus::VALID == 0 {
+            return None;
+        }
+        let read_if = |flag, register| (status & flag != 0).then(|| unsafe { Msr::new(register).read() });
+        Some(BankStatus {
+            bank: bank as u8,
+            status,
+            address: read_if(BankStatus::ADDRESS_VALID, base + 1),
+            misc: read_if(BankStatus::MISC_VALID, base + 2),
+        })
+    })
+}
+
+/// Writes MCG_STATUS and every bank holding an error to serial, and returns the first uncorrected
+/// one (or else the first), for the panic message.
+pub fn report() -> Option<BankStatus> {
+    if !cpu::features().mca {
+        writeln!(serial(), "MCE: no machine check architecture, no details").unwrap();
+        return None;
+    }
+    // RIPV: the interrupted code can go on; EIPV: the error was at that instruction; MCIP: in progress
+    let global = unsafe { Msr::new(IA32_MCG_STATUS).read() };
+    writeln!(serial(), "MCE: MCG_STATUS {:#x} (ripv {}, eipv {}, mcip {})",
+        global, global & 1, global >> 1 & 1, global >> 2 & 1).unwrap();
+    let mut worst: Option<BankStatus> = None;
+    for bank in banks() {
+        writeln!(serial(), "MCE: {}", bank).unwrap();
+        if worst.is_none_or(|worst| !worst.uncorrected() && bank.uncorrected()) {
+            worst = Some(bank);
+        }
+    }
+    worst
+}
+
+/// Why the chipset raised an NMI, if it was for a hardware error: a memory parity or PCI system
+/// error, or an I/O channel check. None for others (a watchdog, or another CPU).
+pub fn nmi_reason() -> Option<&'static str> {
+    let status = unsafe { Port::<u8>::new(NMI_STATUS_PORT).read() };
+    if status & NMI_PARITY_ERROR != 0 {
+        Some("memory parity or system error (SERR#)")
+    } else if status & NMI_CHANNEL_CHECK != 0 {
+        Some("I/O channel check (IOCHK#)")
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::format;
+    use super::*;
+
+    #[test_case]
+    fn machine_check_banks_describe_their_errors() {
+        let bank = BankStatus { bank: 4, status: 0xB200_0000_0013_0135, address: Some(0x1234), misc: None };
+        assert!(bank.uncorrected());
+        assert_eq!((bank.mca_code(), bank.model_code()), (0x0135, 0x0013));
+        let line = format!("{}", bank);
+        assert!(line.starts_with("bank 4 "));
+        assert!(line.contains("uncorrected, context corrupt)"));
+        assert!(line.ends_with("addr 0x1234"));
+        // Whatever QEMU has, looking must not fault
+        let _ = banks().count();
+    }
+}
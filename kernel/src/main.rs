@@ -1,28 +1,79 @@
 #![feature(sync_unsafe_cell)]
 #![feature(abi_x86_interrupt)]
+#![feature(custom_test_frameworks)]
 #![no_std]
 #![no_main]
+#![test_runner(kernel::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
 
 extern crate alloc;
 
 mod screen;
-mod allocator;
-mod frame_allocator;
-mod interrupts;
-mod gdt;
+mod vga_text;
+mod ansi_text;
+mod settings;
+mod controls;
+mod memory_map;
+mod sequence;
+mod shell;
+mod netplay;
+mod highscores;
+mod saved;
+mod theme;
+mod replay;
+mod benchmark;
+mod snapshot;
+mod launcher;
+mod snake;
+mod tetris;
+mod screensaver;
+mod strings;
+mod sound;
+mod controllers;
+mod tween;
+mod bus;
+mod frame_limiter;
+mod title;
+mod memtest;
+mod profiles;
+mod instant_replay;
+mod accessibility;
+mod quick_chat;
+mod frame_step;
+mod latency;
+mod spectator;
 
 use alloc::boxed::Box;
 use core::fmt::Write;
 use core::slice;
+use core::time::Duration;
 use bootloader_api::{entry_point, BootInfo, BootloaderConfig};
 use bootloader_api::config::Mapping::Dynamic;
 use bootloader_api::info::MemoryRegionKind;
-use kernel::{HandlerTable, serial};
-use pc_keyboard::DecodedKey;
+use game::{Difficulty, Direction, Game, GameMode, Kind, ai_direction};
+use game::ai::{Controller, Personality};
+use kernel::{HandlerTable, allocator, audio, eventlog, gdt, interrupts, rng, serial, speaker};
+use kernel::keyboard::{self, Leds};
+use kernel::audio::{Note, VoiceId};
+use kernel::bridge::{self, Packet};
+use kernel::gamepad::GamepadState;
+use kernel::mouse::MouseEvent;
+use kernel::profiler::{self, Phase};
+use kernel::spsc::SpscQueue;
+use pc_keyboard::{DecodedKey, KeyCode};
 use x86_64::registers::control::Cr3;
-use x86_64::VirtAddr;
-use crate::frame_allocator::BootInfoFrameAllocator;
+use x86_64::structures::paging::{Page, Size4KiB};
+use x86_64::{PhysAddr, VirtAddr};
+use kernel::frame_allocator;
+use kernel::fixed::FixedString;
 use crate::screen::{Writer, screenwriter};
+use crate::settings::Settings;
+use crate::highscores::HighScores;
+use crate::controls::{Action, key_name};
+use crate::sequence::SequenceDetector;
+use crate::theme::Theme;
+use crate::strings::{Text, fill, text};
+use crate::tween::{Easing, Tween};
 
 const BOOTLOADER_CONFIG: BootloaderConfig = {
     let mut config = BootloaderConfig::new_default();
@@ -32,223 +83,533 @@ const BOOTLOADER_CONFIG: BootloaderConfig = {
 };
 entry_point!(kernel_main, config = &BOOTLOADER_CONFIG);
 
-#[derive(PartialEq, Eq)]
-pub enum GameMode {
-    Menu,
-    OnePlayer,
-    TwoPlayer,
-    GameOver,
+/// Key sequences the game reacts to.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Cheat {
+    RainbowBall,
 }
 
+const KONAMI_CODE: [DecodedKey; 10] = [
+    DecodedKey::RawKey(KeyCode::ArrowUp),
+    DecodedKey::RawKey(KeyCode::ArrowUp),
+    DecodedKey::RawKey(KeyCode::ArrowDown),
+    DecodedKey::RawKey(KeyCode::ArrowDown),
+    DecodedKey::RawKey(KeyCode::ArrowLeft),
+    DecodedKey::RawKey(KeyCode::ArrowRight),
+    DecodedKey::RawKey(KeyCode::ArrowLeft),
+    DecodedKey::RawKey(KeyCode::ArrowRight),
+    DecodedKey::Unicode('b'),
+    DecodedKey::Unicode('a'),
+];
+
+const RAINBOW: [(u8, u8, u8); 6] = [
+    (0xFF, 0x00, 0x00),
+    (0xFF, 0x88, 0x00),
+    (0xFF, 0xFF, 0x00),
+    (0x00, 0xFF, 0x00),
+    (0x00, 0x88, 0xFF),
+    (0xAA, 0x00, 0xFF),
+];
+
+/// Background music while a game is on, looped.
+const MUSIC: [Note; 15] = [
+    Note { frequency: 262, ms: 200 }, Note { frequency: 330, ms: 200 }, Note { frequency: 392, ms: 200 }, Note { frequency: 330, ms: 200 },
+    Note { frequency: 294, ms: 200 }, Note { frequency: 349, ms: 200 }, Note { frequency: 440, ms: 200 }, Note { frequency: 349, ms: 200 },
+    Note { frequency: 262, ms: 200 }, Note { frequency: 330, ms: 200 }, Note { frequency: 392, ms: 200 }, Note { frequency: 523, ms: 200 },
+    Note { frequency: 494, ms: 200 }, Note { frequency: 392, ms: 200 }, Note { frequency: 0, ms: 400 },
+];
+const MUSIC_VOLUME: u8 = 24;
+/// The PC speaker's tune on the menu, looped, and its jingle at the end of a game.
+const MENU_TUNE: [Note; 12] = [
+    Note { frequency: 392, ms: 300 }, Note { frequency: 330, ms: 150 }, Note { frequency: 392, ms: 150 }, Note { frequency: 523, ms: 300 },
+    Note { frequency: 0, ms: 150 }, Note { frequency: 440, ms: 150 }, Note { frequency: 392, ms: 300 }, Note { frequency: 330, ms: 300 },
+    Note { frequency: 294, ms: 300 }, Note { frequency: 330, ms: 150 }, Note { frequency: 262, ms: 450 }, Note { frequency: 0, ms: 900 },
+];
+const GAME_OVER_JINGLE: [Note; 5] = [
+    Note { frequency: 523, ms: 120 }, Note { frequency: 659, ms: 120 }, Note { frequency: 784, ms: 120 }, Note { frequency: 0, ms: 60 },
+    Note { frequency: 1047, ms: 400 },
+];
+/// The melodies played: the ones above, or the ramdisk's `sounds/music`, `sounds/menu` and
+/// `sounds/game_over` in their place (kernel::assets, written as audio::parse_melody reads them).
+struct Sounds {
+    music: &'static [Note],
+    menu_tune: &'static [Note],
+    game_over_jingle: &'static [Note],
+}
+static SOUNDS: spin::Once<Sounds> = spin::Once::new();
+const EFFECT_VOLUME: u8 = 64;
+/// How long a new screen takes to fade in, in milliseconds.
+const FADE_MS: u32 = 250;
+/// Pixels below their place the menu's options start, and how long they take to slide up.
+const MENU_SLIDE: f32 = 40.0;
+const MENU_SLIDE_MS: u32 = 300;
+/// How long the score stands out after a point.
+const SCORE_POP_MS: u32 = 600;
+const SCORE_POP_COLOR: (u8, u8, u8) = (0xFF, 0xFF, 0x55);
+/// How long the Num Lock and Caps Lock lights flash for after a point.
+const LED_FLASH_MS: u64 = 500;
+/// Pixels between the dots of the training overlay's path, and how far towards the ball's color
+/// from black they are drawn.
+const TRAINING_DOT_SPACING: f32 = 6.0;
+const TRAINING_SHADE: f32 = 0.4;
+
 pub struct Pong {
-    pub game_mode: GameMode,
-    pub ball_x: usize,
-    pub ball_y: usize,
-    pub ball_dx: isize,
-    pub ball_dy: isize,
-    pub player1_y: usize,
-    pub player2_y: usize,
-    pub player1_score: u32,
-    pub player2_score: u32,
-    pub width: usize,
-    pub height: usize,
-    pub paddle_height: usize,
+    /// The rules and the state of play, see the game crate.
+    pub game: Game,
+    pub settings: Settings,
+    pub high_scores: HighScores,
+    /// The place the last one-player game took in [Self::high_scores], if it made it.
+    pub new_high_score: Option<usize>,
+    pub gamepad: GamepadState,
+    /// The controller bridge's devices, see [controllers].
+    pub controllers: [GamepadState; bridge::MAX_DEVICES],
+    pub ticks: u64,
+    pub sequences: SequenceDetector<Cheat>,
+    pub rainbow_ball: bool,
+    pub show_stats: bool,
+    /// The background music, while it plays.
+    pub music: Option<VoiceId>,
+    /// The network or serial link game, from the lobby to the end of the game.
+    pub netplay: Option<netplay::Session>,
+    pub theme: &'static Theme,
+    /// The recording being made or replayed, with the `record` or `replay` boot option.
+    pub replay: Option<replay::Session>,
+    /// The rendering benchmark, while it runs.
+    pub benchmark: Option<benchmark::Benchmark>,
+    /// The input latency test, while it runs.
+    pub latency: Option<latency::LatencyTest>,
+    /// The launcher's game being played, if it is not Pong.
+    pub launched: Option<Box<dyn launcher::Game>>,
+    /// The menu's screensaver, while it is on.
+    pub screensaver: Option<screensaver::Screensaver>,
+    /// The tick of the last input, or of the last on which the menu was not showing.
+    pub last_input: u64,
+    /// How far the screen has faded in since it changed, 0 to 1.
+    pub fade: Tween,
+    /// Pixels the menu's options are below their place.
+    pub menu_slide: Tween,
+    /// How much the score stands out, 1 just after a point and 0 once it has settled.
+    pub score_pop: Tween,
+    /// What hears about the game's events, see [bus].
+    pub bus: bus::Bus,
+    pub statistics: bus::Statistics,
+    /// When to tick and when to draw, see [frame_limiter].
+    pub limiter: frame_limiter::FrameLimiter,
+    /// The computer player in a one-player game.
+    pub ai: Controller,
+    /// The ball bouncing behind the menu, see [title].
+    pub demo_ball: title::DemoBall,
+    /// The players' names and records, see [profiles].
+    pub profiles: profiles::Profiles,
+    /// The keyboard's lights as last set, and the tick the point's flash ends at.
+    pub leds: Leds,
+    pub led_flash_until: u64,
+    /// The last moments of play, shown again slowly after a point, see [instant_replay].
+    pub instant_replay: instant_replay::InstantReplay,
+    /// The two players' taunts, see [quick_chat].
+    pub quick_chat: quick_chat::QuickChat,
+    /// The debug mode that runs the game a tick at a time, see [frame_step].
+    pub frame_step: frame_step::FrameStep,
 }
 
 impl Pong {
     pub const fn new(width: usize, height: usize) -> Self {
         Self {
-            game_mode: GameMode::Menu,
-            ball_x: width / 2,
-            ball_y: height / 2,
-            ball_dx: 1,
-            ball_dy: 1,
-            player1_y: height / 2,
-            player2_y: height / 2,
-            player1_score: 0,
-            player2_score: 0,
-            width,
-            height,
-            paddle_height: 50,
-        }
-    }
-
-    pub fn reset(&mut self) {
-        self.ball_x = self.width / 2;
-        self.ball_y = self.height / 2;
-        self.ball_dx = if fast_rand() % 2 == 0 { 1 } else { -1 };
-        self.ball_dy = if fast_rand() % 2 == 0 { 1 } else { -1 };
-        self.player1_y = self.height / 2;
-        self.player2_y = self.height / 2;
+            game: Game::new(width, height),
+            settings: Settings::new(),
+            high_scores: HighScores::new(),
+            new_high_score: None,
+            gamepad: GamepadState::new(),
+            controllers: [GamepadState::new(); bridge::MAX_DEVICES],
+            ticks: 0,
+            sequences: SequenceDetector::new(),
+            rainbow_ball: false,
+            show_stats: false,
+            music: None,
+            netplay: None,
+            theme: theme::CLASSIC,
+            replay: None,
+            benchmark: None,
+            latency: None,
+            launched: None,
+            screensaver: None,
+            last_input: 0,
+            fade: Tween::done(1.0),
+            menu_slide: Tween::done(0.0),
+            score_pop: Tween::done(0.0),
+            bus: bus::Bus::new(),
+            statistics: bus::Statistics::new(),
+            limiter: frame_limiter::FrameLimiter::new(),
+            ai: Controller::new(Personality::Steady),
+            demo_ball: title::DemoBall::new(),
+            profiles: profiles::Profiles::new(),
+            leds: Leds { scroll_lock: false, num_lock: false, caps_lock: false },
+            led_flash_until: 0,
+            instant_replay: instant_replay::InstantReplay::new(),
+            quick_chat: quick_chat::QuickChat::new(),
+            frame_step: frame_step::FrameStep::new(),
+        }
+    }
+
+    /// Starts the animations of a screen just shown.
+    pub fn enter_screen(&mut self) {
+        self.fade = Tween::new(0.0, 1.0, FADE_MS, Easing::Linear);
+        if self.game.game_mode == GameMode::Menu {
+            self.menu_slide = Tween::new(MENU_SLIDE, 0.0, MENU_SLIDE_MS, Easing::EaseOut);
+        }
+    }
+
+    /// Moves the animations on by a tick.
+    pub fn animate(&mut self) {
+        self.fade.advance();
+        self.menu_slide.advance();
+        self.score_pop.advance();
+        self.quick_chat.advance();
+    }
+
+    /// The colors to draw with: the theme's, or the high-contrast ones if the accessibility
+    /// settings ask for them.
+    fn colors(&self) -> &'static Theme {
+        self.settings.accessibility.theme(self.theme)
+    }
+
+    /// `color` pulsing as a prompt does, or steady with reduced flashing.
+    fn pulse(&self, color: (u8, u8, u8)) -> (u8, u8, u8) {
+        if self.settings.accessibility.reduced_flash { color } else { title::pulse(color, self.ticks) }
+    }
+
+    /// `color` as far as the screen has faded in.
+    fn faded(&self, color: (u8, u8, u8)) -> (u8, u8, u8) {
+        tween::mix((0, 0, 0), color, self.fade.value())
+    }
+
+    /// Follows a change of screen size: takes the new size, and serves again from the middle.
+    pub fn fit_screen(&mut self) {
+        let (width, height) = {
+            let writer = screenwriter();
+            (writer.width(), writer.height())
+        };
+        self.game.resize(width, height, rng::u32);
     }
 
     pub fn draw(&self) {
         screenwriter().clear();
 
-        match self.game_mode {
+        match self.game.game_mode {
+            GameMode::Menu if self.screensaver.is_some() => {
+                if let Some(screensaver) = &self.screensaver {
+                    screensaver.draw(self.ticks);
+                }
+            }
             GameMode::Menu => {
-                // Centered title
-                screenwriter().draw_string_centered(100, "PONG GAME", 0xFF, 0xFF, 0xFF);
+                self.demo_ball.draw(self.faded(self.colors().ball));
+
+                // Centered title, its color drifting
+                let (r, g, b) = self.faded(title::cycle(self.colors().text, self.ticks));
+                screenwriter().draw_string_centered(100, text(Text::Title), r, g, b);
                 
-                // Centered menu options
-                screenwriter().draw_string_centered(130, "Press 1: 1 Player", 0xAA, 0xFF, 0xAA);
-                screenwriter().draw_string_centered(150, "Press 2: 2 Player", 0xAA, 0xAA, 0xFF);
+                // Centered menu options, sliding up into place as they fade in; the prompts to
+                // start a game pulse
+                let launcher_line = launcher::menu_line();
+                let options = [
+                    (text(Text::MenuOnePlayer), self.pulse((0xAA, 0xFF, 0xAA))),
+                    (text(Text::MenuTwoPlayer), self.pulse((0xAA, 0xAA, 0xFF))),
+                    (text(Text::MenuSettings), (0xFF, 0xFF, 0xAA)),
+                    (text(Text::MenuControls), (0xFF, 0xFF, 0xAA)),
+                    (text(Text::MenuProgram), (0xFF, 0xAA, 0xAA)),
+                    (text(Text::MenuNetwork), (0xAA, 0xFF, 0xFF)),
+                    (text(Text::MenuSerial), (0xAA, 0xFF, 0xFF)),
+                    (launcher_line.as_str(), (0xFF, 0xAA, 0xFF)),
+                    (text(Text::MenuProfiles), (0xFF, 0xFF, 0xAA)),
+                    (text(Text::MenuQuit), (0xAA, 0xAA, 0xAA)),
+                ];
+                let slide = self.menu_slide.value() as usize;
+                for (i, (option, color)) in options.into_iter().enumerate() {
+                    let (r, g, b) = self.faded(color);
+                    screenwriter().draw_string_centered(130 + i * 18 + slide, option, r, g, b);
+                }
                 
                 // Controls information
-                screenwriter().draw_string_centered(180, "Controls:", 0xFF, 0xFF, 0xFF);
-                screenwriter().draw_string_centered(200, "Player 1: W/S to move", 0xAA, 0xFF, 0xAA);
-                screenwriter().draw_string_centered(220, "Player 2: I/K to move", 0xAA, 0xAA, 0xFF);
+                let bindings = &self.settings.bindings;
+                let player1 = fill(Text::PlayerMoves,
+                    &[&1, &key_name(bindings.key(Action::Player1Up)), &key_name(bindings.key(Action::Player1Down))]);
+                let player2 = fill(Text::PlayerMoves,
+                    &[&2, &key_name(bindings.key(Action::Player2Up)), &key_name(bindings.key(Action::Player2Down))]);
+                screenwriter().draw_string_centered(310, text(Text::ControlsHeading), 0xFF, 0xFF, 0xFF);
+                screenwriter().draw_string_centered(330, &player1, 0xAA, 0xFF, 0xAA);
+                screenwriter().draw_string_centered(350, &player2, 0xAA, 0xAA, 0xFF);
+
+                if let Some(now) = self.settings.show_clock.then(|| *CLOCK.lock()).flatten() {
+                    let now = alloc::format!("{}", now);
+                    screenwriter().draw_string_centered(380, &now, 0xAA, 0xAA, 0xAA);
+                }
+            }
+            GameMode::Settings => {
+                self.settings.draw();
+            }
+            GameMode::Controls => {
+                self.settings.bindings.draw();
+            }
+            GameMode::Sound => {
+                self.settings.sound.draw();
+            }
+            GameMode::Accessibility => {
+                self.settings.accessibility.draw();
+            }
+            GameMode::Profiles => {
+                self.profiles.draw();
+            }
+            GameMode::MemoryMap => {
+                memory_map::draw();
+            }
+            GameMode::NetworkLobby => {
+                netplay::draw_lobby(self);
+            }
+            GameMode::Benchmark => {
+                if let Some(benchmark) = &self.benchmark {
+                    benchmark.draw();
+                }
+            }
+            GameMode::LatencyTest => {
+                if let Some(test) = &self.latency {
+                    test.draw();
+                }
+            }
+            GameMode::Launched => {
+                if let Some(game) = &self.launched {
+                    game.draw();
+                }
             }
             GameMode::GameOver => {
-                let winner = if self.player1_score > self.player2_score {
-                    "Player 1 Wins!"
-                } else {
-                    "Player 2 Wins!"
-                };
-                screenwriter().draw_string_centered(100, winner, 0xFF, 0xFF, 0xFF);
-                screenwriter().draw_string_centered(130, "Press P to play again", 0xFF, 0xFF, 0xFF);
-                screenwriter().draw_string_centered(150, "Press R to return to menu", 0xFF, 0xFF, 0xFF);
+                let winner = self.game.scoreboard.leader();
+                let winner = fill(Text::PlayerWins, &[&winner]);
+                let (r, g, b) = self.faded(self.colors().text);
+                screenwriter().draw_string_centered(100, &winner, r, g, b);
+                let (r, g, b) = self.faded((0xFF, 0xFF, 0xFF));
+                screenwriter().draw_string_centered(130, text(Text::PlayAgain), r, g, b);
+                screenwriter().draw_string_centered(150, text(Text::ReturnToMenu), r, g, b);
+                self.high_scores.draw(190, self.new_high_score);
             }
             _ => {
                 self.draw_game();
             }
         }
-    }
 
-    pub fn draw_game(&self) {
-        // Draw paddles
-        for y in 0..self.paddle_height {
-            screenwriter().draw_pixel(10, self.player1_y + y, 0xFF, 0xFF, 0xFF);
-            screenwriter().draw_pixel(self.width - 10, self.player2_y + y, 0xFF, 0xFF, 0xFF);
+        if self.frame_step.is_on() {
+            self.frame_step.draw(&self.game);
         }
-
-        // Draw ball (larger for better visibility)
-        let ball_size = 6; // Radius of 2 pixels (total 5x5)
-        for dy in -ball_size..=ball_size {
-            for dx in -ball_size..=ball_size {
-                screenwriter().draw_pixel(
-                    (self.ball_x as isize + dx) as usize,
-                    (self.ball_y as isize + dy) as usize,
-                    0xFF, 0xFF, 0xFF
-                );
-            }
+        if self.show_stats {
+            self.draw_stats();
         }
-
-        // Draw scores
-        let score_text = alloc::format!("{} - {}", self.player1_score, self.player2_score);
-        screenwriter().draw_string_centered(20, &score_text, 0xFF, 0xFF, 0xFF);
     }
 
-    pub fn update(&mut self) {
-        if self.game_mode != GameMode::OnePlayer && self.game_mode != GameMode::TwoPlayer {
-            return;
+    /// Debug overlay with interrupt counts and heap usage, toggled with F3.
+    fn draw_stats(&self) {
+        let mut y = 10;
+        for (vector, name, count) in interrupts::interrupt_stats() {
+            let line = alloc::format!("{:#04x} {}: {}", vector, name, count);
+            screenwriter().draw_string(10, y, &line, 0x55, 0xFF, 0x55);
+            y += 16;
         }
+        let missed = alloc::format!("missed ticks: {}", interrupts::missed_ticks());
+        screenwriter().draw_string(10, y, &missed, 0x55, 0xFF, 0x55);
+        y += 16;
+        let cpu = alloc::format!("cpu: {}% busy", kernel::idle::busy_percent());
+        screenwriter().draw_string(10, y, &cpu, 0x55, 0xFF, 0x55);
 
-        // Increase ball speed
-        self.ball_x = (self.ball_x as isize + self.ball_dx * 36) as usize;
-        self.ball_y = (self.ball_y as isize + self.ball_dy * 36) as usize;
-
-        // Ball collision with top/bottom
-        if self.ball_y <= 1 || self.ball_y >= self.height - 2 {
-            self.ball_dy = -self.ball_dy;
+        let heap = allocator::stats();
+        let heap_lines = [
+            alloc::format!("heap used: {} B, free: {} B", heap.used, heap.free),
+            alloc::format!("heap peak: {} B", heap.peak),
+            alloc::format!("allocs: {}, frees: {}", heap.allocations, heap.deallocations),
+        ];
+        for line in heap_lines {
+            y += 16;
+            screenwriter().draw_string(10, y, &line, 0x55, 0xFF, 0x55);
+        }
+        for phase in Phase::ALL {
+            y += 16;
+            let line = alloc::format!("{}", profiler::stats(phase));
+            screenwriter().draw_string(10, y, &line, 0x55, 0xFF, 0x55);
         }
+    }
 
-        // Ball collision with paddles - with explicit type annotations
-        let paddle_hit = |paddle_x: usize, paddle_y: usize| -> bool {
-            self.ball_x >= paddle_x.saturating_sub(3) &&
-            self.ball_x <= paddle_x + 3 &&
-            self.ball_y >= paddle_y &&
-            self.ball_y <= paddle_y + self.paddle_height
+    pub fn draw_game(&self) {
+        let mut writer = screenwriter();
+        let (colors, accessibility) = (self.colors(), &self.settings.accessibility);
+        let ball_color = if self.rainbow_ball && !accessibility.reduced_flash {
+            RAINBOW[(self.ticks % RAINBOW.len() as u64) as usize]
+        } else {
+            colors.ball
         };
-
-        // Player 1 paddle (left)
-        if paddle_hit(10, self.player1_y) {
-            self.ball_dx = self.ball_dx.abs(); // Ensure ball moves right
+        // The training overlay: the ball's path to the next paddle, behind everything else
+        let replaying = self.instant_replay.frame();
+        if self.settings.training_overlay && self.game.is_playing() && replaying.is_none() {
+            let (r, g, b) = tween::mix((0, 0, 0), ball_color, TRAINING_SHADE);
+            for segment in self.game.trajectory().points().windows(2) {
+                let ((x0, y0), (x1, y1)) = (segment[0], segment[1]);
+                // Spaced along the longer of the two, as there is no square root without std
+                let dots = ((x1 - x0).abs().max((y1 - y0).abs()) / TRAINING_DOT_SPACING) as usize;
+                for dot in 0..=dots {
+                    let t = dot as f32 / dots.max(1) as f32;
+                    writer.draw_pixel((x0 + (x1 - x0) * t) as usize, (y0 + (y1 - y0) * t) as usize, r, g, b);
+                }
+            }
         }
-        
-        // Player 2 paddle (right)
-        if paddle_hit(self.width - 10, self.player2_y) {
-            self.ball_dx = -self.ball_dx.abs(); // Ensure ball moves left
+        for entity in replaying.unwrap_or(self.game.entities()) {
+            match entity.kind {
+                // A line down from its top, thickened away from the field if the paddles are drawn
+                // bigger, so the side the ball bounces off stays where it is
+                Kind::Paddle(player) => {
+                    let (r, g, b) = colors.paddles;
+                    for i in 0..accessibility.paddle_width() {
+                        let x = if player == 1 { (entity.x as usize).saturating_sub(i) } else { entity.x as usize + i };
+                        for y in 0..entity.height as usize {
+                            writer.draw_pixel(x, entity.y as usize + y, r, g, b);
+                        }
+                    }
+                }
+                // A square around its center
+                Kind::Ball => {
+                    let (r, g, b) = ball_color;
+                    let radius = accessibility.ball_radius(entity.width as usize / 2) as isize;
+                    for dy in -radius..=radius {
+                        for dx in -radius..=radius {
+                            writer.draw_pixel((entity.x as isize + dx) as usize, (entity.y as isize + dy) as usize, r, g, b);
+                        }
+                    }
+                }
+            }
         }
 
-        // Scoring
-        if self.ball_x <= 0 {
-            self.player2_score += 1;
-            self.reset();
-        } else if self.ball_x >= self.width {
-            self.player1_score += 1;
-            self.reset();
+        // Scores, with the rally and the time played either side; the score stands out for a
+        // moment after a point
+        let score_text = alloc::format!("{} - {}", self.game.scoreboard.points(1), self.game.scoreboard.points(2));
+        let rally = fill(Text::Rally, &[&self.game.rally]);
+        let seconds = self.game.match_ticks / tick_hz();
+        let clock = alloc::format!("{}:{:02}", seconds / 60, seconds % 60);
+        let (r, g, b) = colors.text;
+        writer.draw_hud(&rally, "", &clock, r, g, b);
+        if replaying.is_some() {
+            let (r, g, b) = self.pulse(SCORE_POP_COLOR);
+            let y = writer.height() / 24 + 20;
+            writer.draw_string_centered(y, text(Text::InstantReplay), r, g, b);
         }
+        let pop = if accessibility.reduced_flash { 0.0 } else { self.score_pop.value() };
+        let (r, g, b) = tween::mix(colors.text, SCORE_POP_COLOR, pop);
+        writer.draw_hud("", &score_text, "", r, g, b);
+        drop(writer);
+        self.quick_chat.draw(&self.game, colors.text);
+    }
 
-        // Game over condition
-        if self.player1_score >= 1 || self.player2_score >= 1 {
-            self.game_mode = GameMode::GameOver;
+    pub fn update(&mut self) {
+        // The game holds still while the last point is shown again
+        if self.instant_replay.is_showing() {
+            self.instant_replay.advance();
+            return;
         }
+        if self.game.is_playing() {
+            self.instant_replay.record(&self.game);
+        } else {
+            self.instant_replay.clear();
+            self.quick_chat.clear();
+        }
+        let events = self.game.update(rng::u32);
+        let (ball, paddle1, paddle2) = (self.game.ball(), self.game.paddle(1), self.game.paddle(2));
+        kernel::kassert!(ball.x.is_finite() && ball.y.is_finite(), "ball at {}, {}", ball.x, ball.y);
+        let field = self.game.height as f32;
+        kernel::kassert!(paddle1.y + paddle1.height <= field && paddle2.y + paddle2.height <= field,
+            "paddles at {} and {}, field {} high", paddle1.y, paddle2.y, self.game.height);
+        bus::publish(self, events);
 
-        // Improved AI for single player
-        if self.game_mode == GameMode::OnePlayer {
-            let target_y = self.ball_y.saturating_sub(self.paddle_height / 2);
-            let ai_paddle_center = self.player2_y + self.paddle_height / 2;
-            
-            if ai_paddle_center < target_y {
-                self.move_paddle(false, false);
-            } else if ai_paddle_center > target_y {
-                self.move_paddle(false, true);
+        // The computer player, as the personality picked in the settings plays
+        if self.game.game_mode == GameMode::OnePlayer {
+            if self.ai.personality() != self.settings.personality {
+                self.ai = Controller::new(self.settings.personality);
             }
+            let (target_y, ai_paddle_center) = self.ai.strategy().aim(&self.game);
+
+            // With a second core the decision is made there, and applied one frame later; but not
+            // in a recording, which must come out the same however the cores are timed
+            let direction = if kernel::smp::online() > 0 && self.replay.is_none() {
+                use core::sync::atomic::Ordering;
+                AI_INPUT.store((target_y as u64) << 32 | ai_paddle_center as u64, Ordering::Relaxed);
+                kernel::smp::run_on_ap(ai_job);
+                Direction::from_u8(AI_DIRECTION.load(Ordering::Relaxed))
+            } else {
+                ai_direction(target_y, ai_paddle_center)
+            };
+            self.game.move_ai(direction);
         }
     }
+}
 
-    pub fn move_paddle(&mut self, is_player1: bool, up: bool) {
-        let paddle_y = if is_player1 {
-            &mut self.player1_y
-        } else {
-            &mut self.player2_y
-        };
+/// Target and paddle center for [ai_job], packed as target << 32 | center.
+static AI_INPUT: core::sync::atomic::AtomicU64 = core::sync::atomic::AtomicU64::new(0);
+/// [ai_job]'s latest decision, as a Direction discriminant.
+static AI_DIRECTION: core::sync::atomic::AtomicU8 = core::sync::atomic::AtomicU8::new(Direction::Stay as u8);
 
-        // Increase paddle movement speed
-        let step = 25;
-        
-        if up {
-            *paddle_y = paddle_y.saturating_sub(step);
-        } else {
-            *paddle_y = (*paddle_y + step).min(self.height - self.paddle_height);
-        }
-    }
+// Runs on an application processor
+fn ai_job() {
+    use core::sync::atomic::Ordering;
+    let input = AI_INPUT.load(Ordering::Relaxed);
+    let direction = ai_direction((input >> 32) as usize, input as u32 as usize);
+    AI_DIRECTION.store(direction as u8, Ordering::Relaxed);
 }
 
-// Simple pseudo-random number generator
-fn fast_rand() -> u32 {
-    use core::sync::atomic::{AtomicU32, Ordering};
-    static SEED: AtomicU32 = AtomicU32::new(123456789);
-    let mut x = SEED.load(Ordering::Relaxed);
-    x ^= x << 13;
-    x ^= x >> 17;
-    x ^= x << 5;
-    SEED.store(x, Ordering::Relaxed);
-    x
+/// The `tick_hz` boot option, or [interrupts::DEFAULT_TICK_HZ], the rate ball and paddle speeds
+/// are tuned for. Everything moves per tick, so a faster rate makes a faster game.
+fn tick_hz() -> u32 {
+    kernel::config::value("tick_hz").filter(|hz| (10..=1000).contains(hz)).unwrap_or(interrupts::DEFAULT_TICK_HZ)
 }
 
+/// The `timer_hz` boot option, or twice the tick rate: how often the timer wakes the game up to
+/// see whether a tick or a frame is due (see [frame_limiter]). It does not change the game's pace.
+fn timer_hz() -> u32 {
+    kernel::config::value("timer_hz").filter(|hz| (10..=1000).contains(hz)).unwrap_or((2 * tick_hz()).min(1000))
+}
+
+/// The game. No interrupt handler takes it: input is queued ([INPUT]) and the tick deferred, so a
+/// plain spin lock does, and the whole frame runs with interrupts on and input still serviced.
 static PONG: spin::Mutex<Pong> = spin::Mutex::new(Pong::new(0, 0));
 
 fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
+    #[cfg(test)]
+    kernel::qemu::set_exit_on_panic(true);
+    kernel::cpu::init();
+    kernel::fpu::init();
+    kernel::machine_check::init();
     writeln!(serial(), "Entered kernel with boot info: {boot_info:?}").unwrap();
-    writeln!(serial(), "Frame Buffer: {:p}", boot_info.framebuffer.as_ref().unwrap().buffer()).unwrap();
+    match boot_info.framebuffer.as_mut() {
+        Some(framebuffer) => {
+            writeln!(serial(), "Frame Buffer: {:p}", framebuffer.buffer()).unwrap();
+            screen::init(framebuffer);
+        }
+        // Without one, play in the VGA text buffer
+        None => {
+            writeln!(serial(), "No framebuffer, falling back to VGA text mode").unwrap();
+            screen::init_text(*boot_info.physical_memory_offset.as_ref().expect("Failed to find physical memory offset"));
+        }
+    }
+    let (width, height) = {
+        let writer = screenwriter();
+        (writer.width(), writer.height())
+    };
 
-    let frame_info = boot_info.framebuffer.as_ref().unwrap().info();
-    let framebuffer = boot_info.framebuffer.as_mut().unwrap();
-    screen::init(framebuffer);
-    
     // Initialize Pong game with screen dimensions
     {
         let mut pong = PONG.lock();
-        pong.width = frame_info.width as usize;
-        pong.height = frame_info.height as usize;
+        pong.game.resize(width, height, rng::u32);
+        pong.sequences.register(Cheat::RainbowBall, &KONAMI_CODE, Duration::from_secs(1));
     }
 
-    for x in 0..frame_info.width {
-        screenwriter().draw_pixel(x, frame_info.height-15, 0xff, 0, 0);
-        screenwriter().draw_pixel(x, frame_info.height-10, 0, 0xff, 0);
-        screenwriter().draw_pixel(x, frame_info.height-5, 0, 0, 0xff);
+    {
+        let mut writer = screenwriter();
+        for x in 0..width {
+            writer.draw_pixel(x, height-15, 0xff, 0, 0);
+            writer.draw_pixel(x, height-10, 0, 0xff, 0);
+            writer.draw_pixel(x, height-5, 0, 0, 0xff);
+        }
     }
 
     for r in boot_info.memory_regions.iter() {
@@ -273,13 +634,90 @@ fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
     let cr3_page = unsafe { slice::from_raw_parts_mut((cr3 + physical_offset) as *mut usize, 6) };
     writeln!(serial(), "CR3 Page table virtual address {cr3_page:#p}").unwrap();
 
-    allocator::init_heap((physical_offset + usable_region.start) as usize);
+    // The heap takes the first HEAP_SIZE bytes of usable memory, wherever they are. Everything
+    // else goes to the buddy allocator, which also backs the frame allocator.
+    memory_map::record(&boot_info.memory_regions);
+    memory_map::mark(boot_info.kernel_addr..boot_info.kernel_addr + boot_info.kernel_len, memory_map::Usage::Kernel);
+    let mut heap_needed = allocator::HEAP_SIZE as u64;
+    // The other CPUs start in real mode, so they need a page below 1 MiB to start from
+    let mut smp_trampoline = None;
+    for mut region in frame_allocator::usable_regions(&boot_info.memory_regions) {
+        let page = region.start.max(0x1000).next_multiple_of(0x1000);
+        if smp_trampoline.is_none() && page + 0x1000 <= region.end.min(0x10_0000) {
+            smp_trampoline = Some(page);
+            region.start = page + 0x1000;
+        }
+        let heap_end = (region.start + heap_needed).min(region.end);
+        if heap_end > region.start {
+            memory_map::mark(region.start..heap_end, memory_map::Usage::Heap);
+            allocator::init_heap((physical_offset + region.start) as usize, (heap_end - region.start) as usize);
+            heap_needed -= heap_end - region.start;
+        }
+        kernel::buddy::BUDDY.lock().add_region(physical_offset, heap_end..region.end);
+    }
+    allocator::enable_growth(physical_offset, allocator::HEAP_LIMIT);
+    allocator::set_low_memory_handler(low_memory);
+    allocator::set_oom_display(show_oom);
+    writeln!(serial(), "Heap: {} KiB, free pages: {} KiB", allocator::stats().free / 1024, kernel::buddy::BUDDY.lock().free_bytes() / 1024).unwrap();
+
+    // Boot options and artwork come as the ramdisk, which the bootloader has mapped
+    if let Some(&address) = boot_info.ramdisk_addr.as_ref() {
+        kernel::assets::init(unsafe { slice::from_raw_parts(address as *const u8, boot_info.ramdisk_len as usize) });
+        kernel::config::init(kernel::assets::get(kernel::assets::CONFIG).unwrap_or(&[]));
+        theme::load_assets();
+    }
+    {
+        let mut pong = PONG.lock();
+        // Headless, nobody looks at the display: play in the serial console's terminal
+        if kernel::config::flag("headless").unwrap_or(cfg!(feature = "headless")) {
+            screen::init_serial();
+            let (width, height) = {
+                let writer = screenwriter();
+                (writer.width(), writer.height())
+            };
+            pong.game.resize(width, height, rng::u32);
+        }
+        if let Some(name) = kernel::config::get("ai") {
+            match Difficulty::from_name(name) {
+                Some(difficulty) => pong.game.difficulty = difficulty,
+                None => writeln!(serial(), "config: unknown ai {}, expected easy, normal or hard", name).unwrap(),
+            }
+        }
+        if let Some(name) = kernel::config::get("theme") {
+            match theme::named(name) {
+                Some(theme) => pong.theme = theme,
+                None => writeln!(serial(), "config: unknown theme {}", name).unwrap(),
+            }
+        }
+    }
+
+    if kernel::config::flag("memtest").unwrap_or(false) {
+        memtest::run(physical_offset);
+    }
 
     let rsdp = boot_info.rsdp_addr.take();
-    let mut mapper = frame_allocator::init(VirtAddr::new(physical_offset));
-    let mut frame_allocator = BootInfoFrameAllocator::new(&boot_info.memory_regions);
+    kernel::memory::init(VirtAddr::new(physical_offset));
+    // The heap lives in the bootloader's physical memory map, which already uses huge pages
+    if let Some((_, page_size)) = kernel::memory::translate(VirtAddr::new(physical_offset)) {
+        writeln!(serial(), "Physical memory map uses {} KiB pages", page_size / 1024).unwrap();
+    }
+    // W^X for the kernel image, and nothing in the physical memory map (the heap included) runs
+    let kernel_image = unsafe { slice::from_raw_parts((physical_offset + boot_info.kernel_addr) as *const u8, boot_info.kernel_len as usize) };
+    kernel::memory::protect_kernel(kernel_image, boot_info.kernel_image_offset);
+    kernel::symbols::init(kernel_image, boot_info.kernel_image_offset);
+    let memory_end = frame_allocator::usable_regions(&boot_info.memory_regions).map(|region| region.end).max().unwrap_or(0);
+    kernel::memory::protect(VirtAddr::new(physical_offset), memory_end, true, false);
+    if let Some(range) = screen::physical_range() {
+        memory_map::mark(range, memory_map::Usage::Framebuffer);
+    }
+    screen::remap_framebuffer();
+    let mut frame_allocator = frame_allocator::BootInfoFrameAllocator::new();
     
     gdt::init();
+    install_stack_guard();
+    kernel::page_fault::set_display(show_page_fault);
+    kernel::crash::set_display(show_crash);
+    kernel::set_panic_display(show_panic);
 
     let x = Box::new(42);
     let y = Box::new(24);
@@ -289,62 +727,778 @@ fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
     
     writeln!(serial(), "Starting kernel...").unwrap();
 
-    let lapic_ptr = interrupts::init_apic(rsdp.expect("Failed to get RSDP address") as usize, physical_offset, &mut mapper, &mut frame_allocator);
+    kernel::time::init();
+    let now = kernel::rtc::now();
+    writeln!(serial(), "RTC: {}", now).unwrap();
+    kernel::rng::init();
+
+    kernel::acpi_tables::init(rsdp.expect("Failed to get RSDP address") as usize, VirtAddr::new(physical_offset));
+    let lapic_ptr = interrupts::init_apic();
+    kernel::xhci::init(physical_offset, &mut frame_allocator);
+    audio::init();
+    if kernel::virtio_net::init() {
+        kernel::net::init();
+    }
+    kernel::virtio_console::init();
+    if kernel::link::init() && kernel::config::flag("spectate").unwrap_or(false) {
+        spectator::set(true);
+    }
+    if kernel::block::init() {
+        kernel::fat32::init();
+    }
+    // A virtio-gpu takes over the display at the size the host prefers
+    if kernel::virtio_gpu::init() {
+        let (width, height) = kernel::virtio_gpu::native_mode().unwrap_or((0, 0));
+        if screen::set_resolution(width as usize, height as usize) {
+            PONG.lock().fit_screen();
+        }
+    }
+    // A logical resolution, e.g. logical_size=640x400, is scaled up to whatever the display is
+    if let Some(size) = kernel::config::get("logical_size") {
+        let parsed = size.split_once('x').and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)));
+        match parsed {
+            Some((width, height)) if screen::set_logical_resolution(width, height) => PONG.lock().fit_screen(),
+            _ => writeln!(serial(), "config: cannot draw at logical_size={}", size).unwrap(),
+        }
+    }
+    // The game's own tests run on the booted kernel, before the game starts
+    #[cfg(test)]
+    test_main();
+    if let Some(trampoline) = smp_trampoline {
+        kernel::smp::start_aps(PhysAddr::new(trampoline));
+    }
     HandlerTable::new()
         .keyboard(key)
+        .mouse(mouse)
+        .serial(serial_key)
+        .gamepad(gamepad)
+        .bridge(bridge)
         .timer(tick)
+        .tick_hz(timer_hz())
         .startup(start)
+        .cpu_loop(kernel::executor::run)
         .start(lapic_ptr)
 }
 
+/// Makes sure the page below the kernel stack is unmapped, so an overflow faults on it instead of
+/// silently running into whatever lies below, and registers it for the fault handlers.
+fn install_stack_guard() {
+    let stack_pointer: u64;
+    unsafe { core::arch::asm!("mov {}, rsp", out(reg) stack_pointer) };
+
+    // Walk down from the current page to the end of the mapped stack
+    let stack_pages = BOOTLOADER_CONFIG.kernel_stack_size / 4096;
+    let mut bottom = Page::<Size4KiB>::containing_address(VirtAddr::new(stack_pointer));
+    for _ in 0..stack_pages {
+        if !kernel::memory::is_mapped((bottom - 1).start_address()) {
+            break;
+        }
+        bottom -= 1;
+    }
+
+    let guard = bottom - 1;
+    kernel::memory::unmap_region(guard.start_address(), 4096);
+    interrupts::set_stack_guard(guard.start_address());
+    writeln!(serial(), "Kernel stack guard page at {:?}", guard.start_address()).unwrap();
+}
+
+/// Puts fatal page faults on screen; the full report goes to serial.
+fn show_page_fault(fault: &kernel::page_fault::PageFault) {
+    let _ = writeln!(Writer, "{}", fault);
+    let _ = writeln!(Writer, "error code: {:?}", fault.error_code);
+}
+
+/// Full-screen "blue screen" for fatal exceptions. Formats on the stack: the crash may have hit
+/// inside the allocator.
+fn show_crash(crash: &kernel::crash::Crash) {
+    let mut writer = unsafe { screen::take_over() };
+    writer.clear_screen(0x00, 0x00, 0xAA);
+    let title = FixedString::<256>::format(format_args!("EXCEPTION: {}", crash.name()));
+    writer.draw_string_centered(40, title.as_str(), 0xFF, 0xFF, 0xFF);
+    let detail = FixedString::<256>::format(format_args!("vector {}, error code {:#x}", crash.registers.vector, crash.registers.error_code));
+    writer.draw_string_centered(60, detail.as_str(), 0xFF, 0xFF, 0xFF);
+
+    let mut y = 100;
+    for [(a, a_value), (b, b_value)] in crash.lines() {
+        let line = FixedString::<256>::format(format_args!("{} {:#018x}    {} {:#018x}", a, a_value, b, b_value));
+        writer.draw_string_centered(y, line.as_str(), 0xFF, 0xFF, 0xFF);
+        y += 20;
+    }
+    writer.draw_string_centered(y + 20, "The system has been halted.", 0xAA, 0xAA, 0xFF);
+    drop(writer);
+    screen::present();
+}
+
+/// Shows why the kernel panicked, for when no serial console is attached.
+fn show_panic(info: &core::panic::PanicInfo) {
+    let mut writer = unsafe { screen::take_over() };
+    writer.clear_screen(0xAA, 0x00, 0x00);
+    writer.draw_string_centered(40, "KERNEL PANIC", 0xFF, 0xFF, 0xFF);
+
+    let mut y = 80;
+    if let Some(location) = info.location() {
+        let location = FixedString::<256>::format(format_args!("at {}:{}:{}", location.file(), location.line(), location.column()));
+        y = writer.draw_string_wrapped(20, y, location.as_str(), 0xFF, 0xFF, 0xAA);
+    }
+    let message = FixedString::<256>::format(format_args!("{}", info.message()));
+    y = writer.draw_string_wrapped(20, y + 10, message.as_str(), 0xFF, 0xFF, 0xFF);
+    writer.draw_string_wrapped(20, y + 20, "The system has been halted. Restart the machine to play again.", 0xFF, 0xAA, 0xAA);
+    drop(writer);
+    screen::present();
+}
+
 fn start() {
     writeln!(Writer, "Hello, world!").unwrap();
+    kernel::syscall::set_screen(kernel::syscall::Screen {
+        size: || {
+            let writer = screenwriter();
+            (writer.width(), writer.height())
+        },
+        fill_rect: program_fill_rect,
+        draw_text: |x, y, text, color| screenwriter().draw_string(x, y, text, (color >> 16) as u8, (color >> 8) as u8, color as u8),
+    });
+    // Sent once the keyboard is found
+    if let (Some(delay), Some(rate)) = (kernel::config::value("key_delay"), kernel::config::value("key_rate")) {
+        keyboard::set_typematic(delay, rate);
+    }
+    // Read before PONG is taken: a replay and the records come from the disk
+    let replay = replay::from_config();
+    let profiles = profiles::load();
+    {
+        let mut pong = PONG.lock();
+        saved::load(&mut pong);
+        saved::init();
+        pong.profiles = profiles;
+        if let Some(mut session) = replay {
+            session.begin(&mut pong);
+            pong.replay = Some(session);
+        }
+        if kernel::config::flag("benchmark").unwrap_or(false) {
+            benchmark::start(&mut pong);
+        }
+        speaker_tune(&pong);
+    }
     PONG.lock().draw();
+    screen::present();
+    kernel::scheduler::spawn(stats_logger);
+    kernel::executor::spawn(update_clock());
+    kernel::watchdog::enable(timer_hz() * WATCHDOG_SECS, Some(watchdog_bite));
+    // Headless, the serial console is the keyboard
+    if kernel::config::flag("serial_shell").unwrap_or(!screen::is_serial()) {
+        shell::init();
+    }
+}
+
+/// The userspace Pong, started from the menu.
+static PONG_PROGRAM: &[u8] = include_bytes!(env!("CARGO_BIN_FILE_USER_pong"));
+
+fn program_fill_rect(x: usize, y: usize, width: usize, height: usize, color: u32) {
+    let mut writer = screenwriter();
+    let (r, g, b) = ((color >> 16) as u8, (color >> 8) as u8, color as u8);
+    // The program's numbers, clipped to the screen
+    let (right, bottom) = (x.saturating_add(width).min(writer.width()), y.saturating_add(height).min(writer.height()));
+    for y in y..bottom {
+        for x in x..right {
+            writer.draw_pixel(x, y, r, g, b);
+        }
+    }
+}
+
+/// Wall clock shown on the menu. Reading the RTC takes several slow port accesses, so it is
+/// refreshed once a second rather than every frame.
+static CLOCK: spin::Mutex<Option<kernel::rtc::DateTime>> = spin::Mutex::new(None);
+
+async fn update_clock() {
+    loop {
+        *CLOCK.lock() = Some(kernel::rtc::now());
+        kernel::executor::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+const WATCHDOG_SECS: u32 = 2;
+
+/// Set by the shell, or by the watchdog; the next tick returns to the menu with a fresh game.
+static RESET_REQUESTED: core::sync::atomic::AtomicBool = core::sync::atomic::AtomicBool::new(false);
+
+/// Runs each time another [WATCHDOG_SECS] pass without a frame, in the timer interrupt; `bites`
+/// counts them. With PONG free, the stall is elsewhere (a slow disk write, a backlog of deferred
+/// work), and the game is sent back to the menu for when the tick runs again. With PONG held, the
+/// frame itself is stuck: it gets one more timeout, then the kernel panics for the crash screen
+/// and the crash dump. Breaking the lock instead would let two users at the game at once.
+fn watchdog_bite(bites: u32) {
+    if !PONG.is_locked() {
+        if !RESET_REQUESTED.swap(true, core::sync::atomic::Ordering::Relaxed) {
+            writeln!(serial(), "watchdog: stalled outside the game, resetting it to the menu").unwrap();
+        }
+    } else if bites < 2 {
+        writeln!(serial(), "watchdog: the frame holds PONG, waiting {} more seconds", WATCHDOG_SECS).unwrap();
+    } else {
+        panic!("watchdog: PONG held with no frame for {} seconds", bites * WATCHDOG_SECS);
+    }
+}
+
+/// Heap usage when the heap ran out, or 0. Until usage is back under [LOW_MEMORY_RECOVERED]
+/// percent of it, the game keeps running minus the extras that allocate.
+static LOW_MEMORY: core::sync::atomic::AtomicUsize = core::sync::atomic::AtomicUsize::new(0);
+const LOW_MEMORY_RECOVERED: usize = 75;
+
+/// Puts out-of-memory reports on screen, once there is one.
+fn show_oom(text: &str) {
+    if screen::is_ready() {
+        let _ = Writer.write_str(text);
+    }
+}
+
+/// Low-memory handler. Nothing can be freed from inside the allocator without risking a deadlock
+/// on PONG, so this only flags the shortage; the next tick turns the stats overlay off.
+fn low_memory() {
+    LOW_MEMORY.store(allocator::stats().used.max(1), core::sync::atomic::Ordering::Relaxed);
+}
+
+/// Whether the heap is short, clearing the shortage once usage has dropped back far enough.
+fn is_low_memory() -> bool {
+    let short_at = LOW_MEMORY.load(core::sync::atomic::Ordering::Relaxed);
+    if short_at == 0 {
+        return false;
+    }
+    let used = allocator::stats().used;
+    if used >= short_at / 100 * LOW_MEMORY_RECOVERED {
+        return true;
+    }
+    // A shortage flagged since the load is not lost: only the value read is cleared
+    if LOW_MEMORY.compare_exchange(short_at, 0, core::sync::atomic::Ordering::Relaxed, core::sync::atomic::Ordering::Relaxed).is_ok() {
+        writeln!(serial(), "heap: {} KiB in use, down from {} KiB when it ran out", used / 1024, short_at / 1024).unwrap();
+    }
+    false
 }
 
+const STATS_PERIOD_SECS: u64 = 10;
+
+/// Thread that reports the achieved tick rate on the serial console every [STATS_PERIOD_SECS].
+fn stats_logger() {
+    loop {
+        kernel::scheduler::sleep(Duration::from_secs(STATS_PERIOD_SECS));
+        log_stats();
+    }
+}
+
+fn log_stats() {
+    use core::sync::atomic::{AtomicU64, Ordering};
+    static LAST_TICKS: AtomicU64 = AtomicU64::new(0);
+
+    let Some(pong) = PONG.try_lock() else { return };
+    let ticks = pong.ticks - LAST_TICKS.swap(pong.ticks, Ordering::Relaxed);
+    writeln!(serial(), "uptime {}s, {} ticks/s, {} missed, {}s idle", kernel::time::uptime().as_secs(), ticks / STATS_PERIOD_SECS, interrupts::missed_ticks(), kernel::idle::halted().as_secs()).unwrap();
+    for (vector, name, count) in interrupts::interrupt_stats() {
+        writeln!(serial(), "  {:#04x} {}: {}", vector, name, count).unwrap();
+    }
+}
+
+/// Input delivered by the interrupt handlers. Handlers only enqueue events; the game applies
+/// them at the start of the next tick, so no game logic or drawing runs in input interrupts.
+#[derive(Clone, Copy)]
+enum InputEvent {
+    Key(DecodedKey),
+    SerialKey(DecodedKey),
+    Mouse(MouseEvent),
+    Gamepad(GamepadState),
+    /// A controller bridge device, by number, and its new state.
+    Controller(u8, GamepadState),
+}
+
+static INPUT: SpscQueue<InputEvent, 64> = SpscQueue::new();
+
+/// The timer handler: runs the ticks due since the last time, then draws a frame if one is due.
 fn tick() {
     let mut pong = PONG.lock();
-    pong.update();
+    let frame_rate = pong.settings.frame_rate;
+    let (steps, draw) = pong.limiter.advance(kernel::time::uptime_ns(), tick_hz(), frame_rate);
+    if steps > 0 {
+        let _frame = profiler::scope(Phase::Frame);
+        let mut drawable = false;
+        for _ in 0..steps {
+            drawable = step(&mut pong);
+        }
+        if draw && drawable {
+            {
+                let _draw = profiler::scope(Phase::Draw);
+                pong.draw();
+            }
+            {
+                let _present = profiler::scope(Phase::Present);
+                screen::present();
+            }
+            latency::presented(&mut pong);
+        }
+    }
+    kernel::watchdog::pet();
+}
+
+/// One tick of the game: input, then the update. Returns whether the game's screen is there to
+/// draw; a paused assertion draws its own, and a program draws for itself.
+fn step(pong: &mut Pong) -> bool {
+    pong.ticks += 1;
+    let mode = pong.game.game_mode;
+
+    if RESET_REQUESTED.swap(false, core::sync::atomic::Ordering::Relaxed) {
+        pong.game.reset(rng::u32);
+        pong.game.game_mode = GameMode::Menu;
+    }
+    if is_low_memory() {
+        pong.show_stats = false;
+    }
+    // A failed kassert! holds the game until the player chooses what to do
+    if let Some(failure) = kernel::kassert::pending() {
+        assertion_paused(pong, &failure);
+        return false;
+    }
+
+    if pong.game.game_mode == GameMode::Program {
+        // The program draws for itself; it only needs its keys
+        while let Some(event) = INPUT.pop() {
+            if let InputEvent::Key(key) = event {
+                kernel::process::send_key(key);
+            }
+        }
+        if !kernel::process::is_running() {
+            pong.game.game_mode = GameMode::Menu;
+        } else {
+            return false;
+        }
+    }
+
+    {
+        let _input = profiler::scope(Phase::Input);
+        let mut replay = pong.replay.take();
+        match &mut replay {
+            // The recorded input stands in for the real one
+            Some(session) if session.is_replaying() => {
+                while INPUT.pop().is_some() {}
+                while let Some(event) = session.next_input(pong.ticks) {
+                    handle_input(pong, event);
+                }
+            }
+            session => {
+                while let Some(event) = INPUT.pop() {
+                    if let Some(session) = session {
+                        session.record(pong.ticks, &event);
+                    }
+                    handle_input(pong, event);
+                }
+            }
+        }
+        pong.replay = replay;
+
+        // The D-pad moves Player 1's paddle (this side's, in a network game) for as long as it is held
+        if pong.gamepad.up || pong.gamepad.down {
+            let up = pong.gamepad.up;
+            if pong.game.game_mode == GameMode::Network {
+                netplay::press(pong, up);
+            } else {
+                pong.game.move_paddle(true, up);
+            }
+        }
+        controllers::move_paddles(pong);
+    }
+
+    {
+        let _update = profiler::scope(Phase::Update);
+        // A network game only advances when the other side's input is in
+        netplay::tick(pong);
+        if pong.game.game_mode != GameMode::Network && pong.frame_step.take_tick(&pong.game) {
+            pong.update();
+        }
+        benchmark::update(pong);
+        launcher::update(pong);
+        screensaver::update(pong);
+        if pong.game.game_mode == GameMode::Menu {
+            let (width, height) = (pong.game.width, pong.game.height);
+            pong.demo_ball.step(width, height);
+        }
+        if let Some(mut session) = pong.replay.take()
+            && session.after_update(pong)
+        {
+            pong.replay = Some(session);
+        }
+    }
+    spectator::tick(pong, mode);
+    if pong.game.game_mode != mode {
+        eventlog::record(eventlog::Kind::Game, format_args!("{:?} -> {:?}", mode, pong.game.game_mode));
+        pong.enter_screen();
+        speaker_tune(pong);
+    }
+    pong.animate();
+    let playing = pong.game.is_playing();
+    match (playing, pong.music) {
+        (true, None) => pong.music = audio::play_melody(sounds().music, MUSIC_VOLUME, true),
+        (false, Some(music)) => {
+            audio::stop(music);
+            pong.music = None;
+        }
+        _ => {}
+    }
+    // Scroll Lock shows a game is on; Num Lock and Caps Lock flash for a point
+    let flash = pong.ticks < pong.led_flash_until;
+    let leds = Leds { scroll_lock: playing, num_lock: flash, caps_lock: flash };
+    if leds != pong.leds && keyboard::set_leds(leds) {
+        pong.leds = leds;
+    }
+    true
+}
+
+/// Starts the PC speaker's tune for the screen showing: the menu's, looped, or the jingle for the
+/// end of a game; or stops it on any other. Only with the music on, as the sound card's is.
+fn speaker_tune(pong: &Pong) {
+    match pong.game.game_mode {
+        GameMode::Menu if pong.settings.sound.music => {
+            speaker::play(sounds().menu_tune, true);
+        }
+        GameMode::GameOver if pong.settings.sound.music => {
+            speaker::play(sounds().game_over_jingle, false);
+        }
+        _ => speaker::stop(),
+    }
+}
+
+/// The melodies to play, read from the ramdisk the first time they are needed.
+fn sounds() -> &'static Sounds {
+    SOUNDS.call_once(|| {
+        let melody = |path: &str, built_in: &'static [Note]| -> &'static [Note] {
+            match kernel::assets::get(path).map(audio::parse_melody) {
+                Some(Some(notes)) => Box::leak(notes.into_boxed_slice()),
+                Some(_) => {
+                    writeln!(serial(), "assets: ignoring {}, expected frequency:ms words", path).unwrap();
+                    built_in
+                }
+                None => built_in,
+            }
+        };
+        Sounds {
+            music: melody("sounds/music", &MUSIC),
+            menu_tune: melody("sounds/menu", &MENU_TUNE),
+            game_over_jingle: melody("sounds/game_over", &GAME_OVER_JINGLE),
+        }
+    })
+}
+
+/// One tick paused on a failed assertion: shows it over the game, and takes C to carry on from
+/// where the game was or R to go back to the menu with a fresh game. Other input is dropped.
+fn assertion_paused(pong: &mut Pong, failure: &kernel::kassert::Failure) {
+    if let Some(music) = pong.music.take() {
+        audio::stop(music);
+    }
+    while let Some(event) = INPUT.pop() {
+        match event {
+            InputEvent::Key(DecodedKey::Unicode('c' | 'C')) => kernel::kassert::resolve(),
+            InputEvent::Key(DecodedKey::Unicode('r' | 'R')) => {
+                kernel::kassert::resolve();
+                pong.game.reset(rng::u32);
+                pong.game.game_mode = GameMode::Menu;
+            }
+            _ => {}
+        }
+    }
+
     pong.draw();
+    let (width, height) = {
+        let writer = screenwriter();
+        (writer.width(), writer.height())
+    };
+    let (left, top) = (20, 60);
+    program_fill_rect(left, top, width.saturating_sub(2 * left), height.saturating_sub(2 * top).min(200), 0x550000);
+    let mut writer = screenwriter();
+    writer.draw_string_centered(top + 10, "ASSERTION FAILED", 0xFF, 0xFF, 0xFF);
+    let location = alloc::format!("at {}:{}:{}", failure.file, failure.line, failure.column);
+    let mut y = writer.draw_string_wrapped(left + 10, top + 40, failure.condition, 0xFF, 0xFF, 0xAA);
+    y = writer.draw_string_wrapped(left + 10, y, &location, 0xFF, 0xFF, 0xFF);
+    if let Some(message) = &failure.message {
+        y = writer.draw_string_wrapped(left + 10, y, message, 0xFF, 0xFF, 0xFF);
+    }
+    writer.draw_string_wrapped(left + 10, y + 20, "Press C to continue, R to reset the game", 0xAA, 0xFF, 0xAA);
+    drop(writer);
+    screen::present();
 }
 
 fn key(key: DecodedKey) {
-    let mut pong = PONG.lock();
-    
+    eventlog::record(eventlog::Kind::Key, format_args!("{:?}", key));
+    latency::key_interrupt();
+    // Here rather than in handle_key, so that it works whatever has the keyboard
+    if key == DecodedKey::RawKey(KeyCode::F8) {
+        audio::toggle_mute();
+        return;
+    }
+    INPUT.push(InputEvent::Key(key));
+}
+
+fn mouse(event: MouseEvent) {
+    INPUT.push(InputEvent::Mouse(event));
+}
+
+fn serial_key(key: DecodedKey) {
+    eventlog::record(eventlog::Kind::Key, format_args!("serial {:?}", key));
+    INPUT.push(InputEvent::SerialKey(key));
+}
+
+fn gamepad(state: GamepadState) {
+    INPUT.push(InputEvent::Gamepad(state));
+}
+
+fn bridge(packet: Packet) {
+    if let Some(event) = controllers::input(packet) {
+        INPUT.push(event);
+    }
+}
+
+fn handle_input(pong: &mut Pong, event: InputEvent) {
+    // A gamepad may send the same report over and over while nothing is touched
+    let repeated = matches!(event, InputEvent::Gamepad(state) if state == pong.gamepad);
+    if !repeated && screensaver::wake(pong) {
+        match event {
+            InputEvent::Gamepad(state) => pong.gamepad = state,
+            InputEvent::Controller(device, state) => pong.controllers[device as usize] = state,
+            _ => {}
+        }
+        return;
+    }
+    match event {
+        InputEvent::Key(key) => handle_key(pong, key),
+        InputEvent::SerialKey(key) => handle_serial_key(pong, key),
+        InputEvent::Mouse(event) => handle_mouse(pong, event),
+        InputEvent::Gamepad(state) => handle_gamepad(pong, state),
+        InputEvent::Controller(device, state) => controllers::handle(pong, device, state),
+    }
+}
+
+fn handle_key(pong: &mut Pong, key: DecodedKey) {
+    if let Some(Cheat::RainbowBall) = pong.sequences.feed(key) {
+        pong.rainbow_ball = !pong.rainbow_ball;
+    }
+    if key == DecodedKey::RawKey(KeyCode::F3) {
+        // The overlay formats its lines on the heap every frame
+        pong.show_stats = !pong.show_stats && !is_low_memory();
+        return;
+    }
+    // Anywhere, even in the middle of a game
+    if key == DecodedKey::RawKey(KeyCode::F10) {
+        kernel::power::shutdown();
+    }
+    if key == DecodedKey::RawKey(KeyCode::F9) {
+        kernel::power::reboot();
+    }
+    // Not in a network game, which would go on without this side
+    if key == DecodedKey::RawKey(KeyCode::F7) && matches!(pong.game.game_mode, GameMode::OnePlayer | GameMode::TwoPlayer) {
+        pong.frame_step.toggle();
+        return;
+    }
+    if pong.frame_step.key(key) {
+        return;
+    }
+    if key == DecodedKey::RawKey(KeyCode::F4) && pong.game.game_mode == GameMode::Menu {
+        pong.game.game_mode = GameMode::MemoryMap;
+        return;
+    }
+    // Not on the menu: the benchmark is for developers
+    if key == DecodedKey::RawKey(KeyCode::F6) && pong.game.game_mode == GameMode::Menu {
+        benchmark::start(pong);
+        return;
+    }
+    if pong.game.game_mode == GameMode::Benchmark {
+        benchmark::stop(pong);
+        return;
+    }
+    // Hidden too, for measuring changes to the input and display paths
+    if key == DecodedKey::RawKey(KeyCode::F5) && pong.game.game_mode == GameMode::Menu {
+        latency::start(pong);
+        return;
+    }
+    if pong.game.game_mode == GameMode::LatencyTest {
+        latency::handle_key(pong, key);
+        return;
+    }
+    if pong.game.game_mode == GameMode::Launched {
+        launcher::handle_key(pong, key);
+        return;
+    }
+    if pong.game.game_mode == GameMode::Menu && launcher::launch(pong, key) {
+        return;
+    }
+
     match key {
-        DecodedKey::Unicode('1') if pong.game_mode == GameMode::Menu => {
-            pong.reset();
-            pong.game_mode = GameMode::OnePlayer;
+        DecodedKey::Unicode('1') if pong.game.game_mode == GameMode::Menu => {
+            pong.game.start(GameMode::OnePlayer, rng::u32);
+        }
+        DecodedKey::Unicode('2') if pong.game.game_mode == GameMode::Menu => {
+            pong.game.start(GameMode::TwoPlayer, rng::u32);
+        }
+        DecodedKey::Unicode('3') if pong.game.game_mode == GameMode::Menu => {
+            pong.game.game_mode = GameMode::Settings;
         }
-        DecodedKey::Unicode('2') if pong.game_mode == GameMode::Menu => {
-            pong.reset();
-            pong.game_mode = GameMode::TwoPlayer;
+        DecodedKey::Unicode('4') if pong.game.game_mode == GameMode::Menu => {
+            pong.game.game_mode = GameMode::Controls;
         }
-        DecodedKey::Unicode('r') if pong.game_mode == GameMode::GameOver => {
-            pong.player1_score = 0;
-            pong.player2_score = 0;
-            pong.game_mode = GameMode::Menu;
+        DecodedKey::Unicode('6') if pong.game.game_mode == GameMode::Menu => match netplay::Session::network(rng::u32()) {
+            Some(session) => {
+                pong.netplay = Some(session);
+                pong.game.game_mode = GameMode::NetworkLobby;
+            }
+            None => writeln!(serial(), "netplay: no network").unwrap(),
+        },
+        DecodedKey::Unicode('7') if pong.game.game_mode == GameMode::Menu => match netplay::Session::serial(rng::u32()) {
+            Some(session) => {
+                pong.netplay = Some(session);
+                pong.game.game_mode = GameMode::NetworkLobby;
+            }
+            None => writeln!(serial(), "netplay: no serial link on COM2").unwrap(),
+        },
+        DecodedKey::Unicode('q') if pong.game.game_mode == GameMode::Menu => kernel::power::shutdown(),
+        DecodedKey::Unicode('p') if pong.game.game_mode == GameMode::Menu => pong.game.game_mode = GameMode::Profiles,
+        key if pong.game.game_mode == GameMode::Profiles => {
+            if !pong.profiles.handle_key(key) {
+                pong.game.game_mode = GameMode::Menu;
+            }
+        }
+        DecodedKey::Unicode('5') if pong.game.game_mode == GameMode::Menu => match kernel::process::spawn(PONG_PROGRAM) {
+            Ok(_) => {
+                screenwriter().clear();
+                pong.game.game_mode = GameMode::Program;
+            }
+            Err(error) => writeln!(serial(), "cannot start Pong program: {:?}", error).unwrap(),
+        },
+        key if pong.game.game_mode == GameMode::Controls => {
+            if !pong.settings.bindings.handle_key(key) {
+                pong.game.game_mode = GameMode::Menu;
+            }
         }
+        key if pong.game.game_mode == GameMode::Sound => {
+            if !pong.settings.sound.handle_key(key) {
+                pong.game.game_mode = GameMode::Settings;
+                saved::save(pong);
+            }
+        }
+        key if pong.game.game_mode == GameMode::Accessibility => {
+            if !pong.settings.accessibility.handle_key(key) {
+                pong.game.game_mode = GameMode::Settings;
+                saved::save(pong);
+            }
+        }
+        DecodedKey::Unicode('\n') if pong.game.game_mode == GameMode::Settings && pong.settings.sound_selected() => {
+            pong.game.game_mode = GameMode::Sound;
+        }
+        DecodedKey::Unicode('\n') if pong.game.game_mode == GameMode::Settings && pong.settings.accessibility_selected() => {
+            pong.game.game_mode = GameMode::Accessibility;
+        }
+        DecodedKey::Unicode('w') if pong.game.game_mode == GameMode::Settings => pong.settings.select(true),
+        DecodedKey::Unicode('s') if pong.game.game_mode == GameMode::Settings => pong.settings.select(false),
+        DecodedKey::Unicode('a' | 'd') if pong.game.game_mode == GameMode::Settings => {
+            pong.settings.change(key == DecodedKey::Unicode('d'));
+            pong.fit_screen();
+            saved::save(pong);
+        }
+        DecodedKey::Unicode('r') if matches!(pong.game.game_mode, GameMode::Settings | GameMode::MemoryMap | GameMode::NetworkLobby) => {
+            pong.game.game_mode = GameMode::Menu;
+        }
+        DecodedKey::Unicode('r') if pong.game.game_mode == GameMode::GameOver => pong.game.quit(),
+        DecodedKey::Unicode('p') if pong.game.game_mode == GameMode::GameOver => pong.game.play_again(rng::u32),
+        // Paddle keys push the paddles, which speed up and slow down in Game::update
+        key => match pong.settings.bindings.action(key) {
+            // Either player's keys move this side's paddle
+            Some(Action::Player1Up | Action::Player2Up) if pong.game.game_mode == GameMode::Network => netplay::press(pong, true),
+            Some(Action::Player1Down | Action::Player2Down) if pong.game.game_mode == GameMode::Network => netplay::press(pong, false),
+            Some(Action::Player1Up) => pong.game.move_paddle(true, true),
+            Some(Action::Player1Down) => pong.game.move_paddle(true, false),
+            Some(Action::Player2Up) if pong.game.game_mode == GameMode::TwoPlayer => pong.game.move_paddle(false, true),
+            Some(Action::Player2Down) if pong.game.game_mode == GameMode::TwoPlayer => pong.game.move_paddle(false, false),
+            None if pong.game.game_mode == GameMode::TwoPlayer => {
+                pong.quick_chat.key(key);
+            }
+            _ => {}
+        },
+    }
+}
 
-        DecodedKey::Unicode('p') if pong.game_mode == GameMode::GameOver => {
-        // Keep current game mode
-        let last_mode = if pong.player1_score >= 1 {
-            GameMode::OnePlayer
-        } else {
-            GameMode::TwoPlayer
-        };
-    pong.reset();
-    pong.player1_score = 0;
-    pong.player2_score = 0;
-    pong.game_mode = last_mode;
-}
-        // Faster paddle movement (larger steps)
-        DecodedKey::Unicode('w') => pong.move_paddle(true, true),
-        DecodedKey::Unicode('s') => pong.move_paddle(true, false),
-        DecodedKey::Unicode('i') if pong.game_mode == GameMode::TwoPlayer => pong.move_paddle(false, true),
-        DecodedKey::Unicode('k') if pong.game_mode == GameMode::TwoPlayer => pong.move_paddle(false, false),
+fn handle_mouse(pong: &mut Pong, event: MouseEvent) {
+    // Not in a network game, where the paddle moves in steps both sides agree on
+    let playing = pong.game.game_mode == GameMode::OnePlayer || pong.game.game_mode == GameMode::TwoPlayer;
+    if playing && pong.settings.mouse_control {
+        let sensitivity = pong.settings.mouse_sensitivity;
+        pong.game.mouse_paddle(event.dy, sensitivity);
+    }
+}
+
+/// Keys typed on the serial console control Player 2, so a second person can play without a
+/// second keyboard. The terminal's arrow keys work as well as Player 2's bound keys.
+fn handle_serial_key(pong: &mut Pong, key: DecodedKey) {
+    // Headless, the terminal is the only keyboard there is
+    if screen::is_serial() {
+        handle_key(pong, key);
+        return;
+    }
+    if pong.game.game_mode != GameMode::TwoPlayer {
+        return;
+    }
+
+    match (key, pong.settings.bindings.action(key)) {
+        (DecodedKey::RawKey(KeyCode::ArrowUp), _) | (_, Some(Action::Player2Up)) => pong.game.move_paddle(false, true),
+        (DecodedKey::RawKey(KeyCode::ArrowDown), _) | (_, Some(Action::Player2Down)) => pong.game.move_paddle(false, false),
         _ => {}
     }
-    
-    pong.draw();
-}
\ No newline at end of file
+}
+
+/// The first gamepad button confirms (start a one player game, play again) and the second one
+/// goes back to the menu.
+fn handle_gamepad(pong: &mut Pong, state: GamepadState) {
+    let pressed = state.buttons & !pong.gamepad.buttons;
+    pong.gamepad = state;
+    press_gamepad_buttons(pong, pressed);
+}
+
+/// Acts on the gamepad buttons just pressed, bit n for button n + 1.
+fn press_gamepad_buttons(pong: &mut Pong, pressed: u16) {
+    if pressed & 0x1 != 0 {
+        let confirm = if pong.game.game_mode == GameMode::GameOver { 'p' } else { '1' };
+        handle_key(pong, DecodedKey::Unicode(confirm));
+    }
+    if pressed & 0x2 != 0 {
+        handle_key(pong, DecodedKey::Unicode('r'));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A game between the two paddles, at the usual size, with the serve made predictable.
+    pub(crate) fn game(mode: GameMode) -> Pong {
+        rng::seed(1);
+        let mut pong = Pong::new(640, 480);
+        pong.game.start(mode, rng::u32);
+        pong
+    }
+
+    #[test_case]
+    fn a_point_reaches_the_statistics_and_the_replay() {
+        // The rules are the game crate's to test; this is what Pong::update does with their events
+        let mut pong = game(GameMode::TwoPlayer);
+        pong.game.scoreboard.rules = game::scoreboard::Rules { points_to_win: 2, win_by: 1, games_to_win: 1 };
+        pong.game.paddle_mut(1).y = 200.0;
+        pong.game.ball_mut().x = 20.0;
+        pong.game.ball_mut().y = 225.0;
+        pong.game.ball_mut().dx = -10.0;
+        pong.game.ball_mut().dy = 0.0;
+        pong.update();
+        assert_eq!(pong.statistics.paddle_hits, 1);
+        assert!(!pong.instant_replay.is_showing());
+
+        pong.game.paddle_mut(1).y = 0.0;
+        pong.game.ball_mut().x = 5.0;
+        pong.game.ball_mut().y = 400.0;
+        pong.game.ball_mut().dx = -10.0;
+        pong.update();
+        assert_eq!((pong.statistics.points, pong.statistics.games), (1, 0));
+        assert!(pong.instant_replay.is_showing());
+        // The game holds still while the point is shown again
+        let ball = (pong.game.ball().x, pong.game.ball().y);
+        pong.update();
+        assert_eq!((pong.game.ball().x, pong.game.ball().y), ball);
+    }
+}
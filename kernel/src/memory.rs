@@ -0,0 +1,209 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::structures::paging::mapper::{MapToError, TranslateResult, UnmapError};
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageSize, PageTableFlags, PhysFrame, Size1GiB, Size2MiB, Size4KiB, Translate};
+use x86_64::{PhysAddr, VirtAddr};
+use crate::elf;
+use crate::frame_allocator::{self, BootInfoFrameAllocator};
+
+/// The active page tables. Only touched during setup and by drivers mapping their registers, never
+/// from interrupt handlers.
+static MAPPER: Mutex<Option<OffsetPageTable<'static>>> = Mutex::new(None);
+static PHYSICAL_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+/// Flags for device registers: writable and uncached.
+pub const MMIO: PageTableFlags = PageTableFlags::PRESENT.union(PageTableFlags::WRITABLE).union(PageTableFlags::NO_CACHE);
+
+/// Takes over the active page tables, reached through the physical memory mapping at
+/// `physical_offset`. Page table frames come from the buddy allocator, so it must be filled first.
+pub fn init(physical_offset: VirtAddr) {
+    PHYSICAL_OFFSET.store(physical_offset.as_u64(), Ordering::Relaxed);
+    *MAPPER.lock() = Some(frame_allocator::init(physical_offset));
+}
+
+/// Where physical memory at `phys` can be reached through the bootloader's physical memory map.
+pub fn phys_to_virt(phys: PhysAddr) -> VirtAddr {
+    VirtAddr::new(PHYSICAL_OFFSET.load(Ordering::Relaxed) + phys.as_u64())
+}
+
+fn with_mapper<R>(f: impl FnOnce(&mut OffsetPageTable<'static>) -> R) -> R {
+    let mut mapper = MAPPER.lock();
+    f(mapper.as_mut().expect("memory::init has not run"))
+}
+
+fn pages(start: u64, len: u64) -> impl Iterator<Item = Page<Size4KiB>> {
+    let first = Page::containing_address(VirtAddr::new(start));
+    let last = Page::containing_address(VirtAddr::new(start + len.max(1) - 1));
+    Page::range_inclusive(first, last)
+}
+
+/// Maps the `len` bytes of physical memory at `phys` with `flags` and returns the virtual address
+/// of `phys`. Like all device memory in this kernel the region is identity mapped; pages that are
+/// already mapped (registers shared between devices, or a repeated call) are left as they are.
+///
+/// Every 2 MiB aligned stretch of the region gets a single huge page, which keeps large regions
+/// such as the framebuffer down to a handful of TLB entries. The rest uses 4 KiB pages.
+pub fn map_region(phys: PhysAddr, len: u64, flags: PageTableFlags) -> VirtAddr {
+    let end = phys.as_u64() + len;
+    with_mapper(|mapper| {
+        let mut frame_allocator = BootInfoFrameAllocator::new();
+        let mut address = phys.align_down(Size4KiB::SIZE).as_u64();
+        while address < end {
+            if address.is_multiple_of(Size2MiB::SIZE) && address + Size2MiB::SIZE <= end {
+                let page = Page::<Size2MiB>::containing_address(VirtAddr::new(address));
+                let frame = PhysFrame::containing_address(PhysAddr::new(address));
+                match unsafe { mapper.map_to(page, frame, flags, &mut frame_allocator) } {
+                    Ok(flush) => {
+                        flush.flush();
+                        address += Size2MiB::SIZE;
+                        continue;
+                    }
+                    Err(MapToError::FrameAllocationFailed) => panic!("out of frames mapping {:?}", phys),
+                    // Part of the stretch is mapped with small pages already; fill in around them
+                    Err(MapToError::PageAlreadyMapped(_) | MapToError::ParentEntryHugePage) => {}
+                }
+            }
+
+            let page = Page::<Size4KiB>::containing_address(VirtAddr::new(address));
+            let frame = PhysFrame::containing_address(PhysAddr::new(address));
+            match unsafe { mapper.map_to(page, frame, flags, &mut frame_allocator) } {
+                Ok(flush) => flush.flush(),
+                Err(MapToError::FrameAllocationFailed) => panic!("out of frames mapping {:?}", phys),
+                Err(MapToError::PageAlreadyMapped(_) | MapToError::ParentEntryHugePage) => {}
+            }
+            address += Size4KiB::SIZE;
+        }
+    });
+    VirtAddr::new(phys.as_u64())
+}
+
+/// Unmaps the pages covering `len` bytes at `virt`; a huge page touched by the range is unmapped
+/// as a whole. The frames behind them are not freed: they are device memory, or belong to whoever
+/// set the mapping up.
+pub fn unmap_region(virt: VirtAddr, len: u64) {
+    with_mapper(|mapper| {
+        for page in pages(virt.as_u64(), len) {
+            match mapper.unmap(page) {
+                Ok((_, flush)) => flush.flush(),
+                Err(UnmapError::PageNotMapped) => {}
+                Err(UnmapError::ParentEntryHugePage) => {
+                    let huge = Page::<Size2MiB>::containing_address(page.start_address());
+                    if let Ok((_, flush)) = mapper.unmap(huge) {
+                        flush.flush();
+                    }
+                }
+                Err(error) => panic!("cannot unmap {:?}: {:?}", page, error),
+            }
+        }
+    });
+}
+
+/// Backs the `len` bytes at `virt` with fresh, zeroed frames mapped with `flags`. Fails if a page
+/// in the range is already mapped or memory runs out; pages mapped before that stay mapped, and
+/// are for the caller to [free_region].
+pub fn alloc_region(virt: VirtAddr, len: u64, flags: PageTableFlags) -> Result<(), MapToError<Size4KiB>> {
+    with_mapper(|mapper| {
+        let mut frame_allocator = BootInfoFrameAllocator::new();
+        for page in pages(virt.as_u64(), len) {
+            let frame = frame_allocator.allocate_frame().ok_or(MapToError::FrameAllocationFailed)?;
+            unsafe { phys_to_virt(frame.start_address()).as_mut_ptr::<u8>().write_bytes(0, Size4KiB::SIZE as usize) };
+            match unsafe { mapper.map_to(page, frame, flags, &mut frame_allocator) } {
+                Ok(flush) => flush.flush(),
+                Err(error) => {
+                    unsafe { frame_allocator.deallocate_frame(frame) };
+                    return Err(error);
+                }
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Unmaps the pages covering `len` bytes at `virt` and frees the frames behind them, undoing
+/// [alloc_region]. Pages that are not mapped are skipped.
+pub fn free_region(virt: VirtAddr, len: u64) {
+    with_mapper(|mapper| {
+        let mut frame_allocator = BootInfoFrameAllocator::new();
+        for page in pages(virt.as_u64(), len) {
+            if let Ok((frame, flush)) = mapper.unmap(page) {
+                flush.flush();
+                unsafe { frame_allocator.deallocate_frame(frame) };
+            }
+        }
+    });
+}
+
+/// Whether `virt` is backed by a mapping.
+pub fn is_mapped(virt: VirtAddr) -> bool {
+    with_mapper(|mapper| !matches!(mapper.translate(virt), TranslateResult::NotMapped))
+}
+
+/// The physical address `virt` is mapped to, and the size of the page that maps it.
+pub fn translate(virt: VirtAddr) -> Option<(PhysAddr, u64)> {
+    with_mapper(|mapper| match mapper.translate(virt) {
+        TranslateResult::Mapped { frame, offset, .. } => Some((frame.start_address() + offset, frame.size())),
+        _ => None,
+    })
+}
+
+/// Like [translate], but with the full page table entry and without waiting for the page tables,
+/// for fault handlers that may have interrupted a mapping in progress. None if they are busy.
+pub fn try_translate(virt: VirtAddr) -> Option<TranslateResult> {
+    MAPPER.try_lock()?.as_ref().map(|mapper| mapper.translate(virt))
+}
+
+/// Sets whether the pages covering `len` bytes at `virt` are writable and executable, keeping
+/// their other flags. A huge page that the range touches is changed as a whole.
+pub fn protect(virt: VirtAddr, len: u64, writable: bool, executable: bool) {
+    let mut permissions = PageTableFlags::empty();
+    if writable {
+        permissions |= PageTableFlags::WRITABLE;
+    }
+    if !executable {
+        permissions |= PageTableFlags::NO_EXECUTE;
+    }
+    let end = virt.as_u64() + len;
+
+    with_mapper(|mapper| {
+        let mut address = virt.align_down(Size4KiB::SIZE);
+        while address.as_u64() < end {
+            let TranslateResult::Mapped { frame, flags, .. } = mapper.translate(address) else {
+                address += Size4KiB::SIZE;
+                continue;
+            };
+            let flags = flags.difference(PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE) | permissions;
+            let result = match frame.size() {
+                Size4KiB::SIZE => unsafe { mapper.update_flags(Page::<Size4KiB>::containing_address(address), flags) }.map(|flush| flush.flush()),
+                Size2MiB::SIZE => unsafe { mapper.update_flags(Page::<Size2MiB>::containing_address(address), flags) }.map(|flush| flush.flush()),
+                _ => unsafe { mapper.update_flags(Page::<Size1GiB>::containing_address(address), flags) }.map(|flush| flush.flush()),
+            };
+            if let Err(error) = result {
+                panic!("cannot protect {:?}: {:?}", address, error);
+            }
+            address = address.align_down(frame.size()) + frame.size();
+        }
+    });
+}
+
+/// Applies W^X to the loaded kernel: code becomes read-only and executable, read-only data
+/// read-only and non-executable, and writable data non-executable. `image` is the kernel's ELF
+/// file and `load_offset` the address it was relocated to. A page shared by two segments gets
+/// the permissions of both.
+pub fn protect_kernel(image: &[u8], load_offset: u64) {
+    let page_of = |address: u64| address & !(Size4KiB::SIZE - 1);
+    for segment in elf::load_segments(image) {
+        let start = page_of(load_offset + segment.virtual_address);
+        let end = load_offset + segment.virtual_address + segment.memory_size;
+        let mut page = start;
+        while page < end {
+            let sharing = elf::load_segments(image).filter(|other| {
+                let other_start = page_of(load_offset + other.virtual_address);
+                let other_end = load_offset + other.virtual_address + other.memory_size;
+                other_start <= page && page < other_end
+            });
+            let (writable, executable) = sharing.fold((false, false), |(w, x), other| (w || other.is_writable(), x || other.is_executable()));
+            protect(VirtAddr::new(page), Size4KiB::SIZE, writable, executable);
+            page += Size4KiB::SIZE;
+        }
+    }
+}
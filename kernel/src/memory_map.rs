@@ -0,0 +1,157 @@
+use alloc::format;
+use core::fmt::{self, Write};
+use core::ops::Range;
+use bootloader_api::info::{MemoryRegionKind, MemoryRegions};
+use spin::Mutex;
+use kernel::allocator;
+use kernel::buddy::BUDDY;
+use crate::screen::screenwriter;
+
+const MAX_SPANS: usize = 64;
+
+/// What a stretch of physical memory is used for.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Usage {
+    Usable,
+    Bootloader,
+    Firmware,
+    Kernel,
+    Heap,
+    Framebuffer,
+    /// Failed the boot-time memory test, and kept out of use.
+    Faulty,
+}
+
+const USAGES: [Usage; 7] = [Usage::Usable, Usage::Bootloader, Usage::Firmware, Usage::Kernel, Usage::Heap, Usage::Framebuffer, Usage::Faulty];
+
+impl Usage {
+    fn name(self) -> &'static str {
+        match self {
+            Usage::Usable => "Usable",
+            Usage::Bootloader => "Bootloader",
+            Usage::Firmware => "Firmware",
+            Usage::Kernel => "Kernel",
+            Usage::Heap => "Heap (initial)",
+            Usage::Framebuffer => "Framebuffer",
+            Usage::Faulty => "Faulty (memory test)",
+        }
+    }
+
+    fn color(self) -> (u8, u8, u8) {
+        match self {
+            Usage::Usable => (0x33, 0xAA, 0x33),
+            Usage::Bootloader => (0xDD, 0xDD, 0x33),
+            Usage::Firmware => (0x77, 0x77, 0x77),
+            Usage::Kernel => (0xDD, 0x33, 0x33),
+            Usage::Heap => (0x33, 0x77, 0xFF),
+            Usage::Framebuffer => (0xCC, 0x33, 0xCC),
+            Usage::Faulty => (0xFF, 0x66, 0x00),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Span {
+    start: u64,
+    end: u64,
+    usage: Usage,
+}
+
+/// Physical memory layout recorded at boot. Spans added later are drawn over earlier ones, so
+/// the kernel, heap and framebuffer show up on top of the bootloader's regions.
+struct MemoryMap {
+    spans: [Span; MAX_SPANS],
+    len: usize,
+}
+
+static MAP: Mutex<MemoryMap> = Mutex::new(MemoryMap {
+    spans: [Span { start: 0, end: 0, usage: Usage::Usable }; MAX_SPANS],
+    len: 0,
+});
+
+/// Records the bootloader's memory map.
+pub fn record(regions: &MemoryRegions) {
+    for region in regions.iter() {
+        let usage = match region.kind {
+            MemoryRegionKind::Usable => Usage::Usable,
+            MemoryRegionKind::Bootloader => Usage::Bootloader,
+            _ => Usage::Firmware,
+        };
+        mark(region.start..region.end, usage);
+    }
+}
+
+/// Records that `range` is used for `usage`. Spans past the first [MAX_SPANS] are dropped.
+pub fn mark(range: Range<u64>, usage: Usage) {
+    let mut map = MAP.lock();
+    let len = map.len;
+    if len < MAX_SPANS && range.start < range.end {
+        map.spans[len] = Span { start: range.start, end: range.end, usage };
+        map.len += 1;
+    }
+}
+
+/// Writes the recorded spans and the page allocator's free memory as text, for the serial shell.
+pub fn write_summary(out: &mut impl Write) -> fmt::Result {
+    let map = MAP.lock();
+    for span in &map.spans[..map.len] {
+        writeln!(out, "  {:#012x}-{:#012x} {:>8} KiB  {}", span.start, span.end, (span.end - span.start) / 1024, span.usage.name())?;
+    }
+    writeln!(out, "page allocator: {} KiB free", BUDDY.lock().free_bytes() / 1024)
+}
+
+fn fill_rect(x: usize, y: usize, width: usize, height: usize, (r, g, b): (u8, u8, u8)) {
+    for dy in 0..height {
+        for dx in 0..width {
+            screenwriter().draw_pixel(x + dx, y + dy, r, g, b);
+        }
+    }
+}
+
+/// Debug screen drawing physical memory as a bar from address 0 to the highest recorded
+/// address, with a legend and the current heap and page allocator occupancy.
+pub fn draw() {
+    const BAR_Y: usize = 140;
+    const BAR_HEIGHT: usize = 40;
+
+    screenwriter().draw_string_centered(100, "MEMORY MAP", 0xFF, 0xFF, 0xFF);
+
+    let map = MAP.lock();
+    let spans = &map.spans[..map.len];
+    let top = spans.iter().map(|span| span.end).max().unwrap_or(1);
+
+    let left = 20;
+    let width = screenwriter().width().saturating_sub(2 * left);
+    for span in spans {
+        let x = left + (span.start as u128 * width as u128 / top as u128) as usize;
+        let end = left + (span.end as u128 * width as u128 / top as u128) as usize;
+        // Keep small regions visible
+        fill_rect(x, BAR_Y, (end - x).max(1), BAR_HEIGHT, span.usage.color());
+    }
+    screenwriter().draw_string(left, BAR_Y + BAR_HEIGHT + 4, "0x0", 0xAA, 0xAA, 0xAA);
+    let top_label = format!("{:#x}", top);
+    screenwriter().draw_string(left + width.saturating_sub(top_label.len() * 8), BAR_Y + BAR_HEIGHT + 4, &top_label, 0xAA, 0xAA, 0xAA);
+
+    let mut y = BAR_Y + BAR_HEIGHT + 30;
+    for usage in USAGES {
+        let total: u64 = spans.iter().filter(|span| span.usage == usage).map(|span| span.end - span.start).sum();
+        fill_rect(left, y + 2, 12, 12, usage.color());
+        let line = format!("{}: {} KiB", usage.name(), total / 1024);
+        screenwriter().draw_string(left + 20, y, &line, 0xFF, 0xFF, 0xFF);
+        y += 20;
+    }
+
+    let heap = allocator::stats();
+    let free_pages = BUDDY.lock().free_bytes();
+    let lines = [
+        format!("heap: {} KiB used of {} KiB, peak {} KiB", heap.used / 1024, (heap.used + heap.free) / 1024, heap.peak / 1024),
+        format!("page allocator: {} KiB free", free_pages / 1024),
+    ];
+    y += 10;
+    for line in lines {
+        screenwriter().draw_string(left, y, &line, 0xAA, 0xFF, 0xAA);
+        y += 20;
+    }
+
+    screenwriter().draw_string_centered(y + 20, "Press R to return to menu", 0xFF, 0xFF, 0xFF);
+}
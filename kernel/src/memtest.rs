@@ -0,0 +1,172 @@
+use core::fmt::Write;
+use core::{ptr, slice};
+use kernel::buddy::{BUDDY, MAX_ORDER, PAGE_SIZE};
+use kernel::fixed::FixedString;
+use kernel::{pit, serial};
+use x86_64::PhysAddr;
+use crate::memory_map::{self, Usage};
+use crate::screen::{self, screenwriter};
+
+// Boot-time memory test, for hardware of unknown health. With the boot option `memtest=on`, before
+// the game starts, every page the page allocator has free is taken from it a block at a time,
+// written with a set of patterns and read back. Blocks that held them are given back at the end;
+// blocks that did not are kept, so nothing is ever put in them, and are listed on serial, on screen
+// and in the memory map (F4). The kernel, the heap and everything else in use is none of the
+// allocator's, so it is never touched.
+
+/// Written over a whole block and read back, in turn. After them each word is written its own
+/// address, which catches address lines that are stuck or shorted together.
+const PATTERNS: [u64; 4] = [0, u64::MAX, 0xAAAA_AAAA_AAAA_AAAA, 0x5555_5555_5555_5555];
+/// Most bad blocks listed; any more are still kept out of use.
+const MAX_BAD: usize = 16;
+/// How long the result stays on screen before the game starts, in microseconds.
+const RESULT_US: u64 = 3_000_000;
+
+const BAR_Y: usize = 140;
+const BAR_HEIGHT: usize = 24;
+
+/// The first word of a block that did not read back what was written there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fault {
+    pub address: u64,
+    pub expected: u64,
+    pub found: u64,
+}
+
+/// A block taken from the page allocator, and what it failed with, if it did.
+#[derive(Clone, Copy)]
+struct Block {
+    start: PhysAddr,
+    pages: usize,
+    fault: Option<Fault>,
+}
+
+/// Tests all of the page allocator's free memory, reached through the physical memory map at
+/// `physical_offset`, showing how far it has got.
+pub fn run(physical_offset: u64) {
+    let total = BUDDY.lock().free_bytes();
+    writeln!(serial(), "memtest: testing {} MiB", total >> 20).unwrap();
+    let (mut tested, mut bad_count) = (0, 0);
+    let mut bad = [Block { start: PhysAddr::zero(), pages: 0, fault: None }; MAX_BAD];
+    // The good blocks are chained through their first word, held until every block is tested so
+    // the allocator does not hand one out twice
+    let mut good: Option<Block> = None;
+
+    for order in (0..=MAX_ORDER).rev() {
+        let pages = 1 << order;
+        while let Some(start) = BUDDY.lock().alloc_pages(pages) {
+            let words = unsafe { slice::from_raw_parts_mut((physical_offset + start.as_u64()) as *mut u64, pages * PAGE_SIZE as usize / 8) };
+            let fault = test_block(words, start.as_u64());
+            match fault {
+                None => {
+                    words[0] = good.map_or(u64::MAX, |block| block.start.as_u64());
+                    words[1] = good.map_or(0, |block| block.pages as u64);
+                    good = Some(Block { start, pages, fault });
+                }
+                Some(fault) => {
+                    writeln!(serial(), "memtest: bad block {:#x}, {} KiB: {:#x} read {:#018x} for {:#018x}",
+                        start, pages * 4, fault.address, fault.found, fault.expected).unwrap();
+                    memory_map::mark(start.as_u64()..start.as_u64() + (pages as u64) * PAGE_SIZE, Usage::Faulty);
+                    if bad_count < MAX_BAD {
+                        bad[bad_count] = Block { start, pages, fault: Some(fault) };
+                    }
+                    bad_count += 1;
+                }
+            }
+            let before = tested;
+            tested += pages as u64 * PAGE_SIZE;
+            // Redrawn a MiB at a time; the smallest blocks come by the thousand
+            if tested >> 20 != before >> 20 {
+                draw_progress(tested, total, bad_count);
+            }
+        }
+    }
+
+    // Only the good blocks go back
+    while let Some(block) = good {
+        let words = unsafe { slice::from_raw_parts((physical_offset + block.start.as_u64()) as *const u64, 2) };
+        good = (words[0] != u64::MAX).then(|| Block { start: PhysAddr::new(words[0]), pages: words[1] as usize, fault: None });
+        BUDDY.lock().free_pages(block.start, block.pages);
+    }
+    writeln!(serial(), "memtest: {} MiB tested, {} bad blocks", tested >> 20, bad_count).unwrap();
+    draw_result(tested, &bad[..bad_count.min(MAX_BAD)], bad_count);
+    pit::wait_us(RESULT_US);
+}
+
+/// Writes each pattern over `words`, the memory at physical address `start`, and reads it back.
+pub fn test_block(words: &mut [u64], start: u64) -> Option<Fault> {
+    for pass in 0..=PATTERNS.len() {
+        let expected = |i: usize| PATTERNS.get(pass).copied().unwrap_or(start + i as u64 * 8);
+        // Volatile, so that every word really goes to memory and comes back from it
+        for (i, word) in words.iter_mut().enumerate() {
+            unsafe { ptr::write_volatile(word, expected(i)) };
+        }
+        for (i, word) in words.iter().enumerate() {
+            let found = unsafe { ptr::read_volatile(word) };
+            if found != expected(i) {
+                return Some(Fault { address: start + i as u64 * 8, expected: expected(i), found });
+            }
+        }
+    }
+    None
+}
+
+fn fill_rect(x: usize, y: usize, width: usize, height: usize, (r, g, b): (u8, u8, u8)) {
+    let mut writer = screenwriter();
+    for dy in 0..height {
+        for dx in 0..width {
+            writer.draw_pixel(x + dx, y + dy, r, g, b);
+        }
+    }
+}
+
+fn draw_progress(tested: u64, total: u64, bad_count: usize) {
+    let left = 20;
+    let width = screenwriter().width().saturating_sub(2 * left);
+    let done = (tested as u128 * width as u128 / total.max(1) as u128) as usize;
+    {
+        let mut writer = screenwriter();
+        writer.clear_screen(0, 0, 0);
+        writer.draw_string_centered(100, "MEMORY TEST", 0xFF, 0xFF, 0xFF);
+    }
+    fill_rect(left, BAR_Y, width, BAR_HEIGHT, (0x33, 0x33, 0x33));
+    fill_rect(left, BAR_Y, done.min(width), BAR_HEIGHT, if bad_count > 0 { (0xDD, 0x33, 0x33) } else { (0x33, 0xAA, 0x33) });
+    let line = FixedString::<256>::format(format_args!("{} of {} MiB, {} bad blocks", tested >> 20, total >> 20, bad_count));
+    screenwriter().draw_string_centered(BAR_Y + BAR_HEIGHT + 10, line.as_str(), 0xAA, 0xAA, 0xAA);
+    screen::present();
+}
+
+fn draw_result(tested: u64, listed: &[Block], bad_count: usize) {
+    draw_progress(tested, tested, bad_count);
+    let mut writer = screenwriter();
+    let mut y = BAR_Y + BAR_HEIGHT + 40;
+    if bad_count == 0 {
+        writer.draw_string_centered(y, "No errors found", 0x55, 0xFF, 0x55);
+    }
+    for block in listed {
+        let Some(fault) = block.fault else { continue };
+        let line = FixedString::<256>::format(format_args!("{:#012x} {:>5} KiB: {:#x} read {:#018x} for {:#018x}",
+            block.start.as_u64(), block.pages * 4, fault.address, fault.found, fault.expected));
+        writer.draw_string(20, y, line.as_str(), 0xFF, 0x77, 0x77);
+        y += 20;
+    }
+    if bad_count > listed.len() {
+        let line = FixedString::<256>::format(format_args!("and {} more, see serial", bad_count - listed.len()));
+        writer.draw_string(20, y, line.as_str(), 0xFF, 0x77, 0x77);
+    }
+    drop(writer);
+    screen::present();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn memtest_passes_good_memory() {
+        let mut words = alloc::vec![0u64; 512];
+        assert_eq!(test_block(&mut words, 0x10_0000), None);
+        // The last pass leaves each word its own address
+        assert_eq!((words[0], words[511]), (0x10_0000, 0x10_0000 + 511 * 8));
+    }
+}
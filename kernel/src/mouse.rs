@@ -0,0 +1,81 @@
+use core::fmt::Write;
+use crate::{ps2, serial};
+
+// https://wiki.osdev.org/PS/2_Mouse
+const SET_DEFAULTS: u8 = 0xF6;
+const ENABLE_REPORTING: u8 = 0xF4;
+
+/// Movement and button state decoded from one PS/2 mouse packet.
+/// `dy` is positive when the mouse moves away from the user (up the screen).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseEvent {
+    pub dx: i16,
+    pub dy: i16,
+    pub left: bool,
+    pub right: bool,
+    pub middle: bool,
+}
+
+/// Assembles the 3-byte packets the mouse sends, one interrupt per byte.
+pub struct Mouse {
+    packet: [u8; 3],
+    index: usize,
+}
+
+impl Mouse {
+    pub const fn new() -> Self {
+        Self { packet: [0; 3], index: 0 }
+    }
+
+    /// Feeds one byte read from the data port. Returns an event once a packet is complete.
+    pub fn add_byte(&mut self, byte: u8) -> Option<MouseEvent> {
+        // Bit 3 of the first byte is always set; use it to resynchronize after a lost byte.
+        if self.index == 0 && byte & 0x08 == 0 {
+            return None;
+        }
+
+        self.packet[self.index] = byte;
+        self.index += 1;
+        if self.index < self.packet.len() {
+            return None;
+        }
+        self.index = 0;
+
+        let flags = self.packet[0];
+        // Discard packets whose movement overflowed
+        if flags & 0xC0 != 0 {
+            return None;
+        }
+
+        Some(MouseEvent {
+            dx: sign_extend(self.packet[1], flags & 0x10 != 0),
+            dy: sign_extend(self.packet[2], flags & 0x20 != 0),
+            left: flags & 0x01 != 0,
+            right: flags & 0x02 != 0,
+            middle: flags & 0x04 != 0,
+        })
+    }
+}
+
+impl Default for Mouse {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn sign_extend(value: u8, negative: bool) -> i16 {
+    if negative {
+        value as i16 - 0x100
+    } else {
+        value as i16
+    }
+}
+
+/// Turns on data reporting, provided [ps2::init] found a mouse on the second port.
+pub fn init() {
+    if !ps2::mouse_present() {
+        return;
+    }
+    let ok = ps2::send_mouse(SET_DEFAULTS) && ps2::send_mouse(ENABLE_REPORTING);
+    writeln!(serial(), "PS/2 mouse initialized: {}", ok).unwrap();
+}
@@ -0,0 +1,115 @@
+use x86_64::PhysAddr;
+use crate::{interrupts, memory};
+use crate::pci::PciAddress;
+
+// Message-signaled interrupts: the device writes `data` to `address` instead of raising a shared
+// interrupt line. The address selects the target local APIC, the data holds the vector.
+// See the PCI Local Bus Specification, section 6.8, and https://wiki.osdev.org/PCI#Message_Signaled_Interrupts
+const CAP_MSI: u8 = 0x05;
+const CAP_MSI_X: u8 = 0x11;
+
+const MSI_ENABLE: u32 = 1 << 16;
+const MSI_64BIT: u32 = 1 << 23;
+const MSI_MULTIPLE_ENABLE: u32 = 0b111 << 20;
+const MSI_X_ENABLE: u32 = 1 << 31;
+const MSI_X_FUNCTION_MASK: u32 = 1 << 30;
+
+const INTX_DISABLE: u32 = 1 << 10;
+const ENTRY_MASKED: u32 = 1 << 0;
+
+fn message_address(cpu: u8) -> u32 {
+    0xFEE0_0000 | (cpu as u32) << 12
+}
+
+fn disable_intx(pci: PciAddress) {
+    pci.write(0x04, pci.read(0x04) | INTX_DISABLE);
+}
+
+/// Has the function signal `vector` on the CPU with local APIC ID `cpu` through MSI, in place
+/// of its legacy interrupt line. Returns false if the function does not support MSI.
+pub fn enable_msi(pci: PciAddress, vector: u8, cpu: u8) -> bool {
+    let Some(cap) = pci.find_capability(CAP_MSI) else { return false };
+
+    let control = pci.read(cap);
+    pci.write(cap + 4, message_address(cpu));
+    if control & MSI_64BIT != 0 {
+        pci.write(cap + 8, 0);
+        pci.write(cap + 12, vector as u32);
+    } else {
+        pci.write(cap + 8, vector as u32);
+    }
+    // A single message; the control word shares its dword with the capability header
+    pci.write(cap, (control & !MSI_MULTIPLE_ENABLE) | MSI_ENABLE);
+    disable_intx(pci);
+    true
+}
+
+/// Allocates a vector for `handler` (see [interrupts::allocate_irq]) and enables MSI for it.
+/// Returns the vector, or None if the function has no MSI support or no vector is free.
+pub fn allocate_msi(pci: PciAddress, cpu: u8, handler: fn()) -> Option<u8> {
+    pci.find_capability(CAP_MSI)?;
+    let vector = interrupts::allocate_irq(handler)?;
+    enable_msi(pci, vector, cpu);
+    Some(vector)
+}
+
+/// The MSI-X table of a PCI function, where each of its interrupt sources gets its own
+/// address, data and mask.
+pub struct MsiX {
+    pci: PciAddress,
+    capability: u8,
+    table: *mut u32,
+    size: u16,
+}
+
+unsafe impl Send for MsiX {}
+
+impl MsiX {
+    /// Finds the function's MSI-X capability and maps its table. Returns None if the function
+    /// does not support MSI-X.
+    pub fn new(pci: PciAddress) -> Option<Self> {
+        let capability = pci.find_capability(CAP_MSI_X)?;
+        let size = ((pci.read(capability) >> 16) & 0x7FF) as u16 + 1;
+        let table_location = pci.read(capability + 4);
+        let bar = (table_location & 0x7) as u8;
+        let table = pci.memory_bar(bar) + (table_location & !0x7) as u64;
+
+        memory::map_region(PhysAddr::new(table), size as u64 * 16, memory::MMIO);
+
+        Some(Self { pci, capability, table: table as *mut u32, size })
+    }
+
+    /// Number of entries in the table.
+    pub fn table_size(&self) -> u16 {
+        self.size
+    }
+
+    fn entry(&self, index: u16) -> *mut u32 {
+        assert!(index < self.size, "MSI-X entry {} out of range", index);
+        unsafe { self.table.add(index as usize * 4) }
+    }
+
+    /// Points entry `index` at `vector` on the CPU with local APIC ID `cpu` and unmasks it.
+    pub fn set_entry(&mut self, index: u16, vector: u8, cpu: u8) {
+        let entry = self.entry(index);
+        unsafe {
+            entry.add(3).write_volatile(ENTRY_MASKED);
+            entry.write_volatile(message_address(cpu));
+            entry.add(1).write_volatile(0);
+            entry.add(2).write_volatile(vector as u32);
+            entry.add(3).write_volatile(0);
+        }
+    }
+
+    pub fn set_masked(&mut self, index: u16, masked: bool) {
+        let entry = self.entry(index);
+        unsafe { entry.add(3).write_volatile(if masked { ENTRY_MASKED } else { 0 }) };
+    }
+
+    /// Switches the function from its legacy interrupt line to the MSI-X table.
+    pub fn enable(&mut self) {
+        let control = self.pci.read(self.capability);
+        self.pci.write(self.capability, (control & !MSI_X_FUNCTION_MASK) | MSI_X_ENABLE);
+        disable_intx(self.pci);
+    }
+}
@@ -0,0 +1,315 @@
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::net::Ipv4Addr;
+use crate::sync::IrqSafeMutex;
+use crate::{serial, virtio_net};
+
+// A minimal IPv4 stack over the virtio-net card: ARP, IPv4 without fragments or options, and UDP.
+// There is no router and no DHCP; the machine takes a link-local address, and peers on the same
+// Ethernet segment reach it directly.
+//
+// Received frames are handled whenever a socket is read or [poll] is called, so the stack needs
+// no thread of its own.
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_ARP: u16 = 0x0806;
+const PROTOCOL_UDP: u8 = 17;
+
+const ETHERNET_HEADER: usize = 14;
+const IPV4_HEADER: usize = 20;
+const UDP_HEADER: usize = 8;
+const ARP_PACKET: usize = 28;
+
+/// Largest UDP payload that fits one frame.
+pub const MAX_PAYLOAD: usize = virtio_net::MAX_FRAME - ETHERNET_HEADER - IPV4_HEADER - UDP_HEADER;
+
+const BROADCAST_MAC: [u8; 6] = [0xFF; 6];
+const ARP_CACHE_SIZE: usize = 16;
+/// Datagrams kept per socket; more are dropped until it is read.
+const SOCKET_QUEUE: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetError {
+    /// No network card, or [init] has not run.
+    NoDevice,
+    /// The destination's MAC address is not known yet. An ARP request has gone out; try again
+    /// shortly.
+    Resolving,
+    /// Longer than [MAX_PAYLOAD].
+    TooLong,
+    /// All transmit buffers are in use.
+    Busy,
+}
+
+/// A UDP datagram received on a [UdpSocket].
+#[derive(Debug, Clone)]
+pub struct Datagram {
+    pub source: Ipv4Addr,
+    pub source_port: u16,
+    pub data: Vec<u8>,
+}
+
+struct Stack {
+    mac: [u8; 6],
+    address: Ipv4Addr,
+    /// Most recently learned last.
+    arp_cache: VecDeque<(Ipv4Addr, [u8; 6])>,
+    sockets: BTreeMap<u16, VecDeque<Datagram>>,
+    next_id: u16,
+}
+
+static STACK: IrqSafeMutex<Option<Stack>> = IrqSafeMutex::new(None);
+
+/// Brings the stack up on the network card, with a link-local address (169.254.0.0/16) made from
+/// the end of the MAC address. Returns false without a card.
+pub fn init() -> bool {
+    let Some(mac) = virtio_net::mac_address() else { return false };
+    // .0 and .255 in the last byte are avoided, and 169.254.0.x and 169.254.255.x are reserved
+    let address = Ipv4Addr::new(169, 254, mac[4].clamp(1, 254), mac[5].clamp(1, 254));
+    *STACK.lock() = Some(Stack { mac, address, arp_cache: VecDeque::new(), sockets: BTreeMap::new(), next_id: 0 });
+    writeln!(serial(), "net: address {}", address).unwrap();
+    true
+}
+
+/// This machine's address, once [init] has run.
+pub fn address() -> Option<Ipv4Addr> {
+    STACK.lock().as_ref().map(|stack| stack.address)
+}
+
+/// Replaces the address [init] picked.
+pub fn set_address(address: Ipv4Addr) {
+    if let Some(stack) = STACK.lock().as_mut() {
+        stack.address = address;
+    }
+}
+
+/// Handles the frames received since the last call: answers ARP requests, learns addresses, and
+/// queues datagrams for the sockets they are addressed to.
+pub fn poll() {
+    while let Some(frame) = virtio_net::receive() {
+        let mut guard = STACK.lock();
+        let Some(stack) = guard.as_mut() else { return };
+        stack.handle_frame(&frame);
+    }
+}
+
+fn read16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn ipv4_at(bytes: &[u8], offset: usize) -> Ipv4Addr {
+    Ipv4Addr::new(bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3])
+}
+
+fn mac_at(bytes: &[u8], offset: usize) -> [u8; 6] {
+    bytes[offset..offset + 6].try_into().unwrap()
+}
+
+/// The Internet checksum (RFC 1071) of `bytes`, continuing from `sum`.
+fn checksum(bytes: &[u8], mut sum: u32) -> u16 {
+    for pair in bytes.chunks(2) {
+        sum += u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)]) as u32;
+    }
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Checksum of the UDP pseudo-header, to continue with the UDP header and payload.
+fn pseudo_header_sum(source: Ipv4Addr, destination: Ipv4Addr, udp_len: u16) -> u32 {
+    let mut sum = 0;
+    for address in [source, destination] {
+        let octets = address.octets();
+        sum += u16::from_be_bytes([octets[0], octets[1]]) as u32 + u16::from_be_bytes([octets[2], octets[3]]) as u32;
+    }
+    sum + PROTOCOL_UDP as u32 + udp_len as u32
+}
+
+impl Stack {
+    fn learn(&mut self, address: Ipv4Addr, mac: [u8; 6]) {
+        if address.is_unspecified() || address.is_broadcast() || mac == BROADCAST_MAC {
+            return;
+        }
+        self.arp_cache.retain(|&(known, _)| known != address);
+        if self.arp_cache.len() == ARP_CACHE_SIZE {
+            self.arp_cache.pop_front();
+        }
+        self.arp_cache.push_back((address, mac));
+    }
+
+    fn resolve(&self, address: Ipv4Addr) -> Option<[u8; 6]> {
+        if address.is_broadcast() {
+            return Some(BROADCAST_MAC);
+        }
+        self.arp_cache.iter().find(|&&(known, _)| known == address).map(|&(_, mac)| mac)
+    }
+
+    fn send_frame(&self, destination: [u8; 6], ethertype: u16, payload: &[u8]) -> Result<(), NetError> {
+        let mut frame = Vec::with_capacity(ETHERNET_HEADER + payload.len());
+        frame.extend_from_slice(&destination);
+        frame.extend_from_slice(&self.mac);
+        frame.extend_from_slice(&ethertype.to_be_bytes());
+        frame.extend_from_slice(payload);
+        if virtio_net::send(&frame) { Ok(()) } else { Err(NetError::Busy) }
+    }
+
+    fn send_arp(&self, operation: u16, target_mac: [u8; 6], target: Ipv4Addr) -> Result<(), NetError> {
+        let mut packet = Vec::with_capacity(ARP_PACKET);
+        packet.extend_from_slice(&[0, 1, 0x08, 0x00, 6, 4]);
+        packet.extend_from_slice(&operation.to_be_bytes());
+        packet.extend_from_slice(&self.mac);
+        packet.extend_from_slice(&self.address.octets());
+        packet.extend_from_slice(&target_mac);
+        packet.extend_from_slice(&target.octets());
+        let destination = if operation == 1 { BROADCAST_MAC } else { target_mac };
+        self.send_frame(destination, ETHERTYPE_ARP, &packet)
+    }
+
+    fn send_udp(&mut self, source_port: u16, destination: Ipv4Addr, destination_port: u16, data: &[u8]) -> Result<(), NetError> {
+        if data.len() > MAX_PAYLOAD {
+            return Err(NetError::TooLong);
+        }
+        let Some(mac) = self.resolve(destination) else {
+            self.send_arp(1, [0; 6], destination)?;
+            return Err(NetError::Resolving);
+        };
+
+        let udp_len = (UDP_HEADER + data.len()) as u16;
+        let total_len = IPV4_HEADER as u16 + udp_len;
+        self.next_id = self.next_id.wrapping_add(1);
+        let mut packet = Vec::with_capacity(total_len as usize);
+        // Version 4, 5 words of header; don't fragment; TTL 64
+        packet.extend_from_slice(&[0x45, 0]);
+        packet.extend_from_slice(&total_len.to_be_bytes());
+        packet.extend_from_slice(&self.next_id.to_be_bytes());
+        packet.extend_from_slice(&[0x40, 0, 64, PROTOCOL_UDP, 0, 0]);
+        packet.extend_from_slice(&self.address.octets());
+        packet.extend_from_slice(&destination.octets());
+        let header_checksum = checksum(&packet[..IPV4_HEADER], 0);
+        packet[10..12].copy_from_slice(&header_checksum.to_be_bytes());
+
+        packet.extend_from_slice(&source_port.to_be_bytes());
+        packet.extend_from_slice(&destination_port.to_be_bytes());
+        packet.extend_from_slice(&udp_len.to_be_bytes());
+        packet.extend_from_slice(&[0, 0]);
+        packet.extend_from_slice(data);
+        let mut udp_checksum = checksum(&packet[IPV4_HEADER..], pseudo_header_sum(self.address, destination, udp_len));
+        // Zero means "no checksum"; a computed zero is sent as all ones
+        if udp_checksum == 0 {
+            udp_checksum = 0xFFFF;
+        }
+        packet[IPV4_HEADER + 6..IPV4_HEADER + 8].copy_from_slice(&udp_checksum.to_be_bytes());
+        self.send_frame(mac, ETHERTYPE_IPV4, &packet)
+    }
+
+    fn handle_frame(&mut self, frame: &[u8]) {
+        if frame.len() < ETHERNET_HEADER {
+            return;
+        }
+        let source_mac = mac_at(frame, 6);
+        let payload = &frame[ETHERNET_HEADER..];
+        match read16(frame, 12) {
+            ETHERTYPE_ARP => self.handle_arp(payload),
+            ETHERTYPE_IPV4 => self.handle_ipv4(source_mac, payload),
+            _ => {}
+        }
+    }
+
+    fn handle_arp(&mut self, packet: &[u8]) {
+        // Ethernet and IPv4 only
+        if packet.len() < ARP_PACKET || packet[..6] != [0, 1, 0x08, 0x00, 6, 4] {
+            return;
+        }
+        let operation = read16(packet, 6);
+        let (sender_mac, sender) = (mac_at(packet, 8), ipv4_at(packet, 14));
+        let target = ipv4_at(packet, 24);
+        self.learn(sender, sender_mac);
+        if operation == 1 && target == self.address {
+            let _ = self.send_arp(2, sender_mac, sender);
+        }
+    }
+
+    fn handle_ipv4(&mut self, source_mac: [u8; 6], packet: &[u8]) {
+        if packet.len() < IPV4_HEADER || packet[0] >> 4 != 4 {
+            return;
+        }
+        let header_len = (packet[0] & 0xF) as usize * 4;
+        let total_len = read16(packet, 2) as usize;
+        let fragmented = read16(packet, 6) & 0x3FFF != 0;
+        if header_len < IPV4_HEADER || total_len < header_len || total_len > packet.len() || fragmented || checksum(&packet[..header_len], 0) != 0 {
+            return;
+        }
+        let (source, destination) = (ipv4_at(packet, 12), ipv4_at(packet, 16));
+        if destination != self.address && !destination.is_broadcast() {
+            return;
+        }
+        self.learn(source, source_mac);
+        if packet[9] == PROTOCOL_UDP {
+            self.handle_udp(source, destination, &packet[header_len..total_len]);
+        }
+    }
+
+    fn handle_udp(&mut self, source: Ipv4Addr, destination: Ipv4Addr, segment: &[u8]) {
+        if segment.len() < UDP_HEADER {
+            return;
+        }
+        let udp_len = read16(segment, 4) as usize;
+        if udp_len < UDP_HEADER || udp_len > segment.len() {
+            return;
+        }
+        let segment = &segment[..udp_len];
+        if read16(segment, 6) != 0 && checksum(segment, pseudo_header_sum(source, destination, udp_len as u16)) != 0 {
+            return;
+        }
+        let (source_port, destination_port) = (read16(segment, 0), read16(segment, 2));
+        if let Some(queue) = self.sockets.get_mut(&destination_port).filter(|queue| queue.len() < SOCKET_QUEUE) {
+            queue.push_back(Datagram { source, source_port, data: segment[UDP_HEADER..].to_vec() });
+        }
+    }
+}
+
+/// A bound UDP port. Datagrams to it are queued until read; the port is freed on drop.
+#[derive(Debug)]
+pub struct UdpSocket {
+    port: u16,
+}
+
+impl UdpSocket {
+    /// Binds `port`. Returns None if it is taken or the stack is not up.
+    pub fn bind(port: u16) -> Option<Self> {
+        let mut guard = STACK.lock();
+        let stack = guard.as_mut()?;
+        if stack.sockets.contains_key(&port) {
+            return None;
+        }
+        stack.sockets.insert(port, VecDeque::new());
+        Some(UdpSocket { port })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Sends `data` to `port` at `destination`, which may be [Ipv4Addr::BROADCAST].
+    pub fn send_to(&self, data: &[u8], destination: Ipv4Addr, port: u16) -> Result<(), NetError> {
+        let mut guard = STACK.lock();
+        let stack = guard.as_mut().ok_or(NetError::NoDevice)?;
+        stack.send_udp(self.port, destination, port, data)
+    }
+
+    /// The oldest datagram received on the port, if any. Never blocks.
+    pub fn recv_from(&self) -> Option<Datagram> {
+        poll();
+        STACK.lock().as_mut()?.sockets.get_mut(&self.port)?.pop_front()
+    }
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        if let Some(stack) = STACK.lock().as_mut() {
+            stack.sockets.remove(&self.port);
+        }
+    }
+}
@@ -0,0 +1,353 @@
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::net::Ipv4Addr;
+use kernel::net::{self, UdpSocket};
+use kernel::{link, rng, serial};
+use crate::screen::screenwriter;
+use crate::strings::{Text, fill, text};
+use crate::{GameMode, Pong};
+
+// Head-to-head Pong between two machines, in lockstep: each side sends its paddle input for every
+// frame, and a frame is only simulated once both inputs for it are in. Both sides start from the
+// same random seed and run the same code, so they stay in step without ever sending the ball's
+// position. Every [CHECK_PERIOD] frames each side also sends a checksum of its game state; if
+// they differ the two have drifted apart, and the game ends rather than play on two different
+// games.
+//
+// Packets go either as UDP on [PORT], broadcast from the lobby until the other player answers, or
+// as frames on the serial link (see [link]). Each side picks a random nonce; the lower one plays
+// Player 1 (left). Inputs are delayed by [INPUT_DELAY] frames to give them time to arrive, and
+// each packet repeats the last [REDUNDANCY] of them, so a lost packet costs nothing once the next
+// one gets through.
+
+const PORT: u16 = 7777;
+const MAGIC: [u8; 2] = *b"PN";
+
+const HELLO: u8 = 1;
+const INPUT: u8 = 2;
+const BYE: u8 = 3;
+
+const INPUT_DELAY: u32 = 3;
+const REDUNDANCY: u32 = 8;
+/// Frames of input kept; more than the peer can ever be ahead.
+const WINDOW: usize = 32;
+/// Ticks between HELLOs.
+const HELLO_PERIOD: u64 = 15;
+/// Ticks without the peer's input before the game is abandoned, 5 s.
+const TIMEOUT_TICKS: u32 = 150;
+/// Frames between state checksums.
+const CHECK_PERIOD: u32 = 30;
+/// Own checksums kept for comparing, covering more frames than the peer can be behind.
+const CHECKS: usize = 4;
+
+enum Transport {
+    Network(UdpSocket),
+    Serial,
+}
+
+impl Transport {
+    /// Sends to `address`, or on the network to everyone if there is none yet.
+    fn send(&self, packet: &[u8], address: Option<Ipv4Addr>) {
+        match self {
+            // Lost or not yet resolved: the next tick sends again
+            Transport::Network(socket) => {
+                let _ = socket.send_to(packet, address.unwrap_or(Ipv4Addr::BROADCAST), PORT);
+            }
+            Transport::Serial => {
+                link::send_frame(packet);
+            }
+        }
+    }
+
+    /// The next packet received, and who from on the network.
+    fn receive(&self) -> Option<(Option<Ipv4Addr>, Vec<u8>)> {
+        match self {
+            Transport::Network(socket) => socket.recv_from().map(|datagram| (Some(datagram.source), datagram.data)),
+            Transport::Serial => link::receive_frame().map(|frame| (None, frame)),
+        }
+    }
+}
+
+struct Peer {
+    /// None on the serial link.
+    address: Option<Ipv4Addr>,
+    nonce: u32,
+}
+
+impl Peer {
+    fn name(&self) -> alloc::string::String {
+        match self.address {
+            Some(address) => alloc::format!("{}", address),
+            None => "the serial link".into(),
+        }
+    }
+}
+
+pub struct Session {
+    transport: Transport,
+    nonce: u32,
+    peer: Option<Peer>,
+    /// Paddle keys pressed since the last tick, down positive.
+    pending: i8,
+    /// Next frame to simulate.
+    frame: u32,
+    /// Next frame to record a local input for.
+    input_frame: u32,
+    local: [i8; WINDOW],
+    remote: [Option<i8>; WINDOW],
+    /// Ticks since a frame was last simulated.
+    stalled: u32,
+    /// Why the lobby is still waiting, if not just for a player.
+    status: Option<&'static str>,
+    /// Own state checksums, by frame.
+    checks: [Option<(u32, u32)>; CHECKS],
+    /// The peer's latest state checksum not yet compared, by frame.
+    remote_check: Option<(u32, u32)>,
+}
+
+impl Session {
+    /// Opens the lobby for a game on the network. Returns None without a network.
+    pub fn network(seed: u32) -> Option<Self> {
+        UdpSocket::bind(PORT).map(|socket| Self::new(Transport::Network(socket), seed))
+    }
+
+    /// Opens the lobby for a game over the serial link. Returns None without the link.
+    pub fn serial(seed: u32) -> Option<Self> {
+        link::is_available().then(|| Self::new(Transport::Serial, seed))
+    }
+
+    /// Whether the session plays over the serial link, which it then has to itself.
+    pub fn uses_link(&self) -> bool {
+        matches!(self.transport, Transport::Serial)
+    }
+
+    fn new(transport: Transport, seed: u32) -> Self {
+        let tsc = unsafe { core::arch::x86_64::_rdtsc() };
+        Session {
+            transport,
+            nonce: seed ^ tsc as u32 ^ (tsc >> 32) as u32,
+            peer: None,
+            pending: 0,
+            frame: 0,
+            input_frame: INPUT_DELAY,
+            local: [0; WINDOW],
+            remote: [None; WINDOW],
+            stalled: 0,
+            status: None,
+            checks: [None; CHECKS],
+            remote_check: None,
+        }
+    }
+
+    fn is_player1(&self) -> bool {
+        self.peer.as_ref().is_some_and(|peer| self.nonce < peer.nonce)
+    }
+
+    fn header(&self, kind: u8) -> Vec<u8> {
+        let mut packet = Vec::with_capacity(64);
+        packet.extend_from_slice(&MAGIC);
+        packet.push(kind);
+        packet.extend_from_slice(&self.nonce.to_be_bytes());
+        packet
+    }
+
+    fn send(&self, packet: &[u8]) {
+        self.transport.send(packet, self.peer.as_ref().and_then(|peer| peer.address));
+    }
+
+    fn send_hello(&self, pong: &Pong) {
+        let mut packet = self.header(HELLO);
+        packet.extend_from_slice(&(pong.game.width as u32).to_be_bytes());
+        packet.extend_from_slice(&(pong.game.height as u32).to_be_bytes());
+        self.transport.send(&packet, None);
+    }
+
+    /// The last [REDUNDANCY] inputs, then the latest state checksum.
+    fn send_inputs(&self) {
+        let first = self.input_frame.saturating_sub(REDUNDANCY);
+        let mut packet = self.header(INPUT);
+        packet.extend_from_slice(&first.to_be_bytes());
+        packet.push((self.input_frame - first) as u8);
+        packet.extend((first..self.input_frame).map(|frame| self.local[frame as usize % WINDOW] as u8));
+        if let Some((frame, checksum)) = self.checks.iter().flatten().max_by_key(|(frame, _)| *frame) {
+            packet.extend_from_slice(&frame.to_be_bytes());
+            packet.extend_from_slice(&checksum.to_be_bytes());
+        }
+        self.send(&packet);
+    }
+
+    fn handle(&mut self, pong: &mut Pong, source: Option<Ipv4Addr>, data: &[u8]) {
+        if data.len() < 7 || data[..2] != MAGIC {
+            return;
+        }
+        let nonce = u32::from_be_bytes(data[3..7].try_into().unwrap());
+        if nonce == self.nonce {
+            // Our own broadcast
+            return;
+        }
+        let body = &data[7..];
+        match (data[2], &self.peer) {
+            (HELLO, None) if body.len() >= 8 => {
+                let width = u32::from_be_bytes(body[0..4].try_into().unwrap()) as usize;
+                let height = u32::from_be_bytes(body[4..8].try_into().unwrap()) as usize;
+                if (width, height) != (pong.game.width, pong.game.height) {
+                    self.status = Some("found a player with a different screen size");
+                    return;
+                }
+                self.start(pong, Peer { address: source, nonce });
+            }
+            // The peer has not heard us yet
+            (HELLO, Some(peer)) if peer.nonce == nonce && self.frame == 0 => self.send_hello(pong),
+            (INPUT, Some(peer)) if peer.nonce == nonce && body.len() >= 5 => {
+                let first = u32::from_be_bytes(body[0..4].try_into().unwrap());
+                let count = body[4] as usize;
+                let inputs = body[5..].iter().take(count);
+                for (frame, &input) in (first..).zip(inputs) {
+                    if frame >= self.frame && frame < self.frame + WINDOW as u32 {
+                        self.remote[frame as usize % WINDOW] = Some(input as i8);
+                    }
+                }
+                if let Some(check) = body.get(5 + count..5 + count + 8) {
+                    let frame = u32::from_be_bytes(check[0..4].try_into().unwrap());
+                    let checksum = u32::from_be_bytes(check[4..8].try_into().unwrap());
+                    self.remote_check = Some((frame, checksum));
+                    self.verify(pong);
+                }
+            }
+            (BYE, Some(peer)) if peer.nonce == nonce => {
+                writeln!(serial(), "netplay: {} left", peer.name()).unwrap();
+                pong.game.game_mode = GameMode::Menu;
+            }
+            _ => {}
+        }
+    }
+
+    fn start(&mut self, pong: &mut Pong, peer: Peer) {
+        writeln!(serial(), "netplay: playing {} as Player {}", peer.name(), if self.nonce < peer.nonce { 1 } else { 2 }).unwrap();
+        rng::seed(self.nonce ^ peer.nonce);
+        self.peer = Some(peer);
+        self.send_hello(pong);
+        pong.game.start(GameMode::Network, rng::u32);
+    }
+
+    /// Simulates the next frame if both inputs for it are in.
+    fn step(&mut self, pong: &mut Pong) {
+        if self.input_frame <= self.frame + INPUT_DELAY {
+            self.local[self.input_frame as usize % WINDOW] = core::mem::take(&mut self.pending);
+            self.input_frame += 1;
+        }
+        self.send_inputs();
+
+        let slot = self.frame as usize % WINDOW;
+        let Some(remote) = self.remote[slot].take() else {
+            self.stalled += 1;
+            if self.stalled > TIMEOUT_TICKS {
+                writeln!(serial(), "netplay: no input from the other player, giving up").unwrap();
+                pong.game.game_mode = GameMode::Menu;
+            }
+            return;
+        };
+        let local = self.local[slot];
+        let (player1, player2) = if self.is_player1() { (local, remote) } else { (remote, local) };
+        for (is_player1, steps) in [(true, player1), (false, player2)] {
+            for _ in 0..steps.unsigned_abs() {
+                pong.game.move_paddle(is_player1, steps < 0);
+            }
+        }
+        pong.update();
+        self.frame += 1;
+        self.stalled = 0;
+
+        if self.frame.is_multiple_of(CHECK_PERIOD) {
+            self.checks[(self.frame / CHECK_PERIOD) as usize % CHECKS] = Some((self.frame, checksum(pong)));
+            self.verify(pong);
+        }
+    }
+
+    /// Compares the peer's checksum with ours for the same frame, once we have simulated it, and
+    /// ends the game if they differ.
+    fn verify(&mut self, pong: &mut Pong) {
+        let Some((frame, remote)) = self.remote_check else { return };
+        if frame > self.frame {
+            return;
+        }
+        self.remote_check = None;
+        let Some(&(_, local)) = self.checks.iter().flatten().find(|(checked, _)| *checked == frame) else {
+            // Too old to compare
+            return;
+        };
+        if local != remote {
+            writeln!(serial(), "netplay: game state differs from the other player's at frame {}, stopping", frame).unwrap();
+            pong.game.game_mode = GameMode::Menu;
+        }
+    }
+}
+
+/// FNV-1a over everything the simulation carries from frame to frame.
+pub(crate) fn checksum(pong: &Pong) -> u32 {
+    let state = [
+        pong.game.ball().x.to_bits(),
+        pong.game.ball().y.to_bits(),
+        pong.game.ball().dx.to_bits(),
+        pong.game.ball().dy.to_bits(),
+        pong.game.paddle(1).y as u32,
+        pong.game.paddle(2).y as u32,
+        pong.game.scoreboard.points(1),
+        pong.game.scoreboard.points(2),
+    ];
+    state.iter().flat_map(|value| value.to_le_bytes()).fold(0x811C_9DC5, |hash, byte| (hash ^ byte as u32).wrapping_mul(0x0100_0193))
+}
+
+/// Runs the network or serial link game for one tick: reads what arrived, then in the lobby says
+/// hello, or in a game sends this tick's input and simulates a frame if it can. Ends the session
+/// once the game has left the lobby and the network game.
+pub fn tick(pong: &mut Pong) {
+    let Some(mut session) = pong.netplay.take() else { return };
+    if pong.game.game_mode != GameMode::NetworkLobby && pong.game.game_mode != GameMode::Network {
+        if session.peer.is_some() {
+            session.send(&session.header(BYE));
+        }
+        return;
+    }
+
+    while let Some((source, packet)) = session.transport.receive() {
+        session.handle(pong, source, &packet);
+    }
+    match pong.game.game_mode {
+        GameMode::NetworkLobby if pong.ticks.is_multiple_of(HELLO_PERIOD) => session.send_hello(pong),
+        GameMode::Network => session.step(pong),
+        _ => {}
+    }
+    pong.netplay = Some(session);
+}
+
+/// A paddle key pressed during a network or serial link game; it pushes this side's paddle once
+/// the frame it is scheduled for is simulated.
+pub fn press(pong: &mut Pong, up: bool) {
+    if let Some(session) = pong.netplay.as_mut() {
+        session.pending = session.pending.saturating_add(if up { -1 } else { 1 });
+    }
+}
+
+pub fn draw_lobby(pong: &Pong) {
+    let Some(session) = pong.netplay.as_ref() else { return };
+    let mut writer = screenwriter();
+    match session.transport {
+        Transport::Network(_) => {
+            writer.draw_string_centered(100, text(Text::NetworkTitle), 0xFF, 0xFF, 0xFF);
+            writer.draw_string_centered(130, text(Text::NetworkWaiting), 0xAA, 0xFF, 0xAA);
+            if let Some(address) = net::address() {
+                let line = fill(Text::NetworkAddress, &[&address]);
+                writer.draw_string_centered(150, &line, 0xAA, 0xAA, 0xAA);
+            }
+        }
+        Transport::Serial => {
+            writer.draw_string_centered(100, text(Text::SerialTitle), 0xFF, 0xFF, 0xFF);
+            writer.draw_string_centered(130, text(Text::SerialWaiting), 0xAA, 0xFF, 0xAA);
+        }
+    }
+    if let Some(status) = session.status {
+        writer.draw_string_centered(170, status, 0xFF, 0xAA, 0xAA);
+    }
+    writer.draw_string_centered(200, text(Text::ReturnToMenu), 0xFF, 0xFF, 0xFF);
+}
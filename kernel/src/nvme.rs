@@ -0,0 +1,428 @@
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::time::Duration;
+use x86_64::PhysAddr;
+use crate::block::{self, BlockError, SECTOR_SIZE};
+use crate::dma::{self, DmaBuffer};
+use crate::msi::MsiX;
+use crate::pci::{self, Bar};
+use crate::sync::IrqSafeMutex;
+use crate::{acpi_tables, interrupts, memory, serial, time};
+
+// NVMe SSD (QEMU: `-device nvme,serial=...,drive=...`), a backend of block.rs. Uses namespace 1,
+// which must have 512-byte sectors.
+//
+// The admin queue pair sets the controller up and is polled. I/O goes through one more queue
+// pair, with the completion queue interrupting through MSI-X entry 0 when there is one. As with
+// virtio-blk, requests take one of a fixed set of slots, each with its own data buffer and the
+// PRP list pointing at its pages; the slot index is the command identifier. See the "NVM Express
+// Base Specification" 1.4, sections 3 (registers), 4 (queues) and 5-6 (commands).
+
+const CLASS: (u8, u8, u8) = (0x01, 0x08, 0x02);
+
+// Controller registers
+const CAP: u64 = 0x00;
+const CC: u64 = 0x14;
+const CSTS: u64 = 0x1C;
+const AQA: u64 = 0x24;
+const ASQ: u64 = 0x28;
+const ACQ: u64 = 0x30;
+const DOORBELLS: u64 = 0x1000;
+
+const CC_ENABLE: u32 = 1 << 0;
+/// 64-byte submission and 16-byte completion queue entries, as powers of two.
+const CC_ENTRY_SIZES: u32 = 6 << 16 | 4 << 20;
+const CSTS_READY: u32 = 1 << 0;
+const CSTS_FATAL: u32 = 1 << 1;
+
+const ADMIN_CREATE_SQ: u8 = 0x01;
+const ADMIN_CREATE_CQ: u8 = 0x05;
+const ADMIN_IDENTIFY: u8 = 0x06;
+const IO_FLUSH: u8 = 0x00;
+const IO_WRITE: u8 = 0x01;
+const IO_READ: u8 = 0x02;
+
+const IDENTIFY_NAMESPACE: u32 = 0;
+const IDENTIFY_CONTROLLER: u32 = 1;
+
+const NAMESPACE: u32 = 1;
+const PAGE_SIZE: usize = 4096;
+const ADMIN_ENTRIES: u16 = 16;
+const IO_ENTRIES: u16 = 32;
+const IO_QUEUE: u16 = 1;
+/// Requests in flight at once.
+const SLOTS: usize = 8;
+/// Sectors in one request, unless the controller takes fewer; longer transfers are split.
+const MAX_SECTORS: usize = 128;
+const ADMIN_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Copy)]
+enum SlotState {
+    Free,
+    InFlight,
+    /// Finished, with the status field of the completion.
+    Done(u16),
+    /// Given up on by [block::wait]; free once the controller completes it.
+    Abandoned,
+}
+
+struct Slot {
+    data: DmaBuffer,
+    /// Addresses of the data pages after the first, for transfers of more than two pages.
+    prp_list: DmaBuffer,
+    state: SlotState,
+}
+
+/// A submission or completion queue. `index` is the tail of a submission queue and the head of
+/// a completion queue; `phase` is the phase bit of a completion queue's new entries.
+struct Queue {
+    entries: DmaBuffer,
+    size: u16,
+    index: u16,
+    phase: bool,
+    doorbell: u64,
+}
+
+impl Queue {
+    fn new(entry_size: usize, size: u16, doorbell: u64) -> Option<Self> {
+        Some(Queue { entries: dma::alloc_contiguous(entry_size * size as usize)?, size, index: 0, phase: true, doorbell })
+    }
+
+    /// Copies `command` to the tail and tells the controller.
+    fn submit(&mut self, command: &[u8; 64]) {
+        unsafe {
+            let entry = self.entries.as_mut_ptr().add(64 * self.index as usize);
+            core::ptr::copy_nonoverlapping(command.as_ptr(), entry, 64);
+        }
+        self.index = (self.index + 1) % self.size;
+        write32(self.doorbell, self.index as u32);
+    }
+
+    /// The next completion, as command identifier and status field, if the controller has
+    /// posted one.
+    fn next_completion(&mut self) -> Option<(u16, u16)> {
+        let entry = unsafe { self.entries.as_ptr().add(16 * self.index as usize) };
+        let status = unsafe { (entry.add(14) as *const u16).read_volatile() };
+        if (status & 1 != 0) != self.phase {
+            return None;
+        }
+        let command = unsafe { (entry.add(12) as *const u16).read_volatile() };
+        self.index = (self.index + 1) % self.size;
+        if self.index == 0 {
+            self.phase = !self.phase;
+        }
+        write32(self.doorbell, self.index as u32);
+        Some((command, status >> 1))
+    }
+}
+
+struct Nvme {
+    /// Unused once the I/O queues exist, but the controller still owns their memory.
+    _admin: (Queue, Queue),
+    io_sq: Queue,
+    io_cq: Queue,
+    slots: Vec<Slot>,
+    /// In sectors.
+    capacity: u64,
+    max_sectors: usize,
+    /// Whether the controller has a volatile write cache to flush.
+    write_cache: bool,
+    interrupts: bool,
+    /// Kept for as long as the controller may interrupt through it.
+    _msix: Option<MsiX>,
+}
+
+static NVME: IrqSafeMutex<Option<Nvme>> = IrqSafeMutex::new(None);
+
+fn read32(address: u64) -> u32 {
+    unsafe { (address as *const u32).read_volatile() }
+}
+
+fn write32(address: u64, value: u32) {
+    unsafe { (address as *mut u32).write_volatile(value) }
+}
+
+fn read64(address: u64) -> u64 {
+    read32(address) as u64 | (read32(address + 4) as u64) << 32
+}
+
+fn write64(address: u64, value: u64) {
+    write32(address, value as u32);
+    write32(address + 4, (value >> 32) as u32);
+}
+
+/// A command with its opcode, identifier and namespace filled in.
+fn command(opcode: u8, id: u16, namespace: u32) -> [u8; 64] {
+    let mut command = [0; 64];
+    command[0] = opcode;
+    command[2..4].copy_from_slice(&id.to_le_bytes());
+    command[4..8].copy_from_slice(&namespace.to_le_bytes());
+    command
+}
+
+fn set_dword(command: &mut [u8; 64], index: usize, value: u32) {
+    command[4 * index..4 * index + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn set_data(command: &mut [u8; 64], prp1: PhysAddr, prp2: PhysAddr) {
+    command[24..32].copy_from_slice(&prp1.as_u64().to_le_bytes());
+    command[32..40].copy_from_slice(&prp2.as_u64().to_le_bytes());
+}
+
+/// Finds an NVMe controller and brings it up with an I/O queue pair. Returns false if there is
+/// none, or it cannot be set up.
+pub fn init() -> bool {
+    let (class, subclass, prog_if) = CLASS;
+    let Some(found) = pci::devices().iter().find(|device| (device.class, device.subclass, device.prog_if) == (class, subclass, prog_if)) else {
+        return false;
+    };
+    let Some(Bar::Memory { address, size, .. }) = found.bars[0] else {
+        writeln!(serial(), "nvme: {} has no register BAR", found).unwrap();
+        return false;
+    };
+    found.address.enable_bus_master();
+    let registers = memory::map_region(PhysAddr::new(address), size, memory::MMIO).as_u64();
+
+    match bring_up(found.address, registers) {
+        Some(nvme) => {
+            writeln!(serial(), "nvme: {:?}, {} sectors ({} MiB), {}", found.address, nvme.capacity, (nvme.capacity * SECTOR_SIZE as u64) >> 20,
+                if nvme.interrupts { "MSI-X" } else { "polled" }).unwrap();
+            *NVME.lock() = Some(nvme);
+            true
+        }
+        None => {
+            writeln!(serial(), "nvme: {:?} could not be set up", found.address).unwrap();
+            false
+        }
+    }
+}
+
+fn bring_up(pci: pci::PciAddress, registers: u64) -> Option<Nvme> {
+    let capabilities = read64(registers + CAP);
+    let max_entries = (capabilities & 0xFFFF) + 1;
+    let stride = 4u64 << ((capabilities >> 32) & 0xF);
+    let ready_timeout = Duration::from_millis(500 * ((capabilities >> 24) & 0xFF).max(1));
+    if (capabilities >> 48) & 0xF != 0 {
+        writeln!(serial(), "nvme: controller does not take 4 KiB pages").unwrap();
+        return None;
+    }
+
+    // Reset, then enable with the admin queues
+    write32(registers + CC, read32(registers + CC) & !CC_ENABLE);
+    if !time::poll_until(ready_timeout, || read32(registers + CSTS) & CSTS_READY == 0) {
+        writeln!(serial(), "nvme: controller did not reset").unwrap();
+        return None;
+    }
+    let doorbell = |queue: u16, completion: bool| registers + DOORBELLS + (2 * queue as u64 + completion as u64) * stride;
+    let mut admin_sq = Queue::new(64, ADMIN_ENTRIES, doorbell(0, false))?;
+    let mut admin_cq = Queue::new(16, ADMIN_ENTRIES, doorbell(0, true))?;
+    write32(registers + AQA, (ADMIN_ENTRIES as u32 - 1) << 16 | (ADMIN_ENTRIES as u32 - 1));
+    write64(registers + ASQ, admin_sq.entries.phys_addr().as_u64());
+    write64(registers + ACQ, admin_cq.entries.phys_addr().as_u64());
+    write32(registers + CC, CC_ENTRY_SIZES | CC_ENABLE);
+    if !time::poll_until(ready_timeout, || read32(registers + CSTS) & (CSTS_READY | CSTS_FATAL) != 0) || read32(registers + CSTS) & CSTS_FATAL != 0 {
+        writeln!(serial(), "nvme: controller did not start").unwrap();
+        return None;
+    }
+
+    let mut admin = |mut command: [u8; 64], data: Option<PhysAddr>| -> Option<()> {
+        if let Some(data) = data {
+            set_data(&mut command, data, PhysAddr::zero());
+        }
+        admin_sq.submit(&command);
+        let mut status = None;
+        time::poll_until(ADMIN_TIMEOUT, || {
+            status = admin_cq.next_completion().map(|(_, status)| status);
+            status.is_some()
+        });
+        (status? == 0).then_some(())
+    };
+
+    let identify = dma::alloc_contiguous(PAGE_SIZE)?;
+    let mut identify_command = command(ADMIN_IDENTIFY, 0, 0);
+    set_dword(&mut identify_command, 10, IDENTIFY_CONTROLLER);
+    admin(identify_command, Some(identify.phys_addr()))?;
+    let controller = identify.as_slice();
+    let max_transfer = controller[77];
+    let write_cache = controller[525] & 1 != 0;
+    let model = core::str::from_utf8(&controller[24..64]).unwrap_or("?").trim_end();
+    writeln!(serial(), "nvme: {}", model).unwrap();
+
+    let mut identify_command = command(ADMIN_IDENTIFY, 0, NAMESPACE);
+    set_dword(&mut identify_command, 10, IDENTIFY_NAMESPACE);
+    admin(identify_command, Some(identify.phys_addr()))?;
+    let namespace = identify.as_slice();
+    let capacity = u64::from_le_bytes(namespace[0..8].try_into().unwrap());
+    let format = (namespace[26] & 0xF) as usize;
+    let sector_size = 1usize << namespace[128 + 4 * format + 2];
+    if capacity == 0 || sector_size != SECTOR_SIZE {
+        writeln!(serial(), "nvme: namespace {} has {} sectors of {} B, need 512 B", NAMESPACE, capacity, sector_size).unwrap();
+        return None;
+    }
+
+    let cpu = acpi_tables::madt().and_then(|madt| madt.boot_processor()).map_or(0, |processor| processor.local_apic_id as u8);
+    let mut msix = MsiX::new(pci);
+    let vector = msix.as_mut().and_then(|msix| {
+        let vector = interrupts::allocate_irq(interrupt)?;
+        msix.set_entry(0, vector, cpu);
+        msix.enable();
+        Some(vector)
+    });
+
+    // Completion queue first, as the submission queue names it
+    let entries = (IO_ENTRIES as u64).min(max_entries) as u16;
+    let io_sq = Queue::new(64, entries, doorbell(IO_QUEUE, false))?;
+    let io_cq = Queue::new(16, entries, doorbell(IO_QUEUE, true))?;
+    let mut create = command(ADMIN_CREATE_CQ, 0, 0);
+    set_data(&mut create, io_cq.entries.phys_addr(), PhysAddr::zero());
+    set_dword(&mut create, 10, (entries as u32 - 1) << 16 | IO_QUEUE as u32);
+    // Physically contiguous, interrupts on entry 0 if there is MSI-X
+    set_dword(&mut create, 11, 1 | if vector.is_some() { 1 << 1 } else { 0 });
+    admin(create, None)?;
+    let mut create = command(ADMIN_CREATE_SQ, 0, 0);
+    set_data(&mut create, io_sq.entries.phys_addr(), PhysAddr::zero());
+    set_dword(&mut create, 10, (entries as u32 - 1) << 16 | IO_QUEUE as u32);
+    set_dword(&mut create, 11, (IO_QUEUE as u32) << 16 | 1);
+    admin(create, None)?;
+
+    let max_sectors = match max_transfer {
+        0 => MAX_SECTORS,
+        exponent => MAX_SECTORS.min((PAGE_SIZE << exponent.min(16)) / SECTOR_SIZE),
+    };
+    let mut slots = Vec::with_capacity(SLOTS);
+    for _ in 0..SLOTS {
+        let data = dma::alloc_contiguous(MAX_SECTORS * SECTOR_SIZE)?;
+        let mut prp_list = dma::alloc_contiguous(PAGE_SIZE)?;
+        for (i, entry) in prp_list.as_mut_slice().as_chunks_mut::<8>().0.iter_mut().take(MAX_SECTORS * SECTOR_SIZE / PAGE_SIZE - 1).enumerate() {
+            *entry = (data.phys_addr() + ((i + 1) * PAGE_SIZE) as u64).as_u64().to_le_bytes();
+        }
+        slots.push(Slot { data, prp_list, state: SlotState::Free });
+    }
+
+    Some(Nvme { _admin: (admin_sq, admin_cq), io_sq, io_cq, slots, capacity, max_sectors, write_cache, interrupts: vector.is_some(), _msix: msix })
+}
+
+impl Nvme {
+    /// Marks the slots the controller has completed done.
+    fn collect(&mut self) {
+        while let Some((id, status)) = self.io_cq.next_completion() {
+            let Some(slot) = self.slots.get_mut(id as usize) else { continue };
+            slot.state = match slot.state {
+                SlotState::Abandoned => SlotState::Free,
+                _ => SlotState::Done(status),
+            };
+        }
+    }
+
+    /// Queues `opcode` for `len` bytes at `sector`, copying in `data` for a write. Returns the
+    /// slot to wait on.
+    fn submit(&mut self, opcode: u8, sector: u64, len: usize, data: Option<&[u8]>) -> Result<usize, BlockError> {
+        let index = self.slots.iter().position(|slot| matches!(slot.state, SlotState::Free)).ok_or(BlockError::Busy)?;
+        let slot = &mut self.slots[index];
+        if let Some(data) = data {
+            slot.data.as_mut_slice()[..data.len()].copy_from_slice(data);
+        }
+
+        let mut entry = command(opcode, index as u16, NAMESPACE);
+        if len > 0 {
+            let base = slot.data.phys_addr();
+            let prp2 = match len.div_ceil(PAGE_SIZE) {
+                1 => PhysAddr::zero(),
+                2 => base + PAGE_SIZE as u64,
+                _ => slot.prp_list.phys_addr(),
+            };
+            set_data(&mut entry, base, prp2);
+            set_dword(&mut entry, 10, sector as u32);
+            set_dword(&mut entry, 11, (sector >> 32) as u32);
+            // Sector count, less one
+            set_dword(&mut entry, 12, (len / SECTOR_SIZE - 1) as u32);
+        }
+        slot.state = SlotState::InFlight;
+        self.io_sq.submit(&entry);
+        Ok(index)
+    }
+
+    /// The result of the request in `slot` if it is done, copying out the data into `out` for a
+    /// read. The slot is free again.
+    fn complete(&mut self, index: usize, out: Option<&mut [u8]>) -> Option<Result<(), BlockError>> {
+        let slot = &mut self.slots[index];
+        let SlotState::Done(status) = slot.state else { return None };
+        slot.state = SlotState::Free;
+        if status != 0 {
+            writeln!(serial(), "nvme: command failed, status {:#x}", status).unwrap();
+            return Some(Err(BlockError::Io));
+        }
+        if let Some(out) = out {
+            out.copy_from_slice(&slot.data.as_slice()[..out.len()]);
+        }
+        Some(Ok(()))
+    }
+}
+
+/// I/O completion queue interrupt.
+fn interrupt() {
+    if let Some(nvme) = NVME.lock().as_mut() {
+        nvme.collect();
+    }
+}
+
+fn wait(slot: usize, mut out: Option<&mut [u8]>) -> Result<(), BlockError> {
+    let interrupt_driven = NVME.lock().as_ref().is_some_and(|nvme| nvme.interrupts);
+    block::wait(interrupt_driven, |timed_out| {
+        let mut guard = NVME.lock();
+        let Some(nvme) = guard.as_mut() else { return Some(Err(BlockError::NoDevice)) };
+        nvme.collect();
+        nvme.complete(slot, out.as_deref_mut()).or_else(|| timed_out.then(|| {
+            nvme.slots[slot].state = SlotState::Abandoned;
+            Err(BlockError::Timeout)
+        }))
+    })
+}
+
+/// Checks a transfer and returns the most sectors a request may carry.
+fn check(sector: u64, len: usize, write: bool) -> Result<usize, BlockError> {
+    let guard = NVME.lock();
+    let nvme = guard.as_ref().ok_or(BlockError::NoDevice)?;
+    block::check_request(sector, len, nvme.capacity, false, write)?;
+    Ok(nvme.max_sectors)
+}
+
+fn submit(opcode: u8, sector: u64, len: usize, data: Option<&[u8]>) -> Result<usize, BlockError> {
+    NVME.lock().as_mut().ok_or(BlockError::NoDevice)?.submit(opcode, sector, len, data)
+}
+
+/// Size of namespace 1 in sectors, 0 without a controller.
+pub fn capacity() -> u64 {
+    NVME.lock().as_ref().map_or(0, |nvme| nvme.capacity)
+}
+
+/// See [block::read].
+pub fn read(sector: u64, buffer: &mut [u8]) -> Result<(), BlockError> {
+    let max_sectors = check(sector, buffer.len(), false)?;
+    for (i, chunk) in buffer.chunks_mut(max_sectors * SECTOR_SIZE).enumerate() {
+        let slot = submit(IO_READ, sector + (i * max_sectors) as u64, chunk.len(), None)?;
+        wait(slot, Some(chunk))?;
+    }
+    Ok(())
+}
+
+/// See [block::write].
+pub fn write(sector: u64, data: &[u8]) -> Result<(), BlockError> {
+    let max_sectors = check(sector, data.len(), true)?;
+    for (i, chunk) in data.chunks(max_sectors * SECTOR_SIZE).enumerate() {
+        let slot = submit(IO_WRITE, sector + (i * max_sectors) as u64, chunk.len(), Some(chunk))?;
+        wait(slot, None)?;
+    }
+    Ok(())
+}
+
+/// Waits until everything written so far is on the disk. Does nothing on a controller without
+/// a volatile write cache.
+pub fn flush() -> Result<(), BlockError> {
+    let write_cache = NVME.lock().as_ref().ok_or(BlockError::NoDevice)?.write_cache;
+    if !write_cache {
+        return Ok(());
+    }
+    let slot = submit(IO_FLUSH, 0, 0, None)?;
+    wait(slot, None)
+}
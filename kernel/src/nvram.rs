@@ -0,0 +1,55 @@
+use alloc::vec::Vec;
+use x86_64::instructions::interrupts;
+use crate::rtc;
+
+// A record of a few bytes kept in the CMOS NVRAM, next to the RTC, so it survives reboots without
+// a disk. It lives in the last 32 of the 128 bytes: below that are the RTC registers, the
+// firmware's settings and what QEMU stores for its firmware (memory sizes, boot order, CPU count).
+//
+// Layout: [MAGIC], the data length, the data, and a Fletcher-16 checksum of all of those. A record
+// that does not check out, as on a machine that never saved one or whose firmware uses the bytes,
+// reads as none.
+
+const START: u8 = 0x60;
+const END: u8 = 0x80;
+const MAGIC: u8 = 0xB7;
+/// Bytes of data a record can hold: the area less the magic, the length and the checksum.
+pub const CAPACITY: usize = (END - START) as usize - 4;
+
+fn fletcher16(bytes: &[u8]) -> u16 {
+    let (low, high) = bytes.iter().fold((0u16, 0u16), |(low, high), &byte| {
+        let low = (low + byte as u16) % 255;
+        (low, (high + low) % 255)
+    });
+    high << 8 | low
+}
+
+/// The saved record's data, or None if there is no valid record.
+pub fn load() -> Option<Vec<u8>> {
+    // Each access is safe on its own (see rtc::read_register); this keeps the record whole
+    let raw: Vec<u8> = interrupts::without_interrupts(|| (START..END).map(rtc::read_register).collect());
+    let len = raw[1] as usize;
+    if raw[0] != MAGIC || len > CAPACITY {
+        return None;
+    }
+    let checksum = u16::from_le_bytes([raw[2 + len], raw[3 + len]]);
+    (fletcher16(&raw[..2 + len]) == checksum).then(|| raw[2..2 + len].to_vec())
+}
+
+/// Replaces the saved record with `data`. Returns false if it is longer than [CAPACITY].
+pub fn save(data: &[u8]) -> bool {
+    if data.len() > CAPACITY {
+        return false;
+    }
+    let mut raw = Vec::with_capacity(data.len() + 4);
+    raw.push(MAGIC);
+    raw.push(data.len() as u8);
+    raw.extend_from_slice(data);
+    raw.extend_from_slice(&fletcher16(&raw).to_le_bytes());
+    interrupts::without_interrupts(|| {
+        for (register, &byte) in (START..).zip(&raw) {
+            rtc::write_register(register, byte);
+        }
+    });
+    true
+}
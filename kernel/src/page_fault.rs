@@ -0,0 +1,120 @@
+use core::fmt;
+use core::fmt::Write;
+use spin::Mutex;
+use x86_64::structures::idt::PageFaultErrorCode;
+use x86_64::structures::paging::mapper::TranslateResult;
+use x86_64::VirtAddr;
+use crate::{memory, serial};
+
+/// What the faulting access was trying to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+/// A decoded page fault.
+#[derive(Debug, Clone, Copy)]
+pub struct PageFault {
+    /// The address that was accessed (CR2).
+    pub address: VirtAddr,
+    /// The instruction that faulted.
+    pub instruction_pointer: VirtAddr,
+    pub error_code: PageFaultErrorCode,
+}
+
+impl PageFault {
+    pub fn access(&self) -> Access {
+        if self.error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH) {
+            Access::Execute
+        } else if self.error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE) {
+            Access::Write
+        } else {
+            Access::Read
+        }
+    }
+
+    /// Whether the page was mapped and the access broke its permissions, as opposed to the page
+    /// not being mapped at all.
+    pub fn is_protection_violation(&self) -> bool {
+        self.error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION)
+    }
+
+    pub fn is_user_mode(&self) -> bool {
+        self.error_code.contains(PageFaultErrorCode::USER_MODE)
+    }
+
+    /// Whether mapping the page could fix the fault, which is what a demand paging handler
+    /// would do. Permission violations and corrupt page tables are always fatal.
+    pub fn is_recoverable(&self) -> bool {
+        !self.is_protection_violation() && !self.error_code.contains(PageFaultErrorCode::MALFORMED_TABLE)
+    }
+}
+
+impl fmt::Display for PageFault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let access = match self.access() {
+            Access::Read => "read from",
+            Access::Write => "write to",
+            Access::Execute => "instruction fetch from",
+        };
+        let page = if self.is_protection_violation() { "protected page" } else { "non-present page" };
+        let mode = if self.is_user_mode() { "user" } else { "kernel" };
+        write!(f, "page fault: {} {} at {:?} from {:?} in {} mode", access, page, self.address, self.instruction_pointer, mode)
+    }
+}
+
+/// Maps the page a recoverable fault needs, see [set_resolver].
+type Resolver = fn(&PageFault) -> bool;
+
+static RESOLVER: Mutex<Option<Resolver>> = Mutex::new(None);
+static DISPLAY: Mutex<Option<fn(&PageFault)>> = Mutex::new(None);
+
+/// Registers a handler for recoverable faults (see [PageFault::is_recoverable]). It returns true
+/// once it has mapped the page, and the faulting instruction is retried.
+pub fn set_resolver(resolver: Resolver) {
+    *RESOLVER.lock() = Some(resolver);
+}
+
+/// Registers a function that shows fatal faults to the user, on top of the serial report.
+pub fn set_display(display: fn(&PageFault)) {
+    *DISPLAY.lock() = Some(display);
+}
+
+/// Gives the resolver a chance to handle `fault`. Returns whether it did.
+pub(crate) fn resolve(fault: &PageFault) -> bool {
+    if !fault.is_recoverable() {
+        return false;
+    }
+    // try_lock: the fault may have hit while the resolver was being registered
+    let resolver = RESOLVER.try_lock().and_then(|resolver| *resolver);
+    resolver.is_some_and(|resolver| resolver(fault))
+}
+
+/// Writes the fault and the page table entry behind its address to serial and the display.
+pub(crate) fn report(fault: &PageFault) {
+    let mut serial = serial();
+    let _ = writeln!(serial, "{}", fault);
+    let _ = writeln!(serial, "  error code: {:?}", fault.error_code);
+    match memory::try_translate(fault.address) {
+        Some(TranslateResult::Mapped { frame, offset, flags }) => {
+            let _ = writeln!(serial, "  mapped to {:?} ({} KiB page), flags {:?}", frame.start_address() + offset, frame.size() / 1024, flags);
+        }
+        Some(TranslateResult::NotMapped) => {
+            let _ = writeln!(serial, "  address is not mapped");
+        }
+        Some(TranslateResult::InvalidFrameAddress(address)) => {
+            let _ = writeln!(serial, "  page table entry points to invalid frame {:?}", address);
+        }
+        None => {
+            let _ = writeln!(serial, "  page tables unavailable");
+        }
+    }
+    let _ = writeln!(serial, "  {}", if fault.is_recoverable() { "no handler mapped the page" } else { "fatal" });
+
+    let display = DISPLAY.try_lock().and_then(|display| *display);
+    if let Some(display) = display {
+        display(fault);
+    }
+}
@@ -0,0 +1,335 @@
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use spin::{Mutex, Once};
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::port::Port;
+use x86_64::{PhysAddr, VirtAddr};
+use crate::{acpi_tables, memory, serial};
+
+// PCI configuration space, and the list of functions found on the bus at boot.
+//
+// Configuration space is reached through the memory-mapped ECAM window the MCFG table describes,
+// or, without one, through the legacy address and data ports. Drivers look their device up in
+// [devices] (or with [find_by_class]) rather than probing the bus themselves.
+
+// https://wiki.osdev.org/PCI#Configuration_Space_Access_Mechanism_.231
+const CONFIG_ADDRESS: u16 = 0xCF8;
+const CONFIG_DATA: u16 = 0xCFC;
+
+const COMMAND: u8 = 0x04;
+const COMMAND_DECODE: u32 = 0x3;
+const HEADER_TYPE_MULTIFUNCTION: u8 = 0x80;
+
+/// The ECAM window for segment group 0, mapped at `base`.
+struct Ecam {
+    base: VirtAddr,
+    start_bus: u8,
+    end_bus: u8,
+}
+
+static ECAM: Once<Ecam> = Once::new();
+/// The address and data ports take two accesses; another CPU's must not come in between.
+static PORTS: Mutex<()> = Mutex::new(());
+static DEVICES: Once<Vec<PciDevice>> = Once::new();
+
+impl Ecam {
+    fn register(&self, address: &PciAddress, offset: u8) -> Option<*mut u32> {
+        if !(self.start_bus..=self.end_bus).contains(&address.bus) {
+            return None;
+        }
+        let offset = ((address.bus - self.start_bus) as u64) << 20
+            | (address.device as u64) << 15
+            | (address.function as u64) << 12
+            | (offset & 0xFC) as u64;
+        Some((self.base + offset).as_mut_ptr())
+    }
+}
+
+/// Location of a PCI function on the bus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciAddress {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl PciAddress {
+    fn config_address(&self, offset: u8) -> u32 {
+        0x8000_0000
+            | (self.bus as u32) << 16
+            | (self.device as u32) << 11
+            | (self.function as u32) << 8
+            | (offset as u32 & 0xFC)
+    }
+
+    pub fn read(&self, offset: u8) -> u32 {
+        if let Some(register) = ECAM.get().and_then(|ecam| ecam.register(self, offset)) {
+            return unsafe { register.read_volatile() };
+        }
+        without_interrupts(|| {
+            let _ports = PORTS.lock();
+            unsafe {
+                Port::<u32>::new(CONFIG_ADDRESS).write(self.config_address(offset));
+                Port::<u32>::new(CONFIG_DATA).read()
+            }
+        })
+    }
+
+    pub fn write(&self, offset: u8, value: u32) {
+        if let Some(register) = ECAM.get().and_then(|ecam| ecam.register(self, offset)) {
+            return unsafe { register.write_volatile(value) };
+        }
+        without_interrupts(|| {
+            let _ports = PORTS.lock();
+            unsafe {
+                Port::<u32>::new(CONFIG_ADDRESS).write(self.config_address(offset));
+                Port::<u32>::new(CONFIG_DATA).write(value);
+            }
+        })
+    }
+
+    /// Whether a function answers at this address.
+    fn exists(&self) -> bool {
+        self.read(0x00) & 0xFFFF != 0xFFFF
+    }
+
+    fn header_type(&self) -> u8 {
+        (self.read(0x0C) >> 16) as u8
+    }
+
+    /// Returns the physical address programmed into the given memory BAR, following 64-bit BARs
+    /// into the next slot.
+    pub fn memory_bar(&self, index: u8) -> u64 {
+        let offset = 0x10 + index * 4;
+        let low = self.read(offset);
+        let address = (low & !0xF) as u64;
+        if low & 0x6 == 0x4 {
+            address | (self.read(offset + 4) as u64) << 32
+        } else {
+            address
+        }
+    }
+
+    /// Returns the port number programmed into the given I/O BAR.
+    pub fn io_bar(&self, index: u8) -> u16 {
+        (self.read(0x10 + index * 4) & !0x3) as u16
+    }
+
+    /// Returns the config space offset of the first capability with the given ID.
+    pub fn find_capability(&self, id: u8) -> Option<u8> {
+        self.capabilities().find(|&(cap_id, _)| cap_id == id).map(|(_, offset)| offset)
+    }
+
+    /// The function's capabilities, as (ID, config space offset) pairs in list order.
+    pub fn capabilities(&self) -> impl Iterator<Item = (u8, u8)> + '_ {
+        // Status register bit 4: the capability list pointer is valid
+        let mut offset = if self.read(COMMAND) & (1 << 20) != 0 { self.read(0x34) as u8 & 0xFC } else { 0 };
+        // Bounded in case of a malformed (looping) list
+        (0..48).map_while(move |_| {
+            if offset == 0 {
+                return None;
+            }
+            let header = self.read(offset);
+            let capability = (header as u8, offset);
+            offset = (header >> 8) as u8 & 0xFC;
+            Some(capability)
+        })
+    }
+
+    /// Enables I/O space decoding, for devices with registers behind [PciAddress::io_bar].
+    pub fn enable_io_space(&self) {
+        let command = self.read(COMMAND);
+        self.write(COMMAND, command | 0x1);
+    }
+
+    /// Enables memory space decoding and bus mastering (DMA) for the function.
+    pub fn enable_bus_master(&self) {
+        let command = self.read(COMMAND);
+        self.write(COMMAND, command | 0x6);
+    }
+}
+
+/// A base address register, decoded and sized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bar {
+    Memory { address: u64, size: u64, prefetchable: bool },
+    Io { port: u16, size: u16 },
+}
+
+impl fmt::Display for Bar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Bar::Memory { address, size, prefetchable } => {
+                write!(f, "mem {:#x} ({} KiB{})", address, size / 1024, if prefetchable { ", prefetchable" } else { "" })
+            }
+            Bar::Io { port, size } => write!(f, "io {:#x} ({} ports)", port, size),
+        }
+    }
+}
+
+/// A function found on the bus at boot.
+#[derive(Debug, Clone, Copy)]
+pub struct PciDevice {
+    pub address: PciAddress,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub header_type: u8,
+    /// Indexed like the registers; the upper half of a 64-bit BAR is None.
+    pub bars: [Option<Bar>; 6],
+}
+
+impl PciDevice {
+    fn new(address: PciAddress) -> Self {
+        let id = address.read(0x00);
+        let class = address.read(0x08);
+        let header_type = address.header_type() & !HEADER_TYPE_MULTIFUNCTION;
+        let mut device = PciDevice {
+            address,
+            vendor_id: id as u16,
+            device_id: (id >> 16) as u16,
+            class: (class >> 24) as u8,
+            subclass: (class >> 16) as u8,
+            prog_if: (class >> 8) as u8,
+            header_type,
+            bars: [None; 6],
+        };
+        // Bridges only have two
+        let bar_count = match header_type {
+            0x00 => 6,
+            0x01 => 2,
+            _ => 0,
+        };
+        let mut index = 0;
+        while index < bar_count {
+            let (bar, slots) = size_bar(address, index as u8);
+            device.bars[index] = bar;
+            index += slots;
+        }
+        device
+    }
+}
+
+impl fmt::Display for PciDevice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let PciAddress { bus, device, function } = self.address;
+        write!(f, "{:02x}:{:02x}.{} {:04x}:{:04x} class {:02x}.{:02x}.{:02x}", bus, device, function, self.vendor_id, self.device_id, self.class, self.subclass, self.prog_if)
+    }
+}
+
+/// Decodes the BAR at `index` and finds its size by writing all ones to it and reading back which
+/// bits stick. Returns the BAR and the number of register slots it takes.
+fn size_bar(address: PciAddress, index: u8) -> (Option<Bar>, usize) {
+    let offset = 0x10 + index * 4;
+    let low = address.read(offset);
+    let is_io = low & 0x1 != 0;
+    let is_64 = !is_io && low & 0x6 == 0x4;
+    let slots = if is_64 { 2 } else { 1 };
+
+    // Stop the function decoding the BAR while it holds the probe value
+    let command = address.read(COMMAND);
+    address.write(COMMAND, command & !COMMAND_DECODE);
+    address.write(offset, 0xFFFF_FFFF);
+    let low_mask = address.read(offset);
+    address.write(offset, low);
+    let high_mask = if is_64 {
+        let high = address.read(offset + 4);
+        address.write(offset + 4, 0xFFFF_FFFF);
+        let mask = address.read(offset + 4);
+        address.write(offset + 4, high);
+        mask
+    } else {
+        0xFFFF_FFFF
+    };
+    address.write(COMMAND, command);
+
+    if is_io {
+        let size = (!(low_mask & !0x3) as u16).wrapping_add(1);
+        let bar = (low_mask & !0x3 != 0).then_some(Bar::Io { port: (low & !0x3) as u16, size });
+        return (bar, slots);
+    }
+    let mask = (high_mask as u64) << 32 | (low_mask & !0xF) as u64;
+    if mask & 0xFFFF_FFFF == 0 && !is_64 {
+        return (None, slots);
+    }
+    let size = (!mask).wrapping_add(1);
+    let bar = (size != 0).then_some(Bar::Memory { address: address.memory_bar(index), size, prefetchable: low & 0x8 != 0 });
+    (bar, slots)
+}
+
+/// Maps the ECAM window from the MCFG table, if there is one, then scans the bus and logs every
+/// function found. Runs once at boot, before any driver looks for its device.
+pub(crate) fn init() {
+    // An entry ending before it starts is broken firmware, and left to port I/O
+    if let Some(region) = acpi_tables::mcfg().iter().find(|region| region.segment_group == 0 && !region.buses.is_empty()) {
+        let (start_bus, end_bus) = (*region.buses.start(), *region.buses.end());
+        let len = ((end_bus - start_bus) as u64 + 1) << 20;
+        let base = memory::map_region(PhysAddr::new(region.base_address), len, memory::MMIO);
+        ECAM.call_once(|| Ecam { base, start_bus, end_bus });
+        writeln!(serial(), "pci: ECAM at {:#x}, buses {}-{}", region.base_address, start_bus, end_bus).unwrap();
+    }
+
+    let devices = DEVICES.call_once(|| {
+        let mut devices = Vec::new();
+        let host = PciAddress { bus: 0, device: 0, function: 0 };
+        if host.header_type() & HEADER_TYPE_MULTIFUNCTION == 0 {
+            scan_bus(0, &mut devices);
+        } else {
+            // Several host bridges: function N of 0:0 is the one for bus N
+            for function in 0..8 {
+                if (PciAddress { bus: 0, device: 0, function }).exists() {
+                    scan_bus(function, &mut devices);
+                }
+            }
+        }
+        devices
+    });
+    for device in devices {
+        write!(serial(), "pci: {}", device).unwrap();
+        for bar in device.bars.iter().flatten() {
+            write!(serial(), ", {}", bar).unwrap();
+        }
+        writeln!(serial()).unwrap();
+    }
+}
+
+/// Adds the functions on `bus` to `devices`, and those behind any PCI-to-PCI bridge on it.
+fn scan_bus(bus: u8, devices: &mut Vec<PciDevice>) {
+    for device in 0..32 {
+        let first = PciAddress { bus, device, function: 0 };
+        if !first.exists() {
+            continue;
+        }
+        let functions = if first.header_type() & HEADER_TYPE_MULTIFUNCTION != 0 { 8 } else { 1 };
+        for function in 0..functions {
+            let address = PciAddress { bus, device, function };
+            if !address.exists() {
+                continue;
+            }
+            let found = PciDevice::new(address);
+            devices.push(found);
+            if found.class == 0x06 && found.subclass == 0x04 {
+                let secondary = (address.read(0x18) >> 8) as u8;
+                // A bridge not set up by the firmware reports bus 0; do not scan bus 0 again
+                if secondary > bus {
+                    scan_bus(secondary, devices);
+                }
+            }
+        }
+    }
+}
+
+/// The functions found on the bus at boot; empty before [init].
+pub fn devices() -> &'static [PciDevice] {
+    DEVICES.get().map_or(&[], Vec::as_slice)
+}
+
+/// Finds the first function with the given class, subclass and programming interface.
+pub fn find_by_class(class: u8, subclass: u8, prog_if: u8) -> Option<PciAddress> {
+    devices()
+        .iter()
+        .find(|device| (device.class, device.subclass, device.prog_if) == (class, subclass, prog_if))
+        .map(|device| device.address)
+}
@@ -0,0 +1,103 @@
+use alloc::boxed::Box;
+use core::arch::asm;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::registers::model_specific::{GsBase, KernelGsBase};
+use x86_64::VirtAddr;
+use crate::spsc::SpscQueue;
+
+// Data each CPU keeps for itself, found through the GS base register: every CPU points GS at its
+// own block, so code reaching for "this CPU's" state needs no lock and no CPU number. State that
+// is really per CPU belongs here rather than in a global static.
+//
+// Loading a selector into GS resets the base, so gdt::init and the AP startup set it after their
+// segment registers. A ring 3 program can do the same, or write the base itself, so [init] also
+// keeps the block's address in the KernelGsBase MSR, which ring 3 cannot reach, and [restore]
+// puts it back on every entry to the kernel while a program runs: the first thing interrupts do
+// (interrupts::count) and system calls do. Nothing uses swapgs.
+
+/// Marks a thread slot as empty, before the scheduler has started.
+const NO_THREAD: u64 = u64::MAX;
+
+#[repr(C)]
+pub struct PerCpu {
+    /// The block's own address, at offset 0 so [current] needs a single load through GS.
+    this: *const PerCpu,
+    /// 0 for the boot CPU, then the application processors in start order.
+    index: usize,
+    /// Id of the thread running on this CPU.
+    current_thread: AtomicU64,
+    /// Interrupts and exceptions taken by this CPU.
+    interrupts: AtomicU64,
+    /// Work deferred by this CPU's interrupt handlers, see [crate::deferred].
+    deferred: SpscQueue<fn(), 32>,
+}
+
+// Only ever used by the CPU it belongs to, apart from the atomics
+unsafe impl Sync for PerCpu {}
+
+impl PerCpu {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn interrupt_count(&self) -> u64 {
+        self.interrupts.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn count_interrupt(&self) {
+        self.interrupts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn current_thread(&self) -> Option<u64> {
+        Some(self.current_thread.load(Ordering::Relaxed)).filter(|&id| id != NO_THREAD)
+    }
+
+    pub(crate) fn set_current_thread(&self, id: u64) {
+        self.current_thread.store(id, Ordering::Relaxed);
+    }
+
+    pub(crate) fn deferred(&self) -> &SpscQueue<fn(), 32> {
+        &self.deferred
+    }
+}
+
+/// Creates the calling CPU's block and points GS at it. Runs once per CPU, after its segment
+/// registers are loaded and before anything uses [current].
+pub fn init(index: usize) {
+    let block = Box::leak(Box::new(PerCpu {
+        this: core::ptr::null(),
+        index,
+        current_thread: AtomicU64::new(NO_THREAD),
+        interrupts: AtomicU64::new(0),
+        deferred: SpscQueue::new(),
+    }));
+    block.this = block;
+    KernelGsBase::write(VirtAddr::from_ptr(block));
+    GsBase::write(VirtAddr::from_ptr(block));
+}
+
+/// Points GS back at the calling CPU's block, wherever ring 3 left it. Call on entry from ring 3
+/// before anything uses [current]; it does nothing before [init].
+pub(crate) fn restore() {
+    let block = KernelGsBase::read();
+    if GsBase::read() != block {
+        GsBase::write(block);
+    }
+}
+
+/// The calling CPU's block. [init] must have run on this CPU; before that GS points nowhere
+/// and this faults.
+pub fn current() -> &'static PerCpu {
+    let this: *const PerCpu;
+    unsafe {
+        asm!("mov {}, gs:[0]", out(reg) this, options(nostack, readonly, preserves_flags));
+        &*this
+    }
+}
+
+/// The calling CPU's block, or None this early in its startup. Reads the GS base register to
+/// check, which is slower than [current]; for code that may run before [init], such as
+/// exception handlers.
+pub fn try_current() -> Option<&'static PerCpu> {
+    (!GsBase::read().is_null()).then(current)
+}
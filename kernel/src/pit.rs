@@ -0,0 +1,73 @@
+use x86_64::instructions::port::Port;
+
+// https://wiki.osdev.org/Programmable_Interval_Timer
+pub const FREQUENCY: u32 = 1_193_182;
+
+const CHANNEL2_DATA: u16 = 0x42;
+const MODE_COMMAND: u16 = 0x43;
+const SPEAKER_CONTROL: u16 = 0x61;
+
+// Longest wait a single 16-bit countdown can cover, ~54.9 ms
+const MAX_COUNT: u32 = 0xFFFF;
+
+/// Busy-waits for the given number of microseconds using PIT channel 2 in one-shot mode.
+/// Works with interrupts disabled, which makes it usable for calibrating other timers at boot.
+pub fn wait_us(us: u64) {
+    let mut remaining = us * FREQUENCY as u64 / 1_000_000;
+    while remaining > 0 {
+        let count = remaining.min(MAX_COUNT as u64) as u16;
+        countdown(count);
+        remaining -= count as u64;
+    }
+}
+
+/// Sounds the PC speaker at `frequency` Hz until [speaker_off], with channel 2 as a square wave
+/// generator. [wait_us] needs channel 2 too, and silences the speaker.
+pub fn speaker_on(frequency: u32) {
+    let divisor = (FREQUENCY / frequency.max(19)).min(MAX_COUNT) as u16;
+    let mut control = Port::<u8>::new(SPEAKER_CONTROL);
+    let mut command = Port::<u8>::new(MODE_COMMAND);
+    let mut data = Port::<u8>::new(CHANNEL2_DATA);
+
+    unsafe {
+        // Channel 2, lobyte/hibyte, mode 3 (square wave)
+        command.write(0b1011_0110);
+        data.write(divisor as u8);
+        data.write((divisor >> 8) as u8);
+        // Gate it on and connect it to the speaker
+        let speaker = control.read();
+        control.write(speaker | 0x03);
+    }
+}
+
+pub fn speaker_off() {
+    let mut control = Port::<u8>::new(SPEAKER_CONTROL);
+    unsafe {
+        let speaker = control.read() & !0x03;
+        control.write(speaker);
+    }
+}
+
+fn countdown(count: u16) {
+    let mut control = Port::<u8>::new(SPEAKER_CONTROL);
+    let mut command = Port::<u8>::new(MODE_COMMAND);
+    let mut data = Port::<u8>::new(CHANNEL2_DATA);
+
+    unsafe {
+        // Gate channel 2 off (and keep the speaker disconnected) while programming it
+        let speaker = control.read() & !0x03;
+        control.write(speaker);
+
+        // Channel 2, lobyte/hibyte, mode 0 (interrupt on terminal count)
+        command.write(0b1011_0000);
+        data.write(count as u8);
+        data.write((count >> 8) as u8);
+
+        // Raise the gate to start counting; bit 5 goes high when the count reaches zero
+        control.write(speaker | 0x01);
+        while control.read() & 0x20 == 0 {
+            core::hint::spin_loop();
+        }
+        control.write(speaker);
+    }
+}
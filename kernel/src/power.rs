@@ -0,0 +1,167 @@
+use core::fmt::Write;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+use x86_64::structures::DescriptorTablePointer;
+use x86_64::VirtAddr;
+use crate::acpi_tables::{self, ResetRegister};
+use crate::qemu::{self, ExitCode};
+use crate::{hlt_loop, serial, time, uart};
+
+// Leaving the machine: rebooting it, or turning it off.
+//
+// Turning off goes through ACPI sleep state S5: the FADT says where the PM1 control registers are,
+// and the \_S5 object in the DSDT holds the SLP_TYP values to write there. The DSDT is AML
+// bytecode; there is no interpreter here, so \_S5 is found by pattern, which works for the
+// simple package every firmware (and QEMU) uses for it.
+
+const PS2_COMMAND: u16 = 0x64;
+const PS2_INPUT_FULL: u8 = 1 << 1;
+const PS2_PULSE_RESET: u8 = 0xFE;
+
+const SCI_EN: u16 = 1 << 0;
+const SLP_EN: u16 = 1 << 13;
+
+/// What S5 needs, from the ACPI tables.
+#[derive(Debug, Clone, Copy)]
+struct SleepControl {
+    pm1a_control: u16,
+    pm1b_control: Option<u16>,
+    sleep_type_a: u16,
+    sleep_type_b: u16,
+    /// Where to write `acpi_enable` if the firmware still owns the power registers.
+    smi_command: u16,
+    acpi_enable: u8,
+}
+
+static SLEEP_CONTROL: Mutex<Option<SleepControl>> = Mutex::new(None);
+static RESET_REGISTER: Mutex<Option<ResetRegister>> = Mutex::new(None);
+static BEFORE_OFF: Mutex<Option<fn()>> = Mutex::new(None);
+
+/// Registers a function to run before the machine is turned off or restarted, to save what has
+/// not been saved yet. It runs with interrupts enabled, and must not wait on a lock its caller may
+/// hold.
+pub fn set_before_off(hook: fn()) {
+    *BEFORE_OFF.lock() = Some(hook);
+}
+
+fn run_before_off() {
+    let hook = BEFORE_OFF.try_lock().and_then(|hook| *hook);
+    if let Some(hook) = hook {
+        hook();
+    }
+}
+
+/// Reads the power management registers from the FADT and the sleep type from the DSDT (see
+/// [acpi_tables]). Without them, [shutdown] and [reboot] fall back to their legacy methods.
+pub(crate) fn init() {
+    let Some(fadt) = acpi_tables::fadt() else { return };
+    *RESET_REGISTER.lock() = fadt.reset;
+
+    let sleep_types = acpi_tables::dsdt().and_then(s5_sleep_types);
+    match (fadt.pm1a_control, sleep_types) {
+        (Some(pm1a_control), Some((sleep_type_a, sleep_type_b))) => {
+            *SLEEP_CONTROL.lock() = Some(SleepControl {
+                pm1a_control,
+                pm1b_control: fadt.pm1b_control,
+                sleep_type_a,
+                sleep_type_b,
+                smi_command: fadt.smi_command,
+                acpi_enable: fadt.acpi_enable,
+            });
+        }
+        _ => writeln!(serial(), "power: no ACPI S5, shutdown only works under QEMU").unwrap(),
+    }
+}
+
+/// SLP_TYPa and SLP_TYPb of \_S5, from `Name(_S5_, Package() { a, b, ... })` in the AML.
+fn s5_sleep_types(aml: &[u8]) -> Option<(u16, u16)> {
+    const PACKAGE_OP: u8 = 0x12;
+    const BYTE_PREFIX: u8 = 0x0A;
+    const ZERO_OP: u8 = 0x00;
+    const ONE_OP: u8 = 0x01;
+
+    let name = aml.windows(4).position(|window| window == b"_S5_")?;
+    let mut rest = aml.get(name + 4..)?;
+    if *rest.first()? != PACKAGE_OP {
+        return None;
+    }
+    // The package length takes 1 to 4 bytes; the top two bits of the first say how many follow
+    let length_bytes = 1 + (*rest.get(1)? >> 6) as usize;
+    // Then the element count
+    rest = rest.get(1 + length_bytes + 1..)?;
+
+    let mut element = || -> Option<u16> {
+        let (value, len) = match *rest.first()? {
+            BYTE_PREFIX => (*rest.get(1)? as u16, 2),
+            ZERO_OP => (0, 1),
+            ONE_OP => (1, 1),
+            _ => return None,
+        };
+        rest = &rest[len..];
+        Some(value)
+    };
+    let a = element()?;
+    let b = element().unwrap_or(0);
+    Some((a, b))
+}
+
+/// Turns the machine off: ACPI S5, or failing that QEMU's isa-debug-exit device. Halts if
+/// neither works.
+pub fn shutdown() -> ! {
+    run_before_off();
+    interrupts::disable();
+    writeln!(serial(), "power: shutting down").unwrap();
+
+    let sleep_control = *SLEEP_CONTROL.lock();
+    if let Some(control) = sleep_control {
+        unsafe {
+            let mut pm1a = Port::<u16>::new(control.pm1a_control);
+            if pm1a.read() & SCI_EN == 0 && control.smi_command != 0 {
+                // The firmware still owns the registers; ask for them
+                Port::<u8>::new(control.smi_command).write(control.acpi_enable);
+                time::poll_until(core::time::Duration::from_secs(1), || pm1a.read() & SCI_EN != 0);
+            }
+            if let Some(pm1b) = control.pm1b_control {
+                Port::<u16>::new(pm1b).write((control.sleep_type_b << 10) | SLP_EN);
+            }
+            pm1a.write((control.sleep_type_a << 10) | SLP_EN);
+        }
+        time::delay_us(100_000);
+    }
+
+    uart::flush();
+    qemu::exit(ExitCode::Success);
+    writeln!(serial(), "power: shutdown failed, halting").unwrap();
+    hlt_loop();
+}
+
+/// Restarts the machine: the ACPI reset register, then a reset pulse from the PS/2 controller,
+/// then a triple fault, which no machine survives.
+pub fn reboot() -> ! {
+    run_before_off();
+    interrupts::disable();
+    writeln!(serial(), "power: rebooting").unwrap();
+
+    let reset_register = *RESET_REGISTER.lock();
+    if let Some(reset) = reset_register {
+        unsafe { Port::<u8>::new(reset.port).write(reset.value) };
+        time::delay_us(100_000);
+    }
+
+    unsafe {
+        let mut command = Port::<u8>::new(PS2_COMMAND);
+        time::poll_until(core::time::Duration::from_millis(100), || command.read() & PS2_INPUT_FULL == 0);
+        command.write(PS2_PULSE_RESET);
+    }
+    time::delay_us(100_000);
+
+    // With an empty IDT, the next exception cannot be delivered, and neither can the double
+    // fault that follows
+    let empty = DescriptorTablePointer { limit: 0, base: VirtAddr::zero() };
+    unsafe {
+        x86_64::instructions::tables::lidt(&empty);
+        core::arch::asm!("int3", options(nomem, nostack));
+    }
+    hlt_loop();
+}
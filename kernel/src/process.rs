@@ -0,0 +1,164 @@
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use pc_keyboard::DecodedKey;
+use spin::Mutex;
+use x86_64::structures::paging::mapper::{MapToError, TranslateResult};
+use x86_64::structures::paging::{PageSize, PageTableFlags, Size4KiB};
+use x86_64::VirtAddr;
+use crate::spsc::SpscQueue;
+use crate::{elf, memory, scheduler, serial, syscall};
+
+// A single ring 3 program at a time, running as a kernel thread that drops to user mode. It gets
+// its own range of the shared address space; all it can reach outside of that is the system
+// calls in syscall.rs.
+
+/// Where programs are loaded. They are position-independent, so any address works.
+const LOAD_BASE: u64 = 0x10_0000_0000;
+/// Programs and their stack must fit below this.
+const USER_END: u64 = 0x20_0000_0000;
+const STACK_SIZE: u64 = 64 * 1024;
+const STACK_TOP: u64 = USER_END;
+
+#[derive(Debug)]
+pub enum LoadError {
+    /// Another program is still running.
+    Busy,
+    /// Not a 64-bit ELF executable, or it does not fit in the user range.
+    InvalidImage,
+    /// The image needs a kind of relocation the loader does not apply.
+    UnsupportedRelocation(u32),
+    /// Part of the user range is already mapped.
+    AddressInUse,
+    OutOfMemory,
+}
+
+static RUNNING: AtomicBool = AtomicBool::new(false);
+static ENTRY: AtomicU64 = AtomicU64::new(0);
+/// The end of the loaded image, which is freed along with the stack when the program exits.
+static IMAGE_END: AtomicU64 = AtomicU64::new(LOAD_BASE);
+static EXIT_CODE: Mutex<Option<i64>> = Mutex::new(None);
+/// Characters typed for the program, taken by the poll_key system call.
+static KEYS: SpscQueue<char, 32> = SpscQueue::new();
+
+/// Loads the ELF executable `image` and starts running it in ring 3 on a thread of its own.
+pub fn spawn(image: &[u8]) -> Result<scheduler::ThreadId, LoadError> {
+    if RUNNING.swap(true, Ordering::Acquire) {
+        return Err(LoadError::Busy);
+    }
+    match load(image) {
+        Ok(entry) => {
+            ENTRY.store(entry.as_u64(), Ordering::Relaxed);
+            *EXIT_CODE.lock() = None;
+            while KEYS.pop().is_some() {}
+            Ok(scheduler::spawn(run))
+        }
+        Err(error) => {
+            free();
+            RUNNING.store(false, Ordering::Release);
+            Err(error)
+        }
+    }
+}
+
+fn load(image: &[u8]) -> Result<VirtAddr, LoadError> {
+    let entry = elf::entry_point(image).ok_or(LoadError::InvalidImage)?;
+    let image_end = elf::load_segments(image).map(|segment| segment.virtual_address + segment.memory_size).max().ok_or(LoadError::InvalidImage)?;
+    if LOAD_BASE + image_end > STACK_TOP - STACK_SIZE {
+        return Err(LoadError::InvalidImage);
+    }
+    IMAGE_END.store(LOAD_BASE + image_end, Ordering::Relaxed);
+
+    // Everything is mapped writable for loading, and gets its final permissions after
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE | PageTableFlags::NO_EXECUTE;
+    let map = |start: u64, len: u64| {
+        // Checked up front, so a failed load only ever frees pages of its own
+        if (start..start + len).step_by(4096).any(|page| memory::is_mapped(VirtAddr::new(page))) {
+            return Err(LoadError::AddressInUse);
+        }
+        memory::alloc_region(VirtAddr::new(start), len, flags).map_err(|error| match error {
+            MapToError::FrameAllocationFailed => LoadError::OutOfMemory,
+            _ => LoadError::AddressInUse,
+        })
+    };
+    map(LOAD_BASE, image_end)?;
+    map(STACK_TOP - STACK_SIZE, STACK_SIZE)?;
+    for segment in elf::load_segments(image) {
+        let file = image.get(segment.offset as usize..(segment.offset + segment.file_size) as usize).ok_or(LoadError::InvalidImage)?;
+        let start = LOAD_BASE + segment.virtual_address;
+        unsafe { (start as *mut u8).copy_from_nonoverlapping(file.as_ptr(), file.len()) };
+    }
+
+    for relocation in elf::relocations(image).map_err(LoadError::UnsupportedRelocation)? {
+        let target = LOAD_BASE + relocation.offset;
+        if !is_user_range(target, 8) {
+            return Err(LoadError::InvalidImage);
+        }
+        unsafe { (target as *mut u64).write_unaligned(LOAD_BASE + relocation.addend) };
+    }
+
+    for segment in elf::load_segments(image) {
+        memory::protect(VirtAddr::new(LOAD_BASE + segment.virtual_address), segment.memory_size, segment.is_writable(), segment.is_executable());
+    }
+    Ok(VirtAddr::new(LOAD_BASE + entry))
+}
+
+fn free() {
+    let image_end = IMAGE_END.load(Ordering::Relaxed);
+    memory::free_region(VirtAddr::new(LOAD_BASE), image_end - LOAD_BASE);
+    memory::free_region(VirtAddr::new(STACK_TOP - STACK_SIZE), STACK_SIZE);
+}
+
+fn run() {
+    let entry = VirtAddr::new(ENTRY.load(Ordering::Relaxed));
+    // Aligned as if _start had been called
+    unsafe { syscall::enter_user_mode(entry, VirtAddr::new(STACK_TOP - 8)) }
+}
+
+/// Whether the `len` bytes at `address` lie in the program's part of the address space.
+pub fn is_user_range(address: u64, len: u64) -> bool {
+    address >= LOAD_BASE && address.checked_add(len).is_some_and(|end| end <= USER_END)
+}
+
+/// Whether the program can read the `len` bytes at `address`: they lie in its part of the address
+/// space, and every page they cover is mapped for ring 3. A system call checks this before it
+/// touches a buffer the program passed, which might point into a gap in its range.
+pub fn is_user_memory(address: u64, len: u64) -> bool {
+    if !is_user_range(address, len) {
+        return false;
+    }
+    let mut pages = (address & !(Size4KiB::SIZE - 1)..address + len).step_by(Size4KiB::SIZE as usize);
+    pages.all(|page| match memory::try_translate(VirtAddr::new(page)) {
+        Some(TranslateResult::Mapped { flags, .. }) => flags.contains(PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE),
+        _ => false,
+    })
+}
+
+/// Whether a program is loaded and has not exited yet.
+pub fn is_running() -> bool {
+    RUNNING.load(Ordering::Acquire)
+}
+
+/// The code the last program exited with, or None while it runs. Killed programs exit with -1.
+pub fn exit_code() -> Option<i64> {
+    *EXIT_CODE.lock()
+}
+
+/// Passes a key to the running program. Only characters are, other keys are dropped.
+pub fn send_key(key: DecodedKey) {
+    if let DecodedKey::Unicode(c) = key {
+        KEYS.push(c);
+    }
+}
+
+pub(crate) fn next_key() -> Option<char> {
+    KEYS.pop()
+}
+
+/// Ends the running program, from its own thread: a system call or a fault it caused.
+pub(crate) fn exit(code: i64) -> ! {
+    writeln!(serial(), "user program exited with code {}", code).unwrap();
+    free();
+    *EXIT_CODE.lock() = Some(code);
+    RUNNING.store(false, Ordering::Release);
+    scheduler::exit()
+}
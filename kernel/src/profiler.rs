@@ -0,0 +1,132 @@
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+use crate::time::Instant;
+
+// Frame profiler. A [Scope] stamps the TSC when it is made and again when it is dropped, and adds
+// the time between to its phase's count, total, minimum and maximum. Recording is a few atomic
+// operations, cheap enough to leave on in every frame. The serial shell's `profile` command and
+// the F3 overlay show the figures; `profile reset` starts them over.
+
+/// The parts of a frame that are timed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// The whole frame, from the tick to the end of present.
+    Frame,
+    /// Draining the queued keyboard, mouse, serial and gamepad input.
+    Input,
+    /// Moving the ball and paddles, including the network game's exchange.
+    Update,
+    Draw,
+    /// Handing the frame to the display.
+    Present,
+}
+
+impl Phase {
+    pub const ALL: [Phase; 5] = [Phase::Frame, Phase::Input, Phase::Update, Phase::Draw, Phase::Present];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::Frame => "frame",
+            Phase::Input => "input",
+            Phase::Update => "update",
+            Phase::Draw => "draw",
+            Phase::Present => "present",
+        }
+    }
+}
+
+struct Counters {
+    count: AtomicU64,
+    total_ns: AtomicU64,
+    min_ns: AtomicU64,
+    max_ns: AtomicU64,
+}
+
+impl Counters {
+    const fn new() -> Self {
+        Self {
+            count: AtomicU64::new(0),
+            total_ns: AtomicU64::new(0),
+            min_ns: AtomicU64::new(u64::MAX),
+            max_ns: AtomicU64::new(0),
+        }
+    }
+}
+
+static COUNTERS: [Counters; Phase::ALL.len()] = [const { Counters::new() }; Phase::ALL.len()];
+
+/// Times a phase until it is dropped.
+#[must_use = "the phase is timed until the scope is dropped"]
+pub struct Scope {
+    phase: Phase,
+    start: Instant,
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        record(self.phase, self.start.elapsed());
+    }
+}
+
+/// Starts timing `phase`; the time counts when the returned scope is dropped.
+pub fn scope(phase: Phase) -> Scope {
+    Scope { phase, start: Instant::now() }
+}
+
+/// Adds one run of `phase` that took `elapsed`.
+pub fn record(phase: Phase, elapsed: Duration) {
+    let ns = elapsed.as_nanos() as u64;
+    let counters = &COUNTERS[phase as usize];
+    counters.count.fetch_add(1, Ordering::Relaxed);
+    counters.total_ns.fetch_add(ns, Ordering::Relaxed);
+    counters.min_ns.fetch_min(ns, Ordering::Relaxed);
+    counters.max_ns.fetch_max(ns, Ordering::Relaxed);
+}
+
+/// What a phase took since the last [reset].
+#[derive(Debug, Clone, Copy)]
+pub struct PhaseStats {
+    pub phase: Phase,
+    pub count: u64,
+    pub min: Duration,
+    pub avg: Duration,
+    pub max: Duration,
+}
+
+impl fmt::Display for PhaseStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:<8} min {:>6} us, avg {:>6} us, max {:>6} us",
+            self.phase.name(), self.min.as_micros(), self.avg.as_micros(), self.max.as_micros())
+    }
+}
+
+pub fn stats(phase: Phase) -> PhaseStats {
+    let counters = &COUNTERS[phase as usize];
+    let count = counters.count.load(Ordering::Relaxed);
+    let total = counters.total_ns.load(Ordering::Relaxed);
+    let min = counters.min_ns.load(Ordering::Relaxed);
+    PhaseStats {
+        phase,
+        count,
+        min: Duration::from_nanos(if count == 0 { 0 } else { min }),
+        avg: Duration::from_nanos(total.checked_div(count).unwrap_or(0)),
+        max: Duration::from_nanos(counters.max_ns.load(Ordering::Relaxed)),
+    }
+}
+
+/// Writes every phase's figures, one line each.
+pub fn write_report(out: &mut impl Write) -> fmt::Result {
+    writeln!(out, "{} frames", stats(Phase::Frame).count)?;
+    Phase::ALL.iter().try_for_each(|&phase| writeln!(out, "{}", stats(phase)))
+}
+
+/// Starts every phase's figures over.
+pub fn reset() {
+    for counters in &COUNTERS {
+        counters.count.store(0, Ordering::Relaxed);
+        counters.total_ns.store(0, Ordering::Relaxed);
+        counters.min_ns.store(u64::MAX, Ordering::Relaxed);
+        counters.max_ns.store(0, Ordering::Relaxed);
+    }
+}
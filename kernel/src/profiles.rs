@@ -0,0 +1,189 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use game::GameMode;
+use kernel::{fat32, serial};
+use pc_keyboard::DecodedKey;
+use crate::PONG;
+use crate::screen::screenwriter;
+use crate::strings::{Text, fill, text};
+
+// Player profiles: a name on each side of the table, and for every name the games won and lost
+// under it, kept in /PROFILES.TXT on the disk as a `name wins losses` line each. The profiles
+// screen, P on the menu, is where the names are typed and the records shown. A one-player game
+// counts for Player 1 only, as the computer keeps no record; a game with no name on a side counts
+// for nobody there. Without a disk the records last until the machine is turned off.
+
+const PATH: &str = "/PROFILES.TXT";
+/// Longest name. Only letters and digits, which the font has and which keep the file simple.
+pub const MAX_NAME: usize = 10;
+/// Records listed on the screen, the most wins first.
+const SHOWN: usize = 10;
+
+/// A name's career.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub name: String,
+    pub wins: u32,
+    pub losses: u32,
+}
+
+pub struct Profiles {
+    /// In the order the names were first played.
+    records: Vec<Record>,
+    /// Player 1's and Player 2's names; empty for nobody.
+    pub players: [String; 2],
+    /// The side whose name is typed on the screen, 0 or 1.
+    editing: usize,
+}
+
+impl Profiles {
+    pub const fn new() -> Self {
+        Self { records: Vec::new(), players: [String::new(), String::new()], editing: 0 }
+    }
+
+    /// Reads the records from the file's text. Lines that are not a record are skipped.
+    pub fn parse(text: &str) -> Self {
+        let mut profiles = Self::new();
+        for line in text.lines() {
+            let mut words = line.split_whitespace();
+            let (Some(name), Some(wins), Some(losses), None) = (words.next(), words.next(), words.next(), words.next()) else { continue };
+            if let (true, Ok(wins), Ok(losses)) = (valid_name(name), wins.parse(), losses.parse()) {
+                profiles.records.push(Record { name: String::from(name), wins, losses });
+            }
+        }
+        profiles
+    }
+
+    /// The records as the file has them.
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for record in &self.records {
+            writeln!(text, "{} {} {}", record.name, record.wins, record.losses).unwrap();
+        }
+        text
+    }
+
+    pub fn record(&self, name: &str) -> Option<&Record> {
+        self.records.iter().find(|record| record.name == name)
+    }
+
+    /// Counts a finished game of `mode` won by `winner`, 1 or 2, for the names playing. Returns
+    /// whether any record changed.
+    pub fn count_game(&mut self, mode: GameMode, winner: u8) -> bool {
+        let sides = match mode {
+            GameMode::OnePlayer => 1,
+            GameMode::TwoPlayer => 2,
+            _ => return false,
+        };
+        let mut changed = false;
+        for player in 0..sides {
+            let name = self.players[player].clone();
+            if name.is_empty() {
+                continue;
+            }
+            let record = match self.records.iter().position(|record| record.name == name) {
+                Some(index) => &mut self.records[index],
+                None => {
+                    self.records.push(Record { name, wins: 0, losses: 0 });
+                    self.records.last_mut().unwrap()
+                }
+            };
+            if winner as usize == player + 1 {
+                record.wins = record.wins.saturating_add(1);
+            } else {
+                record.losses = record.losses.saturating_add(1);
+            }
+            changed = true;
+        }
+        changed
+    }
+
+    /// Handles a key on the profiles screen. Returns false when the player leaves it.
+    pub fn handle_key(&mut self, key: DecodedKey) -> bool {
+        let name = &mut self.players[self.editing];
+        match key {
+            DecodedKey::Unicode('\n' | '\u{1b}') => return false,
+            DecodedKey::Unicode('\t') => self.editing = 1 - self.editing,
+            DecodedKey::Unicode('\u{8}') => {
+                name.pop();
+            }
+            DecodedKey::Unicode(c) if c.is_ascii_alphanumeric() && name.len() < MAX_NAME => name.push(c),
+            _ => {}
+        }
+        true
+    }
+
+    pub fn draw(&self) {
+        let mut writer = screenwriter();
+        writer.draw_string_centered(100, text(Text::ProfilesTitle), 0xFF, 0xFF, 0xFF);
+        writer.draw_string_centered(130, text(Text::ProfilesHelp), 0xAA, 0xAA, 0xAA);
+        for (player, name) in self.players.iter().enumerate() {
+            let line = if player == self.editing {
+                fill(Text::ProfilePlayer, &[&(player + 1), &format_args!("{}_", name)])
+            } else if name.is_empty() {
+                fill(Text::ProfilePlayer, &[&(player + 1), &text(Text::ProfileNobody)])
+            } else {
+                fill(Text::ProfilePlayer, &[&(player + 1), name])
+            };
+            let (r, g, b) = if player == self.editing { (0xFF, 0xFF, 0x55) } else { (0xAA, 0xAA, 0xAA) };
+            writer.draw_string_centered(170 + player * 20, &line, r, g, b);
+        }
+
+        if self.records.is_empty() {
+            writer.draw_string_centered(230, text(Text::ProfilesNone), 0xAA, 0xAA, 0xAA);
+        }
+        let mut records: Vec<&Record> = self.records.iter().collect();
+        records.sort_by(|a, b| b.wins.cmp(&a.wins).then(a.losses.cmp(&b.losses)));
+        for (i, record) in records.into_iter().take(SHOWN).enumerate() {
+            let line = fill(Text::ProfileRecord, &[&record.name, &record.wins, &record.losses]);
+            let playing = self.players.contains(&record.name);
+            let (r, g, b) = if playing { (0xAA, 0xFF, 0xAA) } else { (0xFF, 0xFF, 0xFF) };
+            writer.draw_string_centered(230 + i * 20, &line, r, g, b);
+        }
+    }
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name.len() <= MAX_NAME && name.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+/// Reads the records from the disk. Reads the file, so must not run under PONG.
+pub fn load() -> Profiles {
+    match fat32::read_file(PATH) {
+        Ok(data) => {
+            let profiles = Profiles::parse(&String::from_utf8_lossy(&data));
+            writeln!(serial(), "profiles: {} records from {}", profiles.records.len(), PATH).unwrap();
+            profiles
+        }
+        Err(_) => Profiles::new(),
+    }
+}
+
+/// Writes the records to the disk, replacing what was there. Deferred: writing the file must not
+/// happen under PONG.
+pub fn save() {
+    let text = PONG.lock().profiles.to_text();
+    if let Err(error) = fat32::write_file(PATH, text.as_bytes()) {
+        writeln!(serial(), "profiles: cannot write {}: {:?}", PATH, error).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn profiles_count_games_and_round_trip() {
+        let mut profiles = Profiles::parse("ada 3 1\nnot a record\nbob 0 2\n");
+        profiles.players = [String::from("ada"), String::from("cy")];
+        assert!(profiles.count_game(GameMode::TwoPlayer, 2));
+        assert!(!profiles.count_game(GameMode::Network, 1));
+        assert_eq!((profiles.record("ada").unwrap().wins, profiles.record("ada").unwrap().losses), (3, 2));
+        assert_eq!(profiles.record("cy").unwrap().wins, 1);
+        // Only Player 1 plays a one-player game
+        assert!(profiles.count_game(GameMode::OnePlayer, 1));
+        assert_eq!(profiles.record("cy").unwrap().losses, 0);
+        assert_eq!(Profiles::parse(&profiles.to_text()).to_text(), "ada 4 2\nbob 0 2\ncy 1 0\n");
+    }
+}
@@ -0,0 +1,173 @@
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+use x86_64::instructions::port::Port;
+use crate::{keyboard, serial, time};
+
+// https://wiki.osdev.org/I8042_PS/2_Controller
+const DATA_PORT: u16 = 0x60;
+const COMMAND_PORT: u16 = 0x64;
+
+const READ_CONFIG: u8 = 0x20;
+const WRITE_CONFIG: u8 = 0x60;
+const DISABLE_PORT2: u8 = 0xA7;
+const ENABLE_PORT2: u8 = 0xA8;
+const TEST_PORT2: u8 = 0xA9;
+const SELF_TEST: u8 = 0xAA;
+const TEST_PORT1: u8 = 0xAB;
+const DISABLE_PORT1: u8 = 0xAD;
+const ENABLE_PORT1: u8 = 0xAE;
+const WRITE_PORT2: u8 = 0xD4;
+
+const CONFIG_PORT1_IRQ: u8 = 1 << 0;
+const CONFIG_PORT2_IRQ: u8 = 1 << 1;
+const CONFIG_PORT2_CLOCK_DISABLED: u8 = 1 << 5;
+const CONFIG_TRANSLATION: u8 = 1 << 6;
+
+const DEVICE_RESET: u8 = 0xFF;
+const KEYBOARD_SCANCODE_SET: u8 = 0xF0;
+pub(crate) const ACK: u8 = 0xFA;
+const SELF_TEST_PASSED: u8 = 0xAA;
+
+// How long to wait on the controller before giving up.
+const TIMEOUT: Duration = Duration::from_millis(100);
+
+static KEYBOARD_PRESENT: AtomicBool = AtomicBool::new(false);
+static MOUSE_PRESENT: AtomicBool = AtomicBool::new(false);
+
+/// Whether a keyboard answered on the first PS/2 port during [init].
+pub fn keyboard_present() -> bool {
+    KEYBOARD_PRESENT.load(Ordering::Relaxed)
+}
+
+/// Whether a mouse answered on the second PS/2 port during [init].
+pub fn mouse_present() -> bool {
+    MOUSE_PRESENT.load(Ordering::Relaxed)
+}
+
+/// Initializes the controller from scratch instead of relying on the state the firmware left:
+/// self-test, port tests, device reset and detection, scancode set 2 with translation (so the
+/// decoder sees set 1), and finally interrupts for the ports that have a device.
+/// The second port is only brought up when `use_mouse` is set.
+pub fn init(use_mouse: bool) {
+    command(DISABLE_PORT1);
+    command(DISABLE_PORT2);
+    flush();
+
+    let config = read_config() & !(CONFIG_PORT1_IRQ | CONFIG_PORT2_IRQ);
+    write_config(config);
+
+    command(SELF_TEST);
+    if read_data() != Some(0x55) {
+        writeln!(serial(), "PS/2: controller self-test failed").unwrap();
+        return;
+    }
+    // The self-test may reset the controller
+    write_config(config);
+
+    let mut dual_channel = false;
+    if use_mouse {
+        command(ENABLE_PORT2);
+        dual_channel = read_config() & CONFIG_PORT2_CLOCK_DISABLED == 0;
+        command(DISABLE_PORT2);
+    }
+
+    command(TEST_PORT1);
+    let port1_ok = read_data() == Some(0x00);
+    let port2_ok = dual_channel && {
+        command(TEST_PORT2);
+        read_data() == Some(0x00)
+    };
+
+    if port1_ok {
+        command(ENABLE_PORT1);
+        let keyboard = reset_device(false);
+        if keyboard {
+            // Scancode set 2 on the wire; the controller translates it to set 1
+            send_keyboard(KEYBOARD_SCANCODE_SET);
+            send_keyboard(0x02);
+        }
+        KEYBOARD_PRESENT.store(keyboard, Ordering::Relaxed);
+    }
+    if port2_ok {
+        command(ENABLE_PORT2);
+        let mouse = reset_device(true);
+        // Mice send their device ID after the self-test result
+        read_data();
+        MOUSE_PRESENT.store(mouse, Ordering::Relaxed);
+    }
+    flush();
+
+    let mut config = read_config() | CONFIG_TRANSLATION;
+    if keyboard_present() {
+        config |= CONFIG_PORT1_IRQ;
+    }
+    if mouse_present() {
+        config = (config | CONFIG_PORT2_IRQ) & !CONFIG_PORT2_CLOCK_DISABLED;
+    }
+    write_config(config);
+    // Their acknowledgements come in through the keyboard interrupt
+    keyboard::send_queued();
+
+    writeln!(serial(), "PS/2: keyboard {}, mouse {}",
+        if keyboard_present() { "present" } else { "not found" },
+        if mouse_present() { "present" } else { "not found" }).unwrap();
+}
+
+fn reset_device(port2: bool) -> bool {
+    let acked = if port2 { send_mouse(DEVICE_RESET) } else { send_keyboard(DEVICE_RESET) };
+    // The self-test can take a while, so allow for a few timeouts
+    acked && (0..5).any(|_| read_data() == Some(SELF_TEST_PASSED))
+}
+
+fn read_config() -> u8 {
+    command(READ_CONFIG);
+    read_data().unwrap_or(0)
+}
+
+fn write_config(config: u8) {
+    command(WRITE_CONFIG);
+    write_data(config);
+}
+
+/// Discards any bytes waiting in the output buffer.
+fn flush() {
+    let mut status = Port::<u8>::new(COMMAND_PORT);
+    while unsafe { status.read() } & 0x01 != 0 {
+        unsafe { Port::<u8>::new(DATA_PORT).read() };
+    }
+}
+
+pub(crate) fn command(command: u8) {
+    wait_input_empty();
+    unsafe { Port::<u8>::new(COMMAND_PORT).write(command) };
+}
+
+pub(crate) fn write_data(data: u8) {
+    wait_input_empty();
+    unsafe { Port::<u8>::new(DATA_PORT).write(data) };
+}
+
+pub(crate) fn read_data() -> Option<u8> {
+    let mut status = Port::<u8>::new(COMMAND_PORT);
+    let ready = time::poll_until(TIMEOUT, || unsafe { status.read() } & 0x01 != 0);
+    ready.then(|| unsafe { Port::<u8>::new(DATA_PORT).read() })
+}
+
+fn wait_input_empty() {
+    let mut status = Port::<u8>::new(COMMAND_PORT);
+    time::poll_until(TIMEOUT, || unsafe { status.read() } & 0x02 == 0);
+}
+
+/// Sends a byte to the keyboard and returns whether it was acknowledged.
+pub(crate) fn send_keyboard(byte: u8) -> bool {
+    write_data(byte);
+    read_data() == Some(ACK)
+}
+
+/// Sends a byte to the mouse and returns whether it was acknowledged.
+pub(crate) fn send_mouse(byte: u8) -> bool {
+    command(WRITE_PORT2);
+    write_data(byte);
+    read_data() == Some(ACK)
+}
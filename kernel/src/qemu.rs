@@ -0,0 +1,42 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+use x86_64::instructions::port::Port;
+
+// QEMU's isa-debug-exit device (`-device isa-debug-exit,iobase=0xf4,iosize=0x04`, which the
+// runner adds): a value written to its port ends QEMU with exit status (value << 1) | 1. Lets a
+// headless run, a test kernel in CI say, end itself and report how it went. On a machine without
+// the device the write goes nowhere and [exit] returns.
+
+const PORT: u16 = 0xF4;
+
+/// What [exit] tells QEMU. Both are odd exit statuses, so neither can be mistaken for QEMU
+/// ending normally (0) or failing itself (1).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum ExitCode {
+    Success = 0x10,
+    Failure = 0x11,
+}
+
+impl ExitCode {
+    /// The exit status QEMU ends with: 33 for success, 35 for failure.
+    pub const fn status(self) -> i32 {
+        (self as i32) << 1 | 1
+    }
+}
+
+static EXIT_ON_PANIC: AtomicBool = AtomicBool::new(false);
+
+/// Ends QEMU with `code`. Returns if there is no isa-debug-exit device.
+pub fn exit(code: ExitCode) {
+    unsafe { Port::<u32>::new(PORT).write(code as u32) };
+}
+
+/// Makes the panic handler end QEMU with [ExitCode::Failure] once it has reported the panic,
+/// instead of halting. For test kernels and CI runs, where nobody is watching the screen.
+pub fn set_exit_on_panic(exit: bool) {
+    EXIT_ON_PANIC.store(exit, Ordering::Relaxed);
+}
+
+pub(crate) fn exit_on_panic() -> bool {
+    EXIT_ON_PANIC.load(Ordering::Relaxed)
+}
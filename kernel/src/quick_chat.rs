@@ -0,0 +1,115 @@
+use game::Game;
+use pc_keyboard::DecodedKey;
+use crate::screen::screenwriter;
+use crate::strings::{Text, text};
+use crate::tween::{self, Easing, Tween};
+
+// Quick chat for two players on one keyboard: during a two-player game the number keys send a
+// preset taunt, 1 to 4 Player 1's and 7 to 0 Player 2's, which pops up by the sender's paddle,
+// rises a little and fades away. Keys bound to a paddle move it instead. A new taunt is one more
+// entry in [PRESETS] and its text in strings.rs.
+
+/// The taunts, in the order of the keys that send them.
+const PRESETS: [Text; 4] = [Text::TauntNiceShot, Text::TauntTooEasy, Text::TauntOops, Text::TauntGoodGame];
+/// Player 1's keys, then Player 2's, for [PRESETS] in order.
+const KEYS: [[char; PRESETS.len()]; 2] = [['1', '2', '3', '4'], ['7', '8', '9', '0']];
+/// How long a taunt shows for.
+const SHOW_MS: u32 = 1500;
+/// Pixels a taunt rises while it shows, from just above the paddle.
+const RISE: f32 = 16.0;
+/// Pixels between a taunt and the paddle line.
+const GAP: usize = 12;
+
+#[derive(Clone, Copy)]
+struct Bubble {
+    text: Text,
+    /// From 1 when sent to 0 when gone.
+    life: Tween,
+}
+
+/// Each player's taunt on screen, Player 1's first.
+pub struct QuickChat {
+    bubbles: [Option<Bubble>; 2],
+}
+
+impl QuickChat {
+    pub const fn new() -> Self {
+        Self { bubbles: [None; 2] }
+    }
+
+    /// Sends the taunt `key` is for, if it is one. Returns whether it was.
+    pub fn key(&mut self, key: DecodedKey) -> bool {
+        let DecodedKey::Unicode(c) = key else { return false };
+        for (player, keys) in KEYS.iter().enumerate() {
+            if let Some(preset) = keys.iter().position(|&k| k == c) {
+                self.bubbles[player] = Some(Bubble { text: PRESETS[preset], life: Tween::new(1.0, 0.0, SHOW_MS, Easing::EaseIn) });
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Moves the taunts on by a tick, taking away those whose time is up.
+    pub fn advance(&mut self) {
+        for slot in &mut self.bubbles {
+            if let Some(bubble) = slot {
+                bubble.life.advance();
+                if bubble.life.is_done() {
+                    *slot = None;
+                }
+            }
+        }
+    }
+
+    /// The taunt each player has showing, Player 1's first.
+    pub fn showing(&self) -> [Option<Text>; 2] {
+        self.bubbles.map(|bubble| bubble.map(|bubble| bubble.text))
+    }
+
+    /// Forgets the taunts showing, as at the end of a game.
+    pub fn clear(&mut self) {
+        self.bubbles = [None; 2];
+    }
+
+    /// Draws the taunts above the paddles of `game`, in `color` as far as each has faded.
+    pub fn draw(&self, game: &Game, color: (u8, u8, u8)) {
+        let mut writer = screenwriter();
+        for (player, bubble) in [1, 2].into_iter().zip(&self.bubbles) {
+            let Some(bubble) = bubble else { continue };
+            let paddle = game.paddle(player);
+            let life = bubble.life.value();
+            let line = text(bubble.text);
+            // On the field's side of the paddle, and kept on screen
+            let x = if player == 1 {
+                paddle.x as usize + GAP
+            } else {
+                (paddle.x as usize).saturating_sub(GAP + line.len() * 8)
+            };
+            let rise = (RISE * (1.0 - life)) as usize;
+            let y = (paddle.y as usize).saturating_sub(GAP + 16 + rise);
+            let (r, g, b) = tween::mix((0, 0, 0), color, life);
+            writer.draw_string(x, y, line, r, g, b);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pc_keyboard::KeyCode;
+    use super::*;
+    use crate::tick_hz;
+
+    #[test_case]
+    fn taunts_come_from_the_number_keys_and_fade() {
+        let mut chat = QuickChat::new();
+        assert!(chat.key(DecodedKey::Unicode('1')));
+        assert!(chat.key(DecodedKey::Unicode('0')));
+        assert!(!chat.key(DecodedKey::Unicode('5')));
+        assert!(!chat.key(DecodedKey::RawKey(KeyCode::F1)));
+        assert_eq!(chat.showing(), [Some(Text::TauntNiceShot), Some(Text::TauntGoodGame)]);
+        for _ in 0..2 * tick_hz() {
+            chat.advance();
+        }
+        assert_eq!(chat.showing(), [None, None]);
+    }
+}
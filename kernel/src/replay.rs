@@ -0,0 +1,339 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use game::{Difficulty, GameMode, PADDLE_HEIGHT, PADDLE_INSET};
+use game::ai::Personality;
+use kernel::bridge::MAX_DEVICES;
+use kernel::gamepad::GamepadState;
+use kernel::mouse::MouseEvent;
+use kernel::{config, fat32, rng, serial};
+use pc_keyboard::DecodedKey;
+use crate::controls::RAW_KEYS;
+use crate::{InputEvent, PONG, Pong, netplay};
+
+// Deterministic simulation: records the RNG seed and every input with the tick it was applied
+// at, or replays such a recording, so a physics bug seen once can be reproduced exactly.
+//
+// The boot option `record=on` starts recording at boot; the recording is saved to [PATH] at the
+// end of each game. `replay=<path>` replays a recording instead: the seed and settings are taken
+// from it, the real input is ignored, and the recorded input is applied at the same ticks. When
+// the recording runs out the keyboard works again. While either runs, the AI decides on this CPU,
+// never a frame late on another one. Changes made from the serial shell are not recorded.
+//
+// The recording is text, a line per record. A header first:
+//
+//   version 1
+//   seed <hex>
+//   size <width> <height>            of the playing field
+//   ai easy|normal|hard
+//   personality steady|aggressive|lazy|jittery   steady if left out
+//   mouse on|off <sensitivity>
+//
+// then a line per input or check, in order, `<tick>` counting from the start of the recording:
+//
+//   <tick> key|serial <key>          u+<hex> for a character, or a key's name
+//   <tick> mouse <dx> <dy> <buttons>  left, right and middle as bits 0, 1 and 2
+//   <tick> pad <up> <down> <buttons>  up and down as 0 or 1, buttons in hex
+//   <tick> bridge <device> <up> <down> <buttons>  a controller bridge device, likewise
+//   <tick> check <hex>               the state checksum, every CHECK_TICKS while playing
+//
+// A replay compares its own checksums with the recorded ones, and reports the first tick where
+// they differ: everything after it is a different game.
+
+const VERSION: u32 = 1;
+/// Where recordings are saved.
+pub const PATH: &str = "/REPLAY.TXT";
+const CHECK_TICKS: u64 = 30;
+
+/// A recording being made or replayed, from boot to the end of it.
+pub struct Session {
+    /// [Pong::ticks] when the recording started.
+    start: u64,
+    kind: Kind,
+    /// Whether a game was on at the last tick.
+    was_playing: bool,
+}
+
+enum Kind {
+    Record { text: String },
+    Replay { header: Header, records: Vec<(u64, Record)>, next: usize, diverged: Option<u64> },
+}
+
+#[derive(Clone, Copy)]
+enum Record {
+    Input(InputEvent),
+    Check(u32),
+}
+
+/// What a recording starts from.
+struct Header {
+    seed: u32,
+    size: (usize, usize),
+    difficulty: Difficulty,
+    /// Steady in recordings from before there were personalities.
+    personality: Personality,
+    mouse_control: bool,
+    mouse_sensitivity: usize,
+}
+
+/// Prepares the recording or replay the boot options ask for, if any. Reads the file to replay,
+/// so must not run under PONG.
+pub fn from_config() -> Option<Session> {
+    if let Some(path) = config::get("replay") {
+        let text = match fat32::read_file(path) {
+            Ok(data) => data,
+            Err(error) => {
+                writeln!(serial(), "replay: cannot read {}: {:?}", path, error).unwrap();
+                return None;
+            }
+        };
+        let text = String::from_utf8_lossy(&text);
+        return match parse(&text) {
+            Ok((header, records)) => {
+                writeln!(serial(), "replay: {} records from {}, seed {:#x}", records.len(), path, header.seed).unwrap();
+                let kind = Kind::Replay { header, records, next: 0, diverged: None };
+                Some(Session { start: 0, kind, was_playing: false })
+            }
+            Err(ParseError::Line(line)) => {
+                writeln!(serial(), "replay: {} line {} is not a record", path, line).unwrap();
+                None
+            }
+            Err(ParseError::NoSize) => {
+                writeln!(serial(), "replay: {} has no size line", path).unwrap();
+                None
+            }
+        };
+    }
+    if config::flag("record").unwrap_or(false) {
+        return Some(Session { start: 0, kind: Kind::Record { text: String::new() }, was_playing: false });
+    }
+    None
+}
+
+impl Session {
+    /// Starts the recording or the replay at this tick: seeds the generator, and records or
+    /// applies what else the game depends on.
+    pub fn begin(&mut self, pong: &mut Pong) {
+        self.start = pong.ticks;
+        match &mut self.kind {
+            Kind::Replay { header, .. } => {
+                if header.size != (pong.game.width, pong.game.height) {
+                    writeln!(serial(), "replay: recorded at {}x{}, playing at that size off this screen's {}x{}",
+                        header.size.0, header.size.1, pong.game.width, pong.game.height).unwrap();
+                }
+                pong.game.resize(header.size.0, header.size.1, rng::u32);
+                pong.game.difficulty = header.difficulty;
+                pong.settings.personality = header.personality;
+                pong.settings.mouse_control = header.mouse_control;
+                pong.settings.mouse_sensitivity = header.mouse_sensitivity;
+                rng::seed(header.seed);
+            }
+            Kind::Record { text } => {
+                // Xorshift never gives zero, which the generator would not take
+                let seed = rng::u32();
+                rng::seed(seed);
+                let game = &pong.game;
+                let settings = &pong.settings;
+                writeln!(text, "version {}", VERSION).unwrap();
+                writeln!(text, "seed {:#x}", seed).unwrap();
+                writeln!(text, "size {} {}", game.width, game.height).unwrap();
+                writeln!(text, "ai {}", game.difficulty.name()).unwrap();
+                writeln!(text, "personality {}", settings.personality.name()).unwrap();
+                writeln!(text, "mouse {} {}", if settings.mouse_control { "on" } else { "off" }, settings.mouse_sensitivity).unwrap();
+                writeln!(serial(), "replay: recording, seed {:#x}, saved to {} after each game", seed, PATH).unwrap();
+            }
+        }
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        matches!(self.kind, Kind::Replay { .. })
+    }
+
+    /// Records `event`, applied at this tick.
+    pub(crate) fn record(&mut self, ticks: u64, event: &InputEvent) {
+        let tick = ticks - self.start;
+        let Kind::Record { text } = &mut self.kind else { return };
+        let line = match *event {
+            InputEvent::Key(key) => key_name(key).map(|key| format!("{} key {}", tick, key)),
+            InputEvent::SerialKey(key) => key_name(key).map(|key| format!("{} serial {}", tick, key)),
+            InputEvent::Mouse(event) => {
+                let buttons = event.left as u8 | (event.right as u8) << 1 | (event.middle as u8) << 2;
+                Some(format!("{} mouse {} {} {}", tick, event.dx, event.dy, buttons))
+            }
+            InputEvent::Gamepad(state) => Some(format!("{} pad {} {} {:x}", tick, state.up as u8, state.down as u8, state.buttons)),
+            InputEvent::Controller(device, state) => {
+                Some(format!("{} bridge {} {} {} {:x}", tick, device, state.up as u8, state.down as u8, state.buttons))
+            }
+        };
+        match line {
+            Some(line) => writeln!(text, "{}", line).unwrap(),
+            None => writeln!(serial(), "replay: cannot record that key, the replay will differ").unwrap(),
+        }
+    }
+
+    /// The next recorded input due at this tick, if any.
+    pub(crate) fn next_input(&mut self, ticks: u64) -> Option<InputEvent> {
+        let tick = ticks - self.start;
+        let Kind::Replay { records, next, .. } = &mut self.kind else { return None };
+        match records.get(*next) {
+            Some(&(at, Record::Input(event))) if at <= tick => {
+                *next += 1;
+                Some(event)
+            }
+            _ => None,
+        }
+    }
+
+    /// Runs after the tick's update: records or compares the checksum, and saves the recording
+    /// when a game has ended. Returns false once a replay is over, and the session should go.
+    pub fn after_update(&mut self, pong: &Pong) -> bool {
+        let tick = pong.ticks - self.start;
+        let playing = pong.game.is_playing();
+        let ended = self.was_playing && pong.game.game_mode == GameMode::GameOver;
+        self.was_playing = playing;
+        match &mut self.kind {
+            Kind::Record { text } => {
+                if playing && tick.is_multiple_of(CHECK_TICKS) {
+                    writeln!(text, "{} check {:08x}", tick, netplay::checksum(pong)).unwrap();
+                }
+                if ended {
+                    kernel::deferred::defer(save);
+                }
+                true
+            }
+            Kind::Replay { records, next, diverged, .. } => {
+                while let Some(&(at, Record::Check(expected))) = records.get(*next) {
+                    if at > tick {
+                        break;
+                    }
+                    *next += 1;
+                    let actual = netplay::checksum(pong);
+                    if diverged.is_none() && (at != tick || actual != expected) {
+                        *diverged = Some(at);
+                        writeln!(serial(), "replay: diverged at tick {}, checksum {:08x}, recorded {:08x}", at, actual, expected).unwrap();
+                    }
+                }
+                if *next < records.len() {
+                    return true;
+                }
+                match diverged {
+                    Some(at) => writeln!(serial(), "replay: done after {} ticks, diverged at tick {}", tick, at).unwrap(),
+                    None => writeln!(serial(), "replay: done after {} ticks, every check matched", tick).unwrap(),
+                }
+                false
+            }
+        }
+    }
+}
+
+/// Saves the recording made so far. Deferred: writing the file must not happen under PONG.
+fn save() {
+    let text = match &PONG.lock().replay {
+        Some(Session { kind: Kind::Record { text }, .. }) => text.clone(),
+        _ => return,
+    };
+    match fat32::write_file(PATH, text.as_bytes()) {
+        Ok(()) => writeln!(serial(), "replay: saved {} bytes to {}", text.len(), PATH).unwrap(),
+        Err(error) => writeln!(serial(), "replay: cannot save to {}: {:?}", PATH, error).unwrap(),
+    }
+}
+
+fn key_name(key: DecodedKey) -> Option<String> {
+    match key {
+        DecodedKey::Unicode(c) => Some(format!("u+{:04x}", c as u32)),
+        DecodedKey::RawKey(code) => RAW_KEYS.contains(&code).then(|| format!("{:?}", code)),
+    }
+}
+
+fn parse_key(name: &str) -> Option<DecodedKey> {
+    if let Some(hex) = name.strip_prefix("u+") {
+        return u32::from_str_radix(hex, 16).ok().and_then(char::from_u32).map(DecodedKey::Unicode);
+    }
+    RAW_KEYS.iter().find(|code| format!("{:?}", code) == name).map(|&code| DecodedKey::RawKey(code))
+}
+
+/// Why a recording cannot be replayed.
+enum ParseError {
+    /// The line, from 1, is neither a header line nor a record.
+    Line(usize),
+    /// There is no `size` line, which the field cannot do without.
+    NoSize,
+}
+
+/// The header and the records of a recording.
+fn parse(text: &str) -> Result<(Header, Vec<(u64, Record)>), ParseError> {
+    let mut header = Header { seed: 0, size: (0, 0), difficulty: Difficulty::Normal, personality: Personality::Steady, mouse_control: false, mouse_sensitivity: 4 };
+    let mut sized = false;
+    let mut records = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let record = match fields.as_slice() {
+            [] => continue,
+            ["version", version] => (version.parse() == Ok(VERSION)).then_some(None),
+            ["seed", seed] => seed.strip_prefix("0x").and_then(|seed| u32::from_str_radix(seed, 16).ok()).map(|seed| {
+                header.seed = seed;
+                None
+            }),
+            // Only a field Game::restore would take: the paddles apart, and room for them
+            ["size", width, height] => width.parse().ok().zip(height.parse().ok()).filter(|&(width, height)| width > 2 * PADDLE_INSET && height > PADDLE_HEIGHT).map(|size| {
+                (header.size, sized) = (size, true);
+                None
+            }),
+            ["ai", name] => Difficulty::from_name(name).map(|difficulty| {
+                header.difficulty = difficulty;
+                None
+            }),
+            ["personality", name] => Personality::from_name(name).map(|personality| {
+                header.personality = personality;
+                None
+            }),
+            ["mouse", control, sensitivity] => sensitivity.parse().ok().filter(|_| matches!(*control, "on" | "off")).map(|sensitivity: usize| {
+                header.mouse_control = *control == "on";
+                // As saved.rs keeps it: the settings count down from it
+                header.mouse_sensitivity = sensitivity.clamp(1, 10);
+                None
+            }),
+            [tick, kind, rest @ ..] => tick.parse().ok().zip(parse_record(kind, rest)).map(Some),
+            _ => None,
+        };
+        match record {
+            Some(Some(record)) => records.push(record),
+            Some(None) => {}
+            None => return Err(ParseError::Line(i + 1)),
+        }
+    }
+    if !sized {
+        return Err(ParseError::NoSize);
+    }
+    Ok((header, records))
+}
+
+fn parse_record(kind: &str, fields: &[&str]) -> Option<Record> {
+    let event = match (kind, fields) {
+        ("key", [key]) => InputEvent::Key(parse_key(key)?),
+        ("serial", [key]) => InputEvent::SerialKey(parse_key(key)?),
+        ("mouse", [dx, dy, buttons]) => {
+            let buttons: u8 = buttons.parse().ok()?;
+            InputEvent::Mouse(MouseEvent {
+                dx: dx.parse().ok()?,
+                dy: dy.parse().ok()?,
+                left: buttons & 1 != 0,
+                right: buttons & 2 != 0,
+                middle: buttons & 4 != 0,
+            })
+        }
+        ("pad", [up, down, buttons]) => InputEvent::Gamepad(GamepadState {
+            up: *up == "1",
+            down: *down == "1",
+            buttons: u16::from_str_radix(buttons, 16).ok()?,
+        }),
+        ("bridge", [device, up, down, buttons]) => InputEvent::Controller(
+            device.parse().ok().filter(|&device: &u8| (device as usize) < MAX_DEVICES)?,
+            GamepadState { up: *up == "1", down: *down == "1", buttons: u16::from_str_radix(buttons, 16).ok()? },
+        ),
+        ("check", [checksum]) => return u32::from_str_radix(checksum, 16).ok().map(Record::Check),
+        _ => return None,
+    };
+    Some(Record::Input(event))
+}
@@ -0,0 +1,77 @@
+use core::arch::x86_64::{_rdrand64_step, _rdseed64_step, _rdtsc};
+use core::ops::Range;
+use core::sync::atomic::{AtomicU32, Ordering};
+use crate::cpu;
+
+// Random numbers for the game. A xorshift generator, seeded at boot from the CPU's hardware
+// random number generator (RDSEED, else RDRAND) when it has one and from the TSC otherwise.
+// [seed] restarts it from a known value, so that two machines (netplay), a replay of the same
+// inputs or a restored game state get the same numbers.
+
+/// Xorshift state; never zero.
+static STATE: AtomicU32 = AtomicU32::new(123456789);
+
+/// RDRAND and RDSEED can fail when the hardware has run out of entropy; Intel recommends giving
+/// up after this many tries.
+const RETRIES: usize = 10;
+
+#[target_feature(enable = "rdseed")]
+unsafe fn rdseed() -> Option<u64> {
+    let mut value = 0;
+    (0..RETRIES).any(|_| _rdseed64_step(&mut value) == 1).then_some(value)
+}
+
+#[target_feature(enable = "rdrand")]
+unsafe fn rdrand() -> Option<u64> {
+    let mut value = 0;
+    (0..RETRIES).any(|_| _rdrand64_step(&mut value) == 1).then_some(value)
+}
+
+/// 64 bits from the hardware generator, if the CPU has one and it delivers.
+pub fn hardware() -> Option<u64> {
+    let features = cpu::features();
+    let seed = if features.rdseed { unsafe { rdseed() } } else { None };
+    seed.or_else(|| if features.rdrand { unsafe { rdrand() } } else { None })
+}
+
+/// An unpredictable value: from the hardware generator, or else the TSC.
+pub fn entropy() -> u64 {
+    hardware().unwrap_or_else(|| unsafe { _rdtsc() })
+}
+
+/// Seeds the generator from [entropy]. Runs at boot, after cpu::init.
+pub fn init() {
+    let entropy = entropy();
+    seed(entropy as u32 ^ (entropy >> 32) as u32);
+}
+
+/// Restarts the generator from `seed`; the same seed gives the same numbers. Xorshift never
+/// leaves zero, so a zero seed is ignored.
+pub fn seed(seed: u32) {
+    if seed != 0 {
+        STATE.store(seed, Ordering::Relaxed);
+    }
+}
+
+/// Where the generator is: seeding it with this carries on with the same numbers.
+pub fn state() -> u32 {
+    STATE.load(Ordering::Relaxed)
+}
+
+pub fn u32() -> u32 {
+    let mut x = STATE.load(Ordering::Relaxed);
+    x ^= x << 13;
+    x ^= x >> 17;
+    x ^= x << 5;
+    STATE.store(x, Ordering::Relaxed);
+    x
+}
+
+/// A number in `range`, which must not be empty.
+pub fn range(range: Range<u32>) -> u32 {
+    range.start + u32() % (range.end - range.start)
+}
+
+pub fn bool() -> bool {
+    u32() & 1 != 0
+}
@@ -0,0 +1,122 @@
+use core::fmt;
+use x86_64::instructions::port::Port;
+use crate::sync::IrqSafeMutex;
+
+// https://wiki.osdev.org/CMOS#The_Real-Time_Clock
+const CMOS_ADDRESS: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+const SECONDS: u8 = 0x00;
+const MINUTES: u8 = 0x02;
+const HOURS: u8 = 0x04;
+const DAY: u8 = 0x07;
+const MONTH: u8 = 0x08;
+const YEAR: u8 = 0x09;
+const STATUS_A: u8 = 0x0A;
+const STATUS_B: u8 = 0x0B;
+
+const UPDATE_IN_PROGRESS: u8 = 1 << 7;
+const HOUR_FORMAT_24: u8 = 1 << 1;
+const BINARY_MODE: u8 = 1 << 2;
+const HOUR_PM: u8 = 1 << 7;
+
+/// Calendar date and time of day as kept by the RTC (usually UTC under QEMU).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Seconds since the Unix epoch.
+    pub fn timestamp(&self) -> u64 {
+        // Days from civil, http://howardhinnant.github.io/date_algorithms.html
+        let (year, month) = if self.month <= 2 {
+            (self.year as i64 - 1, self.month as i64 + 9)
+        } else {
+            (self.year as i64, self.month as i64 - 3)
+        };
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let day_of_year = (153 * month + 2) / 5 + self.day as i64 - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146_097 + day_of_era - 719_468;
+
+        days as u64 * 86_400 + self.hour as u64 * 3600 + self.minute as u64 * 60 + self.second as u64
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second)
+    }
+}
+
+/// Held from selecting a register to reading or writing it, which nothing may come between: not
+/// an interrupt handler on this CPU, nor another CPU (crate::nvram shares the ports).
+static CMOS: IrqSafeMutex<()> = IrqSafeMutex::new(());
+
+pub(crate) fn read_register(register: u8) -> u8 {
+    let _cmos = CMOS.lock();
+    unsafe {
+        Port::<u8>::new(CMOS_ADDRESS).write(register);
+        Port::<u8>::new(CMOS_DATA).read()
+    }
+}
+
+pub(crate) fn write_register(register: u8, value: u8) {
+    let _cmos = CMOS.lock();
+    unsafe {
+        Port::<u8>::new(CMOS_ADDRESS).write(register);
+        Port::<u8>::new(CMOS_DATA).write(value);
+    }
+}
+
+fn read_raw() -> [u8; 6] {
+    while read_register(STATUS_A) & UPDATE_IN_PROGRESS != 0 {
+        core::hint::spin_loop();
+    }
+    [SECONDS, MINUTES, HOURS, DAY, MONTH, YEAR].map(read_register)
+}
+
+fn from_bcd(value: u8) -> u8 {
+    (value & 0x0F) + (value >> 4) * 10
+}
+
+/// Reads the current date and time. The registers are read until two consecutive reads agree,
+/// so an update happening mid-read cannot produce a torn value.
+pub fn now() -> DateTime {
+    let mut raw = read_raw();
+    loop {
+        let again = read_raw();
+        if again == raw {
+            break;
+        }
+        raw = again;
+    }
+
+    let status_b = read_register(STATUS_B);
+    let [mut second, mut minute, mut hour, mut day, mut month, mut year] = raw;
+    let pm = hour & HOUR_PM != 0;
+    hour &= !HOUR_PM;
+
+    if status_b & BINARY_MODE == 0 {
+        second = from_bcd(second);
+        minute = from_bcd(minute);
+        hour = from_bcd(hour);
+        day = from_bcd(day);
+        month = from_bcd(month);
+        year = from_bcd(year);
+    }
+    if status_b & HOUR_FORMAT_24 == 0 {
+        // 12 AM is midnight, 12 PM is noon
+        hour = (hour % 12) + if pm { 12 } else { 0 };
+    }
+
+    DateTime { year: 2000 + year as u16, month, day, hour, minute, second }
+}
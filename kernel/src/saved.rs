@@ -0,0 +1,181 @@
+use core::fmt::Write;
+use core::time::Duration;
+use game::ai::Personality;
+use spin::Mutex;
+use kernel::keyboard::{self, Layout};
+use kernel::audio::MAX_LEVEL;
+use kernel::{deferred, nvram, power, serial, timers};
+use crate::Pong;
+use crate::accessibility::SIZES;
+use crate::highscores::COUNT;
+use crate::settings::FRAME_RATES;
+use crate::strings::{self, Language};
+
+// The settings and high scores, saved in the CMOS NVRAM (kernel::nvram) so they survive a reboot
+// without a disk. The key bindings are not saved: they do not fit. Nor is the resolution, which
+// depends on the display there is at the next boot.
+//
+// A change is not written at once: [save] keeps the record, and it is written every [AUTOSAVE]
+// if it changed, and before the machine is turned off or restarted (kernel::power). Closing QEMU
+// loses at most the last few seconds' changes.
+//
+// Layout: [VERSION], a flags byte with the language's index in Language::ALL above the flags, the
+// mouse sensitivity, the keyboard layout's index in Layout::ALL, the master and effects volumes,
+// 1 if the music is on, the frame rate's index in FRAME_RATES, the computer player's personality's
+// index in Personality::ALL, 1 if the training overlay is on, an accessibility byte with the size's
+// index in SIZES above the flags, then the high scores as little-endian u16s. Version 1 had no
+// volumes, version 2 no frame rate, version 3 no personality, version 4 no training overlay and
+// version 5 no accessibility; their records still load, with what they lack at the defaults.
+
+/// How often a changed record is written.
+const AUTOSAVE: Duration = Duration::from_secs(10);
+
+const VERSION: u8 = 6;
+const LEN: usize = 11 + 2 * COUNT;
+const VERSION_1_LEN: usize = 4 + 2 * COUNT;
+const VERSION_2_LEN: usize = 7 + 2 * COUNT;
+const VERSION_3_LEN: usize = 8 + 2 * COUNT;
+const VERSION_4_LEN: usize = 9 + 2 * COUNT;
+const VERSION_5_LEN: usize = 10 + 2 * COUNT;
+
+const MOUSE_CONTROL: u8 = 1 << 0;
+const SHOW_CLOCK: u8 = 1 << 1;
+const LANGUAGE_SHIFT: u8 = 2;
+
+const HIGH_CONTRAST: u8 = 1 << 0;
+const REDUCED_FLASH: u8 = 1 << 1;
+const SIZE_SHIFT: u8 = 2;
+
+/// The record [save] made since the last write, if it made one.
+static PENDING: Mutex<Option<[u8; LEN]>> = Mutex::new(None);
+
+/// Starts writing changes: every [AUTOSAVE], and before the machine goes off.
+pub fn init() {
+    if timers::add(AUTOSAVE, autosave).is_none() {
+        writeln!(serial(), "saved: no timer free, changes are only saved at shutdown").unwrap();
+    }
+    power::set_before_off(flush);
+}
+
+/// Runs in the timer interrupt, which must not allocate nor come between the CMOS's index and
+/// data accesses of the code it interrupted: the write waits for the CPU loop.
+fn autosave() {
+    deferred::defer(flush);
+}
+
+/// Writes the record [save] made, if it has not been written yet.
+pub fn flush() {
+    let pending = PENDING.lock().take();
+    if let Some(data) = pending {
+        nvram::save(&data);
+    }
+}
+
+/// Restores what was saved. Without a valid record, or one of another version, keeps the
+/// defaults.
+pub fn load(pong: &mut Pong) {
+    let Some(data) = nvram::load().filter(|data| match data.first() {
+        Some(&VERSION) => data.len() == LEN,
+        Some(&5) => data.len() == VERSION_5_LEN,
+        Some(&4) => data.len() == VERSION_4_LEN,
+        Some(&3) => data.len() == VERSION_3_LEN,
+        Some(&2) => data.len() == VERSION_2_LEN,
+        Some(&1) => data.len() == VERSION_1_LEN,
+        _ => false,
+    }) else {
+        writeln!(serial(), "saved: no saved settings, using the defaults").unwrap();
+        return;
+    };
+    let settings = &mut pong.settings;
+    settings.mouse_control = data[1] & MOUSE_CONTROL != 0;
+    settings.show_clock = data[1] & SHOW_CLOCK != 0;
+    settings.language = Language::ALL.get((data[1] >> LANGUAGE_SHIFT) as usize).copied().unwrap_or(Language::English);
+    strings::set_language(settings.language);
+    settings.mouse_sensitivity = (data[2] as usize).clamp(1, 10);
+    settings.keyboard_layout = Layout::ALL.get(data[3] as usize).copied().unwrap_or(Layout::Qwerty);
+    keyboard::set_layout(settings.keyboard_layout);
+    let mut scores_at = 4;
+    if data[0] >= 2 {
+        settings.sound.master_volume = data[4].min(MAX_LEVEL);
+        settings.sound.effects_volume = data[5].min(MAX_LEVEL);
+        settings.sound.music = data[6] != 0;
+        settings.sound.apply();
+        scores_at = 7;
+    }
+    if data[0] >= 3 {
+        settings.frame_rate = FRAME_RATES.get(data[7] as usize).copied().unwrap_or(settings.frame_rate);
+        scores_at = 8;
+    }
+    if data[0] >= 4 {
+        settings.personality = Personality::ALL.get(data[8] as usize).copied().unwrap_or(Personality::Steady);
+        scores_at = 9;
+    }
+    if data[0] >= 5 {
+        settings.training_overlay = data[9] != 0;
+        scores_at = 10;
+    }
+    if data[0] >= 6 {
+        let accessibility = &mut settings.accessibility;
+        accessibility.high_contrast = data[10] & HIGH_CONTRAST != 0;
+        accessibility.reduced_flash = data[10] & REDUCED_FLASH != 0;
+        accessibility.size = SIZES.get((data[10] >> SIZE_SHIFT) as usize).copied().unwrap_or(SIZES[0]);
+        scores_at = 11;
+    }
+
+    let mut scores = [0; COUNT];
+    for (score, bytes) in scores.iter_mut().zip(data[scores_at..].as_chunks::<2>().0) {
+        *score = u16::from_le_bytes(*bytes);
+    }
+    pong.high_scores.set_scores(scores);
+    writeln!(serial(), "saved: settings and high scores restored").unwrap();
+}
+
+/// Saves the settings and the high scores, replacing what was saved before, at the next [flush].
+pub fn save(pong: &Pong) {
+    let settings = &pong.settings;
+    let mut data = [0; LEN];
+    data[0] = VERSION;
+    let language = Language::ALL.iter().position(|&language| language == settings.language).unwrap_or(0) as u8;
+    data[1] = if settings.mouse_control { MOUSE_CONTROL } else { 0 } | if settings.show_clock { SHOW_CLOCK } else { 0 }
+        | language << LANGUAGE_SHIFT;
+    data[2] = settings.mouse_sensitivity as u8;
+    data[3] = Layout::ALL.iter().position(|&layout| layout == settings.keyboard_layout).unwrap_or(0) as u8;
+    data[4] = settings.sound.master_volume;
+    data[5] = settings.sound.effects_volume;
+    data[6] = settings.sound.music as u8;
+    data[7] = FRAME_RATES.iter().position(|&rate| rate == settings.frame_rate).unwrap_or(0) as u8;
+    data[8] = settings.personality as u8;
+    data[9] = settings.training_overlay as u8;
+    let accessibility = &settings.accessibility;
+    let size = SIZES.iter().position(|&size| size == accessibility.size).unwrap_or(0) as u8;
+    data[10] = if accessibility.high_contrast { HIGH_CONTRAST } else { 0 }
+        | if accessibility.reduced_flash { REDUCED_FLASH } else { 0 } | size << SIZE_SHIFT;
+    for (bytes, score) in data[11..].as_chunks_mut::<2>().0.iter_mut().zip(pong.high_scores.scores()) {
+        *bytes = score.to_le_bytes();
+    }
+    *PENDING.lock() = Some(data);
+}
+
+#[cfg(test)]
+mod tests {
+    use game::GameMode;
+    use super::*;
+    use crate::tests::game;
+
+    #[test_case]
+    fn settings_are_written_on_flush() {
+        let overlay = || nvram::load().and_then(|data| data.get(9).copied());
+        let mut pong = game(GameMode::Menu);
+        pong.settings.training_overlay = true;
+        save(&pong);
+        flush();
+        assert_eq!(overlay(), Some(1));
+
+        // Kept until the next flush
+        pong.settings.training_overlay = false;
+        save(&pong);
+        assert_eq!(overlay(), Some(1));
+        flush();
+        assert_eq!(overlay(), Some(0));
+    }
+}
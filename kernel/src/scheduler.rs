@@ -0,0 +1,200 @@
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::arch::global_asm;
+use core::time::Duration;
+use spin::Mutex;
+use x86_64::instructions::interrupts::{self, without_interrupts};
+use crate::fpu::{self, FpuState};
+use crate::percpu;
+use crate::time::Instant;
+
+/// Stack size of spawned threads. Interrupt handlers run on the stack of whichever thread they
+/// interrupt, so this must leave room for them too.
+pub const STACK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThreadId(u64);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ready,
+    Sleeping(Instant),
+    Finished,
+}
+
+struct Thread {
+    id: ThreadId,
+    /// Saved stack pointer while the thread is not running.
+    rsp: u64,
+    /// Freed along with the thread. None for the boot thread, which runs on the bootloader's stack.
+    _stack: Option<Box<[u8]>>,
+    /// FPU and SSE registers while the thread is not running.
+    fpu: Box<FpuState>,
+    state: State,
+}
+
+/// Round-robin over all threads. Thread 0 is the boot thread (the CPU loop); it is added when
+/// the first thread is spawned and never finishes.
+struct Scheduler {
+    threads: Vec<Thread>,
+    current: usize,
+    next_id: u64,
+}
+
+static SCHEDULER: Mutex<Scheduler> = Mutex::new(Scheduler { threads: Vec::new(), current: 0, next_id: 1 });
+
+// switch_context(old_rsp: *mut u64, new_rsp: u64) saves the callee-saved registers on the current
+// stack, stores the stack pointer in *old_rsp, and restores the same from new_rsp. Everything
+// else was saved by the caller. A new thread's stack is laid out so that the restore "returns"
+// into thread_start with its entry function in r12.
+global_asm!(
+    ".global scheduler_switch_context",
+    "scheduler_switch_context:",
+    "push rbp",
+    "push rbx",
+    "push r12",
+    "push r13",
+    "push r14",
+    "push r15",
+    "mov [rdi], rsp",
+    "mov rsp, rsi",
+    "pop r15",
+    "pop r14",
+    "pop r13",
+    "pop r12",
+    "pop rbx",
+    "pop rbp",
+    "ret",
+    ".global scheduler_thread_start",
+    "scheduler_thread_start:",
+    "mov rdi, r12",
+    "call {thread_main}",
+    "ud2",
+    thread_main = sym thread_main,
+);
+
+unsafe extern "C" {
+    fn scheduler_switch_context(old_rsp: *mut u64, new_rsp: u64);
+    fn scheduler_thread_start();
+}
+
+extern "C" fn thread_main(entry: usize) -> ! {
+    let entry: fn() = unsafe { core::mem::transmute(entry) };
+    // The FPU still holds whatever the previous thread left in it
+    fpu::reset();
+    // Threads start from inside a switch, where interrupts are off
+    interrupts::enable();
+    entry();
+    exit();
+}
+
+/// Starts a kernel thread running `entry`. It gets the CPU in turn with the other threads,
+/// switching on every timer interrupt.
+pub fn spawn(entry: fn()) -> ThreadId {
+    let mut stack = vec![0u8; STACK_SIZE].into_boxed_slice();
+    let top = (stack.as_mut_ptr() as u64 + STACK_SIZE as u64) & !0xF;
+    // Registers popped by scheduler_switch_context, then its return address, then padding that
+    // leaves the stack 16-byte aligned in thread_start
+    let rsp = top - 9 * 8;
+    let frame = [0, 0, 0, entry as usize as u64, 0, 0, (scheduler_thread_start as unsafe extern "C" fn()) as usize as u64];
+    unsafe { (rsp as *mut [u64; 7]).write(frame) };
+
+    without_interrupts(|| {
+        let mut scheduler = SCHEDULER.lock();
+        if scheduler.threads.is_empty() {
+            scheduler.threads.push(Thread { id: ThreadId(0), rsp: 0, _stack: None, fpu: Box::new(FpuState::new()), state: State::Ready });
+            percpu::current().set_current_thread(0);
+        }
+        let id = ThreadId(scheduler.next_id);
+        scheduler.next_id += 1;
+        scheduler.threads.push(Thread { id, rsp, _stack: Some(stack), fpu: Box::new(FpuState::new()), state: State::Ready });
+        id
+    })
+}
+
+/// The thread running on this CPU, or None before any thread has been spawned.
+pub fn current() -> Option<ThreadId> {
+    percpu::current().current_thread().map(ThreadId)
+}
+
+impl Scheduler {
+    /// Leaves the current thread in `state` and picks the next runnable one. Returns where to
+    /// save the current stack pointer and FPU state, and the stack pointer to switch to, or None
+    /// to keep running the current thread.
+    fn switch_next(&mut self, state: State) -> Option<(*mut u64, *mut FpuState, u64)> {
+        if self.threads.len() < 2 {
+            return None;
+        }
+
+        // Stacks of finished threads can go once they are no longer running on them
+        let current_id = self.threads[self.current].id;
+        self.threads.retain(|thread| thread.state != State::Finished || thread.id == current_id);
+        self.current = self.threads.iter().position(|thread| thread.id == current_id).unwrap_or(0);
+
+        let now = Instant::now();
+        let count = self.threads.len();
+        let next = (1..=count).map(|offset| (self.current + offset) % count).find(|&i| match self.threads[i].state {
+            State::Ready => true,
+            State::Sleeping(until) => until <= now,
+            State::Finished => false,
+        });
+        let next = next.filter(|&next| next != self.current)?;
+
+        self.threads[self.current].state = state;
+        self.threads[next].state = State::Ready;
+        let old = &mut self.threads[self.current].rsp as *mut u64;
+        let fpu = &mut *self.threads[self.current].fpu as *mut FpuState;
+        self.current = next;
+        percpu::current().set_current_thread(self.threads[next].id.0);
+        Some((old, fpu, self.threads[next].rsp))
+    }
+}
+
+/// Switches away from the current thread, leaving it in `state`. Returns whether another thread
+/// ran.
+fn switch(state: State) -> bool {
+    without_interrupts(|| {
+        // The lock must be released before switching; the next thread will want it
+        let Some((old, fpu, new)) = SCHEDULER.lock().switch_next(state) else { return false };
+        // Each thread saves its own FPU state, and restores it once it runs again. The box
+        // lives as long as the thread, which is not freed while it is the one switching.
+        unsafe {
+            (*fpu).save();
+            scheduler_switch_context(old, new);
+            (*fpu).restore();
+        }
+        true
+    })
+}
+
+/// Gives the CPU to the next ready thread. Called from the timer interrupt, after the interrupt
+/// has been acknowledged; the interrupted thread continues from there when its turn comes again.
+pub fn preempt() {
+    switch(State::Ready);
+}
+
+/// Lets other threads run before continuing.
+pub fn yield_now() {
+    switch(State::Ready);
+}
+
+/// Blocks the current thread for at least `duration`. With no other thread to run, the CPU
+/// halts until the next interrupt.
+pub fn sleep(duration: Duration) {
+    let until = Instant::now() + duration;
+    while Instant::now() < until {
+        if !switch(State::Sleeping(until)) {
+            x86_64::instructions::hlt();
+        }
+    }
+}
+
+/// Ends the current thread. Its stack is freed on a later switch.
+pub fn exit() -> ! {
+    loop {
+        switch(State::Finished);
+        // Only the boot thread could be left, and it never exits
+        x86_64::instructions::hlt();
+    }
+}
@@ -1,28 +1,382 @@
-use core::{fmt, ptr};
+use core::{fmt, ptr, slice};
+use core::fmt::Write;
+use core::ops::{Deref, DerefMut, Range};
 use noto_sans_mono_bitmap::{FontWeight, get_raster, RasterizedChar};
 use bootloader_api::info::{FrameBuffer, FrameBufferInfo, PixelFormat};
 use noto_sans_mono_bitmap::RasterHeight::Size16;
-use kernel::RacyCell;
+use kernel::{dma, memory, serial, virtio_gpu};
+use kernel::dma::DmaBuffer;
+use kernel::sync::{IrqSafeMutex, IrqSafeMutexGuard};
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+use crate::ansi_text::AnsiScreen;
+use crate::vga_text::TextScreen;
 
-static WRITER: RacyCell<Option<ScreenWriter>> = RacyCell::new(None);
+static WRITER: IrqSafeMutex<Option<Display>> = IrqSafeMutex::new(None);
+
+/// Height of a line of [Renderer::draw_string_wrapped], in pixels.
+const WRAPPED_LINE_HEIGHT: usize = Size16 as usize + 4;
+
+/// Something the game can draw on. Positions and sizes are in pixels, whatever the screen
+/// really is; characters are 8 pixels wide and 16 high.
+pub trait Renderer: fmt::Write + Send {
+    fn width(&self) -> usize;
+    fn height(&self) -> usize;
+    /// Blanks the screen and moves the text cursor back to the top.
+    fn clear(&mut self);
+    fn clear_screen(&mut self, r: u8, g: u8, b: u8);
+    fn draw_pixel(&mut self, x: usize, y: usize, r: u8, g: u8, b: u8);
+    /// The color at (x, y), or black outside the screen.
+    fn read_pixel(&self, x: usize, y: usize) -> (u8, u8, u8);
+    fn draw_char(&mut self, x: usize, y: usize, c: char, r: u8, g: u8, b: u8);
+
+    fn draw_string(&mut self, x: usize, y: usize, text: &str, r: u8, g: u8, b: u8) {
+        let mut x_pos = x;
+        for c in text.chars() {
+            self.draw_char(x_pos, y, c, r, g, b);
+            // A program may ask for any x, and the text goes off the right edge
+            x_pos = x_pos.saturating_add(8);
+        }
+    }
+
+    fn draw_string_centered(&mut self, y: usize, text: &str, r: u8, g: u8, b: u8) {
+        let x = (self.width() - text.len() * 8) / 2;
+        self.draw_string(x, y, text, r, g, b);
+    }
+
+    /// Draws the heads-up line at the top of the screen: `center` in the middle, and `left` and
+    /// `right` against the edges, with a margin and a height from the top that grow with the
+    /// screen so the line sits alike at any resolution.
+    fn draw_hud(&mut self, left: &str, center: &str, right: &str, r: u8, g: u8, b: u8) {
+        let (margin, y) = (self.width() / 32 + 8, self.height() / 24);
+        self.draw_string(margin, y, left, r, g, b);
+        self.draw_string_centered(y, center, r, g, b);
+        let right_x = self.width().saturating_sub(margin + right.len() * 8);
+        self.draw_string(right_x, y, right, r, g, b);
+    }
+
+    /// Draws `text` from `x`, `y`, wrapping onto further lines at the right edge of the screen
+    /// and at newlines. Returns the y of the line after the last one drawn.
+    fn draw_string_wrapped(&mut self, x: usize, y: usize, text: &str, r: u8, g: u8, b: u8) -> usize {
+        let columns = (self.width().saturating_sub(x) / 8).max(1);
+        let mut y = y;
+        for line in text.split('\n') {
+            let mut chars = line.chars().peekable();
+            loop {
+                let mut x_pos = x;
+                for c in chars.by_ref().take(columns) {
+                    self.draw_char(x_pos, y, c, r, g, b);
+                    x_pos += 8;
+                }
+                y += WRAPPED_LINE_HEIGHT;
+                if chars.peek().is_none() {
+                    break;
+                }
+            }
+        }
+        y
+    }
+}
+
+/// What [screenwriter] draws on: the bootloader's framebuffer, the VGA text buffer without one,
+/// the virtio-gpu's framebuffer once [set_resolution] has switched to it, a terminal on the
+/// serial console when headless, or a back buffer scaled onto one of the framebuffers once
+/// [set_logical_resolution] has been asked for one.
+enum Display {
+    Framebuffer(ScreenWriter),
+    Text(TextScreen),
+    Gpu(ScreenWriter),
+    Serial(AnsiScreen),
+    Scaled(Scaled),
+}
+
+impl Display {
+    fn renderer(&self) -> &(dyn Renderer + 'static) {
+        match self {
+            Display::Framebuffer(writer) | Display::Gpu(writer) => writer,
+            Display::Text(screen) => screen,
+            Display::Serial(screen) => screen,
+            Display::Scaled(scaled) => &scaled.back,
+        }
+    }
+
+    fn renderer_mut(&mut self) -> &mut (dyn Renderer + 'static) {
+        match self {
+            Display::Framebuffer(writer) | Display::Gpu(writer) => writer,
+            Display::Text(screen) => screen,
+            Display::Serial(screen) => screen,
+            Display::Scaled(scaled) => &mut scaled.back,
+        }
+    }
+
+    /// The bootloader's framebuffer.
+    fn framebuffer(&mut self) -> Option<&mut ScreenWriter> {
+        match self {
+            Display::Framebuffer(writer) => Some(writer),
+            Display::Scaled(scaled) if !scaled.gpu => Some(&mut scaled.target),
+            Display::Text(_) | Display::Gpu(_) | Display::Serial(_) | Display::Scaled(_) => None,
+        }
+    }
+}
+
+/// A back buffer at a fixed size, which the game draws on as if it were the screen, put on a
+/// bigger framebuffer (the target) by [present], each pixel a square of `scale` pixels, centered
+/// with black bars round it. The game's geometry is then the same on every display, and drawing
+/// costs the same on a 4K one as at the back buffer's size.
+struct Scaled {
+    back: ScreenWriter,
+    /// The back buffer's pages, which [Self::back] draws on.
+    _pages: DmaBuffer,
+    target: ScreenWriter,
+    /// Whether the target is the virtio-gpu's framebuffer, rather than the bootloader's.
+    gpu: bool,
+    scale: usize,
+    left: usize,
+    top: usize,
+}
+
+impl Scaled {
+    /// Works out where the back buffer goes on the target, which must be at least its size.
+    fn fit(&mut self) {
+        let (target, back) = ((self.target.width(), self.target.height()), (self.back.width(), self.back.height()));
+        (self.scale, self.left, self.top) = scale_to_fit(target, back).unwrap_or((1, 0, 0));
+        // The bars stay black
+        self.target.clear();
+    }
+
+    /// Copies the back buffer onto the target. Each row is scaled once, then copied down.
+    fn present(&mut self) {
+        let bytes_per_pixel = self.target.info.bytes_per_pixel;
+        let stride = self.target.info.stride * bytes_per_pixel;
+        let back_row_len = self.back.width() * 4;
+        let row_len = self.back.width() * self.scale * bytes_per_pixel;
+        for y in 0..self.back.height() {
+            let back_row = &self.back.framebuffer[y * back_row_len..(y + 1) * back_row_len];
+            let start = (self.top + y * self.scale) * stride + self.left * bytes_per_pixel;
+            let row = &mut self.target.framebuffer[start..start + row_len];
+            for (pixel, square) in back_row.as_chunks::<4>().0.iter().zip(row.chunks_exact_mut(self.scale * bytes_per_pixel)) {
+                // The back buffer is BGR
+                let color = match self.target.info.pixel_format {
+                    PixelFormat::Rgb => [pixel[2], pixel[1], pixel[0], 0],
+                    _ => [pixel[0], pixel[1], pixel[2], 0],
+                };
+                for target_pixel in square.chunks_exact_mut(bytes_per_pixel) {
+                    target_pixel.copy_from_slice(&color[..bytes_per_pixel]);
+                }
+            }
+            for i in 1..self.scale {
+                self.target.framebuffer.copy_within(start..start + row_len, start + i * stride);
+            }
+        }
+    }
+}
+
+/// The largest whole scale at which `logical` fits on a screen of size `target`, and where it
+/// then goes to be centered: (scale, left, top). None if it does not fit at all.
+pub fn scale_to_fit(target: (usize, usize), logical: (usize, usize)) -> Option<(usize, usize, usize)> {
+    let scale = target.0.checked_div(logical.0)?.min(target.1.checked_div(logical.1)?);
+    if scale == 0 {
+        return None;
+    }
+    Some((scale, (target.0 - logical.0 * scale) / 2, (target.1 - logical.1 * scale) / 2))
+}
+
+/// Writes text to the screen at the cursor. Output is dropped while the screen is locked
+/// elsewhere, which for a fault report means it hit in the middle of a draw; serial still has it.
 pub struct Writer;
 
 impl fmt::Write for Writer {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        let writer = unsafe { WRITER.get_mut() }.as_mut().unwrap();
-        writer.write_str(s)
+        match WRITER.try_lock() {
+            Some(mut writer) => writer.as_mut().unwrap().renderer_mut().write_str(s),
+            None => Ok(()),
+        }
     }
 }
 
-pub fn screenwriter() -> &'static mut ScreenWriter {
-    unsafe { WRITER.get_mut() }.as_mut().unwrap()
+/// Whether [init] has run and the screen is free, so [Writer] can be used. Never waits: the
+/// out-of-memory report asks, and may be running under a [Screen] of its own CPU.
+pub fn is_ready() -> bool {
+    WRITER.try_lock().is_some_and(|writer| writer.is_some())
+}
+
+/// Locks the screen for drawing. Interrupts are held off until the returned [Screen] is dropped,
+/// so keep it for one statement or one loop at a time.
+pub fn screenwriter() -> Screen {
+    Screen(WRITER.lock())
+}
+
+/// Takes the screen for a crash or panic display, breaking the lock if the code that crashed was
+/// holding it.
+///
+/// ## Safety
+/// Only for displays after which the system halts: whoever held the lock must never run again.
+pub unsafe fn take_over() -> Screen {
+    if WRITER.is_locked() {
+        unsafe { WRITER.force_unlock() };
+    }
+    screenwriter()
+}
+
+/// The locked screen, from [screenwriter].
+pub struct Screen(IrqSafeMutexGuard<'static, Option<Display>>);
+
+impl Deref for Screen {
+    type Target = dyn Renderer;
+
+    fn deref(&self) -> &(dyn Renderer + 'static) {
+        self.0.as_ref().unwrap().renderer()
+    }
+}
+
+impl DerefMut for Screen {
+    fn deref_mut(&mut self) -> &mut (dyn Renderer + 'static) {
+        self.0.as_mut().unwrap().renderer_mut()
+    }
 }
 
 pub fn init(buffer: &'static mut FrameBuffer) {
     let info = buffer.info();
     let framebuffer = buffer.buffer_mut();
     let writer = ScreenWriter::new(framebuffer, info);
-    *unsafe { WRITER.get_mut() } = Some(writer);
+    *WRITER.lock() = Some(Display::Framebuffer(writer));
+}
+
+/// Draws on the VGA text buffer instead, for when the bootloader found no framebuffer. It is
+/// reached through the physical memory map at `physical_offset`.
+pub fn init_text(physical_offset: u64) {
+    *WRITER.lock() = Some(Display::Text(TextScreen::new(physical_offset)));
+}
+
+/// Draws in a terminal on the serial console instead, for running headless; see [AnsiScreen].
+pub fn init_serial() {
+    *WRITER.lock() = Some(Display::Serial(AnsiScreen::new()));
+}
+
+/// Whether the screen is the serial console's terminal, whose keyboard is then the only one.
+pub fn is_serial() -> bool {
+    matches!(*WRITER.lock(), Some(Display::Serial(_)))
+}
+
+/// Switches to the virtio-gpu display at `width`x`height`. Returns false without one, or if the
+/// host refuses the mode; the screen stays as it was then.
+///
+/// With a logical resolution set, the mode has to fit it, and the back buffer is scaled onto it.
+pub fn set_resolution(width: usize, height: usize) -> bool {
+    let mut display = WRITER.lock();
+    if matches!(display.as_ref(), Some(Display::Scaled(scaled)) if scale_to_fit((width, height), (scaled.back.width(), scaled.back.height())).is_none()) {
+        return false;
+    }
+    // The old framebuffer is replaced under the lock, so nothing draws on it again
+    let Some(framebuffer) = (unsafe { virtio_gpu::set_mode(width as u32, height as u32) }) else { return false };
+    let info = FrameBufferInfo { byte_len: framebuffer.len(), width, height, pixel_format: PixelFormat::Bgr, bytes_per_pixel: 4, stride: width };
+    let writer = ScreenWriter::new(framebuffer, info);
+    match display.as_mut() {
+        Some(Display::Scaled(scaled)) => {
+            (scaled.target, scaled.gpu) = (writer, true);
+            scaled.fit();
+        }
+        _ => *display = Some(Display::Gpu(writer)),
+    }
+    true
+}
+
+/// Has the game draw at `width`x`height` from now on, whatever the framebuffer's size, on a back
+/// buffer [present] scales onto it (see [Scaled]). Returns false on the text and serial screens,
+/// if the framebuffer is smaller, or if there is no memory for the back buffer; the screen stays
+/// as it was then.
+pub fn set_logical_resolution(width: usize, height: usize) -> bool {
+    let mut display = WRITER.lock();
+    let target = match display.as_ref() {
+        Some(Display::Framebuffer(target) | Display::Gpu(target)) => target,
+        Some(Display::Scaled(scaled)) => &scaled.target,
+        _ => return false,
+    };
+    if scale_to_fit((target.width(), target.height()), (width, height)).is_none() {
+        return false;
+    }
+    let Some(mut pages) = dma::alloc_contiguous(width * height * 4) else { return false };
+    // The pages stay with the back buffer for as long as it is drawn on
+    let buffer = unsafe { slice::from_raw_parts_mut(pages.as_mut_ptr(), pages.len()) };
+    let info = FrameBufferInfo { byte_len: buffer.len(), width, height, pixel_format: PixelFormat::Bgr, bytes_per_pixel: 4, stride: width };
+    let back = ScreenWriter::new(buffer, info);
+    let (target, gpu) = match display.take() {
+        Some(Display::Framebuffer(target)) => (target, false),
+        Some(Display::Gpu(target)) => (target, true),
+        Some(Display::Scaled(scaled)) => (scaled.target, scaled.gpu),
+        _ => unreachable!(),
+    };
+    let mut scaled = Scaled { back, _pages: pages, target, gpu, scale: 1, left: 0, top: 0 };
+    scaled.fit();
+    *display = Some(Display::Scaled(scaled));
+    true
+}
+
+/// Puts what has been drawn on the display, for the virtio-gpu and the serial terminal, which
+/// only show it when told, and for a scaled back buffer. Call once a frame, with the screen
+/// unlocked.
+pub fn present() {
+    let mut display = WRITER.lock();
+    match display.as_mut() {
+        Some(Display::Gpu(_)) => {
+            drop(display);
+            virtio_gpu::flush();
+        }
+        Some(Display::Scaled(scaled)) => {
+            scaled.present();
+            let gpu = scaled.gpu;
+            drop(display);
+            if gpu {
+                virtio_gpu::flush();
+            }
+        }
+        // Sent with the screen unlocked: serial is slow
+        Some(Display::Serial(screen)) => {
+            let changes = screen.changes();
+            drop(display);
+            let _ = serial().write_str(&changes);
+        }
+        _ => {}
+    }
+}
+
+/// Physical address range of the bootloader's framebuffer, None on the other screens.
+pub fn physical_range() -> Option<Range<u64>> {
+    let (address, len) = {
+        let mut display = WRITER.lock();
+        let writer = display.as_mut()?.framebuffer()?;
+        (VirtAddr::from_ptr(writer.framebuffer.as_ptr()), writer.framebuffer.len())
+    };
+    let (start, _) = memory::translate(address)?;
+    Some(start.as_u64()..start.as_u64() + len as u64)
+}
+
+/// Moves the writer onto a mapping of the framebuffer built from 2 MiB pages where its alignment
+/// allows. The bootloader maps it with 4 KiB pages, so every full-screen redraw went through
+/// hundreds of TLB entries. Keeps the old mapping if the new address is already taken.
+pub fn remap_framebuffer() {
+    let mut display = WRITER.lock();
+    let Some(writer) = display.as_mut().and_then(Display::framebuffer) else { return };
+    let len = writer.framebuffer.len();
+    let Some((phys, _)) = memory::translate(VirtAddr::from_ptr(writer.framebuffer.as_ptr())) else { return };
+
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let virt = memory::map_region(phys, len as u64, flags);
+    // Pages mapped already are left as they were, so any of them may point somewhere else
+    let mut page_size = None;
+    let mut offset = 0;
+    while offset < len as u64 {
+        let Some((address, size)) = memory::translate(virt + offset) else { return };
+        if address != phys + offset {
+            return;
+        }
+        page_size.get_or_insert(size);
+        // On to the start of the next page
+        offset += size - (virt + offset).as_u64() % size;
+    }
+    let Some(page_size) = page_size else { return };
+    writer.framebuffer = unsafe { slice::from_raw_parts_mut(virt.as_mut_ptr(), len) };
+    writeln!(serial(), "Framebuffer remapped at {:?} with {} KiB pages", virt, page_size / 1024).unwrap();
 }
 
 const LINE_SPACING: usize = 0;
@@ -55,29 +409,6 @@ impl ScreenWriter {
         self.x_pos = 0;
     }
 
-    pub fn clear(&mut self) {
-        self.x_pos = 0;
-        self.y_pos = 0;
-        self.framebuffer.fill(0);
-    }
-
-
-    pub fn clear_screen(&mut self, r: u8, g: u8, b: u8) {
-        for y in 0..self.height() {
-            for x in 0..self.width() {
-                self.draw_pixel(x, y, r, g, b);
-            }
-        }
-    }
-
-    pub fn width(&self) -> usize {
-        self.info.width as usize
-    }
-
-    pub fn height(&self) -> usize {
-        self.info.height as usize
-    }
-
     fn write_char(&mut self, c: char) {
         match c {
             '\n' => self.newline(),
@@ -96,7 +427,47 @@ impl ScreenWriter {
         }
     }
 
-    pub fn draw_pixel(&mut self, x: usize, y: usize, r: u8, g: u8, b: u8) {
+    fn write_rendered_char(&mut self, rendered_char: RasterizedChar) {
+        for (y, row) in rendered_char.raster().iter().enumerate() {
+            for (x, &byte) in row.iter().enumerate() {
+                self.draw_pixel(
+                    self.x_pos + x, 
+                    self.y_pos + y,
+                    byte / 4,
+                    byte,
+                    byte / 2
+                );
+            }
+        }
+        self.x_pos += rendered_char.width();
+    }
+}
+
+
+impl Renderer for ScreenWriter {
+    fn width(&self) -> usize {
+        self.info.width as usize
+    }
+
+    fn height(&self) -> usize {
+        self.info.height as usize
+    }
+
+    fn clear(&mut self) {
+        self.x_pos = 0;
+        self.y_pos = 0;
+        self.framebuffer.fill(0);
+    }
+
+    fn clear_screen(&mut self, r: u8, g: u8, b: u8) {
+        for y in 0..self.height() {
+            for x in 0..self.width() {
+                self.draw_pixel(x, y, r, g, b);
+            }
+        }
+    }
+
+    fn draw_pixel(&mut self, x: usize, y: usize, r: u8, g: u8, b: u8) {
         if x >= self.width() || y >= self.height() {
             return;
         }
@@ -120,48 +491,32 @@ impl ScreenWriter {
         }
     }
 
-    pub fn draw_char(&mut self, x: usize, y: usize, c: char, r: u8, g: u8, b: u8) {
+    fn read_pixel(&self, x: usize, y: usize) -> (u8, u8, u8) {
+        let bytes_per_pixel = self.info.bytes_per_pixel as usize;
+        let byte_offset = (y * self.info.stride as usize + x) * bytes_per_pixel;
+        if x >= self.width() || y >= self.height() || byte_offset + 3 > self.framebuffer.len() {
+            return (0, 0, 0);
+        }
+        let pixel = &self.framebuffer[byte_offset..byte_offset + 3];
+        match self.info.pixel_format {
+            PixelFormat::Bgr => (pixel[2], pixel[1], pixel[0]),
+            _ => (pixel[0], pixel[1], pixel[2]),
+        }
+    }
+
+    fn draw_char(&mut self, x: usize, y: usize, c: char, r: u8, g: u8, b: u8) {
         if let Some(bitmap_char) = get_raster(c, FontWeight::Regular, Size16) {
             for (char_y, row) in bitmap_char.raster().iter().enumerate() {
                 for (char_x, &intensity) in row.iter().enumerate() {
                     if intensity > 0 {
-                        self.draw_pixel(x + char_x, y + char_y, r, g, b);
+                        self.draw_pixel(x.saturating_add(char_x), y.saturating_add(char_y), r, g, b);
                     }
                 }
             }
         }
     }
-
-    pub fn draw_string(&mut self, x: usize, y: usize, text: &str, r: u8, g: u8, b: u8) {
-        let mut x_pos = x;
-        for c in text.chars() {
-            self.draw_char(x_pos, y, c, r, g, b);
-            x_pos += 8;
-        }
-    }
-
-    pub fn draw_string_centered(&mut self, y: usize, text: &str, r: u8, g: u8, b: u8) {
-        let x = (self.width() - text.len() * 8) / 2;
-        self.draw_string(x, y, text, r, g, b);
-    }
-
-    fn write_rendered_char(&mut self, rendered_char: RasterizedChar) {
-        for (y, row) in rendered_char.raster().iter().enumerate() {
-            for (x, &byte) in row.iter().enumerate() {
-                self.draw_pixel(
-                    self.x_pos + x, 
-                    self.y_pos + y,
-                    byte / 4,
-                    byte,
-                    byte / 2
-                );
-            }
-        }
-        self.x_pos += rendered_char.width();
-    }
 }
 
-
 unsafe impl Send for ScreenWriter {}
 unsafe impl Sync for ScreenWriter {}
 
@@ -172,4 +527,18 @@ impl fmt::Write for ScreenWriter {
         }
         Ok(())
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn logical_screen_scales_by_whole_steps() {
+        // 4K: five times over, with bars on all four sides
+        assert_eq!(scale_to_fit((3840, 2160), (640, 400)), Some((5, 320, 80)));
+        assert_eq!(scale_to_fit((800, 600), (640, 400)), Some((1, 80, 100)));
+        assert_eq!(scale_to_fit((1280, 800), (640, 400)), Some((2, 0, 0)));
+        assert_eq!(scale_to_fit((640, 360), (640, 400)), None);
+        assert_eq!(scale_to_fit((640, 400), (0, 400)), None);
+    }
+}
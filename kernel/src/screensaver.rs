@@ -0,0 +1,112 @@
+use game::GameMode;
+use crate::Pong;
+use crate::screen::screenwriter;
+
+// Screensaver for the menu: after [DEFAULT_MINUTES] minutes without input there, or as many as the
+// boot option `screensaver=<minutes>` says (0 turns it off), the menu gives way to the game's logo
+// bouncing round a black screen and slowly changing color, so a real display left on the menu does
+// not keep the same picture burnt into it. Any input brings the menu back, and is not passed on.
+
+const DEFAULT_MINUTES: u64 = 5;
+const LOGO_WIDTH: usize = 160;
+const LOGO_HEIGHT: usize = 64;
+/// Pixels the logo moves a tick, across and down.
+const SPEED: isize = 1;
+/// Ticks to go once round the color wheel.
+const CYCLE_TICKS: u64 = 600;
+
+pub struct Screensaver {
+    x: usize,
+    y: usize,
+    dx: isize,
+    dy: isize,
+}
+
+/// Ticks without input before the screensaver starts, or None if it is off.
+fn timeout() -> Option<u64> {
+    let minutes = kernel::config::value("screensaver").unwrap_or(DEFAULT_MINUTES);
+    (minutes > 0).then(|| minutes * 60 * crate::tick_hz() as u64)
+}
+
+/// Starts the screensaver once the menu has been left alone long enough, and moves the logo.
+pub fn update(pong: &mut Pong) {
+    // Only time on the menu counts
+    if pong.game.game_mode != GameMode::Menu {
+        pong.screensaver = None;
+        pong.last_input = pong.ticks;
+        return;
+    }
+    let (width, height) = (pong.game.width, pong.game.height);
+    match &mut pong.screensaver {
+        Some(screensaver) => screensaver.step(width, height),
+        None => {
+            if timeout().is_some_and(|timeout| pong.ticks.saturating_sub(pong.last_input) >= timeout) {
+                let (x, y) = (width.saturating_sub(LOGO_WIDTH) / 2, height.saturating_sub(LOGO_HEIGHT) / 2);
+                pong.screensaver = Some(Screensaver { x, y, dx: SPEED, dy: SPEED });
+            }
+        }
+    }
+}
+
+/// Notes input, and stops the screensaver if it was on. Returns true if it was, so that the
+/// input is not taken as a choice on the menu.
+pub fn wake(pong: &mut Pong) -> bool {
+    pong.last_input = pong.ticks;
+    pong.screensaver.take().is_some()
+}
+
+impl Screensaver {
+    fn step(&mut self, width: usize, height: usize) {
+        let (max_x, max_y) = (width.saturating_sub(LOGO_WIDTH) as isize, height.saturating_sub(LOGO_HEIGHT) as isize);
+        let (x, y) = (self.x as isize + self.dx, self.y as isize + self.dy);
+        if x < 0 || x > max_x {
+            self.dx = -self.dx;
+        }
+        if y < 0 || y > max_y {
+            self.dy = -self.dy;
+        }
+        self.x = x.clamp(0, max_x) as usize;
+        self.y = y.clamp(0, max_y) as usize;
+    }
+
+    /// The logo, a court with its two paddles, the ball and the name, all in one color.
+    pub fn draw(&self, ticks: u64) {
+        let (r, g, b) = hue(ticks % CYCLE_TICKS * 360 / CYCLE_TICKS);
+        let (x, y) = (self.x, self.y);
+        let mut writer = screenwriter();
+        for i in 0..LOGO_WIDTH {
+            writer.draw_pixel(x + i, y, r, g, b);
+            writer.draw_pixel(x + i, y + LOGO_HEIGHT - 1, r, g, b);
+        }
+        for i in 0..LOGO_HEIGHT {
+            writer.draw_pixel(x, y + i, r, g, b);
+            writer.draw_pixel(x + LOGO_WIDTH - 1, y + i, r, g, b);
+        }
+        for i in 0..24 {
+            for j in 0..4 {
+                writer.draw_pixel(x + 8 + j, y + 12 + i, r, g, b);
+                writer.draw_pixel(x + LOGO_WIDTH - 12 + j, y + 28 + i, r, g, b);
+            }
+        }
+        for i in 0..6 {
+            for j in 0..6 {
+                writer.draw_pixel(x + 40 + j, y + 16 + i, r, g, b);
+            }
+        }
+        writer.draw_string(x + (LOGO_WIDTH - 4 * 8) / 2, y + 24, "PONG", r, g, b);
+    }
+}
+
+/// A fully saturated color at `degrees` round the color wheel, from red through green and blue.
+pub fn hue(degrees: u64) -> (u8, u8, u8) {
+    let rise = (degrees % 60 * 255 / 60) as u8;
+    let fall = 255 - rise;
+    match degrees / 60 {
+        0 => (255, rise, 0),
+        1 => (fall, 255, 0),
+        2 => (0, 255, rise),
+        3 => (0, fall, 255),
+        4 => (rise, 0, 255),
+        _ => (255, 0, fall),
+    }
+}
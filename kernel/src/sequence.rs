@@ -0,0 +1,83 @@
+use alloc::vec::Vec;
+use core::time::Duration;
+use kernel::time::Instant;
+use pc_keyboard::DecodedKey;
+
+struct Pattern<T> {
+    tag: T,
+    keys: &'static [DecodedKey],
+    timeout: Duration,
+    progress: usize,
+    last_key: Option<Instant>,
+}
+
+/// Watches the key stream for registered key sequences (cheat codes, debug commands).
+/// Each key of a sequence must follow the previous one within the pattern's timeout.
+pub struct SequenceDetector<T> {
+    patterns: Vec<Pattern<T>>,
+}
+
+impl<T: Copy> SequenceDetector<T> {
+    pub const fn new() -> Self {
+        Self { patterns: Vec::new() }
+    }
+
+    /// Registers a sequence. `tag` is returned by [SequenceDetector::feed] when it is completed.
+    /// An empty one is ignored, as no key press could complete it.
+    pub fn register(&mut self, tag: T, keys: &'static [DecodedKey], timeout: Duration) {
+        if keys.is_empty() {
+            return;
+        }
+        self.patterns.push(Pattern { tag, keys, timeout, progress: 0, last_key: None });
+    }
+
+    /// Feeds a key press. Returns the tag of a sequence it completes.
+    pub fn feed(&mut self, key: DecodedKey) -> Option<T> {
+        let now = Instant::now();
+        let mut completed = None;
+
+        for pattern in self.patterns.iter_mut() {
+            let timed_out = pattern.last_key.is_some_and(|last| now.duration_since(last) > pattern.timeout);
+            if timed_out {
+                pattern.progress = 0;
+            }
+
+            pattern.progress = advance(pattern.keys, pattern.progress, key);
+            pattern.last_key = Some(now);
+
+            if pattern.progress == pattern.keys.len() {
+                pattern.progress = 0;
+                completed = completed.or(Some(pattern.tag));
+            }
+        }
+
+        completed
+    }
+}
+
+/// How much of `keys` is matched once `key` follows the first `matched` of them: the longest
+/// start of `keys` that the keys seen end with, as in Knuth-Morris-Pratt. After a mismatch in
+/// "up up down", a third "up" still leaves "up up" matched.
+fn advance(keys: &[DecodedKey], matched: usize, key: DecodedKey) -> usize {
+    (0..=matched)
+        .rev()
+        .find(|&len| keys[len] == key && keys[matched - len..matched] == keys[..len])
+        .map_or(0, |len| len + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn a_mismatch_keeps_what_still_matches() {
+        static KEYS: [DecodedKey; 3] = [DecodedKey::Unicode('w'), DecodedKey::Unicode('w'), DecodedKey::Unicode('s')];
+        let mut detector = SequenceDetector::new();
+        detector.register((), &KEYS, Duration::from_secs(1));
+        detector.register((), &[], Duration::from_secs(1));
+        let fed = "wwws".chars().map(|c| detector.feed(DecodedKey::Unicode(c))).collect::<Vec<_>>();
+        assert_eq!(fed, [None, None, None, Some(())]);
+        let fed = "wsws".chars().map(|c| detector.feed(DecodedKey::Unicode(c))).collect::<Vec<_>>();
+        assert_eq!(fed, [None, None, None, None]);
+    }
+}
@@ -0,0 +1,57 @@
+use pc_keyboard::{DecodedKey, KeyCode};
+
+/// Turns bytes received on the serial console into keys, including the ANSI escape sequences
+/// terminals send for the arrow keys (`ESC [ A` and friends).
+pub struct SerialDecoder {
+    state: EscapeState,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum EscapeState {
+    Ground,
+    Escape,
+    Csi,
+}
+
+impl SerialDecoder {
+    pub const fn new() -> Self {
+        Self { state: EscapeState::Ground }
+    }
+
+    /// Feeds one received byte. Returns a key once a character or full escape sequence is read.
+    pub fn add_byte(&mut self, byte: u8) -> Option<DecodedKey> {
+        match (self.state, byte) {
+            (EscapeState::Ground, 0x1B) => {
+                self.state = EscapeState::Escape;
+                None
+            }
+            (EscapeState::Ground, b'\r') => Some(DecodedKey::Unicode('\n')),
+            (EscapeState::Ground, byte) => Some(DecodedKey::Unicode(byte as char)),
+            (EscapeState::Escape, b'[') => {
+                self.state = EscapeState::Csi;
+                None
+            }
+            (EscapeState::Escape, byte) => {
+                // Not a sequence we know; treat it as a plain key press
+                self.state = EscapeState::Ground;
+                Some(DecodedKey::Unicode(byte as char))
+            }
+            (EscapeState::Csi, byte) => {
+                self.state = EscapeState::Ground;
+                match byte {
+                    b'A' => Some(DecodedKey::RawKey(KeyCode::ArrowUp)),
+                    b'B' => Some(DecodedKey::RawKey(KeyCode::ArrowDown)),
+                    b'C' => Some(DecodedKey::RawKey(KeyCode::ArrowRight)),
+                    b'D' => Some(DecodedKey::RawKey(KeyCode::ArrowLeft)),
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
+impl Default for SerialDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
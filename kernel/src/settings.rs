@@ -0,0 +1,180 @@
+use alloc::string::String;
+use game::ai::Personality;
+use kernel::keyboard::{self, Layout};
+use kernel::virtio_gpu;
+use crate::accessibility::AccessibilitySettings;
+use crate::controls::KeyBindings;
+use crate::screen::{self, screenwriter};
+use crate::sound::SoundSettings;
+use crate::strings::{self, Language, Text, fill, text};
+
+const ITEM_COUNT: usize = 11;
+/// The item that opens the sound screen.
+const SOUND_ITEM: usize = 5;
+const FRAME_RATE_ITEM: usize = 6;
+const PERSONALITY_ITEM: usize = 7;
+const TRAINING_ITEM: usize = 8;
+/// The item that opens the accessibility screen.
+const ACCESSIBILITY_ITEM: usize = 9;
+/// Frame rates to pick from, in frames a second. The game itself runs at the tick rate whatever
+/// is picked; a lower rate draws less often, for a slow display.
+pub const FRAME_RATES: [u32; 4] = [15, 20, 30, 60];
+/// Resolutions to pick from on a virtio-gpu, after the display's own.
+const RESOLUTIONS: [(usize, usize); 3] = [(640, 480), (800, 600), (1024, 768)];
+
+/// Player-adjustable options, edited from the settings screen.
+pub struct Settings {
+    pub mouse_control: bool,
+    pub mouse_sensitivity: usize,
+    pub keyboard_layout: Layout,
+    pub bindings: KeyBindings,
+    pub show_clock: bool,
+    pub language: Language,
+    pub sound: SoundSettings,
+    /// The most frames drawn a second, one of [FRAME_RATES].
+    pub frame_rate: u32,
+    /// How the computer player plays.
+    pub personality: Personality,
+    /// Whether the ball's path to the paddles is drawn during a game, for learning where to be.
+    pub training_overlay: bool,
+    pub accessibility: AccessibilitySettings,
+    /// 0 for the display's own resolution, else 1 + the index in [RESOLUTIONS].
+    pub resolution: usize,
+    selected: usize,
+}
+
+impl Settings {
+    pub const fn new() -> Self {
+        Self {
+            mouse_control: false,
+            mouse_sensitivity: 4,
+            keyboard_layout: Layout::Qwerty,
+            bindings: KeyBindings::new(),
+            show_clock: true,
+            language: Language::English,
+            sound: SoundSettings::new(),
+            frame_rate: 60,
+            personality: Personality::Steady,
+            training_overlay: false,
+            accessibility: AccessibilitySettings::new(),
+            resolution: 0,
+            selected: 0,
+        }
+    }
+
+    pub fn draw(&self) {
+        screenwriter().draw_string_centered(100, text(Text::SettingsTitle), 0xFF, 0xFF, 0xFF);
+
+        for i in 0..ITEM_COUNT {
+            let (r, g, b) = if i == self.selected { (0xFF, 0xFF, 0x55) } else { (0xAA, 0xAA, 0xAA) };
+            // Labelled before locking the screen, which the resolution's label reads
+            let label = self.label(i);
+            screenwriter().draw_string_centered(130 + i * 20, &label, r, g, b);
+        }
+
+        screenwriter().draw_string_centered(130 + ITEM_COUNT * 20 + 20, text(Text::SettingsHelp), 0xFF, 0xFF, 0xFF);
+        screenwriter().draw_string_centered(130 + ITEM_COUNT * 20 + 40, text(Text::ReturnToMenu), 0xFF, 0xFF, 0xFF);
+    }
+
+    fn label(&self, item: usize) -> String {
+        match item {
+            0 => fill(Text::MouseControl, &[&on_off(self.mouse_control)]),
+            1 => fill(Text::MouseSensitivity, &[&self.mouse_sensitivity]),
+            2 => fill(Text::KeyboardLayout, &[&self.keyboard_layout.name()]),
+            3 => fill(Text::ShowClock, &[&on_off(self.show_clock)]),
+            4 => fill(Text::Language, &[&self.language.name()]),
+            SOUND_ITEM => String::from(text(Text::SoundSettings)),
+            FRAME_RATE_ITEM => fill(Text::FrameRate, &[&self.frame_rate]),
+            PERSONALITY_ITEM => fill(Text::Personality, &[&self.personality.name()]),
+            TRAINING_ITEM => fill(Text::TrainingOverlay, &[&on_off(self.training_overlay)]),
+            ACCESSIBILITY_ITEM => String::from(text(Text::AccessibilitySettings)),
+            _ => {
+                let (width, height) = {
+                    let writer = screenwriter();
+                    (writer.width(), writer.height())
+                };
+                match (virtio_gpu::is_available(), self.resolution) {
+                    (false, _) => fill(Text::ResolutionFixed, &[&width, &height]),
+                    (true, 0) => fill(Text::ResolutionNative, &[&width, &height]),
+                    (true, _) => fill(Text::Resolution, &[&width, &height]),
+                }
+            }
+        }
+    }
+
+    pub fn select(&mut self, up: bool) {
+        self.selected = if up {
+            (self.selected + ITEM_COUNT - 1) % ITEM_COUNT
+        } else {
+            (self.selected + 1) % ITEM_COUNT
+        };
+    }
+
+    /// Whether Enter opens the sound screen.
+    pub fn sound_selected(&self) -> bool {
+        self.selected == SOUND_ITEM
+    }
+
+    /// Whether Enter opens the accessibility screen.
+    pub fn accessibility_selected(&self) -> bool {
+        self.selected == ACCESSIBILITY_ITEM
+    }
+
+    pub fn change(&mut self, increase: bool) {
+        match self.selected {
+            0 => self.mouse_control = !self.mouse_control,
+            1 => {
+                self.mouse_sensitivity = if increase {
+                    (self.mouse_sensitivity + 1).min(10)
+                } else {
+                    (self.mouse_sensitivity - 1).max(1)
+                };
+            }
+            2 => {
+                let count = Layout::ALL.len();
+                let current = Layout::ALL.iter().position(|&l| l == self.keyboard_layout).unwrap_or(0);
+                let next = if increase { (current + 1) % count } else { (current + count - 1) % count };
+                self.keyboard_layout = Layout::ALL[next];
+                keyboard::set_layout(self.keyboard_layout);
+            }
+            3 => self.show_clock = !self.show_clock,
+            4 => {
+                let count = Language::ALL.len();
+                let current = Language::ALL.iter().position(|&l| l == self.language).unwrap_or(0);
+                let next = if increase { (current + 1) % count } else { (current + count - 1) % count };
+                self.language = Language::ALL[next];
+                strings::set_language(self.language);
+            }
+            SOUND_ITEM => {}
+            FRAME_RATE_ITEM => {
+                let count = FRAME_RATES.len();
+                let current = FRAME_RATES.iter().position(|&rate| rate == self.frame_rate).unwrap_or(count - 1);
+                let next = if increase { (current + 1).min(count - 1) } else { current.saturating_sub(1) };
+                self.frame_rate = FRAME_RATES[next];
+            }
+            PERSONALITY_ITEM => {
+                let count = Personality::ALL.len();
+                let current = self.personality as usize;
+                let next = if increase { (current + 1) % count } else { (current + count - 1) % count };
+                self.personality = Personality::ALL[next];
+            }
+            TRAINING_ITEM => self.training_overlay = !self.training_overlay,
+            ACCESSIBILITY_ITEM => {}
+            _ => {
+                let count = RESOLUTIONS.len() + 1;
+                let next = if increase { (self.resolution + 1) % count } else { (self.resolution + count - 1) % count };
+                let size = match next {
+                    0 => virtio_gpu::native_mode().map(|(width, height)| (width as usize, height as usize)),
+                    _ => Some(RESOLUTIONS[next - 1]),
+                };
+                if size.is_some_and(|(width, height)| screen::set_resolution(width, height)) {
+                    self.resolution = next;
+                }
+            }
+        }
+    }
+}
+
+fn on_off(on: bool) -> &'static str {
+    text(if on { Text::On } else { Text::Off })
+}
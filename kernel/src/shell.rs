@@ -0,0 +1,315 @@
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::sync::atomic::Ordering;
+use kernel::{allocator, eventlog, interrupts, memory, serial, watchdog};
+use spin::Mutex;
+use x86_64::VirtAddr;
+use crate::screen::screenwriter;
+use crate::{bus, memory_map, snapshot, spectator, GameMode, PONG, RESET_REQUESTED};
+
+// Debug shell on the serial console. Each line typed there runs as deferred work, between frames,
+// so commands can look at and change the game while it runs. During a two player game the serial
+// console belongs to Player 2, and lines are ignored.
+
+const HELP: &str = "\
+commands:
+  help                  this list
+  mem                   physical memory map and free pages
+  mem <address> [len]   hex dump of mapped kernel memory
+  irqstats              interrupt counts
+  heap                  heap usage
+  profile [reset]       time per frame phase, min/avg/max
+  events [kind] [n]     the last n recent events (default 20), of one kind:
+                        key, score, game, alloc, irq or assert
+  events clear          forget the recorded events
+  score                 game mode and score
+  stats                 paddle hits, bounces, points, games and frames skipped since boot
+  state                 the whole game state as hex
+  state load <hex>      restore a state printed by state
+  state diff <hex>      what differs between a printed state and now
+  set ballspeed <n>     ball speed in pixels per tick
+  spectate [on|off]     stream each tick's state on COM2 for a viewer
+  screenshot [scale]    the screen as a base64 PPM image, every scale-th pixel
+  ls [path]             a directory on the disk
+  cat <path>            a file on the disk, as text
+  rx [path]             receive a file by XMODEM, onto the disk or else into memory
+  reset                 back to the menu with a fresh game
+  reboot, poweroff";
+
+/// Most bytes `mem` dumps at once.
+const MAX_DUMP: u64 = 4096;
+/// Largest file `rx` takes.
+const MAX_RECEIVE: usize = 4 * 1024 * 1024;
+
+/// The last file `rx` received without a path, kept for `mem` to look at.
+static RECEIVED: Mutex<Vec<u8>> = Mutex::new(Vec::new());
+
+pub fn init() {
+    kernel::uart::set_line_handler(execute);
+    let _ = write!(serial(), "shell ready, type help\n> ");
+}
+
+fn execute(line: &str) {
+    if PONG.lock().game.game_mode == GameMode::TwoPlayer {
+        return;
+    }
+
+    let mut words = line.split_whitespace();
+    let mut out = serial();
+    let result = match (words.next(), words.next(), words.next()) {
+        (None, _, _) => Ok(()),
+        (Some("help"), _, _) => writeln!(out, "{}", HELP),
+        (Some("mem"), None, _) => memory_map::write_summary(&mut out),
+        (Some("mem"), Some(address), len) => dump(&mut out, address, len),
+        (Some("irqstats"), _, _) => irq_stats(&mut out),
+        (Some("heap"), _, _) => heap(&mut out),
+        (Some("profile"), Some("reset"), _) => {
+            kernel::profiler::reset();
+            writeln!(out, "profile reset")
+        }
+        (Some("profile"), _, _) => kernel::profiler::write_report(&mut out),
+        (Some("events"), Some("clear"), _) => {
+            eventlog::clear();
+            writeln!(out, "events cleared")
+        }
+        (Some("events"), first, second) => events(&mut out, first, second),
+        (Some("score"), _, _) => {
+            let pong = PONG.lock();
+            writeln!(out, "{:?}: {} - {}", pong.game.game_mode, pong.game.scoreboard.points(1), pong.game.scoreboard.points(2))
+        }
+        (Some("stats"), _, _) => {
+            let (statistics, skipped) = {
+                let pong = PONG.lock();
+                (pong.statistics, pong.limiter.skipped)
+            };
+            bus::write_statistics(&mut out, &statistics).and_then(|_| writeln!(out, "{} frames skipped", skipped))
+        }
+        (Some("state"), None, _) => {
+            let state = snapshot::save(&PONG.lock());
+            writeln!(out, "{}", state)
+        }
+        (Some("state"), Some("load"), Some(text)) => {
+            let loaded = snapshot::load(&mut PONG.lock(), text);
+            match loaded {
+                Ok(()) => writeln!(out, "state loaded"),
+                Err(error) => writeln!(out, "state: {}", error),
+            }
+        }
+        (Some("state"), Some("diff"), Some(text)) => {
+            let diff = snapshot::diff(&PONG.lock(), text);
+            match diff {
+                Ok(lines) if lines.is_empty() => writeln!(out, "no differences"),
+                Ok(lines) => lines.iter().try_for_each(|line| writeln!(out, "{}", line)),
+                Err(error) => writeln!(out, "state: {}", error),
+            }
+        }
+        (Some("state"), _, _) => writeln!(out, "expected state, state load <hex> or state diff <hex>"),
+        (Some("set"), Some("ballspeed"), Some(speed)) => match speed.parse::<f32>() {
+            Ok(speed) if speed > 0.0 && speed < 200.0 => {
+                PONG.lock().game.ball_speed = speed;
+                writeln!(out, "ball speed {} from the next serve", speed)
+            }
+            _ => writeln!(out, "expected a speed between 0 and 200"),
+        },
+        (Some("spectate"), None, _) => writeln!(out, "spectator broadcast {}", if spectator::is_on() { "on" } else { "off" }),
+        (Some("spectate"), Some("on"), _) => {
+            if spectator::set(true) { Ok(()) } else { writeln!(out, "spectate: no serial link on COM2") }
+        }
+        (Some("spectate"), Some("off"), _) => {
+            spectator::set(false);
+            Ok(())
+        }
+        (Some("spectate"), _, _) => writeln!(out, "expected spectate, spectate on or spectate off"),
+        (Some("screenshot"), scale, _) => match scale.map_or(Ok(2), str::parse::<usize>) {
+            Ok(scale) if scale > 0 => screenshot(&mut out, scale),
+            _ => writeln!(out, "expected a scale of 1 or more"),
+        },
+        (Some("ls"), path, _) => match kernel::fat32::list_dir(path.unwrap_or("/")) {
+            Ok(entries) => entries.iter().try_for_each(|entry| writeln!(out, "{}", entry)),
+            Err(error) => writeln!(out, "ls: {:?}", error),
+        },
+        (Some("cat"), Some(path), _) => match kernel::fat32::read_file(path) {
+            Ok(data) => writeln!(out, "{}", alloc::string::String::from_utf8_lossy(&data)),
+            Err(error) => writeln!(out, "cat: {:?}", error),
+        },
+        (Some("rx"), path, _) => receive(&mut out, path),
+        (Some("reset"), _, _) => {
+            RESET_REQUESTED.store(true, Ordering::Relaxed);
+            writeln!(out, "resetting")
+        }
+        (Some("reboot"), _, _) => kernel::power::reboot(),
+        (Some("poweroff"), _, _) => kernel::power::shutdown(),
+        (Some(command), _, _) => writeln!(out, "unknown command {}, try help", command),
+    };
+    let _ = result.and_then(|_| write!(out, "> "));
+}
+
+fn heap(out: &mut impl Write) -> core::fmt::Result {
+    let heap = allocator::stats();
+    writeln!(out, "used {} B, free {} B, peak {} B, {} allocations, {} frees", heap.used, heap.free, heap.peak, heap.allocations, heap.deallocations)?;
+    if cfg!(feature = "heap-debug") {
+        writeln!(out, "{} heap corruption reports", kernel::heap_debug::reports())?;
+    }
+    Ok(())
+}
+
+/// Events shown when no count is given.
+const EVENTS: usize = 20;
+
+/// `events [kind] [n]`, in either order.
+fn events(out: &mut impl Write, first: Option<&str>, second: Option<&str>) -> core::fmt::Result {
+    let mut kind = None;
+    let mut count = EVENTS;
+    for word in first.into_iter().chain(second) {
+        if let Some(named) = eventlog::Kind::from_name(word) {
+            kind = Some(named);
+        } else if let Ok(n) = word.parse() {
+            count = n;
+        } else {
+            return writeln!(out, "expected events [key|score|game|alloc|irq|assert] [n]");
+        }
+    }
+    eventlog::write_recent(out, kind, count)
+}
+
+fn parse_number(text: &str) -> Option<u64> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+fn dump(out: &mut impl Write, address: &str, len: Option<&str>) -> core::fmt::Result {
+    let (Some(start), Some(len)) = (parse_number(address), len.map_or(Some(64), parse_number)) else {
+        return writeln!(out, "expected mem <address> [len]");
+    };
+    if VirtAddr::try_new(start).is_err() {
+        return writeln!(out, "{:#x} is not a canonical address", start);
+    }
+    for row in (0..len.min(MAX_DUMP)).step_by(16) {
+        // The range may run off the end of the address space, or into the non-canonical hole
+        let Some(row_start) = start.checked_add(row) else { return Ok(()) };
+        write!(out, "{:#018x}:", row_start)?;
+        for offset in 0..16.min(len - row) {
+            let Some(address) = row_start.checked_add(offset).and_then(|address| VirtAddr::try_new(address).ok()) else {
+                return writeln!(out, " (not canonical)");
+            };
+            if !memory::is_mapped(address) {
+                return writeln!(out, " (not mapped)");
+            }
+            write!(out, " {:02x}", unsafe { *address.as_ptr::<u8>() })?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+fn receive(out: &mut impl Write, path: Option<&str>) -> core::fmt::Result {
+    writeln!(out, "start the XMODEM transfer now")?;
+    let data = match kernel::xmodem::receive(MAX_RECEIVE) {
+        Ok(data) => data,
+        Err(error) => return writeln!(out, "rx: {:?}", error),
+    };
+    match path {
+        Some(path) => match kernel::fat32::write_file(path, &data) {
+            Ok(()) => writeln!(out, "{} bytes written to {}", data.len(), path),
+            Err(error) => writeln!(out, "rx: {:?}", error),
+        },
+        None => {
+            let mut received = RECEIVED.lock();
+            *received = data;
+            writeln!(out, "{} bytes at {:#x}", received.len(), received.as_ptr() as u64)
+        }
+    }
+}
+
+fn irq_stats(out: &mut impl Write) -> core::fmt::Result {
+    for (vector, name, count) in interrupts::interrupt_stats() {
+        writeln!(out, "  {:#04x} {}: {}", vector, name, count)?;
+    }
+    writeln!(out, "missed ticks: {}", interrupts::missed_ticks())
+}
+
+/// Writes the screen as a binary PPM encoded in base64, between marker lines, so it can be cut
+/// out of the console log and decoded on the host.
+fn screenshot(out: &mut impl Write, scale: usize) -> core::fmt::Result {
+    let (width, height) = {
+        let writer = screenwriter();
+        (writer.width() / scale, writer.height() / scale)
+    };
+    writeln!(out, "-----BEGIN SCREENSHOT-----")?;
+    let mut encoder = Base64::new(out);
+    let header = alloc::format!("P6\n{} {}\n255\n", width, height);
+    encoder.write(header.as_bytes())?;
+    let mut row = Vec::with_capacity(width * 3);
+    for y in 0..height {
+        row.clear();
+        {
+            let writer = screenwriter();
+            for x in 0..width {
+                let (r, g, b) = writer.read_pixel(x * scale, y * scale);
+                row.extend_from_slice(&[r, g, b]);
+            }
+        }
+        encoder.write(&row)?;
+        // Big screenshots take a while; the game is not stuck
+        watchdog::pet();
+    }
+    encoder.finish()?;
+    writeln!(out, "-----END SCREENSHOT-----")
+}
+
+/// Streaming base64 encoder, wrapping lines at 76 characters.
+struct Base64<'a, W: Write> {
+    out: &'a mut W,
+    pending: [u8; 3],
+    pending_len: usize,
+    column: usize,
+}
+
+impl<'a, W: Write> Base64<'a, W> {
+    const ALPHABET: &'static [u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    fn new(out: &'a mut W) -> Self {
+        Base64 { out, pending: [0; 3], pending_len: 0, column: 0 }
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> core::fmt::Result {
+        for &byte in bytes {
+            self.pending[self.pending_len] = byte;
+            self.pending_len += 1;
+            if self.pending_len == 3 {
+                self.emit(4)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the first `chars` characters of the pending group's encoding, padded to four.
+    fn emit(&mut self, chars: usize) -> core::fmt::Result {
+        let [a, b, c] = self.pending;
+        let group = (a as u32) << 16 | (b as u32) << 8 | c as u32;
+        for i in 0..4 {
+            let char = if i < chars { Self::ALPHABET[(group >> (18 - 6 * i)) as usize & 0x3F] as char } else { '=' };
+            self.out.write_char(char)?;
+        }
+        self.pending = [0; 3];
+        self.pending_len = 0;
+        self.column += 4;
+        if self.column == 76 {
+            self.column = 0;
+            self.out.write_char('\n')?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> core::fmt::Result {
+        if self.pending_len > 0 {
+            self.emit(self.pending_len + 1)?;
+        }
+        if self.column > 0 {
+            self.out.write_char('\n')?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,78 @@
+use core::alloc::Layout;
+use core::ptr::null_mut;
+
+/// Object sizes served by slab caches. Everything the game allocates every frame (input events,
+/// balls, timer entries, short strings) falls into one of these; larger requests go to the
+/// general free list.
+pub const SIZE_CLASSES: [usize; 6] = [16, 32, 64, 128, 256, 512];
+
+/// Size (and alignment) of the pages slab caches carve objects from.
+pub const SLAB_SIZE: usize = 4096;
+
+struct FreeObject {
+    next: *mut FreeObject,
+}
+
+/// A cache of equally sized objects. Allocation and freeing pop and push a free list, O(1) and
+/// without fragmenting the general heap. Slabs are never given back, so memory a cache has
+/// grown to stays with it.
+pub struct SlabCache {
+    object_size: usize,
+    free: *mut FreeObject,
+}
+
+unsafe impl Send for SlabCache {}
+
+impl SlabCache {
+    pub const fn new(object_size: usize) -> Self {
+        Self { object_size, free: null_mut() }
+    }
+
+    /// Takes a free object, or None if the cache needs another slab.
+    pub fn alloc(&mut self) -> Option<*mut u8> {
+        if self.free.is_null() {
+            return None;
+        }
+        let object = self.free;
+        self.free = unsafe { (*object).next };
+        Some(object as *mut u8)
+    }
+
+    /// Returns an object obtained from [SlabCache::alloc].
+    ///
+    /// ## Safety
+    /// `object` must come from this cache and not be in use.
+    pub unsafe fn free(&mut self, object: *mut u8) {
+        let object = object as *mut FreeObject;
+        unsafe { object.write(FreeObject { next: self.free }) };
+        self.free = object;
+    }
+
+    /// Carves a [SLAB_SIZE]-byte, [SLAB_SIZE]-aligned block into objects.
+    ///
+    /// ## Safety
+    /// `slab` must be unused memory owned by the caller from now on.
+    pub unsafe fn add_slab(&mut self, slab: *mut u8) {
+        for offset in (0..SLAB_SIZE).step_by(self.object_size).rev() {
+            unsafe { self.free(slab.add(offset)) };
+        }
+    }
+}
+
+/// Index of the size class that serves `layout`, if any. Slabs are page aligned and the
+/// classes are powers of two, so every object is aligned to its own size.
+pub fn size_class(layout: Layout) -> Option<usize> {
+    let size = layout.size().max(layout.align());
+    SIZE_CLASSES.iter().position(|&class| size <= class)
+}
+
+/// One cache per entry of [SIZE_CLASSES].
+pub const fn caches() -> [SlabCache; SIZE_CLASSES.len()] {
+    let mut caches = [const { SlabCache::new(0) }; SIZE_CLASSES.len()];
+    let mut i = 0;
+    while i < SIZE_CLASSES.len() {
+        caches[i] = SlabCache::new(SIZE_CLASSES[i]);
+        i += 1;
+    }
+    caches
+}
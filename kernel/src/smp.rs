@@ -0,0 +1,235 @@
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::vec;
+use core::arch::global_asm;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::segmentation::{Segment, CS, DS, ES, SS};
+use x86_64::instructions::tables::load_tss;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable};
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::structures::tss::TaskStateSegment;
+use x86_64::{PhysAddr, VirtAddr};
+use crate::interrupts::{IST_INDICES, IST_STACK_SIZE};
+use crate::{deferred, memory, serial, time};
+
+// Bringing up the other CPUs ("application processors"): each one is sent INIT and two
+// STARTUP IPIs, and starts in real mode at the trampoline, which takes it straight to long mode on
+// the kernel's page tables and calls ap_main. See https://wiki.osdev.org/SMP
+
+const MAX_CPUS: usize = 16;
+const STACK_SIZE: usize = 64 * 1024;
+
+/// Local APIC IDs of the application processors waiting to be started, from the MADT.
+static PROCESSORS: Mutex<([u32; MAX_CPUS], usize)> = Mutex::new(([0; MAX_CPUS], 0));
+/// Application processors that reached ap_main.
+static ONLINE: AtomicUsize = AtomicUsize::new(0);
+static JOBS: Mutex<VecDeque<fn()>> = Mutex::new(VecDeque::new());
+
+pub(crate) fn set_processors(apic_ids: impl Iterator<Item = u32>) {
+    let mut processors = PROCESSORS.lock();
+    for id in apic_ids.take(MAX_CPUS) {
+        let count = processors.1;
+        processors.0[count] = id;
+        processors.1 += 1;
+    }
+}
+
+// Offsets of the fields the trampoline is patched with
+const GDT_POINTER_BASE: usize = 34;
+const FAR_JUMP_OFFSET: usize = 40;
+const CR3: usize = 48;
+const STACK: usize = 56;
+const ENTRY: usize = 64;
+const CPU: usize = 72;
+
+// Copied to a page below 1 MiB. Real mode addresses are relative to the page (CS = DS = page),
+// the GDT pointer and far jump are made absolute when the copy is patched, and the 64-bit part
+// only uses RIP-relative addressing.
+global_asm!(
+    ".balign 16",
+    ".global smp_trampoline_start",
+    ".global smp_trampoline_end",
+    ".code16",
+    "smp_trampoline_start:",
+    "    jmp 2f",
+    ".balign 8",
+    // 8: null, 64-bit code and data descriptors
+    "    .quad 0",
+    "    .quad 0x00AF9A000000FFFF",
+    "    .quad 0x00CF92000000FFFF",
+    // 32: GDT pointer; the base is patched to page + 8
+    "    .word 23",
+    "    .long 8",
+    ".balign 8",
+    // 40: far pointer to the 64-bit code; the offset is patched to page + (3f - start)
+    "    .long 3f - smp_trampoline_start",
+    "    .word 0x08",
+    ".balign 8",
+    // 48: CR3, 56: stack top, 64: entry point, 72: CPU index
+    "    .quad 0",
+    "    .quad 0",
+    "    .quad 0",
+    "    .quad 0",
+    "2:",
+    "    cli",
+    "    mov %cs, %ax",
+    "    mov %ax, %ds",
+    "    lgdtl 32",
+    "    mov %cr4, %eax",
+    "    or $0x20, %eax", // PAE
+    "    mov %eax, %cr4",
+    "    mov 48, %eax",
+    "    mov %eax, %cr3",
+    "    mov $0xC0000080, %ecx", // EFER
+    "    rdmsr",
+    "    or $0x900, %eax", // long mode, no-execute
+    "    wrmsr",
+    "    mov %cr0, %eax",
+    "    or $0x80010001, %eax", // paging, write protect, protected mode
+    "    mov %eax, %cr0",
+    "    ljmpl *40",
+    ".code64",
+    "3:",
+    "    mov $0x10, %ax",
+    "    mov %ax, %ds",
+    "    mov %ax, %es",
+    "    mov %ax, %ss",
+    "    mov smp_trampoline_start+56(%rip), %rsp",
+    "    mov smp_trampoline_start+64(%rip), %rax",
+    "    mov smp_trampoline_start+72(%rip), %rdi",
+    "    call *%rax",
+    "    ud2",
+    "smp_trampoline_end:",
+    options(att_syntax),
+);
+
+unsafe extern "C" {
+    static smp_trampoline_start: u8;
+    static smp_trampoline_end: u8;
+}
+
+/// Starts every application processor listed in the MADT, using the page at `trampoline` (below
+/// 1 MiB, unused) for their first instructions. The interrupt controllers must be set up. Returns
+/// how many came online.
+pub fn start_aps(trampoline: PhysAddr) -> usize {
+    let (ids, count) = *PROCESSORS.lock();
+    let (cr3, _) = Cr3::read();
+    if count == 0 || cr3.start_address().as_u64() > u32::MAX as u64 {
+        // The trampoline can only load a 32-bit CR3
+        return 0;
+    }
+
+    let start = &raw const smp_trampoline_start;
+    let len = &raw const smp_trampoline_end as usize - start as usize;
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    let page = memory::map_region(trampoline, 4096, flags).as_mut_ptr::<u8>();
+    let base = trampoline.as_u64();
+    unsafe {
+        page.copy_from_nonoverlapping(start, len);
+        let patch_u32 = |offset: usize, add: u32| {
+            let field = page.add(offset) as *mut u32;
+            field.write_unaligned(field.read_unaligned() + add);
+        };
+        patch_u32(GDT_POINTER_BASE, base as u32);
+        patch_u32(FAR_JUMP_OFFSET, base as u32);
+        (page.add(CR3) as *mut u64).write(cr3.start_address().as_u64());
+        (page.add(ENTRY) as *mut u64).write((ap_main as extern "C" fn(u64) -> !) as usize as u64);
+    }
+
+    for (cpu, &apic_id) in ids[..count].iter().enumerate() {
+        let stack = Box::leak(vec![0u8; STACK_SIZE].into_boxed_slice());
+        let top = (stack.as_ptr() as u64 + STACK_SIZE as u64) & !0xF;
+        unsafe {
+            (page.add(STACK) as *mut u64).write_volatile(top);
+            (page.add(CPU) as *mut u64).write_volatile(cpu as u64 + 1);
+        }
+
+        let online = ONLINE.load(Ordering::Acquire);
+        interrupts::without_interrupts(|| {
+            crate::interrupts::send_ipi(apic_id, 0x4500); // INIT
+            time::delay_us(10_000);
+            for _ in 0..2 {
+                crate::interrupts::send_ipi(apic_id, 0x4600 | (base >> 12) as u32); // STARTUP
+                time::delay_us(200);
+            }
+        });
+        if !time::poll_until(Duration::from_millis(100), || ONLINE.load(Ordering::Acquire) > online) {
+            writeln!(serial(), "SMP: CPU with APIC ID {} did not start", apic_id).unwrap();
+        }
+    }
+
+    memory::unmap_region(VirtAddr::new(base), 4096);
+    let online = ONLINE.load(Ordering::Acquire);
+    writeln!(serial(), "SMP: {} of {} application processors online", online, count).unwrap();
+    online
+}
+
+/// Gives the CPU its own GDT and TSS, with interrupt stacks of its own.
+fn load_gdt() {
+    let tss = Box::leak(Box::new(TaskStateSegment::new()));
+    for index in IST_INDICES {
+        let ist = Box::leak(vec![0u8; IST_STACK_SIZE].into_boxed_slice());
+        tss.interrupt_stack_table[index as usize] = (VirtAddr::from_ptr(ist.as_ptr()) + IST_STACK_SIZE as u64).align_down(16u64);
+    }
+
+    let gdt = Box::leak(Box::new(GlobalDescriptorTable::new()));
+    let code_selector = gdt.append(Descriptor::kernel_code_segment());
+    let data_selector = gdt.append(Descriptor::kernel_data_segment());
+    let tss_selector = gdt.append(Descriptor::tss_segment(tss));
+    gdt.load();
+    unsafe {
+        CS::set_reg(code_selector);
+        SS::set_reg(data_selector);
+        DS::set_reg(data_selector);
+        ES::set_reg(data_selector);
+        load_tss(tss_selector);
+    }
+}
+
+extern "C" fn ap_main(cpu: u64) -> ! {
+    crate::fpu::init();
+    crate::machine_check::init();
+    load_gdt();
+    crate::percpu::init(cpu as usize);
+    crate::interrupts::init_ap();
+    writeln!(serial(), "SMP: CPU {} online", cpu).unwrap();
+    ONLINE.fetch_add(1, Ordering::Release);
+
+    loop {
+        // Checked with interrupts off, so a wakeup sent in between still ends the hlt
+        interrupts::disable();
+        let job = JOBS.lock().pop_front();
+        match job {
+            Some(job) => {
+                interrupts::enable();
+                job();
+            }
+            None if deferred::has_pending() => {
+                interrupts::enable();
+                deferred::run_pending();
+            }
+            None => interrupts::enable_and_hlt(),
+        }
+    }
+}
+
+/// Number of application processors running.
+pub fn online() -> usize {
+    ONLINE.load(Ordering::Acquire)
+}
+
+/// Queues `job` to run on the next free application processor. Returns false, without queueing
+/// it, if none is online.
+pub fn run_on_ap(job: fn()) -> bool {
+    if online() == 0 {
+        return false;
+    }
+    JOBS.lock().push_back(job);
+    crate::interrupts::wake_others();
+    true
+}
@@ -0,0 +1,103 @@
+use alloc::boxed::Box;
+use core::time::Duration;
+use game::snake::{Heading, Snake};
+use kernel::{audio, rng};
+use pc_keyboard::{DecodedKey, KeyCode};
+use crate::launcher::{self, Game};
+use crate::screen::screenwriter;
+use crate::strings::{Text, fill, text};
+
+// Snake, on the launcher; the rules are game::snake's. The grid fills the screen below the score
+// line, in cells of [CELL] pixels. The arrow keys or WASD steer, R goes back to the menu, and
+// after a game Space plays another.
+
+/// Size of a cell of the grid, in pixels.
+const CELL: usize = 16;
+/// Room above the grid for the score.
+const TOP: usize = 32;
+const STEPS_PER_SECOND: u32 = 10;
+
+const BODY: (u8, u8, u8) = (0x55, 0xFF, 0x55);
+const HEAD: (u8, u8, u8) = (0xFF, 0xFF, 0x55);
+const FOOD: (u8, u8, u8) = (0xFF, 0x55, 0x55);
+
+struct SnakeGame {
+    snake: Snake,
+    width: usize,
+    height: usize,
+    /// Ticks since the last step.
+    ticks: u32,
+}
+
+pub fn start(width: usize, height: usize) -> Box<dyn Game> {
+    Box::new(SnakeGame { snake: new_snake(width, height), width, height, ticks: 0 })
+}
+
+fn new_snake(width: usize, height: usize) -> Snake {
+    Snake::new(width / CELL, height.saturating_sub(TOP) / CELL, rng::u32)
+}
+
+impl SnakeGame {
+    /// Top left of the grid on the screen.
+    fn origin(&self) -> (usize, usize) {
+        ((self.width - self.snake.columns * CELL) / 2, TOP)
+    }
+
+    fn draw_cell(&self, (x, y): (usize, usize), color: (u8, u8, u8)) {
+        let (left, top) = self.origin();
+        launcher::fill(left + x * CELL + 1, top + y * CELL + 1, CELL - 2, CELL - 2, color);
+    }
+}
+
+impl Game for SnakeGame {
+    fn update(&mut self) {
+        self.ticks += 1;
+        if self.ticks < (crate::tick_hz() / STEPS_PER_SECOND).max(1) {
+            return;
+        }
+        self.ticks = 0;
+        let events = self.snake.step(rng::u32);
+        if events.ate {
+            audio::play_tone(880, Duration::from_millis(40), crate::EFFECT_VOLUME);
+        }
+        if events.died {
+            audio::play_tone(220, Duration::from_millis(250), crate::EFFECT_VOLUME);
+        }
+    }
+
+    fn draw(&self) {
+        let (_, top) = self.origin();
+        for x in 0..self.width {
+            screenwriter().draw_pixel(x, top - 1, 0x55, 0x55, 0x55);
+        }
+        self.draw_cell(self.snake.food, FOOD);
+        for cell in self.snake.body() {
+            self.draw_cell(cell, BODY);
+        }
+        self.draw_cell(self.snake.head(), HEAD);
+
+        let score = fill(Text::SnakeStatus, &[&self.snake.score()]);
+        screenwriter().draw_string(10, 8, &score, 0xFF, 0xFF, 0xFF);
+        if self.snake.over {
+            let y = self.height / 2;
+            screenwriter().draw_string_centered(y - 20, text(Text::GameOver), 0xFF, 0x55, 0x55);
+            screenwriter().draw_string_centered(y, text(Text::SpaceToPlayAgain), 0xFF, 0xFF, 0xFF);
+        }
+    }
+
+    fn handle_key(&mut self, key: DecodedKey) -> bool {
+        match key {
+            DecodedKey::RawKey(KeyCode::ArrowUp) | DecodedKey::Unicode('w') => self.snake.turn(Heading::Up),
+            DecodedKey::RawKey(KeyCode::ArrowDown) | DecodedKey::Unicode('s') => self.snake.turn(Heading::Down),
+            DecodedKey::RawKey(KeyCode::ArrowLeft) | DecodedKey::Unicode('a') => self.snake.turn(Heading::Left),
+            DecodedKey::RawKey(KeyCode::ArrowRight) | DecodedKey::Unicode('d') => self.snake.turn(Heading::Right),
+            DecodedKey::Unicode(' ') if self.snake.over => {
+                self.snake = new_snake(self.width, self.height);
+                self.ticks = 0;
+            }
+            DecodedKey::Unicode('r') => return false,
+            _ => {}
+        }
+        true
+    }
+}
@@ -0,0 +1,189 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use game::{Game, GameMode, SAVED_LEN};
+use kernel::keyboard::{self, Layout};
+use kernel::rng;
+use pc_keyboard::DecodedKey;
+use crate::Pong;
+use crate::controls::{self, Action, KeyBindings, RAW_KEYS};
+use crate::theme::{self, Theme};
+
+// The whole state of play as a line of hex, for the serial shell: `state` prints it, `state load`
+// puts it back, and `state diff` names what differs between it and the state now. That makes a
+// game to save and come back to, a fixture to start a bug report or a test from, and a way to see
+// what changed between two moments.
+//
+// It holds the game (see Game::save), the RNG state, so the next serve is the same one, the tick
+// count, the settings, the theme and the rainbow ball. Not the resolution: a state saved at
+// another size is played at that size, as a replay is. Nor what belongs to something running, a
+// ring 3 program, a network game or the benchmark: a state saved during one loads at the menu.
+//
+// Layout: [VERSION], the ticks as a u64, the game's SAVED_LEN bytes, the RNG state as a u32, a
+// flags byte, the mouse sensitivity, the keyboard layout's index in Layout::ALL, the theme's in
+// theme::THEMES, then each action's key: 0 and the character, or 1 and the key's index in
+// RAW_KEYS, as a u32. Little-endian throughout, and hex so that two states compare byte by byte.
+
+const VERSION: u8 = 3;
+const GAME: usize = 1 + 8;
+const KEYS: usize = GAME + SAVED_LEN + 4 + 4;
+const KEY_LEN: usize = 5;
+const LEN: usize = KEYS + KEY_LEN * Action::ALL.len();
+
+const MOUSE_CONTROL: u8 = 1 << 0;
+const SHOW_CLOCK: u8 = 1 << 1;
+const RAINBOW_BALL: u8 = 1 << 2;
+
+/// A saved state, read back.
+struct Snapshot {
+    ticks: u64,
+    game: Game,
+    rng: u32,
+    mouse_control: bool,
+    show_clock: bool,
+    rainbow_ball: bool,
+    mouse_sensitivity: usize,
+    keyboard_layout: Layout,
+    theme: &'static Theme,
+    keys: [DecodedKey; Action::ALL.len()],
+}
+
+/// The state of `pong` as hex, for [load].
+pub fn save(pong: &Pong) -> String {
+    let settings = &pong.settings;
+    let mut data = Vec::with_capacity(LEN);
+    data.push(VERSION);
+    data.extend_from_slice(&pong.ticks.to_le_bytes());
+    data.extend_from_slice(&pong.game.save());
+    data.extend_from_slice(&rng::state().to_le_bytes());
+    data.push(if settings.mouse_control { MOUSE_CONTROL } else { 0 }
+        | if settings.show_clock { SHOW_CLOCK } else { 0 }
+        | if pong.rainbow_ball { RAINBOW_BALL } else { 0 });
+    data.push(settings.mouse_sensitivity as u8);
+    data.push(Layout::ALL.iter().position(|&layout| layout == settings.keyboard_layout).unwrap_or(0) as u8);
+    data.push(theme::THEMES.iter().position(|theme| core::ptr::eq(theme, pong.theme)).unwrap_or(0) as u8);
+    for action in Action::ALL {
+        let (tag, value) = match settings.bindings.key(action) {
+            DecodedKey::Unicode(c) => (0, c as u32),
+            DecodedKey::RawKey(code) => (1, RAW_KEYS.iter().position(|&raw| raw == code).unwrap_or(0) as u32),
+        };
+        data.push(tag);
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Restores a state [save] gave. During a ring 3 program, a network game, the benchmark, the
+/// latency test, another of the launcher's games, a recording or a replay, which it would leave in
+/// a state they cannot go on from, it refuses.
+pub fn load(pong: &mut Pong, text: &str) -> Result<(), &'static str> {
+    let snapshot = parse(text)?;
+    if pong.replay.is_some() {
+        return Err("not while recording or replaying");
+    }
+    if matches!(pong.game.game_mode, GameMode::Program | GameMode::NetworkLobby | GameMode::Network | GameMode::Benchmark | GameMode::LatencyTest | GameMode::Launched) {
+        return Err("not while a program, a network game, the benchmark, the latency test or another game runs");
+    }
+
+    let mut game = snapshot.game;
+    if matches!(game.game_mode, GameMode::Program | GameMode::NetworkLobby | GameMode::Network | GameMode::Benchmark | GameMode::LatencyTest | GameMode::Launched) {
+        game.game_mode = GameMode::Menu;
+    }
+    pong.game = game;
+    pong.ticks = snapshot.ticks;
+    rng::seed(snapshot.rng);
+    pong.rainbow_ball = snapshot.rainbow_ball;
+    pong.theme = snapshot.theme;
+    pong.new_high_score = None;
+    let settings = &mut pong.settings;
+    settings.mouse_control = snapshot.mouse_control;
+    settings.show_clock = snapshot.show_clock;
+    settings.mouse_sensitivity = snapshot.mouse_sensitivity;
+    settings.keyboard_layout = snapshot.keyboard_layout;
+    keyboard::set_layout(snapshot.keyboard_layout);
+    // Bound in order, each saved key swaps away from whichever action holds it, which is never
+    // one bound before it
+    settings.bindings = KeyBindings::new();
+    for (action, key) in Action::ALL.into_iter().zip(snapshot.keys) {
+        settings.bindings.bind(action, key);
+    }
+    Ok(())
+}
+
+/// The fields where the state `text` and `pong`'s state now differ, a line each with the
+/// saved value and the current one.
+pub fn diff(pong: &Pong, text: &str) -> Result<Vec<String>, &'static str> {
+    let saved = parse(text)?;
+    let now = parse(&save(pong))?;
+    let lines: Vec<String> = fields(&saved).into_iter().zip(fields(&now))
+        .filter(|((_, saved), (_, now))| saved != now)
+        .map(|((name, saved), (_, now))| format!("{}: {} -> {}", name, saved, now))
+        .collect();
+    if lines.is_empty() && saved.game.save() != now.game.save() {
+        return Ok(alloc::vec![String::from("the mode of the last game started or a paddle key's push differs")]);
+    }
+    Ok(lines)
+}
+
+fn fields(snapshot: &Snapshot) -> [(&'static str, String); 18] {
+    let game = &snapshot.game;
+    let keys: Vec<String> = snapshot.keys.iter().map(|&key| controls::key_name(key)).collect();
+    [
+        ("ticks", format!("{}", snapshot.ticks)),
+        ("mode", format!("{:?}", game.game_mode)),
+        ("ball", format!("{}, {}", game.ball().x, game.ball().y)),
+        ("velocity", format!("{}, {}", game.ball().dx, game.ball().dy)),
+        ("ball speed", format!("{}", game.ball_speed)),
+        ("paddles", format!("{}, {}", game.paddle(1).y, game.paddle(2).y)),
+        ("paddle speeds", format!("{}, {}", game.paddle(1).dy, game.paddle(2).dy)),
+        ("score", format!("{} - {}", game.scoreboard.points(1), game.scoreboard.points(2))),
+        ("returns", format!("{}", game.returns)),
+        ("rally, match ticks", format!("{}, {}", game.rally, game.match_ticks)),
+        ("size", format!("{}x{}, paddles {}", game.width, game.height, game.paddle_height())),
+        ("ai", String::from(game.difficulty.name())),
+        ("rng", format!("{:08x}", snapshot.rng)),
+        ("mouse", format!("{} {}", if snapshot.mouse_control { "on" } else { "off" }, snapshot.mouse_sensitivity)),
+        ("clock, rainbow", format!("{}, {}", snapshot.show_clock, snapshot.rainbow_ball)),
+        ("layout", String::from(snapshot.keyboard_layout.name())),
+        ("theme", String::from(snapshot.theme.name)),
+        ("keys", keys.join(" ")),
+    ]
+}
+
+fn parse(text: &str) -> Result<Snapshot, &'static str> {
+    let text = text.trim();
+    if !text.len().is_multiple_of(2) || !text.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return Err("not a state: expected hex");
+    }
+    let data: Vec<u8> = (0..text.len()).step_by(2).map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap()).collect();
+    if data.first() != Some(&VERSION) {
+        return Err("a state of another version");
+    }
+    if data.len() != LEN {
+        return Err("not a state: wrong length");
+    }
+    let game = Game::restore(&data[GAME..GAME + SAVED_LEN]).ok_or("not a state: the game is not one")?;
+    let u32_at = |at: usize| u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]);
+    let settings = &data[KEYS - 4..KEYS];
+    let mut keys = [DecodedKey::Unicode(' '); Action::ALL.len()];
+    for (i, key) in keys.iter_mut().enumerate() {
+        let at = KEYS + i * KEY_LEN;
+        *key = match data[at] {
+            0 => char::from_u32(u32_at(at + 1)).map(DecodedKey::Unicode),
+            1 => RAW_KEYS.get(u32_at(at + 1) as usize).map(|&code| DecodedKey::RawKey(code)),
+            _ => None,
+        }.ok_or("not a state: a key is not one")?;
+    }
+    Ok(Snapshot {
+        ticks: u64::from_le_bytes(data[1..GAME].try_into().unwrap()),
+        game,
+        rng: u32_at(GAME + SAVED_LEN),
+        mouse_control: settings[0] & MOUSE_CONTROL != 0,
+        show_clock: settings[0] & SHOW_CLOCK != 0,
+        rainbow_ball: settings[0] & RAINBOW_BALL != 0,
+        mouse_sensitivity: (settings[1] as usize).clamp(1, 10),
+        keyboard_layout: *Layout::ALL.get(settings[2] as usize).ok_or("not a state: no such keyboard layout")?,
+        theme: theme::THEMES.get(settings[3] as usize).ok_or("not a state: no such theme")?,
+        keys,
+    })
+}
@@ -0,0 +1,76 @@
+use kernel::audio::{self, Channel, MAX_LEVEL};
+use pc_keyboard::DecodedKey;
+use crate::screen::screenwriter;
+use crate::strings::{Text, fill, text};
+
+const ITEM_COUNT: usize = 3;
+
+/// The volumes, edited from the sound screen, which the settings screen opens. F8 mutes
+/// everything from anywhere (see `key` in main.rs) without changing them.
+pub struct SoundSettings {
+    /// From 0 to [MAX_LEVEL], as are the effects.
+    pub master_volume: u8,
+    pub effects_volume: u8,
+    pub music: bool,
+    selected: usize,
+}
+
+impl SoundSettings {
+    pub const fn new() -> Self {
+        Self { master_volume: MAX_LEVEL, effects_volume: MAX_LEVEL, music: true, selected: 0 }
+    }
+
+    /// Hands the volumes to the mixer.
+    pub fn apply(&self) {
+        audio::set_master_volume(self.master_volume);
+        audio::set_volume(Channel::Effects, self.effects_volume);
+        audio::set_volume(Channel::Music, if self.music { MAX_LEVEL } else { 0 });
+    }
+
+    pub fn draw(&self) {
+        screenwriter().draw_string_centered(100, text(Text::SoundTitle), 0xFF, 0xFF, 0xFF);
+
+        for i in 0..ITEM_COUNT {
+            let (r, g, b) = if i == self.selected { (0xFF, 0xFF, 0x55) } else { (0xAA, 0xAA, 0xAA) };
+            let label = match i {
+                0 => fill(Text::MasterVolume, &[&self.master_volume, &MAX_LEVEL]),
+                1 => fill(Text::EffectsVolume, &[&self.effects_volume, &MAX_LEVEL]),
+                _ => fill(Text::Music, &[&text(if self.music { Text::On } else { Text::Off })]),
+            };
+            screenwriter().draw_string_centered(130 + i * 20, &label, r, g, b);
+        }
+
+        let y = 130 + ITEM_COUNT * 20 + 20;
+        screenwriter().draw_string_centered(y, text(Text::SettingsHelp), 0xFF, 0xFF, 0xFF);
+        screenwriter().draw_string_centered(y + 20, text(Text::ReturnToSettings), 0xFF, 0xFF, 0xFF);
+        if !audio::is_available() {
+            screenwriter().draw_string_centered(y + 50, text(Text::NoSoundCard), 0xFF, 0x55, 0x55);
+        } else if audio::is_muted() {
+            screenwriter().draw_string_centered(y + 50, text(Text::Muted), 0xFF, 0x55, 0x55);
+        }
+    }
+
+    /// Handles a key press on the sound screen. Returns false when the player leaves the screen.
+    pub fn handle_key(&mut self, key: DecodedKey) -> bool {
+        let increase = key == DecodedKey::Unicode('d');
+        match key {
+            DecodedKey::Unicode('w') => self.selected = (self.selected + ITEM_COUNT - 1) % ITEM_COUNT,
+            DecodedKey::Unicode('s') => self.selected = (self.selected + 1) % ITEM_COUNT,
+            DecodedKey::Unicode('a' | 'd') => {
+                match self.selected {
+                    0 => self.master_volume = step(self.master_volume, increase),
+                    1 => self.effects_volume = step(self.effects_volume, increase),
+                    _ => self.music = !self.music,
+                }
+                self.apply();
+            }
+            DecodedKey::Unicode('r') => return false,
+            _ => {}
+        }
+        true
+    }
+}
+
+fn step(level: u8, increase: bool) -> u8 {
+    if increase { (level + 1).min(MAX_LEVEL) } else { level.saturating_sub(1) }
+}
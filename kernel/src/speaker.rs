@@ -0,0 +1,103 @@
+use core::time::Duration;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use crate::audio::{self, Note};
+use crate::pit;
+use crate::time::Instant;
+use crate::timers::{self, TimerId};
+
+// Music on the PC speaker, which every PC has, whatever its sound card: a sequencer plays a tune
+// of [Note]s one after the other, from a timer (crate::timers) that starts each note when the last
+// one's time is up. The speaker plays one note at a time, so a new tune replaces the one playing;
+// it is apart from the sound card's voices (crate::audio), which go on as they were. Muting
+// (audio::set_muted) silences it too, without stopping the tune.
+//
+// The speaker is sounded by PIT channel 2, which pit::wait_us also uses; waiting on it (only done
+// at boot) cuts the note short.
+
+/// How often the sequencer looks whether the note is over; a note ends up to this much late.
+const STEP: Duration = Duration::from_millis(5);
+
+struct Sequencer {
+    notes: &'static [Note],
+    looping: bool,
+    /// The note after the one playing, an index in [Self::notes], and when the one playing is
+    /// over; None before the first.
+    next: usize,
+    note_ends: Option<Instant>,
+    timer: Option<TimerId>,
+}
+
+static SEQUENCER: Mutex<Sequencer> =
+    Mutex::new(Sequencer { notes: &[], looping: false, next: 0, note_ends: None, timer: None });
+
+/// Plays `notes` on the speaker, from the start again once they end if `looping`, in place of any
+/// tune playing. Returns false if there is no timer free to play it with.
+pub fn play(notes: &'static [Note], looping: bool) -> bool {
+    without_interrupts(|| {
+        let mut sequencer = SEQUENCER.lock();
+        if sequencer.timer.is_none() {
+            sequencer.timer = timers::add(STEP, step);
+        }
+        (sequencer.notes, sequencer.looping, sequencer.next, sequencer.note_ends) = (notes, looping, 0, None);
+        sequencer.timer.is_some()
+    })
+}
+
+/// Stops the tune playing, if one is.
+pub fn stop() {
+    without_interrupts(|| {
+        let mut sequencer = SEQUENCER.lock();
+        if let Some(timer) = sequencer.timer.take() {
+            timers::remove(timer);
+        }
+        sequencer.notes = &[];
+        pit::speaker_off();
+    });
+}
+
+pub fn is_playing() -> bool {
+    without_interrupts(|| SEQUENCER.lock().timer.is_some())
+}
+
+/// Starts the next note when the last one is over. Runs in the timer interrupt.
+fn step() {
+    let mut sequencer = SEQUENCER.lock();
+    let now = Instant::now();
+    if sequencer.note_ends.is_some_and(|ends| now < ends) {
+        return;
+    }
+    if sequencer.next == sequencer.notes.len() {
+        if !sequencer.looping || sequencer.notes.is_empty() {
+            // Over: the timer goes, as it does on stop
+            if let Some(timer) = sequencer.timer.take() {
+                timers::remove(timer);
+            }
+            pit::speaker_off();
+            return;
+        }
+        sequencer.next = 0;
+    }
+    let note = sequencer.notes[sequencer.next];
+    match note.frequency {
+        0 => pit::speaker_off(),
+        _ if audio::is_muted() => pit::speaker_off(),
+        frequency => pit::speaker_on(frequency as u32),
+    }
+    sequencer.next += 1;
+    sequencer.note_ends = Some(now + Duration::from_millis(note.ms as u64));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn speaker_tune_plays_until_stopped() {
+        static TUNE: [Note; 1] = [Note { frequency: 440, ms: 10 }];
+        assert!(play(&TUNE, true));
+        assert!(is_playing());
+        stop();
+        assert!(!is_playing());
+    }
+}
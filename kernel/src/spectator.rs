@@ -0,0 +1,117 @@
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, Ordering};
+use game::GameMode;
+use kernel::{deferred, link, serial};
+use spin::Mutex;
+use crate::{netplay, Pong};
+
+// Spectator broadcast: while it is on, every tick of a game sends the field's state as one frame
+// on the serial link (see [link]), for a viewer on the other end of COM2 to draw the match live,
+// record it, or compare with another machine's. tools/spectator.py is one for the host. Nothing
+// is ever read back, so a viewer can come and go. Turned on with the boot option `spectate=on` or
+// the shell's `spectate on`; a game over the serial link has COM2 to itself, and is not sent.
+//
+// Each frame is [LEN] bytes, little-endian:
+//
+//   0  [MAGIC]              2 bytes
+//   2  game mode            its index in GameMode::ALL
+//   3  tick                 u32, ticks played in the match
+//   7  field                u16 width, u16 height, in pixels
+//  11  ball                 i16 x, i16 y, its top left corner
+//  15  paddles              i16 Player 1's top, i16 Player 2's top, u16 their height
+//  21  score                u8 points, Player 1's then Player 2's, then u8 games the same way
+//  25  checksum             u32, the network game's (see netplay::checksum)
+//
+// The checksum covers the state as the network game compares it, so the streams of the two
+// machines in one can be lined up by tick to find where they first differ. The last frame of a
+// match is sent in the game over's mode, with the final score.
+//
+// At 115200 baud a byte takes about 87 µs, so a frame holds the line for about 2.8 ms. Frames are
+// sent from deferred work after the tick rather than in it; if the line falls behind, a frame not
+// sent yet is replaced by the next tick's.
+
+const MAGIC: [u8; 2] = *b"PS";
+pub const LEN: usize = 29;
+
+static ON: AtomicBool = AtomicBool::new(false);
+/// The frame waiting for [send], if any.
+static PENDING: Mutex<Option<[u8; LEN]>> = Mutex::new(None);
+
+/// Starts or stops the broadcast. Returns false, leaving it off, without the serial link.
+pub fn set(on: bool) -> bool {
+    let on = on && link::is_available();
+    ON.store(on, Ordering::Relaxed);
+    writeln!(serial(), "spectator: broadcast {}", if on { "on COM2" } else { "off" }).unwrap();
+    on
+}
+
+pub fn is_on() -> bool {
+    ON.load(Ordering::Relaxed)
+}
+
+/// Queues this tick's state to be sent if the broadcast is on and a game is being played, or has
+/// just ended from `before`, the mode at the start of the tick.
+pub fn tick(pong: &Pong, before: GameMode) {
+    let playing = |mode: GameMode| matches!(mode, GameMode::OnePlayer | GameMode::TwoPlayer | GameMode::Network);
+    if !is_on() || !(playing(pong.game.game_mode) || playing(before)) {
+        return;
+    }
+    if pong.netplay.as_ref().is_some_and(netplay::Session::uses_link) {
+        return;
+    }
+    // A frame waiting already has its work queued; otherwise, with the queue full, drop this one
+    if PENDING.lock().replace(encode(pong)).is_none() && !deferred::defer(send) {
+        PENDING.lock().take();
+    }
+}
+
+fn send() {
+    let frame = PENDING.lock().take();
+    if let Some(frame) = frame {
+        link::send_frame(&frame);
+    }
+}
+
+/// The frame for `pong`'s state as it is.
+pub fn encode(pong: &Pong) -> [u8; LEN] {
+    let game = &pong.game;
+    let (ball, paddle1, paddle2) = (game.ball(), game.paddle(1), game.paddle(2));
+    let mut frame = [0; LEN];
+    frame[0..2].copy_from_slice(&MAGIC);
+    frame[2] = game.game_mode as u8;
+    frame[3..7].copy_from_slice(&game.match_ticks.to_le_bytes());
+    frame[7..9].copy_from_slice(&(game.width as u16).to_le_bytes());
+    frame[9..11].copy_from_slice(&(game.height as u16).to_le_bytes());
+    // Float to int casts saturate, which is as good as anything for a ball gone that far
+    for (at, position) in [(11, ball.x), (13, ball.y), (15, paddle1.y), (17, paddle2.y)] {
+        frame[at..at + 2].copy_from_slice(&(position as i16).to_le_bytes());
+    }
+    frame[19..21].copy_from_slice(&(game.paddle_height() as u16).to_le_bytes());
+    let score = &game.scoreboard;
+    frame[21..25].copy_from_slice(&[score.points(1), score.points(2), score.games(1), score.games(2)].map(|n| n.min(255) as u8));
+    frame[25..29].copy_from_slice(&netplay::checksum(pong).to_le_bytes());
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::game;
+
+    #[test_case]
+    fn spectator_frame_carries_the_field() {
+        let mut pong = game(GameMode::TwoPlayer);
+        pong.game.ball_mut().x = -3.5;
+        pong.game.paddle_mut(2).y = 120.0;
+        pong.game.scoreboard.set_points(2, 1);
+        pong.game.match_ticks = 300;
+        let frame = encode(&pong);
+        let word = |at: usize| u16::from_le_bytes([frame[at], frame[at + 1]]);
+        assert_eq!((&frame[..2], frame[2]), (&b"PS"[..], GameMode::TwoPlayer as u8));
+        assert_eq!(u32::from_le_bytes(frame[3..7].try_into().unwrap()), 300);
+        assert_eq!((word(7), word(9)), (640, 480));
+        assert_eq!((word(11) as i16, word(17), word(19) as usize), (-3, 120, pong.game.paddle_height()));
+        assert_eq!(frame[21..25], [2, 1, 0, 0]);
+        assert_eq!(u32::from_le_bytes(frame[25..].try_into().unwrap()), netplay::checksum(&pong));
+    }
+}
@@ -0,0 +1,363 @@
+use alloc::string::String;
+use core::fmt::{Display, Write};
+use core::sync::atomic::{AtomicU8, Ordering};
+
+// The text the player reads, in each language there is. Screens ask for a [Text] and get it in
+// the language chosen in the settings; one with numbers or names in it has a `{}` for each, which
+// [fill] replaces in order, so a translation can put them where its grammar wants them. Every
+// language has to give every text, or the match in its table does not compile.
+//
+// The font only has ASCII (and VGA text mode only shows it), so translations keep to it.
+
+/// Languages the game can be played in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Language {
+    English,
+    Dutch,
+}
+
+impl Language {
+    pub const ALL: [Language; 2] = [Language::English, Language::Dutch];
+
+    /// The language's name for itself.
+    pub fn name(self) -> &'static str {
+        match self {
+            Language::English => "English",
+            Language::Dutch => "Nederlands",
+        }
+    }
+}
+
+/// Index in [Language::ALL] of the language the text is in.
+static LANGUAGE: AtomicU8 = AtomicU8::new(0);
+
+pub fn set_language(language: Language) {
+    let index = Language::ALL.iter().position(|&l| l == language).unwrap_or(0);
+    LANGUAGE.store(index as u8, Ordering::Relaxed);
+}
+
+pub fn language() -> Language {
+    Language::ALL[LANGUAGE.load(Ordering::Relaxed) as usize]
+}
+
+/// Every piece of text on the menus and around the games.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Text {
+    Title,
+    MenuOnePlayer,
+    MenuTwoPlayer,
+    MenuSettings,
+    MenuControls,
+    MenuProgram,
+    MenuNetwork,
+    MenuSerial,
+    /// Before the launcher's games, "8: Snake  9: Tetris".
+    MenuPress,
+    MenuProfiles,
+    MenuQuit,
+    ControlsHeading,
+    /// The player, then the keys up and down.
+    PlayerMoves,
+    /// The player.
+    PlayerWins,
+    PlayAgain,
+    ReturnToMenu,
+    HighScores,
+    /// The number of returns.
+    Returns,
+    /// Paddle hits since the serve, on the HUD.
+    Rally,
+    InstantReplay,
+    /// The quick chat's taunts.
+    TauntNiceShot,
+    TauntTooEasy,
+    TauntOops,
+    TauntGoodGame,
+
+    SettingsTitle,
+    SettingsHelp,
+    On,
+    Off,
+    MouseControl,
+    MouseSensitivity,
+    KeyboardLayout,
+    ShowClock,
+    Language,
+    SoundSettings,
+    /// Frames drawn a second, at most.
+    FrameRate,
+    /// The computer player's personality.
+    Personality,
+    TrainingOverlay,
+    AccessibilitySettings,
+    /// Width and height.
+    Resolution,
+    ResolutionFixed,
+    ResolutionNative,
+
+    SoundTitle,
+    /// The volume and the most it goes up to, as are the effects.
+    MasterVolume,
+    EffectsVolume,
+    /// On or off.
+    Music,
+    ReturnToSettings,
+    Muted,
+    NoSoundCard,
+
+    AccessibilityTitle,
+    /// On or off, as is the reduced flashing.
+    HighContrast,
+    /// In percent.
+    DrawSize,
+    ReducedFlash,
+
+    ControlsTitle,
+    ControlsHelp,
+    /// The action.
+    PressKeyFor,
+    /// The player, in the names of the actions.
+    ActionUp,
+    ActionDown,
+
+    NetworkTitle,
+    NetworkWaiting,
+    /// This machine's IP address.
+    NetworkAddress,
+    SerialTitle,
+    SerialWaiting,
+
+    ProfilesTitle,
+    ProfilesHelp,
+    /// The player, then the name.
+    ProfilePlayer,
+    ProfileNobody,
+    /// The name, the wins and the losses.
+    ProfileRecord,
+    ProfilesNone,
+
+    GameOver,
+    SpaceToPlayAgain,
+    /// The score.
+    SnakeStatus,
+    Score,
+    Lines,
+    Level,
+    Next,
+    RToMenu,
+    SpaceAgain,
+}
+
+/// `text` in the language chosen.
+pub fn text(text: Text) -> &'static str {
+    match language() {
+        Language::English => english(text),
+        Language::Dutch => dutch(text),
+    }
+}
+
+/// `text` in the language chosen, with its `{}`s replaced by `args` in order.
+pub fn fill(text: Text, args: &[&dyn Display]) -> String {
+    let mut pieces = self::text(text).split("{}");
+    let mut out = String::from(pieces.next().unwrap_or_default());
+    let mut args = args.iter();
+    for piece in pieces {
+        if let Some(arg) = args.next() {
+            write!(out, "{}", arg).unwrap();
+        }
+        out.push_str(piece);
+    }
+    out
+}
+
+fn english(text: Text) -> &'static str {
+    match text {
+        Text::Title => "PONG GAME",
+        Text::MenuOnePlayer => "Press 1: 1 Player",
+        Text::MenuTwoPlayer => "Press 2: 2 Player",
+        Text::MenuSettings => "Press 3: Settings",
+        Text::MenuControls => "Press 4: Controls",
+        Text::MenuProgram => "Press 5: Pong (user program)",
+        Text::MenuNetwork => "Press 6: Network game",
+        Text::MenuSerial => "Press 7: Serial link game",
+        Text::MenuPress => "Press {}",
+        Text::MenuProfiles => "Press P: Player profiles",
+        Text::MenuQuit => "Press Q: Quit",
+        Text::ControlsHeading => "Controls:",
+        Text::PlayerMoves => "Player {}: {}/{} to move",
+        Text::PlayerWins => "Player {} Wins!",
+        Text::PlayAgain => "Press P to play again",
+        Text::ReturnToMenu => "Press R to return to menu",
+        Text::HighScores => "HIGH SCORES",
+        Text::Returns => "{} returns",
+        Text::Rally => "Rally: {}",
+        Text::InstantReplay => "REPLAY",
+        Text::TauntNiceShot => "Nice shot!",
+        Text::TauntTooEasy => "Too easy!",
+        Text::TauntOops => "Oops!",
+        Text::TauntGoodGame => "Good game!",
+
+        Text::SettingsTitle => "SETTINGS",
+        Text::SettingsHelp => "W/S: select  A/D: change",
+        Text::On => "On",
+        Text::Off => "Off",
+        Text::MouseControl => "Mouse control (Player 1): {}",
+        Text::MouseSensitivity => "Mouse sensitivity: {}",
+        Text::KeyboardLayout => "Keyboard layout: {}",
+        Text::ShowClock => "Show clock on menu: {}",
+        Text::Language => "Language: {}",
+        Text::SoundSettings => "Sound: Enter to change",
+        Text::FrameRate => "Frame rate: {} fps",
+        Text::Personality => "Computer player: {}",
+        Text::TrainingOverlay => "Show the ball's path: {}",
+        Text::AccessibilitySettings => "Accessibility: Enter to change",
+        Text::Resolution => "Resolution: {}x{}",
+        Text::ResolutionFixed => "Resolution: {}x{} (fixed)",
+        Text::ResolutionNative => "Resolution: {}x{} (native)",
+
+        Text::SoundTitle => "SOUND",
+        Text::MasterVolume => "Volume: {}/{}",
+        Text::EffectsVolume => "Sound effects: {}/{}",
+        Text::Music => "Music: {}",
+        Text::ReturnToSettings => "Press R to return to settings",
+        Text::Muted => "Muted: press F8 to hear it again",
+        Text::NoSoundCard => "No sound card found",
+
+        Text::AccessibilityTitle => "ACCESSIBILITY",
+        Text::HighContrast => "High contrast: {}",
+        Text::DrawSize => "Ball and paddle size: {}%",
+        Text::ReducedFlash => "Reduced flashing: {}",
+
+        Text::ControlsTitle => "CONTROLS",
+        Text::ControlsHelp => "W/S: select  Enter: rebind",
+        Text::PressKeyFor => "Press a key for {}",
+        Text::ActionUp => "Player {} Up",
+        Text::ActionDown => "Player {} Down",
+
+        Text::NetworkTitle => "NETWORK GAME",
+        Text::NetworkWaiting => "Waiting for another player on the network...",
+        Text::NetworkAddress => "This machine is {}",
+        Text::SerialTitle => "SERIAL LINK GAME",
+        Text::SerialWaiting => "Waiting for the other machine on the serial link...",
+
+        Text::ProfilesTitle => "PLAYER PROFILES",
+        Text::ProfilesHelp => "Type a name, Tab for the other player, Enter when done",
+        Text::ProfilePlayer => "Player {}: {}",
+        Text::ProfileNobody => "(nobody)",
+        Text::ProfileRecord => "{}  {} won, {} lost",
+        Text::ProfilesNone => "No records yet",
+
+        Text::GameOver => "Game over",
+        Text::SpaceToPlayAgain => "Press Space to play again",
+        Text::SnakeStatus => "SNAKE  Score: {}  R: menu",
+        Text::Score => "Score: {}",
+        Text::Lines => "Lines: {}",
+        Text::Level => "Level: {}",
+        Text::Next => "Next:",
+        Text::RToMenu => "R: menu",
+        Text::SpaceAgain => "Space: again",
+    }
+}
+
+fn dutch(text: Text) -> &'static str {
+    match text {
+        Text::Title => "PONG",
+        Text::MenuOnePlayer => "Druk op 1: 1 speler",
+        Text::MenuTwoPlayer => "Druk op 2: 2 spelers",
+        Text::MenuSettings => "Druk op 3: Instellingen",
+        Text::MenuControls => "Druk op 4: Besturing",
+        Text::MenuProgram => "Druk op 5: Pong (gebruikersprogramma)",
+        Text::MenuNetwork => "Druk op 6: Netwerkspel",
+        Text::MenuSerial => "Druk op 7: Spel via nulmodemkabel",
+        Text::MenuPress => "Druk op {}",
+        Text::MenuProfiles => "Druk op P: Spelersprofielen",
+        Text::MenuQuit => "Druk op Q: Afsluiten",
+        Text::ControlsHeading => "Besturing:",
+        Text::PlayerMoves => "Speler {}: {}/{} om te bewegen",
+        Text::PlayerWins => "Speler {} wint!",
+        Text::PlayAgain => "Druk op P om opnieuw te spelen",
+        Text::ReturnToMenu => "Druk op R om terug te gaan naar het menu",
+        Text::HighScores => "TOPSCORES",
+        Text::Returns => "{} keer teruggeslagen",
+        Text::Rally => "Slagen: {}",
+        Text::InstantReplay => "HERHALING",
+        Text::TauntNiceShot => "Mooi geslagen!",
+        Text::TauntTooEasy => "Te makkelijk!",
+        Text::TauntOops => "Oeps!",
+        Text::TauntGoodGame => "Goed gespeeld!",
+
+        Text::SettingsTitle => "INSTELLINGEN",
+        Text::SettingsHelp => "W/S: kiezen  A/D: wijzigen",
+        Text::On => "Aan",
+        Text::Off => "Uit",
+        Text::MouseControl => "Muisbesturing (speler 1): {}",
+        Text::MouseSensitivity => "Muisgevoeligheid: {}",
+        Text::KeyboardLayout => "Toetsenbordindeling: {}",
+        Text::ShowClock => "Klok in het menu: {}",
+        Text::Language => "Taal: {}",
+        Text::SoundSettings => "Geluid: Enter om te wijzigen",
+        Text::FrameRate => "Beeldsnelheid: {} fps",
+        Text::Personality => "Computerspeler: {}",
+        Text::TrainingOverlay => "Baan van de bal tonen: {}",
+        Text::AccessibilitySettings => "Toegankelijkheid: Enter om te wijzigen",
+        Text::Resolution => "Resolutie: {}x{}",
+        Text::ResolutionFixed => "Resolutie: {}x{} (vast)",
+        Text::ResolutionNative => "Resolutie: {}x{} (eigen)",
+
+        Text::SoundTitle => "GELUID",
+        Text::MasterVolume => "Volume: {}/{}",
+        Text::EffectsVolume => "Geluidseffecten: {}/{}",
+        Text::Music => "Muziek: {}",
+        Text::ReturnToSettings => "Druk op R om terug te gaan naar de instellingen",
+        Text::Muted => "Gedempt: druk op F8 om het weer te horen",
+        Text::NoSoundCard => "Geen geluidskaart gevonden",
+
+        Text::AccessibilityTitle => "TOEGANKELIJKHEID",
+        Text::HighContrast => "Hoog contrast: {}",
+        Text::DrawSize => "Grootte van bal en batjes: {}%",
+        Text::ReducedFlash => "Minder knipperen: {}",
+
+        Text::ControlsTitle => "BESTURING",
+        Text::ControlsHelp => "W/S: kiezen  Enter: wijzigen",
+        Text::PressKeyFor => "Druk op een toets voor {}",
+        Text::ActionUp => "Speler {} omhoog",
+        Text::ActionDown => "Speler {} omlaag",
+
+        Text::NetworkTitle => "NETWERKSPEL",
+        Text::NetworkWaiting => "Wachten op een andere speler op het netwerk...",
+        Text::NetworkAddress => "Deze machine is {}",
+        Text::SerialTitle => "SPEL VIA NULMODEMKABEL",
+        Text::SerialWaiting => "Wachten op de andere machine aan de kabel...",
+
+        Text::ProfilesTitle => "SPELERSPROFIELEN",
+        Text::ProfilesHelp => "Typ een naam, Tab voor de andere speler, Enter als je klaar bent",
+        Text::ProfilePlayer => "Speler {}: {}",
+        Text::ProfileNobody => "(niemand)",
+        Text::ProfileRecord => "{}  {} gewonnen, {} verloren",
+        Text::ProfilesNone => "Nog geen resultaten",
+
+        Text::GameOver => "Afgelopen",
+        Text::SpaceToPlayAgain => "Druk op spatie om opnieuw te spelen",
+        Text::SnakeStatus => "SNAKE  Score: {}  R: menu",
+        Text::Score => "Score: {}",
+        Text::Lines => "Rijen: {}",
+        Text::Level => "Niveau: {}",
+        Text::Next => "Volgende:",
+        Text::RToMenu => "R: menu",
+        Text::SpaceAgain => "Spatie: opnieuw",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn text_is_in_the_language_chosen() {
+        set_language(Language::Dutch);
+        assert_eq!(fill(Text::PlayerWins, &[&2]), "Speler 2 wint!");
+        set_language(Language::English);
+        assert_eq!(fill(Text::PlayerMoves, &[&1, &"W", &"S"]), "Player 1: W/S to move");
+        assert_eq!(text(Text::MenuQuit), "Press Q: Quit");
+    }
+}
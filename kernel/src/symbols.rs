@@ -0,0 +1,91 @@
+use core::fmt;
+use spin::Mutex;
+use crate::elf;
+
+/// The kernel's ELF file and the offset it was relocated by, kept for looking up symbols.
+static KERNEL: Mutex<Option<(&'static [u8], u64)>> = Mutex::new(None);
+
+/// Makes the symbol table of `image`, the kernel's own ELF file loaded at `load_offset`,
+/// available to [lookup].
+pub fn init(image: &'static [u8], load_offset: u64) {
+    *KERNEL.lock() = Some((image, load_offset));
+}
+
+/// The function containing `address`, and how far into it the address is. Searches the whole
+/// table, which is fine for backtraces but not for anything frequent.
+pub fn lookup(address: u64) -> Option<(Demangled<'static>, u64)> {
+    // try_lock: lookups happen on panic, possibly in the middle of init
+    let (image, load_offset) = (*KERNEL.try_lock()?)?;
+    let address = address.checked_sub(load_offset)?;
+    elf::function_symbols(image)
+        .find(|symbol| symbol.address <= address && address < symbol.address + symbol.size.max(1))
+        .map(|symbol| (Demangled(symbol.name), address - symbol.address))
+}
+
+/// A symbol name that displays demangled. Handles the legacy Rust mangling
+/// (`_ZN4core3fmt5write17h0123456789abcdefE` is `core::fmt::write`); anything else is shown
+/// as it is.
+#[derive(Debug, Clone, Copy)]
+pub struct Demangled<'a>(pub &'a str);
+
+impl fmt::Display for Demangled<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(mut rest) = self.0.strip_prefix("_ZN").and_then(|name| name.strip_suffix('E')) else {
+            return f.write_str(self.0);
+        };
+
+        let mut first = true;
+        while !rest.is_empty() {
+            let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+            let Some(len) = rest[..digits].parse::<usize>().ok().filter(|&len| digits + len <= rest.len()) else {
+                return f.write_str(self.0);
+            };
+            let segment = &rest[digits..digits + len];
+            rest = &rest[digits + len..];
+            // The last segment is a hash that tells apart otherwise identical names
+            if rest.is_empty() && segment.len() == 17 && segment.starts_with('h') {
+                break;
+            }
+            if !first {
+                f.write_str("::")?;
+            }
+            first = false;
+            write_segment(f, segment)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes a path segment, undoing the escapes for characters symbols cannot hold.
+fn write_segment(f: &mut fmt::Formatter<'_>, segment: &str) -> fmt::Result {
+    // Segments starting with an escape get an underscore in front
+    let mut rest = if segment.starts_with("_$") { &segment[1..] } else { segment };
+    while !rest.is_empty() {
+        if let Some(tail) = rest.strip_prefix("..") {
+            f.write_str("::")?;
+            rest = tail;
+        } else if let Some(tail) = rest.strip_prefix('$') {
+            let Some(end) = tail.find('$') else { return f.write_str(rest) };
+            match &tail[..end] {
+                "SP" => f.write_str("@")?,
+                "BP" => f.write_str("*")?,
+                "RF" => f.write_str("&")?,
+                "LT" => f.write_str("<")?,
+                "GT" => f.write_str(">")?,
+                "LP" => f.write_str("(")?,
+                "RP" => f.write_str(")")?,
+                "C" => f.write_str(",")?,
+                escape => match escape.strip_prefix('u').and_then(|hex| u32::from_str_radix(hex, 16).ok()).and_then(char::from_u32) {
+                    Some(c) => write!(f, "{}", c)?,
+                    None => write!(f, "${}$", escape)?,
+                },
+            }
+            rest = &tail[end + 1..];
+        } else {
+            let end = rest.find(['$', '.']).unwrap_or(rest.len()).max(1);
+            f.write_str(&rest[..end])?;
+            rest = &rest[end..];
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,82 @@
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+use x86_64::instructions::interrupts;
+
+/// A spin lock that keeps interrupts disabled on the holding CPU for as long as it is held.
+///
+/// With a plain spin lock, an interrupt handler that takes a lock the interrupted code is holding
+/// spins forever. Here the interrupt cannot arrive until the lock is released. The previous
+/// interrupt flag is restored on unlock, so locks nest and can be taken inside handlers.
+///
+/// Interrupts are delayed for the whole critical section; keep it short.
+pub struct IrqSafeMutex<T> {
+    inner: spin::Mutex<T>,
+}
+
+impl<T> IrqSafeMutex<T> {
+    pub const fn new(value: T) -> Self {
+        IrqSafeMutex { inner: spin::Mutex::new(value) }
+    }
+
+    pub fn lock(&self) -> IrqSafeMutexGuard<'_, T> {
+        let interrupts_were_enabled = interrupts::are_enabled();
+        interrupts::disable();
+        IrqSafeMutexGuard { guard: ManuallyDrop::new(self.inner.lock()), interrupts_were_enabled }
+    }
+
+    pub fn try_lock(&self) -> Option<IrqSafeMutexGuard<'_, T>> {
+        let interrupts_were_enabled = interrupts::are_enabled();
+        interrupts::disable();
+        match self.inner.try_lock() {
+            Some(guard) => Some(IrqSafeMutexGuard { guard: ManuallyDrop::new(guard), interrupts_were_enabled }),
+            None => {
+                if interrupts_were_enabled {
+                    interrupts::enable();
+                }
+                None
+            }
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.inner.is_locked()
+    }
+
+    /// Releases the lock without a guard. The interrupt flag stays as the holder left it.
+    ///
+    /// ## Safety
+    /// The holder must never touch the data again, e.g. because it is stuck or the system is
+    /// about to halt.
+    pub unsafe fn force_unlock(&self) {
+        unsafe { self.inner.force_unlock() };
+    }
+}
+
+pub struct IrqSafeMutexGuard<'a, T> {
+    guard: ManuallyDrop<spin::MutexGuard<'a, T>>,
+    interrupts_were_enabled: bool,
+}
+
+impl<T> Deref for IrqSafeMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for IrqSafeMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T> Drop for IrqSafeMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        // Unlock first: an interrupt arriving right after enabling may want the lock
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        if self.interrupts_were_enabled {
+            interrupts::enable();
+        }
+    }
+}
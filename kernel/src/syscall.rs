@@ -0,0 +1,176 @@
+use core::arch::{asm, global_asm};
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU16, AtomicU64, Ordering};
+use core::time::Duration;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::registers::model_specific::{Efer, EferFlags, LStar, SFMask, Star};
+use x86_64::registers::rflags::RFlags;
+use x86_64::structures::gdt::SegmentSelector;
+use x86_64::VirtAddr;
+use crate::{percpu, process, scheduler, serial, time};
+
+// System calls from ring 3 programs. The number goes in rax and up to five arguments in rdi, rsi,
+// rdx, r10 and r8 (rcx and r11 are taken by the syscall instruction); the result comes back in
+// rax. The numbers are shared with the user crate, and must stay in step with it.
+
+/// exit(code): ends the program.
+pub const EXIT: u64 = 0;
+/// sleep(ms): blocks for at least `ms` milliseconds.
+pub const SLEEP: u64 = 1;
+/// poll_key() -> the next character typed, or [NO_KEY].
+pub const POLL_KEY: u64 = 2;
+/// screen_size() -> width << 32 | height, in pixels.
+pub const SCREEN_SIZE: u64 = 3;
+/// fill_rect(x, y, width, height, 0xRRGGBB)
+pub const FILL_RECT: u64 = 4;
+/// draw_text(x, y, pointer, length, 0xRRGGBB): draws a UTF-8 string.
+pub const DRAW_TEXT: u64 = 5;
+/// uptime() -> milliseconds since boot.
+pub const UPTIME: u64 = 6;
+/// write(pointer, length): writes a UTF-8 string to the serial console.
+pub const WRITE: u64 = 7;
+
+/// Returned by [POLL_KEY] when no key is waiting.
+pub const NO_KEY: u64 = u64::MAX;
+/// Returned for an unknown system call or invalid arguments.
+pub const ERROR: u64 = u64::MAX - 1;
+
+/// Where programs draw. Registered by the kernel binary, which owns the framebuffer.
+#[derive(Clone, Copy)]
+pub struct Screen {
+    pub size: fn() -> (usize, usize),
+    pub fill_rect: fn(usize, usize, usize, usize, u32),
+    pub draw_text: fn(usize, usize, &str, u32),
+}
+
+static SCREEN: Mutex<Option<Screen>> = Mutex::new(None);
+
+pub fn set_screen(screen: Screen) {
+    *SCREEN.lock() = Some(screen);
+}
+
+/// Top of the stack system calls run on, the same one the TSS gives interrupts from ring 3.
+static KERNEL_STACK: AtomicU64 = AtomicU64::new(0);
+/// Scratch space for the user stack pointer while switching stacks.
+static USER_STACK: AtomicU64 = AtomicU64::new(0);
+static USER_CODE: AtomicU16 = AtomicU16::new(0);
+static USER_DATA: AtomicU16 = AtomicU16::new(0);
+
+// On entry rcx holds the user RIP and r11 the user RFLAGS, and interrupts are off (SFMask). The
+// user stack pointer is kept on the kernel stack, so a thread switch during the call is fine.
+global_asm!(
+    ".global syscall_entry",
+    "syscall_entry:",
+    "mov [rip + {user_stack}], rsp",
+    "mov rsp, [rip + {kernel_stack}]",
+    "push [rip + {user_stack}]",
+    "push rcx",
+    "push r11",
+    "mov rcx, r10",
+    "mov r9, rax",
+    "sub rsp, 8",
+    "call {dispatch}",
+    "add rsp, 8",
+    // Nothing may interrupt once rsp is the user's again
+    "cli",
+    "pop r11",
+    "pop rcx",
+    "pop rsp",
+    "sysretq",
+    user_stack = sym USER_STACK,
+    kernel_stack = sym KERNEL_STACK,
+    dispatch = sym dispatch,
+);
+
+unsafe extern "C" {
+    fn syscall_entry();
+}
+
+/// Enables the syscall instruction. `kernel_stack` is the top of the stack calls run on; the TSS
+/// must send interrupts from ring 3 to the same one. The selectors must be in the order sysret
+/// expects: user data right before user code.
+pub fn init(kernel_code: SegmentSelector, kernel_data: SegmentSelector, user_code: SegmentSelector, user_data: SegmentSelector, kernel_stack: VirtAddr) {
+    KERNEL_STACK.store(kernel_stack.as_u64(), Ordering::Relaxed);
+    USER_CODE.store(user_code.0, Ordering::Relaxed);
+    USER_DATA.store(user_data.0, Ordering::Relaxed);
+    Star::write(user_code, user_data, kernel_code, kernel_data).expect("GDT is not laid out for sysret");
+    LStar::write(VirtAddr::new((syscall_entry as unsafe extern "C" fn()) as usize as u64));
+    SFMask::write(RFlags::INTERRUPT_FLAG | RFlags::DIRECTION_FLAG | RFlags::TRAP_FLAG);
+    unsafe { Efer::update(|flags| *flags |= EferFlags::SYSTEM_CALL_EXTENSIONS) };
+}
+
+/// Drops to ring 3 at `entry`, with the stack pointer at `stack`.
+pub(crate) unsafe fn enter_user_mode(entry: VirtAddr, stack: VirtAddr) -> ! {
+    let code = USER_CODE.load(Ordering::Relaxed) as u64;
+    let data = USER_DATA.load(Ordering::Relaxed) as u64;
+    let flags = (RFlags::INTERRUPT_FLAG | RFlags::from_bits_retain(0x2)).bits();
+    unsafe {
+        asm!(
+            "mov ds, {data:x}",
+            "mov es, {data:x}",
+            "push {data}",
+            "push {stack}",
+            "push {flags}",
+            "push {code}",
+            "push {entry}",
+            "iretq",
+            data = in(reg) data,
+            stack = in(reg) stack.as_u64(),
+            flags = in(reg) flags,
+            code = in(reg) code,
+            entry = in(reg) entry.as_u64(),
+            options(noreturn),
+        );
+    }
+}
+
+/// The string a program passed as `pointer` and `length`, if it lies in memory the program can
+/// read and is UTF-8.
+fn user_str<'a>(pointer: u64, length: u64) -> Option<&'a str> {
+    if !process::is_user_memory(pointer, length) {
+        return None;
+    }
+    let bytes = unsafe { core::slice::from_raw_parts(pointer as *const u8, length as usize) };
+    core::str::from_utf8(bytes).ok()
+}
+
+extern "C" fn dispatch(a0: u64, a1: u64, a2: u64, a3: u64, a4: u64, number: u64) -> u64 {
+    // The program may have moved GS; before interrupts, whose handlers would fix it up too
+    percpu::restore();
+    // Calls may block, and the timer has to keep running meanwhile
+    interrupts::enable();
+    let screen = *SCREEN.lock();
+    match number {
+        EXIT => process::exit(a0 as i64),
+        SLEEP => {
+            scheduler::sleep(Duration::from_millis(a0));
+            0
+        }
+        POLL_KEY => process::next_key().map_or(NO_KEY, |c| c as u64),
+        SCREEN_SIZE => screen.map_or(0, |screen| {
+            let (width, height) = (screen.size)();
+            (width as u64) << 32 | height as u64
+        }),
+        FILL_RECT => screen.map_or(ERROR, |screen| {
+            (screen.fill_rect)(a0 as usize, a1 as usize, a2 as usize, a3 as usize, a4 as u32);
+            0
+        }),
+        DRAW_TEXT => match (screen, user_str(a2, a3)) {
+            (Some(screen), Some(text)) => {
+                (screen.draw_text)(a0 as usize, a1 as usize, text, a4 as u32);
+                0
+            }
+            _ => ERROR,
+        },
+        UPTIME => time::uptime().as_millis() as u64,
+        WRITE => match user_str(a0, a1) {
+            Some(text) => {
+                let _ = serial().write_str(text);
+                0
+            }
+            None => ERROR,
+        },
+        _ => ERROR,
+    }
+}
@@ -0,0 +1,77 @@
+use core::fmt::Write;
+use core::slice;
+use bootloader_api::BootInfo;
+use bootloader_api::BootloaderConfig;
+use bootloader_api::config::Mapping::Dynamic;
+use x86_64::VirtAddr;
+use crate::buddy::BUDDY;
+use crate::{acpi_tables, allocator, cpu, fpu, frame_allocator, gdt, interrupts, memory, qemu, serial, symbols, time, uart};
+
+// Custom test framework for the kernel, which has no std and so no libtest. Test kernels (the
+// ones in kernel/tests, and the game kernel built for `cargo test`) name [test_runner] in their
+// `#![test_runner]`, and mark tests with `#[test_case]`. They run in QEMU through the cargo
+// runner in test-runner/: results go to serial, and the kernel ends QEMU with the outcome
+// through the debug-exit device. A failing test panics, and the panic handler ends QEMU with
+// qemu::ExitCode::Failure.
+
+/// Bootloader settings for test kernels: the same memory layout as the game's.
+pub const BOOTLOADER_CONFIG: BootloaderConfig = {
+    let mut config = BootloaderConfig::new_default();
+    config.mappings.physical_memory = Some(Dynamic);
+    config.kernel_stack_size = 256 * 1024;
+    config
+};
+
+/// A test: any function taking nothing, named in the report by its path.
+pub trait Testable {
+    fn run(&self);
+}
+
+impl<T: Fn()> Testable for T {
+    fn run(&self) {
+        let _ = write!(serial(), "{}... ", core::any::type_name::<T>());
+        self();
+        let _ = writeln!(serial(), "[ok]");
+    }
+}
+
+/// Runs `tests` in order and ends QEMU with success; the first failure never gets back here.
+pub fn test_runner(tests: &[&dyn Testable]) {
+    let _ = writeln!(serial(), "running {} tests", tests.len());
+    for test in tests {
+        test.run();
+    }
+    let _ = writeln!(serial(), "test result: ok");
+    uart::flush();
+    qemu::exit(qemu::ExitCode::Success);
+}
+
+/// The part of the boot a test kernel needs: CPU features, the heap and page allocator, page
+/// tables, GDT, clock, ACPI and the local APIC. Returns the local APIC's address for
+/// [crate::HandlerTable::start], which sets up interrupts. Panics end QEMU from here on.
+pub fn init(boot_info: &'static mut BootInfo) -> *mut u32 {
+    qemu::set_exit_on_panic(true);
+    cpu::init();
+    fpu::init();
+
+    let physical_offset = boot_info.physical_memory_offset.take().expect("Failed to find physical memory offset");
+    let mut heap_needed = allocator::HEAP_SIZE as u64;
+    for region in frame_allocator::usable_regions(&boot_info.memory_regions) {
+        let heap_end = (region.start + heap_needed).min(region.end);
+        if heap_end > region.start {
+            allocator::init_heap((physical_offset + region.start) as usize, (heap_end - region.start) as usize);
+            heap_needed -= heap_end - region.start;
+        }
+        BUDDY.lock().add_region(physical_offset, heap_end..region.end);
+    }
+    allocator::enable_growth(physical_offset, allocator::HEAP_LIMIT);
+
+    memory::init(VirtAddr::new(physical_offset));
+    let kernel_image = unsafe { slice::from_raw_parts((physical_offset + boot_info.kernel_addr) as *const u8, boot_info.kernel_len as usize) };
+    symbols::init(kernel_image, boot_info.kernel_image_offset);
+    gdt::init();
+    time::init();
+    let rsdp = boot_info.rsdp_addr.take().expect("Failed to get RSDP address");
+    acpi_tables::init(rsdp as usize, VirtAddr::new(physical_offset));
+    interrupts::init_apic()
+}
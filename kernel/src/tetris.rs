@@ -0,0 +1,147 @@
+use alloc::boxed::Box;
+use core::time::Duration;
+use game::tetris::{COLUMNS, Piece, ROWS, Shape, Tetris};
+use kernel::{audio, rng};
+use pc_keyboard::{DecodedKey, KeyCode};
+use crate::launcher::{self, Game};
+use crate::screen::screenwriter;
+use crate::strings::{Text, fill, text};
+
+// Tetris, on the launcher; the rules are game::tetris's. The well stands in the middle of the
+// screen with the score and the next piece beside it. Left and right (or A and D) move the piece,
+// up (W) turns it, down (S) brings it down a row and Space drops it. R goes back to the menu, and
+// after a game Space plays another.
+
+/// Size of a cell of the well, in pixels.
+const CELL: usize = 16;
+const WALL: (u8, u8, u8) = (0x55, 0x55, 0x55);
+
+fn color(shape: Shape) -> (u8, u8, u8) {
+    match shape {
+        Shape::I => (0x55, 0xFF, 0xFF),
+        Shape::O => (0xFF, 0xFF, 0x55),
+        Shape::T => (0xFF, 0x55, 0xFF),
+        Shape::S => (0x55, 0xFF, 0x55),
+        Shape::Z => (0xFF, 0x55, 0x55),
+        Shape::J => (0x55, 0x55, 0xFF),
+        Shape::L => (0xFF, 0xAA, 0x00),
+    }
+}
+
+struct TetrisGame {
+    tetris: Tetris,
+    width: usize,
+    height: usize,
+    /// Ticks since the piece last fell.
+    ticks: u32,
+}
+
+pub fn start(width: usize, height: usize) -> Box<dyn Game> {
+    Box::new(TetrisGame { tetris: Tetris::new(rng::u32), width, height, ticks: 0 })
+}
+
+impl TetrisGame {
+    /// Top left of the well on the screen.
+    fn origin(&self) -> (usize, usize) {
+        ((self.width - COLUMNS * CELL) / 2, self.height.saturating_sub(ROWS * CELL) / 2)
+    }
+
+    fn draw_cell(&self, (left, top): (usize, usize), (x, y): (usize, usize), shape: Shape) {
+        launcher::fill(left + x * CELL + 1, top + y * CELL + 1, CELL - 2, CELL - 2, color(shape));
+    }
+
+    /// Ticks between falls: half a second at level 1, down to a twentieth from level 10.
+    fn fall_ticks(&self) -> u32 {
+        let level = self.tetris.level().min(10);
+        (crate::tick_hz() * (11 - level) / 20).max(1)
+    }
+
+    fn locked(&self, events: game::tetris::Events) {
+        if events.game_over {
+            audio::play_tone(220, Duration::from_millis(250), crate::EFFECT_VOLUME);
+        } else if events.lines > 0 {
+            audio::play_tone(880, Duration::from_millis(80), crate::EFFECT_VOLUME);
+        }
+    }
+}
+
+impl Game for TetrisGame {
+    fn update(&mut self) {
+        self.ticks += 1;
+        if self.ticks < self.fall_ticks() {
+            return;
+        }
+        self.ticks = 0;
+        let events = self.tetris.fall(rng::u32);
+        self.locked(events);
+    }
+
+    fn draw(&self) {
+        let (left, top) = self.origin();
+        // Walls and floor, a cell thick
+        launcher::fill(left - CELL, top, CELL, ROWS * CELL, WALL);
+        launcher::fill(left + COLUMNS * CELL, top, CELL, ROWS * CELL, WALL);
+        launcher::fill(left - CELL, top + ROWS * CELL, (COLUMNS + 2) * CELL, CELL.min(self.height - top - ROWS * CELL), WALL);
+        for y in 0..ROWS {
+            for x in 0..COLUMNS {
+                if let Some(shape) = self.tetris.cell(x, y) {
+                    self.draw_cell((left, top), (x, y), shape);
+                }
+            }
+        }
+        if !self.tetris.over {
+            let piece = self.tetris.piece;
+            for (x, y) in piece.cells() {
+                self.draw_cell((left, top), (x as usize, y as usize), piece.shape);
+            }
+        }
+
+        let side = left + (COLUMNS + 2) * CELL;
+        let (r, g, b) = (0xFF, 0xFF, 0xFF);
+        screenwriter().draw_string(side, top, "TETRIS", r, g, b);
+        screenwriter().draw_string(side, top + 32, &fill(Text::Score, &[&self.tetris.score]), r, g, b);
+        screenwriter().draw_string(side, top + 52, &fill(Text::Lines, &[&self.tetris.lines]), r, g, b);
+        screenwriter().draw_string(side, top + 72, &fill(Text::Level, &[&self.tetris.level()]), r, g, b);
+        screenwriter().draw_string(side, top + 112, text(Text::Next), r, g, b);
+        for (x, y) in Piece::preview(self.tetris.next).cells() {
+            self.draw_cell((side, top + 136), (x as usize, y as usize), self.tetris.next);
+        }
+        screenwriter().draw_string(side, top + 216, text(Text::RToMenu), 0xAA, 0xAA, 0xAA);
+        if self.tetris.over {
+            screenwriter().draw_string(side, top + 176, text(Text::GameOver), 0xFF, 0x55, 0x55);
+            screenwriter().draw_string(side, top + 196, text(Text::SpaceAgain), 0xFF, 0xFF, 0xFF);
+        }
+    }
+
+    fn handle_key(&mut self, key: DecodedKey) -> bool {
+        match key {
+            DecodedKey::RawKey(KeyCode::ArrowLeft) | DecodedKey::Unicode('a') => {
+                self.tetris.shift(-1);
+            }
+            DecodedKey::RawKey(KeyCode::ArrowRight) | DecodedKey::Unicode('d') => {
+                self.tetris.shift(1);
+            }
+            DecodedKey::RawKey(KeyCode::ArrowUp) | DecodedKey::Unicode('w') => {
+                self.tetris.rotate();
+            }
+            DecodedKey::RawKey(KeyCode::ArrowDown) | DecodedKey::Unicode('s') => {
+                let events = self.tetris.fall(rng::u32);
+                self.locked(events);
+                self.ticks = 0;
+            }
+            DecodedKey::Unicode(' ') if self.tetris.over => {
+                self.tetris = Tetris::new(rng::u32);
+                self.ticks = 0;
+            }
+            DecodedKey::Unicode(' ') => {
+                let events = self.tetris.hard_drop(rng::u32);
+                self.locked(events);
+                self.ticks = 0;
+            }
+            DecodedKey::Unicode('r') => return false,
+            _ => {}
+        }
+        true
+    }
+}
+
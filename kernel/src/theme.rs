@@ -0,0 +1,70 @@
+use alloc::vec::Vec;
+use core::fmt::Write;
+use kernel::serial;
+use spin::Once;
+
+// Colors of the playing field, picked at boot with the `theme` option: one of [THEMES], or one
+// the ramdisk brings (see [load_assets]).
+
+pub type Color = (u8, u8, u8);
+
+pub struct Theme {
+    pub name: &'static str,
+    pub paddles: Color,
+    pub ball: Color,
+    /// Scores and titles.
+    pub text: Color,
+}
+
+pub const THEMES: [Theme; 3] = [
+    Theme { name: "classic", paddles: (0xFF, 0xFF, 0xFF), ball: (0xFF, 0xFF, 0xFF), text: (0xFF, 0xFF, 0xFF) },
+    Theme { name: "neon", paddles: (0x00, 0xFF, 0xFF), ball: (0xFF, 0x2A, 0xD4), text: (0x39, 0xFF, 0x14) },
+    Theme { name: "amber", paddles: (0xFF, 0xB0, 0x00), ball: (0xFF, 0xB0, 0x00), text: (0xFF, 0xB0, 0x00) },
+];
+
+pub const CLASSIC: &Theme = &THEMES[0];
+/// The accessibility settings' high-contrast colors, in place of whichever theme was picked: the
+/// ball stands out from the paddles, and nothing is dimmer than full brightness.
+pub const HIGH_CONTRAST: Theme =
+    Theme { name: "high contrast", paddles: (0xFF, 0xFF, 0xFF), ball: (0xFF, 0xFF, 0x00), text: (0xFF, 0xFF, 0xFF) };
+
+/// Themes from the ramdisk (kernel::assets), after [THEMES].
+static LOADED: Once<Vec<Theme>> = Once::new();
+
+/// Reads the themes in the ramdisk's `themes/`, each file named for its theme and holding
+/// `paddles=RRGGBB ball=RRGGBB text=RRGGBB`; a color left out is the classic theme's. Runs once at
+/// boot, after kernel::assets::init; one that does not parse is logged and left out.
+pub fn load_assets() {
+    LOADED.call_once(|| {
+        kernel::assets::under("themes/")
+            .filter_map(|(name, data)| {
+                let theme = parse(name, data);
+                if theme.is_none() {
+                    writeln!(serial(), "theme: ignoring {}, expected paddles=RRGGBB ball=RRGGBB text=RRGGBB", name).unwrap();
+                }
+                theme
+            })
+            .collect()
+    });
+}
+
+fn parse(name: &'static str, data: &[u8]) -> Option<Theme> {
+    let mut theme = Theme { name, paddles: CLASSIC.paddles, ball: CLASSIC.ball, text: CLASSIC.text };
+    for word in core::str::from_utf8(data).ok()?.split_whitespace() {
+        let (key, value) = word.split_once('=')?;
+        let rgb = u32::from_str_radix(value, 16).ok().filter(|_| value.len() == 6)?;
+        let color = ((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8);
+        match key {
+            "paddles" => theme.paddles = color,
+            "ball" => theme.ball = color,
+            "text" => theme.text = color,
+            _ => return None,
+        }
+    }
+    Some(theme)
+}
+
+/// The theme called `name`, built in or from the ramdisk.
+pub fn named(name: &str) -> Option<&'static Theme> {
+    THEMES.iter().chain(LOADED.get().into_iter().flatten()).find(|theme| theme.name == name)
+}
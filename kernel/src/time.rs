@@ -0,0 +1,120 @@
+use core::arch::x86_64::_rdtsc;
+use core::fmt::Write;
+use core::ops::Add;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+use x86_64::instructions::interrupts;
+use crate::{cpu, pit, serial};
+
+// Calibration window measured against the PIT
+const CALIBRATION_US: u64 = 10_000;
+
+/// TSC ticks per second, measured by [init].
+static TSC_FREQUENCY: AtomicU64 = AtomicU64::new(0);
+static BOOT_TSC: AtomicU64 = AtomicU64::new(0);
+
+/// Calibrates the time stamp counter against the PIT. Must run once at boot, before any other
+/// function of this module is used.
+pub fn init() {
+    let invariant = cpu::features().invariant_tsc;
+
+    let start = rdtsc();
+    pit::wait_us(CALIBRATION_US);
+    let frequency = (rdtsc() - start) * 1_000_000 / CALIBRATION_US;
+
+    TSC_FREQUENCY.store(frequency, Ordering::Relaxed);
+    BOOT_TSC.store(start, Ordering::Relaxed);
+    writeln!(serial(), "TSC calibrated: {} Hz, invariant: {}", frequency, invariant).unwrap();
+}
+
+fn rdtsc() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+fn ticks_to_duration(ticks: u64) -> Duration {
+    let frequency = TSC_FREQUENCY.load(Ordering::Relaxed).max(1);
+    Duration::from_nanos((ticks as u128 * 1_000_000_000 / frequency as u128) as u64)
+}
+
+fn duration_to_ticks(duration: Duration) -> u64 {
+    let frequency = TSC_FREQUENCY.load(Ordering::Relaxed);
+    (duration.as_nanos() * frequency as u128 / 1_000_000_000) as u64
+}
+
+/// A point in time read from the TSC, in the style of `std::time::Instant`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Instant(u64);
+
+impl Instant {
+    pub fn now() -> Self {
+        Instant(rdtsc())
+    }
+
+    /// Time elapsed from `earlier` to this instant, zero if `earlier` is later.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        ticks_to_duration(self.0.saturating_sub(earlier.0))
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, duration: Duration) -> Instant {
+        Instant(self.0 + duration_to_ticks(duration))
+    }
+}
+
+/// Time since [init] ran.
+pub fn uptime() -> Duration {
+    Instant::now().duration_since(Instant(BOOT_TSC.load(Ordering::Relaxed)))
+}
+
+/// Nanoseconds since [init] ran.
+pub fn uptime_ns() -> u64 {
+    uptime().as_nanos() as u64
+}
+
+/// Busy-waits for the given number of microseconds. Works with interrupts disabled, so drivers
+/// can use it during initialization.
+pub fn delay_us(us: u64) {
+    if TSC_FREQUENCY.load(Ordering::Relaxed) == 0 {
+        // Not calibrated yet
+        pit::wait_us(us);
+        return;
+    }
+    let deadline = Instant::now() + Duration::from_micros(us);
+    while Instant::now() < deadline {
+        core::hint::spin_loop();
+    }
+}
+
+/// Sleeps for the given number of milliseconds, halting the CPU between interrupts. With
+/// interrupts disabled nothing would wake the CPU, so this falls back to [delay_us].
+pub fn sleep_ms(ms: u64) {
+    if !interrupts::are_enabled() {
+        delay_us(ms * 1000);
+        return;
+    }
+    let deadline = Instant::now() + Duration::from_millis(ms);
+    while Instant::now() < deadline {
+        x86_64::instructions::hlt();
+    }
+}
+
+/// Polls `condition` until it holds or `timeout` passes. Returns whether it held.
+pub fn poll_until(timeout: Duration, mut condition: impl FnMut() -> bool) -> bool {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if condition() {
+            return true;
+        }
+        if Instant::now() >= deadline {
+            return false;
+        }
+        core::hint::spin_loop();
+    }
+}
@@ -0,0 +1,67 @@
+use core::time::Duration;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use crate::time::Instant;
+
+const MAX_TIMERS: usize = 16;
+
+// A callback that fell this many periods behind skips ahead instead of running them all
+const MAX_CATCH_UP: u32 = 4;
+
+/// Identifies a timer registered with [add], for [remove].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimerId(usize);
+
+#[derive(Clone, Copy)]
+struct Timer {
+    callback: fn(),
+    period: Duration,
+    next: Instant,
+}
+
+static TIMERS: Mutex<[Option<Timer>; MAX_TIMERS]> = Mutex::new([None; MAX_TIMERS]);
+
+/// Registers `callback` to run every `period`, starting one period from now. Callbacks run in
+/// the timer interrupt, with interrupts off, so the timer rate ([crate::HandlerTable::tick_hz]) bounds how finely
+/// they are spaced; a period shorter than the tick interval runs several times per tick.
+///
+/// Returns None when all timer slots are in use.
+pub fn add(period: Duration, callback: fn()) -> Option<TimerId> {
+    // The timer interrupt takes the same lock
+    without_interrupts(|| {
+        let mut timers = TIMERS.lock();
+        let slot = timers.iter().position(Option::is_none)?;
+        timers[slot] = Some(Timer { callback, period, next: Instant::now() + period });
+        Some(TimerId(slot))
+    })
+}
+
+/// Stops a timer registered with [add].
+pub fn remove(id: TimerId) {
+    without_interrupts(|| TIMERS.lock()[id.0] = None);
+}
+
+/// Runs every callback that is due. Called by the timer interrupt handler.
+pub(crate) fn run_due() {
+    let now = Instant::now();
+    for slot in 0..MAX_TIMERS {
+        // Take what is needed and release the lock, so callbacks may add or remove timers
+        let due = {
+            let mut timers = TIMERS.lock();
+            let Some(timer) = timers[slot].as_mut() else { continue };
+            let mut runs = 0;
+            while timer.next <= now && runs < MAX_CATCH_UP {
+                timer.next = timer.next + timer.period;
+                runs += 1;
+            }
+            if timer.next <= now {
+                timer.next = now + timer.period;
+            }
+            (timer.callback, runs)
+        };
+
+        for _ in 0..due.1 {
+            (due.0)();
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use crate::screen::screenwriter;
+use crate::screensaver::hue;
+use crate::tween::mix;
+
+// The menu's title screen, kept moving so it is not the same frame over and over: a demo ball
+// bounces round behind the text, the prompts to start a game pulse, and the title's color drifts
+// slowly round the color wheel. The tick handler moves it on the menu, as it moves the game; none
+// of it is part of the game's state.
+
+/// Pixels the demo ball moves a tick, across and down.
+const BALL_SPEED: f32 = 3.0;
+const BALL_SIZE: usize = 8;
+/// The ball is drawn this far towards the theme's color from black, to stay behind the text.
+const BALL_SHADE: f32 = 0.35;
+/// Ticks a prompt takes to fade down and back up.
+const PULSE_TICKS: u64 = 45;
+/// Dimmest a pulsing prompt gets, from 0 to 1.
+const PULSE_LOW: f32 = 0.45;
+/// Ticks to go once round the color wheel, and how far the title leans towards it.
+const CYCLE_TICKS: u64 = 900;
+const CYCLE_AMOUNT: f32 = 0.3;
+
+pub struct DemoBall {
+    x: f32,
+    y: f32,
+    dx: f32,
+    dy: f32,
+}
+
+impl DemoBall {
+    pub const fn new() -> Self {
+        Self { x: 0.0, y: 0.0, dx: BALL_SPEED, dy: BALL_SPEED * 0.6 }
+    }
+
+    /// Moves the ball a tick, bouncing it off the edges of a `width` by `height` screen.
+    pub fn step(&mut self, width: usize, height: usize) {
+        let (max_x, max_y) = (width.saturating_sub(BALL_SIZE) as f32, height.saturating_sub(BALL_SIZE) as f32);
+        (self.x, self.y) = (self.x + self.dx, self.y + self.dy);
+        if self.x < 0.0 || self.x > max_x {
+            self.dx = -self.dx;
+        }
+        if self.y < 0.0 || self.y > max_y {
+            self.dy = -self.dy;
+        }
+        (self.x, self.y) = (self.x.clamp(0.0, max_x), self.y.clamp(0.0, max_y));
+    }
+
+    pub fn position(&self) -> (f32, f32) {
+        (self.x, self.y)
+    }
+
+    /// Draws the ball in a shade of `color`; before the text, so that it passes behind it.
+    pub fn draw(&self, color: (u8, u8, u8)) {
+        let (r, g, b) = mix((0, 0, 0), color, BALL_SHADE);
+        let mut writer = screenwriter();
+        for dy in 0..BALL_SIZE {
+            for dx in 0..BALL_SIZE {
+                writer.draw_pixel(self.x as usize + dx, self.y as usize + dy, r, g, b);
+            }
+        }
+    }
+}
+
+/// A prompt's `color` as bright as its pulse is at `ticks`.
+pub fn pulse(color: (u8, u8, u8), ticks: u64) -> (u8, u8, u8) {
+    // A triangle wave, from 1 down to 0 and back up
+    let phase = (ticks % PULSE_TICKS) as f32 / PULSE_TICKS as f32;
+    let wave = (2.0 * phase - 1.0).abs();
+    mix((0, 0, 0), color, PULSE_LOW + (1.0 - PULSE_LOW) * wave)
+}
+
+/// The title's `color`, leaning towards where the color wheel is at `ticks`.
+pub fn cycle(color: (u8, u8, u8), ticks: u64) -> (u8, u8, u8) {
+    mix(color, hue(ticks % CYCLE_TICKS * 360 / CYCLE_TICKS), CYCLE_AMOUNT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn title_animates_within_the_screen() {
+        let mut ball = DemoBall::new();
+        for _ in 0..1000 {
+            ball.step(64, 48);
+        }
+        // Still on screen after many bounces
+        let (x, y) = ball.position();
+        assert!(x <= 56.0 && y <= 40.0);
+        assert_ne!(pulse((200, 200, 200), 0), pulse((200, 200, 200), 20));
+        assert_ne!(cycle((200, 200, 200), 0), cycle((200, 200, 200), 300));
+    }
+}
@@ -0,0 +1,94 @@
+// Animations for the screens around the games: a [Tween] goes from one value to another over a
+// number of ticks, along an [Easing] curve, and the screen reads its value when it draws, so
+// things slide, fade and pop instead of snapping into place from one frame to the next. Each is
+// advanced once a tick by its owner; the game's state never depends on one, so replays and
+// network games are not affected.
+
+/// How a tween's value moves from start to end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Easing {
+    Linear,
+    /// Starts slowly and speeds up.
+    EaseIn,
+    /// Starts quickly and slows down into place.
+    EaseOut,
+    EaseInOut,
+}
+
+impl Easing {
+    /// How far along the way the value is, 0 to 1, when `t` of the time has passed.
+    pub fn apply(self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t) * (1.0 - t),
+            Easing::EaseInOut if t < 0.5 => 2.0 * t * t,
+            Easing::EaseInOut => 1.0 - 2.0 * (1.0 - t) * (1.0 - t),
+        }
+    }
+}
+
+/// A value on its way from `from` to `to`.
+#[derive(Debug, Clone, Copy)]
+pub struct Tween {
+    from: f32,
+    to: f32,
+    /// In ticks.
+    duration: u32,
+    elapsed: u32,
+    easing: Easing,
+}
+
+impl Tween {
+    /// One taking `ms` milliseconds, at the tick rate there is now.
+    pub fn new(from: f32, to: f32, ms: u32, easing: Easing) -> Self {
+        let duration = (ms as u64 * crate::tick_hz() as u64 / 1000).max(1) as u32;
+        Self { from, to, duration, elapsed: 0, easing }
+    }
+
+    /// One already at `value`, for before anything starts it.
+    pub const fn done(value: f32) -> Self {
+        Self { from: value, to: value, duration: 1, elapsed: 1, easing: Easing::Linear }
+    }
+
+    /// Moves it on by a tick.
+    pub fn advance(&mut self) {
+        self.elapsed = (self.elapsed + 1).min(self.duration);
+    }
+
+    pub fn value(&self) -> f32 {
+        let t = self.elapsed as f32 / self.duration as f32;
+        self.from + (self.to - self.from) * self.easing.apply(t)
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.elapsed == self.duration
+    }
+}
+
+/// The color `amount` of the way from `from` to `to`.
+pub fn mix(from: (u8, u8, u8), to: (u8, u8, u8), amount: f32) -> (u8, u8, u8) {
+    let channel = |from: u8, to: u8| (from as f32 + (to as f32 - from as f32) * amount.clamp(0.0, 1.0)) as u8;
+    (channel(from.0, to.0), channel(from.1, to.1), channel(from.2, to.2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn tween_eases_to_its_end_and_stays() {
+        // A tick at the default 30 Hz is 33 ms
+        let mut tween = Tween::new(0.0, 10.0, 100, Easing::Linear);
+        tween.advance();
+        assert!(tween.value() > 3.0 && tween.value() < 3.5);
+        for _ in 0..5 {
+            tween.advance();
+        }
+        assert!(tween.is_done());
+        assert_eq!(tween.value(), 10.0);
+        assert_eq!(Easing::EaseOut.apply(0.5), 0.75);
+        assert_eq!(mix((0, 0, 0), (200, 100, 50), 0.5), (100, 50, 25));
+    }
+}
@@ -0,0 +1,231 @@
+use alloc::string::String;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::{Mutex, Once};
+use uart_16550::SerialPort;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+use crate::deferred;
+use crate::fixed::RingBuffer;
+use crate::spsc::SpscQueue;
+use crate::sync::IrqSafeMutex;
+use crate::virtio_console;
+
+// Interrupt-driven driver for the COM1 serial port. Output goes into a ring buffer that the
+// transmit interrupt drains 16 bytes (one FIFO) at a time, so a log line costs a copy rather than
+// a wait on the line; received bytes land in a queue for read_byte and read_line.
+//
+// Until interrupts are set up, and whenever they are disabled (interrupt handlers, panics), writes
+// are polled: the ring is flushed first, so the output stays in order.
+//
+// When there is a virtio-console, output goes out on it as well, and its input joins COM1's.
+
+const COM1: u16 = 0x3F8;
+const DATA: u16 = COM1;
+const INTERRUPT_ENABLE: u16 = COM1 + 1;
+const LINE_STATUS: u16 = COM1 + 5;
+
+const IER_RECEIVED: u8 = 1 << 0;
+const IER_TRANSMIT_EMPTY: u8 = 1 << 1;
+const LSR_DATA_READY: u8 = 1 << 0;
+const LSR_TRANSMIT_EMPTY: u8 = 1 << 5;
+const FIFO_SIZE: usize = 16;
+
+const TX_SIZE: usize = 4096;
+/// Text output kept for crash dumps.
+const RECENT_SIZE: usize = 4096;
+
+static PORT: Once = Once::new();
+/// Set once the serial interrupt is routed, so writes can be left to it.
+static INTERRUPTS_READY: AtomicBool = AtomicBool::new(false);
+static TX: IrqSafeMutex<RingBuffer<u8, TX_SIZE>> = IrqSafeMutex::new(RingBuffer::new());
+/// Room for a few XMODEM blocks, which a virtio-console delivers 512 bytes at a time.
+static RX: SpscQueue<u8, 4096> = SpscQueue::new();
+/// Set while a binary protocol owns the console, see [set_raw].
+static RAW: AtomicBool = AtomicBool::new(false);
+/// Called with each line received, see [set_line_handler].
+static LINE_HANDLER: Mutex<Option<fn(&str)>> = Mutex::new(None);
+/// The line [read_line] is collecting, and whether the last byte was a carriage return.
+static LINE: Mutex<(String, bool)> = Mutex::new((String::new(), false));
+/// The last [RECENT_SIZE] bytes of text written, see [recent_output].
+static RECENT: Mutex<RingBuffer<u8, RECENT_SIZE>> = Mutex::new(RingBuffer::new());
+
+fn read(port: u16) -> u8 {
+    unsafe { Port::<u8>::new(port).read() }
+}
+
+fn write(port: u16, value: u8) {
+    unsafe { Port::<u8>::new(port).write(value) }
+}
+
+/// Programs the UART (line settings, FIFOs, receive interrupt) the first time it is used.
+fn init_port() {
+    PORT.call_once(|| unsafe { SerialPort::new(COM1).init() });
+}
+
+fn send_polled(byte: u8) {
+    while read(LINE_STATUS) & LSR_TRANSMIT_EMPTY == 0 {
+        core::hint::spin_loop();
+    }
+    write(DATA, byte);
+}
+
+fn drain_polled(tx: &mut RingBuffer<u8, TX_SIZE>) {
+    while let Some(byte) = tx.pop() {
+        send_polled(byte);
+    }
+}
+
+/// Sends everything still queued for the line, waiting on it. Call before anything that ends the
+/// machine, such as [crate::qemu::exit], or the tail of the output is lost.
+pub fn flush() {
+    drain_polled(&mut TX.lock());
+}
+
+/// Writer for the serial port, from [crate::serial].
+pub struct Serial;
+
+impl fmt::Write for Serial {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if RAW.load(Ordering::Acquire) {
+            return Ok(());
+        }
+        // try_lock: skipped while a crash dump reads it, or if this interrupted another write
+        if let Some(mut recent) = RECENT.try_lock() {
+            s.bytes().for_each(|byte| recent.push_overwrite(byte));
+        }
+        write_bytes(s.as_bytes());
+        Ok(())
+    }
+}
+
+fn write_bytes(bytes: &[u8]) {
+    init_port();
+    if !INTERRUPTS_READY.load(Ordering::Acquire) || !interrupts::are_enabled() {
+        // try_lock: this may have interrupted a write, or be a panic on the way out
+        if let Some(mut tx) = TX.try_lock() {
+            drain_polled(&mut tx);
+        }
+        bytes.iter().copied().for_each(send_polled);
+        virtio_console::write(bytes);
+        return;
+    }
+
+    let mut tx = TX.lock();
+    for &byte in bytes {
+        if !tx.push(byte) {
+            // Full: faster to wait on the line here than to come back for every byte
+            drain_polled(&mut tx);
+            tx.push(byte);
+        }
+    }
+    // Raises an interrupt right away if the transmitter is idle
+    write(INTERRUPT_ENABLE, IER_RECEIVED | IER_TRANSMIT_EMPTY);
+    drop(tx);
+    virtio_console::write(bytes);
+}
+
+/// Passes the most recent text output, oldest first, to `f` in one or two pieces. Output written
+/// meanwhile (by `f` itself, say) is not recorded.
+pub fn recent_output(mut f: impl FnMut(&[u8])) {
+    if let Some(recent) = RECENT.try_lock() {
+        let (first, second) = recent.as_slices();
+        f(first);
+        f(second);
+    }
+}
+
+/// Switches output to the transmit interrupt. Called once the serial interrupt is routed to
+/// [handle_interrupt].
+pub(crate) fn enable_interrupts() {
+    init_port();
+    INTERRUPTS_READY.store(true, Ordering::Release);
+}
+
+/// Serial interrupt: hands each byte received to `on_byte`, which queues it with [received], and
+/// refills the transmit FIFO.
+pub(crate) fn handle_interrupt(mut on_byte: impl FnMut(u8)) {
+    while read(LINE_STATUS) & LSR_DATA_READY != 0 {
+        on_byte(read(DATA));
+    }
+
+    let mut tx = TX.lock();
+    if read(LINE_STATUS) & LSR_TRANSMIT_EMPTY != 0 {
+        for _ in 0..FIFO_SIZE {
+            let Some(byte) = tx.pop() else { break };
+            write(DATA, byte);
+        }
+    }
+    if tx.is_empty() {
+        write(INTERRUPT_ENABLE, IER_RECEIVED);
+    }
+}
+
+/// Queues a byte received on the serial console, from COM1 or [crate::virtio_console]. Only
+/// called from their interrupt handlers, on the boot processor: [RX] takes one producer. They go
+/// through [crate::interrupts::serial_received], which keeps controller bridge packets out.
+pub(crate) fn received(byte: u8) {
+    // Dropped if nobody reads the queue
+    RX.push(byte);
+    if !RAW.load(Ordering::Relaxed) && (byte == b'\r' || byte == b'\n') && LINE_HANDLER.lock().is_some() {
+        deferred::defer(run_line_handler);
+    }
+}
+
+/// Hands the serial console to a binary protocol, such as [crate::xmodem], or back. While raw,
+/// what is written with [crate::serial] is dropped, received bytes are neither lines for the
+/// line handler nor keys, and [write_raw] is the only way out.
+pub fn set_raw(raw: bool) {
+    RAW.store(raw, Ordering::Release);
+}
+
+pub(crate) fn is_raw() -> bool {
+    RAW.load(Ordering::Relaxed)
+}
+
+/// Sends `bytes` as they are, on COM1 and a virtio-console if there is one.
+pub fn write_raw(bytes: &[u8]) {
+    write_bytes(bytes);
+}
+
+/// Has `handler` called with every line received, as deferred work (see [crate::deferred]), for
+/// a command shell. It takes over [read_line] and [read_byte].
+pub fn set_line_handler(handler: fn(&str)) {
+    interrupts::without_interrupts(|| *LINE_HANDLER.lock() = Some(handler));
+}
+
+fn run_line_handler() {
+    let handler = interrupts::without_interrupts(|| *LINE_HANDLER.lock());
+    if let Some(handler) = handler {
+        while let Some(line) = read_line() {
+            handler(&line);
+        }
+    }
+}
+
+/// The next byte received, if any. Never blocks.
+pub fn read_byte() -> Option<u8> {
+    RX.pop()
+}
+
+/// The next complete line received, without its line ending, once Enter has been pressed.
+/// Collects what has arrived so far and returns None until then; backspace removes the last
+/// character. Never blocks.
+pub fn read_line() -> Option<String> {
+    let mut guard = LINE.lock();
+    let (line, after_cr) = &mut *guard;
+    while let Some(byte) = read_byte() {
+        let follows_cr = core::mem::replace(after_cr, byte == b'\r');
+        match byte {
+            // A CR LF line ending is one line ending
+            b'\n' if follows_cr => {}
+            b'\r' | b'\n' => return Some(core::mem::take(line)),
+            0x08 | 0x7F => {
+                line.pop();
+            }
+            byte if byte.is_ascii() && !byte.is_ascii_control() => line.push(byte as char),
+            _ => {}
+        }
+    }
+    None
+}
@@ -0,0 +1,152 @@
+use core::fmt;
+use crate::screen::Renderer;
+
+// The fallback screen when the bootloader found no framebuffer: the VGA text buffer, 80x25
+// character cells. It pretends to be a 640x400 pixel screen so the game draws on it unchanged;
+// each 8x16 block of pixels is one cell, a pixel fills its cell with a solid block, and a character
+// takes the cell its top-left corner falls in.
+
+const COLUMNS: usize = 80;
+const ROWS: usize = 25;
+const CELL_WIDTH: usize = 8;
+const CELL_HEIGHT: usize = 16;
+const BUFFER: u64 = 0xB8000;
+
+/// Code page 437 full block.
+const BLOCK: u8 = 0xDB;
+/// Light grey on black.
+const DEFAULT_ATTRIBUTE: u8 = 0x07;
+
+/// The 16 text mode colors. Backgrounds can only be the first 8: bit 7 of the attribute blinks.
+pub const PALETTE: [(u8, u8, u8); 16] = [
+    (0x00, 0x00, 0x00), (0x00, 0x00, 0xAA), (0x00, 0xAA, 0x00), (0x00, 0xAA, 0xAA),
+    (0xAA, 0x00, 0x00), (0xAA, 0x00, 0xAA), (0xAA, 0x55, 0x00), (0xAA, 0xAA, 0xAA),
+    (0x55, 0x55, 0x55), (0x55, 0x55, 0xFF), (0x55, 0xFF, 0x55), (0x55, 0xFF, 0xFF),
+    (0xFF, 0x55, 0x55), (0xFF, 0x55, 0xFF), (0xFF, 0xFF, 0x55), (0xFF, 0xFF, 0xFF),
+];
+
+/// The palette index closest to (r, g, b), from the first `count`.
+pub fn nearest(r: u8, g: u8, b: u8, count: usize) -> u8 {
+    let distance = |&(pr, pg, pb): &(u8, u8, u8)| {
+        let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+        d(r, pr) + d(g, pg) + d(b, pb)
+    };
+    (0..count).min_by_key(|&i| distance(&PALETTE[i])).unwrap_or(0) as u8
+}
+
+pub struct TextScreen {
+    /// Character and attribute of each cell, through the physical memory map.
+    cells: *mut u16,
+    /// Background attribute bits of a cleared cell.
+    background: u8,
+    column: usize,
+    row: usize,
+}
+
+unsafe impl Send for TextScreen {}
+
+impl TextScreen {
+    /// The text buffer, reached through the physical memory map at `physical_offset`.
+    pub fn new(physical_offset: u64) -> Self {
+        let mut screen = TextScreen { cells: (physical_offset + BUFFER) as *mut u16, background: 0, column: 0, row: 0 };
+        screen.clear();
+        screen
+    }
+
+    fn read(&self, column: usize, row: usize) -> (u8, u8) {
+        let [character, attribute] = unsafe { self.cells.add(row * COLUMNS + column).read_volatile() }.to_le_bytes();
+        (character, attribute)
+    }
+
+    fn write(&mut self, column: usize, row: usize, character: u8, attribute: u8) {
+        if column < COLUMNS && row < ROWS {
+            unsafe { self.cells.add(row * COLUMNS + column).write_volatile(u16::from_le_bytes([character, attribute])) };
+        }
+    }
+
+    /// Sets the cell at pixel (x, y) to `character` in (r, g, b), on the cell's background.
+    fn put(&mut self, x: usize, y: usize, character: u8, r: u8, g: u8, b: u8) {
+        let (column, row) = (x / CELL_WIDTH, y / CELL_HEIGHT);
+        if column >= COLUMNS || row >= ROWS {
+            return;
+        }
+        let (_, attribute) = self.read(column, row);
+        self.write(column, row, character, attribute & 0xF0 | nearest(r, g, b, PALETTE.len()));
+    }
+
+    fn fill(&mut self, attribute: u8) {
+        for row in 0..ROWS {
+            for column in 0..COLUMNS {
+                self.write(column, row, b' ', attribute);
+            }
+        }
+    }
+}
+
+impl Renderer for TextScreen {
+    fn width(&self) -> usize {
+        COLUMNS * CELL_WIDTH
+    }
+
+    fn height(&self) -> usize {
+        ROWS * CELL_HEIGHT
+    }
+
+    fn clear(&mut self) {
+        self.column = 0;
+        self.row = 0;
+        self.background = 0;
+        self.fill(DEFAULT_ATTRIBUTE);
+    }
+
+    fn clear_screen(&mut self, r: u8, g: u8, b: u8) {
+        self.background = nearest(r, g, b, 8) << 4;
+        self.fill(self.background | 0x0F);
+    }
+
+    fn draw_pixel(&mut self, x: usize, y: usize, r: u8, g: u8, b: u8) {
+        self.put(x, y, BLOCK, r, g, b);
+    }
+
+    fn read_pixel(&self, x: usize, y: usize) -> (u8, u8, u8) {
+        let (column, row) = (x / CELL_WIDTH, y / CELL_HEIGHT);
+        if column >= COLUMNS || row >= ROWS {
+            return (0, 0, 0);
+        }
+        let (character, attribute) = self.read(column, row);
+        let color = if character == b' ' { attribute >> 4 & 0x07 } else { attribute & 0x0F };
+        PALETTE[color as usize]
+    }
+
+    fn draw_char(&mut self, x: usize, y: usize, c: char, r: u8, g: u8, b: u8) {
+        let character = if c.is_ascii() && !c.is_ascii_control() { c as u8 } else { b'?' };
+        self.put(x, y, character, r, g, b);
+    }
+}
+
+impl fmt::Write for TextScreen {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            match c {
+                '\n' => {
+                    self.column = 0;
+                    self.row += 1;
+                }
+                '\r' => self.column = 0,
+                c => {
+                    if self.column >= COLUMNS {
+                        self.column = 0;
+                        self.row += 1;
+                    }
+                    if self.row >= ROWS {
+                        self.clear();
+                    }
+                    let character = if c.is_ascii() && !c.is_ascii_control() { c as u8 } else { b'?' };
+                    self.write(self.column, self.row, character, self.background | 0x0A);
+                    self.column += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,394 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::sync::atomic::{fence, Ordering};
+use x86_64::PhysAddr;
+use crate::dma::{self, DmaBuffer};
+use crate::msi::MsiX;
+use crate::pci::PciAddress;
+use crate::{interrupts, memory, serial};
+
+// Virtio devices on PCI, "modern" (virtio 1.x) interface only, and their split virtqueues. The
+// device drivers (virtio_net.rs, ...) sit on top of this. See the "Virtual I/O Device (VIRTIO)
+// Version 1.1" specification, sections 2 (virtqueues) and 4.1 (PCI transport).
+
+pub const VENDOR_ID: u16 = 0x1AF4;
+
+/// The device complies with virtio 1.x; without it the device only speaks the legacy interface.
+pub const F_VERSION_1: u64 = 1 << 32;
+
+const CAP_VENDOR: u8 = 0x09;
+const CFG_COMMON: u8 = 1;
+const CFG_NOTIFY: u8 = 2;
+const CFG_ISR: u8 = 3;
+const CFG_DEVICE: u8 = 4;
+
+// Common configuration structure
+const DEVICE_FEATURE_SELECT: u64 = 0x00;
+const DEVICE_FEATURE: u64 = 0x04;
+const DRIVER_FEATURE_SELECT: u64 = 0x08;
+const DRIVER_FEATURE: u64 = 0x0C;
+const MSIX_CONFIG: u64 = 0x10;
+const DEVICE_STATUS: u64 = 0x14;
+const QUEUE_SELECT: u64 = 0x16;
+const QUEUE_SIZE: u64 = 0x18;
+const QUEUE_MSIX_VECTOR: u64 = 0x1A;
+const QUEUE_ENABLE: u64 = 0x1C;
+const QUEUE_NOTIFY_OFF: u64 = 0x1E;
+const QUEUE_DESC: u64 = 0x20;
+const QUEUE_DRIVER: u64 = 0x28;
+const QUEUE_DEVICE: u64 = 0x30;
+
+const STATUS_ACKNOWLEDGE: u8 = 1;
+const STATUS_DRIVER: u8 = 2;
+const STATUS_DRIVER_OK: u8 = 4;
+const STATUS_FEATURES_OK: u8 = 8;
+const STATUS_FAILED: u8 = 128;
+
+/// No MSI-X entry, for MSIX_CONFIG and QUEUE_MSIX_VECTOR.
+const NO_VECTOR: u16 = 0xFFFF;
+
+const DESC_NEXT: u16 = 1;
+const DESC_WRITE: u16 = 2;
+
+fn read8(address: u64) -> u8 {
+    unsafe { (address as *const u8).read_volatile() }
+}
+
+fn write8(address: u64, value: u8) {
+    unsafe { (address as *mut u8).write_volatile(value) }
+}
+
+fn read16(address: u64) -> u16 {
+    unsafe { (address as *const u16).read_volatile() }
+}
+
+fn write16(address: u64, value: u16) {
+    unsafe { (address as *mut u16).write_volatile(value) }
+}
+
+fn read32(address: u64) -> u32 {
+    unsafe { (address as *const u32).read_volatile() }
+}
+
+fn write32(address: u64, value: u32) {
+    unsafe { (address as *mut u32).write_volatile(value) }
+}
+
+fn write64(address: u64, value: u64) {
+    // Two halves: the spec does not require devices to accept 64-bit accesses
+    write32(address, value as u32);
+    write32(address + 4, (value >> 32) as u32);
+}
+
+/// A virtio device's PCI function, with its configuration structures mapped.
+pub struct VirtioDevice {
+    pci: PciAddress,
+    common: u64,
+    notify: u64,
+    notify_multiplier: u32,
+    isr: u64,
+    device: u64,
+    msix: Option<MsiX>,
+}
+
+unsafe impl Send for VirtioDevice {}
+
+impl VirtioDevice {
+    /// Finds the configuration structures through the function's vendor capabilities and maps
+    /// them. Returns None for a legacy-only device.
+    pub fn new(pci: PciAddress) -> Option<Self> {
+        let (mut common, mut notify, mut isr, mut device) = (None, None, None, None);
+        let mut notify_multiplier = 0;
+        for (_, cap) in pci.capabilities().filter(|&(id, _)| id == CAP_VENDOR) {
+            let header = pci.read(cap);
+            let bar = pci.read(cap + 4) as u8;
+            if bar > 5 {
+                continue;
+            }
+            let (offset, length) = (pci.read(cap + 8) as u64, pci.read(cap + 12) as u64);
+            let address = pci.memory_bar(bar) + offset;
+            // The first structure of each type is the preferred one
+            let slot = match (header >> 24) as u8 {
+                CFG_COMMON => &mut common,
+                CFG_NOTIFY => {
+                    notify_multiplier = pci.read(cap + 16);
+                    &mut notify
+                }
+                CFG_ISR => &mut isr,
+                CFG_DEVICE => &mut device,
+                _ => continue,
+            };
+            if slot.is_none() {
+                memory::map_region(PhysAddr::new(address), length, memory::MMIO);
+                *slot = Some(address);
+            }
+        }
+        pci.enable_bus_master();
+        Some(VirtioDevice { pci, common: common?, notify: notify?, notify_multiplier, isr: isr?, device: device.unwrap_or(0), msix: None })
+    }
+
+    pub fn pci(&self) -> PciAddress {
+        self.pci
+    }
+
+    fn status(&self) -> u8 {
+        read8(self.common + DEVICE_STATUS)
+    }
+
+    fn add_status(&mut self, bits: u8) {
+        write8(self.common + DEVICE_STATUS, self.status() | bits);
+    }
+
+    /// Resets the device and agrees on features: those in `wanted` the device offers, plus
+    /// [F_VERSION_1], which is required. Returns the features agreed on, or None (and marks the
+    /// device failed) if the device refuses them.
+    pub fn init(&mut self, wanted: u64) -> Option<u64> {
+        write8(self.common + DEVICE_STATUS, 0);
+        while self.status() != 0 {
+            core::hint::spin_loop();
+        }
+        self.add_status(STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+
+        let mut offered = 0;
+        for half in 0..2 {
+            write32(self.common + DEVICE_FEATURE_SELECT, half);
+            offered |= (read32(self.common + DEVICE_FEATURE) as u64) << (32 * half);
+        }
+        let features = offered & (wanted | F_VERSION_1);
+        if features & F_VERSION_1 == 0 {
+            self.add_status(STATUS_FAILED);
+            return None;
+        }
+        for half in 0..2 {
+            write32(self.common + DRIVER_FEATURE_SELECT, half);
+            write32(self.common + DRIVER_FEATURE, (features >> (32 * half)) as u32);
+        }
+        self.add_status(STATUS_FEATURES_OK);
+        if self.status() & STATUS_FEATURES_OK == 0 {
+            self.add_status(STATUS_FAILED);
+            return None;
+        }
+        Some(features)
+    }
+
+    /// Routes the device's queue interrupts to `handler` on the CPU with local APIC ID `cpu`,
+    /// through MSI-X entry 0, and returns the vector. Call after [VirtioDevice::init] and before
+    /// setting up queues, which are then given `Some(0)` as their entry. Returns None if the
+    /// device has no MSI-X or no vector is free; the driver has to poll then.
+    pub fn enable_msix(&mut self, cpu: u8, handler: fn()) -> Option<u8> {
+        let mut msix = MsiX::new(self.pci)?;
+        let vector = interrupts::allocate_irq(handler)?;
+        msix.set_entry(0, vector, cpu);
+        msix.enable();
+        write16(self.common + MSIX_CONFIG, NO_VECTOR);
+        self.msix = Some(msix);
+        Some(vector)
+    }
+
+    /// Creates queue `index` with up to `max_size` entries, interrupting through MSI-X entry
+    /// `msix_entry` if given. Returns None if the device has no such queue, or out of memory.
+    pub fn setup_queue(&mut self, index: u16, max_size: u16, msix_entry: Option<u16>) -> Option<Virtqueue> {
+        write16(self.common + QUEUE_SELECT, index);
+        let device_max = read16(self.common + QUEUE_SIZE);
+        if device_max == 0 {
+            return None;
+        }
+        // Kept a power of two, which split queues need on some devices
+        let size = 1 << (device_max.min(max_size).max(1)).ilog2();
+        let queue = Virtqueue::new(index, size, self.notify + read16(self.common + QUEUE_NOTIFY_OFF) as u64 * self.notify_multiplier as u64)?;
+
+        write16(self.common + QUEUE_SIZE, size);
+        write64(self.common + QUEUE_DESC, queue.descriptors.phys_addr().as_u64());
+        write64(self.common + QUEUE_DRIVER, queue.available.phys_addr().as_u64());
+        write64(self.common + QUEUE_DEVICE, queue.used.phys_addr().as_u64());
+        if let Some(entry) = msix_entry {
+            write16(self.common + QUEUE_MSIX_VECTOR, entry);
+            if read16(self.common + QUEUE_MSIX_VECTOR) != entry {
+                writeln!(serial(), "virtio: {:?} refused MSI-X entry {} for queue {}", self.pci, entry, index).unwrap();
+            }
+        }
+        write16(self.common + QUEUE_ENABLE, 1);
+        Some(queue)
+    }
+
+    /// Tells the device the driver is ready; it starts using its queues.
+    pub fn driver_ok(&mut self) {
+        self.add_status(STATUS_DRIVER_OK);
+    }
+
+    /// Reads and clears the interrupt status (bit 0: a queue was used, bit 1: the configuration
+    /// changed). Only meaningful without MSI-X.
+    pub fn isr_status(&self) -> u8 {
+        read8(self.isr)
+    }
+
+    pub fn config_read8(&self, offset: u64) -> u8 {
+        read8(self.device + offset)
+    }
+
+    pub fn config_read16(&self, offset: u64) -> u16 {
+        read16(self.device + offset)
+    }
+
+    pub fn config_read32(&self, offset: u64) -> u32 {
+        read32(self.device + offset)
+    }
+
+    /// Two 32-bit reads, low half first, as the device configuration may only take those.
+    pub fn config_read64(&self, offset: u64) -> u64 {
+        self.config_read32(offset) as u64 | (self.config_read32(offset + 4) as u64) << 32
+    }
+}
+
+/// A split virtqueue: a table of buffer descriptors, the ring where the driver offers chains of
+/// them (available) and the ring where the device hands them back (used).
+pub struct Virtqueue {
+    index: u16,
+    size: u16,
+    descriptors: DmaBuffer,
+    available: DmaBuffer,
+    used: DmaBuffer,
+    notify: u64,
+    /// Head of the chain of free descriptors, linked through their next fields.
+    free_head: u16,
+    free_count: u16,
+    /// The available ring's index, as last written.
+    available_index: u16,
+    /// Used ring entries consumed so far.
+    last_used: u16,
+    /// The caller's token for each chain in flight, by head descriptor.
+    tokens: Vec<usize>,
+}
+
+unsafe impl Send for Virtqueue {}
+
+/// A buffer to add to a [Virtqueue]: its address, length, and whether the device writes it
+/// (rather than reads it).
+#[derive(Debug, Clone, Copy)]
+pub struct Buffer {
+    pub address: PhysAddr,
+    pub len: u32,
+    pub device_writes: bool,
+}
+
+impl Virtqueue {
+    fn new(index: u16, size: u16, notify: u64) -> Option<Self> {
+        let n = size as usize;
+        let mut queue = Virtqueue {
+            index,
+            size,
+            descriptors: dma::alloc_contiguous(16 * n)?,
+            available: dma::alloc_contiguous(6 + 2 * n)?,
+            used: dma::alloc_contiguous(6 + 8 * n)?,
+            notify,
+            free_head: 0,
+            free_count: size,
+            available_index: 0,
+            last_used: 0,
+            tokens: vec![0; n],
+        };
+        for i in 0..size {
+            queue.write_descriptor(i, PhysAddr::zero(), 0, 0, (i + 1) % size);
+        }
+        Some(queue)
+    }
+
+    fn descriptor(&self, i: u16) -> *mut u8 {
+        unsafe { (self.descriptors.as_ptr() as *mut u8).add(16 * i as usize) }
+    }
+
+    fn write_descriptor(&mut self, i: u16, address: PhysAddr, len: u32, flags: u16, next: u16) {
+        let descriptor = self.descriptor(i);
+        unsafe {
+            (descriptor as *mut u64).write_volatile(address.as_u64());
+            (descriptor.add(8) as *mut u32).write_volatile(len);
+            (descriptor.add(12) as *mut u16).write_volatile(flags);
+            (descriptor.add(14) as *mut u16).write_volatile(next);
+        }
+    }
+
+    fn descriptor_flags_next(&self, i: u16) -> (u16, u16) {
+        let descriptor = self.descriptor(i);
+        unsafe { ((descriptor.add(12) as *const u16).read_volatile(), (descriptor.add(14) as *const u16).read_volatile()) }
+    }
+
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    /// Descriptors not in use; a chain takes one per buffer.
+    pub fn free_descriptors(&self) -> u16 {
+        self.free_count
+    }
+
+    /// Offers a chain of buffers to the device, which must come in order: those it reads, then
+    /// those it writes. `token` comes back from [Virtqueue::pop_used] with the chain. Returns
+    /// false if there are not enough free descriptors. The device only looks once notified.
+    pub fn add(&mut self, buffers: &[Buffer], token: usize) -> bool {
+        if buffers.is_empty() || buffers.len() > self.free_count as usize {
+            return false;
+        }
+        let head = self.free_head;
+        let mut current = head;
+        for (i, buffer) in buffers.iter().enumerate() {
+            let (_, next) = self.descriptor_flags_next(current);
+            let mut flags = if buffer.device_writes { DESC_WRITE } else { 0 };
+            if i + 1 < buffers.len() {
+                flags |= DESC_NEXT;
+            }
+            self.write_descriptor(current, buffer.address, buffer.len, flags, next);
+            current = next;
+        }
+        self.free_head = current;
+        self.free_count -= buffers.len() as u16;
+        self.tokens[head as usize] = token;
+
+        let ring = self.available.as_ptr() as *mut u16;
+        unsafe {
+            ring.add(2 + (self.available_index % self.size) as usize).write_volatile(head);
+            // The entry must be visible before the index that publishes it
+            fence(Ordering::SeqCst);
+            self.available_index = self.available_index.wrapping_add(1);
+            ring.add(1).write_volatile(self.available_index);
+        }
+        true
+    }
+
+    /// Tells the device there are new buffers.
+    pub fn notify(&self) {
+        fence(Ordering::SeqCst);
+        write16(self.notify, self.index);
+    }
+
+    /// The next chain the device is done with: its token and the number of bytes the device
+    /// wrote. Its descriptors are free again.
+    pub fn pop_used(&mut self) -> Option<(usize, u32)> {
+        let ring = self.used.as_ptr() as *const u16;
+        let used_index = unsafe { ring.add(1).read_volatile() };
+        if used_index == self.last_used {
+            return None;
+        }
+        fence(Ordering::Acquire);
+        let element = unsafe { (self.used.as_ptr() as *const u32).add(1 + 2 * (self.last_used % self.size) as usize) };
+        let (head, len) = unsafe { (element.read_volatile() as u16, element.add(1).read_volatile()) };
+        self.last_used = self.last_used.wrapping_add(1);
+
+        // Put the chain back at the front of the free list
+        let mut last = head;
+        let mut count = 1;
+        loop {
+            let (flags, next) = self.descriptor_flags_next(last);
+            if flags & DESC_NEXT == 0 {
+                break;
+            }
+            last = next;
+            count += 1;
+        }
+        let free_head = self.free_head;
+        self.write_descriptor(last, PhysAddr::zero(), 0, 0, free_head);
+        self.free_head = head;
+        self.free_count += count;
+        Some((self.tokens[head as usize], len))
+    }
+}
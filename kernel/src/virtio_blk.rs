@@ -0,0 +1,239 @@
+use alloc::vec::Vec;
+use core::fmt::Write;
+use crate::block::{self, BlockError, SECTOR_SIZE};
+use crate::dma::{self, DmaBuffer};
+use crate::sync::IrqSafeMutex;
+use crate::virtio::{self, Buffer, VirtioDevice, Virtqueue};
+use crate::{acpi_tables, pci, serial};
+
+// virtio-blk disk (QEMU: `-device virtio-blk-pci`), a backend of block.rs. Reads and writes whole
+// 512-byte sectors.
+//
+// Requests go through a fixed set of slots, each with its own DMA buffer holding the request
+// header, the status byte the device fills in and up to [MAX_SECTORS] of data; the caller's data
+// is copied in and out of it. A request is a chain of three buffers (header, data, status) on the
+// one request queue. The queue interrupt marks finished slots done, and the caller waits for its
+// slot by halting, or by polling without MSI-X.
+
+const DEVICE_IDS: [u16; 2] = [0x1042, 0x1001];
+
+const F_RO: u64 = 1 << 5;
+const F_FLUSH: u64 = 1 << 9;
+
+const T_IN: u32 = 0;
+const T_OUT: u32 = 1;
+const T_FLUSH: u32 = 4;
+
+const S_OK: u8 = 0;
+const S_UNSUPPORTED: u8 = 2;
+
+const REQUEST_QUEUE: u16 = 0;
+/// Requests in flight at once.
+const SLOTS: usize = 8;
+/// Sectors in one request; longer transfers are split.
+const MAX_SECTORS: usize = 128;
+const HEADER_LEN: u32 = 16;
+const STATUS_OFFSET: usize = 16;
+const DATA_OFFSET: usize = SECTOR_SIZE;
+
+#[derive(Clone, Copy)]
+enum SlotState {
+    Free,
+    InFlight,
+    /// Finished, with the device's status.
+    Done(u8),
+    /// Given up on by [block::wait]; free once the device hands it back.
+    Abandoned,
+}
+
+struct Slot {
+    buffer: DmaBuffer,
+    state: SlotState,
+}
+
+struct VirtioBlk {
+    device: VirtioDevice,
+    queue: Virtqueue,
+    slots: Vec<Slot>,
+    /// In sectors.
+    capacity: u64,
+    read_only: bool,
+    can_flush: bool,
+    interrupts: bool,
+}
+
+static BLK: IrqSafeMutex<Option<VirtioBlk>> = IrqSafeMutex::new(None);
+
+/// Finds a virtio-blk disk and brings it up. Returns false if there is none, or it cannot be
+/// set up.
+pub fn init() -> bool {
+    let Some(found) = pci::devices().iter().find(|device| device.vendor_id == virtio::VENDOR_ID && DEVICE_IDS.contains(&device.device_id) && device.class == 0x01) else {
+        return false;
+    };
+    let Some(mut device) = VirtioDevice::new(found.address) else {
+        writeln!(serial(), "blk: {} has no virtio 1.x interface", found).unwrap();
+        return false;
+    };
+    let Some(features) = device.init(F_RO | F_FLUSH) else {
+        writeln!(serial(), "blk: device refused the features").unwrap();
+        return false;
+    };
+
+    let cpu = acpi_tables::madt().and_then(|madt| madt.boot_processor()).map_or(0, |processor| processor.local_apic_id as u8);
+    let vector = device.enable_msix(cpu, interrupt);
+    // Three descriptors a request
+    let Some(queue) = device.setup_queue(REQUEST_QUEUE, (SLOTS * 3).next_power_of_two() as u16, vector.map(|_| 0)) else {
+        writeln!(serial(), "blk: queue setup failed").unwrap();
+        return false;
+    };
+    let Some(slots) = (0..SLOTS).map(|_| dma::alloc_contiguous(DATA_OFFSET + MAX_SECTORS * SECTOR_SIZE).map(|buffer| Slot { buffer, state: SlotState::Free })).collect() else {
+        writeln!(serial(), "blk: no memory for buffers").unwrap();
+        return false;
+    };
+
+    let capacity = device.config_read64(0);
+    let mut blk = VirtioBlk { device, queue, slots, capacity, read_only: features & F_RO != 0, can_flush: features & F_FLUSH != 0, interrupts: vector.is_some() };
+    writeln!(serial(), "blk: virtio-blk at {:?}, {} sectors ({} KiB){}, {}", blk.device.pci(), capacity, capacity * SECTOR_SIZE as u64 / 1024,
+        if blk.read_only { ", read-only" } else { "" }, if blk.interrupts { "MSI-X" } else { "polled" }).unwrap();
+    blk.device.driver_ok();
+    *BLK.lock() = Some(blk);
+    true
+}
+
+impl VirtioBlk {
+    /// Marks the slots the device has finished with done.
+    fn collect(&mut self) {
+        while let Some((slot, _)) = self.queue.pop_used() {
+            let slot = &mut self.slots[slot];
+            slot.state = match slot.state {
+                SlotState::Abandoned => SlotState::Free,
+                _ => SlotState::Done(unsafe { slot.buffer.as_ptr().add(STATUS_OFFSET).read_volatile() }),
+            };
+        }
+    }
+
+    /// Queues a request for `len` bytes at `sector`, copying in `data` for a write. Returns the
+    /// slot to wait on.
+    fn submit(&mut self, kind: u32, sector: u64, len: usize, data: Option<&[u8]>) -> Result<usize, BlockError> {
+        let index = self.slots.iter().position(|slot| matches!(slot.state, SlotState::Free)).ok_or(BlockError::Busy)?;
+        let slot = &mut self.slots[index];
+        let bytes = slot.buffer.as_mut_slice();
+        bytes[0..4].copy_from_slice(&kind.to_le_bytes());
+        bytes[4..8].fill(0);
+        bytes[8..16].copy_from_slice(&sector.to_le_bytes());
+        bytes[STATUS_OFFSET] = 0xFF;
+        if let Some(data) = data {
+            bytes[DATA_OFFSET..DATA_OFFSET + data.len()].copy_from_slice(data);
+        }
+
+        let base = slot.buffer.phys_addr();
+        let header = Buffer { address: base, len: HEADER_LEN, device_writes: false };
+        let status = Buffer { address: base + STATUS_OFFSET as u64, len: 1, device_writes: true };
+        let added = if len == 0 {
+            self.queue.add(&[header, status], index)
+        } else {
+            let data = Buffer { address: base + DATA_OFFSET as u64, len: len as u32, device_writes: kind == T_IN };
+            self.queue.add(&[header, data, status], index)
+        };
+        if !added {
+            return Err(BlockError::Busy);
+        }
+        slot.state = SlotState::InFlight;
+        self.queue.notify();
+        Ok(index)
+    }
+
+    /// The result of the request in `slot` if it is done, copying out the data into `out` for a
+    /// read. The slot is free again.
+    fn complete(&mut self, index: usize, out: Option<&mut [u8]>) -> Option<Result<(), BlockError>> {
+        let slot = &mut self.slots[index];
+        let SlotState::Done(status) = slot.state else { return None };
+        slot.state = SlotState::Free;
+        Some(match status {
+            S_OK => {
+                if let Some(out) = out {
+                    out.copy_from_slice(&slot.buffer.as_slice()[DATA_OFFSET..DATA_OFFSET + out.len()]);
+                }
+                Ok(())
+            }
+            S_UNSUPPORTED => Err(BlockError::Unsupported),
+            _ => Err(BlockError::Io),
+        })
+    }
+}
+
+/// Queue interrupt.
+fn interrupt() {
+    if let Some(blk) = BLK.lock().as_mut() {
+        blk.collect();
+    }
+}
+
+/// Waits for the request in `slot` and returns its result, copying out the data for a read.
+fn wait(slot: usize, mut out: Option<&mut [u8]>) -> Result<(), BlockError> {
+    let interrupt_driven = BLK.lock().as_ref().is_some_and(|blk| blk.interrupts);
+    block::wait(interrupt_driven, |timed_out| {
+        let mut guard = BLK.lock();
+        let Some(blk) = guard.as_mut() else { return Some(Err(BlockError::NoDevice)) };
+        blk.collect();
+        blk.complete(slot, out.as_deref_mut()).or_else(|| timed_out.then(|| {
+            blk.slots[slot].state = SlotState::Abandoned;
+            Err(BlockError::Timeout)
+        }))
+    })
+}
+
+fn check(sector: u64, len: usize, write: bool) -> Result<(), BlockError> {
+    let guard = BLK.lock();
+    let blk = guard.as_ref().ok_or(BlockError::NoDevice)?;
+    block::check_request(sector, len, blk.capacity, blk.read_only, write)
+}
+
+fn submit(kind: u32, sector: u64, len: usize, data: Option<&[u8]>) -> Result<usize, BlockError> {
+    BLK.lock().as_mut().ok_or(BlockError::NoDevice)?.submit(kind, sector, len, data)
+}
+
+/// Whether [init] found a disk.
+pub fn is_available() -> bool {
+    BLK.lock().is_some()
+}
+
+/// Size of the disk in sectors, 0 without one.
+pub fn capacity() -> u64 {
+    BLK.lock().as_ref().map_or(0, |blk| blk.capacity)
+}
+
+pub fn is_read_only() -> bool {
+    BLK.lock().as_ref().is_some_and(|blk| blk.read_only)
+}
+
+/// See [block::read].
+pub fn read(sector: u64, buffer: &mut [u8]) -> Result<(), BlockError> {
+    check(sector, buffer.len(), false)?;
+    for (i, chunk) in buffer.chunks_mut(MAX_SECTORS * SECTOR_SIZE).enumerate() {
+        let slot = submit(T_IN, sector + (i * MAX_SECTORS) as u64, chunk.len(), None)?;
+        wait(slot, Some(chunk))?;
+    }
+    Ok(())
+}
+
+/// See [block::write].
+pub fn write(sector: u64, data: &[u8]) -> Result<(), BlockError> {
+    check(sector, data.len(), true)?;
+    for (i, chunk) in data.chunks(MAX_SECTORS * SECTOR_SIZE).enumerate() {
+        let slot = submit(T_OUT, sector + (i * MAX_SECTORS) as u64, chunk.len(), Some(chunk))?;
+        wait(slot, None)?;
+    }
+    Ok(())
+}
+
+/// Waits until everything written so far is on the disk. Does nothing on a disk without a write
+/// cache to flush.
+pub fn flush() -> Result<(), BlockError> {
+    let can_flush = BLK.lock().as_ref().ok_or(BlockError::NoDevice)?.can_flush;
+    if !can_flush {
+        return Ok(());
+    }
+    let slot = submit(T_FLUSH, 0, 0, None)?;
+    wait(slot, None)
+}
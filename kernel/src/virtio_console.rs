@@ -0,0 +1,163 @@
+use core::fmt::Write;
+use core::time::Duration;
+use x86_64::instructions::interrupts;
+use crate::dma::{self, DmaBuffer};
+use crate::sync::IrqSafeMutex;
+use crate::virtio::{self, Buffer, VirtioDevice, Virtqueue};
+use crate::{acpi_tables, pci, serial, time};
+
+// virtio-console (QEMU: `-device virtio-serial-pci -device virtconsole,chardev=...`), a second
+// way to reach the serial console. It carries the same bytes as COM1: everything written with
+// serial() goes out on both, and what arrives goes where COM1 input goes (read_byte, read_line,
+// the line handler, the serial keys), so the shell and Player 2 work on either. Unlike the UART
+// it moves whole buffers at a time and waits for the host rather than dropping bytes.
+//
+// Only port 0 is used (no multiport): receive queue 0, transmit queue 1.
+
+const DEVICE_IDS: [u16; 2] = [0x1043, 0x1003];
+
+const RX_QUEUE: u16 = 0;
+const TX_QUEUE: u16 = 1;
+const RX_BUFFERS: usize = 8;
+const TX_BUFFERS: usize = 16;
+const BUFFER_SIZE: usize = 512;
+/// How long a write waits for the host to take a buffer back before dropping the rest.
+const TX_TIMEOUT: Duration = Duration::from_millis(10);
+
+struct Console {
+    device: VirtioDevice,
+    rx: Virtqueue,
+    tx: Virtqueue,
+    rx_buffers: DmaBuffer,
+    tx_buffers: DmaBuffer,
+    /// Transmit buffers not in flight, one bit each.
+    tx_free: u32,
+    /// Set when a write timed out; until the host takes a buffer back, writes do not wait.
+    stalled: bool,
+}
+
+static CONSOLE: IrqSafeMutex<Option<Console>> = IrqSafeMutex::new(None);
+
+/// Finds a virtio-console and brings up its first port. Returns false if there is none, or it
+/// cannot be set up.
+pub fn init() -> bool {
+    let Some(found) = pci::devices().iter().find(|device| device.vendor_id == virtio::VENDOR_ID && DEVICE_IDS.contains(&device.device_id) && device.class == 0x07) else {
+        return false;
+    };
+    let Some(mut device) = VirtioDevice::new(found.address) else {
+        writeln!(serial(), "console: {} has no virtio 1.x interface", found).unwrap();
+        return false;
+    };
+    if device.init(0).is_none() {
+        writeln!(serial(), "console: device refused the features").unwrap();
+        return false;
+    }
+
+    let cpu = acpi_tables::madt().and_then(|madt| madt.boot_processor()).map_or(0, |processor| processor.local_apic_id as u8);
+    let vector = device.enable_msix(cpu, interrupt);
+    let entry = vector.map(|_| 0);
+    let (Some(rx), Some(tx)) = (device.setup_queue(RX_QUEUE, RX_BUFFERS as u16, entry), device.setup_queue(TX_QUEUE, TX_BUFFERS as u16, entry)) else {
+        writeln!(serial(), "console: queue setup failed").unwrap();
+        return false;
+    };
+    let (Some(rx_buffers), Some(tx_buffers)) = (dma::alloc_contiguous(RX_BUFFERS * BUFFER_SIZE), dma::alloc_contiguous(TX_BUFFERS * BUFFER_SIZE)) else {
+        writeln!(serial(), "console: no memory for buffers").unwrap();
+        return false;
+    };
+
+    let tx_slots = TX_BUFFERS.min(tx.size() as usize);
+    let mut console = Console { device, rx, tx, rx_buffers, tx_buffers, tx_free: (1 << tx_slots) - 1, stalled: false };
+    for slot in 0..RX_BUFFERS.min(console.rx.size() as usize) {
+        console.offer_rx(slot);
+    }
+    console.device.driver_ok();
+    console.rx.notify();
+    writeln!(serial(), "console: virtio-console at {:?}, {}", console.device.pci(), if vector.is_some() { "MSI-X" } else { "output only (no MSI-X)" }).unwrap();
+    *CONSOLE.lock() = Some(console);
+    true
+}
+
+impl Console {
+    fn offer_rx(&mut self, slot: usize) {
+        let address = self.rx_buffers.phys_addr() + (slot * BUFFER_SIZE) as u64;
+        self.rx.add(&[Buffer { address, len: BUFFER_SIZE as u32, device_writes: true }], slot);
+    }
+
+    fn collect_tx(&mut self) {
+        while let Some((slot, _)) = self.tx.pop_used() {
+            self.tx_free |= 1 << slot;
+        }
+    }
+
+    /// A transmit buffer, waiting up to [TX_TIMEOUT] for the host to give one back unless it
+    /// has stalled.
+    fn free_tx_slot(&mut self) -> Option<usize> {
+        self.collect_tx();
+        if self.tx_free == 0 && !self.stalled {
+            self.stalled = !time::poll_until(TX_TIMEOUT, || {
+                self.collect_tx();
+                self.tx_free != 0
+            });
+        }
+        if self.tx_free == 0 {
+            return None;
+        }
+        self.stalled = false;
+        Some(self.tx_free.trailing_zeros() as usize)
+    }
+
+    fn send(&mut self, bytes: &[u8]) {
+        for chunk in bytes.chunks(BUFFER_SIZE) {
+            let Some(slot) = self.free_tx_slot() else { return };
+            let start = slot * BUFFER_SIZE;
+            self.tx_buffers.as_mut_slice()[start..start + chunk.len()].copy_from_slice(chunk);
+            let address = self.tx_buffers.phys_addr() + start as u64;
+            if !self.tx.add(&[Buffer { address, len: chunk.len() as u32, device_writes: false }], slot) {
+                return;
+            }
+            self.tx_free &= !(1 << slot);
+            self.tx.notify();
+        }
+    }
+}
+
+/// Queue interrupt: hands what was received to the serial console's input, and takes back sent
+/// buffers.
+fn interrupt() {
+    loop {
+        // Copied out so the lock is free again when the bytes are handled, which may write
+        let mut bytes = [0; BUFFER_SIZE];
+        let len = {
+            let mut guard = CONSOLE.lock();
+            let Some(console) = guard.as_mut() else { return };
+            console.collect_tx();
+            let Some((slot, len)) = console.rx.pop_used() else { return };
+            let len = (len as usize).min(BUFFER_SIZE);
+            let start = slot * BUFFER_SIZE;
+            bytes[..len].copy_from_slice(&console.rx_buffers.as_slice()[start..start + len]);
+            console.offer_rx(slot);
+            console.rx.notify();
+            len
+        };
+        for &byte in &bytes[..len] {
+            crate::interrupts::serial_received(byte);
+        }
+    }
+}
+
+/// Whether [init] found a console.
+pub fn is_available() -> bool {
+    CONSOLE.lock().is_some()
+}
+
+/// Sends `bytes` to the host, if there is a console; called for everything written with
+/// [crate::serial]. Waits for the host to take earlier output if all buffers are in flight, and
+/// drops what it cannot send within [TX_TIMEOUT]: the host may not be reading.
+pub(crate) fn write(bytes: &[u8]) {
+    // try_lock with interrupts off: this may have interrupted a write, or be a panic on the way
+    // out
+    let mut guard = if interrupts::are_enabled() { Some(CONSOLE.lock()) } else { CONSOLE.try_lock() };
+    if let Some(console) = guard.as_mut().and_then(|guard| guard.as_mut()) {
+        console.send(bytes);
+    }
+}
@@ -0,0 +1,304 @@
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::time::Duration;
+use x86_64::VirtAddr;
+use x86_64::structures::paging::PageTableFlags;
+use crate::dma::{self, DmaBuffer};
+use crate::sync::IrqSafeMutex;
+use crate::virtio::{self, Buffer, VirtioDevice, Virtqueue};
+use crate::{memory, pci, serial, time};
+
+// virtio-gpu display (QEMU: `-device virtio-vga` or `virtio-gpu-pci`), 2D only: one framebuffer
+// shown on the first scanout, at any resolution the host accepts.
+//
+// The framebuffer is ordinary memory, mapped at one of two fixed virtual windows from pages that
+// can be anywhere, and handed to the device as the backing of a 2D resource (a list of its
+// physically contiguous runs). The host only sees what was drawn once told: [flush] copies the
+// framebuffer into the host's resource and puts it on the display, once a frame. Changing the
+// mode builds the new framebuffer in the other window and switches the scanout to it before the
+// old one goes, so a failed switch leaves the display as it was. Commands go over the control
+// queue one at a time and are polled; the host answers them right away.
+
+const DEVICE_ID: u16 = 0x1050;
+const CONTROL_QUEUE: u16 = 0;
+
+const CMD_GET_DISPLAY_INFO: u32 = 0x0100;
+const CMD_RESOURCE_CREATE_2D: u32 = 0x0101;
+const CMD_RESOURCE_UNREF: u32 = 0x0102;
+const CMD_SET_SCANOUT: u32 = 0x0103;
+const CMD_RESOURCE_FLUSH: u32 = 0x0104;
+const CMD_TRANSFER_TO_HOST_2D: u32 = 0x0105;
+const CMD_RESOURCE_ATTACH_BACKING: u32 = 0x0106;
+const CMD_RESOURCE_DETACH_BACKING: u32 = 0x0107;
+const RESP_OK_NODATA: u32 = 0x1100;
+const RESP_OK_DISPLAY_INFO: u32 = 0x1101;
+
+/// Blue, green, red and an unused byte: the framebuffer's [PixelFormat::Bgr] with 4 bytes a pixel.
+///
+/// [PixelFormat::Bgr]: bootloader_api::info::PixelFormat::Bgr
+const FORMAT_B8G8R8X8: u32 = 2;
+const BYTES_PER_PIXEL: usize = 4;
+const HEADER_LEN: usize = 24;
+/// Where the request goes in the command buffer, and where the response comes back.
+const RESPONSE_OFFSET: usize = 2048;
+const MAX_SCANOUTS: usize = 16;
+
+/// The two framebuffer windows, and how large a framebuffer can be.
+const WINDOWS: [u64; 2] = [0x30_0000_0000, 0x30_4000_0000];
+const WINDOW_SIZE: usize = 64 * 1024 * 1024;
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+struct Framebuffer {
+    resource: u32,
+    window: usize,
+    width: u32,
+    height: u32,
+}
+
+impl Framebuffer {
+    fn len(&self) -> usize {
+        self.width as usize * self.height as usize * BYTES_PER_PIXEL
+    }
+}
+
+struct VirtioGpu {
+    device: VirtioDevice,
+    queue: Virtqueue,
+    /// Request at 0, response at [RESPONSE_OFFSET].
+    commands: DmaBuffer,
+    /// Size of the first scanout as the host prefers it.
+    native: (u32, u32),
+    framebuffer: Option<Framebuffer>,
+    next_resource: u32,
+}
+
+static GPU: IrqSafeMutex<Option<VirtioGpu>> = IrqSafeMutex::new(None);
+
+/// A command's header.
+fn header(command: u32) -> Vec<u8> {
+    let mut request = Vec::with_capacity(64);
+    request.extend_from_slice(&command.to_le_bytes());
+    request.resize(HEADER_LEN, 0);
+    request
+}
+
+fn push_u32s(request: &mut Vec<u8>, values: &[u32]) {
+    for value in values {
+        request.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+/// Finds a virtio-gpu and reads the display size the host would like. Returns false if there is
+/// none, or it cannot be set up. The display keeps showing what it did until [set_mode].
+pub fn init() -> bool {
+    let Some(found) = pci::devices().iter().find(|device| device.vendor_id == virtio::VENDOR_ID && device.device_id == DEVICE_ID) else {
+        return false;
+    };
+    let Some(mut device) = VirtioDevice::new(found.address) else {
+        writeln!(serial(), "gpu: {} has no virtio 1.x interface", found).unwrap();
+        return false;
+    };
+    if device.init(0).is_none() {
+        writeln!(serial(), "gpu: device refused the features").unwrap();
+        return false;
+    }
+    let Some(queue) = device.setup_queue(CONTROL_QUEUE, 16, None) else {
+        writeln!(serial(), "gpu: queue setup failed").unwrap();
+        return false;
+    };
+    let Some(commands) = dma::alloc_contiguous(2 * RESPONSE_OFFSET) else {
+        writeln!(serial(), "gpu: no memory for commands").unwrap();
+        return false;
+    };
+    device.driver_ok();
+
+    let mut gpu = VirtioGpu { device, queue, commands, native: (0, 0), framebuffer: None, next_resource: 1 };
+    let Some(native) = gpu.display_info() else {
+        writeln!(serial(), "gpu: no display").unwrap();
+        return false;
+    };
+    gpu.native = native;
+    writeln!(serial(), "gpu: virtio-gpu at {:?}, display {}x{}", gpu.device.pci(), native.0, native.1).unwrap();
+    *GPU.lock() = Some(gpu);
+    true
+}
+
+impl VirtioGpu {
+    /// Sends `request` and waits for the response, which must be `expected` to count as done.
+    /// Returns the response, `response_len` bytes of it.
+    fn command(&mut self, request: &[u8], response_len: usize, expected: u32) -> Option<Vec<u8>> {
+        // Requests larger than the command buffer (long backing lists) get a buffer of their own
+        let mut large = None;
+        let (request_address, response_address) = if request.len() > RESPONSE_OFFSET {
+            let mut buffer = dma::alloc_contiguous(request.len())?;
+            buffer.as_mut_slice()[..request.len()].copy_from_slice(request);
+            let address = buffer.phys_addr();
+            large = Some(buffer);
+            (address, self.commands.phys_addr() + RESPONSE_OFFSET as u64)
+        } else {
+            self.commands.as_mut_slice()[..request.len()].copy_from_slice(request);
+            (self.commands.phys_addr(), self.commands.phys_addr() + RESPONSE_OFFSET as u64)
+        };
+        self.commands.as_mut_slice()[RESPONSE_OFFSET..RESPONSE_OFFSET + 4].fill(0);
+
+        let buffers = [
+            Buffer { address: request_address, len: request.len() as u32, device_writes: false },
+            Buffer { address: response_address, len: response_len as u32, device_writes: true },
+        ];
+        if !self.queue.add(&buffers, 0) {
+            return None;
+        }
+        self.queue.notify();
+        let queue = &mut self.queue;
+        let done = time::poll_until(TIMEOUT, || queue.pop_used().is_some());
+        if !done {
+            // The device may still read it
+            core::mem::forget(large);
+        }
+        let response = &self.commands.as_slice()[RESPONSE_OFFSET..RESPONSE_OFFSET + response_len];
+        if !done || u32_at(response, 0) != expected {
+            let command = u32_at(request, 0);
+            writeln!(serial(), "gpu: command {:#x} failed ({})", command, if done { "error" } else { "timed out" }).unwrap();
+            return None;
+        }
+        Some(response.to_vec())
+    }
+
+    fn simple(&mut self, request: &[u8]) -> bool {
+        self.command(request, HEADER_LEN, RESP_OK_NODATA).is_some()
+    }
+
+    /// Size of the first enabled scanout.
+    fn display_info(&mut self) -> Option<(u32, u32)> {
+        let response = self.command(&header(CMD_GET_DISPLAY_INFO), HEADER_LEN + MAX_SCANOUTS * 24, RESP_OK_DISPLAY_INFO)?;
+        (0..MAX_SCANOUTS).map(|i| HEADER_LEN + i * 24).find(|&mode| u32_at(&response, mode + 16) != 0)
+            .map(|mode| (u32_at(&response, mode + 8), u32_at(&response, mode + 12)))
+    }
+
+    /// Maps a framebuffer of `width`x`height` in `window` and makes it the backing of a new
+    /// resource. Undoes what it did if a step fails.
+    fn create(&mut self, window: usize, width: u32, height: u32) -> Option<Framebuffer> {
+        let framebuffer = Framebuffer { resource: self.next_resource, window, width, height };
+        let len = framebuffer.len();
+        if len == 0 || len > WINDOW_SIZE {
+            return None;
+        }
+        let start = VirtAddr::new(WINDOWS[window]);
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+        if memory::alloc_region(start, len as u64, flags).is_err() {
+            memory::free_region(start, len as u64);
+            return None;
+        }
+
+        // The backing: the framebuffer's pages, merged into physically contiguous runs
+        let mut runs: Vec<(u64, u32)> = Vec::new();
+        for offset in (0..len).step_by(4096) {
+            let Some((phys, _)) = memory::translate(start + offset as u64) else {
+                memory::free_region(start, len as u64);
+                return None;
+            };
+            let page_len = (len - offset).min(4096) as u32;
+            match runs.last_mut() {
+                Some((address, run_len)) if *address + *run_len as u64 == phys.as_u64() => *run_len += page_len,
+                _ => runs.push((phys.as_u64(), page_len)),
+            }
+        }
+
+        let mut create = header(CMD_RESOURCE_CREATE_2D);
+        push_u32s(&mut create, &[framebuffer.resource, FORMAT_B8G8R8X8, width, height]);
+        if !self.simple(&create) {
+            memory::free_region(start, len as u64);
+            return None;
+        }
+        self.next_resource += 1;
+        let mut attach = header(CMD_RESOURCE_ATTACH_BACKING);
+        push_u32s(&mut attach, &[framebuffer.resource, runs.len() as u32]);
+        for (address, run_len) in runs {
+            attach.extend_from_slice(&address.to_le_bytes());
+            push_u32s(&mut attach, &[run_len, 0]);
+        }
+        if !self.simple(&attach) {
+            self.destroy(framebuffer, false);
+            return None;
+        }
+        Some(framebuffer)
+    }
+
+    /// Frees a framebuffer's resource and its memory.
+    fn destroy(&mut self, framebuffer: Framebuffer, attached: bool) {
+        if attached {
+            let mut detach = header(CMD_RESOURCE_DETACH_BACKING);
+            push_u32s(&mut detach, &[framebuffer.resource, 0]);
+            self.simple(&detach);
+        }
+        let mut unref = header(CMD_RESOURCE_UNREF);
+        push_u32s(&mut unref, &[framebuffer.resource, 0]);
+        self.simple(&unref);
+        memory::free_region(VirtAddr::new(WINDOWS[framebuffer.window]), framebuffer.len() as u64);
+    }
+
+    fn set_scanout(&mut self, resource: u32, width: u32, height: u32) -> bool {
+        let mut set = header(CMD_SET_SCANOUT);
+        push_u32s(&mut set, &[0, 0, width, height, 0, resource]);
+        self.simple(&set)
+    }
+
+    fn flush(&mut self) {
+        let Some(&Framebuffer { resource, width, height, .. }) = self.framebuffer.as_ref() else { return };
+        let mut transfer = header(CMD_TRANSFER_TO_HOST_2D);
+        push_u32s(&mut transfer, &[0, 0, width, height]);
+        transfer.extend_from_slice(&0u64.to_le_bytes());
+        push_u32s(&mut transfer, &[resource, 0]);
+        let mut flush = header(CMD_RESOURCE_FLUSH);
+        push_u32s(&mut flush, &[0, 0, width, height, resource, 0]);
+        if self.simple(&transfer) {
+            self.simple(&flush);
+        }
+    }
+}
+
+pub fn is_available() -> bool {
+    GPU.lock().is_some()
+}
+
+/// The display size the host prefers, if there is a virtio-gpu.
+pub fn native_mode() -> Option<(u32, u32)> {
+    GPU.lock().as_ref().map(|gpu| gpu.native)
+}
+
+/// Shows a new, black `width`x`height` framebuffer and returns it, 4 bytes a pixel (blue, green,
+/// red, unused) with no padding between rows. Returns None if there is no virtio-gpu or the host
+/// refuses the mode; the display stays as it was then.
+///
+/// ## Safety
+/// The framebuffer returned by the previous call is unmapped: it must no longer be in use.
+pub unsafe fn set_mode(width: u32, height: u32) -> Option<&'static mut [u8]> {
+    let mut guard = GPU.lock();
+    let gpu = guard.as_mut()?;
+    let window = gpu.framebuffer.as_ref().map_or(0, |old| 1 - old.window);
+    let framebuffer = gpu.create(window, width, height)?;
+    if !gpu.set_scanout(framebuffer.resource, width, height) {
+        gpu.destroy(framebuffer, true);
+        return None;
+    }
+    let len = framebuffer.len();
+    if let Some(old) = gpu.framebuffer.replace(framebuffer) {
+        gpu.destroy(old, true);
+    }
+    gpu.flush();
+    writeln!(serial(), "gpu: mode {}x{}", width, height).unwrap();
+    Some(unsafe { core::slice::from_raw_parts_mut(WINDOWS[window] as *mut u8, len) })
+}
+
+/// Puts what has been drawn in the framebuffer on the display. Skipped if the driver is busy,
+/// as when a crash hit in the middle of a command.
+pub fn flush() {
+    let Some(mut guard) = GPU.try_lock() else { return };
+    if let Some(gpu) = guard.as_mut() {
+        gpu.flush();
+    }
+}
@@ -0,0 +1,201 @@
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use crate::dma::{self, DmaBuffer};
+use crate::virtio::{self, Buffer, VirtioDevice, Virtqueue};
+use crate::{acpi_tables, deferred, pci, serial};
+
+// virtio-net network card (QEMU: `-device virtio-net-pci`). Sends and receives Ethernet frames;
+// what goes in them is up to the caller. Each direction has a queue of fixed buffers: receive
+// buffers stay offered to the device and are offered again once their frame is copied out,
+// transmit buffers are taken for a frame and come back once the device has sent it.
+
+const DEVICE_IDS: [u16; 2] = [0x1041, 0x1000];
+
+const F_MAC: u64 = 1 << 5;
+
+const RX_QUEUE: u16 = 0;
+const TX_QUEUE: u16 = 1;
+const BUFFERS: usize = 32;
+/// Every buffer starts with a virtio_net_hdr; for transmit it stays zero (no offloads).
+const HEADER_LEN: usize = 12;
+pub const MAX_FRAME: usize = 1514;
+const BUFFER_SIZE: usize = 2048;
+/// Frames kept for [receive]; more are dropped until the caller catches up.
+const MAX_RECEIVED: usize = 64;
+
+struct VirtioNet {
+    device: VirtioDevice,
+    rx: Virtqueue,
+    tx: Virtqueue,
+    rx_buffers: DmaBuffer,
+    tx_buffers: DmaBuffer,
+    tx_free: Vec<usize>,
+    received: VecDeque<Vec<u8>>,
+    mac: [u8; 6],
+}
+
+static NET: Mutex<Option<VirtioNet>> = Mutex::new(None);
+static RX_FRAMES: AtomicU64 = AtomicU64::new(0);
+static TX_FRAMES: AtomicU64 = AtomicU64::new(0);
+static RX_DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Frame counts since boot, from [stats].
+#[derive(Debug, Clone, Copy)]
+pub struct NetStats {
+    pub received: u64,
+    pub sent: u64,
+    /// Received while [receive] was [MAX_RECEIVED] frames behind.
+    pub dropped: u64,
+}
+
+/// Finds a virtio-net card and brings it up. Returns false if there is none, or it cannot be
+/// set up.
+pub fn init() -> bool {
+    let Some(found) = pci::devices().iter().find(|device| device.vendor_id == virtio::VENDOR_ID && DEVICE_IDS.contains(&device.device_id) && device.class == 0x02) else {
+        return false;
+    };
+    let Some(mut device) = VirtioDevice::new(found.address) else {
+        writeln!(serial(), "net: {} has no virtio 1.x interface", found).unwrap();
+        return false;
+    };
+    let Some(features) = device.init(F_MAC) else {
+        writeln!(serial(), "net: device refused the features").unwrap();
+        return false;
+    };
+
+    let cpu = acpi_tables::madt().and_then(|madt| madt.boot_processor()).map_or(0, |processor| processor.local_apic_id as u8);
+    let vector = device.enable_msix(cpu, interrupt);
+    let entry = vector.map(|_| 0);
+    let (Some(rx), Some(tx)) = (device.setup_queue(RX_QUEUE, BUFFERS as u16, entry), device.setup_queue(TX_QUEUE, BUFFERS as u16, entry)) else {
+        writeln!(serial(), "net: queue setup failed").unwrap();
+        return false;
+    };
+    let (Some(rx_buffers), Some(tx_buffers)) = (dma::alloc_contiguous(BUFFERS * BUFFER_SIZE), dma::alloc_contiguous(BUFFERS * BUFFER_SIZE)) else {
+        writeln!(serial(), "net: no memory for buffers").unwrap();
+        return false;
+    };
+
+    let mut mac = [0; 6];
+    if features & F_MAC != 0 {
+        for (i, byte) in mac.iter_mut().enumerate() {
+            *byte = device.config_read8(i as u64);
+        }
+    } else {
+        // Locally administered, unicast
+        mac = [0x02, 0x00, 0x00, 0x00, 0x00, 0x01];
+    }
+
+    let mut net = VirtioNet { device, rx, tx, rx_buffers, tx_buffers, tx_free: (0..BUFFERS).collect(), received: VecDeque::new(), mac };
+    for slot in 0..BUFFERS.min(net.rx.size() as usize) {
+        net.offer_rx(slot);
+    }
+    net.device.driver_ok();
+    net.rx.notify();
+    writeln!(serial(), "net: virtio-net at {:?}, MAC {}, {}", net.device.pci(), format_mac(mac), if vector.is_some() { "MSI-X" } else { "polled" }).unwrap();
+    without_interrupts(|| *NET.lock() = Some(net));
+    true
+}
+
+/// `aa:bb:cc:dd:ee:ff`
+pub fn format_mac(mac: [u8; 6]) -> alloc::string::String {
+    alloc::format!("{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}", mac[0], mac[1], mac[2], mac[3], mac[4], mac[5])
+}
+
+impl VirtioNet {
+    fn offer_rx(&mut self, slot: usize) {
+        let address = self.rx_buffers.phys_addr() + (slot * BUFFER_SIZE) as u64;
+        self.rx.add(&[Buffer { address, len: BUFFER_SIZE as u32, device_writes: true }], slot);
+    }
+
+    /// Copies out received frames, offers their buffers again and takes back sent ones.
+    fn collect(&mut self) {
+        let mut offered = false;
+        while let Some((slot, len)) = self.rx.pop_used() {
+            let len = (len as usize).clamp(HEADER_LEN, BUFFER_SIZE);
+            RX_FRAMES.fetch_add(1, Ordering::Relaxed);
+            if self.received.len() < MAX_RECEIVED {
+                let start = slot * BUFFER_SIZE;
+                self.received.push_back(self.rx_buffers.as_slice()[start + HEADER_LEN..start + len].to_vec());
+            } else {
+                RX_DROPPED.fetch_add(1, Ordering::Relaxed);
+            }
+            self.offer_rx(slot);
+            offered = true;
+        }
+        if offered {
+            self.rx.notify();
+        }
+        while let Some((slot, _)) = self.tx.pop_used() {
+            self.tx_free.push(slot);
+        }
+    }
+}
+
+/// Queue interrupt: the work, which allocates, is left for later.
+fn interrupt() {
+    deferred::defer(|| without_interrupts(|| {
+        if let Some(net) = NET.lock().as_mut() {
+            net.collect();
+        }
+    }));
+}
+
+/// Whether [init] found a card.
+pub fn is_available() -> bool {
+    without_interrupts(|| NET.lock().is_some())
+}
+
+pub fn mac_address() -> Option<[u8; 6]> {
+    without_interrupts(|| NET.lock().as_ref().map(|net| net.mac))
+}
+
+/// Sends an Ethernet frame: destination and source MAC, EtherType, payload, without the CRC.
+/// Returns false without a card, if the frame is longer than [MAX_FRAME], or if all transmit
+/// buffers are still waiting to be sent.
+pub fn send(frame: &[u8]) -> bool {
+    if frame.len() > MAX_FRAME {
+        return false;
+    }
+    without_interrupts(|| {
+        let mut guard = NET.lock();
+        let Some(net) = guard.as_mut() else { return false };
+        net.collect();
+        let Some(slot) = net.tx_free.pop() else { return false };
+
+        let start = slot * BUFFER_SIZE;
+        let buffer = &mut net.tx_buffers.as_mut_slice()[start..start + HEADER_LEN + frame.len()];
+        buffer[..HEADER_LEN].fill(0);
+        buffer[HEADER_LEN..].copy_from_slice(frame);
+        let address = net.tx_buffers.phys_addr() + start as u64;
+        if !net.tx.add(&[Buffer { address, len: (HEADER_LEN + frame.len()) as u32, device_writes: false }], slot) {
+            net.tx_free.push(slot);
+            return false;
+        }
+        net.tx.notify();
+        TX_FRAMES.fetch_add(1, Ordering::Relaxed);
+        true
+    })
+}
+
+/// The oldest Ethernet frame received and not yet taken, if any. Never blocks.
+pub fn receive() -> Option<Vec<u8>> {
+    without_interrupts(|| {
+        let mut guard = NET.lock();
+        let net = guard.as_mut()?;
+        // Also picks frames up when there is no interrupt
+        net.collect();
+        net.received.pop_front()
+    })
+}
+
+pub fn stats() -> NetStats {
+    NetStats {
+        received: RX_FRAMES.load(Ordering::Relaxed),
+        sent: TX_FRAMES.load(Ordering::Relaxed),
+        dropped: RX_DROPPED.load(Ordering::Relaxed),
+    }
+}
@@ -0,0 +1,68 @@
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU32, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::structures::idt::InterruptStackFrame;
+use crate::{interrupts, serial, time};
+
+/// Timer interrupts allowed between pets; 0 while disabled.
+static TIMEOUT: AtomicU32 = AtomicU32::new(0);
+static TICKS_SINCE_PET: AtomicU32 = AtomicU32::new(0);
+/// Timeouts in a row without a pet. Diagnostics are written on the first only, rather than on
+/// every one of a long hang.
+static BITES: AtomicU32 = AtomicU32::new(0);
+static ON_BITE: Mutex<Option<fn(u32)>> = Mutex::new(None);
+
+/// Starts the watchdog. If [pet] is not called within `timeout_ticks` timer interrupts,
+/// diagnostics are written to serial and `on_bite` runs (in interrupt context), and again after
+/// every further `timeout_ticks` without a pet. It is given how many timeouts that makes.
+pub fn enable(timeout_ticks: u32, on_bite: Option<fn(u32)>) {
+    without_interrupts(|| *ON_BITE.lock() = on_bite);
+    TICKS_SINCE_PET.store(0, Ordering::Relaxed);
+    TIMEOUT.store(timeout_ticks, Ordering::Relaxed);
+}
+
+pub fn disable() {
+    TIMEOUT.store(0, Ordering::Relaxed);
+}
+
+/// Signals that the watched loop made progress. Call at the end of every frame.
+pub fn pet() {
+    TICKS_SINCE_PET.store(0, Ordering::Relaxed);
+    if BITES.swap(0, Ordering::Relaxed) > 0 {
+        writeln!(serial(), "watchdog: recovered").unwrap();
+    }
+}
+
+/// Called from the timer interrupt. `stack_frame` shows where the CPU was when it fired, which
+/// for a hung loop is where it is stuck.
+pub(crate) fn check(stack_frame: &InterruptStackFrame) {
+    let timeout = TIMEOUT.load(Ordering::Relaxed);
+    if timeout == 0 {
+        return;
+    }
+    let ticks = TICKS_SINCE_PET.fetch_add(1, Ordering::Relaxed) + 1;
+    if ticks < timeout {
+        return;
+    }
+    TICKS_SINCE_PET.store(0, Ordering::Relaxed);
+    let bites = BITES.fetch_add(1, Ordering::Relaxed) + 1;
+    if bites == 1 {
+        report(ticks, stack_frame);
+    }
+
+    let on_bite = *ON_BITE.lock();
+    if let Some(on_bite) = on_bite {
+        on_bite(bites);
+    }
+}
+
+fn report(ticks: u32, stack_frame: &InterruptStackFrame) {
+    let mut serial = serial();
+    writeln!(serial, "watchdog: no progress for {} ticks at {}s uptime", ticks, time::uptime().as_secs()).unwrap();
+    writeln!(serial, "  interrupted at {:?}, stack {:?}", stack_frame.instruction_pointer, stack_frame.stack_pointer).unwrap();
+    writeln!(serial, "  missed ticks: {}", interrupts::missed_ticks()).unwrap();
+    for (vector, name, count) in interrupts::interrupt_stats() {
+        writeln!(serial, "  {:#04x} {}: {}", vector, name, count).unwrap();
+    }
+}
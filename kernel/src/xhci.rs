@@ -0,0 +1,598 @@
+use core::fmt::Write;
+use core::ptr;
+use core::time::Duration;
+use spin::Mutex;
+use x86_64::structures::paging::{FrameAllocator, Size4KiB};
+use x86_64::PhysAddr;
+use crate::gamepad::{GamepadState, ReportLayout};
+use crate::{memory, pci, serial, time};
+
+// Minimal polled xHCI driver, just enough to find a HID gamepad on a root hub port and read its
+// interrupt IN reports. See the "eXtensible Host Controller Interface for USB" specification and
+// https://wiki.osdev.org/EXtensible_Host_Controller_Interface
+
+const MMIO_PAGES: u64 = 16;
+const RING_SIZE: usize = 256; // TRBs per 4 KiB page
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+// Operational registers
+const USBCMD: u64 = 0x00;
+const USBSTS: u64 = 0x04;
+const CRCR: u64 = 0x18;
+const DCBAAP: u64 = 0x30;
+const CONFIG: u64 = 0x38;
+const PORTSC: u64 = 0x400;
+
+const CMD_RUN: u32 = 1 << 0;
+const CMD_RESET: u32 = 1 << 1;
+const STS_HALTED: u32 = 1 << 0;
+const STS_NOT_READY: u32 = 1 << 11;
+
+const PORT_CONNECTED: u32 = 1 << 0;
+const PORT_ENABLED: u32 = 1 << 1;
+const PORT_RESET: u32 = 1 << 4;
+const PORT_RESET_CHANGE: u32 = 1 << 21;
+// Bits of PORTSC that are preserved by a read-modify-write (everything except the RW1C bits)
+const PORT_PRESERVE: u32 = 0x0E00_C3E0;
+
+// TRB types
+const TRB_NORMAL: u32 = 1;
+const TRB_SETUP: u32 = 2;
+const TRB_DATA: u32 = 3;
+const TRB_STATUS: u32 = 4;
+const TRB_LINK: u32 = 6;
+const TRB_ENABLE_SLOT: u32 = 9;
+const TRB_ADDRESS_DEVICE: u32 = 11;
+const TRB_CONFIGURE_ENDPOINT: u32 = 12;
+const TRB_EVALUATE_CONTEXT: u32 = 13;
+const TRB_TRANSFER_EVENT: u32 = 32;
+const TRB_COMMAND_COMPLETION: u32 = 33;
+
+const TRB_CYCLE: u32 = 1 << 0;
+const TRB_TOGGLE_CYCLE: u32 = 1 << 1;
+const TRB_IOC: u32 = 1 << 5;
+const TRB_IDT: u32 = 1 << 6;
+const TRB_DIR_IN: u32 = 1 << 16;
+
+const COMPLETION_SUCCESS: u32 = 1;
+const COMPLETION_SHORT_PACKET: u32 = 13;
+
+const ENDPOINT_CONTROL: u32 = 4;
+const ENDPOINT_INTERRUPT_IN: u32 = 7;
+
+static XHCI: Mutex<Option<Xhci>> = Mutex::new(None);
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Trb {
+    parameter: u64,
+    status: u32,
+    control: u32,
+}
+
+impl Trb {
+    fn kind(&self) -> u32 {
+        (self.control >> 10) & 0x3F
+    }
+
+    fn completion_code(&self) -> u32 {
+        self.status >> 24
+    }
+
+    fn slot(&self) -> u8 {
+        (self.control >> 24) as u8
+    }
+}
+
+/// A zeroed, page-sized DMA buffer.
+#[derive(Clone, Copy)]
+struct Dma {
+    virt: *mut u8,
+    phys: u64,
+}
+
+/// Producer ring (command or transfer ring) closed by a link TRB.
+struct Ring {
+    buffer: Dma,
+    index: usize,
+    cycle: bool,
+}
+
+impl Ring {
+    fn new(buffer: Dma) -> Self {
+        Self { buffer, index: 0, cycle: true }
+    }
+
+    fn trb(&self, index: usize) -> *mut Trb {
+        unsafe { (self.buffer.virt as *mut Trb).add(index) }
+    }
+
+    /// Enqueues a TRB and returns its physical address.
+    fn push(&mut self, parameter: u64, status: u32, control: u32) -> u64 {
+        let address = self.buffer.phys + (self.index * 16) as u64;
+        write_trb(self.trb(self.index), parameter, status, control | self.cycle as u32);
+        self.index += 1;
+
+        if self.index == RING_SIZE - 1 {
+            let link = TRB_LINK << 10 | TRB_TOGGLE_CYCLE | self.cycle as u32;
+            write_trb(self.trb(self.index), self.buffer.phys, 0, link);
+            self.index = 0;
+            self.cycle = !self.cycle;
+        }
+        address
+    }
+}
+
+fn write_trb(trb: *mut Trb, parameter: u64, status: u32, control: u32) {
+    unsafe {
+        ptr::addr_of_mut!((*trb).parameter).write_volatile(parameter);
+        ptr::addr_of_mut!((*trb).status).write_volatile(status);
+        // The control word carries the cycle bit, so it must be written last
+        ptr::addr_of_mut!((*trb).control).write_volatile(control);
+    }
+}
+
+/// Consumer side of the single-segment event ring.
+struct EventRing {
+    buffer: Dma,
+    index: usize,
+    cycle: bool,
+}
+
+impl EventRing {
+    fn pop(&mut self) -> Option<Trb> {
+        let trb = unsafe { (self.buffer.virt as *const Trb).add(self.index).read_volatile() };
+        if (trb.control & TRB_CYCLE != 0) != self.cycle {
+            return None;
+        }
+        self.index += 1;
+        if self.index == RING_SIZE {
+            self.index = 0;
+            self.cycle = !self.cycle;
+        }
+        Some(trb)
+    }
+
+    fn dequeue_pointer(&self) -> u64 {
+        self.buffer.phys + (self.index * 16) as u64
+    }
+}
+
+struct Gamepad {
+    slot: u8,
+    endpoint: u8,
+    ring: Ring,
+    report: Dma,
+    report_len: usize,
+    layout: ReportLayout,
+}
+
+struct Xhci {
+    operational: u64,
+    runtime: u64,
+    doorbells: u64,
+    context_size: usize,
+    dcbaa: Dma,
+    commands: Ring,
+    events: EventRing,
+    scratch: Dma,
+    gamepad: Option<Gamepad>,
+}
+
+unsafe impl Send for Xhci {}
+
+fn read32(address: u64) -> u32 {
+    unsafe { (address as *const u32).read_volatile() }
+}
+
+fn write32(address: u64, value: u32) {
+    unsafe { (address as *mut u32).write_volatile(value) }
+}
+
+fn write64(address: u64, value: u64) {
+    write32(address, value as u32);
+    write32(address + 4, (value >> 32) as u32);
+}
+
+fn wait_until(condition: impl Fn() -> bool) -> bool {
+    time::poll_until(TIMEOUT, condition)
+}
+
+fn alloc_dma(physical_offset: u64, frame_allocator: &mut impl FrameAllocator<Size4KiB>) -> Dma {
+    let frame = frame_allocator.allocate_frame().expect("out of frames for xHCI");
+    let phys = frame.start_address().as_u64();
+    let virt = (physical_offset + phys) as *mut u8;
+    unsafe { virt.write_bytes(0, 4096) };
+    Dma { virt, phys }
+}
+
+/// Looks for an xHCI controller, resets it and enumerates the root hub ports, keeping the first
+/// HID gamepad found. Reports are picked up by [poll].
+pub fn init(physical_offset: u64, frame_allocator: &mut impl FrameAllocator<Size4KiB>) {
+    let Some(pci) = pci::find_by_class(0x0C, 0x03, 0x30) else {
+        writeln!(serial(), "xHCI: no controller found").unwrap();
+        return;
+    };
+    pci.enable_bus_master();
+
+    let base = pci.memory_bar(0);
+    memory::map_region(PhysAddr::new(base), MMIO_PAGES * 4096, memory::MMIO);
+
+    let cap_length = read32(base) & 0xFF;
+    let hcs_params1 = read32(base + 0x04);
+    let hcs_params2 = read32(base + 0x08);
+    let hcc_params1 = read32(base + 0x10);
+    let max_slots = hcs_params1 & 0xFF;
+    let max_ports = hcs_params1 >> 24;
+
+    take_ownership(base, hcc_params1);
+
+    let operational = base + cap_length as u64;
+    let mut xhci = Xhci {
+        operational,
+        runtime: base + (read32(base + 0x18) & !0x1F) as u64,
+        doorbells: base + (read32(base + 0x14) & !0x3) as u64,
+        context_size: if hcc_params1 & (1 << 2) != 0 { 64 } else { 32 },
+        dcbaa: alloc_dma(physical_offset, frame_allocator),
+        commands: Ring::new(alloc_dma(physical_offset, frame_allocator)),
+        events: EventRing { buffer: alloc_dma(physical_offset, frame_allocator), index: 0, cycle: true },
+        scratch: alloc_dma(physical_offset, frame_allocator),
+        gamepad: None,
+    };
+
+    // Stop and reset the controller left running by the firmware
+    write32(operational + USBCMD, read32(operational + USBCMD) & !CMD_RUN);
+    wait_until(|| read32(operational + USBSTS) & STS_HALTED != 0);
+    write32(operational + USBCMD, CMD_RESET);
+    if !wait_until(|| read32(operational + USBCMD) & CMD_RESET == 0 && read32(operational + USBSTS) & STS_NOT_READY == 0) {
+        writeln!(serial(), "xHCI: controller reset timed out").unwrap();
+        return;
+    }
+
+    write32(operational + CONFIG, max_slots);
+
+    let scratchpads = ((hcs_params2 >> 21) & 0x1F) << 5 | (hcs_params2 >> 27);
+    if scratchpads > 0 {
+        let array = alloc_dma(physical_offset, frame_allocator);
+        for i in 0..scratchpads.min(512) as usize {
+            let page = alloc_dma(physical_offset, frame_allocator);
+            unsafe { (array.virt as *mut u64).add(i).write_volatile(page.phys) };
+        }
+        unsafe { (xhci.dcbaa.virt as *mut u64).write_volatile(array.phys) };
+    }
+    write64(operational + DCBAAP, xhci.dcbaa.phys);
+    write64(operational + CRCR, xhci.commands.buffer.phys | 1);
+
+    // Event ring segment table with a single segment, for interrupter 0
+    let erst = alloc_dma(physical_offset, frame_allocator);
+    unsafe {
+        (erst.virt as *mut u64).write_volatile(xhci.events.buffer.phys);
+        (erst.virt.add(8) as *mut u32).write_volatile(RING_SIZE as u32);
+    }
+    let interrupter = xhci.runtime + 0x20;
+    write32(interrupter + 0x08, 1);
+    write64(interrupter + 0x18, xhci.events.buffer.phys);
+    write64(interrupter + 0x10, erst.phys);
+
+    write32(operational + USBCMD, CMD_RUN);
+    wait_until(|| read32(operational + USBSTS) & STS_HALTED == 0);
+    writeln!(serial(), "xHCI: controller running, {} ports, {} slots", max_ports, max_slots).unwrap();
+
+    for port in 1..=max_ports as u8 {
+        if xhci.gamepad.is_some() {
+            break;
+        }
+        if let Some(speed) = xhci.reset_port(port) {
+            xhci.enumerate(port, speed, physical_offset, frame_allocator);
+        }
+    }
+
+    *XHCI.lock() = Some(xhci);
+}
+
+/// Claims the controller from the firmware through the USB Legacy Support capability.
+fn take_ownership(base: u64, hcc_params1: u32) {
+    let mut offset = ((hcc_params1 >> 16) as u64) << 2;
+    while offset != 0 {
+        let capability = base + offset;
+        let value = read32(capability);
+        if value & 0xFF == 1 {
+            write32(capability, value | 1 << 24);
+            wait_until(|| read32(capability) & (1 << 16) == 0);
+            return;
+        }
+        offset = (((value >> 8) & 0xFF) as u64) << 2;
+        if offset == 0 {
+            return;
+        }
+        offset += capability - base;
+    }
+}
+
+impl Xhci {
+    fn ring_doorbell(&self, slot: u8, target: u8) {
+        write32(self.doorbells + slot as u64 * 4, target as u32);
+    }
+
+    fn acknowledge_events(&self) {
+        // Writing the Event Handler Busy bit clears it
+        write64(self.runtime + 0x38, self.events.dequeue_pointer() | 1 << 3);
+    }
+
+    /// Waits for an event of the given type, discarding unrelated events.
+    fn wait_event(&mut self, kind: u32) -> Option<Trb> {
+        let mut found = None;
+        time::poll_until(TIMEOUT, || {
+            let Some(event) = self.events.pop() else { return false };
+            self.acknowledge_events();
+            found = Some(event).filter(|event| event.kind() == kind);
+            found.is_some()
+        });
+        found
+    }
+
+    fn command(&mut self, parameter: u64, control: u32) -> Option<Trb> {
+        self.commands.push(parameter, 0, control);
+        self.ring_doorbell(0, 0);
+        let event = self.wait_event(TRB_COMMAND_COMPLETION)?;
+        (event.completion_code() == COMPLETION_SUCCESS).then_some(event)
+    }
+
+    /// Resets a connected port and returns its speed once enabled.
+    fn reset_port(&self, port: u8) -> Option<u32> {
+        let portsc = self.operational + PORTSC + (port as u64 - 1) * 0x10;
+        let status = read32(portsc);
+        if status & PORT_CONNECTED == 0 {
+            return None;
+        }
+
+        // USB 3 ports enable themselves; USB 2 ports need a reset first
+        if status & PORT_ENABLED == 0 {
+            write32(portsc, (status & PORT_PRESERVE) | PORT_RESET);
+            wait_until(|| read32(portsc) & PORT_RESET_CHANGE != 0);
+            write32(portsc, (read32(portsc) & PORT_PRESERVE) | PORT_RESET_CHANGE);
+        }
+
+        let status = read32(portsc);
+        (status & PORT_ENABLED != 0).then_some((status >> 10) & 0xF)
+    }
+
+    fn context(&self, base: Dma, index: usize) -> *mut u32 {
+        unsafe { base.virt.add(index * self.context_size) as *mut u32 }
+    }
+
+    /// Issues a control transfer on the default endpoint, using the scratch page for data.
+    /// Returns the number of bytes transferred.
+    fn control(&mut self, slot: u8, ep0: &mut Ring, setup: [u8; 8], data_in: bool) -> Option<usize> {
+        let length = u16::from_le_bytes([setup[6], setup[7]]) as u32;
+        let transfer_type = match (length, data_in) {
+            (0, _) => 0,
+            (_, false) => 2,
+            (_, true) => 3,
+        };
+
+        ep0.push(u64::from_le_bytes(setup), 8, TRB_SETUP << 10 | TRB_IDT | transfer_type << 16);
+        if length > 0 {
+            ep0.push(self.scratch.phys, length, TRB_DATA << 10 | if data_in { TRB_DIR_IN } else { 0 });
+        }
+        let status_in = length == 0 || !data_in;
+        ep0.push(0, 0, TRB_STATUS << 10 | TRB_IOC | if status_in { TRB_DIR_IN } else { 0 });
+        self.ring_doorbell(slot, 1);
+
+        let event = self.wait_event(TRB_TRANSFER_EVENT)?;
+        match event.completion_code() {
+            COMPLETION_SUCCESS | COMPLETION_SHORT_PACKET => Some(length as usize - (event.status & 0xFF_FFFF) as usize),
+            _ => None,
+        }
+    }
+
+    fn get_descriptor(&mut self, slot: u8, ep0: &mut Ring, request_type: u8, kind: u8, index: u16, length: u16) -> Option<&[u8]> {
+        let [len_lo, len_hi] = length.to_le_bytes();
+        let [index_lo, index_hi] = index.to_le_bytes();
+        let setup = [request_type, 0x06, 0, kind, index_lo, index_hi, len_lo, len_hi];
+        let received = self.control(slot, ep0, setup, true)?;
+        Some(unsafe { core::slice::from_raw_parts(self.scratch.virt, received) })
+    }
+
+    fn enumerate(&mut self, port: u8, speed: u32, physical_offset: u64, frame_allocator: &mut impl FrameAllocator<Size4KiB>) {
+        let Some(event) = self.command(0, TRB_ENABLE_SLOT << 10) else {
+            return;
+        };
+        let slot = event.slot();
+
+        let device_context = alloc_dma(physical_offset, frame_allocator);
+        unsafe { (self.dcbaa.virt as *mut u64).add(slot as usize).write_volatile(device_context.phys) };
+
+        let mut ep0 = Ring::new(alloc_dma(physical_offset, frame_allocator));
+        let input = alloc_dma(physical_offset, frame_allocator);
+
+        // Low/full speed devices report their real packet size in the device descriptor
+        let mut max_packet = match speed {
+            3 => 64,
+            4 => 512,
+            _ => 8,
+        };
+
+        unsafe {
+            self.context(input, 0).add(1).write_volatile(0b11);
+            let slot_context = self.context(input, 1);
+            slot_context.write_volatile(speed << 20 | 1 << 27);
+            slot_context.add(1).write_volatile((port as u32) << 16);
+            let ep0_context = self.context(input, 2);
+            ep0_context.add(1).write_volatile(3 << 1 | ENDPOINT_CONTROL << 3 | max_packet << 16);
+            ep0_context.add(2).write_volatile(ep0.buffer.phys as u32 | 1);
+            ep0_context.add(3).write_volatile((ep0.buffer.phys >> 32) as u32);
+        }
+        if self.command(input.phys, TRB_ADDRESS_DEVICE << 10 | (slot as u32) << 24).is_none() {
+            writeln!(serial(), "xHCI: address device failed on port {}", port).unwrap();
+            return;
+        }
+
+        let Some(packet_size) = self.get_descriptor(slot, &mut ep0, 0x80, 1, 0, 8).and_then(|d| d.get(7).copied()) else {
+            return;
+        };
+        if packet_size as u32 != max_packet && packet_size != 0 {
+            max_packet = packet_size as u32;
+            unsafe {
+                self.context(input, 0).add(1).write_volatile(0b10);
+                let ep0_context = self.context(input, 2);
+                ep0_context.add(1).write_volatile(3 << 1 | ENDPOINT_CONTROL << 3 | max_packet << 16);
+            }
+            self.command(input.phys, TRB_EVALUATE_CONTEXT << 10 | (slot as u32) << 24);
+        }
+
+        // A device may send less than asked for; one too short to hold the length is skipped
+        let Some(&[low, high]) = self.get_descriptor(slot, &mut ep0, 0x80, 2, 0, 9).and_then(|d| d.get(2..4)) else {
+            return;
+        };
+        let total = u16::from_le_bytes([low, high]).min(4096);
+        let Some(config) = self.get_descriptor(slot, &mut ep0, 0x80, 2, 0, total) else {
+            return;
+        };
+        let (Some(hid), Some(&configuration)) = (find_hid_interface(config), config.get(5)) else {
+            return;
+        };
+
+        let setup = [0x00, 0x09, configuration, 0, 0, 0, 0, 0];
+        if self.control(slot, &mut ep0, setup, false).is_none() {
+            return;
+        }
+
+        let Some(layout) = self
+            .get_descriptor(slot, &mut ep0, 0x81, 0x22, hid.interface as u16, hid.report_descriptor_len.min(4096))
+            .and_then(ReportLayout::parse)
+        else {
+            return;
+        };
+
+        let endpoint = (hid.endpoint & 0xF) * 2 + 1;
+        let ring = Ring::new(alloc_dma(physical_offset, frame_allocator));
+        // Interrupt intervals are in 125 µs units as a power of two
+        let interval = if speed >= 3 {
+            hid.interval.saturating_sub(1) as u32
+        } else {
+            (hid.interval as u32 * 8).max(1).ilog2()
+        };
+        unsafe {
+            self.context(input, 0).write_volatile(0);
+            self.context(input, 0).add(1).write_volatile(1 | 1 << endpoint);
+            let slot_context = self.context(input, 1);
+            slot_context.write_volatile(speed << 20 | (endpoint as u32) << 27);
+            let context = self.context(input, endpoint as usize + 1);
+            context.write_volatile(interval << 16);
+            context.add(1).write_volatile(3 << 1 | ENDPOINT_INTERRUPT_IN << 3 | (hid.max_packet as u32) << 16);
+            context.add(2).write_volatile(ring.buffer.phys as u32 | 1);
+            context.add(3).write_volatile((ring.buffer.phys >> 32) as u32);
+            context.add(4).write_volatile(hid.max_packet as u32 | (hid.max_packet as u32) << 16);
+        }
+        if self.command(input.phys, TRB_CONFIGURE_ENDPOINT << 10 | (slot as u32) << 24).is_none() {
+            return;
+        }
+
+        writeln!(serial(), "xHCI: gamepad on port {} (slot {})", port, slot).unwrap();
+        let mut gamepad = Gamepad {
+            slot,
+            endpoint,
+            ring,
+            report: alloc_dma(physical_offset, frame_allocator),
+            report_len: hid.max_packet as usize,
+            layout,
+        };
+        self.queue_report(&mut gamepad);
+        self.gamepad = Some(gamepad);
+    }
+
+    fn queue_report(&self, gamepad: &mut Gamepad) {
+        gamepad.ring.push(gamepad.report.phys, gamepad.report_len as u32, TRB_NORMAL << 10 | TRB_IOC);
+        self.ring_doorbell(gamepad.slot, gamepad.endpoint);
+    }
+}
+
+struct HidInterface {
+    interface: u8,
+    endpoint: u8,
+    max_packet: u16,
+    interval: u8,
+    report_descriptor_len: u16,
+}
+
+/// Finds a HID interface that is not a boot keyboard or mouse, with an interrupt IN endpoint.
+fn find_hid_interface(config: &[u8]) -> Option<HidInterface> {
+    let mut found: Option<HidInterface> = None;
+    let mut in_hid = false;
+    let mut i = 0;
+    while i + 2 <= config.len() {
+        let length = config[i] as usize;
+        if length < 2 || i + length > config.len() {
+            break;
+        }
+        let descriptor = &config[i..i + length];
+        match descriptor[1] {
+            // Interface
+            4 if length >= 9 => {
+                if found.as_ref().is_some_and(|hid| hid.endpoint != 0) {
+                    break;
+                }
+                in_hid = descriptor[5] == 3 && descriptor[7] != 1 && descriptor[7] != 2;
+                found = in_hid.then_some(HidInterface {
+                    interface: descriptor[2],
+                    endpoint: 0,
+                    max_packet: 0,
+                    interval: 0,
+                    report_descriptor_len: 0,
+                });
+            }
+            // HID
+            0x21 if in_hid && length >= 9 => {
+                if let Some(hid) = found.as_mut() {
+                    hid.report_descriptor_len = u16::from_le_bytes([descriptor[7], descriptor[8]]);
+                }
+            }
+            // Endpoint, interrupt IN
+            5 if in_hid && length >= 7 && descriptor[2] & 0x80 != 0 && descriptor[3] & 0x3 == 3 => {
+                if let Some(hid) = found.as_mut().filter(|hid| hid.endpoint == 0) {
+                    hid.endpoint = descriptor[2];
+                    hid.max_packet = u16::from_le_bytes([descriptor[4], descriptor[5]]) & 0x7FF;
+                    hid.interval = descriptor[6];
+                }
+            }
+            _ => {}
+        }
+        i += length;
+    }
+    found.filter(|hid| hid.endpoint != 0)
+}
+
+/// Processes completed transfers, calling the handler with each decoded gamepad report.
+/// Called from the timer interrupt.
+pub fn poll(mut handler: impl FnMut(GamepadState)) {
+    let mut guard = XHCI.lock();
+    let Some(xhci) = guard.as_mut() else {
+        return;
+    };
+
+    let mut any = false;
+    while let Some(event) = xhci.events.pop() {
+        any = true;
+        if event.kind() != TRB_TRANSFER_EVENT {
+            continue;
+        }
+        let Some(mut gamepad) = xhci.gamepad.take() else {
+            continue;
+        };
+        if event.slot() == gamepad.slot {
+            if matches!(event.completion_code(), COMPLETION_SUCCESS | COMPLETION_SHORT_PACKET) {
+                let received = gamepad.report_len - (event.status & 0xFF_FFFF) as usize;
+                let report = unsafe { core::slice::from_raw_parts(gamepad.report.virt, received) };
+                if let Some(state) = gamepad.layout.decode(report) {
+                    handler(state);
+                }
+            }
+            xhci.queue_report(&mut gamepad);
+        }
+        xhci.gamepad = Some(gamepad);
+    }
+    if any {
+        xhci.acknowledge_events();
+    }
+}
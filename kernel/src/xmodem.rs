@@ -0,0 +1,176 @@
+use alloc::vec::Vec;
+use core::time::Duration;
+use crate::{time, uart, watchdog};
+
+// XMODEM receiver on the serial console, to push files (sprites, fonts, sounds) from the host
+// into the running kernel. Speaks XMODEM-CRC with 128-byte and 1K blocks, and falls back to the
+// original checksum when the sender does not answer 'C'; from Linux, `sx -k file` (lrzsz) with
+// the console on its standard input and output.
+//
+// The console is raw for the whole transfer (see uart::set_raw): no log output, no shell lines,
+// no serial keys. The sender pads the last block with SUB, which is cut off again.
+
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18;
+const SUB: u8 = 0x1A;
+/// Asks for CRC mode instead of NAK.
+const CRC_MODE: u8 = b'C';
+
+/// Start requests sent before giving up, the first few asking for CRC mode.
+const START_TRIES: usize = 20;
+const CRC_TRIES: usize = 4;
+/// Gap between start requests, and how long the sender may take to start the next block.
+const BLOCK_TIMEOUT: Duration = Duration::from_secs(3);
+/// How long the sender may pause within a block.
+const BYTE_TIMEOUT: Duration = Duration::from_secs(1);
+/// Bad blocks in a row before giving up.
+const MAX_ERRORS: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XmodemError {
+    /// The sender never started, or went quiet.
+    Timeout,
+    /// The sender cancelled (two CANs).
+    Cancelled,
+    /// The file is longer than the limit given to [receive].
+    TooLarge,
+    /// A block out of order, or too many bad blocks.
+    Protocol,
+}
+
+/// Receives a file over the serial console, of at most `max_len` bytes. Blocks until the
+/// transfer ends; call where the rest of the system can wait (the watchdog is petted meanwhile).
+pub fn receive(max_len: usize) -> Result<Vec<u8>, XmodemError> {
+    uart::set_raw(true);
+    // Whatever came before the transfer is not part of it
+    while uart::read_byte().is_some() {}
+    let result = receive_blocks(max_len);
+    if result.is_err() {
+        uart::write_raw(&[CAN, CAN, CAN]);
+    }
+    // Lets the sender finish, so nothing it still sends is taken for lines or keys
+    time::poll_until(BYTE_TIMEOUT, || {
+        watchdog::pet();
+        false
+    });
+    while uart::read_byte().is_some() {}
+    uart::set_raw(false);
+    result
+}
+
+fn receive_blocks(max_len: usize) -> Result<Vec<u8>, XmodemError> {
+    let mut data = Vec::new();
+    let mut expected: u8 = 1;
+    let mut errors = 0;
+
+    // Start by asking for CRC mode, then checksums; the first block header is the answer
+    let (mut header, use_crc) = start()?;
+    loop {
+        let size = match header {
+            SOH => 128,
+            STX => 1024,
+            EOT => {
+                uart::write_raw(&[ACK]);
+                while data.last() == Some(&SUB) {
+                    data.pop();
+                }
+                return Ok(data);
+            }
+            CAN => {
+                if read_byte(BYTE_TIMEOUT) == Some(CAN) {
+                    return Err(XmodemError::Cancelled);
+                }
+                0
+            }
+            _ => 0,
+        };
+
+        let block = if size > 0 { read_block(size, use_crc) } else { None };
+        match block {
+            Some((number, payload)) if number == expected => {
+                if data.len() + payload.len() > max_len {
+                    return Err(XmodemError::TooLarge);
+                }
+                data.extend_from_slice(&payload);
+                expected = expected.wrapping_add(1);
+                errors = 0;
+                uart::write_raw(&[ACK]);
+            }
+            // Our ACK was lost and the sender repeated the block
+            Some((number, _)) if number == expected.wrapping_sub(1) => uart::write_raw(&[ACK]),
+            Some(_) => return Err(XmodemError::Protocol),
+            None => {
+                errors += 1;
+                if errors == MAX_ERRORS {
+                    return Err(XmodemError::Protocol);
+                }
+                purge();
+                uart::write_raw(&[NAK]);
+            }
+        }
+
+        header = read_byte(BLOCK_TIMEOUT).ok_or(XmodemError::Timeout)?;
+    }
+}
+
+/// Sends start requests until the sender answers, returning its first byte and whether the
+/// transfer uses CRCs.
+fn start() -> Result<(u8, bool), XmodemError> {
+    for attempt in 0..START_TRIES {
+        let use_crc = attempt < CRC_TRIES;
+        uart::write_raw(&[if use_crc { CRC_MODE } else { NAK }]);
+        if let Some(byte) = read_byte(BLOCK_TIMEOUT) {
+            return Ok((byte, use_crc));
+        }
+    }
+    Err(XmodemError::Timeout)
+}
+
+/// The rest of a block after its header: the block number and the data, if it arrived whole and
+/// its check matches.
+fn read_block(size: usize, use_crc: bool) -> Option<(u8, Vec<u8>)> {
+    let number = read_byte(BYTE_TIMEOUT)?;
+    let complement = read_byte(BYTE_TIMEOUT)?;
+    let mut payload = Vec::with_capacity(size);
+    for _ in 0..size {
+        payload.push(read_byte(BYTE_TIMEOUT)?);
+    }
+    let valid = if use_crc {
+        let crc = u16::from_be_bytes([read_byte(BYTE_TIMEOUT)?, read_byte(BYTE_TIMEOUT)?]);
+        crc == crc16(&payload)
+    } else {
+        read_byte(BYTE_TIMEOUT)? == payload.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+    };
+    (valid && number == !complement).then_some((number, payload))
+}
+
+/// Drops the rest of a bad block, once the line has been quiet for [BYTE_TIMEOUT].
+fn purge() {
+    while read_byte(BYTE_TIMEOUT).is_some() {}
+}
+
+fn read_byte(timeout: Duration) -> Option<u8> {
+    let mut byte = None;
+    time::poll_until(timeout, || {
+        // Nothing else runs until the transfer is over
+        watchdog::pet();
+        byte = uart::read_byte();
+        byte.is_some()
+    });
+    byte
+}
+
+/// CRC-16/XMODEM
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0, |crc, &byte| {
+        let mut crc = crc ^ ((byte as u16) << 8);
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+        crc
+    })
+}
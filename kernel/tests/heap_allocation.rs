@@ -0,0 +1,92 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+// The heap: slab-sized and large allocations, reuse of freed memory, and growth past the
+// initial heap.
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use bootloader_api::{entry_point, BootInfo};
+use kernel::allocator::{self, HEAP_SIZE};
+use kernel::HandlerTable;
+
+entry_point!(main, config = &kernel::testing::BOOTLOADER_CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    let lapic = kernel::testing::init(boot_info);
+    HandlerTable::new().cpu_loop(run).start(lapic)
+}
+
+fn run() -> ! {
+    test_main();
+    kernel::hlt_loop()
+}
+
+#[test_case]
+fn simple_allocation() {
+    let a = Box::new(41);
+    let b = Box::new(13);
+    assert_eq!(*a, 41);
+    assert_eq!(*b, 13);
+}
+
+#[test_case]
+fn large_vec() {
+    let n = 1000;
+    let vec: Vec<u64> = (0..n).collect();
+    assert_eq!(vec.iter().sum::<u64>(), (n - 1) * n / 2);
+}
+
+#[test_case]
+fn freed_memory_is_reused() {
+    // Together far more than the heap holds, one at a time
+    for i in 0..HEAP_SIZE {
+        let x = Box::new(i);
+        assert_eq!(*x, i);
+    }
+}
+
+#[test_case]
+fn long_lived_allocation_survives() {
+    let long_lived = Box::new(1);
+    for i in 0..HEAP_SIZE {
+        let x = Box::new(i);
+        assert_eq!(*x, i);
+    }
+    assert_eq!(*long_lived, 1);
+}
+
+#[test_case]
+fn allocations_are_aligned() {
+    for align in [8, 64, 512, 4096] {
+        let layout = core::alloc::Layout::from_size_align(align * 3, align).unwrap();
+        let ptr = unsafe { alloc::alloc::alloc(layout) };
+        assert!(!ptr.is_null());
+        assert_eq!(ptr as usize % align, 0);
+        unsafe { alloc::alloc::dealloc(ptr, layout) };
+    }
+}
+
+#[test_case]
+fn heap_grows_past_its_initial_size() {
+    let big: Vec<u8> = alloc::vec![0xAB; HEAP_SIZE * 4];
+    assert!(big.iter().all(|&byte| byte == 0xAB));
+    assert!(allocator::stats().used >= HEAP_SIZE * 4);
+}
+
+#[test_case]
+fn stats_count_allocations() {
+    let before = allocator::stats();
+    let x = Box::new([0u8; 100]);
+    let during = allocator::stats();
+    drop(x);
+    let after = allocator::stats();
+    assert_eq!(during.allocations, before.allocations + 1);
+    assert_eq!(after.deallocations, before.deallocations + 1);
+    assert_eq!(after.used, before.used);
+}
@@ -0,0 +1,82 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+// Interrupt delivery: the LAPIC timer at the rate asked for, the timer handler through deferred
+// work, and vectors claimed with interrupts::register_irq.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+use core::time::Duration;
+use bootloader_api::{entry_point, BootInfo};
+use kernel::{deferred, interrupts, time, HandlerTable};
+
+entry_point!(main, config = &kernel::testing::BOOTLOADER_CONFIG);
+
+const TICK_HZ: u32 = 100;
+const TIMER_VECTOR: u8 = 0x20;
+/// A device vector no driver claims in a test kernel.
+const TEST_VECTOR: u8 = 0x3E;
+
+static TICKS: AtomicU32 = AtomicU32::new(0);
+static TEST_IRQS: AtomicU32 = AtomicU32::new(0);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    let lapic = kernel::testing::init(boot_info);
+    HandlerTable::new().timer(tick).tick_hz(TICK_HZ).cpu_loop(run).start(lapic)
+}
+
+fn run() -> ! {
+    test_main();
+    kernel::hlt_loop()
+}
+
+fn tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+#[test_case]
+fn interrupts_are_enabled() {
+    assert!(x86_64::instructions::interrupts::are_enabled());
+}
+
+#[test_case]
+fn timer_fires_at_the_tick_rate() {
+    let before = interrupts::interrupt_count(TIMER_VECTOR);
+    let start = time::Instant::now();
+    time::poll_until(Duration::from_millis(500), || false);
+    let elapsed = start.elapsed().as_millis() as u64;
+    let count = interrupts::interrupt_count(TIMER_VECTOR) - before;
+    let expected = TICK_HZ as u64 * elapsed / 1000;
+    // Generous: QEMU's timer may lag when the host is busy
+    assert!(count >= expected / 2 && count <= expected * 2, "{} timer interrupts in {} ms", count, elapsed);
+}
+
+#[test_case]
+fn timer_handler_runs_as_deferred_work() {
+    let before = TICKS.load(Ordering::Relaxed);
+    let ran = time::poll_until(Duration::from_secs(1), || {
+        deferred::run_pending();
+        TICKS.load(Ordering::Relaxed) >= before + 3
+    });
+    assert!(ran);
+}
+
+fn test_irq() {
+    TEST_IRQS.fetch_add(1, Ordering::Relaxed);
+}
+
+#[test_case]
+fn registered_vector_is_delivered() {
+    assert!(interrupts::register_irq(TEST_VECTOR, test_irq));
+    // Claimed once only
+    assert!(!interrupts::register_irq(TEST_VECTOR, test_irq));
+    unsafe { core::arch::asm!("int 0x3E") };
+    assert_eq!(TEST_IRQS.load(Ordering::Relaxed), 1);
+    assert_eq!(interrupts::interrupt_count(TEST_VECTOR), 1);
+
+    interrupts::unregister_irq(TEST_VECTOR);
+    unsafe { core::arch::asm!("int 0x3E") };
+    assert_eq!(TEST_IRQS.load(Ordering::Relaxed), 1);
+}
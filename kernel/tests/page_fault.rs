@@ -0,0 +1,92 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+// Page fault handling: faults on unmapped pages reach the resolver set with
+// page_fault::set_resolver, which maps the page on demand, and the access is retried. Fatal
+// faults end the kernel, so they cannot be tested here.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+use bootloader_api::{entry_point, BootInfo};
+use kernel::page_fault::{self, Access, PageFault};
+use kernel::{memory, HandlerTable};
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+entry_point!(main, config = &kernel::testing::BOOTLOADER_CONFIG);
+
+/// Pages mapped on demand, in address space nothing else uses.
+const WINDOW_START: u64 = 0x40_0000_0000;
+const WINDOW_PAGES: u64 = 16;
+
+static FAULTS: AtomicU32 = AtomicU32::new(0);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    let lapic = kernel::testing::init(boot_info);
+    page_fault::set_resolver(map_on_demand);
+    HandlerTable::new().cpu_loop(run).start(lapic)
+}
+
+fn run() -> ! {
+    test_main();
+    kernel::hlt_loop()
+}
+
+/// Maps the faulting page if it is in the window.
+fn map_on_demand(fault: &PageFault) -> bool {
+    let address = fault.address.as_u64();
+    if !(WINDOW_START..WINDOW_START + WINDOW_PAGES * 4096).contains(&address) {
+        return false;
+    }
+    FAULTS.fetch_add(1, Ordering::Relaxed);
+    let page = VirtAddr::new(address).align_down(4096u64);
+    memory::alloc_region(page, 4096, PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE).is_ok()
+}
+
+fn window_page(index: u64) -> *mut u64 {
+    (WINDOW_START + index * 4096) as *mut u64
+}
+
+#[test_case]
+fn window_starts_unmapped() {
+    for index in 0..WINDOW_PAGES {
+        assert!(!memory::is_mapped(VirtAddr::from_ptr(window_page(index))));
+    }
+}
+
+#[test_case]
+fn read_maps_a_zeroed_page() {
+    let before = FAULTS.load(Ordering::Relaxed);
+    let value = unsafe { window_page(0).read_volatile() };
+    assert_eq!(value, 0);
+    assert_eq!(FAULTS.load(Ordering::Relaxed), before + 1);
+    assert!(memory::translate(VirtAddr::from_ptr(window_page(0))).is_some());
+}
+
+#[test_case]
+fn write_maps_a_page_once() {
+    let before = FAULTS.load(Ordering::Relaxed);
+    let page = window_page(1);
+    unsafe {
+        page.write_volatile(0xDEAD_BEEF);
+        page.add(1).write_volatile(42);
+        assert_eq!(page.read_volatile(), 0xDEAD_BEEF);
+        assert_eq!(page.add(1).read_volatile(), 42);
+    }
+    // The second access found the page mapped
+    assert_eq!(FAULTS.load(Ordering::Relaxed), before + 1);
+}
+
+#[test_case]
+fn fault_is_decoded() {
+    let fault = PageFault {
+        address: VirtAddr::new(WINDOW_START),
+        instruction_pointer: VirtAddr::new(0),
+        error_code: x86_64::structures::idt::PageFaultErrorCode::CAUSED_BY_WRITE,
+    };
+    assert_eq!(fault.access(), Access::Write);
+    assert!(!fault.is_protection_violation());
+    assert!(fault.is_recoverable());
+}
@@ -19,8 +19,77 @@ fn main() {
     // set kernel image
     cmd.arg("-drive").arg(format!("format=raw,file={uefi_path}"));
     cmd.arg("-serial").arg("stdio");
+
+    // display: the standard VGA card, or with PONG_DISPLAY=virtio a virtio-gpu, whose resolution
+    // the kernel can change from the settings screen
+    if std::env::var("PONG_DISPLAY").as_deref() == Ok("virtio") {
+        cmd.arg("-vga").arg("none").arg("-device").arg("virtio-vga");
+    }
+    // a headless kernel plays on the serial console, so no window
+    if cfg!(feature = "headless") {
+        cmd.arg("-display").arg("none");
+    }
+
+    // USB controller for gamepads, pass one through with e.g.
+    // `-device usb-host,vendorid=0x045e,productid=0x028e`
+    cmd.arg("-device").arg("qemu-xhci");
+
+    // sound card for the game's effects and music
+    cmd.arg("-device").arg("AC97");
+
+    // network card, on QEMU's user mode network unless PONG_NETDEV says otherwise. For a network
+    // game between two instances, put both on one segment and give each its own MAC, e.g.
+    // PONG_NETDEV=socket,mcast=230.0.0.1:1234 PONG_MAC=52:54:00:12:34:57
+    let netdev = std::env::var("PONG_NETDEV").unwrap_or_else(|_| "user".to_string());
+    let mac = std::env::var("PONG_MAC").unwrap_or_else(|_| "52:54:00:12:34:56".to_string());
+    cmd.arg("-netdev").arg(format!("{netdev},id=net0"));
+    cmd.arg("-device").arg(format!("virtio-net-pci,netdev=net0,mac={mac}"));
+
+    // second serial port for the serial link game, only when PONG_LINK names a chardev. One
+    // instance listens, the other connects:
+    // PONG_LINK=tcp::4555,server=on,wait=off and PONG_LINK=tcp:localhost:4555
+    if let Ok(link) = std::env::var("PONG_LINK") {
+        cmd.arg("-serial").arg(link);
+    }
+
+    // virtio-console carrying the same console as the serial port, when PONG_CONSOLE names a
+    // chardev, e.g. PONG_CONSOLE=socket,path=/tmp/pong-console,server=on,wait=off for
+    // `socat - UNIX-CONNECT:/tmp/pong-console`
+    if let Ok(console) = std::env::var("PONG_CONSOLE") {
+        cmd.arg("-chardev").arg(format!("{console},id=console0"));
+        cmd.arg("-device").arg("virtio-serial-pci").arg("-device").arg("virtconsole,chardev=console0");
+    }
+
+    // disk for the kernel's virtio-blk driver: PONG_DISK, or a blank 64 MiB image made on first
+    // run (the smallest size FAT32 comfortably fits), which the kernel formats and which keeps
+    // what is written to it from one run to the next
+    let disk = std::env::var("PONG_DISK").unwrap_or_else(|_| "target/pong-disk.img".to_string());
+    if !std::path::Path::new(&disk).exists() {
+        std::fs::File::create(&disk).and_then(|file| file.set_len(64 * 1024 * 1024)).expect("failed to create the disk image");
+    }
+    cmd.arg("-drive").arg(format!("if=none,format=raw,id=disk0,file={disk}"));
+    // attached as virtio-blk, or as an NVMe SSD or a SATA disk with PONG_DISK_DEVICE=nvme or ahci
+    match std::env::var("PONG_DISK_DEVICE").as_deref() {
+        Ok("nvme") => cmd.arg("-device").arg("nvme,serial=pong,drive=disk0"),
+        Ok("ahci") => cmd.arg("-device").arg("ahci,id=ahci").arg("-device").arg("ide-hd,drive=disk0,bus=ahci.0"),
+        _ => cmd.arg("-device").arg("virtio-blk-pci,drive=disk0"),
+    };
+
+    // lets the kernel exit QEMU when ACPI shutdown is unavailable, and report success or failure
+    // (kernel::qemu)
+    cmd.arg("-device").arg("isa-debug-exit,iobase=0xf4,iosize=0x04");
     
     // launch qemu and wait until it terminates
     let mut child = cmd.spawn().unwrap();
-    child.wait().unwrap();
+    let status = child.wait().unwrap();
+    std::process::exit(exit_code(status.code()));
+}
+
+/// The runner's exit code for how QEMU ended: 0 for a normal end or the kernel reporting success
+/// through isa-debug-exit (status 33), 1 for the kernel reporting failure (35) or anything else.
+fn exit_code(status: Option<i32>) -> i32 {
+    match status {
+        Some(0 | 33) => 0,
+        _ => 1,
+    }
 }
\ No newline at end of file
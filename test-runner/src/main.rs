@@ -0,0 +1,55 @@
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode};
+use std::time::{Duration, Instant};
+use ovmf_prebuilt::{Arch, FileType, Prebuilt, Source};
+
+// Cargo runner for kernel test binaries (.cargo/config.toml points x86_64-unknown-none at it), so
+// `cargo test -p kernel --target x86_64-unknown-none` boots each test kernel in QEMU. The kernel
+// goes on a UEFI disk image next to it and runs headless, with its serial port on standard
+// output. It ends QEMU through isa-debug-exit with the result (kernel::testing).
+
+/// QEMU's exit status when the kernel reports success: (0x10 << 1) | 1.
+const SUCCESS: i32 = 33;
+/// A test kernel that takes longer than this is taken to be hung.
+const TIMEOUT: Duration = Duration::from_secs(120);
+
+fn main() -> ExitCode {
+    let kernel = PathBuf::from(std::env::args().nth(1).expect("usage: test-runner <kernel> [args]"));
+    let image = kernel.with_extension("img");
+    bootloader::UefiBoot::new(&kernel).create_disk_image(&image).expect("failed to create the disk image");
+
+    // Same firmware as the game's runner
+    let edk = Source {
+        tag: "edk2-stable202211-r1",
+        sha256: "b085cfe18fd674bf70a31af1dc3e991bcd25cb882981c6d3523d81260f1e0d12",
+    };
+    let ovmf_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../target/ovmf");
+    let prebuilt = Prebuilt::fetch(edk, ovmf_dir).expect("failed to fetch prebuilt");
+
+    let mut cmd = Command::new("qemu-system-x86_64");
+    cmd.arg("-drive").arg(format!("if=pflash,format=raw,unit=0,readonly=on,file={}", prebuilt.get_file(Arch::X64, FileType::Code).display()));
+    // A copy of the variables, which the firmware writes to, per test kernel
+    let vars = kernel.with_extension("vars.fd");
+    std::fs::copy(prebuilt.get_file(Arch::X64, FileType::Vars), &vars).expect("failed to copy the UEFI variables");
+    cmd.arg("-drive").arg(format!("if=pflash,format=raw,unit=1,file={}", vars.display()));
+    cmd.arg("-drive").arg(format!("format=raw,file={}", image.display()));
+    cmd.arg("-serial").arg("stdio");
+    cmd.arg("-display").arg("none");
+    cmd.arg("-device").arg("isa-debug-exit,iobase=0xf4,iosize=0x04");
+    // A triple fault ends the run instead of rebooting into the test again
+    cmd.arg("-no-reboot");
+
+    let mut child = cmd.spawn().expect("failed to start QEMU");
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        if let Some(status) = child.try_wait().expect("failed to wait for QEMU") {
+            return if status.code() == Some(SUCCESS) { ExitCode::SUCCESS } else { ExitCode::FAILURE };
+        }
+        if Instant::now() >= deadline {
+            eprintln!("{} timed out after {} s", kernel.display(), TIMEOUT.as_secs());
+            let _ = child.kill();
+            return ExitCode::FAILURE;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
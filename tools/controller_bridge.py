@@ -0,0 +1,134 @@
+#!/usr/bin/env python3
+"""Streams the host's gamepads and keyboard into the game over its serial console, by the
+controller bridge protocol (see kernel/src/bridge.rs).
+
+    PONG_CONSOLE=socket,path=/tmp/pong-console,server=on,wait=off cargo run
+    tools/controller_bridge.py /tmp/pong-console                     # every /dev/input/js*
+    tools/controller_bridge.py --pad /dev/input/js1 --keys /tmp/pong-console
+
+The console is a Unix socket (the virtio-console above), or a serial device such as a pty or a
+USB adapter. Gamepads are read with the Linux joystick API and numbered from 0 in the order given:
+the first plays as Player 1, the second as Player 2. With --keys, keys typed in this terminal are
+sent as well, Enter and the arrows included; Ctrl-C stops.
+"""
+
+import argparse
+import glob
+import os
+import select
+import socket
+import stat
+import struct
+import sys
+import termios
+import tty
+
+SYNC = 0xFE
+HELLO, AXIS, BUTTON, KEY = range(4)
+KEYBOARD, GAMEPAD = range(2)
+MAX_DEVICES = 4
+
+# struct js_event from linux/joystick.h
+JS_EVENT = struct.Struct("IhBB")
+JS_EVENT_BUTTON = 0x01
+JS_EVENT_AXIS = 0x02
+JS_EVENT_INIT = 0x80
+
+ARROWS = {b"A": 0x80, b"B": 0x81, b"D": 0x82, b"C": 0x83}
+
+
+def packet(kind, device, index, value):
+    body = bytes([kind, device, index, value & 0xFF])
+    return bytes([SYNC]) + body + bytes([sum(body) & 0xFF])
+
+
+def open_console(path):
+    """A function that sends bytes to the console at `path`."""
+    if stat.S_ISSOCK(os.stat(path).st_mode):
+        sock = socket.socket(socket.AF_UNIX, socket.SOCK_STREAM)
+        sock.connect(path)
+        return sock.sendall
+    fd = os.open(path, os.O_WRONLY | os.O_NOCTTY)
+    return lambda data: os.write(fd, data)
+
+
+def pad_packets(device, data):
+    """The packets for the joystick events in `data`."""
+    for offset in range(0, len(data) - JS_EVENT.size + 1, JS_EVENT.size):
+        _, value, kind, number = JS_EVENT.unpack_from(data, offset)
+        kind &= ~JS_EVENT_INIT
+        if kind == JS_EVENT_BUTTON and number < 16:
+            yield packet(BUTTON, device, number, 1 if value else 0)
+        elif kind == JS_EVENT_AXIS and number < 2:
+            yield packet(AXIS, device, number, value >> 8)
+
+
+def key_packets(device, data):
+    """The packets for the keys typed in `data`: a press and a release each."""
+    keys = []
+    i = 0
+    while i < len(data):
+        if data[i:i + 2] == b"\x1b[" and data[i + 2:i + 3] in ARROWS:
+            keys.append(ARROWS[data[i + 2:i + 3]])
+            i += 3
+            continue
+        byte = data[i]
+        if byte in (0x0A, 0x0D):
+            keys.append(0x0D)
+        elif 0x20 <= byte <= 0x7E:
+            keys.append(byte)
+        i += 1
+    for key in keys:
+        yield packet(KEY, device, key, 1)
+        yield packet(KEY, device, key, 0)
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__, formatter_class=argparse.RawDescriptionHelpFormatter)
+    parser.add_argument("console", help="the game's serial console: a Unix socket or a serial device")
+    parser.add_argument("--pad", action="append", metavar="DEVICE", help="a joystick device, all of /dev/input/js* by default")
+    parser.add_argument("--keys", action="store_true", help="send the keys typed in this terminal too")
+    args = parser.parse_args()
+
+    pads = args.pad if args.pad is not None else sorted(glob.glob("/dev/input/js*"))
+    if len(pads) + args.keys > MAX_DEVICES:
+        sys.stderr.write(f"at most {MAX_DEVICES} devices\n")
+        return 1
+    send = open_console(args.console)
+
+    devices = {}
+    for number, path in enumerate(pads):
+        devices[os.open(path, os.O_RDONLY | os.O_NONBLOCK)] = (number, pad_packets)
+        send(packet(HELLO, number, GAMEPAD, 0))
+        sys.stderr.write(f"device {number}: {path}\n")
+    saved = None
+    if args.keys:
+        number = len(pads)
+        devices[sys.stdin.fileno()] = (number, key_packets)
+        send(packet(HELLO, number, KEYBOARD, 0))
+        sys.stderr.write(f"device {number}: this terminal's keys\n")
+        saved = termios.tcgetattr(sys.stdin)
+        tty.setcbreak(sys.stdin)
+    if not devices:
+        sys.stderr.write("no gamepad found, and no --keys\n")
+        return 1
+
+    try:
+        while True:
+            ready, _, _ = select.select(list(devices), [], [])
+            for fd in ready:
+                number, packets = devices[fd]
+                data = os.read(fd, 1024)
+                if not data:
+                    return 0
+                for bytes_out in packets(number, data):
+                    send(bytes_out)
+    except KeyboardInterrupt:
+        return 0
+    finally:
+        if saved is not None:
+            termios.tcsetattr(sys.stdin, termios.TCSADRAIN, saved)
+
+
+if __name__ == "__main__":
+    sys.exit(main())
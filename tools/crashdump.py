@@ -0,0 +1,142 @@
+#!/usr/bin/env python3
+"""Pretty-prints and archives the crash dumps in a serial log (see kernel/src/crashdump.rs).
+
+    cargo run 2>&1 | tee serial.log
+    tools/crashdump.py serial.log                  # show every dump in the log
+    tools/crashdump.py --archive crashes serial.log
+
+With --archive, each dump is also saved whole to the directory, named after the time it was
+archived and its reason. Reads standard input when no file is given.
+"""
+
+import argparse
+import datetime
+import pathlib
+import sys
+
+BEGIN = "-----BEGIN CRASH DUMP-----"
+END = "-----END CRASH DUMP-----"
+REGISTER_ORDER = [
+    "rax", "rbx", "rcx", "rdx", "rsi", "rdi", "rbp", "rsp", "r8", "r9", "r10", "r11",
+    "r12", "r13", "r14", "r15", "rip", "rflags", "cs", "ss", "cr2", "cr3",
+]
+
+
+def find_dumps(lines):
+    """Yields the record lines of each complete dump."""
+    dump = None
+    for line in lines:
+        line = line.rstrip("\r\n")
+        # The serial console may have put something in front of the marker on the same line
+        if line.endswith(BEGIN):
+            dump = []
+        elif line == END and dump is not None:
+            yield dump
+            dump = None
+        elif dump is not None:
+            dump.append(line)
+
+
+def parse(records):
+    dump = {"registers": {}, "frames": [], "stack": [], "events": [], "log": []}
+    for record in records:
+        keyword, _, rest = record.partition(" ")
+        if keyword == "reg":
+            name, value = rest.split()
+            dump["registers"][name] = int(value, 16)
+        elif keyword == "frame":
+            fields = rest.split(" ", 2)
+            symbol = fields[2] if len(fields) > 2 else "?"
+            dump["frames"].append((int(fields[0]), int(fields[1], 16), symbol))
+        elif keyword == "stack":
+            address, data = rest.split()
+            dump["stack"].append((int(address, 16), bytes.fromhex(data)))
+        elif keyword == "heap":
+            used, free, peak, allocations, frees = (int(field) for field in rest.split())
+            dump["heap"] = dict(used=used, free=free, peak=peak, allocations=allocations, frees=frees)
+        elif keyword == "event":
+            time_ns, kind, repeats, *text = rest.split(" ", 3)
+            dump["events"].append((int(time_ns), kind, int(repeats), text[0] if text else ""))
+        elif keyword == "log":
+            dump["log"].append(rest)
+        elif keyword in ("version", "uptime_ns", "cpu"):
+            dump[keyword] = int(rest)
+        elif keyword in ("reason", "message"):
+            dump[keyword] = rest
+        # Anything else is from a later version
+    return dump
+
+
+def pretty(dump, out):
+    uptime = dump.get("uptime_ns", 0) / 1e9
+    out.write(f"{dump.get('reason', '?')} on CPU {dump.get('cpu', '?')} at {uptime:.3f} s\n")
+    out.write(f"  {dump.get('message', '')}\n\n")
+
+    registers = dump["registers"]
+    names = [name for name in REGISTER_ORDER if name in registers]
+    names += sorted(set(registers) - set(names))
+    for i in range(0, len(names), 2):
+        pair = names[i:i + 2]
+        out.write("  " + "  ".join(f"{name.upper():<6} {registers[name]:#018x}" for name in pair) + "\n")
+
+    if dump["frames"]:
+        out.write("\nbacktrace:\n")
+        for index, address, symbol in dump["frames"]:
+            out.write(f"  #{index:<2} {address:#018x} {symbol}\n")
+
+    if "heap" in dump:
+        heap = dump["heap"]
+        out.write(f"\nheap: {heap['used']} B used, {heap['free']} B free, {heap['peak']} B peak, "
+                  f"{heap['allocations']} allocations, {heap['frees']} frees\n")
+
+    if dump["stack"]:
+        rsp = registers.get("rsp")
+        out.write("\nstack:\n")
+        for address, data in dump["stack"]:
+            for offset in range(0, len(data), 8):
+                value = int.from_bytes(data[offset:offset + 8], "little")
+                here = address + offset
+                marker = " <- rsp" if rsp is not None and here <= rsp < here + 8 else ""
+                out.write(f"  {here:#018x}  {value:#018x}{marker}\n")
+
+    if dump["events"]:
+        out.write("\nrecent events:\n")
+        for time_ns, kind, repeats, text in dump["events"]:
+            count = f" (x{repeats})" if repeats > 1 else ""
+            out.write(f"  {time_ns / 1e9:10.3f} s  {kind:<5} {text}{count}\n")
+
+    if dump["log"]:
+        out.write("\nlast output:\n")
+        for line in dump["log"]:
+            out.write(f"  {line}\n")
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__, formatter_class=argparse.RawDescriptionHelpFormatter)
+    parser.add_argument("log", nargs="?", help="serial log to read, standard input by default")
+    parser.add_argument("--archive", metavar="DIR", help="also save each dump to DIR")
+    args = parser.parse_args()
+
+    source = open(args.log, errors="replace") if args.log else sys.stdin
+    count = 0
+    for records in find_dumps(source):
+        dump = parse(records)
+        if count:
+            sys.stdout.write("\n" + "=" * 72 + "\n\n")
+        pretty(dump, sys.stdout)
+        if args.archive:
+            directory = pathlib.Path(args.archive)
+            directory.mkdir(parents=True, exist_ok=True)
+            stamp = datetime.datetime.now().strftime("%Y%m%d-%H%M%S")
+            path = directory / f"crash-{stamp}-{count}-{dump.get('reason', 'unknown')}.txt"
+            path.write_text("\n".join([BEGIN, *records, END]) + "\n")
+            sys.stdout.write(f"\nsaved to {path}\n")
+        count += 1
+    if count == 0:
+        sys.stderr.write("no crash dump found\n")
+        return 1
+    return 0
+
+
+if __name__ == "__main__":
+    sys.exit(main())
@@ -0,0 +1,167 @@
+#!/usr/bin/env python3
+"""Draws a match live in this terminal from the game's spectator broadcast on its serial link
+(see kernel/src/spectator.rs), and records it.
+
+    PONG_LINK=tcp::4556,server=on,wait=off PONG_OPTIONS=spectate=on cargo run
+    tools/spectator.py localhost:4556                         # watch
+    tools/spectator.py --record match.bin localhost:4556      # watch and keep the stream
+    tools/spectator.py match.bin                              # watch it again
+    tools/spectator.py --log match.bin > match.txt            # one line a tick, for diffing
+
+The source is host:port, a TCP server such as QEMU's chardev above, or a file or serial device.
+A recorded file plays back at --rate ticks a second. With --log, each tick is printed as a line
+with the state's checksum instead of drawn, so the logs of the two machines in a network game
+can be diffed to find the first tick they disagree on.
+"""
+
+import argparse
+import os
+import socket
+import stat
+import struct
+import sys
+import time
+
+FLAG = 0x7E
+ESCAPE = 0x7D
+ESCAPE_XOR = 0x20
+MAGIC = b"PS"
+FRAME = struct.Struct("<2sBIHHhhhhHBBBBI")
+MODES = [
+    "Menu", "Settings", "Controls", "OnePlayer", "TwoPlayer", "GameOver", "MemoryMap", "Program",
+    "NetworkLobby", "Network", "Benchmark", "Launched", "Sound", "Profiles", "Accessibility",
+    "LatencyTest",
+]
+# The game's paddles, 10 pixels in from the sides, and its ball
+PADDLE_INSET = 10
+BALL_SIZE = 13
+COLUMNS, ROWS = 80, 24
+
+
+def crc16(data):
+    """CRC-16/CCITT-FALSE, as kernel/src/link.rs checks its frames."""
+    crc = 0xFFFF
+    for byte in data:
+        crc ^= byte << 8
+        for _ in range(8):
+            crc = ((crc << 1) ^ 0x1021 if crc & 0x8000 else crc << 1) & 0xFFFF
+    return crc
+
+
+def payloads(chunks):
+    """Yields the payload of every good frame in the byte strings `chunks`, dropping the rest."""
+    frame = None
+    escaped = False
+    for chunk in chunks:
+        for byte in chunk:
+            if byte == FLAG:
+                if frame and len(frame) > 2 and crc16(frame[:-2]) == int.from_bytes(frame[-2:], "big"):
+                    yield bytes(frame[:-2])
+                frame, escaped = bytearray(), False
+            elif frame is None:
+                continue
+            elif byte == ESCAPE:
+                escaped = True
+            else:
+                frame.append(byte ^ ESCAPE_XOR if escaped else byte)
+                escaped = False
+
+
+def decode(payload):
+    """The state in a spectator frame as a dict, or None if it is not one."""
+    if len(payload) != FRAME.size or not payload.startswith(MAGIC):
+        return None
+    (_, mode, tick, width, height, ball_x, ball_y, paddle1, paddle2, paddle_height,
+     points1, points2, games1, games2, checksum) = FRAME.unpack(payload)
+    return {
+        "mode": MODES[mode] if mode < len(MODES) else str(mode), "tick": tick,
+        "width": width, "height": height, "ball": (ball_x, ball_y),
+        "paddles": (paddle1, paddle2), "paddle_height": paddle_height,
+        "points": (points1, points2), "games": (games1, games2), "checksum": checksum,
+    }
+
+
+def draw(state):
+    """The field as text, COLUMNS by ROWS, with the score above it."""
+    width, height = max(state["width"], 1), max(state["height"], 1)
+    cells = [[" "] * COLUMNS for _ in range(ROWS)]
+
+    def put(x, y, char):
+        column, row = x * COLUMNS // width, y * ROWS // height
+        if 0 <= column < COLUMNS and 0 <= row < ROWS:
+            cells[row][column] = char
+
+    for x, top in ((PADDLE_INSET, state["paddles"][0]), (width - PADDLE_INSET, state["paddles"][1])):
+        for y in range(top, top + state["paddle_height"], max(height // ROWS, 1)):
+            put(min(x, width - 1), y, "█")
+    ball_x, ball_y = state["ball"]
+    put(ball_x + BALL_SIZE // 2, ball_y + BALL_SIZE // 2, "●")
+
+    (points1, points2), (games1, games2) = state["points"], state["games"]
+    header = f"{state['mode']}  tick {state['tick']}  {points1} - {points2}  games {games1} - {games2}"
+    border = "+" + "-" * COLUMNS + "+"
+    rows = ["|" + "".join(row) + "|" for row in cells]
+    return "\n".join([header.ljust(COLUMNS + 2), border, *rows, border])
+
+
+def log_line(state):
+    (ball_x, ball_y), (paddle1, paddle2) = state["ball"], state["paddles"]
+    return (f"{state['tick']} {state['mode']} ball {ball_x} {ball_y} paddles {paddle1} {paddle2} "
+            f"score {state['points'][0]}-{state['points'][1]} checksum {state['checksum']:08x}")
+
+
+def open_source(source):
+    """The byte strings read from `source`, and whether it is a recording to pace."""
+    if os.path.exists(source):
+        is_file = stat.S_ISREG(os.stat(source).st_mode)
+        fd = os.open(source, os.O_RDONLY | os.O_NOCTTY)
+        return iter(lambda: os.read(fd, 4096), b""), is_file
+    host, _, port = source.rpartition(":")
+    sock = socket.create_connection((host or "localhost", int(port)))
+    return iter(lambda: sock.recv(4096), b""), False
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__, formatter_class=argparse.RawDescriptionHelpFormatter)
+    parser.add_argument("source", help="host:port, or a recording or serial device")
+    parser.add_argument("--record", metavar="FILE", help="also save what is received, to play back later")
+    parser.add_argument("--log", action="store_true", help="print a line a tick instead of drawing")
+    parser.add_argument("--rate", type=float, default=30.0, help="ticks a second to play a recording at (30)")
+    args = parser.parse_args()
+
+    chunks, paced = open_source(args.source)
+    if args.record:
+        record = open(args.record, "ab")
+
+        def recorded(chunks):
+            for chunk in chunks:
+                record.write(chunk)
+                record.flush()
+                yield chunk
+
+        chunks = recorded(chunks)
+    if not args.log:
+        # Clear the screen and hide the cursor
+        sys.stdout.write("\x1b[2J\x1b[?25l")
+    try:
+        for payload in payloads(chunks):
+            state = decode(payload)
+            if state is None:
+                continue
+            if args.log:
+                print(log_line(state))
+            else:
+                sys.stdout.write("\x1b[H" + draw(state))
+                sys.stdout.flush()
+            if paced:
+                time.sleep(1 / args.rate)
+    except KeyboardInterrupt:
+        pass
+    finally:
+        if not args.log:
+            sys.stdout.write("\x1b[?25h\n")
+    return 0
+
+
+if __name__ == "__main__":
+    sys.exit(main())
@@ -0,0 +1,144 @@
+//! Single player Pong as a ring 3 program: W/S move the left paddle, Q quits. A demonstration of
+//! the system calls; the full game is still the one compiled into the kernel.
+#![no_std]
+#![no_main]
+
+use core::fmt::Write;
+use user::{FixedString, draw_text, exit, fill_rect, poll_key, screen_size, sleep};
+
+const FRAME_MS: u64 = 33;
+const PADDLE_WIDTH: usize = 4;
+const PADDLE_HEIGHT: usize = 50;
+const PADDLE_STEP: usize = 25;
+const AI_STEP: usize = 6;
+const BALL_SIZE: usize = 12;
+const BALL_SPEED: isize = 8;
+const WINNING_SCORE: u32 = 5;
+const WHITE: u32 = 0xFFFFFF;
+const BLACK: u32 = 0x000000;
+
+struct Game {
+    width: usize,
+    height: usize,
+    left_y: usize,
+    right_y: usize,
+    ball_x: usize,
+    ball_y: usize,
+    ball_dx: isize,
+    ball_dy: isize,
+    player_score: u32,
+    ai_score: u32,
+}
+
+impl Game {
+    fn new(width: usize, height: usize) -> Self {
+        let mut game = Game {
+            width,
+            height,
+            left_y: (height - PADDLE_HEIGHT) / 2,
+            right_y: (height - PADDLE_HEIGHT) / 2,
+            ball_x: 0,
+            ball_y: 0,
+            ball_dx: BALL_SPEED,
+            ball_dy: BALL_SPEED / 2,
+            player_score: 0,
+            ai_score: 0,
+        };
+        game.serve();
+        game
+    }
+
+    fn serve(&mut self) {
+        self.ball_x = (self.width - BALL_SIZE) / 2;
+        self.ball_y = (self.height - BALL_SIZE) / 2;
+        self.ball_dx = -self.ball_dx;
+    }
+
+    fn move_left(&mut self, up: bool) {
+        self.left_y = if up {
+            self.left_y.saturating_sub(PADDLE_STEP)
+        } else {
+            (self.left_y + PADDLE_STEP).min(self.height - PADDLE_HEIGHT)
+        };
+    }
+
+    fn update(&mut self) {
+        // The AI paddle follows the ball at a limited speed
+        let target = (self.ball_y + BALL_SIZE / 2).saturating_sub(PADDLE_HEIGHT / 2).min(self.height - PADDLE_HEIGHT);
+        if self.right_y < target {
+            self.right_y = (self.right_y + AI_STEP).min(target);
+        } else {
+            self.right_y = self.right_y.saturating_sub(AI_STEP).max(target);
+        }
+
+        let x = self.ball_x as isize + self.ball_dx;
+        let y = self.ball_y as isize + self.ball_dy;
+        if y <= 0 || y as usize + BALL_SIZE >= self.height {
+            self.ball_dy = -self.ball_dy;
+        }
+        let y = y.clamp(0, (self.height - BALL_SIZE) as isize) as usize;
+
+        let hits = |paddle_y: usize| y + BALL_SIZE >= paddle_y && y <= paddle_y + PADDLE_HEIGHT;
+        let left_edge = 10 + PADDLE_WIDTH;
+        let right_edge = self.width - 10 - PADDLE_WIDTH;
+        if x <= left_edge as isize && self.ball_dx < 0 {
+            if hits(self.left_y) {
+                self.ball_dx = -self.ball_dx;
+            } else {
+                self.ai_score += 1;
+                self.serve();
+                return;
+            }
+        } else if x as usize + BALL_SIZE >= right_edge && self.ball_dx > 0 {
+            if hits(self.right_y) {
+                self.ball_dx = -self.ball_dx;
+            } else {
+                self.player_score += 1;
+                self.serve();
+                return;
+            }
+        }
+        self.ball_x = x.clamp(0, (self.width - BALL_SIZE) as isize) as usize;
+        self.ball_y = y;
+    }
+
+    /// Draws the paddles, ball and score in `color`; black erases the last frame.
+    fn draw(&self, color: u32) {
+        fill_rect(10, self.left_y, PADDLE_WIDTH, PADDLE_HEIGHT, color);
+        fill_rect(self.width - 10 - PADDLE_WIDTH, self.right_y, PADDLE_WIDTH, PADDLE_HEIGHT, color);
+        fill_rect(self.ball_x, self.ball_y, BALL_SIZE, BALL_SIZE, color);
+
+        let mut score = FixedString::<24>::new();
+        let _ = write!(score, "{} - {}", self.player_score, self.ai_score);
+        draw_text(self.width / 2 - 20, 10, score.as_str(), color);
+    }
+}
+
+#[unsafe(no_mangle)]
+extern "C" fn _start() -> ! {
+    let (width, height) = screen_size();
+    fill_rect(0, 0, width, height, BLACK);
+    let mut game = Game::new(width, height);
+
+    while game.player_score < WINNING_SCORE && game.ai_score < WINNING_SCORE {
+        game.draw(BLACK);
+        while let Some(key) = poll_key() {
+            match key {
+                'w' => game.move_left(true),
+                's' => game.move_left(false),
+                'q' => exit(0),
+                _ => {}
+            }
+        }
+        game.update();
+        game.draw(WHITE);
+        sleep(FRAME_MS);
+    }
+
+    let result = if game.player_score > game.ai_score { "You win! Press any key" } else { "You lose! Press any key" };
+    draw_text(width / 2 - 90, height / 2, result, WHITE);
+    while poll_key().is_none() {
+        sleep(FRAME_MS);
+    }
+    exit(0);
+}
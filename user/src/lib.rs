@@ -0,0 +1,96 @@
+//! The system call interface for programs running on the kernel in ring 3.
+#![no_std]
+
+use core::arch::asm;
+use core::fmt;
+use core::fmt::Write;
+use core::panic::PanicInfo;
+
+// System call numbers, the same as in kernel/src/syscall.rs
+const EXIT: u64 = 0;
+const SLEEP: u64 = 1;
+const POLL_KEY: u64 = 2;
+const SCREEN_SIZE: u64 = 3;
+const FILL_RECT: u64 = 4;
+const DRAW_TEXT: u64 = 5;
+const UPTIME: u64 = 6;
+const WRITE: u64 = 7;
+
+const NO_KEY: u64 = u64::MAX;
+
+// Text formatted on the stack, since programs have no heap
+pub use fixed::FixedString;
+
+unsafe fn syscall(number: u64, a0: u64, a1: u64, a2: u64, a3: u64, a4: u64) -> u64 {
+    let result;
+    unsafe {
+        asm!(
+            "syscall",
+            inlateout("rax") number => result,
+            in("rdi") a0,
+            in("rsi") a1,
+            in("rdx") a2,
+            in("r10") a3,
+            in("r8") a4,
+            // The kernel's handler is an ordinary function, free to use the scratch registers
+            clobber_abi("C"),
+        );
+    }
+    result
+}
+
+/// Ends the program with `code`.
+pub fn exit(code: i64) -> ! {
+    unsafe { syscall(EXIT, code as u64, 0, 0, 0, 0) };
+    unreachable!("exit returned");
+}
+
+/// Blocks for at least `ms` milliseconds.
+pub fn sleep(ms: u64) {
+    unsafe { syscall(SLEEP, ms, 0, 0, 0, 0) };
+}
+
+/// The next character typed, if any.
+pub fn poll_key() -> Option<char> {
+    match unsafe { syscall(POLL_KEY, 0, 0, 0, 0, 0) } {
+        NO_KEY => None,
+        c => char::from_u32(c as u32),
+    }
+}
+
+/// Width and height of the screen in pixels.
+pub fn screen_size() -> (usize, usize) {
+    let size = unsafe { syscall(SCREEN_SIZE, 0, 0, 0, 0, 0) };
+    ((size >> 32) as usize, size as u32 as usize)
+}
+
+/// Fills a rectangle with `color`, as 0xRRGGBB.
+pub fn fill_rect(x: usize, y: usize, width: usize, height: usize, color: u32) {
+    unsafe { syscall(FILL_RECT, x as u64, y as u64, width as u64, height as u64, color as u64) };
+}
+
+/// Draws `text` with its top left corner at `x`, `y`.
+pub fn draw_text(x: usize, y: usize, text: &str, color: u32) {
+    unsafe { syscall(DRAW_TEXT, x as u64, y as u64, text.as_ptr() as u64, text.len() as u64, color as u64) };
+}
+
+/// Milliseconds since the machine booted.
+pub fn uptime() -> u64 {
+    unsafe { syscall(UPTIME, 0, 0, 0, 0, 0) }
+}
+
+/// The serial console, for logging.
+pub struct Serial;
+
+impl Write for Serial {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        unsafe { syscall(WRITE, s.as_ptr() as u64, s.len() as u64, 0, 0, 0) };
+        Ok(())
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let _ = writeln!(Serial, "user program panicked: {}", info);
+    exit(101);
+}